@@ -1,4 +1,4 @@
-use std::{cell::RefCell, io, rc::Rc};
+use std::{cell::RefCell, io, path::PathBuf, rc::Rc};
 
 use argh::FromArgs;
 use crossterm::event::{self, Event, KeyCode};
@@ -7,6 +7,7 @@ use ratatui::{
     Terminal,
 };
 
+use crate::components::qemu::DiskInterface;
 use crate::ui::{
     action_selector::ActionSelector,
     component::{Component, ComponentManager},
@@ -34,6 +35,80 @@ pub struct App {
 
     #[argh(switch, short = 'v', description = "display debug messages")]
     pub verbose: bool,
+
+    #[argh(
+        option,
+        short = 'i',
+        description = "disk image layout configuration file (TOML)"
+    )]
+    pub image: Option<PathBuf>,
+
+    #[argh(
+        switch,
+        description = "rebuild every part, ignoring the cached artifacts"
+    )]
+    pub rebuild: bool,
+
+    #[argh(
+        switch,
+        description = "build without the terminal interface, printing JSON records"
+    )]
+    pub no_tui: bool,
+
+    #[argh(subcommand)]
+    pub command: Option<AppCommand>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum AppCommand {
+    Run(RunCommand),
+}
+
+#[derive(FromArgs)]
+#[argh(
+    subcommand,
+    name = "run",
+    description = "build the disk image using the default parameters, and boot it in QEMU"
+)]
+pub struct RunCommand {
+    #[argh(
+        option,
+        short = 'm',
+        default = "String::from(\"512M\")",
+        description = "memory of the virtual machine (default: 512M)"
+    )]
+    pub memory: String,
+
+    #[argh(
+        option,
+        short = 'd',
+        default = "DiskInterface::Ahci",
+        description = "disk interface: ahci, ide or virtio (default: ahci)"
+    )]
+    pub disk: DiskInterface,
+
+    #[argh(
+        switch,
+        short = 'g',
+        description = "wait for gdb to attach on localhost:1234 before booting"
+    )]
+    pub gdb: bool,
+
+    #[argh(switch, description = "do not open a graphical display")]
+    pub headless: bool,
+
+    #[argh(
+        switch,
+        description = "boot the existing disk image without building it"
+    )]
+    pub no_build: bool,
+
+    #[argh(
+        option,
+        description = "additional argument passed to QEMU (may be repeated)"
+    )]
+    pub qemu_arg: Vec<String>,
 }
 
 pub fn run_app<B: Backend + 'static>(term: &mut Terminal<B>) -> io::Result<()> {
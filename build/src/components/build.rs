@@ -1,11 +1,16 @@
+use crate::components::image::{
+    self, parse_size, FileCopy, ImageConfig, PartitionContent, PartitionExtent, PartitionTableKind,
+    KERNEL_IMAGE_PATH, KERNEL_SLOTS, SECTOR_SIZE,
+};
 use crate::errors::BuildError;
 use async_trait::async_trait;
 use crossbeam::channel::{unbounded, Receiver, Sender};
 use gpt::disk::LogicalBlockSize::Lb512;
 use llvm_tools::{exe, LlvmTools};
 use rayon::prelude::*;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::io::{Read, Seek, SeekFrom};
-use std::ops::Add;
 use std::os::unix::fs::FileExt;
 use std::{
     env,
@@ -20,9 +25,199 @@ pub enum BuildEvent {
     StepFinished(String, usize),
     Finished(String, usize),
     StepFailed(String, String),
+
+    /// A line printed on the serial port of the virtual machine.
+    Serial(String),
+}
+
+/// Offset of the boot metadata block inside the MBR boot code.
+const BOOT_METADATA_OFFSET: usize = 420;
+
+/// Signature of the boot metadata block.
+const BOOT_METADATA_MAGIC: &[u8; 4] = b"FZBM";
+
+const BOOT_METADATA_VERSION: u8 = 1;
+
+/// Size of the MBR, at the start of the bootloader image and of the bootstrap stage binary.
+const MBR_SIZE: u64 = 0x200;
+
+/// The MBR only loads the lower 16 bits of the LBA addresses: the stages must end before this sector.
+const MBR_MAX_LBA: u64 = 0xFFFF;
+
+/// Location of the bootloader stages on disk, read by the MBR to load the following stages.
+///
+/// Its layout must match the `.boot_meta` section defined in `src/x86/real/boot.S`.
+pub struct BootMetadata {
+    pub bootstrap_lba: u32,
+    pub bootstrap_sectors: u16,
+    pub stage2_lba: u32,
+    pub stage2_sectors: u16,
+}
+
+impl BootMetadata {
+    /// Describes the bootloader stages, from the size of the bootloader image (`build_img_len`) and of the bootstrap
+    /// stage binary (`bootstrap_bin_len`), both starting with the MBR, once copied to the sector `first_lba`.
+    ///
+    /// Fails if an image is smaller than the MBR, if the bootstrap stage does not fit in the bootloader image, or if
+    /// a stage ends past [`MBR_MAX_LBA`].
+    pub fn new(
+        first_lba: u64,
+        build_img_len: u64,
+        bootstrap_bin_len: u64,
+    ) -> Result<Self, BuildError> {
+        let too_small = |name: &str| BuildError(Some(format!("{name} is smaller than the MBR")));
+
+        let bootstrap_len = bootstrap_bin_len
+            .checked_sub(MBR_SIZE)
+            .ok_or_else(|| too_small("Bootstrap stage binary"))?;
+        let stage2_len = build_img_len
+            .checked_sub(MBR_SIZE)
+            .ok_or_else(|| too_small("Bootloader image"))?
+            .checked_sub(bootstrap_len)
+            .ok_or(BuildError(Some(String::from(
+                "Bootstrap stage is larger than the bootloader image",
+            ))))?;
+
+        let bootstrap_sectors = sectors_count(bootstrap_len)?;
+        let bootstrap_lba = u32::try_from(first_lba).map_err(|_| {
+            BuildError(Some(String::from(
+                "Bootloader partition is out of reach of the MBR",
+            )))
+        })?;
+        let stage2_lba = bootstrap_lba
+            .checked_add(u32::from(bootstrap_sectors))
+            .ok_or(BuildError(Some(String::from(
+                "Bootloader second stage is out of reach of the MBR",
+            ))))?;
+
+        let stage2_sectors = sectors_count(stage2_len)?;
+
+        // The second stage is located right after the bootstrap stage, and therefore ends last.
+        if u64::from(stage2_lba) + u64::from(stage2_sectors) > MBR_MAX_LBA {
+            return Err(BuildError(Some(String::from(
+                "Bootloader stages are out of reach of the MBR",
+            ))));
+        }
+
+        Ok(Self {
+            bootstrap_lba,
+            bootstrap_sectors,
+            stage2_lba,
+            stage2_sectors,
+        })
+    }
+
+    /// Patches the boot metadata block of the MBR boot code.
+    ///
+    /// Fails if the boot code does not contain a compatible metadata block.
+    pub fn write_to(&self, bootcode: &mut [u8]) -> BuildResult {
+        let block = bootcode
+            .get_mut(BOOT_METADATA_OFFSET..BOOT_METADATA_OFFSET + 20)
+            .ok_or(BuildError(Some(String::from(
+                "MBR boot code is too small to hold the boot metadata block",
+            ))))?;
+
+        if &block[0..4] != BOOT_METADATA_MAGIC || block[4] != BOOT_METADATA_VERSION {
+            return Err(BuildError(Some(String::from(
+                "Invalid boot metadata block in MBR boot code",
+            ))));
+        }
+
+        block[6..8].copy_from_slice(&self.bootstrap_sectors.to_le_bytes());
+        block[8..12].copy_from_slice(&self.bootstrap_lba.to_le_bytes());
+        block[12..16].copy_from_slice(&self.stage2_lba.to_le_bytes());
+        block[16..18].copy_from_slice(&self.stage2_sectors.to_le_bytes());
+
+        Ok(())
+    }
 }
 
-const DEFAULT_DISK_IMAGE_SIZE: u32 = 5 * 1024 * 1024;
+/// Sector of the disk image holding the kernel metadata: the first one following the primary GPT partition entries.
+const KERNEL_METADATA_LBA: u64 = 34;
+
+/// Signature of the kernel metadata sector (`FZMD`).
+const KERNEL_METADATA_MAGIC: &[u8; 4] = b"FZMD";
+
+const KERNEL_METADATA_VERSION: u16 = 2;
+
+/// Size of the kernel metadata structure, in bytes.
+const KERNEL_METADATA_SIZE: usize = 64;
+
+/// Offset of the first kernel slot in the kernel metadata structure.
+const KERNEL_SLOTS_OFFSET: usize = 16;
+
+/// Size of a kernel slot, in bytes.
+const KERNEL_SLOT_SIZE: usize = 24;
+
+/// Kernel slot flags: the slot holds a kernel image, which is known to boot.
+const KERNEL_SLOT_VALID_HEALTHY: u32 = 0b11;
+
+/// Description of the kernel slots, checked by the bootloader before loading a kernel image.
+///
+/// Its layout must match the `BootMetadataSector` structure defined in `src/boot/metadata.rs`.
+pub struct KernelMetadata {
+    /// First sector of the partition of each slot.
+    pub slots_lba: Vec<u64>,
+    pub kernel_size: u64,
+    pub kernel_crc32: u32,
+}
+
+impl KernelMetadata {
+    /// Describes the kernel image `image`, stored on each partition starting at one of `slots_lba`. Every slot is
+    /// healthy, and the first one is active.
+    pub fn new(slots_lba: Vec<u64>, image: &[u8]) -> Self {
+        let mut crc = flate2::Crc::new();
+        crc.update(image);
+
+        Self {
+            slots_lba,
+            kernel_size: image.len() as u64,
+            kernel_crc32: crc.sum(),
+        }
+    }
+
+    /// Writes the metadata sector to the disk image.
+    ///
+    /// Fails if a partition overlaps the metadata sector.
+    pub fn write_to(&self, disk_image: &std::fs::File, extents: &[PartitionExtent]) -> BuildResult {
+        if extents.iter().any(|extent| {
+            (extent.first_lba..extent.first_lba + extent.sectors).contains(&KERNEL_METADATA_LBA)
+        }) {
+            return Err(BuildError(Some(format!(
+                "A partition overlaps the kernel metadata sector (LBA {KERNEL_METADATA_LBA})"
+            ))));
+        }
+
+        let mut sector = [0u8; SECTOR_SIZE as usize];
+        sector[0..4].copy_from_slice(KERNEL_METADATA_MAGIC);
+        sector[4..6].copy_from_slice(&KERNEL_METADATA_VERSION.to_le_bytes());
+        sector[6..8].copy_from_slice(&(KERNEL_METADATA_SIZE as u16).to_le_bytes());
+
+        for (i, lba) in self.slots_lba.iter().take(KERNEL_SLOTS).enumerate() {
+            let slot =
+                &mut sector[KERNEL_SLOTS_OFFSET + i * KERNEL_SLOT_SIZE..][..KERNEL_SLOT_SIZE];
+            slot[0..8].copy_from_slice(&lba.to_le_bytes());
+            slot[8..16].copy_from_slice(&self.kernel_size.to_le_bytes());
+            slot[16..20].copy_from_slice(&self.kernel_crc32.to_le_bytes());
+            slot[20..24].copy_from_slice(&KERNEL_SLOT_VALID_HEALTHY.to_le_bytes());
+        }
+
+        let mut crc = flate2::Crc::new();
+        crc.update(&sector[..KERNEL_METADATA_SIZE]);
+        sector[8..12].copy_from_slice(&crc.sum().to_le_bytes());
+
+        disk_image
+            .write_at(&sector, KERNEL_METADATA_LBA * SECTOR_SIZE)
+            .map_err(|_| BuildError(None))?;
+
+        Ok(())
+    }
+}
+
+fn sectors_count(len: u64) -> Result<u16, BuildError> {
+    u16::try_from(len.div_ceil(MBR_SIZE))
+        .map_err(|_| BuildError(Some(String::from("Bootloader stage is too large"))))
+}
 
 pub type BuildResult = Result<(), BuildError>;
 
@@ -52,14 +247,189 @@ impl ImageDiskBuild {
     }
 }
 
+impl ImageDiskBuild {
+    /// Writes the GPT partition table described by the image layout.
+    ///
+    /// Returns the location of each partition.
+    fn write_gpt_partitions(
+        &self,
+        disk_image: &mut std::fs::File,
+    ) -> Result<Vec<PartitionExtent>, BuildError> {
+        let layout = &self.config.layout;
+        let disk_sectors = layout.size_bytes()? / SECTOR_SIZE;
+
+        let mbr = gpt::mbr::ProtectiveMBR::with_lb_size(
+            u32::try_from(disk_sectors - 1).unwrap_or(u32::MAX),
+        );
+        mbr.update_conservative(disk_image)
+            .map_err(|_| BuildError(None))?;
+
+        let mut gpt_disk = gpt::GptConfig::default()
+            .initialized(false)
+            .writable(true)
+            .logical_block_size(Lb512)
+            .create_from_device(Box::new(&mut *disk_image), None)
+            .map_err(|_| BuildError(None))?;
+
+        gpt_disk
+            .update_partitions(std::collections::BTreeMap::<u32, gpt::partition::Partition>::new())
+            .map_err(|_| BuildError(None))?;
+
+        let mut part_ids = vec![];
+        for partition in &layout.partitions {
+            let (part_type, alignment) = match partition.content {
+                PartitionContent::Bootloader => (gpt::partition_types::BASIC, Some(128)),
+                PartitionContent::Kernel => (gpt::partition_types::BASIC, None),
+                PartitionContent::Data => (gpt::partition_types::LINUX_FS, None),
+            };
+
+            let part_id = gpt_disk
+                .add_partition(
+                    &partition.name,
+                    parse_size(&partition.size)?,
+                    part_type,
+                    0,
+                    alignment,
+                )
+                .map_err(|_| {
+                    BuildError(Some(format!(
+                        "Partition {} does not fit in the disk image",
+                        partition.name
+                    )))
+                })?;
+            part_ids.push(part_id);
+        }
+
+        let extents = part_ids
+            .iter()
+            .map(|part_id| {
+                let partition = &gpt_disk.partitions()[part_id];
+                PartitionExtent {
+                    first_lba: partition.first_lba,
+                    sectors: partition.last_lba - partition.first_lba + 1,
+                }
+            })
+            .collect();
+        gpt_disk.write().map_err(|_| BuildError(None))?;
+
+        Ok(extents)
+    }
+
+    /// Compresses the kernel image if required by the layout, and returns the path of the file to copy to the kernel
+    /// partitions, along with its content.
+    ///
+    /// The same image is copied to every kernel slot, using the compression of the first kernel partition.
+    fn stage_kernel_image(
+        &self,
+        kernel_extents: &[PartitionExtent],
+        master: &Sender<BuildEvent>,
+    ) -> Result<(PathBuf, Vec<u8>), BuildError> {
+        let kernel_partition = self
+            .config
+            .layout
+            .partitions
+            .iter()
+            .find(|partition| partition.content == PartitionContent::Kernel)
+            .ok_or(BuildError(None))?;
+
+        let mut kernel_code =
+            std::fs::read(&self.config.kernel_img).map_err(|_| BuildError(None))?;
+        let mut kernel_image = self.config.kernel_img.clone();
+
+        if let Some(compression) = kernel_partition.compression {
+            let raw_len = kernel_code.len();
+            kernel_code = compression.compress(&kernel_code)?;
+            master
+                .send(BuildEvent::Update(format!(
+                    "Compressed kernel image ({compression:?}): {raw_len} -> {} bytes",
+                    kernel_code.len()
+                )))
+                .ok();
+
+            kernel_image.set_extension("elf.z");
+            std::fs::write(&kernel_image, &kernel_code).map_err(|_| BuildError(None))?;
+        }
+
+        if kernel_extents
+            .iter()
+            .any(|extent| kernel_code.len() as u64 > extent.len())
+        {
+            return Err(BuildError(Some(String::from(
+                "Kernel does not fit in its partition",
+            ))));
+        }
+
+        Ok((kernel_image, kernel_code))
+    }
+
+    /// Creates the filesystems of the partitions, and copies the kernel image to the kernel partition.
+    fn format_partitions(
+        &self,
+        extents: &[PartitionExtent],
+        kernel_image: &Path,
+        master: &Sender<BuildEvent>,
+    ) -> BuildResult {
+        for (partition, extent) in self.config.layout.partitions.iter().zip(extents) {
+            if partition.filesystem.is_none() {
+                continue;
+            }
+
+            let mut partition = partition.clone();
+            if partition.content == PartitionContent::Kernel {
+                partition.files.push(FileCopy {
+                    src: kernel_image.to_path_buf(),
+                    dest: PathBuf::from(KERNEL_IMAGE_PATH),
+                });
+            }
+
+            let start = SystemTime::now();
+            master
+                .send(BuildEvent::Update(format!(
+                    "Creating filesystem on {}",
+                    partition.name
+                )))
+                .ok();
+
+            image::format_partition(&self.config.disk_img, &partition, *extent).map_err(|err| {
+                let msg = format!("Failed to create filesystem on {}", partition.name);
+                master
+                    .send(BuildEvent::StepFailed(
+                        msg.clone(),
+                        err.0.unwrap_or_default(),
+                    ))
+                    .ok();
+                BuildError(Some(msg))
+            })?;
+
+            master
+                .send(BuildEvent::StepFinished(
+                    partition.name.clone(),
+                    start.elapsed().unwrap().as_micros() as usize,
+                ))
+                .ok();
+        }
+
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl BuildStep for ImageDiskBuild {
     fn steps_count(&self) -> usize {
-        1
+        let filesystems = self
+            .config
+            .layout
+            .partitions
+            .iter()
+            .filter(|partition| partition.filesystem.is_some())
+            .count();
+
+        1 + filesystems
     }
 
     async fn build(&mut self, master: Sender<BuildEvent>) -> BuildResult {
         let start = SystemTime::now();
+        let layout = &self.config.layout;
         let mut disk_image = std::fs::File::options()
             .write(true)
             .read(true)
@@ -69,87 +439,78 @@ impl BuildStep for ImageDiskBuild {
             .unwrap();
 
         disk_image
-            .set_len(u64::from(DEFAULT_DISK_IMAGE_SIZE))
-            .unwrap();
-
-        let mbr = gpt::mbr::ProtectiveMBR::with_lb_size(DEFAULT_DISK_IMAGE_SIZE);
-        mbr.update_conservative(&mut disk_image)
+            .set_len(layout.size_bytes()?)
             .map_err(|_| BuildError(None))?;
+
+        let extents = match layout.table {
+            PartitionTableKind::Gpt => self.write_gpt_partitions(&mut disk_image)?,
+            PartitionTableKind::Mbr => {
+                master
+                    .send(BuildEvent::Update(String::from(
+                        "MBR partitions have no name: the kernel partition cannot be located at boot",
+                    )))
+                    .ok();
+                image::write_mbr_partitions(&disk_image, layout)?
+            }
+        };
+
+        let extent_of = |content| {
+            layout
+                .partitions
+                .iter()
+                .zip(&extents)
+                .find(|(partition, _)| partition.content == content)
+                .map(|(_, extent)| *extent)
+                .ok_or(BuildError(None))
+        };
+        let boot_extent = extent_of(PartitionContent::Bootloader)?;
+        let kernel_extents: Vec<PartitionExtent> = layout
+            .partitions
+            .iter()
+            .zip(&extents)
+            .filter(|(partition, _)| partition.content == PartitionContent::Kernel)
+            .map(|(_, extent)| *extent)
+            .collect();
+
         let mut bootcode = [0u8; 440];
         let mut build_img =
             std::fs::File::open(&self.config.build_img).map_err(|_| BuildError(None))?;
 
-        let mut kernel_img =
-            std::fs::File::open(&self.config.kernel_img).map_err(|_| BuildError(None))?;
-
         build_img
             .read(&mut bootcode)
             .map_err(|_| BuildError(None))?;
-        gpt::mbr::write_bootcode(&mut disk_image, &bootcode);
-
-        let mut gpt_disk = gpt::GptConfig::default()
-            .initialized(false)
-            .writable(true)
-            .logical_block_size(Lb512)
-            .create_from_device(Box::new(&mut disk_image), None)
-            .map_err(|_| BuildError(None))?;
-
-        gpt_disk
-            .update_partitions(std::collections::BTreeMap::<u32, gpt::partition::Partition>::new())
-            .map_err(|_| BuildError(None))?;
-
-        let boot_part_id = gpt_disk
-            .add_partition(
-                "fzboot",
-                1024 * 1024,
-                gpt::partition_types::BASIC,
-                0,
-                Some(128),
-            )
-            .map_err(|_| BuildError(None))?;
-
-        let kernel_part_id = gpt_disk
-            .add_partition(
-                "kernelfs",
-                1024 * 1024 * 1,
-                gpt::partition_types::BASIC,
-                0,
-                None,
-            )
-            .map_err(|_| BuildError(None))?;
 
-        gpt_disk
-            .add_partition(
-                "rootfs",
-                1024 * 1024 * 2,
-                gpt::partition_types::LINUX_FS,
-                0,
-                None,
-            )
-            .map_err(|_| BuildError(None))?;
+        let build_img_len = build_img.metadata().map_err(|_| BuildError(None))?.len();
+        let bootstrap_bin_len = std::fs::metadata(&self.config.bootstrap_bin)
+            .map_err(|_| BuildError(None))?
+            .len();
 
-        let boot_part_start_lba = gpt_disk.partitions().get(&boot_part_id).unwrap().first_lba;
-        let kernel_part_start_lba = gpt_disk
-            .partitions()
-            .get(&kernel_part_id)
-            .unwrap()
-            .first_lba;
-        gpt_disk.write().map_err(|_| BuildError(None))?;
+        BootMetadata::new(boot_extent.first_lba, build_img_len, bootstrap_bin_len)?
+            .write_to(&mut bootcode)?;
+        gpt::mbr::write_bootcode(&mut disk_image, &bootcode);
 
-        let mut post_mbr_code = vec![0; (build_img.metadata().unwrap().len() - 0x200) as usize];
-        build_img.seek(SeekFrom::Start(0x200));
+        // The image is at least as large as the MBR, as checked by `BootMetadata::new`.
+        let post_mbr_len =
+            usize::try_from(build_img_len - MBR_SIZE).map_err(|_| BuildError(None))?;
+        let mut post_mbr_code = vec![0; post_mbr_len];
+        build_img.seek(SeekFrom::Start(MBR_SIZE));
         build_img
             .read(&mut post_mbr_code)
             .map_err(|_| BuildError(None))?;
 
-        disk_image.write_at(&post_mbr_code, boot_part_start_lba * 0x200);
-
-        let mut kernel_code = vec![0; kernel_img.metadata().unwrap().len() as usize];
-        kernel_img
-            .read(&mut kernel_code)
-            .map_err(|_| BuildError(None))?;
+        if post_mbr_code.len() as u64 > boot_extent.len() {
+            return Err(BuildError(Some(String::from(
+                "Bootloader does not fit in its partition",
+            ))));
+        }
+        disk_image.write_at(&post_mbr_code, boot_extent.offset());
 
-        disk_image.write_at(&kernel_code, kernel_part_start_lba * 0x200);
+        let (kernel_image, kernel_code) = self.stage_kernel_image(&kernel_extents, &master)?;
+        let slots_lba = kernel_extents
+            .iter()
+            .map(|extent| extent.first_lba)
+            .collect();
+        KernelMetadata::new(slots_lba, &kernel_code).write_to(&disk_image, &extents)?;
         master
             .send(BuildEvent::StepFinished(
                 String::from("disk image"),
@@ -157,14 +518,18 @@ impl BuildStep for ImageDiskBuild {
             ))
             .unwrap();
 
-        master.send(BuildEvent::Finished(String::from(""), 0));
-        Ok(())
+        drop(disk_image);
+        self.format_partitions(&extents, &kernel_image, &master)
     }
 }
 
 pub struct ImageDiskBuildConfig {
     pub disk_img: PathBuf,
+
+    /// Layout of the disk image (partitions and filesystems).
+    pub layout: ImageConfig,
     pub build_img: PathBuf,
+    pub bootstrap_bin: PathBuf,
     pub kernel_img: PathBuf,
 }
 
@@ -173,6 +538,112 @@ pub struct BootloaderBuildConfig {
     kernel_img: PathBuf,
     bin_parts_path: Vec<PathBuf>,
     src_parts_path: Vec<PathBuf>,
+
+    /// Reuses the artifacts of the parts whose sources did not change since their last build.
+    pub incremental: bool,
+}
+
+/// Directory holding the fingerprint of the sources of each part, as of its last build.
+const FINGERPRINTS_DIR: &str = "target/fingerprints";
+
+/// Independent component of the bootloader.
+///
+/// Parts do not depend on each other, and are built in parallel. The images are assembled once all of them are
+/// built.
+#[derive(Clone, Debug)]
+enum BuildPart {
+    /// MBR boot code and real-mode stages, built using `make`.
+    RealMode,
+
+    /// Rust crate, built using cargo and converted to a flat binary.
+    Crate { src: PathBuf, artifact: PathBuf },
+}
+
+impl BuildPart {
+    fn name(&self) -> String {
+        match self {
+            Self::RealMode => String::from("mbr"),
+            Self::Crate { src, .. } => src
+                .file_stem()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn artifact(&self) -> PathBuf {
+        match self {
+            Self::RealMode => PathBuf::from("artifacts/boot.bin"),
+            Self::Crate { artifact, .. } => artifact.clone(),
+        }
+    }
+
+    /// Files and directories whose changes require the part to be rebuilt.
+    fn sources(&self) -> Vec<PathBuf> {
+        let root = Path::new("..");
+
+        match self {
+            Self::RealMode => vec![root.join("src/x86/real")],
+
+            // Every crate depends on the shared library.
+            Self::Crate { .. } => vec![root.join("src"), root.join("Cargo.toml")],
+        }
+    }
+
+    fn fingerprint_path(&self) -> PathBuf {
+        Path::new(FINGERPRINTS_DIR).join(self.name())
+    }
+
+    /// Computes a fingerprint of the sources of the part, using the size and modification time of each file.
+    fn fingerprint(&self) -> Result<String, BuildError> {
+        let mut files = vec![];
+        for source in self.sources() {
+            collect_files(&source, &mut files)?;
+        }
+        files.sort();
+
+        let mut hasher = DefaultHasher::new();
+        for file in files {
+            let metadata = std::fs::metadata(&file).map_err(|_| BuildError(None))?;
+
+            file.hash(&mut hasher);
+            metadata.len().hash(&mut hasher);
+            metadata
+                .modified()
+                .map_err(|_| BuildError(None))?
+                .hash(&mut hasher);
+        }
+
+        // The command line is embedded in the bootloader at compile time.
+        env::var("FZ_KERNEL_CMDLINE").ok().hash(&mut hasher);
+
+        Ok(format!("{:016x}", hasher.finish()))
+    }
+
+    /// Checks if the artifact of the part is up to date with its sources.
+    fn is_fresh(&self, fingerprint: &str) -> bool {
+        self.artifact().exists()
+            && std::fs::read_to_string(self.fingerprint_path())
+                .is_ok_and(|previous| previous.trim() == fingerprint)
+    }
+
+    fn save_fingerprint(&self, fingerprint: &str) -> Result<(), BuildError> {
+        std::fs::create_dir_all(FINGERPRINTS_DIR).map_err(|_| BuildError(None))?;
+        std::fs::write(self.fingerprint_path(), fingerprint).map_err(|_| BuildError(None))
+    }
+}
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), BuildError> {
+    if !path.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(path).map_err(|_| BuildError(None))? {
+        let entry = entry.map_err(|_| BuildError(None))?;
+        collect_files(&entry.path(), files)?;
+    }
+
+    Ok(())
 }
 
 impl BootloaderBuild {
@@ -251,6 +722,58 @@ impl BootloaderBuild {
         Ok(())
     }
 
+    fn build_real_mode(&self) -> Result<(), BuildError> {
+        let make = Command::new("make")
+            .current_dir("../src/x86/real")
+            .output()
+            .map_err(|_| BuildError(Some(String::from("Could not run make"))))?;
+
+        if !make.status.success() {
+            let make_output = String::from_utf8_lossy(&make.stderr);
+            return Err(BuildError(Some(
+                String::from_utf8_lossy(&make.stdout).to_string() + &make_output,
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Builds a part, unless its artifact is up to date.
+    ///
+    /// Returns whether the part was rebuilt.
+    fn build_cached(
+        &self,
+        part: &BuildPart,
+        master: &Sender<BuildEvent>,
+    ) -> Result<bool, BuildError> {
+        let start = SystemTime::now();
+        let name = part.name();
+        let fingerprint = part.fingerprint()?;
+
+        if self.config.incremental && part.is_fresh(&fingerprint) {
+            master
+                .send(BuildEvent::StepFinished(format!("{name} (cached)"), 0))
+                .map_err(|_| BuildError(None))?;
+            return Ok(false);
+        }
+
+        match part {
+            BuildPart::RealMode => self.build_real_mode()?,
+            BuildPart::Crate { src, .. } => self.build_part(src)?,
+        }
+        part.save_fingerprint(&fingerprint)?;
+
+        let duration: Duration = start.elapsed().map_err(|_| BuildError(None))?;
+        master
+            .send(BuildEvent::StepFinished(
+                name,
+                duration.as_micros() as usize,
+            ))
+            .map_err(|_| BuildError(None))?;
+
+        Ok(true)
+    }
+
     async fn write_part_to_img(&self, file: &mut File, path: &Path) -> Result<(), std::io::Error> {
         let part_bin = tokio::fs::read(path).await?;
         file.write_all(part_bin.as_slice()).await?;
@@ -262,13 +785,14 @@ impl BootloaderBuild {
 #[async_trait]
 impl BuildStep for BootloaderBuild {
     fn steps_count(&self) -> usize {
-        self.config.bin_parts_path.len() + 1
+        self.config.src_parts_path.len() + 2
     }
 
     async fn build(&mut self, master: Sender<BuildEvent>) -> BuildResult {
         let mut build_img = tokio::fs::OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(self.config.disk_img.as_path())
             .await
             .map_err(|_| BuildError(None))?;
@@ -276,43 +800,47 @@ impl BuildStep for BootloaderBuild {
         let mut kernel_img = tokio::fs::OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(self.config.kernel_img.as_path())
             .await
             .map_err(|_| BuildError(None))?;
 
-        let make = Command::new("make")
-            .current_dir("../src/x86/real")
-            .output()
-            .unwrap();
-
-        if !make.status.success() {
-            let make_output = String::from_utf8(make.stderr).unwrap();
-            master
-                .send(BuildEvent::StepFailed(
-                    make_output.clone(),
-                    String::from_utf8(make.stdout).unwrap().add(&make_output),
-                ))
-                .map_err(|_| self.build_fail(master.clone(), None))?;
-        }
+        let parts: Vec<BuildPart> = std::iter::once(BuildPart::RealMode)
+            .chain(
+                self.config
+                    .src_parts_path
+                    .iter()
+                    .zip(&self.config.bin_parts_path)
+                    .map(|(src, artifact)| BuildPart::Crate {
+                        src: src.clone(),
+                        artifact: artifact.clone(),
+                    }),
+            )
+            .collect();
 
-        self.config
-            .src_parts_path
+        let rebuilt = parts
             .par_iter()
-            .enumerate()
-            .try_for_each(|(i, part)| {
-                let start = SystemTime::now();
-                self.build_part(part)?;
-                let duration: Duration = start.elapsed().map_err(|_| BuildError(None))?;
-                master
-                    .send(BuildEvent::StepFinished(
-                        String::from("bootcode"),
-                        duration.as_micros() as usize,
-                    ))
-                    .map_err(|_| self.build_fail(master.clone(), None))?;
-                Ok::<(), BuildError>(())
-            })
+            .map(|part| self.build_cached(part, &master))
+            .collect::<Result<Vec<bool>, BuildError>>()
             .map_err(|err| self.build_fail(master.clone(), err.0))?;
 
+        let (rebuilt, cached): (Vec<_>, Vec<_>) =
+            parts.iter().zip(rebuilt).partition(|(_, rebuilt)| *rebuilt);
+        let names = |parts: Vec<(&BuildPart, bool)>| {
+            parts
+                .iter()
+                .map(|(part, _)| part.name())
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        master
+            .send(BuildEvent::Update(format!(
+                "Rebuilt: [{}], cached: [{}]",
+                names(rebuilt),
+                names(cached)
+            )))
+            .ok();
+
         let start = SystemTime::now();
 
         self.write_part_to_img(&mut build_img, Path::new("artifacts/boot.bin"))
@@ -321,7 +849,8 @@ impl BuildStep for BootloaderBuild {
 
         for part in &self.config.bin_parts_path {
             if part.to_str().unwrap().contains("kernel") {
-                self.write_part_to_img(&mut kernel_img, part)
+                // The kernel is loaded from its ELF executable, rather than from the flat binary.
+                self.write_part_to_img(&mut kernel_img, &part.with_extension(""))
                     .await
                     .map_err(|_| self.build_fail(master.clone(), None))?;
             } else {
@@ -372,6 +901,7 @@ impl BootloaderBuildConfig {
             disk_img: disk_img_path,
             bin_parts_path,
             src_parts_path,
+            incremental: true,
         }
     }
 }
@@ -404,6 +934,9 @@ impl<'a> BuildBlueprint<'a> {
             step.build(self.outgoing.clone()).await?;
         }
 
+        self.outgoing
+            .send(BuildEvent::Finished(String::from(""), 0))
+            .ok();
         Ok(())
     }
 
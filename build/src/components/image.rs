@@ -0,0 +1,464 @@
+//! Declarative disk image layout.
+//!
+//! The layout of the disk image (partition table, partitions and their content) is described by a TOML file:
+//!
+//! ```toml
+//! size = "64M"
+//! table = "gpt"
+//!
+//! [[partitions]]
+//! name = "fzboot"
+//! size = "1M"
+//! content = "bootloader"
+//!
+//! [[partitions]]
+//! name = "kernelfs"
+//! size = "8M"
+//! content = "kernel"
+//! filesystem = "ext4"
+//! compression = "lz4"
+//!
+//! [[partitions]]
+//! name = "rootfs"
+//! size = "32M"
+//! filesystem = "ext4"
+//! source = "../rootfs"
+//! files = [{ src = "../README.md", dest = "/README.md" }]
+//! ```
+//!
+//! Filesystems are created with `mke2fs`, and populated from a staging directory containing the `source` directory
+//! and the extra `files`. The kernel image is copied to [`KERNEL_IMAGE_PATH`] on the kernel partition.
+//!
+//! A second kernel partition may be added, to use A/B kernel slots: the kernel image is copied to both, and the
+//! bootloader rolls back to the previous slot when a newly installed kernel fails to boot.
+//!
+//! Sector 34, right after the GPT partition entries, holds the kernel metadata (kernel slots, size and checksum of the
+//! kernel image) and must not be used by any partition: the bootloader partition is aligned on 128 sectors, and
+//! should come first.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde::Deserialize;
+
+use crate::errors::BuildError;
+
+/// Path of the kernel image on the filesystem of the kernel partition, where the bootloader looks for it.
+pub const KERNEL_IMAGE_PATH: &str = "/boot/kernel.elf";
+
+/// Maximum number of kernel partitions (kernel slots A and B).
+pub const KERNEL_SLOTS: usize = 2;
+
+/// Sector size of the disk image, in bytes.
+pub const SECTOR_SIZE: u64 = 0x200;
+
+/// Alignment of the partitions of a MBR image, in sectors (1 MiB).
+const MBR_PARTITION_ALIGNMENT: u64 = 2048;
+
+/// Offset of the partition entries in the MBR.
+const MBR_PARTITION_TABLE_OFFSET: u64 = 446;
+
+const MBR_MAX_PARTITIONS: usize = 4;
+
+/// Directory in which the content of the filesystems is staged before creating them.
+const STAGING_DIR: &str = "target/image-staging";
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionTableKind {
+    #[default]
+    Gpt,
+
+    /// Legacy partition table, limited to 4 partitions.
+    ///
+    /// MBR partitions have no name, and the bootloader locates the kernel partition using its GPT name: MBR images
+    /// cannot boot the kernel.
+    Mbr,
+}
+
+/// What a partition is used for.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionContent {
+    /// Bootloader stages (following the MBR boot code).
+    Bootloader,
+
+    /// Filesystem containing the kernel image.
+    ///
+    /// An image may contain up to [`KERNEL_SLOTS`] kernel partitions, used as the A and B kernel slots.
+    Kernel,
+
+    /// A filesystem, or nothing if no filesystem is specified.
+    #[default]
+    Data,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilesystemKind {
+    Ext4,
+}
+
+/// Compression of the kernel image, which the bootloader detects and decompresses when loading it.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionKind {
+    /// LZ4 frame, fast to decompress.
+    Lz4,
+
+    /// gzip, smaller but slower to decompress.
+    Gzip,
+}
+
+impl CompressionKind {
+    /// Compresses `data` using this format.
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, BuildError> {
+        use std::io::Write;
+
+        let error = |err: std::io::Error| BuildError(Some(format!("Compression failed: {err}")));
+
+        match self {
+            Self::Lz4 => {
+                // The bootloader needs the size of the content to allocate the decompression buffer.
+                let frame_info =
+                    lz4_flex::frame::FrameInfo::new().content_size(Some(data.len() as u64));
+                let mut encoder =
+                    lz4_flex::frame::FrameEncoder::with_frame_info(frame_info, vec![]);
+                encoder.write_all(data).map_err(error)?;
+                encoder
+                    .finish()
+                    .map_err(|err| BuildError(Some(format!("Compression failed: {err}"))))
+            }
+            Self::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(vec![], flate2::Compression::best());
+                encoder.write_all(data).map_err(error)?;
+                encoder.finish().map_err(error)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct FileCopy {
+    /// Path of the file on the host.
+    pub src: PathBuf,
+
+    /// Absolute path of the file in the filesystem.
+    pub dest: PathBuf,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PartitionConfig {
+    /// Name of the partition (GPT only).
+    pub name: String,
+
+    /// Size of the partition (for instance `32M`).
+    pub size: String,
+
+    #[serde(default)]
+    pub content: PartitionContent,
+
+    pub filesystem: Option<FilesystemKind>,
+
+    /// Label of the filesystem, defaults to the name of the partition.
+    pub label: Option<String>,
+
+    /// Host directory copied to the root of the filesystem.
+    pub source: Option<PathBuf>,
+
+    /// Host files copied into the filesystem.
+    #[serde(default)]
+    pub files: Vec<FileCopy>,
+
+    /// Compression of the kernel image (kernel partition only).
+    pub compression: Option<CompressionKind>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ImageConfig {
+    /// Size of the disk image (for instance `64M`).
+    pub size: String,
+
+    #[serde(default)]
+    pub table: PartitionTableKind,
+
+    pub partitions: Vec<PartitionConfig>,
+}
+
+/// Location of a partition in the disk image.
+#[derive(Clone, Copy, Debug)]
+pub struct PartitionExtent {
+    pub first_lba: u64,
+    pub sectors: u64,
+}
+
+impl PartitionExtent {
+    pub fn offset(&self) -> u64 {
+        self.first_lba * SECTOR_SIZE
+    }
+
+    pub fn len(&self) -> u64 {
+        self.sectors * SECTOR_SIZE
+    }
+}
+
+impl Default for ImageConfig {
+    /// Layout used when no configuration file is given: an unformatted root partition.
+    fn default() -> Self {
+        let partition = |name: &str, size: &str, content| PartitionConfig {
+            name: String::from(name),
+            size: String::from(size),
+            content,
+            filesystem: None,
+            label: None,
+            source: None,
+            files: vec![],
+            compression: None,
+        };
+        let kernelfs = PartitionConfig {
+            filesystem: Some(FilesystemKind::Ext4),
+            ..partition("kernelfs", "8M", PartitionContent::Kernel)
+        };
+
+        Self {
+            size: String::from("12M"),
+            table: PartitionTableKind::Gpt,
+            partitions: vec![
+                partition("fzboot", "1M", PartitionContent::Bootloader),
+                kernelfs,
+                partition("rootfs", "2M", PartitionContent::Data),
+            ],
+        }
+    }
+}
+
+impl ImageConfig {
+    /// Loads an image layout from a TOML file.
+    pub fn load(path: &Path) -> Result<Self, BuildError> {
+        let layout: Self = config::Config::builder()
+            .add_source(config::File::from(path))
+            .build()
+            .and_then(config::Config::try_deserialize)
+            .map_err(|err| {
+                BuildError(Some(format!(
+                    "Invalid image configuration {}: {err}",
+                    path.display()
+                )))
+            })?;
+
+        layout.validate()?;
+        Ok(layout)
+    }
+
+    /// Size of the disk image, in bytes.
+    pub fn size_bytes(&self) -> Result<u64, BuildError> {
+        parse_size(&self.size)
+    }
+
+    fn validate(&self) -> Result<(), BuildError> {
+        let count = |content| {
+            self.partitions
+                .iter()
+                .filter(|partition| partition.content == content)
+                .count()
+        };
+
+        if count(PartitionContent::Bootloader) != 1
+            || !(1..=KERNEL_SLOTS).contains(&count(PartitionContent::Kernel))
+        {
+            return Err(BuildError(Some(format!(
+                "The image must contain exactly one bootloader partition, and one to {KERNEL_SLOTS} kernel partitions"
+            ))));
+        }
+
+        let kernels_have_filesystem = self.partitions.iter().all(|partition| {
+            partition.content != PartitionContent::Kernel || partition.filesystem.is_some()
+        });
+        if !kernels_have_filesystem {
+            return Err(BuildError(Some(format!(
+                "Kernel partitions must have a filesystem, to store the kernel image at {KERNEL_IMAGE_PATH}"
+            ))));
+        }
+
+        if self.table == PartitionTableKind::Mbr && self.partitions.len() > MBR_MAX_PARTITIONS {
+            return Err(BuildError(Some(format!(
+                "A MBR image cannot hold more than {MBR_MAX_PARTITIONS} partitions"
+            ))));
+        }
+
+        for partition in &self.partitions {
+            parse_size(&partition.size)?;
+
+            let has_content = partition.source.is_some() || !partition.files.is_empty();
+            if has_content && partition.filesystem.is_none() {
+                return Err(BuildError(Some(format!(
+                    "Partition {} has files to copy but no filesystem",
+                    partition.name
+                ))));
+            }
+
+            if partition.compression.is_some() && partition.content != PartitionContent::Kernel {
+                return Err(BuildError(Some(format!(
+                    "Partition {} is compressed, but only the kernel image can be",
+                    partition.name
+                ))));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a MBR partition table, laying out the partitions one after the other.
+///
+/// Returns the location of each partition.
+pub fn write_mbr_partitions(
+    disk_image: &fs::File,
+    layout: &ImageConfig,
+) -> Result<Vec<PartitionExtent>, BuildError> {
+    use std::os::unix::fs::FileExt;
+
+    let disk_sectors = layout.size_bytes()? / SECTOR_SIZE;
+    let mut extents = vec![];
+    let mut first_lba = MBR_PARTITION_ALIGNMENT;
+
+    for (i, partition) in layout.partitions.iter().enumerate() {
+        let sectors = parse_size(&partition.size)?.div_ceil(SECTOR_SIZE);
+        if first_lba + sectors > disk_sectors {
+            return Err(BuildError(Some(format!(
+                "Partition {} does not fit in the disk image",
+                partition.name
+            ))));
+        }
+
+        let system_id = match (partition.content, partition.filesystem) {
+            (PartitionContent::Data, Some(FilesystemKind::Ext4)) => 0x83,
+            _ => 0xda,
+        };
+
+        let mut entry = [0u8; 16];
+        // CHS addresses are not used, and set to their maximum value.
+        entry[1..4].copy_from_slice(&[0xfe, 0xff, 0xff]);
+        entry[4] = system_id;
+        entry[5..8].copy_from_slice(&[0xfe, 0xff, 0xff]);
+        entry[8..12].copy_from_slice(&lba_u32(first_lba)?.to_le_bytes());
+        entry[12..16].copy_from_slice(&lba_u32(sectors)?.to_le_bytes());
+
+        disk_image
+            .write_at(&entry, MBR_PARTITION_TABLE_OFFSET + 16 * i as u64)
+            .map_err(|_| BuildError(None))?;
+
+        extents.push(PartitionExtent { first_lba, sectors });
+        first_lba = (first_lba + sectors).next_multiple_of(MBR_PARTITION_ALIGNMENT);
+    }
+
+    disk_image
+        .write_at(&[0x55, 0xaa], 510)
+        .map_err(|_| BuildError(None))?;
+
+    Ok(extents)
+}
+
+/// Creates the filesystem of a partition, populated with its files.
+pub fn format_partition(
+    disk_image: &Path,
+    partition: &PartitionConfig,
+    extent: PartitionExtent,
+) -> Result<(), BuildError> {
+    let Some(FilesystemKind::Ext4) = partition.filesystem else {
+        return Ok(());
+    };
+
+    let staging = stage_partition_files(partition)?;
+    let label = partition.label.as_deref().unwrap_or(&partition.name);
+
+    let mke2fs = Command::new("mke2fs")
+        .args(["-q", "-F", "-t", "ext4", "-b", "4096", "-L", label])
+        .arg("-d")
+        .arg(&staging)
+        .args(["-E", &format!("offset={}", extent.offset())])
+        .arg(disk_image)
+        .arg(format!("{}k", extent.len() / 1024))
+        .output()
+        .map_err(|_| BuildError(Some(String::from("Could not run mke2fs"))))?;
+
+    if !mke2fs.status.success() {
+        return Err(BuildError(Some(
+            String::from_utf8_lossy(&mke2fs.stderr).to_string(),
+        )));
+    }
+
+    Ok(())
+}
+
+/// Copies the `source` directory and the `files` of a partition into a staging directory.
+fn stage_partition_files(partition: &PartitionConfig) -> Result<PathBuf, BuildError> {
+    let staging = Path::new(STAGING_DIR).join(&partition.name);
+    if staging.exists() {
+        fs::remove_dir_all(&staging).map_err(io_error(&staging))?;
+    }
+    fs::create_dir_all(&staging).map_err(io_error(&staging))?;
+
+    if let Some(source) = &partition.source {
+        copy_dir(source, &staging)?;
+    }
+
+    for file in &partition.files {
+        let dest = staging.join(file.dest.strip_prefix("/").unwrap_or(&file.dest));
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(io_error(parent))?;
+        }
+
+        fs::copy(&file.src, &dest).map_err(io_error(&file.src))?;
+    }
+
+    Ok(staging)
+}
+
+fn copy_dir(src: &Path, dest: &Path) -> Result<(), BuildError> {
+    for entry in fs::read_dir(src).map_err(io_error(src))? {
+        let entry = entry.map_err(io_error(src))?;
+        let src_path = entry.path();
+        let dest_path = dest.join(entry.file_name());
+
+        if entry.file_type().map_err(io_error(&src_path))?.is_dir() {
+            fs::create_dir_all(&dest_path).map_err(io_error(&dest_path))?;
+            copy_dir(&src_path, &dest_path)?;
+        } else {
+            fs::copy(&src_path, &dest_path).map_err(io_error(&src_path))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds an error reporting a failed filesystem operation on `path`.
+fn io_error(path: &Path) -> impl Fn(std::io::Error) -> BuildError + '_ {
+    move |err| BuildError(Some(format!("{}: {err}", path.display())))
+}
+
+/// Parses a size, with an optional `K`, `M` or `G` binary suffix.
+pub fn parse_size(size: &str) -> Result<u64, BuildError> {
+    let size = size.trim();
+    let (digits, multiplier) = match size.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&size[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&size[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|digits| digits.checked_mul(multiplier))
+        .ok_or(BuildError(Some(format!("Invalid size: {size}"))))
+}
+
+fn lba_u32(lba: u64) -> Result<u32, BuildError> {
+    u32::try_from(lba)
+        .map_err(|_| BuildError(Some(String::from("Disk image is too large for MBR"))))
+}
@@ -0,0 +1,192 @@
+use std::{
+    fmt::Display,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    process::{Command, Stdio},
+    str::FromStr,
+    time::SystemTime,
+};
+
+use async_trait::async_trait;
+use crossbeam::channel::Sender;
+
+use crate::{
+    components::build::{BuildEvent, BuildResult, BuildStep},
+    errors::BuildError,
+};
+
+const QEMU_BINARY: &str = "qemu-system-x86_64";
+
+/// Port on which QEMU waits for a debugger when started with `-s`.
+const QEMU_GDB_PORT: u16 = 1234;
+
+/// Interface through which the disk image is attached to the virtual machine.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DiskInterface {
+    #[default]
+    Ahci,
+    Ide,
+    Virtio,
+}
+
+impl FromStr for DiskInterface {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ahci" => Ok(Self::Ahci),
+            "ide" => Ok(Self::Ide),
+            "virtio" => Ok(Self::Virtio),
+            _ => Err(format!(
+                "unknown disk interface `{s}` (expected ahci, ide or virtio)"
+            )),
+        }
+    }
+}
+
+impl Display for DiskInterface {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ahci => write!(f, "ahci"),
+            Self::Ide => write!(f, "ide"),
+            Self::Virtio => write!(f, "virtio"),
+        }
+    }
+}
+
+pub struct QemuRunConfig {
+    pub disk_img: PathBuf,
+
+    /// Amount of memory of the virtual machine, using the QEMU syntax (for instance `512M`).
+    pub memory: String,
+    pub disk: DiskInterface,
+
+    /// Waits for a debugger to attach before starting the virtual machine (`-s -S`).
+    pub gdb: bool,
+
+    /// Disables the graphical display.
+    pub headless: bool,
+
+    /// Additional arguments, passed verbatim to QEMU.
+    pub extra_args: Vec<String>,
+}
+
+/// Launches the disk image in QEMU, and streams the output of its serial port.
+pub struct QemuRun {
+    pub config: QemuRunConfig,
+}
+
+impl QemuRun {
+    pub fn new(config: QemuRunConfig) -> Self {
+        Self { config }
+    }
+
+    fn command(&self) -> Command {
+        let disk_img = self.config.disk_img.display();
+        let mut qemu = Command::new(QEMU_BINARY);
+
+        qemu.args(["-m", &self.config.memory])
+            .args(["-serial", "stdio"])
+            .arg("-no-reboot");
+
+        match self.config.disk {
+            DiskInterface::Ahci => qemu
+                .args([
+                    "-drive",
+                    &format!("id=disk,file={disk_img},format=raw,if=none"),
+                ])
+                .args(["-device", "ahci,id=ahci"])
+                .args(["-device", "ide-hd,drive=disk,bus=ahci.0"]),
+            DiskInterface::Ide => {
+                qemu.args(["-drive", &format!("file={disk_img},format=raw,if=ide")])
+            }
+            DiskInterface::Virtio => {
+                qemu.args(["-drive", &format!("file={disk_img},format=raw,if=virtio")])
+            }
+        };
+
+        if self.config.headless {
+            qemu.args(["-display", "none"]);
+        }
+
+        if self.config.gdb {
+            qemu.args(["-s", "-S"]);
+        }
+
+        qemu.args(&self.config.extra_args);
+
+        qemu
+    }
+}
+
+#[async_trait]
+impl BuildStep for QemuRun {
+    fn steps_count(&self) -> usize {
+        1
+    }
+
+    async fn build(&mut self, master: Sender<BuildEvent>) -> BuildResult {
+        let start = SystemTime::now();
+
+        let mut qemu = self
+            .command()
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                let msg = format!("Failed to launch {QEMU_BINARY}");
+                master
+                    .send(BuildEvent::StepFailed(msg.clone(), err.to_string()))
+                    .ok();
+                BuildError(Some(msg))
+            })?;
+
+        master
+            .send(BuildEvent::Update(format!(
+                "Running {} (disk = {}, memory = {})",
+                self.config.disk_img.display(),
+                self.config.disk,
+                self.config.memory
+            )))
+            .ok();
+
+        if self.config.gdb {
+            master
+                .send(BuildEvent::Update(format!(
+                    "Waiting for gdb on localhost:{QEMU_GDB_PORT}"
+                )))
+                .ok();
+        }
+
+        let serial = qemu.stdout.take().ok_or(BuildError(None))?;
+        for line in BufReader::new(serial).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+
+            master.send(BuildEvent::Serial(line)).ok();
+        }
+
+        let output = qemu.wait_with_output().map_err(|_| BuildError(None))?;
+        if !output.status.success() {
+            let msg = format!("QEMU exited with {}", output.status);
+            master
+                .send(BuildEvent::StepFailed(
+                    msg.clone(),
+                    String::from_utf8_lossy(&output.stderr).to_string(),
+                ))
+                .ok();
+            return Err(BuildError(Some(msg)));
+        }
+
+        master
+            .send(BuildEvent::StepFinished(
+                String::from("qemu"),
+                start.elapsed().unwrap().as_micros() as usize,
+            ))
+            .ok();
+
+        Ok(())
+    }
+}
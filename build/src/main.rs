@@ -16,7 +16,12 @@ use crossterm::{
 use parking_lot::Mutex;
 use ratatui::{prelude::CrosstermBackend, Terminal, TerminalOptions, Viewport};
 
+use crate::cli::app::{AppCommand, RunCommand};
 use crate::components::build::{ImageDiskBuild, ImageDiskBuildConfig};
+use crate::components::image::ImageConfig;
+use crate::components::qemu::{QemuRun, QemuRunConfig};
+use crate::errors::BuildError;
+use crate::ui::headless::HeadlessBuild;
 use crate::{
     cli::app::{run_app, App},
     components::build::{BootloaderBuild, BootloaderBuildConfig},
@@ -30,14 +35,21 @@ pub mod ui;
 
 pub static BOOTLOADER_BUILD: OnceCell<Arc<Mutex<BootloaderBuild>>> = OnceCell::uninit();
 pub static IMAGE_DISK_BUILD: OnceCell<Arc<Mutex<ImageDiskBuild>>> = OnceCell::uninit();
+pub static QEMU_RUN: OnceCell<Arc<Mutex<QemuRun>>> = OnceCell::uninit();
 pub static TERMINAL: OnceCell<Arc<Mutex<Terminal<CrosstermBackend<Stdout>>>>> = OnceCell::uninit();
 pub static APP: OnceCell<Arc<Mutex<App>>> = OnceCell::uninit();
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let app: App = argh::from_env();
+    let no_tui = app.no_tui;
     APP.init_once(|| Arc::new(Mutex::new(app)));
 
+    if no_tui {
+        let success = run_headless()?;
+        std::process::exit(i32::from(!success));
+    }
+
     let stdout = io::stdout();
     let backend = CrosstermBackend::new(stdout);
     let term = Terminal::with_options(
@@ -57,40 +69,31 @@ async fn main() -> Result<(), Box<dyn Error>> {
     }));
 
     let mut app = APP.get().unwrap().lock();
-    if app.standalone && app.fast {
+    let layout = load_image_layout(&app)?;
+    let incremental = !app.rebuild;
+
+    if let Some(AppCommand::Run(run)) = app.command.take() {
         drop(app);
-        let boot_img = String::from("artifacts/boot.img");
-        let kernel_img = String::from("artifacts/kernel.img");
-        let parts = vec!["main", "kernel"];
-        let cfg = BootloaderBuildConfig::new(
-            kernel_img.clone(),
-            boot_img.clone(),
-            String::from("src/fzboot/$name"),
-            String::from("target/$name/x86_64-fbios/release/$name.bin"),
-            parts,
-        );
-        let img_cfg = ImageDiskBuildConfig {
-            disk_img: String::from("fzkernel.img").into(),
-            build_img: boot_img.into(),
-            kernel_img: kernel_img.into(),
-        };
-        let build = BootloaderBuild::new(cfg);
-        let img_disk_build = ImageDiskBuild::new(img_cfg);
-
-        BOOTLOADER_BUILD.init_once(|| Arc::new(Mutex::new(build)));
-        IMAGE_DISK_BUILD.init_once(|| Arc::new(Mutex::new(img_disk_build)));
+
+        if !run.no_build {
+            init_default_build(layout, incremental);
+        }
+        init_qemu_run(run);
 
         let ui = BuildUI::default();
         ui.run();
 
-        disable_raw_mode()?;
-        let mut term_guard = TERMINAL.get().expect("Failed to load terminal").lock();
-        let term = &mut *term_guard;
+        return restore_terminal();
+    }
+
+    if app.standalone && app.fast {
+        drop(app);
+        init_default_build(layout, incremental);
 
-        execute!(term.backend_mut(), DisableMouseCapture)?;
-        term.show_cursor()?;
+        let ui = BuildUI::default();
+        ui.run();
 
-        return Ok(());
+        return restore_terminal();
     }
     drop(app);
 
@@ -112,3 +115,89 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+/// Path of the disk image built using the default parameters.
+const DEFAULT_DISK_IMG: &str = "fzkernel.img";
+
+/// Builds the bootloader, the kernel and the disk image using the default parameters, and the given image layout.
+///
+/// Unless `incremental` is false, parts whose sources did not change are not rebuilt.
+fn init_default_build(layout: ImageConfig, incremental: bool) {
+    let boot_img = String::from("artifacts/boot.img");
+    let kernel_img = String::from("artifacts/kernel.elf");
+    let parts = vec!["main", "kernel"];
+    let mut cfg = BootloaderBuildConfig::new(
+        kernel_img.clone(),
+        boot_img.clone(),
+        String::from("src/fzboot/$name"),
+        String::from("target/$name/x86_64-fbios/release/$name.bin"),
+        parts,
+    );
+    cfg.incremental = incremental;
+    let img_cfg = ImageDiskBuildConfig {
+        disk_img: String::from(DEFAULT_DISK_IMG).into(),
+        layout,
+        build_img: boot_img.into(),
+        bootstrap_bin: String::from("artifacts/boot.bin").into(),
+        kernel_img: kernel_img.into(),
+    };
+    let build = BootloaderBuild::new(cfg);
+    let img_disk_build = ImageDiskBuild::new(img_cfg);
+
+    BOOTLOADER_BUILD.init_once(|| Arc::new(Mutex::new(build)));
+    IMAGE_DISK_BUILD.init_once(|| Arc::new(Mutex::new(img_disk_build)));
+}
+
+fn load_image_layout(app: &App) -> Result<ImageConfig, BuildError> {
+    match app.image.as_deref() {
+        Some(path) => ImageConfig::load(path),
+        None => Ok(ImageConfig::default()),
+    }
+}
+
+/// Builds the disk image (and runs it, with the `run` subcommand) without the terminal interface.
+///
+/// Returns whether every step succeeded.
+fn run_headless() -> Result<bool, Box<dyn Error>> {
+    let mut app = APP.get().unwrap().lock();
+    let layout = load_image_layout(&app)?;
+    let incremental = !app.rebuild;
+    let command = app.command.take();
+    drop(app);
+
+    match command {
+        Some(AppCommand::Run(run)) => {
+            if !run.no_build {
+                init_default_build(layout, incremental);
+            }
+            init_qemu_run(run);
+        }
+        None => init_default_build(layout, incremental),
+    }
+
+    Ok(HeadlessBuild::default().run())
+}
+
+fn init_qemu_run(run: RunCommand) {
+    let qemu_cfg = QemuRunConfig {
+        disk_img: String::from(DEFAULT_DISK_IMG).into(),
+        memory: run.memory,
+        disk: run.disk,
+        gdb: run.gdb,
+        headless: run.headless,
+        extra_args: run.qemu_arg,
+    };
+
+    QEMU_RUN.init_once(|| Arc::new(Mutex::new(QemuRun::new(qemu_cfg))));
+}
+
+fn restore_terminal() -> Result<(), Box<dyn Error>> {
+    disable_raw_mode()?;
+    let mut term_guard = TERMINAL.get().expect("Failed to load terminal").lock();
+    let term = &mut *term_guard;
+
+    execute!(term.backend_mut(), DisableMouseCapture)?;
+    term.show_cursor()?;
+
+    Ok(())
+}
@@ -16,8 +16,7 @@ use ratatui::{
 
 use crate::{
     components::build::{BuildBlueprint, BuildEvent},
-    errors::BuildError,
-    APP, BOOTLOADER_BUILD, IMAGE_DISK_BUILD, TERMINAL,
+    APP, BOOTLOADER_BUILD, IMAGE_DISK_BUILD, QEMU_RUN, TERMINAL,
 };
 
 #[derive(Default)]
@@ -31,10 +30,18 @@ impl BuildUI {
         enable_raw_mode()?;
 
         let mut blueprint = BuildBlueprint::default();
-        let mut boot_step = BOOTLOADER_BUILD.get().ok_or(BuildError(None))?.lock();
-        let mut image_disk_step = IMAGE_DISK_BUILD.get().ok_or(BuildError(None))?.lock();
-        blueprint.steps.push(&mut *boot_step);
-        blueprint.steps.push(&mut *image_disk_step);
+        let mut boot_step = BOOTLOADER_BUILD.get().map(|build| build.lock());
+        let mut image_disk_step = IMAGE_DISK_BUILD.get().map(|build| build.lock());
+        if let Some(boot_step) = &mut boot_step {
+            blueprint.steps.push(&mut **boot_step);
+        }
+        if let Some(image_disk_step) = &mut image_disk_step {
+            blueprint.steps.push(&mut **image_disk_step);
+        }
+        let mut qemu_step = QEMU_RUN.get().map(|qemu| qemu.lock());
+        if let Some(qemu_step) = &mut qemu_step {
+            blueprint.steps.push(&mut **qemu_step);
+        }
         self.steps_count = blueprint.steps_count();
 
         let receiver = blueprint.incoming.clone();
@@ -73,6 +80,15 @@ impl BuildUI {
                             .render(buf.area, buf);
                         })?;
                     }
+                    BuildEvent::Serial(line) => {
+                        term.insert_before(1, |buf| {
+                            Paragraph::new(Line::from(vec![
+                                Span::styled("│ ", Style::default().fg(Color::DarkGray)),
+                                Span::from(line),
+                            ]))
+                            .render(buf.area, buf);
+                        })?;
+                    }
                     BuildEvent::StepFailed(msg, output) => {
                         term.insert_before(2, |buf| {
                                 Paragraph::new(vec![Line::from(vec![
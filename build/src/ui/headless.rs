@@ -0,0 +1,130 @@
+//! Headless build, for CI pipelines and scripts.
+//!
+//! Runs the same steps as [`BuildUI`](crate::ui::build::BuildUI), but prints one JSON record per line on the standard
+//! output instead of drawing the progress:
+//!
+//! ```text
+//! {"event":"start","steps":5}
+//! {"event":"step","name":"mbr","duration_us":1832,"finished":1,"total":5}
+//! {"event":"failed","message":"Failed to build frozenboot 0.1.0","output":"..."}
+//! {"event":"result","success":false,"duration_us":92113,"error":"..."}
+//! ```
+
+use std::{io::Write, thread, time::SystemTime};
+
+use serde::Serialize;
+
+use crate::{
+    components::build::{BuildBlueprint, BuildEvent},
+    BOOTLOADER_BUILD, IMAGE_DISK_BUILD, QEMU_RUN,
+};
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Record {
+    Start {
+        steps: usize,
+    },
+    Update {
+        message: String,
+    },
+    Step {
+        name: String,
+        duration_us: usize,
+        finished: usize,
+        total: usize,
+    },
+    Serial {
+        line: String,
+    },
+    Failed {
+        message: String,
+        output: String,
+    },
+    Result {
+        success: bool,
+        duration_us: u128,
+        error: Option<String>,
+    },
+}
+
+impl Record {
+    fn emit(&self) {
+        let mut stdout = std::io::stdout().lock();
+
+        if let Ok(record) = serde_json::to_string(self) {
+            writeln!(stdout, "{record}").ok();
+            stdout.flush().ok();
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HeadlessBuild {}
+
+impl HeadlessBuild {
+    /// Runs the build steps, and returns whether they all succeeded.
+    pub fn run(self) -> bool {
+        let start = SystemTime::now();
+
+        let mut blueprint = BuildBlueprint::default();
+        let mut boot_step = BOOTLOADER_BUILD.get().map(|build| build.lock());
+        let mut image_disk_step = IMAGE_DISK_BUILD.get().map(|build| build.lock());
+        let mut qemu_step = QEMU_RUN.get().map(|qemu| qemu.lock());
+        if let Some(boot_step) = &mut boot_step {
+            blueprint.steps.push(&mut **boot_step);
+        }
+        if let Some(image_disk_step) = &mut image_disk_step {
+            blueprint.steps.push(&mut **image_disk_step);
+        }
+        if let Some(qemu_step) = &mut qemu_step {
+            blueprint.steps.push(&mut **qemu_step);
+        }
+
+        let total = blueprint.steps_count();
+        Record::Start { steps: total }.emit();
+
+        let receiver = blueprint.get_receiver();
+        let printer = thread::spawn(move || {
+            let mut finished = 0;
+
+            // Ends once the blueprint, and every step holding a sender, is dropped.
+            while let Ok(event) = receiver.recv() {
+                let record = match event {
+                    BuildEvent::Update(message) => Record::Update { message },
+                    BuildEvent::StepFinished(name, duration_us) => {
+                        finished += 1;
+                        Record::Step {
+                            name,
+                            duration_us,
+                            finished,
+                            total,
+                        }
+                    }
+                    BuildEvent::Serial(line) => Record::Serial { line },
+                    BuildEvent::StepFailed(message, output) => Record::Failed { message, output },
+                    BuildEvent::Finished(_, _) => continue,
+                };
+
+                record.emit();
+            }
+        });
+
+        let result = futures::executor::block_on(blueprint.build());
+        drop(blueprint);
+        printer.join().ok();
+
+        Record::Result {
+            success: result.is_ok(),
+            duration_us: start.elapsed().unwrap_or_default().as_micros(),
+            error: result
+                .as_ref()
+                .err()
+                .map(ToString::to_string)
+                .filter(|error| !error.is_empty()),
+        }
+        .emit();
+
+        result.is_ok()
+    }
+}
@@ -4,5 +4,6 @@ pub mod build;
 pub mod component;
 pub mod config;
 pub mod footer;
+pub mod headless;
 pub mod main;
 pub mod steps;
@@ -0,0 +1,332 @@
+//! Real-mode bridge, used to call BIOS services from protected mode.
+//!
+//! The BIOS services are only available in real mode: the bootloader mostly calls them before switching to protected
+//! mode, from the real-mode stage. Some of them are still useful later on (for instance, to change the VESA video
+//! mode after the initial switch). [`bios_call`] drops back to real mode for the duration of a single software
+//! interrupt, and then returns to protected mode.
+//!
+//! The switch is done by a small trampoline, copied to [`REAL_BRIDGE_ADDR`] (real-mode code has to be located in the
+//! first megabyte). It saves the protected-mode state (registers, stack, `GDT` and `IDT`), goes through a 16-bit
+//! protected-mode segment to leave protected mode, loads the real-mode interrupt vector table, and restores the
+//! protected-mode state once the BIOS returns. Around the switch, [`bios_call`] also restores the BIOS interrupt
+//! vectors of the `PIC`, and holds the interrupts delivered through the `APIC` pending: the BIOS may enable
+//! interrupts, and would not be able to handle them.
+//!
+//! Data is exchanged with the BIOS through the buffer at [`REAL_BRIDGE_BUFFER`], located in the first segment.
+//!
+//! Only usable from the 32-bit protected-mode bootloader, while paging is disabled.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::bios::bridge::{bios_call, BiosRegisters};
+//!
+//! // INT 10h, AH=0Fh: get the current video mode.
+//! let mut regs = BiosRegisters {
+//!     eax: 0x0F00,
+//!     ..Default::default()
+//! };
+//!
+//! bios_call(0x10, &mut regs).unwrap();
+//! let mode = regs.eax as u8;
+//! ```
+
+use core::arch::{asm, global_asm};
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+
+use crate::errors::{BiosError, CanFail};
+use crate::io::pic::PIC;
+use crate::x86::apic::local_apic::local_apic_initialized;
+
+/// Linear address where the trampoline is copied.
+pub const REAL_BRIDGE_ADDR: u32 = 0x7000;
+
+/// Linear address of the buffer used to exchange data with the BIOS.
+pub const REAL_BRIDGE_BUFFER: u32 = 0x7800;
+
+/// Size of the buffer at [`REAL_BRIDGE_BUFFER`], in bytes.
+pub const REAL_BRIDGE_BUFFER_SIZE: usize = 0x400;
+
+/// Linear address of the [`BiosRegisters`] exchanged with the trampoline.
+const REAL_BRIDGE_REGS: u32 = 0x7600;
+
+/// Linear address of the protected-mode state saved by the trampoline: stack pointer, `GDTR` and `IDTR`.
+const REAL_BRIDGE_SAVE: u32 = 0x7640;
+
+/// Top of the real-mode stack, which grows down towards the VESA buffers.
+const REAL_BRIDGE_STACK: u32 = 0x7000;
+
+/// Maximum size of the trampoline.
+const REAL_BRIDGE_MAX_SIZE: usize = (REAL_BRIDGE_REGS - REAL_BRIDGE_ADDR) as usize;
+
+/// Vectors of the `PIC` interrupts used by the BIOS.
+const PIC_BIOS_OFFSETS: (u8, u8) = (0x08, 0x70);
+
+/// Vectors of the `PIC` interrupts in protected mode.
+const PIC_PROTECTED_OFFSETS: (u8, u8) = (0x20, 0x28);
+
+/// Task priority holding every `APIC` interrupt pending (except for `NMIs` and `ExtINT`, used by the `PIC`).
+const APIC_TPR_BLOCK_ALL: u8 = 0xF0;
+
+static BRIDGE_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+static BRIDGE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Registers passed to, and returned by, a BIOS service.
+///
+/// Segment registers other than `DS` and `ES` are zero when the service is called.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct BiosRegisters {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+    pub esi: u32,
+    pub edi: u32,
+    pub ebp: u32,
+    pub ds: u16,
+    pub es: u16,
+
+    /// `FLAGS` register when the service returned (ignored when calling it).
+    pub flags: u16,
+}
+
+impl BiosRegisters {
+    /// Whether the carry flag was set by the service, which usually reports an error.
+    pub fn carry(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    /// Value of `AH` (the status code returned by most services).
+    pub fn ah(&self) -> u8 {
+        (self.eax >> 8) as u8
+    }
+}
+
+// The trampoline is position-dependent: it is assembled as data, and only runs once copied to `REAL_BRIDGE_ADDR`.
+// It is entered with a `call` from 32-bit protected mode, using flat segments (selectors 0x08 and 0x10).
+global_asm!(
+    r#"
+.pushsection .rodata.real_bridge, "a"
+.code32
+.global real_bridge_start
+real_bridge_start:
+    pushfd
+    cli
+    pushad
+    mov [{save}], esp
+    sgdt [{save} + 4]
+    sidt [{save} + 12]
+    lgdt [RB_GDTR]
+    ljmp 0x18, offset RB_PM16
+
+.code16
+real_bridge_pm16:
+    mov ax, 0x20
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+    mov eax, cr0
+    and eax, 0xFFFFFFFE
+    mov cr0, eax
+    ljmp 0x0000, offset RB_REAL
+
+real_bridge_real:
+    xor ax, ax
+    mov ds, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+    mov sp, {stack}
+    lidt [RB_IVTR]
+
+    mov ax, [{regs} + 30]
+    mov es, ax
+    mov eax, [{regs}]
+    mov ebx, [{regs} + 4]
+    mov ecx, [{regs} + 8]
+    mov edx, [{regs} + 12]
+    mov esi, [{regs} + 16]
+    mov edi, [{regs} + 20]
+    mov ebp, [{regs} + 24]
+    push word ptr [{regs} + 28]
+    pop ds
+
+    sti
+.global real_bridge_int
+real_bridge_int:
+    int 0x00
+    cli
+
+    pushf
+    push ds
+    push es
+    push eax
+    xor ax, ax
+    mov ds, ax
+    pop eax
+    mov [{regs}], eax
+    mov [{regs} + 4], ebx
+    mov [{regs} + 8], ecx
+    mov [{regs} + 12], edx
+    mov [{regs} + 16], esi
+    mov [{regs} + 20], edi
+    mov [{regs} + 24], ebp
+    pop word ptr [{regs} + 30]
+    pop word ptr [{regs} + 28]
+    pop word ptr [{regs} + 32]
+
+    lgdt [RB_GDTR]
+    mov eax, cr0
+    or eax, 1
+    mov cr0, eax
+    ljmp 0x10, offset RB_PM32
+
+.code32
+real_bridge_pm32:
+    mov ax, 0x08
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+    lgdt [{save} + 4]
+    lidt [{save} + 12]
+    mov esp, [{save}]
+    popad
+    popfd
+    ret
+
+.balign 8
+real_bridge_gdt:
+    .quad 0
+    .quad 0x00cf93000000ffff
+    .quad 0x00cf9a000000ffff
+    .quad 0x00009a000000ffff
+    .quad 0x000093000000ffff
+real_bridge_gdtr:
+    .word 5 * 8 - 1
+    .long RB_GDT
+real_bridge_ivtr:
+    .word 0x3ff
+    .long 0
+.global real_bridge_end
+real_bridge_end:
+
+.set RB_PM16, {addr} + (real_bridge_pm16 - real_bridge_start)
+.set RB_REAL, {addr} + (real_bridge_real - real_bridge_start)
+.set RB_PM32, {addr} + (real_bridge_pm32 - real_bridge_start)
+.set RB_GDT, {addr} + (real_bridge_gdt - real_bridge_start)
+.set RB_GDTR, {addr} + (real_bridge_gdtr - real_bridge_start)
+.set RB_IVTR, {addr} + (real_bridge_ivtr - real_bridge_start)
+
+.popsection
+"#,
+    addr = const REAL_BRIDGE_ADDR,
+    regs = const REAL_BRIDGE_REGS,
+    save = const REAL_BRIDGE_SAVE,
+    stack = const REAL_BRIDGE_STACK,
+);
+
+extern "C" {
+    static real_bridge_start: u8;
+    static real_bridge_int: u8;
+    static real_bridge_end: u8;
+}
+
+/// Calls the BIOS service of the software interrupt `vector`, with the registers `regs`.
+///
+/// `regs` is updated with the registers returned by the service. The carry flag is not interpreted: most services
+/// report errors that way, but not all of them.
+///
+/// # Errors
+///
+/// Returns [`BiosError::BridgeUnavailable`] if paging is enabled.
+pub fn bios_call(vector: u8, regs: &mut BiosRegisters) -> CanFail<BiosError> {
+    if paging_enabled() {
+        return Err(BiosError::BridgeUnavailable);
+    }
+
+    let _guard = BRIDGE_LOCK.lock();
+    bridge_install();
+
+    let pic = PIC::default();
+    let (master_mask, slave_mask) = pic.masks();
+    let lapic = local_apic_initialized();
+    let task_priority = lapic
+        .as_ref()
+        .map(|lapic| lapic.set_task_priority(APIC_TPR_BLOCK_ALL));
+
+    unsafe {
+        ptr::write_volatile(bridge_int_vector(), vector);
+        ptr::write_volatile(REAL_BRIDGE_REGS as *mut BiosRegisters, *regs);
+
+        pic.remap(PIC_BIOS_OFFSETS.0, PIC_BIOS_OFFSETS.1);
+        pic.set_masks(master_mask, slave_mask);
+
+        let entry: extern "C" fn() = core::mem::transmute(REAL_BRIDGE_ADDR as usize);
+        entry();
+
+        pic.remap(PIC_PROTECTED_OFFSETS.0, PIC_PROTECTED_OFFSETS.1);
+        pic.set_masks(master_mask, slave_mask);
+
+        *regs = ptr::read_volatile(REAL_BRIDGE_REGS as *const BiosRegisters);
+    }
+
+    if let (Some(lapic), Some(task_priority)) = (lapic, task_priority) {
+        lapic.set_task_priority(task_priority);
+    }
+
+    Ok(())
+}
+
+/// Returns the buffer used to exchange data with the BIOS, at [`REAL_BRIDGE_BUFFER`].
+///
+/// Its real-mode address is `0000:REAL_BRIDGE_BUFFER`.
+///
+/// # Safety
+///
+/// The buffer is shared by every caller of [`bios_call`]: its content is only valid until the next call.
+pub unsafe fn bios_buffer() -> &'static mut [u8] {
+    core::slice::from_raw_parts_mut(REAL_BRIDGE_BUFFER as *mut u8, REAL_BRIDGE_BUFFER_SIZE)
+}
+
+/// Copies the trampoline to [`REAL_BRIDGE_ADDR`], if that was not done already.
+fn bridge_install() {
+    if BRIDGE_INSTALLED.load(Ordering::Acquire) {
+        return;
+    }
+
+    let start = unsafe { ptr::addr_of!(real_bridge_start) };
+    let size = unsafe { ptr::addr_of!(real_bridge_end) } as usize - start as usize;
+    assert!(
+        size <= REAL_BRIDGE_MAX_SIZE,
+        "real-mode bridge does not fit in low memory"
+    );
+
+    unsafe { ptr::copy_nonoverlapping(start, REAL_BRIDGE_ADDR as *mut u8, size) };
+    BRIDGE_INSTALLED.store(true, Ordering::Release);
+}
+
+/// Returns the address of the operand of the `int` instruction of the installed trampoline.
+fn bridge_int_vector() -> *mut u8 {
+    let offset = unsafe { ptr::addr_of!(real_bridge_int) } as usize
+        - unsafe { ptr::addr_of!(real_bridge_start) } as usize;
+
+    (REAL_BRIDGE_ADDR as usize + offset + 1) as *mut u8
+}
+
+fn paging_enabled() -> bool {
+    let cr0: u32;
+    unsafe {
+        asm!("mov {}, cr0", out(reg) cr0, options(nomem, nostack));
+    }
+
+    cr0 & (1 << 31) != 0
+}
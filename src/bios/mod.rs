@@ -1 +1,32 @@
-pub mod smbios;
+#[cfg(all(feature = "alloc", not(any(feature = "real", feature = "x86_64"))))]
+pub mod bridge;
+#[cfg(feature = "real")]
+pub mod services;
+
+/// Returns a short description of a status code returned in `AH` by the BIOS disk services (`INT 13h`).
+///
+/// Available outside of the real-mode stage, so that the errors it reported can be displayed later on.
+pub fn disk_status_name(status: u8) -> &'static str {
+    match status {
+        0x00 => "success",
+        0x01 => "invalid command",
+        0x02 => "address mark not found",
+        0x03 => "write protected",
+        0x04 => "sector not found",
+        0x05 => "reset failed",
+        0x07 => "drive parameter activity failed",
+        0x09 => "DMA boundary crossed",
+        0x0A => "bad sector",
+        0x0C => "unsupported track",
+        0x10 => "uncorrectable data error",
+        0x11 => "corrected data error",
+        0x20 => "controller failure",
+        0x40 => "seek failed",
+        0x80 => "timeout",
+        0xAA => "drive not ready",
+        0xBB => "undefined error",
+        0xCC => "write fault",
+        0xE0 => "status error",
+        _ => "unknown error",
+    }
+}
@@ -0,0 +1,137 @@
+//! A20 gate handling.
+//!
+//! The A20 line has to be enabled before switching to protected mode, otherwise every odd
+//! megabyte of memory is unreachable. Several methods are available, and none of them is
+//! guaranteed to work on every system, so they are tried one after the other:
+//!
+//! - the BIOS `INT 15h, AX=2401h` call
+//! - the 8042 keyboard controller output port
+//! - the "Fast A20" system control port (`0x92`)
+
+use core::{arch::asm, ptr};
+
+use crate::{
+    errors::{BiosError, CanFail},
+    io::{inb, io_delay, outb, ps2, IOPort},
+};
+
+const PS2_CMD_DISABLE_KBD: u8 = 0xAD;
+const PS2_CMD_ENABLE_KBD: u8 = 0xAE;
+const PS2_CMD_READ_OUTPUT: u8 = 0xD0;
+const PS2_CMD_WRITE_OUTPUT: u8 = 0xD1;
+
+const PS2_WAIT_LOOPS: u16 = 0xFFFF;
+
+/// Checks whether the A20 line is currently enabled.
+///
+/// Compares the byte at `0000:0500` with the byte at `FFFF:0510`, which are the same physical
+/// byte if the address wraps around at 1MB.
+pub fn a20_enabled() -> bool {
+    let low = 0x0500 as *mut u8;
+    let saved = unsafe { ptr::read_volatile(low) };
+    let wrapped: u8;
+
+    unsafe {
+        ptr::write_volatile(low, 0x00);
+
+        asm!(
+            "push ds",
+            "mov ax, 0xFFFF",
+            "mov ds, ax",
+            "mov BYTE PTR ds:[0x0510], 0xFF",
+            "pop ds",
+            out("ax") _,
+        );
+
+        wrapped = ptr::read_volatile(low);
+        ptr::write_volatile(low, saved);
+    }
+
+    wrapped != 0xFF
+}
+
+/// Attempts to enable the A20 line through the BIOS `INT 15h, AX=2401h` call.
+pub fn a20_enable_bios() -> CanFail<BiosError> {
+    let status: u16;
+
+    // INT 15h
+    // 2401h call: Enable A20 Gate
+    //
+    // Output: CF = Set on error
+    //         AH = Status
+    unsafe {
+        asm!(
+            "mov ax, 0x2401",
+            "int 0x15",
+            "setc al",
+            out("ax") status,
+        );
+    }
+
+    if status & 0xff != 0 {
+        return Err(BiosError::CallFailed((status >> 8) as u8));
+    }
+
+    Ok(())
+}
+
+/// Attempts to enable the A20 line through the 8042 keyboard controller output port.
+pub fn a20_enable_kbc() -> CanFail<BiosError> {
+    let timeout = |_| BiosError::Unsupported;
+
+    ps2::input_wait(PS2_WAIT_LOOPS).map_err(timeout)?;
+    ps2::send_ps2(PS2_CMD_DISABLE_KBD);
+
+    ps2::input_wait(PS2_WAIT_LOOPS).map_err(timeout)?;
+    ps2::send_ps2(PS2_CMD_READ_OUTPUT);
+
+    ps2::output_wait(PS2_WAIT_LOOPS).map_err(timeout)?;
+    let output_port = ps2::read_ps2();
+
+    ps2::input_wait(PS2_WAIT_LOOPS).map_err(timeout)?;
+    ps2::send_ps2(PS2_CMD_WRITE_OUTPUT);
+
+    ps2::input_wait(PS2_WAIT_LOOPS).map_err(timeout)?;
+    ps2::send_data(output_port | 0x2);
+
+    ps2::input_wait(PS2_WAIT_LOOPS).map_err(timeout)?;
+    ps2::send_ps2(PS2_CMD_ENABLE_KBD);
+
+    ps2::input_wait(PS2_WAIT_LOOPS).map_err(timeout)
+}
+
+/// Attempts to enable the A20 line through the "Fast A20" system control port.
+///
+/// Bit 0 of that port triggers a fast reset, so we make sure to never set it.
+pub fn a20_enable_fast() {
+    let ctrl = inb(IOPort::SYS_CTRL_A);
+
+    if ctrl & 0x2 != 0 {
+        return;
+    }
+
+    outb(IOPort::SYS_CTRL_A, (ctrl | 0x2) & !0x1);
+    io_delay();
+}
+
+/// Enables the A20 line, trying every available method until one of them succeeds.
+pub fn enable_a20() -> CanFail<BiosError> {
+    if a20_enabled() {
+        return Ok(());
+    }
+
+    if a20_enable_bios().is_ok() && a20_enabled() {
+        return Ok(());
+    }
+
+    if a20_enable_kbc().is_ok() && a20_enabled() {
+        return Ok(());
+    }
+
+    a20_enable_fast();
+    if a20_enabled() {
+        return Ok(());
+    }
+
+    Err(BiosError::A20Unavailable)
+}
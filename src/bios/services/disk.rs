@@ -1,9 +1,13 @@
 //! BIOS based utilities to read from disk.
 //!
-//! Uses either LBA addressing or CHS addressing.
+//! Uses either LBA addressing (`INT 13h` extensions) or CHS addressing.
 //! Used for early initialization of the bootloader from disk.
+
 use core::arch::asm;
 
+pub use crate::bios::disk_status_name;
+use crate::errors::{BiosError, CanFail};
+
 /// Checks if INT13h extensions are supported by the bios.
 #[inline]
 pub fn edd_ext_check(drive_number: u8) -> bool {
@@ -29,15 +33,12 @@ pub fn edd_ext_check(drive_number: u8) -> bool {
         "pop bx",
         "sbb ax, ax",
         in("dl") drive_number,
-        out("ax") cflag
+        out("ax") cflag,
+        out("cx") _,
         );
     }
 
-    if cflag == 0x00 {
-        return true;
-    }
-
-    false
+    cflag == 0x00
 }
 
 /// Resets the drive `drive_number`.
@@ -45,7 +46,9 @@ pub fn edd_ext_check(drive_number: u8) -> bool {
 ///
 /// Drive 0 is usually 0x80, drive 1 is 0x81 and so on.
 #[inline]
-pub fn drive_reset(driver_number: u8) {
+pub fn drive_reset(drive_number: u8) -> CanFail<BiosError> {
+    let status: u16;
+
     // INT 13h
     // 00h call: Reset Disk System
     //
@@ -58,9 +61,59 @@ pub fn drive_reset(driver_number: u8) {
         asm!(
         "xor ah, ah",
         "int 0x13",
-        in("dl") driver_number
+        "setc al",
+        in("dl") drive_number,
+        out("ax") status,
         )
     }
+
+    bios_status(status)
+}
+
+/// Geometry of a drive, as reported by the BIOS `INT 13h, AH=08h` call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskGeometry {
+    /// Number of cylinders.
+    pub cylinders: u16,
+
+    /// Number of heads.
+    pub heads: u16,
+
+    /// Number of sectors per track.
+    pub sectors_per_track: u8,
+}
+
+impl DiskGeometry {
+    /// Converts a LBA address to a CHS one, using this drive geometry.
+    ///
+    /// Returns `None` if the address cannot be reached through CHS addressing.
+    pub fn lba_to_chs(&self, lba: u32) -> Option<ChsAddress> {
+        let [cylinder, head, sector] =
+            lba_to_chs(lba, self.sectors_per_track, u8::try_from(self.heads).ok()?);
+
+        if cylinder >= u32::from(self.cylinders) || cylinder > 0x3ff {
+            return None;
+        }
+
+        Some(ChsAddress {
+            cylinder: u16::try_from(cylinder).ok()?,
+            head: u8::try_from(head).ok()?,
+            sector: u8::try_from(sector).ok()?,
+        })
+    }
+}
+
+/// A CHS ('Cylinder-Head-Sector') disk address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChsAddress {
+    /// Cylinder number (10 bits).
+    pub cylinder: u16,
+
+    /// Head number.
+    pub head: u8,
+
+    /// Sector number (6 bits, starts at 1).
+    pub sector: u8,
 }
 
 /// Converts a LBA address to a CHS one.
@@ -79,16 +132,23 @@ pub fn lba_to_chs(lba: u32, sectors_per_track: u8, heads_count: u8) -> [u32; 3]
 }
 
 /// Reads sectors from a disk, through CHS addressing.
+///
+/// Returns the number of sectors actually read.
 #[inline]
 pub fn read_sectors_from_drive(
     sectors_count: u8,
-    cylinder: u8,
-    sector: u8,
-    head: u8,
+    address: ChsAddress,
     segment: u16,
     offset: u16,
     drive_number: u8,
-) {
+) -> Result<u8, BiosError> {
+    let status: u16;
+    let carry: u8;
+
+    // Bits 8-9 of the cylinder number are stored in bits 6-7 of CL.
+    let cylinder_lo = (address.cylinder & 0xff) as u8;
+    let sector = (address.sector & 0x3f) | (((address.cylinder >> 2) & 0xc0) as u8);
+
     unsafe {
         // INT 13h
         // 02h call: Read Sectors From Drive
@@ -105,27 +165,36 @@ pub fn read_sectors_from_drive(
         //           AH = Return code
         //           AL = Actual Sectors Read Count
         asm!(
-        "mov ah, 0x2",
+        "push es",
         "mov es, di",
+        "mov ah, 0x2",
         "int 0x13",
+        "setc dl",
+        "pop es",
         in("di") segment,
-        in("al") sectors_count,
-        in("ch") cylinder,
-        in("cl") (sector | ((cylinder >> 2) & 0xc0)),
-        in("dh") head,
-        in("dl") drive_number,
+        inout("ax") u16::from(sectors_count) => status,
+        in("ch") cylinder_lo,
+        in("cl") sector,
+        in("dh") address.head,
+        inout("dl") drive_number => carry,
         in("bx") offset,
         );
     }
+
+    if carry != 0 {
+        return Err(BiosError::DiskFailed((status >> 8) as u8));
+    }
+
+    Ok((status & 0xff) as u8)
 }
 
-/// Returns information about the disk geometry, in an array with in order:
-///      - Number of heads
-///      - Sectors per track
+/// Returns information about the disk geometry of the drive `drive_number`.
 #[inline]
-pub fn disk_geometry(driver_number: u8) -> [u8; 2] {
+pub fn disk_geometry(drive_number: u8) -> Result<DiskGeometry, BiosError> {
+    let status: u16;
+    let cx: u16;
     let dh: u8;
-    let cl: u8;
+
     unsafe {
         // INT 13h
         // 08h call: Read Drive Parameters
@@ -141,10 +210,35 @@ pub fn disk_geometry(driver_number: u8) -> [u8; 2] {
         //         CX[0:5] = Sectors per track
         //         BL = Drive type
         //         ES:DI = pointer to drive parameter table (for floppy)
-        asm!("mov ah, 0x8", "int 0x13", out("dh") dh, out("cl") cl, in("dl") driver_number, options(nomem, nostack));
+        asm!(
+        "push es",
+        "push di",
+        "push bx",
+        "mov ah, 0x8",
+        "int 0x13",
+        "setc al",
+        "pop bx",
+        "pop di",
+        "pop es",
+        inout("dl") drive_number => _,
+        out("dh") dh,
+        out("cx") cx,
+        out("ax") status,
+        );
     }
 
-    [dh + 1, cl & 0x3f]
+    bios_status(status)?;
+
+    let sectors_per_track = (cx & 0x3f) as u8;
+    if sectors_per_track == 0 {
+        return Err(BiosError::InvalidData);
+    }
+
+    Ok(DiskGeometry {
+        cylinders: ((cx >> 8) | ((cx & 0xc0) << 2)) + 1,
+        heads: u16::from(dh) + 1,
+        sectors_per_track,
+    })
 }
 
 /// A Disk Address Packet.
@@ -195,9 +289,11 @@ impl AddressPacket {
     /// You can choose which drive to read from by indicating its drive number.
     ///
     /// Drive 0 is usually 0x80, drive 1 is 0x81 and so on.
+    ///
+    /// Possible error codes: <http://www.ctyme.com/intr/rb-0606.htm#Table234>
     #[inline]
-    pub fn disk_read(&self, drive_number: u8) {
-        let result: u8;
+    pub fn disk_read(&self, drive_number: u8) -> CanFail<BiosError> {
+        let status: u16;
         let dap_addr: *const AddressPacket = self;
 
         // INT 13h
@@ -214,27 +310,30 @@ impl AddressPacket {
             asm!(
                 "push si",
                 "push ds",
-                "mov ah, 0x42",
                 "mov si, cx",
-                "xor bx, bx",
-                "mov ds, bx",
+                "xor cx, cx",
+                "mov ds, cx",
+                "mov ah, 0x42",
                 "int 0x13",
+                "setc al",
                 "pop ds",
                 "pop si",
                 in("dl") drive_number,
-                in("cx") dap_addr,
-                out("ah") result
+                inout("cx") dap_addr as usize as u16 => _,
+                out("ax") status,
             )
         }
 
-        // The call was unsucessful.
-        //
-        // Usually, a failed disk read at that level is fatal, or at least
-        // we will assume it is.
-        //
-        // Possible error codes: <http://www.ctyme.com/intr/rb-0606.htm#Table234>
-        if result != 0x00 {
-            loop {}
-        }
+        bios_status(status)
+    }
+}
+
+/// Converts the `AX` value returned by a disk service into a [`BiosError`], assuming the carry
+/// flag was stored in `AL`.
+fn bios_status(ax: u16) -> CanFail<BiosError> {
+    if ax & 0xff != 0 {
+        return Err(BiosError::DiskFailed((ax >> 8) as u8));
     }
+
+    Ok(())
 }
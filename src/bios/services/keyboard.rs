@@ -0,0 +1,71 @@
+//! BIOS keyboard services (`INT 16h`).
+
+use core::arch::asm;
+
+/// A keystroke, as returned by the BIOS keyboard services.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyStroke {
+    /// BIOS scan code of the key.
+    pub scancode: u8,
+
+    /// ASCII character corresponding to the key (0 if none).
+    pub ascii: u8,
+}
+
+impl From<u16> for KeyStroke {
+    fn from(value: u16) -> Self {
+        Self {
+            scancode: (value >> 8) as u8,
+            ascii: (value & 0xff) as u8,
+        }
+    }
+}
+
+/// Waits for a keystroke, and removes it from the keyboard buffer.
+pub fn read_key() -> KeyStroke {
+    let key: u16;
+
+    // INT 16h
+    // 00h call: Get Keystroke
+    //
+    // Output: AH = BIOS scan code
+    //         AL = ASCII character
+    unsafe {
+        asm!("xor ah, ah", "int 0x16", out("ax") key);
+    }
+
+    KeyStroke::from(key)
+}
+
+/// Checks whether a keystroke is available, without removing it from the keyboard buffer.
+pub fn peek_key() -> Option<KeyStroke> {
+    let key: u16;
+    let empty: u8;
+
+    // INT 16h
+    // 01h call: Check For Keystroke
+    //
+    // Output: ZF = Set if no keystroke is available
+    //         AH = BIOS scan code
+    //         AL = ASCII character
+    unsafe {
+        asm!(
+            "mov ah, 0x01",
+            "int 0x16",
+            "setz cl",
+            out("ax") key,
+            out("cl") empty,
+        );
+    }
+
+    if empty != 0 {
+        return None;
+    }
+
+    Some(KeyStroke::from(key))
+}
+
+/// Waits for any key to be pressed.
+pub fn wait_key() {
+    let _ = read_key();
+}
@@ -0,0 +1,109 @@
+//! BIOS memory map services (`INT 15h, EAX=E820h`).
+
+use core::arch::asm;
+
+use crate::{errors::BiosError, mem::e820::AddressRangeDescriptor};
+
+/// Signature ('SMAP') expected by / returned from the `E820h` call.
+pub const E820_MAGIC: u32 = 0x534D_4150;
+
+/// Size of an entry returned by the `E820h` call, when ACPI 3.0 extended attributes are included.
+pub const E820_ENTRY_SIZE: u32 = 24;
+
+/// Queries a single entry of the system memory map.
+///
+/// `continuation` must be 0 for the first call, and then be the value returned by the
+/// previous call. The entry is written at the linear address `buffer` (first segment only).
+///
+/// Returns the continuation value for the next call, which is 0 once the last entry has been
+/// returned.
+pub fn e820_entry(continuation: u32, buffer: u16) -> Result<u32, BiosError> {
+    let next: u32;
+    let signature: u32;
+    let carry: u32;
+
+    // INT 15h
+    // E820h call: Query System Address Map
+    //
+    // Input:  EAX = 0xE820
+    //         EBX = Continuation value (0 for the first call)
+    //         ES:DI = Buffer pointer
+    //         ECX = Buffer size
+    //         EDX = 'SMAP' signature
+    //
+    // Output: CF = Set on error
+    //         EAX = 'SMAP' signature
+    //         EBX = Continuation value (0 if last entry)
+    //         ECX = Bytes written to the buffer
+    unsafe {
+        asm!(
+        "push es",
+        "push di",
+        "mov di, cx",
+        "xor cx, cx",
+        "mov es, cx",
+        "mov DWORD PTR es:[di + 20], 1",
+        "mov edx, 0x534D4150",
+        "mov eax, 0xe820",
+        "mov ecx, 24",
+        "int 0x15",
+        "setc dl",
+        "movzx edx, dl",
+        "pop di",
+        "pop es",
+        inout("ebx") continuation => next,
+        inout("ecx") u32::from(buffer) => _,
+        out("eax") signature,
+        out("edx") carry,
+        )
+    }
+
+    if carry != 0 {
+        return Err(BiosError::CallFailed(0));
+    }
+
+    if signature != E820_MAGIC {
+        return Err(BiosError::InvalidData);
+    }
+
+    Ok(next)
+}
+
+/// Fills `buffer` with the system memory map, returning the number of entries written.
+///
+/// Entries with a null length, or flagged as to be ignored through ACPI 3.0 extended
+/// attributes (bit 0 cleared), are skipped.
+///
+/// `buffer` must be located in the first segment of memory, as we rely on `ES = 0`.
+pub fn e820_memory_map(buffer: &mut [AddressRangeDescriptor]) -> Result<usize, BiosError> {
+    let mut continuation = 0;
+    let mut count = 0;
+
+    while count < buffer.len() {
+        let entry_ptr: *mut AddressRangeDescriptor = &mut buffer[count];
+        let entry_addr = u16::try_from(entry_ptr as usize).map_err(|_| BiosError::InvalidData)?;
+
+        continuation = match e820_entry(continuation, entry_addr) {
+            Ok(next) => next,
+
+            // Some BIOSes set the carry flag to signal the end of the list.
+            Err(_) if count != 0 => break,
+            Err(err) => return Err(err),
+        };
+
+        let entry = buffer[count];
+        if entry.length() != 0 && entry.extended_attributes.should_ignore() != 0 {
+            count += 1;
+        }
+
+        if continuation == 0 {
+            break;
+        }
+    }
+
+    if count == 0 {
+        return Err(BiosError::Unsupported);
+    }
+
+    Ok(count)
+}
@@ -0,0 +1,15 @@
+//! Real-mode BIOS services.
+//!
+//! Typed wrappers around the BIOS software interrupts used during the early stages of the
+//! bootloader (MBR and f-init stages). Every service that may fail returns a [`BiosError`]
+//! instead of hanging, so that the caller can decide whether a fallback is available.
+//!
+//! These wrappers can only be used while in real mode, or through a vm86 monitor.
+//!
+//! [`BiosError`]: crate::errors::BiosError
+
+pub mod a20;
+pub mod disk;
+pub mod keyboard;
+pub mod memory;
+pub mod video;
@@ -0,0 +1,131 @@
+//! BIOS video services (`INT 10h`), including the VBE extensions.
+
+use core::arch::asm;
+
+use crate::{
+    errors::{BiosError, CanFail},
+    video::vesa::video_mode::VBE_SUCCESS,
+};
+
+/// Sets a legacy VGA video mode (`INT 10h, AH=00h`).
+///
+/// Mode `0x03` is the standard 80x25 color text mode.
+pub fn set_vga_mode(mode: u8) {
+    unsafe {
+        asm!(
+            "xor ah, ah",
+            "int 0x10",
+            inout("ax") u16::from(mode) => _,
+        )
+    }
+}
+
+/// Writes a character to the screen in teletype mode (`INT 10h, AH=0Eh`).
+pub fn teletype_output(ch: u8) {
+    let reg: u16 = u16::from(ch) | 0x0e00;
+    unsafe {
+        asm!("push bx", "mov bx, 0", "int 0x10", "pop bx", inout("ax") reg => _);
+    }
+}
+
+/// Fills the `VbeInfoBlock` located at linear address `buffer` (first segment only).
+///
+/// The `VbeSignature` field of the block must be set to 'VBE2' beforehand to query VBE 2.0
+/// informations.
+pub fn vbe_controller_info(buffer: u16) -> CanFail<BiosError> {
+    let result: u16;
+
+    // INT 10H
+    // VBE 00h call: Return VBE Controller Information
+    //
+    // Input:  AX = 0x4f00
+    //         ES:DI = Pointer to a `VbeInfoBlock`
+    //                 structure
+    //
+    // Output: AX = VBE return status
+    unsafe {
+        asm!(
+            "push es",
+            "push di",
+            "mov di, ax",
+            "xor ax, ax",
+            "mov es, ax",
+            "mov ax, 0x4f00",
+            "int 0x10",
+            "pop di",
+            "pop es",
+            inout("ax") buffer => result
+        );
+    }
+
+    vbe_status(result)
+}
+
+/// Fills the `ModeInfoBlock` located at linear address `buffer` (first segment only) with
+/// information about the video mode `mode`.
+pub fn vbe_mode_info(mode: u16, buffer: u16) -> CanFail<BiosError> {
+    let result: u16;
+
+    // INT 10H
+    // VBE 01h call: Return VBE Mode Information
+    //
+    // Input:  AX = 0x4f01
+    //         CX = Mode number
+    //         ES:DI = Pointer to `ModeInfoBlock` structure
+    //
+    // Output: AX = VBE return status
+    unsafe {
+        asm!(
+            "push es",
+            "push di",
+            "mov di, ax",
+            "xor ax, ax",
+            "mov es, ax",
+            "mov ax, 0x4f01",
+            "int 0x10",
+            "pop di",
+            "pop es",
+            in("cx") mode,
+            inout("ax") buffer => result
+        );
+    }
+
+    vbe_status(result)
+}
+
+/// Sets the current VBE mode.
+///
+/// Bit 14 of the mode number enables the linear framebuffer, bit 15 prevents the display memory
+/// from being cleared.
+pub fn vbe_set_mode(mode: u16) -> CanFail<BiosError> {
+    let result: u16;
+
+    // INT 10H
+    // VBE 02h call: Set VBE Mode
+    //
+    // Input:  AX = 0x4f02
+    //         BX = Desired mode
+    //
+    // Output: AX = VBE return status
+    unsafe {
+        asm!(
+        "push ebx",
+        "mov ax, 0x4f02",
+        "mov bx, cx",
+        "int 0x10",
+        "pop ebx",
+        in("cx") mode,
+        out("ax") result
+        );
+    }
+
+    vbe_status(result)
+}
+
+fn vbe_status(result: u16) -> CanFail<BiosError> {
+    if result != VBE_SUCCESS {
+        return Err(BiosError::VbeFailed(result));
+    }
+
+    Ok(())
+}
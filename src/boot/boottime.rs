@@ -0,0 +1,154 @@
+//! Boot time instrumentation.
+//!
+//! Milestones of the boot are recorded with [`boottime_mark`], along with the value of the TSC when they are reached.
+//! The milestones of the bootloader ([`BootStage`]) are recorded automatically by [`report_stage`].
+//!
+//! The timeline can be displayed with [`boottime_report`], or written to the first serial port in a machine-readable
+//! form with [`boottime_export_serial`], to track the boot latency across builds:
+//!
+//! ```text
+//! boottime v1 tsc_hz=2400000000
+//! memory 1834201992 0
+//! acpi 1834876231 280
+//! end
+//! ```
+//!
+//! Each line holds the name of the milestone, the raw TSC value and the time elapsed since the first milestone, in
+//! microseconds (`-` if the TSC frequency is not known). As the TSC keeps counting when the kernel is started, the raw
+//! values of the bootloader and the kernel timelines can be compared.
+//!
+//! [`BootStage`]: crate::boot::progress::BootStage
+//! [`report_stage`]: crate::boot::progress::report_stage
+
+use core::fmt::{self, Write};
+use spin::Mutex;
+
+use crate::errors::{CanFail, SerialError};
+use crate::io::serial::COM1;
+use crate::x86::tsc::{rdtsc, TSC_CLK};
+
+/// Maximum number of milestones recorded. Milestones reached once the timeline is full are dropped.
+pub const BOOTTIME_MAX_MARKS: usize = 64;
+
+/// Version of the format written by [`boottime_export_serial`].
+const BOOTTIME_FORMAT_VERSION: u32 = 1;
+
+/// Baud rate of the serial port used by [`boottime_export_serial`].
+const BOOTTIME_EXPORT_BAUD: u32 = 115_200;
+
+static TIMELINE: Mutex<BootTimeline> = Mutex::new(BootTimeline::new());
+
+/// Milestone of the boot timeline.
+#[derive(Clone, Copy, Debug)]
+pub struct BootMark {
+    pub name: &'static str,
+
+    /// Value of the TSC when the milestone was reached.
+    pub tsc: u64,
+}
+
+/// Milestones recorded so far, in the order in which they were reached.
+#[derive(Clone, Copy, Debug)]
+pub struct BootTimeline {
+    marks: [BootMark; BOOTTIME_MAX_MARKS],
+    len: usize,
+}
+
+impl BootTimeline {
+    const fn new() -> Self {
+        Self {
+            marks: [BootMark { name: "", tsc: 0 }; BOOTTIME_MAX_MARKS],
+            len: 0,
+        }
+    }
+
+    pub fn marks(&self) -> &[BootMark] {
+        &self.marks[..self.len]
+    }
+
+    /// Time elapsed between the first milestone and `mark`, in microseconds, if the TSC frequency is known.
+    pub fn elapsed_us(&self, mark: &BootMark) -> Option<f64> {
+        let first = self.marks().first()?;
+
+        TSC_CLK
+            .get()
+            .map(|tsc| tsc.tsc_ticks_to_micro(mark.tsc.saturating_sub(first.tsc) as f64))
+    }
+}
+
+/// Records that the boot reached the milestone `name`.
+pub fn boottime_mark(name: &'static str) {
+    let tsc = rdtsc();
+    let mut timeline = TIMELINE.lock();
+
+    if timeline.len < BOOTTIME_MAX_MARKS {
+        let len = timeline.len;
+        timeline.marks[len] = BootMark { name, tsc };
+        timeline.len += 1;
+    }
+}
+
+/// Returns a copy of the milestones recorded so far.
+pub fn boottime_timeline() -> BootTimeline {
+    *TIMELINE.lock()
+}
+
+/// Writes the boot timeline, one milestone per line, with the time elapsed since the first milestone and since the
+/// previous one.
+pub fn boottime_report(out: &mut impl Write) -> fmt::Result {
+    let timeline = boottime_timeline();
+    let mut previous = None;
+
+    for mark in timeline.marks() {
+        let elapsed = timeline.elapsed_us(mark);
+
+        match (elapsed, previous) {
+            (Some(elapsed), Some(previous)) => writeln!(
+                out,
+                "{:<24} {:>10.3} ms  (+{:.3} ms)",
+                mark.name,
+                elapsed / 1000.,
+                (elapsed - previous) / 1000.
+            )?,
+            (Some(elapsed), None) => {
+                writeln!(out, "{:<24} {:>10.3} ms", mark.name, elapsed / 1000.)?
+            }
+            (None, _) => writeln!(out, "{:<24} tsc = {}", mark.name, mark.tsc)?,
+        }
+
+        previous = elapsed;
+    }
+
+    Ok(())
+}
+
+/// Writes the boot timeline to the first serial port, in the machine-readable format described in the
+/// [module documentation](self).
+///
+/// # Errors
+///
+/// Returns [`SerialError::NotPresent`] if the machine has no serial port.
+pub fn boottime_export_serial() -> CanFail<SerialError> {
+    let timeline = boottime_timeline();
+
+    let mut serial = COM1.lock();
+    if !serial.initialized() {
+        serial.init(BOOTTIME_EXPORT_BAUD)?;
+    }
+
+    let frequency = TSC_CLK.get().map_or(0, |tsc| tsc.frequency() as u64);
+    let _ = writeln!(
+        serial,
+        "boottime v{BOOTTIME_FORMAT_VERSION} tsc_hz={frequency}"
+    );
+
+    for mark in timeline.marks() {
+        let _ = match timeline.elapsed_us(mark) {
+            Some(elapsed) => writeln!(serial, "{} {} {:.0}", mark.name, mark.tsc, elapsed),
+            None => writeln!(serial, "{} {} -", mark.name, mark.tsc),
+        };
+    }
+    let _ = writeln!(serial, "end");
+
+    Ok(())
+}
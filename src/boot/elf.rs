@@ -0,0 +1,178 @@
+//! ELF64 executable images loading.
+//!
+//! Used by the bootloader to load the Kernel image: the loadable (`PT_LOAD`) segments are copied to physical memory,
+//! at an offset from the load address that matches their offset from the base virtual address of the image. The part
+//! of a segment that is not stored in the file (such as `.bss`) is zeroed.
+
+use bytemuck::{pod_read_unaligned, Pod, Zeroable};
+use core::mem::size_of;
+
+use crate::errors::ElfError;
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 62;
+
+const PT_LOAD: u32 = 1;
+
+/// ELF64 file header.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub struct ElfHeader {
+    pub ident: [u8; 16],
+    pub elf_type: u16,
+    pub machine: u16,
+    pub version: u32,
+    pub entry: u64,
+    pub phoff: u64,
+    pub shoff: u64,
+    pub flags: u32,
+    pub ehsize: u16,
+    pub phentsize: u16,
+    pub phnum: u16,
+    pub shentsize: u16,
+    pub shnum: u16,
+    pub shstrndx: u16,
+}
+
+/// ELF64 program header, describing a segment.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+/// An ELF64 executable image, for x86_64.
+#[derive(Debug)]
+pub struct ElfExecutable<'i> {
+    image: &'i [u8],
+    header: ElfHeader,
+}
+
+impl<'i> ElfExecutable<'i> {
+    /// Parses the file header of `image`, and checks that it is an executable for x86_64.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ElfError::InvalidImage`] if `image` is not a valid executable for x86_64.
+    pub fn parse(image: &'i [u8]) -> Result<Self, ElfError> {
+        let header: ElfHeader = read_struct(image, 0)?;
+
+        if header.ident[..4] != ELF_MAGIC
+            || header.ident[4] != ELFCLASS64
+            || header.ident[5] != ELFDATA2LSB
+            || header.elf_type != ET_EXEC
+            || header.machine != EM_X86_64
+            || usize::from(header.phentsize) != size_of::<ProgramHeader>()
+        {
+            return Err(ElfError::InvalidImage);
+        }
+
+        Ok(Self { image, header })
+    }
+
+    /// Virtual address of the entry point.
+    pub fn entry(&self) -> u64 {
+        self.header.entry
+    }
+
+    /// Returns the headers of the loadable segments.
+    pub fn segments(&self) -> impl Iterator<Item = Result<ProgramHeader, ElfError>> + '_ {
+        let table = usize::try_from(self.header.phoff).unwrap_or(usize::MAX);
+
+        (0..usize::from(self.header.phnum))
+            .map(move |i| {
+                read_struct::<ProgramHeader>(
+                    self.image,
+                    table.saturating_add(i * size_of::<ProgramHeader>()),
+                )
+            })
+            .filter(|segment| segment.map_or(true, |segment| segment.p_type == PT_LOAD))
+    }
+
+    /// Returns the size of the image once loaded, from `base` (the virtual address corresponding to the load
+    /// address) to the end of the last segment.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a segment lies below `base`, or if the image has no loadable segment.
+    pub fn memory_size(&self, base: u64) -> Result<u64, ElfError> {
+        let mut end = None;
+
+        for segment in self.segments() {
+            let segment = segment?;
+            if segment.vaddr < base {
+                return Err(ElfError::InvalidSegment);
+            }
+
+            let segment_end = segment
+                .vaddr
+                .checked_add(segment.memsz)
+                .ok_or(ElfError::InvalidSegment)?;
+            end = end.max(Some(segment_end));
+        }
+
+        end.map(|end| end - base).ok_or(ElfError::NoLoadableSegment)
+    }
+
+    /// Copies the loadable segments to `dest`, which corresponds to the virtual address `base`.
+    ///
+    /// # Safety
+    ///
+    /// `dest` must point to at least [`memory_size`](Self::memory_size) bytes of memory that is free, and will not be
+    /// used anywhere else.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a segment lies below `base`, or outside of the image.
+    pub unsafe fn load(&self, dest: *mut u8, base: u64) -> Result<(), ElfError> {
+        for segment in self.segments() {
+            let segment = segment?;
+            let data = slice_at(self.image, segment.offset, segment.filesz)?;
+
+            if segment.filesz > segment.memsz {
+                return Err(ElfError::InvalidSegment);
+            }
+
+            let offset = segment
+                .vaddr
+                .checked_sub(base)
+                .and_then(|offset| usize::try_from(offset).ok())
+                .ok_or(ElfError::InvalidSegment)?;
+            let memsz = usize::try_from(segment.memsz).map_err(|_| ElfError::InvalidSegment)?;
+
+            let segment_mem = dest.add(offset);
+            core::ptr::copy_nonoverlapping(data.as_ptr(), segment_mem, data.len());
+            core::ptr::write_bytes(segment_mem.add(data.len()), 0, memsz - data.len());
+        }
+
+        Ok(())
+    }
+}
+
+fn slice_at(image: &[u8], offset: u64, len: u64) -> Result<&[u8], ElfError> {
+    let start = usize::try_from(offset).map_err(|_| ElfError::InvalidSegment)?;
+    let len = usize::try_from(len).map_err(|_| ElfError::InvalidSegment)?;
+
+    start
+        .checked_add(len)
+        .and_then(|end| image.get(start..end))
+        .ok_or(ElfError::InvalidSegment)
+}
+
+fn read_struct<T: Pod>(image: &[u8], offset: usize) -> Result<T, ElfError> {
+    offset
+        .checked_add(size_of::<T>())
+        .and_then(|end| image.get(offset..end))
+        .map(pod_read_unaligned)
+        .ok_or(ElfError::InvalidImage)
+}
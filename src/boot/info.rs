@@ -0,0 +1,277 @@
+//! FrozenBoot boot information structure.
+//!
+//! Every boot path (the BIOS bootloader, and any future one) hands a [`BootInfo`] structure over to the kernel, whose
+//! physical address is passed in `rcx`. It describes the memory map, the framebuffer, the ACPI `RSDP`, the command
+//! line and the boot modules.
+//!
+//! The structure starts with a magic number, its [version](BOOT_INFO_VERSION) and its size, and is protected by a
+//! checksum: the kernel refuses a structure produced by an incompatible bootloader, or that was overwritten, instead
+//! of reading garbage. New fields are only ever appended, and the version is bumped when an existing field changes.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::boot::info::BootInfo;
+//!
+//! let boot_info = unsafe { BootInfo::from_ptr(boot_info_ptr) }.expect("invalid boot information");
+//!
+//! if let Some(cmdline) = boot_info.cmdline() {
+//!     config_parse_cmdline(cmdline);
+//! }
+//! ```
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::boot::multiboot::mb_information::FramebufferMultibootInformation;
+use crate::errors::BootInfoError;
+use crate::mem::PhyAddr;
+
+/// Magic number at the beginning of the structure (`FZBI`).
+pub const BOOT_INFO_MAGIC: u32 = 0x4942_5A46;
+
+/// Version of the layout of [`BootInfo`].
+pub const BOOT_INFO_VERSION: u16 = 1;
+
+/// Maximum length of the name of a [`BootModule`], in bytes.
+pub const BOOT_MODULE_NAME_LEN: usize = 16;
+
+/// Information handed over by the bootloader to the kernel.
+///
+/// The bootloader fills the structure using the setters, and calls [`BootInfo::seal`] once done. The kernel reads it
+/// with [`BootInfo::from_ptr`].
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C, packed)]
+pub struct BootInfo {
+    magic: u32,
+    version: u16,
+
+    /// Size of the structure, in bytes.
+    size: u16,
+
+    /// Chosen so that the sum of the bytes of the structure is 0.
+    checksum: u8,
+    reserved: [u8; 3],
+
+    /// Physical address of the first entry of the `E820` memory map.
+    ///
+    /// The number of entries is also stored in the 4 bytes preceding the first one.
+    mmap_addr: u64,
+    mmap_entries: u32,
+
+    cmdline_len: u32,
+    cmdline_addr: u64,
+
+    rsdp_addr: u64,
+
+    /// Physical address of an array of [`BootModule`].
+    mods_addr: u64,
+    mods_count: u32,
+
+    framebuffer: FramebufferMultibootInformation,
+}
+
+/// A boot module, loaded in memory along with the kernel image.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub struct BootModule {
+    /// Physical address of the first byte of the module.
+    start: u64,
+
+    /// Size of the module, in bytes.
+    length: u64,
+
+    /// Name of the module, padded with zeros.
+    name: [u8; BOOT_MODULE_NAME_LEN],
+}
+
+impl BootModule {
+    /// Describes the module stored in `[start; start + length)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is longer than [`BOOT_MODULE_NAME_LEN`].
+    pub fn new(start: PhyAddr, length: u64, name: &str) -> Self {
+        assert!(
+            name.len() <= BOOT_MODULE_NAME_LEN,
+            "boot module name too long"
+        );
+
+        let mut module = Self {
+            start: u64::from(start),
+            length,
+            name: [0; BOOT_MODULE_NAME_LEN],
+        };
+        module.name[..name.len()].copy_from_slice(name.as_bytes());
+
+        module
+    }
+
+    /// Returns the name of the module.
+    pub fn name(&self) -> &str {
+        let length = self
+            .name
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap_or(BOOT_MODULE_NAME_LEN);
+
+        core::str::from_utf8(&self.name[..length]).unwrap_or_default()
+    }
+
+    /// Returns the content of the module.
+    ///
+    /// # Safety
+    ///
+    /// The module must still be mapped at its physical address, and must not have been overwritten.
+    pub unsafe fn data(&self) -> &'static [u8] {
+        core::slice::from_raw_parts(self.start as *const u8, self.length as usize)
+    }
+}
+
+impl BootInfo {
+    /// Creates an empty structure: every optional field is absent.
+    pub fn new() -> Self {
+        Self {
+            magic: BOOT_INFO_MAGIC,
+            version: BOOT_INFO_VERSION,
+            size: core::mem::size_of::<Self>() as u16,
+            ..Zeroable::zeroed()
+        }
+    }
+
+    /// Reads and validates the structure stored at `ptr`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BootInfoError`] if the magic number, the version, the size or the checksum of the structure is
+    /// invalid.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to readable memory, at least as large as a [`BootInfo`]. The memory referenced by the
+    /// structure (command line, boot modules) must still be mapped at its physical address while it is accessed.
+    pub unsafe fn from_ptr(ptr: *const u8) -> Result<Self, BootInfoError> {
+        let info = core::ptr::read_unaligned(ptr.cast::<Self>());
+
+        if info.magic != BOOT_INFO_MAGIC {
+            return Err(BootInfoError::InvalidMagic);
+        }
+
+        if info.version != BOOT_INFO_VERSION {
+            return Err(BootInfoError::UnsupportedVersion(info.version));
+        }
+
+        if usize::from(info.size) < core::mem::size_of::<Self>() {
+            return Err(BootInfoError::InvalidSize(info.size));
+        }
+
+        let bytes = core::slice::from_raw_parts(ptr, usize::from(info.size));
+        if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+            return Err(BootInfoError::InvalidChecksum);
+        }
+
+        Ok(info)
+    }
+
+    /// Computes the checksum of the structure. Must be called after the last field was set.
+    pub fn seal(&mut self) {
+        self.checksum = 0;
+
+        let sum = bytemuck::bytes_of(self)
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        self.checksum = sum.wrapping_neg();
+    }
+
+    /// Sets the address and the number of entries of the `E820` memory map.
+    pub fn set_memory_map(&mut self, addr: PhyAddr, entries: u32) {
+        self.mmap_addr = u64::from(addr);
+        self.mmap_entries = entries;
+    }
+
+    /// Returns the physical address of the first entry of the `E820` memory map.
+    pub fn memory_map_addr(&self) -> PhyAddr {
+        PhyAddr::new(self.mmap_addr)
+    }
+
+    /// Returns the number of entries of the `E820` memory map.
+    pub fn memory_map_entries(&self) -> usize {
+        self.mmap_entries as usize
+    }
+
+    /// Sets the framebuffer selected by the bootloader.
+    pub fn set_framebuffer(&mut self, framebuffer: FramebufferMultibootInformation) {
+        self.framebuffer = framebuffer;
+    }
+
+    /// Returns the framebuffer selected by the bootloader, if any.
+    pub fn framebuffer(&self) -> Option<FramebufferMultibootInformation> {
+        let framebuffer = self.framebuffer;
+
+        (u64::from(framebuffer.addr) != 0).then_some(framebuffer)
+    }
+
+    /// Sets the physical address of the ACPI `RSDP`.
+    pub fn set_rsdp(&mut self, addr: PhyAddr) {
+        self.rsdp_addr = u64::from(addr);
+    }
+
+    /// Returns the physical address of the ACPI `RSDP`, if the bootloader located it.
+    pub fn rsdp(&self) -> Option<PhyAddr> {
+        (self.rsdp_addr != 0).then(|| PhyAddr::new(self.rsdp_addr))
+    }
+
+    /// Sets the command line passed to the kernel, stored at `addr`.
+    pub fn set_cmdline(&mut self, addr: PhyAddr, length: u32) {
+        self.cmdline_addr = u64::from(addr);
+        self.cmdline_len = length;
+    }
+
+    /// Returns the command line passed to the kernel, if any.
+    pub fn cmdline(&self) -> Option<&'static str> {
+        if self.cmdline_addr == 0 {
+            return None;
+        }
+
+        let bytes = unsafe {
+            core::slice::from_raw_parts(self.cmdline_addr as *const u8, self.cmdline_len as usize)
+        };
+
+        core::str::from_utf8(bytes).ok()
+    }
+
+    /// Sets the address and the number of the [`BootModule`] structures describing the boot modules.
+    pub fn set_modules(&mut self, addr: PhyAddr, count: u32) {
+        self.mods_addr = u64::from(addr);
+        self.mods_count = count;
+    }
+
+    /// Returns the boot modules.
+    pub fn modules(&self) -> &'static [BootModule] {
+        if self.mods_addr == 0 {
+            return &[];
+        }
+
+        unsafe {
+            core::slice::from_raw_parts(
+                self.mods_addr as *const BootModule,
+                self.mods_count as usize,
+            )
+        }
+    }
+
+    /// Returns the content of the boot module named `name`, if any.
+    ///
+    /// Does not allocate memory: may be used before the heap is available.
+    pub fn module(&self, name: &str) -> Option<&'static [u8]> {
+        self.modules()
+            .iter()
+            .find(|module| module.name() == name)
+            .map(|module| unsafe { module.data() })
+    }
+}
+
+impl Default for BootInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
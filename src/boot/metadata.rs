@@ -0,0 +1,356 @@
+//! On-disk boot metadata sector.
+//!
+//! The image builder writes a [`BootMetadataSector`] at [`BOOT_METADATA_LBA`] of the boot disk, in the gap between the
+//! `GPT` partition entries and the first partition. It describes two kernel slots (`A` and `B`), each one being a
+//! partition holding a kernel image: the first sector of the partition, and the size and `CRC32` of the image file.
+//! The bootloader reads it before loading the kernel, and refuses to jump to an image that does not match it
+//! (truncated by an interrupted update, or corrupted on disk).
+//!
+//! Like the [boot information structure](crate::boot::info), the sector starts with a magic number, its
+//! [version](BOOT_METADATA_VERSION) and its size, and is protected by a checksum (`CRC32` of the structure, computed
+//! with the checksum field set to 0). Its layout must match the one written by the build tool
+//! (`build/src/components/build.rs`).
+//!
+//! # Kernel slots
+//!
+//! A new kernel is installed in the inactive slot (see [`BootMetadataSector::install_kernel`]), which becomes the
+//! active one. Until it is marked healthy, the bootloader counts the attempts to boot it: once the kernel finished
+//! initializing, it calls [`mark_boot_successful`], and the slot is marked healthy by the bootloader on the next
+//! boot. If the new kernel keeps failing to boot (panic, or hang caught by the watchdog), the bootloader rolls back
+//! to the previous slot after [`MAX_BOOT_ATTEMPTS`] attempts (see [`BootMetadataSector::begin_boot`]).
+//!
+//! The kernel has no disk driver, so it reports the successful boot through a byte of the `CMOS` memory, which the
+//! bootloader consumes with [`take_boot_success`].
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::boot::metadata::{take_boot_success, BootMetadataSector};
+//!
+//! let mut metadata = BootMetadataSector::read_from(&device).expect("invalid boot metadata");
+//! let slot = metadata.begin_boot(take_boot_success()).expect("no kernel to boot");
+//! metadata.write_to(&device).expect("failed to update boot metadata");
+//! ```
+
+use alloc::vec;
+use bytemuck::{pod_read_unaligned, Pod, Zeroable};
+
+use crate::drivers::generics::dev_disk::DiskDevice;
+use crate::errors::BootMetadataError;
+use crate::fs::partitions::gpt::crc32_calc;
+use crate::time::rtc::{__cmos_read, __cmos_write};
+
+/// Magic number at the beginning of the sector (`FZMD`).
+pub const BOOT_METADATA_MAGIC: u32 = 0x444D_5A46;
+
+/// Version of the layout of [`BootMetadataSector`]. Version 1 described a single kernel image.
+pub const BOOT_METADATA_VERSION: u16 = 2;
+
+/// Sector of the boot disk holding the [`BootMetadataSector`]: the first one following the primary `GPT` partition
+/// entries.
+pub const BOOT_METADATA_LBA: u64 = 34;
+
+/// Number of kernel slots.
+pub const KERNEL_SLOTS: usize = 2;
+
+/// Number of attempts to boot a kernel that was not marked healthy, before rolling back to the previous one.
+pub const MAX_BOOT_ATTEMPTS: u8 = 3;
+
+/// Slot flag set when the slot holds a kernel image.
+const SLOT_VALID: u32 = 1 << 0;
+
+/// Slot flag set once the kernel of the slot booted successfully.
+const SLOT_HEALTHY: u32 = 1 << 1;
+
+/// `CMOS` register used by the kernel to report a successful boot to the bootloader.
+///
+/// Located in the upper part of the standard `CMOS` bank, which is not covered by the checksum of the firmware.
+const BOOT_STATUS_CMOS_REG: u8 = 0x7E;
+
+/// Value of [`BOOT_STATUS_CMOS_REG`] once the kernel booted successfully.
+const BOOT_STATUS_SUCCESS: u8 = 0xB5;
+
+/// Boot metadata, describing the kernel slots.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C, packed)]
+pub struct BootMetadataSector {
+    magic: u32,
+    version: u16,
+
+    /// Size of the structure, in bytes.
+    size: u16,
+
+    /// `CRC32` of the first `size` bytes of the structure, computed with this field set to 0.
+    checksum: u32,
+
+    /// Slot booted by default.
+    active_slot: u8,
+
+    /// Number of times the active slot was booted since it was installed, while not being healthy.
+    boot_attempts: u8,
+    reserved: u16,
+
+    slots: [KernelSlot; KERNEL_SLOTS],
+}
+
+/// Kernel image stored on a partition.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C, packed)]
+pub struct KernelSlot {
+    /// First sector of the partition holding the kernel image.
+    lba: u64,
+
+    /// Size of the kernel image file, as stored on disk (possibly compressed), in bytes.
+    size: u64,
+
+    /// `CRC32` of the kernel image file, as stored on disk.
+    crc32: u32,
+
+    /// [`SLOT_VALID`] and [`SLOT_HEALTHY`].
+    flags: u32,
+}
+
+impl KernelSlot {
+    /// Returns the first sector of the partition holding the kernel image.
+    pub fn lba(&self) -> u64 {
+        self.lba
+    }
+
+    /// Returns the size of the kernel image file, in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Whether the slot holds a kernel image.
+    pub fn valid(&self) -> bool {
+        self.flags & SLOT_VALID != 0
+    }
+
+    /// Whether the kernel of the slot booted successfully at least once.
+    pub fn healthy(&self) -> bool {
+        self.flags & SLOT_HEALTHY != 0
+    }
+
+    /// Checks that `image` is the kernel image described by the slot.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BootMetadataError::KernelSizeMismatch`] or [`BootMetadataError::KernelChecksumMismatch`] if the
+    /// image does not match.
+    pub fn verify(&self, image: &[u8]) -> Result<(), BootMetadataError> {
+        if image.len() as u64 != self.size {
+            return Err(BootMetadataError::KernelSizeMismatch);
+        }
+
+        if crc32_calc(image) != self.crc32 {
+            return Err(BootMetadataError::KernelChecksumMismatch);
+        }
+
+        Ok(())
+    }
+}
+
+impl BootMetadataSector {
+    /// Describes the kernel image `image`, stored on the partition starting at `kernel_lba`, in a healthy slot `A`.
+    /// Slot `B` is empty.
+    pub fn new(kernel_lba: u64, image: &[u8]) -> Self {
+        let mut metadata = Self {
+            magic: BOOT_METADATA_MAGIC,
+            version: BOOT_METADATA_VERSION,
+            size: core::mem::size_of::<Self>() as u16,
+            ..Zeroable::zeroed()
+        };
+        metadata.install_kernel(0, kernel_lba, image);
+        metadata.slots[0].flags |= SLOT_HEALTHY;
+        metadata.checksum = metadata.compute_checksum();
+
+        metadata
+    }
+
+    /// Reads and validates the structure stored at the beginning of `bytes` (usually the content of the sector at
+    /// [`BOOT_METADATA_LBA`]).
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`BootMetadataError`] if the magic number, the version, the size or the checksum of the structure
+    /// is invalid.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BootMetadataError> {
+        let header = bytes
+            .get(..core::mem::size_of::<Self>())
+            .ok_or(BootMetadataError::InvalidSize(bytes.len() as u16))?;
+        let metadata: Self = pod_read_unaligned(header);
+
+        if metadata.magic != BOOT_METADATA_MAGIC {
+            return Err(BootMetadataError::InvalidMagic);
+        }
+
+        if metadata.version != BOOT_METADATA_VERSION {
+            return Err(BootMetadataError::UnsupportedVersion(metadata.version));
+        }
+
+        if usize::from(metadata.size) != core::mem::size_of::<Self>() {
+            return Err(BootMetadataError::InvalidSize(metadata.size));
+        }
+
+        if metadata.checksum != metadata.compute_checksum() {
+            return Err(BootMetadataError::InvalidChecksum);
+        }
+
+        Ok(metadata)
+    }
+
+    /// Reads and validates the structure stored on `device`, at [`BOOT_METADATA_LBA`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BootMetadataError::ReadFailed`] if the sector cannot be read, or any error of
+    /// [`BootMetadataSector::from_bytes`].
+    pub fn read_from<D: DiskDevice + ?Sized>(device: &D) -> Result<Self, BootMetadataError> {
+        let sector_size = usize::try_from(device.logical_sector_size())
+            .map_err(|_| BootMetadataError::ReadFailed)?;
+        let mut sector = vec![0u8; sector_size];
+
+        device
+            .read_into(BOOT_METADATA_LBA, 1, &mut sector)
+            .map_err(|_| BootMetadataError::ReadFailed)?;
+
+        Self::from_bytes(&sector)
+    }
+
+    /// Updates the checksum of the structure, and writes it to `device`, at [`BOOT_METADATA_LBA`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`BootMetadataError::WriteFailed`] if the sector cannot be written.
+    pub fn write_to<D: DiskDevice + ?Sized>(
+        &mut self,
+        device: &D,
+    ) -> Result<(), BootMetadataError> {
+        self.checksum = self.compute_checksum();
+
+        let sector_size = usize::try_from(device.logical_sector_size())
+            .map_err(|_| BootMetadataError::WriteFailed)?;
+        let mut sector = vec![0u8; sector_size.max(core::mem::size_of::<Self>())];
+        sector[..core::mem::size_of::<Self>()].copy_from_slice(bytemuck::bytes_of(self));
+
+        device
+            .write_from(BOOT_METADATA_LBA, &sector)
+            .and_then(|_| device.flush())
+            .map_err(|_| BootMetadataError::WriteFailed)
+    }
+
+    /// Returns the index of the slot booted by default.
+    pub fn active_slot(&self) -> usize {
+        usize::from(self.active_slot) % KERNEL_SLOTS
+    }
+
+    /// Returns the slot at `index`.
+    pub fn slot(&self, index: usize) -> Option<&KernelSlot> {
+        self.slots.get(index)
+    }
+
+    /// Returns the slot whose kernel image is stored on the partition starting at `lba`.
+    pub fn slot_by_lba(&self, lba: u64) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|slot| slot.valid() && slot.lba == lba)
+    }
+
+    /// Returns the number of times the active slot was booted while not being healthy.
+    pub fn boot_attempts(&self) -> u8 {
+        self.boot_attempts
+    }
+
+    /// Records the kernel image `image`, stored on the partition starting at `lba`, in the slot `index`, and makes it
+    /// the active slot. The kernel must then boot successfully before [`MAX_BOOT_ATTEMPTS`] attempts, or the
+    /// bootloader rolls back to the previous slot.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not a valid slot index.
+    pub fn install_kernel(&mut self, index: usize, lba: u64, image: &[u8]) {
+        self.slots[index] = KernelSlot {
+            lba,
+            size: image.len() as u64,
+            crc32: crc32_calc(image),
+            flags: SLOT_VALID,
+        };
+        self.active_slot = index as u8;
+        self.boot_attempts = 0;
+    }
+
+    /// Updates the metadata at the beginning of a boot, and returns the slot to boot. The metadata must then be
+    /// written back to the disk.
+    ///
+    /// If `previous_boot_successful`, the active slot is marked healthy. Otherwise, if the active slot is not healthy
+    /// yet and was already booted [`MAX_BOOT_ATTEMPTS`] times, the bootloader rolls back to the other slot (see
+    /// [`BootMetadataSector::rollback`]).
+    ///
+    /// Returns `None` if no slot holds a kernel image.
+    pub fn begin_boot(&mut self, previous_boot_successful: bool) -> Option<usize> {
+        let active = self.active_slot();
+
+        if previous_boot_successful && self.slots[active].valid() {
+            self.slots[active].flags |= SLOT_HEALTHY;
+            self.boot_attempts = 0;
+        }
+
+        if !self.slots[active].valid() {
+            return self.rollback();
+        }
+
+        if !self.slots[active].healthy() {
+            if self.boot_attempts >= MAX_BOOT_ATTEMPTS {
+                if let Some(slot) = self.rollback() {
+                    return Some(slot);
+                }
+            }
+
+            self.boot_attempts = self.boot_attempts.saturating_add(1);
+        }
+
+        Some(self.active_slot())
+    }
+
+    /// Rolls back to the other slot, if it holds a healthy kernel. The active slot is invalidated, so that it is not
+    /// booted again until a new kernel is installed.
+    ///
+    /// Returns the new active slot, or `None` if there is no healthy kernel to roll back to.
+    pub fn rollback(&mut self) -> Option<usize> {
+        let active = self.active_slot();
+        let previous = (active + 1) % KERNEL_SLOTS;
+
+        if !self.slots[previous].valid() || !self.slots[previous].healthy() {
+            return None;
+        }
+
+        self.slots[active].flags = 0;
+        self.active_slot = previous as u8;
+        self.boot_attempts = 0;
+
+        Some(previous)
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        let mut metadata = *self;
+        metadata.checksum = 0;
+
+        crc32_calc(bytemuck::bytes_of(&metadata))
+    }
+}
+
+/// Reports that the kernel booted successfully: on the next boot, the bootloader marks the active slot healthy.
+///
+/// Called by the kernel once it finished initializing.
+pub fn mark_boot_successful() {
+    __cmos_write(BOOT_STATUS_CMOS_REG, BOOT_STATUS_SUCCESS);
+}
+
+/// Checks whether the previous boot was reported successful by the kernel (see [`mark_boot_successful`]), and clears
+/// the report.
+pub fn take_boot_success() -> bool {
+    let successful = __cmos_read(BOOT_STATUS_CMOS_REG) == BOOT_STATUS_SUCCESS;
+    __cmos_write(BOOT_STATUS_CMOS_REG, 0);
+
+    successful
+}
@@ -1 +1,9 @@
+pub mod boottime;
+pub mod elf;
+pub mod info;
+#[cfg(feature = "alloc")]
+pub mod metadata;
 pub mod multiboot;
+pub mod progress;
+#[cfg(feature = "alloc")]
+pub mod stages;
@@ -108,21 +108,25 @@ impl MultibootInformation {
             return None;
         }
 
-        let mut bootloader_name = String::new();
-        let mut curr_addr = self.boot_loader_name.as_ptr::<u8>();
-
-        unsafe {
-            loop {
-                let curr_byte = core::ptr::read(curr_addr);
-                if curr_byte == 0 {
-                    break;
-                }
-                bootloader_name.push(char::from(curr_byte));
-                curr_addr = curr_addr.add(1);
-            }
+        Some(unsafe { read_c_string(self.boot_loader_name) })
+    }
+
+    /// Sets the address of the zero-terminated command line passed to the kernel.
+    pub fn set_cmdline(&mut self, str_address: PhyAddr32) {
+        self.flags |= MultibootInformationFlags::CMDLINE_VALID;
+        self.cmdline = str_address;
+    }
+
+    /// Returns the command line passed to the kernel, if any.
+    pub fn get_cmdline(self) -> Option<String> {
+        if !self
+            .flags
+            .contains(MultibootInformationFlags::CMDLINE_VALID)
+        {
+            return None;
         }
 
-        Some(bootloader_name)
+        Some(unsafe { read_c_string(self.cmdline) })
     }
 
     pub fn framebuffer(&self) -> Option<FramebufferMultibootInformation> {
@@ -136,28 +140,55 @@ impl MultibootInformation {
         None
     }
 
+    /// Sets the VBE table fields: the addresses of the `VbeInfoBlock` and of the `ModeInfoBlock` of
+    /// the current video mode, and its mode number.
+    pub fn insert_vbe_info(&mut self, control_info: PhyAddr32, mode_info: PhyAddr32, mode: u16) {
+        self.flags |= MultibootInformationFlags::VBE_TABLE_VALID;
+
+        self.vbe = VbeMultibootInformation {
+            control_info: u32::from(control_info),
+            mode_info: u32::from(mode_info),
+            mode,
+            ..Default::default()
+        };
+    }
+
+    /// Returns the number of the VBE video mode selected by the bootloader, if any.
+    pub fn vbe_mode(&self) -> Option<u16> {
+        if !self
+            .flags
+            .contains(MultibootInformationFlags::VBE_TABLE_VALID)
+        {
+            return None;
+        }
+
+        Some(self.vbe.mode)
+    }
+
     pub fn insert_framebuffer_info(&mut self, mode_info_block: ModeInfoBlock) {
         self.flags |= MultibootInformationFlags::FRAMEBUFFER_VALID;
 
-        self.framebuffer = FramebufferMultibootInformation {
-            addr: u64::from(mode_info_block.framebuffer).into(),
-            pitch: u32::from(
-                mode_info_block.bytes_per_scanline / u16::from(mode_info_block.bits_per_pixel >> 3),
-            ),
-            width: u32::from(mode_info_block.width),
-            height: u32::from(mode_info_block.height),
-            bpp: mode_info_block.bits_per_pixel,
-            framebuffer_type: 1,
-            red_field_pos: mode_info_block.red_field_pos,
-            red_mask_size: mode_info_block.red_mask_s,
-            green_field_pos: mode_info_block.green_field_pos,
-            green_mask_size: mode_info_block.green_mask_s,
-            blue_field_pos: mode_info_block.blue_field_pos,
-            blue_mask_size: mode_info_block.blue_mask_s,
-        };
+        self.framebuffer = FramebufferMultibootInformation::from_mode_info(&mode_info_block);
     }
 }
 
+/// Reads a zero-terminated string, stored at `addr`.
+unsafe fn read_c_string(addr: PhyAddr32) -> String {
+    let mut string = String::new();
+    let mut curr_addr = addr.as_ptr::<u8>();
+
+    loop {
+        let curr_byte = core::ptr::read(curr_addr);
+        if curr_byte == 0 {
+            break;
+        }
+        string.push(char::from(curr_byte));
+        curr_addr = curr_addr.add(1);
+    }
+
+    string
+}
+
 impl Default for MultibootInformation {
     fn default() -> Self {
         Self {
@@ -340,6 +371,28 @@ pub struct FramebufferMultibootInformation {
     pub(crate) blue_mask_size: u8,
 }
 
+impl FramebufferMultibootInformation {
+    /// Describes the framebuffer of the video mode described by `mode_info_block`.
+    pub fn from_mode_info(mode_info_block: &ModeInfoBlock) -> Self {
+        Self {
+            addr: u64::from(mode_info_block.framebuffer).into(),
+            pitch: u32::from(
+                mode_info_block.bytes_per_scanline / u16::from(mode_info_block.bits_per_pixel >> 3),
+            ),
+            width: u32::from(mode_info_block.width),
+            height: u32::from(mode_info_block.height),
+            bpp: mode_info_block.bits_per_pixel,
+            framebuffer_type: 1,
+            red_field_pos: mode_info_block.red_field_pos,
+            red_mask_size: mode_info_block.red_mask_s,
+            green_field_pos: mode_info_block.green_field_pos,
+            green_mask_size: mode_info_block.green_mask_s,
+            blue_field_pos: mode_info_block.blue_field_pos,
+            blue_mask_size: mode_info_block.blue_mask_s,
+        }
+    }
+}
+
 /// Contains information about the disk device from which the OS image was loaded.
 ///
 /// Part of the Multiboot information header.
@@ -0,0 +1,256 @@
+//! Boot progress reporting.
+//!
+//! Subsystems report the boot milestones they reach using [`report_stage`]. Depending on the [`ProgressMode`], the
+//! progress is either rendered as a single text line or as a progress bar on the framebuffer (in which case the
+//! regular log output is captured rather than displayed), or not rendered at all.
+//!
+//! Any error switches back to the verbose log: the captured output is displayed, and the boot continues as in
+//! [`ProgressMode::Verbose`].
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::boot::progress::{self, BootStage, ProgressMode};
+//!
+//! progress::progress_init(ProgressMode::Graphical);
+//! progress::report_stage(BootStage::Memory);
+//! ```
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::boot::boottime::boottime_mark;
+use crate::info;
+use crate::video::vesa::framebuffer::{
+    RgbaColor, TextCursor, TextFrameBuffer, BORDER, CHAR_HEIGHT, LINE_SPACING,
+};
+use crate::video::vesa::{set_output_captured, text_buffer};
+
+/// Color of the filled part of the progress bar.
+const BAR_COLOR: RgbaColor = RgbaColor(234, 190, 124, 0);
+
+/// Color of the outline of the progress bar.
+const BAR_OUTLINE_COLOR: RgbaColor = RgbaColor(120, 120, 120, 0);
+
+/// Height of the progress bar, in pixels.
+const BAR_HEIGHT: usize = 12;
+
+/// Width of the text progress line, in characters.
+const TEXT_BAR_WIDTH: usize = 32;
+
+static PROGRESS_MODE: AtomicU8 = AtomicU8::new(ProgressMode::Verbose as u8);
+
+static CURRENT_STAGE: AtomicU8 = AtomicU8::new(0);
+
+/// How the boot progress is displayed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ProgressMode {
+    /// The regular log output is displayed, the progress is not rendered.
+    Verbose,
+
+    /// The progress is rendered as a single text line.
+    Text,
+
+    /// The progress is rendered as a progress bar.
+    Graphical,
+}
+
+impl From<u8> for ProgressMode {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Self::Text,
+            2 => Self::Graphical,
+            _ => Self::Verbose,
+        }
+    }
+}
+
+/// Boot milestones, in the order in which they are reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum BootStage {
+    /// Memory map and heap initialization.
+    Memory,
+
+    /// ACPI tables parsing.
+    Acpi,
+
+    /// Clocks and interrupts setup.
+    Clocks,
+
+    /// PCI bus enumeration.
+    Pci,
+
+    /// Disk drivers initialization.
+    Disks,
+
+    /// Filesystems mounting, and lookup of the kernel partition.
+    Filesystems,
+
+    /// Kernel loading.
+    Kernel,
+
+    /// Boot completed, about to jump to the kernel.
+    Done,
+}
+
+impl BootStage {
+    /// Number of stages, including [`BootStage::Done`].
+    pub const COUNT: usize = 8;
+
+    /// Name of the stage in the boot timeline (see [`boottime_mark`]).
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Memory => "memory",
+            Self::Acpi => "acpi",
+            Self::Clocks => "clocks",
+            Self::Pci => "pci",
+            Self::Disks => "disks",
+            Self::Filesystems => "filesystems",
+            Self::Kernel => "kernel",
+            Self::Done => "done",
+        }
+    }
+
+    /// Short description of the stage, displayed next to the progress bar.
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Memory => "initializing memory",
+            Self::Acpi => "reading ACPI tables",
+            Self::Clocks => "setting up clocks",
+            Self::Pci => "enumerating PCI devices",
+            Self::Disks => "detecting disks",
+            Self::Filesystems => "mounting filesystems",
+            Self::Kernel => "loading kernel",
+            Self::Done => "starting kernel",
+        }
+    }
+}
+
+impl From<u8> for BootStage {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Memory,
+            1 => Self::Acpi,
+            2 => Self::Clocks,
+            3 => Self::Pci,
+            4 => Self::Disks,
+            5 => Self::Filesystems,
+            6 => Self::Kernel,
+            _ => Self::Done,
+        }
+    }
+}
+
+/// Selects how the boot progress is displayed.
+///
+/// In [`ProgressMode::Text`] and [`ProgressMode::Graphical`] modes, the screen is cleared and the regular output is
+/// captured from then on.
+pub fn progress_init(mode: ProgressMode) {
+    PROGRESS_MODE.store(mode as u8, Ordering::Release);
+
+    if mode == ProgressMode::Verbose {
+        set_output_captured(false);
+        return;
+    }
+
+    set_output_captured(true);
+    text_buffer().buffer.lock().clear();
+    render();
+}
+
+/// Returns the current [`ProgressMode`].
+pub fn progress_mode() -> ProgressMode {
+    ProgressMode::from(PROGRESS_MODE.load(Ordering::Acquire))
+}
+
+/// Reports that the boot reached `stage`.
+pub fn report_stage(stage: BootStage) {
+    boottime_mark(stage.name());
+    CURRENT_STAGE.store(stage as u8, Ordering::Release);
+    info!("boot", "{}", stage.description());
+
+    if progress_mode() != ProgressMode::Verbose {
+        render();
+    }
+}
+
+/// Reports that an error occurred during the boot.
+///
+/// Switches to the verbose log, displaying the output that was captured so far. Called by the `error!` macro.
+pub fn report_error() {
+    if progress_mode() == ProgressMode::Verbose {
+        return;
+    }
+
+    PROGRESS_MODE.store(ProgressMode::Verbose as u8, Ordering::Release);
+
+    text_buffer().buffer.lock().clear();
+    set_output_captured(false);
+}
+
+fn render() {
+    let stage = CURRENT_STAGE.load(Ordering::Acquire);
+    let mut framebuffer = text_buffer().buffer.lock();
+
+    match progress_mode() {
+        ProgressMode::Verbose => {}
+        ProgressMode::Text => render_text(&mut framebuffer, stage),
+        ProgressMode::Graphical => render_bar(&mut framebuffer, stage),
+    }
+}
+
+/// Renders the progress as a single line at the top of the screen, such as `[########        ] 2/8 detecting disks`.
+fn render_text(framebuffer: &mut TextFrameBuffer, stage: u8) {
+    let filled = (usize::from(stage) + 1) * TEXT_BAR_WIDTH / BootStage::COUNT;
+
+    framebuffer.cursor = TextCursor::default();
+
+    let _ = framebuffer.write_char('[');
+    for i in 0..TEXT_BAR_WIDTH {
+        let _ = framebuffer.write_char(if i < filled { '#' } else { ' ' });
+    }
+    let _ = write!(
+        framebuffer,
+        "] {}/{} {:<32}",
+        usize::from(stage) + 1,
+        BootStage::COUNT,
+        BootStage::from(stage).description()
+    );
+}
+
+/// Renders the progress as a bar in the middle of the screen, with the description of the current stage below.
+fn render_bar(framebuffer: &mut TextFrameBuffer, stage: u8) {
+    let screen_width = framebuffer.metadata.width;
+    let bar_width = screen_width / 2;
+    let bar_x = screen_width / 4;
+    let bar_y = framebuffer.metadata.height / 2;
+    let filled = (usize::from(stage) + 1) * (bar_width - 4) / BootStage::COUNT;
+    let bg_color = framebuffer
+        .metadata
+        .bg_color
+        .unwrap_or(RgbaColor(0, 0, 0, 0));
+
+    framebuffer.fill_rect(bar_x, bar_y, bar_width, BAR_HEIGHT, BAR_OUTLINE_COLOR);
+    framebuffer.fill_rect(
+        bar_x + 1,
+        bar_y + 1,
+        bar_width - 2,
+        BAR_HEIGHT - 2,
+        bg_color,
+    );
+    framebuffer.fill_rect(bar_x + 2, bar_y + 2, filled, BAR_HEIGHT - 4, BAR_COLOR);
+
+    framebuffer.cursor = TextCursor {
+        x: bar_x,
+        y: bar_y + BAR_HEIGHT + LINE_SPACING,
+    };
+    let _ = write!(framebuffer, "{:<32}", BootStage::from(stage).description());
+
+    framebuffer.cursor = TextCursor {
+        x: bar_x,
+        y: bar_y.saturating_sub(CHAR_HEIGHT.val() + LINE_SPACING + BORDER),
+    };
+    framebuffer.write_str_with_color("fzboot", &BAR_COLOR);
+}
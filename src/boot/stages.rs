@@ -0,0 +1,197 @@
+//! Initialization stages.
+//!
+//! The boot sequence is split into stages ([`InitStage`]), each one declaring the stages it depends on, instead of
+//! being a hard-coded list of calls. [`run_stages`] orders them so that a stage always runs after its dependencies,
+//! reports the boot milestones they are associated with, and measures how long each stage takes.
+//!
+//! Stages currently run one after the other on the boot processor: there is no executor to run independent stages
+//! concurrently yet. Declaring the dependencies is what allows such an executor to be used later on, and stages whose
+//! dependencies are all satisfied are kept in their declaration order, so that the boot sequence is predictable.
+//!
+//! The time spent in each stage is measured using the TSC, which can be read before it is calibrated: durations are
+//! only converted once the clocks are initialized, and are logged when every stage is done.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::boot::stages::{run_stages, InitStage};
+//!
+//! const STAGES: &[InitStage] = &[
+//!     InitStage::new("acpi", &[], acpi_init),
+//!     InitStage::new("clocks", &["acpi"], clock_init),
+//!     InitStage::new("ps2", &["clocks"], ps2_init),
+//! ];
+//!
+//! run_stages(STAGES).expect("invalid initialization stages");
+//! ```
+
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use spin::Mutex;
+
+use crate::boot::progress::{report_stage, BootStage};
+use crate::errors::InitStageError;
+use crate::info;
+use crate::irq::deferred::run_deferred_work;
+use crate::x86::tsc::{rdtsc, TSC_CLK};
+
+/// Stages that were run, in the order in which they ran.
+static STAGE_TIMINGS: Mutex<Vec<StageTiming>> = Mutex::new(Vec::new());
+
+/// Initialization step of the boot sequence.
+#[derive(Clone, Copy, Debug)]
+pub struct InitStage {
+    /// Name of the stage, referred to by the stages that depend on it.
+    pub name: &'static str,
+
+    /// Names of the stages that must be done before this one starts.
+    pub depends_on: &'static [&'static str],
+
+    /// Boot milestone reported when this stage starts.
+    pub milestone: Option<BootStage>,
+
+    pub init: fn(),
+}
+
+impl InitStage {
+    pub const fn new(name: &'static str, depends_on: &'static [&'static str], init: fn()) -> Self {
+        Self {
+            name,
+            depends_on,
+            milestone: None,
+            init,
+        }
+    }
+
+    /// Reports `milestone` when this stage starts.
+    pub const fn milestone(mut self, milestone: BootStage) -> Self {
+        self.milestone = Some(milestone);
+        self
+    }
+}
+
+/// Time spent in a stage.
+#[derive(Clone, Copy, Debug)]
+pub struct StageTiming {
+    pub name: &'static str,
+
+    /// TSC value when the stage started.
+    pub start: u64,
+
+    /// TSC value when the stage was done.
+    pub end: u64,
+}
+
+impl StageTiming {
+    /// Time spent in the stage, in microseconds, if the TSC frequency is known.
+    pub fn duration_us(&self) -> Option<f64> {
+        TSC_CLK
+            .get()
+            .map(|tsc| tsc.tsc_ticks_to_micro(self.end.saturating_sub(self.start) as f64))
+    }
+}
+
+impl fmt::Display for StageTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.duration_us() {
+            Some(duration) => write!(f, "{:<20} {:>10.0} us", self.name, duration),
+            None => write!(
+                f,
+                "{:<20} {:>10} ticks",
+                self.name,
+                self.end.saturating_sub(self.start)
+            ),
+        }
+    }
+}
+
+/// Returns the order in which `stages` can run (as indices in `stages`).
+///
+/// Among the stages whose dependencies are done, the first declared one runs first.
+pub fn stage_order(stages: &[InitStage]) -> Result<Vec<usize>, InitStageError> {
+    let mut dependencies = Vec::with_capacity(stages.len());
+
+    for stage in stages {
+        let mut indices = Vec::with_capacity(stage.depends_on.len());
+
+        for dependency in stage.depends_on {
+            let Some(index) = stages.iter().position(|other| other.name == *dependency) else {
+                info!(
+                    "init",
+                    "stage {} depends on unknown stage {dependency}", stage.name
+                );
+                return Err(InitStageError::UnknownDependency);
+            };
+            indices.push(index);
+        }
+
+        dependencies.push(indices);
+    }
+
+    let mut done = alloc::vec![false; stages.len()];
+    let mut order = Vec::with_capacity(stages.len());
+
+    while order.len() < stages.len() {
+        let Some(next) = (0..stages.len()).find(|&index| {
+            !done[index]
+                && dependencies[index]
+                    .iter()
+                    .all(|&dependency| done[dependency])
+        }) else {
+            return Err(InitStageError::DependencyCycle);
+        };
+
+        done[next] = true;
+        order.push(next);
+    }
+
+    Ok(order)
+}
+
+/// Runs every stage, after the stages it depends on.
+///
+/// Nothing is run if the dependencies cannot be satisfied. The time spent in each stage is logged once they are all
+/// done.
+pub fn run_stages(stages: &[InitStage]) -> Result<(), InitStageError> {
+    let order = stage_order(stages)?;
+
+    for stage in order.into_iter().map(|index| &stages[index]) {
+        if let Some(milestone) = stage.milestone {
+            report_stage(milestone);
+        }
+
+        let start = rdtsc();
+        (stage.init)();
+        let end = rdtsc();
+
+        // Work deferred by interrupt handlers while the stage was running (hotplug events, ...).
+        run_deferred_work();
+
+        STAGE_TIMINGS.lock().push(StageTiming {
+            name: stage.name,
+            start,
+            end,
+        });
+    }
+
+    let timings = stage_timings();
+    for timing in &timings[timings.len() - stages.len()..] {
+        info!("init", "{timing}");
+    }
+
+    Ok(())
+}
+
+/// Returns the stages that were run, in the order in which they ran.
+pub fn stage_timings() -> Vec<StageTiming> {
+    STAGE_TIMINGS.lock().clone()
+}
+
+/// Writes the time spent in each stage that was run, one per line.
+pub fn stages_report(out: &mut impl Write) -> fmt::Result {
+    for timing in stage_timings() {
+        writeln!(out, "{timing}")?;
+    }
+
+    Ok(())
+}
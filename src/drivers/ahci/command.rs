@@ -1,5 +1,13 @@
 use core::{mem, slice};
 
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{
+    drivers::ahci::AHCIController,
+    errors::{CanFail, IOError},
+    mem::dma::DmaBuffer,
+};
+
 pub(crate) const AHCI_CMDH_ATAPI: u32 = 1 << 5;
 pub(crate) const AHCI_CMDH_WRITE: u32 = 1 << 6;
 pub(crate) const AHCI_CMDH_PREFETCHABLE: u32 = 1 << 7;
@@ -9,10 +17,18 @@ pub(crate) const AHCI_CMDH_CLEAR_BUSY: u32 = 1 << 10;
 pub(crate) const AHCI_CMDH_PMP: u32 = 1 << 12;
 pub(crate) const AHCI_CMDH_PRDTL: u32 = 1 << 16;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug)]
 pub struct AHCITransaction {
     pub header: AHCICommandHeader,
     byte_size: usize,
+
+    /// Command table referenced by the header, released once the transaction completes.
+    command_table: Option<DmaBuffer>,
+
+    /// Buffer the data is transferred from or to, shared with the issuer of the command.
+    ///
+    /// It remains allocated until the transaction completes, even if the issuer stopped waiting for it.
+    data: Option<Arc<DmaBuffer>>,
 }
 
 impl AHCITransaction {
@@ -20,6 +36,8 @@ impl AHCITransaction {
         Self {
             header: AHCICommandHeader::new_empty(),
             byte_size: 0,
+            command_table: None,
+            data: None,
         }
     }
 
@@ -30,6 +48,46 @@ impl AHCITransaction {
     pub fn byte_size(&self) -> usize {
         self.byte_size
     }
+
+    /// Attaches the buffer the data of this transaction is transferred from or to, which the PRD entries point to.
+    pub fn set_data_buffer(&mut self, buffer: Arc<DmaBuffer>) {
+        self.data = Some(buffer);
+    }
+
+    /// Builds the command table of this transaction, in a buffer allocated for DMA by the controller `ahci`.
+    ///
+    /// The command table contains the command FIS (`raw_fis`), the ATAPI command (`raw_acmd`) and the PRD table.
+    pub fn build_command_table(
+        &mut self,
+        ahci: &AHCIController,
+        raw_fis: &[u8],
+        raw_acmd: &[u8],
+        prdt: Vec<AHCIPhysicalRegionDescriptor>,
+    ) -> CanFail<IOError> {
+        assert!(raw_acmd.len() < 0x11,
+            "Invalid ATAPI Command header size (size is {} bytes but the maximum allowed value is 16 bytes)", raw_acmd.len());
+        self.header
+            .set_command_fis_length((raw_fis.len() >> 2) as u8);
+        self.header.set_prd_table_length(prdt.len() as u16);
+        let raw_prdt_len = prdt.len() * mem::size_of::<AHCIPhysicalRegionDescriptor>();
+
+        let mut command_table = ahci.dma_alloc(0x80 + raw_prdt_len)?;
+        let cmd_table_bytes = command_table.as_mut_slice();
+
+        cmd_table_bytes[..raw_fis.len()].copy_from_slice(raw_fis);
+        cmd_table_bytes[0x40..0x40 + raw_acmd.len()].copy_from_slice(raw_acmd);
+
+        unsafe {
+            let raw_prdt = slice::from_raw_parts(prdt.as_ptr() as *const u8, raw_prdt_len);
+            cmd_table_bytes[0x80..0x80 + raw_prdt.len()].copy_from_slice(raw_prdt);
+        }
+
+        self.header
+            .set_cmd_table_base_addr64(command_table.dma_addr());
+        self.command_table = Some(command_table);
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -73,45 +131,6 @@ impl AHCICommandHeader {
         }
     }
 
-    pub fn build_command_table(
-        &mut self,
-        raw_fis: &[u8],
-        raw_acmd: &[u8],
-        prdt: alloc::vec::Vec<AHCIPhysicalRegionDescriptor>,
-    ) {
-        assert!(raw_acmd.len() < 0x11,
-            "Invalid ATAPI Command header size (size is {} bytes but the maximum allowed value is 16 bytes)", raw_acmd.len());
-        self.set_command_fis_length((raw_fis.len() >> 2) as u8);
-        self.set_prd_table_length(prdt.len() as u16);
-        let raw_prdt_len = prdt.len() * mem::size_of::<AHCIPhysicalRegionDescriptor>();
-
-        let total_len =
-            0x40 + 0x10 + 0x30 + (prdt.len() * mem::size_of::<AHCIPhysicalRegionDescriptor>());
-
-        let mut cmd_table_bytes =
-            mem::ManuallyDrop::new(alloc::vec::Vec::<u8>::with_capacity(total_len));
-        unsafe {
-            cmd_table_bytes.set_len(total_len);
-        }
-        let mut raw_fis_ext = [0u8; 0x40];
-        raw_fis_ext[..raw_fis.len()].copy_from_slice(raw_fis);
-        cmd_table_bytes.as_mut_slice()[..0x40].copy_from_slice(&raw_fis_ext);
-
-        let mut raw_acmd_ext = [0u8; 0x10];
-        raw_acmd_ext[..raw_acmd.len()].copy_from_slice(raw_acmd);
-        cmd_table_bytes.as_mut_slice()[0x40..0x50].copy_from_slice(&raw_acmd_ext);
-
-        let reserved = [0u8; 0x30];
-        cmd_table_bytes.as_mut_slice()[0x50..0x80].copy_from_slice(&reserved);
-
-        unsafe {
-            let raw_prdt = slice::from_raw_parts(prdt.as_ptr() as *const u8, raw_prdt_len);
-            cmd_table_bytes.as_mut_slice()[0x80..0x80 + raw_prdt.len()].copy_from_slice(raw_prdt);
-        }
-
-        self.set_cmd_table_base_addr64(cmd_table_bytes.as_ptr());
-    }
-
     /// Length of the Command FIS, in DWORDs.
     pub fn command_fis_length(&self) -> u8 {
         (self.di & 0xf) as u8
@@ -267,15 +286,15 @@ impl AHCICommandHeader {
         self.ctba = addr as u32;
     }
 
-    /// Indicates the physical address of the `command table`, if 64-bit addressing is supported.
-    pub fn cmd_table_base_addr64(&self) -> *mut u8 {
-        (((self.ctba_hi as u64) << 32) | (self.ctba as u64)) as *mut u8
+    /// Indicates the bus address of the `command table`, if 64-bit addressing is supported.
+    pub fn cmd_table_base_addr64(&self) -> u64 {
+        ((self.ctba_hi as u64) << 32) | (self.ctba as u64)
     }
 
-    /// Sets the physical address of the `command table`, if 64-bit addressing is supported..
-    pub fn set_cmd_table_base_addr64(&mut self, addr: *const u8) {
-        self.ctba_hi = ((addr as u64) >> 32) as u32;
-        self.ctba = ((addr as u64) & 0xffffffff) as u32;
+    /// Sets the bus address of the `command table`, if 64-bit addressing is supported.
+    pub fn set_cmd_table_base_addr64(&mut self, addr: u64) {
+        self.ctba_hi = (addr >> 32) as u32;
+        self.ctba = (addr & 0xffffffff) as u32;
     }
 }
 
@@ -302,21 +321,15 @@ impl AHCIPhysicalRegionDescriptor {
             di: 0,
         }
     }
-    pub fn base_address(&self) -> *mut u8 {
-        if self.dbau != 0 {
-            (((self.dbau as u64) << 32) | (self.dba as u64)) as *mut u8
-        } else {
-            self.dba as *mut u8
-        }
+    /// Bus address of the data region.
+    pub fn base_address(&self) -> u64 {
+        ((self.dbau as u64) << 32) | (self.dba as u64)
     }
 
-    pub fn set_base_address(&mut self, addr: *mut u8) {
-        if mem::size_of::<*mut u8>() == 8 {
-            self.dbau = ((addr as u64) >> 32) as u32;
-            self.dba = ((addr as u64) & 0xffffffff) as u32;
-        } else {
-            self.dba = addr as u32;
-        }
+    /// Sets the bus address of the data region.
+    pub fn set_base_address(&mut self, addr: u64) {
+        self.dbau = (addr >> 32) as u32;
+        self.dba = (addr & 0xffffffff) as u32;
     }
 
     pub fn interrupt_on_completion(&self) -> bool {
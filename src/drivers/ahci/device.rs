@@ -2,33 +2,53 @@
 
 use core::cell::UnsafeCell;
 use core::sync::atomic::AtomicBool;
+use core::time::Duration;
 
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use spin::RwLock;
 
 use crate::drivers::generics::dev_disk::DiskDevice;
 use crate::drivers::ide::ata_command::{
-    ATA_EXECUTE_DEVICE_DIAGNOSTIC, ATA_IDENTIFY_DEVICE, ATA_READ_DMA, ATA_WRITE_DMA,
+    ATA_EXECUTE_DEVICE_DIAGNOSTIC, ATA_FLUSH_CACHE, ATA_FLUSH_CACHE_EXT, ATA_IDENTIFY_DEVICE,
+    ATA_READ_DMA, ATA_WRITE_DMA, ATA_WRITE_DMA_EXT,
 };
 use crate::drivers::ide::ata_pio::{
-    AtaError, AtaErrorCode, AtaIdentify, AtaIoRequest, AtaIoResult,
+    AtaAddressingMode, AtaError, AtaErrorCode, AtaIdentify, AtaIoRequest, AtaIoResult,
 };
 use crate::drivers::ide::AtaDeviceIdentifier;
 use crate::{
     drivers::ahci::{
         command::{AHCIPhysicalRegionDescriptor, AHCITransaction},
         fis::RegisterHostDeviceFIS,
-        port::HBAPort,
-        AHCI_CONTROLLER, SATA_COMMAND_QUEUE,
+        AHCI_CONTROLLER, SATA_COMMAND_COMPLETION, SATA_COMMAND_QUEUE,
     },
     errors::{CanFail, IOError},
+    fail_point,
     fs::partitions::{
         gpt::load_drive_gpt,
         mbr::{load_drive_mbr, PartitionType},
+        registry::{reconcile_partitions, register_drive_partitions},
         Partition, PartitionMetadata, PartitionTable,
     },
-    wait_for_or,
+    mem::{dma::DmaBuffer, oom},
 };
 
+/// Waits until the command dispatched in `slot` completes.
+///
+/// Returns [`IOError::IOTimeout`] if the command did not complete within `timeout`.
+fn wait_for_command(slot: u8, timeout: Duration) -> CanFail<IOError> {
+    fail_point!("ahci.timeout", return Err(IOError::IOTimeout));
+
+    if !SATA_COMMAND_COMPLETION
+        .wait_until_timeout(|| !SATA_COMMAND_QUEUE.lock().contains_key(&slot), timeout)
+    {
+        return Err(IOError::IOTimeout);
+    }
+
+    Ok(())
+}
+
 /// `SATADrive` is an interface to a physical drive attached to an [`AHCIController`].
 ///
 /// It offers a convenient way to interact with the device, and other components that want to
@@ -54,7 +74,7 @@ pub struct AHCIDrive {
     pub device_info: AtaIdentify,
     ahci_data: AHCIDriveInfo,
     partition_table: UnsafeCell<PartitionTable>,
-    partitions: UnsafeCell<Vec<Partition>>,
+    partitions: RwLock<Vec<Arc<Partition>>>,
 }
 
 unsafe impl Sync for AHCIDrive {}
@@ -68,18 +88,28 @@ impl DiskDevice for AHCIDrive {
     fn read(&self, start_lba: u64, sectors_count: u16) -> AtaIoRequest {
         let mut io_req = AtaIoRequest::new(AtomicBool::new(true));
         let mut data_buf: Vec<u8> = alloc::vec![];
-        data_buf.resize(
-            usize::from(sectors_count)
-                * usize::try_from(self.logical_sector_size()).expect("invalid sector size"),
-            0,
-        );
+        let buf_size = usize::from(sectors_count)
+            * usize::try_from(self.logical_sector_size()).expect("invalid sector size");
+
+        // The DMA buffer may be large, make sure we do not panic if we are running low on memory.
+        let buf_alloc = oom::with_reclaim(buf_size, || data_buf.try_reserve_exact(buf_size));
+
         // TODO: Improve error codes, they need to be more explicit.
-        let read_result = match self.read_to_buf(start_lba, sectors_count, &mut data_buf) {
-            Ok(_) => crate::drivers::ide::ata_pio::AtaResult::Success,
-            Err(e) => crate::drivers::ide::ata_pio::AtaResult::Error(AtaError {
-                code: AtaErrorCode::CommandAbort,
-                lba: 0,
-            }),
+        let read_result = if buf_alloc.is_err() {
+            crate::drivers::ide::ata_pio::AtaResult::Error(AtaError {
+                code: AtaErrorCode::InvalidBufferSize,
+                lba: start_lba,
+            })
+        } else {
+            data_buf.resize(buf_size, 0);
+
+            match self.read_to_buf(start_lba, sectors_count, &mut data_buf) {
+                Ok(_) => crate::drivers::ide::ata_pio::AtaResult::Success,
+                Err(e) => crate::drivers::ide::ata_pio::AtaResult::Error(AtaError {
+                    code: AtaErrorCode::CommandAbort,
+                    lba: 0,
+                }),
+            }
         };
 
         let mut result = AtaIoResult {
@@ -92,10 +122,45 @@ impl DiskDevice for AHCIDrive {
         io_req
     }
 
+    fn read_into(&self, start_lba: u64, sectors_count: u16, buffer: &mut [u8]) -> CanFail<IOError> {
+        self.read_to_buf(start_lba, sectors_count, buffer)
+    }
+
+    fn read_vectored(&self, start_lba: u64, buffers: &mut [&mut [u8]]) -> CanFail<IOError> {
+        let sector_size = self.device_info.logical_sector_size() as usize;
+        let mut sectors_count = 0usize;
+
+        for buffer in buffers.iter() {
+            if buffer.len() % sector_size != 0 {
+                return Err(IOError::InvalidCommand);
+            }
+
+            sectors_count += buffer.len() / sector_size;
+        }
+
+        let sectors_count = u16::try_from(sectors_count).map_err(|_| IOError::InvalidCommand)?;
+        if sectors_count == 0 {
+            return Ok(());
+        }
+
+        (start_lba as usize + sectors_count as usize <= self.device_info.maximum_addressable_lba())
+            .then_some(())
+            .ok_or(IOError::InvalidCommand)?;
+
+        fail_point!("ahci.read", return Err(IOError::Unknown));
+
+        self.read_dma(start_lba, sectors_count, buffers)
+    }
+
     fn write(&self, start_lba: u64, sectors_count: u16, data: Vec<u8>) -> AtaIoRequest {
         let mut io_req = AtaIoRequest::new(AtomicBool::new(true));
+        let write_size = usize::min(
+            data.len(),
+            usize::from(sectors_count)
+                * usize::try_from(self.logical_sector_size()).expect("invalid sector size"),
+        );
 
-        let write_result = match self.write_from_buf(start_lba, sectors_count, &data) {
+        let write_result = match self.write_from(start_lba, &data[..write_size]) {
             Ok(_) => crate::drivers::ide::ata_pio::AtaResult::Success,
             Err(e) => crate::drivers::ide::ata_pio::AtaResult::Error(AtaError {
                 code: AtaErrorCode::CommandAbort,
@@ -113,8 +178,36 @@ impl DiskDevice for AHCIDrive {
         io_req
     }
 
-    fn partitions(&self) -> &Vec<Partition> {
-        unsafe { &(*self.partitions.get()) }
+    fn write_from(&self, start_lba: u64, data: &[u8]) -> CanFail<IOError> {
+        let sector_size = self.device_info.logical_sector_size() as usize;
+
+        if data.len() % sector_size != 0 {
+            return Err(IOError::InvalidCommand);
+        }
+
+        let mut lba = start_lba;
+
+        for chunk in data.chunks(usize::from(self.max_sectors_per_command()) * sector_size) {
+            let sectors_count =
+                u16::try_from(chunk.len() / sector_size).expect("invalid sectors count");
+
+            self.write_from_buf(lba, sectors_count, chunk)?;
+            lba += u64::from(sectors_count);
+        }
+
+        Ok(())
+    }
+
+    fn flush(&self) -> CanFail<IOError> {
+        self.flush_cache()
+    }
+
+    fn partitions(&self) -> Vec<Arc<Partition>> {
+        self.partitions.read().clone()
+    }
+
+    fn rescan_partitions(&self) {
+        self.load_partition_table();
     }
 
     fn identifier(&self) -> AtaDeviceIdentifier {
@@ -142,7 +235,7 @@ impl AHCIDrive {
             device_info: AtaIdentify::from_bytes([0u16; 256]),
             ahci_data,
             partition_table: UnsafeCell::new(PartitionTable::Unknown),
-            partitions: UnsafeCell::new(alloc::vec![]),
+            partitions: RwLock::new(alloc::vec![]),
         };
 
         drive.load_identification();
@@ -156,63 +249,63 @@ impl AHCIDrive {
         let mbr = load_drive_mbr(self, 0);
 
         if mbr.is_pmbr() {
-            let gpt = load_drive_gpt(self);
-
-            unsafe {
-                if let Some(gpt) = gpt {
-                    *self.partitions.get() = gpt.get_partitions();
+            if let Some(gpt) = load_drive_gpt(self) {
+                let scanned = gpt.get_partitions();
+                unsafe {
                     *self.partition_table.get() = PartitionTable::GPT(gpt);
-
-                    for partition in &mut (*self.partitions.get()) {
-                        partition.load_fs().unwrap();
-                    }
-
-                    return;
                 }
+                self.update_partitions(scanned, true);
+
+                return;
             }
         }
 
-        unsafe {
-            *self.partitions.get() = mbr.get_partitions();
-        }
+        let mut scanned = mbr.get_partitions();
 
-        unsafe {
-            for partition in (*self.partitions.get()).clone().iter() {
-                if let PartitionMetadata::MBR(mut meta) = partition.metadata() {
-                    // if this device uses _EPBR_, we traverse the linked list to find all partitions.
-                    if matches!(meta.partition_type(), PartitionType::Extended)
-                        || matches!(meta.partition_type(), PartitionType::ExtendedLBA)
-                    {
-                        while load_drive_mbr(self, meta.start_lba() as u64).get_partition_metadata()
-                            [1]
+        for partition in scanned.clone().iter() {
+            if let PartitionMetadata::MBR(mut meta) = partition.metadata() {
+                // if this device uses _EPBR_, we traverse the linked list to find all partitions.
+                if matches!(meta.partition_type(), PartitionType::Extended)
+                    || matches!(meta.partition_type(), PartitionType::ExtendedLBA)
+                {
+                    while load_drive_mbr(self, meta.start_lba() as u64).get_partition_metadata()[1]
                         .is_used()
-                            || load_drive_mbr(self, meta.start_lba() as u64)
-                                .get_partition_metadata()[0]
-                                .is_used()
-                        {
-                            let partitions = load_drive_mbr(self, meta.start_lba() as u64)
-                                .get_partition_metadata();
-
-                            let mut ext_part = partitions[0];
-
-                            ext_part.set_start_lba(ext_part.start_lba() + meta.start_lba());
-
-                            (*self.partitions.get()).push(
-                                Partition::from_metadata(
-                                    0,
-                                    self.id,
-                                    PartitionMetadata::MBR(ext_part),
-                                )
+                        || load_drive_mbr(self, meta.start_lba() as u64).get_partition_metadata()[0]
+                            .is_used()
+                    {
+                        let partitions =
+                            load_drive_mbr(self, meta.start_lba() as u64).get_partition_metadata();
+
+                        let mut ext_part = partitions[0];
+
+                        ext_part.set_start_lba(ext_part.start_lba() + meta.start_lba());
+
+                        scanned.push(
+                            Partition::from_metadata(0, self.id, PartitionMetadata::MBR(ext_part))
                                 .unwrap(),
-                            );
-                            meta = partitions[1];
-                        }
+                        );
+                        meta = partitions[1];
                     }
                 }
             }
+        }
 
+        unsafe {
             *self.partition_table.get() = PartitionTable::MBR(mbr);
         }
+        self.update_partitions(scanned, false);
+    }
+
+    /// Replaces the partitions of this device with the `scanned` ones, keeping the unchanged ones and their mounted
+    /// filesystem (see [`reconcile_partitions`]).
+    ///
+    /// References to the previous partitions remain valid: the list is replaced, not the partitions it points to.
+    fn update_partitions(&self, scanned: Vec<Partition>, mount: bool) {
+        let previous = self.partitions.read().clone();
+        let partitions = reconcile_partitions(self.id, &previous, scanned, mount);
+
+        *self.partitions.write() = partitions.clone();
+        register_drive_partitions(self.id, &partitions);
     }
 
     /// Sends a `IDENTIFY DEVICE` command to the corresponding device.
@@ -220,15 +313,7 @@ impl AHCIDrive {
     /// The device shall respond with a 512-bytes data block containing various information
     /// concerning itself.
     pub fn load_identification(&mut self) {
-        self.device_info = AtaIdentify::from_bytes(
-            self.dispach_ata_identify(
-                AHCI_CONTROLLER
-                    .get()
-                    .unwrap()
-                    .lock()
-                    .read_port_register(self.ahci_data.port),
-            ),
-        );
+        self.device_info = AtaIdentify::from_bytes(self.dispach_ata_identify());
     }
 
     /// Reads `sectors_count` sectors from this drive, starting at `start_lba`, into `buffer`.
@@ -258,15 +343,11 @@ impl AHCIDrive {
             .then_some(())
             .ok_or(IOError::InvalidCommand)?;
 
-        let slot = unsafe { self.read_dma(start_lba, sectors_count, buffer.as_mut_ptr()) };
+        fail_point!("ahci.read", return Err(IOError::Unknown));
 
-        wait_for_or!(
-            !SATA_COMMAND_QUEUE.lock().contains_key(&(slot as u8)),
-            10_000,
-            return Err(IOError::IOTimeout)
-        );
+        let byte_size = sectors_count as usize * self.device_info.logical_sector_size() as usize;
 
-        Ok(())
+        self.read_dma(start_lba, sectors_count, &mut [&mut buffer[..byte_size]])
     }
 
     /// Writes `sectors_count` sectors from the buffer to the drive, starting at `start_lba`.
@@ -289,155 +370,239 @@ impl AHCIDrive {
         sectors_count: u16,
         buffer: &[u8],
     ) -> CanFail<IOError> {
+        (sectors_count as usize * self.device_info.logical_sector_size() as usize <= buffer.len())
+            .then_some(())
+            .ok_or(IOError::InvalidCommand)?;
+        (sectors_count != 0 && sectors_count <= self.max_sectors_per_command())
+            .then_some(())
+            .ok_or(IOError::InvalidCommand)?;
         (start_lba as usize + sectors_count as usize <= self.device_info.maximum_addressable_lba())
             .then_some(())
             .ok_or(IOError::InvalidCommand)?;
 
-        let slot = unsafe { self.write_dma(start_lba, sectors_count, buffer.as_ptr()) };
+        fail_point!("ahci.write", return Err(IOError::Unknown));
 
-        wait_for_or!(
-            !SATA_COMMAND_QUEUE.lock().contains_key(&(slot as u8)),
-            10_000,
-            return Err(IOError::IOTimeout)
-        );
+        let byte_size = sectors_count as usize * self.device_info.logical_sector_size() as usize;
+
+        self.write_dma(start_lba, sectors_count, &buffer[..byte_size])
+    }
+
+    /// Sends a `FLUSH CACHE` command to the drive, and waits until all the data held in its volatile write cache was
+    /// written to the media.
+    ///
+    /// `FLUSH CACHE EXT` is used if the drive supports 48-bit addresses.
+    pub fn flush_cache(&self) -> CanFail<IOError> {
+        let mut flush_fis = RegisterHostDeviceFIS::new_empty();
+        flush_fis.set_command(match self.device_info.addressing_mode() {
+            AtaAddressingMode::Lba24 => ATA_FLUSH_CACHE,
+            AtaAddressingMode::Lba48 => ATA_FLUSH_CACHE_EXT,
+        });
+        flush_fis.set_device(1 << 6);
+        flush_fis.set_command_update_bit(true);
+
+        let slot = {
+            let ahci = AHCI_CONTROLLER.get().unwrap().lock();
+
+            let mut ahci_transaction = AHCITransaction::new();
+            ahci_transaction.build_command_table(&ahci, &flush_fis, &[0u8; 0], alloc::vec![])?;
+
+            ahci.dispatch_command(self.ahci_data.port, ahci_transaction)
+        };
+
+        // Flushing a large write cache to a rotating media may take a while.
+        wait_for_command(slot as u8, Duration::from_secs(30))?;
 
         Ok(())
     }
 
-    unsafe fn write_dma(&self, start_lba: u64, sectors_count: u16, buffer: *const u8) -> usize {
+    /// Maximum number of sectors that can be transferred using a single `DMA` command.
+    ///
+    /// 28-bit commands are limited to 256 sectors, we stay below that limit as a count of 0 has a special meaning.
+    fn max_sectors_per_command(&self) -> u16 {
+        match self.device_info.addressing_mode() {
+            AtaAddressingMode::Lba24 => 0xff,
+            AtaAddressingMode::Lba48 => u16::MAX,
+        }
+    }
+
+    /// Issues a single `WRITE DMA` command (or `WRITE DMA EXT` if the drive supports 48-bit addresses), and waits
+    /// until it completes.
+    ///
+    /// The data is first copied into a buffer allocated for DMA by the controller.
+    fn write_dma(&self, start_lba: u64, sectors_count: u16, data: &[u8]) -> CanFail<IOError> {
         let mut write_fis = RegisterHostDeviceFIS::new_empty();
-        let sector_size = self.device_info.logical_sector_size();
-        write_fis.set_command(ATA_WRITE_DMA);
+        write_fis.set_command(match self.device_info.addressing_mode() {
+            AtaAddressingMode::Lba24 => ATA_WRITE_DMA,
+            AtaAddressingMode::Lba48 => ATA_WRITE_DMA_EXT,
+        });
         write_fis.set_device(1 << 6);
         write_fis.set_lba(start_lba);
         write_fis.set_count(sectors_count);
         write_fis.set_command_update_bit(true);
 
-        let mut ahci_transaction = AHCITransaction::new();
-        ahci_transaction.set_byte_size(
-            (sectors_count as u32 * self.device_info.logical_sector_size()) as usize,
-        );
-
-        let mut prdtl = alloc::vec![];
-        let prdt_count = (((sectors_count - 1) >> 4) + 1) as isize;
-
-        for i in 0..prdt_count - 1 {
-            let mut prdt = AHCIPhysicalRegionDescriptor::new_empty();
-
-            prdt.set_base_address(buffer.offset(i * 16 * sector_size as isize) as *mut u8);
-            prdt.set_data_bytes_count(16 * sector_size);
-            prdt.set_interrupt_on_completion(true);
-
-            prdtl.push(prdt);
-        }
-
-        let mut last_prdt = AHCIPhysicalRegionDescriptor::new_empty();
-        last_prdt.set_base_address(
-            buffer.offset((prdt_count - 1) * 16 * sector_size as isize) as *mut u8
-        );
-        last_prdt.set_data_bytes_count(
-            (sectors_count as u32 * sector_size) - ((prdt_count - 1) as u32 * 16 * sector_size),
-        );
-        prdtl.push(last_prdt);
-
-        ahci_transaction
-            .header
-            .build_command_table(&write_fis, &[0u8; 0], prdtl);
-
-        let ahci = AHCI_CONTROLLER.get().unwrap().lock();
-        let port = ahci.read_port_register(self.ahci_data.port);
+        let slot = {
+            let ahci = AHCI_CONTROLLER.get().unwrap().lock();
+
+            let mut buffer = ahci.dma_alloc(data.len())?;
+            buffer.as_mut_slice()[..data.len()].copy_from_slice(data);
+            let buffer = Arc::new(buffer);
+
+            let mut ahci_transaction = AHCITransaction::new();
+            ahci_transaction.set_byte_size(data.len());
+            ahci_transaction.build_command_table(
+                &ahci,
+                &write_fis,
+                &[0u8; 0],
+                self.prd_table(&buffer, data.len()),
+            )?;
+            ahci_transaction.header.set_write(true);
+            ahci_transaction.set_data_buffer(buffer);
+
+            ahci.dispatch_command(self.ahci_data.port, ahci_transaction)
+        };
 
-        port.dispatch_command(ahci_transaction)
+        wait_for_command(slot as u8, Duration::from_secs(10))
     }
 
-    unsafe fn read_dma(&self, start_lba: u64, sectors_count: u16, buffer: *mut u8) -> usize {
+    /// Issues a single `READ DMA` command, and copies the data into a list of buffers (scatter), once the command
+    /// completed.
+    ///
+    /// The size of each buffer must be a multiple of the sector size, their total size being `sectors_count`
+    /// sectors.
+    fn read_dma(
+        &self,
+        start_lba: u64,
+        sectors_count: u16,
+        buffers: &mut [&mut [u8]],
+    ) -> CanFail<IOError> {
         let mut read_fis = RegisterHostDeviceFIS::new_empty();
-        let sector_size = self.device_info.logical_sector_size();
         read_fis.set_command(ATA_READ_DMA);
         read_fis.set_device(1 << 6);
         read_fis.set_lba(start_lba);
         read_fis.set_count(sectors_count);
         read_fis.set_command_update_bit(true);
 
-        let mut ahci_transaction = AHCITransaction::new();
-        ahci_transaction.set_byte_size(
-            (sectors_count as u32 * self.device_info.logical_sector_size()) as usize,
-        );
+        let byte_size = sectors_count as usize * self.device_info.logical_sector_size() as usize;
+
+        let (slot, buffer) = {
+            let ahci = AHCI_CONTROLLER.get().unwrap().lock();
+            let buffer = Arc::new(ahci.dma_alloc(byte_size)?);
+
+            let mut ahci_transaction = AHCITransaction::new();
+            ahci_transaction.set_byte_size(byte_size);
+            ahci_transaction.build_command_table(
+                &ahci,
+                &read_fis,
+                &[0u8; 0],
+                self.prd_table(&buffer, byte_size),
+            )?;
+            ahci_transaction.set_data_buffer(buffer.clone());
+
+            let slot = ahci.dispatch_command(self.ahci_data.port, ahci_transaction);
 
+            (slot, buffer)
+        };
+
+        wait_for_command(slot as u8, Duration::from_secs(10))?;
+
+        let mut data = &buffer.as_slice()[..byte_size];
+        for target in buffers.iter_mut() {
+            let (chunk, remaining) = data.split_at(target.len());
+            target.copy_from_slice(chunk);
+            data = remaining;
+        }
+
+        Ok(())
+    }
+
+    /// Builds the PRD table describing the first `size` bytes of `buffer`.
+    ///
+    /// Each entry describes at most 16 sectors, the last one requests an interrupt on completion.
+    fn prd_table(&self, buffer: &DmaBuffer, size: usize) -> Vec<AHCIPhysicalRegionDescriptor> {
+        let max_prd_size = 16 * self.device_info.logical_sector_size() as usize;
         let mut prdtl = alloc::vec![];
-        let prdt_count = (((sectors_count - 1) >> 4) + 1) as isize;
+        let mut offset = 0;
 
-        for i in 0..prdt_count - 1 {
+        while offset < size {
+            let prd_size = usize::min(max_prd_size, size - offset);
             let mut prdt = AHCIPhysicalRegionDescriptor::new_empty();
 
-            prdt.set_base_address(buffer.offset(i * 16 * sector_size as isize));
-            prdt.set_data_bytes_count(16 * sector_size);
-            prdt.set_interrupt_on_completion(true);
+            prdt.set_base_address(buffer.dma_addr() + offset as u64);
+            prdt.set_data_bytes_count(prd_size as u32);
 
             prdtl.push(prdt);
+            offset += prd_size;
         }
 
-        let mut last_prdt = AHCIPhysicalRegionDescriptor::new_empty();
-        last_prdt.set_base_address(buffer.offset((prdt_count - 1) * 16 * sector_size as isize));
-        last_prdt.set_data_bytes_count(
-            (sectors_count as u32 * sector_size) - ((prdt_count - 1) as u32 * 16 * sector_size),
-        );
-        prdtl.push(last_prdt);
-
-        ahci_transaction
-            .header
-            .build_command_table(&read_fis, &[0u8; 0], prdtl);
-
-        let ahci = AHCI_CONTROLLER.get().unwrap().lock();
-        let port = ahci.read_port_register(self.ahci_data.port);
+        if let Some(last_prdt) = prdtl.last_mut() {
+            last_prdt.set_interrupt_on_completion(true);
+        }
 
-        port.dispatch_command(ahci_transaction)
+        prdtl
     }
 
-    fn internal_device_diagnostic(&mut self) {
+    fn internal_device_diagnostic(&mut self) -> CanFail<IOError> {
         let mut diag_fis = RegisterHostDeviceFIS::new_empty();
         diag_fis.set_command(ATA_EXECUTE_DEVICE_DIAGNOSTIC);
         diag_fis.set_device(0);
         diag_fis.set_command_update_bit(true);
 
-        let mut ahci_transaction = AHCITransaction::new();
-        ahci_transaction
-            .header
-            .build_command_table(&diag_fis, &[0u8; 0], alloc::vec![]);
-
         let ahci = AHCI_CONTROLLER.get().unwrap().lock();
 
-        let port = ahci.read_port_register(0);
+        let mut ahci_transaction = AHCITransaction::new();
+        ahci_transaction.build_command_table(&ahci, &diag_fis, &[0u8; 0], alloc::vec![])?;
+
+        ahci.dispatch_command(self.ahci_data.port, ahci_transaction);
 
-        port.dispatch_command(ahci_transaction);
+        Ok(())
     }
 
-    fn dispach_ata_identify(&mut self, port: &mut HBAPort) -> [u16; 256] {
+    fn dispach_ata_identify(&self) -> [u16; 256] {
         let mut identify_fis = RegisterHostDeviceFIS::new_empty();
         identify_fis.set_command(ATA_IDENTIFY_DEVICE);
         identify_fis.set_device(0);
         identify_fis.set_command_update_bit(true);
 
-        let mut recv_buffer = [0u16; 256];
+        let (slot, buffer) = {
+            let ahci = AHCI_CONTROLLER.get().unwrap().lock();
+            let buffer = Arc::new(
+                ahci.dma_alloc(0x200)
+                    .expect("Cannot allocate the buffer of the ATA IDENTIFY command"),
+            );
 
-        let mut prdt1 = AHCIPhysicalRegionDescriptor::new_empty();
-        prdt1.set_base_address(recv_buffer.as_mut_ptr() as *mut u8);
-        prdt1.set_data_bytes_count(0x200);
+            let mut prdt1 = AHCIPhysicalRegionDescriptor::new_empty();
+            prdt1.set_base_address(buffer.dma_addr());
+            prdt1.set_data_bytes_count(0x200);
 
-        let mut ahci_transaction = AHCITransaction::new();
-        ahci_transaction
-            .header
-            .build_command_table(&identify_fis, &[0u8; 0], alloc::vec![prdt1]);
-        ahci_transaction.set_byte_size(0x200);
+            let mut ahci_transaction = AHCITransaction::new();
+            ahci_transaction
+                .build_command_table(&ahci, &identify_fis, &[0u8; 0], alloc::vec![prdt1])
+                .expect("Cannot allocate the command table of the ATA IDENTIFY command");
+            ahci_transaction.set_byte_size(0x200);
+            ahci_transaction.set_data_buffer(buffer.clone());
+
+            let slot = ahci.dispatch_command(self.ahci_data.port, ahci_transaction);
 
-        port.dispatch_command(ahci_transaction);
+            (slot, buffer)
+        };
+
+        wait_for_command(slot as u8, Duration::from_secs(1))
+            .expect("SATA device did not respond to the ATA IDENTIFY command");
 
         assert_eq!(
-            port.read_received_fis().pio_setup().transfer_count(),
-            0x200,
+            AHCI_CONTROLLER
+                .get()
+                .unwrap()
+                .lock()
+                .received_fis(self.ahci_data.port)
+                .map(|fis| fis.pio_setup().transfer_count()),
+            Some(0x200),
             "Invalid response from SATA device when issuing ATA IDENTIFY command"
         );
 
+        let mut recv_buffer = [0u16; 256];
+        bytemuck::cast_slice_mut(&mut recv_buffer).copy_from_slice(&buffer.as_slice()[..0x200]);
+
         assert_eq!(
             recv_buffer[0] & (1 << 15),
             0,
@@ -1,6 +1,9 @@
 //! AHCI driver for `FrozenBoot`.
 
-use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+
+use alloc::{boxed::Box, collections::BTreeMap, format, sync::Arc, vec::Vec};
 use conquer_once::spin::OnceCell;
 use fzproc_macros::interrupt_handler;
 use spin::RwLock;
@@ -8,20 +11,31 @@ use spin::RwLock;
 use crate::{
     drivers::{
         ahci::{
-            command::{AHCICommandHeader, AHCITransaction},
+            command::AHCITransaction,
             device::AHCIDrive,
-            port::{AHCIDeviceDetection, HBAPort, HBAPortReceivedFIS, SATA_ATA_SIG},
+            port::{AHCIDeviceDetection, HBAPort, HBAPortReceivedFIS, PortMemory, SATA_ATA_SIG},
         },
+        devtree::{devtree_add_disk, devtree_remove_disk, DevicePath},
+        generics::dev_disk::SataDeviceType,
         ide::AtaDeviceIdentifier,
         pci::{
             device::{MappedRegister, PCIDevice, PCIMappedMemory},
-            DeviceClass, PCI_DEVICES,
+            driver::{PciDeviceId, PciDriver},
+            DeviceClass,
+        },
+    },
+    error,
+    errors::{CanFail, IOError},
+    info,
+    irq::{deferred::defer, manager::get_interrupt_manager, InterruptStackFrame},
+    mem::dma::{dma_alloc, DmaBuffer, DmaDevice, DMA_MASK_32, DMA_MASK_64},
+    sync::waitqueue::{poll_until_timeout, WaitQueue},
+    x86::{
+        apic::{
+            io_apic::get_all_io_apics, local_apic::VectorPriorityClass, mp_table::IOApicIntPin,
         },
+        int::{disable_interrupts, enable_interrupts, interrupts_disabled},
     },
-    error, info,
-    irq::{manager::get_interrupt_manager, InterruptStackFrame},
-    wait_for, wait_for_or,
-    x86::apic::{io_apic::get_all_io_apics, mp_table::IOApicIntPin, InterruptVector},
 };
 
 pub mod device;
@@ -36,6 +50,18 @@ pub const GHC_BOFFSET: isize = 0x00;
 /// Offset of the ports registers in the HBA Memory (in bytes).
 pub const PORT_REG_OFFSET: isize = 0x100;
 
+/// Maximum time taken by the firmware to release the controller, after the OS requested its ownership.
+const AHCI_BIOS_HANDOFF_TIMEOUT: Duration = Duration::from_millis(25);
+
+/// Maximum time taken by the controller to complete a reset.
+const AHCI_HBA_RESET_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Time given to a device to establish communication with a port, before considering that no device is attached.
+///
+/// Several times the polling interval (see [`POLL_INTERVAL`](crate::sync::waitqueue::POLL_INTERVAL)), so that the
+/// detection state is checked more than once.
+const AHCI_DEVICE_DETECTION_TIMEOUT: Duration = Duration::from_millis(10);
+
 /// Global internal `AHCI Controller` interface, usable after PCI enumeration if such a controller is
 /// available on the system.
 pub static AHCI_CONTROLLER: OnceCell<spin::Mutex<AHCIController>> = OnceCell::uninit();
@@ -45,6 +71,17 @@ pub static AHCI_CONTROLLER: OnceCell<spin::Mutex<AHCIController>> = OnceCell::un
 pub static SATA_COMMAND_QUEUE: spin::Mutex<BTreeMap<u8, AHCITransaction>> =
     spin::Mutex::new(BTreeMap::new());
 
+/// Commands of the [`SATA_COMMAND_QUEUE`] that completed, indexed by command slot.
+static SATA_RETIRED_COMMANDS: spin::Mutex<[Option<AHCITransaction>; 32]> =
+    spin::Mutex::new([const { None }; 32]);
+
+/// Woken up every time commands from the [`SATA_COMMAND_QUEUE`] complete.
+pub static SATA_COMMAND_COMPLETION: WaitQueue = WaitQueue::new();
+
+/// Bitmap of the ports on which a device was connected or disconnected, since the last call to
+/// [`process_hotplug_events`].
+static HOTPLUG_PENDING_PORTS: AtomicU32 = AtomicU32::new(0);
+
 pub fn ahci_devices() -> &'static RwLock<BTreeMap<AtaDeviceIdentifier, Arc<AHCIDrive>>> {
     static AHCI_DEVICES: OnceCell<RwLock<BTreeMap<AtaDeviceIdentifier, Arc<AHCIDrive>>>> =
         OnceCell::uninit();
@@ -54,101 +91,149 @@ pub fn ahci_devices() -> &'static RwLock<BTreeMap<AtaDeviceIdentifier, Arc<AHCID
         .unwrap()
 }
 
+/// PCI driver for `AHCI` controllers.
+pub static AHCI_DRIVER: PciDriver = PciDriver {
+    name: "ahci",
+    id_table: &[PciDeviceId::class(DeviceClass::SATAControllerAHCI)],
+    probe: ahci_probe,
+    suspend: None,
+    resume: None,
+    remove: None,
+};
+
 /// Initialize the [`AHCIController`] into a minimal working state.
 ///
 /// Performs a firmware initialization phase, and then a system software phase.
 /// Enumerates the available SATA devices, and sets up the corresponding ports on the HBA, as well
 /// as the device itself.
-pub fn ahci_init() {
-    let mut pci_dev_opt = PCI_DEVICES
-        .get()
-        .unwrap()
-        .get_by_class(DeviceClass::SATAControllerAHCI);
+///
+/// Only a single controller is supported, probing another one fails with [`IOError::InvalidDevice`].
+fn ahci_probe(pci_dev: &mut PCIDevice<'static>) -> CanFail<IOError> {
+    if AHCI_CONTROLLER.is_initialized() {
+        return Err(IOError::InvalidDevice);
+    }
 
-    let pci_dev = match pci_dev_opt.get_mut(0) {
-        Some(dev) => dev,
-        None => return,
-    };
+    let vector = get_interrupt_manager()
+        .allocate_vector(VectorPriorityClass::DEVICE, "ahci")
+        .map_err(|e| IOError::Exception(Box::new(e)))?;
 
     for io_apic in get_all_io_apics().unwrap() {
-        io_apic.1.lock().map_pin_to_irq(
-            IOApicIntPin::from(pci_dev.interrupt_line()),
-            InterruptVector::from(0x77),
-        );
+        io_apic
+            .1
+            .lock()
+            .map_pin_to_irq(IOApicIntPin::from(pci_dev.interrupt_line()), vector);
     }
-    get_interrupt_manager().register_static_handler(InterruptVector::from(0x77), irq_entry);
+    get_interrupt_manager().register_static_handler(vector, irq_entry);
 
-    pci_dev.set_memory_space_access(true).unwrap();
-    pci_dev.set_interrupt_disable(false).unwrap();
-    pci_dev.set_bus_master(true).unwrap();
+    pci_dev.set_memory_space_access(true)?;
+    pci_dev.set_interrupt_disable(false)?;
+    pci_dev.set_bus_master(true)?;
 
-    AHCI_CONTROLLER.init_once(|| {
-        spin::Mutex::new(unsafe { AHCIController::try_from_pci_device(pci_dev).unwrap() })
-    });
+    let controller =
+        unsafe { AHCIController::try_from_pci_device(pci_dev) }.ok_or(IOError::InvalidDevice)?;
+    AHCI_CONTROLLER.init_once(|| spin::Mutex::new(controller));
     let mut ahci_ctrl = unsafe { AHCI_CONTROLLER.get().unwrap_unchecked().lock() };
 
     // Performs BIOS/OS Handoff is available.
     if ahci_ctrl.read_ghc().hba_cap_bios_os_handoff() {
         ahci_ctrl.read_ghc().hba_request_ownership(true);
-        wait_for!(!ahci_ctrl.read_ghc().hba_bohc_bos(), 1);
+        let released = poll_until_timeout(
+            || !ahci_ctrl.read_ghc().hba_bohc_bos(),
+            AHCI_BIOS_HANDOFF_TIMEOUT,
+        );
+        if !released {
+            error!(
+                "ahci",
+                "firmware did not release the controller, taking it over"
+            );
+        }
     }
 
     // Performs a HBA hard reset.
     ahci_ctrl.reset();
-    wait_for!(ahci_ctrl.read_ghc().hba_ghc_rst(), 50);
+    let reset = poll_until_timeout(
+        || !ahci_ctrl.read_ghc().hba_ghc_rst(),
+        AHCI_HBA_RESET_TIMEOUT,
+    );
+    if !reset {
+        error!("ahci", "controller reset timed out");
+        return Err(IOError::IOTimeout);
+    }
     ahci_ctrl.enable();
 
     // Setup each implemented port.
-    ahci_ctrl
-        .read_ghc()
-        .ports_implemented()
-        .iter()
-        .map(|&i| ahci_ctrl.read_port_register(i))
-        .for_each(|port| {
-            port.port_set_start(false);
-            port.port_enable_fis_receive(false);
-            wait_for_or!(
+    for i in ahci_ctrl.read_ghc().ports_implemented() {
+        let port = ahci_ctrl.read_port_register(i);
+        port.port_set_start(false);
+        port.port_enable_fis_receive(false);
+        let stopped = poll_until_timeout(
+            || {
                 !(port.port_start()
                     || port.port_command_list_dma_engine_running()
-                    || port.port_fis_receive_dma_engine_running()),
-                50,
-                return
-            );
-            // Allocate memory for received FIS and for the command list.
-            let fis_receive = Box::new(HBAPortReceivedFIS::new());
-            port.port_set_fis_base_address(Box::into_raw(fis_receive) as *mut u8);
+                    || port.port_fis_receive_dma_engine_running())
+            },
+            Duration::from_millis(50),
+        );
+        if !stopped {
+            continue;
+        }
+
+        // Allocate memory for received FIS and for the command list.
+        let memory = match PortMemory::new(&ahci_ctrl) {
+            Ok(memory) => memory,
+            Err(_) => {
+                error!("ahci", "cannot allocate the memory of port {i}");
+                continue;
+            }
+        };
+        memory.attach(port);
+        ahci_ctrl.ports_memory.insert(i, memory);
 
-            let command_list = Box::new([AHCICommandHeader::new_empty(); 32]);
-            port.port_set_cmdlist_base_address(Box::into_raw(command_list) as *mut u8);
+        let port = ahci_ctrl.read_port_register(i);
+        port.port_enable_fis_receive(true);
 
-            port.port_enable_fis_receive(true);
+        // Hotplug events are reported on every port, including the ones without a device yet.
+        port.clear_sata_errors();
+        port.clear_interrupts();
+        port.port_enable_change_interrupt(true);
+        port.port_enable_phyrdy_change_interrupt(true);
 
-            if ahci_ctrl.read_ghc().hba_cap_ss_support() {
-                port.port_spin_up_device(true);
-            }
+        if ahci_ctrl.read_ghc().hba_cap_ss_support() {
+            port.port_spin_up_device(true);
+        }
 
-            wait_for_or!(
+        let detected = poll_until_timeout(
+            || {
                 matches!(
                     port.port_interface_device_detection(),
                     AHCIDeviceDetection::DeviceDetectedPhysicalCom,
-                ),
-                1,
-                return
-            );
+                )
+            },
+            AHCI_DEVICE_DETECTION_TIMEOUT,
+        );
+        if !detected {
+            continue;
+        }
 
-            port.serr = 0xffffffff;
-            wait_for_or!(!(port.device_busy() || port.device_drq()), 50, return);
-
-            // clear interrupts before enabling them.
-            port.is = 0;
-            port.ie = 0xffffffff;
-            if matches!(
-                port.port_interface_device_detection(),
-                AHCIDeviceDetection::DeviceDetectedPhysicalCom
-            ) {
-                port.port_set_start(true);
-            }
-        });
+        port.serr = 0xffffffff;
+        let ready = poll_until_timeout(
+            || !(port.device_busy() || port.device_drq()),
+            Duration::from_millis(50),
+        );
+        if !ready {
+            continue;
+        }
+
+        // clear interrupts before enabling them.
+        port.is = 0;
+        port.ie = 0xffffffff;
+        if matches!(
+            port.port_interface_device_detection(),
+            AHCIDeviceDetection::DeviceDetectedPhysicalCom
+        ) {
+            port.port_set_start(true);
+        }
+    }
 
     ahci_ctrl.read_ghc().set_hba_ghc_interrupt_enable(true);
     info!("ahci", "initializing AHCI controller");
@@ -164,6 +249,8 @@ pub fn ahci_init() {
         AHCI_CONTROLLER.get_unchecked().force_unlock();
         ahci_ctrl.load_sata_drives();
     }
+
+    Ok(())
 }
 
 /// AHCI controller related IRQs entry point.
@@ -184,9 +271,24 @@ pub fn irq_entry(frame: InterruptStackFrame) {
                 .copied()
                 .filter(|&i| !port.port_command_is_issued(i))
                 .collect();
+            // Releasing the DMA buffers of a transaction may require locks held by the interrupted code: they are
+            // released when the slot is used again instead (see `AHCIController::dispatch_command`).
+            let mut retired = SATA_RETIRED_COMMANDS.lock();
             for command_id in &commands_completed {
-                let transaction = unsafe { commands.get(command_id).unwrap_unchecked() };
-                commands.remove(command_id);
+                retired[usize::from(*command_id)] = commands.remove(command_id);
+            }
+            drop(retired);
+            drop(commands);
+
+            if !commands_completed.is_empty() {
+                SATA_COMMAND_COMPLETION.wake_all();
+            }
+
+            if port.port_phyrdy_changed() || port.port_connect_change() {
+                info!("ahci", "hotplug event on port {i}");
+                HOTPLUG_PENDING_PORTS.fetch_or(1 << i, Ordering::Relaxed);
+                port.clear_sata_errors();
+                defer(process_hotplug_events);
             }
 
             if port.tfd_error() != 0 {
@@ -204,6 +306,83 @@ pub fn irq_entry(frame: InterruptStackFrame) {
     ahci_ctrl.read_ghc().reset_pending_interrupts();
 }
 
+/// Handles the hotplug events recorded by [`irq_entry`], which defers its execution (see [`defer`]).
+///
+/// Newly connected drives are identified, added to the device tree and their partitions are loaded. Drives that were
+/// disconnected are removed from the device tree, which publishes a [`DeviceEvent::DiskDetached`] event (so that
+/// filesystems can be unmounted), before they are removed from [`ahci_devices`].
+///
+/// [`DeviceEvent::DiskDetached`]: crate::event::DeviceEvent::DiskDetached
+///
+/// Identifying a drive requires the `AHCI` interrupts to be serviced, this must not be called from an interrupt
+/// handler.
+pub fn process_hotplug_events() {
+    let pending = HOTPLUG_PENDING_PORTS.swap(0, Ordering::Relaxed);
+
+    let Some(controller) = AHCI_CONTROLLER.get() else {
+        return;
+    };
+
+    for port in (0..32u8).filter(|&i| pending & (1 << i) != 0) {
+        let id = AtaDeviceIdentifier::new(SataDeviceType::AHCI, 0, port.into());
+
+        let ahci_ctrl = controller.lock();
+        let port_reg = ahci_ctrl.read_port_register(port);
+        let present = matches!(
+            port_reg.port_interface_device_detection(),
+            AHCIDeviceDetection::DeviceDetectedPhysicalCom
+        );
+        drop(ahci_ctrl);
+
+        let registered = ahci_devices().read().contains_key(&id);
+
+        match (present, registered) {
+            (true, false) => attach_drive(port),
+            (false, true) => {
+                info!("ahci", "SATA device detached (port = {port})");
+                devtree_remove_disk(id);
+                ahci_devices().write().remove(&id);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Sets up a drive that was connected to `port` after the controller initialization, and adds it to the device tree.
+fn attach_drive(port: u8) {
+    let id = AtaDeviceIdentifier::new(SataDeviceType::AHCI, 0, port.into());
+    let ahci_ctrl = AHCI_CONTROLLER.get().unwrap().lock();
+    let port_reg = ahci_ctrl.read_port_register(port);
+
+    // The device signature is only available once the device sent its initial `Register FIS`.
+    let ready = poll_until_timeout(
+        || {
+            port_reg.port_device_sig() == SATA_ATA_SIG
+                && !(port_reg.device_busy() || port_reg.device_drq())
+        },
+        Duration::from_millis(50),
+    );
+    if !ready {
+        return;
+    }
+
+    port_reg.clear_sata_errors();
+    port_reg.clear_interrupts();
+    if !port_reg.port_start() {
+        port_reg.port_set_start(true);
+    }
+    let controller_path = ahci_ctrl.devtree_path.clone();
+    drop(ahci_ctrl);
+
+    let drive = Arc::new(AHCIDrive::build_from_ahci(port, port.into()));
+    ahci_devices().write().insert(id, drive.clone());
+
+    let path = devtree_add_disk(&controller_path, &format!("ata{port}"), id);
+    info!("ahci", "SATA device attached ({path})");
+
+    drive.load_partition_table();
+}
+
 /// Internal representation of an `AHCI Controller` (_Advanced Host Controller Interface_).
 ///
 /// Follows Intel's _AHCI Specifications 1.3.1_
@@ -211,6 +390,15 @@ pub fn irq_entry(frame: InterruptStackFrame) {
 /// devices using PCI-related methods (memory-mapped registers).
 pub struct AHCIController {
     hba_mem: PCIMappedMemory<'static>,
+
+    /// Path of the controller in the device tree.
+    devtree_path: DevicePath,
+
+    /// Identifies the controller for DMA.
+    dma_device: DmaDevice,
+
+    /// Command list and received FIS area of each port that was set up.
+    ports_memory: BTreeMap<u8, PortMemory>,
 }
 
 impl AHCIController {
@@ -227,8 +415,14 @@ impl AHCIController {
 
         if let MappedRegister::Memory(hba_mem) = hba_reg {
             let hba_mem = hba_mem.copy_ref();
-
-            return Some(Self { hba_mem });
+            let (bus, dev, function) = device.location();
+
+            return Some(Self {
+                hba_mem,
+                devtree_path: DevicePath::pci(bus, dev, function),
+                dma_device: DmaDevice::from(device),
+                ports_memory: BTreeMap::new(),
+            });
         }
 
         None
@@ -250,15 +444,14 @@ impl AHCIController {
                     );
                     let drive = AHCIDrive::build_from_ahci(port, port.into());
 
-                    let mut devices = ahci_devices().write();
-                    devices.insert(
-                        AtaDeviceIdentifier::new(
-                            crate::drivers::generics::dev_disk::SataDeviceType::AHCI,
-                            0,
-                            port.into(),
-                        ),
-                        Arc::new(drive),
+                    let id = AtaDeviceIdentifier::new(
+                        crate::drivers::generics::dev_disk::SataDeviceType::AHCI,
+                        0,
+                        port.into(),
                     );
+
+                    ahci_devices().write().insert(id, Arc::new(drive));
+                    devtree_add_disk(&self.devtree_path, &format!("ata{port}"), id);
                 }
             }
         }
@@ -295,6 +488,59 @@ impl AHCIController {
         }
     }
 
+    /// Allocates a zeroed buffer of at least `size` bytes, that the controller can access through DMA.
+    ///
+    /// The buffer is placed below 4 GiB if the controller does not support 64-bit addressing.
+    pub fn dma_alloc(&self, size: usize) -> Result<DmaBuffer, IOError> {
+        let mask = if self.read_ghc().hba_cap_64_addr_support() {
+            DMA_MASK_64
+        } else {
+            DMA_MASK_32
+        };
+
+        dma_alloc(self.dma_device, size, mask).map_err(|e| IOError::Exception(Box::new(e)))
+    }
+
+    /// Issues the command described by `cmd` on `port`, and returns the command slot it was issued in.
+    ///
+    /// The transaction is kept in the [`SATA_COMMAND_QUEUE`] until the command completes, and its buffers remain
+    /// allocated until another command is issued in the same slot.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `port` was not set up during the controller initialization.
+    pub fn dispatch_command(&self, port: u8, cmd: AHCITransaction) -> usize {
+        let memory = self
+            .ports_memory
+            .get(&port)
+            .expect("AHCI command issued on a port that was not set up");
+        let port = self.read_port_register(port);
+
+        let cmd_slot = port.find_command_slot();
+        memory.update_command_list_entry(cmd_slot, &cmd.header);
+
+        // The interrupt handler retires the transactions that complete, with the lock held.
+        let were_disabled = interrupts_disabled();
+        disable_interrupts();
+        let previous = SATA_RETIRED_COMMANDS.lock()[cmd_slot].take();
+        if !were_disabled {
+            enable_interrupts();
+        }
+        drop(previous);
+
+        while port.device_busy() || port.device_drq() {}
+
+        SATA_COMMAND_QUEUE.lock().insert(cmd_slot as u8, cmd);
+        port.port_command_set_issued(cmd_slot as u8);
+
+        cmd_slot
+    }
+
+    /// Returns the `FISes` received from the device attached to `port`, if the port was set up.
+    pub fn received_fis(&self, port: u8) -> Option<&HBAPortReceivedFIS> {
+        self.ports_memory.get(&port).map(PortMemory::received_fis)
+    }
+
     /// Performs a HBA reset on the `AHCIController`.
     ///
     /// It performs the following actions:
@@ -1,12 +1,19 @@
+use core::mem;
+use core::time::Duration;
+
 use super::fis::{DMASetupFIS, PIOSetupFIS, RegisterDeviceHostFIS, SetDeviceBitsFIS};
 use crate::{
-    drivers::ahci::{
-        command::{AHCICommandHeader, AHCITransaction},
-        SATA_COMMAND_QUEUE,
-    },
-    hba_reg_field, wait, wait_for, while_timeout,
+    drivers::ahci::{command::AHCICommandHeader, AHCIController},
+    errors::{CanFail, IOError},
+    hba_reg_field,
+    mem::{dma::DmaBuffer, get_physical_memory},
+    sync::waitqueue::poll_until_timeout,
+    wait, while_timeout,
 };
 
+/// Maximum time taken by a port to stop processing its command list.
+const PORT_STOP_TIMEOUT: Duration = Duration::from_millis(500);
+
 /// ATA Signature field for a `SATA` device.
 pub const SATA_ATA_SIG: u32 = 0x101;
 
@@ -83,6 +90,47 @@ impl Default for HBAPortReceivedFIS {
     }
 }
 
+/// Memory shared by a port with the HBA: the `Command List` and the `Received FIS` area.
+///
+/// Both are allocated for DMA by the controller, the HBA accesses them using their bus address.
+#[derive(Debug)]
+pub struct PortMemory {
+    command_list: DmaBuffer,
+    received_fis: DmaBuffer,
+}
+
+impl PortMemory {
+    pub fn new(ahci: &AHCIController) -> Result<Self, IOError> {
+        Ok(Self {
+            command_list: ahci.dma_alloc(mem::size_of::<[AHCICommandHeader; 32]>())?,
+            received_fis: ahci.dma_alloc(mem::size_of::<HBAPortReceivedFIS>())?,
+        })
+    }
+
+    /// Programs the bus addresses of the `Command List` and of the `Received FIS` area into `port`.
+    pub fn attach(&self, port: &mut HBAPort) {
+        port.port_set_cmdlist_base_address(self.command_list.dma_addr());
+        port.port_set_fis_base_address(self.received_fis.dma_addr());
+    }
+
+    /// Returns the `FISes` received from the device.
+    pub fn received_fis(&self) -> &HBAPortReceivedFIS {
+        unsafe {
+            &*(get_physical_memory(self.received_fis.phys_addr()) as *const HBAPortReceivedFIS)
+        }
+    }
+
+    /// Updates a `Command Header` entry in the `Command List`.
+    pub fn update_command_list_entry(&self, id: usize, new_entry: &AHCICommandHeader) {
+        assert!(id < 32);
+
+        unsafe {
+            let command_list = get_physical_memory(self.command_list.phys_addr());
+            core::ptr::write_volatile((command_list as *mut AHCICommandHeader).add(id), *new_entry)
+        }
+    }
+}
+
 /// Internal representation of an `HBA Port`.
 ///
 /// Follows Intel's _AHCI Specifications 1.3.1_
@@ -145,31 +193,12 @@ pub struct HBAPort {
 }
 
 impl HBAPort {
-    /// Returns the [`HBAPortReceivedFIS`] for this port.
-    ///
-    /// It contains all `FISes` received from the device.
-    pub fn read_received_fis(&self) -> &HBAPortReceivedFIS {
-        unsafe { &*(self.port_fis_base_address() as *const HBAPortReceivedFIS) }
-    }
-
-    pub fn dispatch_command(&mut self, cmd: AHCITransaction) -> usize {
-        let cmd_slot = self.find_command_slot();
-        self.update_command_list_entry(cmd_slot, &cmd.header);
-
-        while self.device_busy() || self.device_drq() {}
-
-        SATA_COMMAND_QUEUE.lock().insert(cmd_slot as u8, cmd);
-        self.port_command_set_issued(cmd_slot as u8);
-
-        cmd_slot
-    }
-
     /// Returns an available command slot for this port.
     ///
     /// # Panic
     ///
     /// Panics if no slot became available in 50 milliseconds.
-    fn find_command_slot(&self) -> usize {
+    pub(super) fn find_command_slot(&self) -> usize {
         while_timeout!(
             false,
             50,
@@ -181,33 +210,27 @@ impl HBAPort {
         panic!("AHCI Timeout when trying to obtain a command slot");
     }
 
-    fn command_list(&self) -> &[AHCICommandHeader; 32] {
-        unsafe { &mut *(self.port_cmdlist_base_address() as *mut [AHCICommandHeader; 32]) }
-    }
-
-    fn command_list_mut(&mut self) -> &mut [AHCICommandHeader; 32] {
-        unsafe { &mut *(self.port_cmdlist_base_address() as *mut [AHCICommandHeader; 32]) }
-    }
-
-    /// Updates a `Command Header` entry in this port `Command List`.
-    pub fn update_command_list_entry(&mut self, id: usize, new_entry: &AHCICommandHeader) {
-        unsafe {
-            core::ptr::write_volatile(
-                &mut self.command_list_mut()[id] as *mut AHCICommandHeader,
-                *new_entry,
-            )
-        }
-    }
-
     /// Resets this `HBAPort`, by sending a _COMRESET_ to it.
-    pub fn hard_reset(&mut self) {
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::IOTimeout`] if the command list of the port could not be stopped beforehand.
+    pub fn hard_reset(&mut self) -> CanFail<IOError> {
         self.port_set_start(false);
-        wait_for!(!self.port_start(), 1);
+        let stopped = poll_until_timeout(
+            || !self.port_command_list_dma_engine_running(),
+            PORT_STOP_TIMEOUT,
+        );
+        if !stopped {
+            return Err(IOError::IOTimeout);
+        }
 
         self.interface_comreset();
         wait!(0.1);
 
         self.serr = 0xffffffff;
+
+        Ok(())
     }
 
     /// Returns the value of the `Err` bit of the `Status` field in the `Task file` register.
@@ -276,33 +299,27 @@ impl HBAPort {
         }
     }
 
-    /// Returns an a `Command Header` ([`AHCICommandHeader`]) in this port `Command List`, given
-    /// its position in the list.
-    pub fn get_command_list_entry(&self, id: usize) -> AHCICommandHeader {
-        unsafe { core::ptr::read_volatile(&self.command_list()[id] as *const AHCICommandHeader) }
-    }
-
-    /// Returns the physical address for the `Command List` for this port.
-    pub fn port_cmdlist_base_address(&self) -> *mut u8 {
+    /// Returns the bus address for the `Command List` for this port.
+    pub fn port_cmdlist_base_address(&self) -> u64 {
         let clbu = unsafe { core::ptr::read_volatile(&self.clbu as *const u32) };
         let clb = unsafe { core::ptr::read_volatile(&self.clb as *const u32) };
-        (((clbu as u64) << 32) | (clb as u64)) as *mut u8
+        ((clbu as u64) << 32) | (clb as u64)
     }
 
-    /// Sets the physical address for the `Command List` for this port.
+    /// Sets the bus address for the `Command List` for this port.
     ///
     /// # Panic
     ///
     /// Panics if the given address is not 1K-bytes aligned.
-    pub fn port_set_cmdlist_base_address(&mut self, address: *mut u8) {
+    pub fn port_set_cmdlist_base_address(&mut self, address: u64) {
         assert_eq!(
-            (address as usize) & ((1 << 10) - 1),
+            address & ((1 << 10) - 1),
             0,
             "Invalid alignement for the Command List Base Address (must be 1K-bytes aligned)"
         );
 
-        let clbu = ((address as u64 >> 32) & 0xffffffff) as u32;
-        let clb = (address as u64 & 0xffffffff) as u32;
+        let clbu = ((address >> 32) & 0xffffffff) as u32;
+        let clb = (address & 0xffffffff) as u32;
 
         unsafe {
             core::ptr::write_volatile(&mut self.clbu as *mut u32, clbu);
@@ -310,26 +327,26 @@ impl HBAPort {
         }
     }
 
-    /// Returns the physical address for the received `FISes` for this port.
-    pub fn port_fis_base_address(&self) -> *mut u8 {
+    /// Returns the bus address for the received `FISes` for this port.
+    pub fn port_fis_base_address(&self) -> u64 {
         let fbu = unsafe { core::ptr::read_volatile(&self.fbu as *const u32) };
         let fb = unsafe { core::ptr::read_volatile(&self.fb as *const u32) };
-        (((fbu as u64) << 32) | (fb as u64)) as *mut u8
+        ((fbu as u64) << 32) | (fb as u64)
     }
 
-    /// Sets the physical address for the received `FISes` for this port.
+    /// Sets the bus address for the received `FISes` for this port.
     ///
     /// # Panic
     ///
     /// Panics if the given address is not 256-bytes aligned.
-    pub fn port_set_fis_base_address(&mut self, address: *mut u8) {
+    pub fn port_set_fis_base_address(&mut self, address: u64) {
         assert_eq!(
-            (address as usize) & ((1 << 8) - 1),
+            address & ((1 << 8) - 1),
             0,
             "Invalid alignement for the FIS Base Address (must be 256-bytes aligned)"
         );
-        let fbu = ((address as u64 >> 32) & 0xffffffff) as u32;
-        let fb = (address as u64 & 0xffffffff) as u32;
+        let fbu = ((address >> 32) & 0xffffffff) as u32;
+        let fb = (address & 0xffffffff) as u32;
 
         unsafe {
             core::ptr::write_volatile(&mut self.fbu as *mut u32, fbu);
@@ -424,6 +441,14 @@ impl HBAPort {
         }
     }
 
+    /// Clears the `Serial ATA Error` register.
+    ///
+    /// Clearing the `DIAG.N` and `DIAG.X` bits also clears the `PhyRdy Change` and `Port Connect Change` interrupt
+    /// status.
+    pub fn clear_sata_errors(&mut self) {
+        unsafe { core::ptr::write_volatile(&mut self.serr as *mut u32, 0xffffffff) }
+    }
+
     /// Clears all pending interrupts for this port.
     pub fn clear_interrupts(&mut self) {
         unsafe {
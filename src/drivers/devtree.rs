@@ -0,0 +1,402 @@
+//! Device tree of the discovered hardware.
+//!
+//! Every device found during enumeration is recorded as a node of a tree (buses, then devices, then partitions), and
+//! named after its location in that tree:
+//!
+//! ```text
+//! /pci0/00:1f.2/ata0/part1
+//! ```
+//!
+//! Unlike the identifiers assigned by the drivers ([`AtaDeviceIdentifier`]), which depend on the order in which the
+//! devices were detected, these paths only depend on where the devices are connected. They are used to name devices
+//! in logs (see [`disk_name`]).
+//!
+//! Adding or removing a device publishes the corresponding event on the device event bus (see [`event`]).
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::drivers::devtree::{devtree_node, DevicePath};
+//!
+//! let node = devtree_node(&DevicePath::from("/pci0/00:1f.2/ata0"));
+//! ```
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Write};
+use core::mem::discriminant;
+use spin::RwLock;
+
+use crate::drivers::ide::AtaDeviceIdentifier;
+use crate::event::{self, DeviceEvent};
+use crate::fs::partitions::registry::partition_records;
+
+/// Every node of the tree, indexed by path (so that children follow their parent).
+static DEVICE_TREE: RwLock<BTreeMap<DevicePath, DeviceNode>> = RwLock::new(BTreeMap::new());
+
+/// Location of a node in the device tree, such as `/pci0/00:1f.2/ata0`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DevicePath(String);
+
+impl DevicePath {
+    /// Path of the root of the tree.
+    pub fn root() -> Self {
+        Self(String::from("/"))
+    }
+
+    /// Path of the PCI function at `bus:device.function`.
+    pub fn pci(bus: u8, device: u8, function: u8) -> Self {
+        Self::root()
+            .join(PCI_BUS_NAME)
+            .join(&format!("{bus:02x}:{device:02x}.{function:x}"))
+    }
+
+    /// Path of the child `name` of this node.
+    pub fn join(&self, name: &str) -> Self {
+        match self.0.as_str() {
+            "/" => Self(format!("/{name}")),
+            path => Self(format!("{path}/{name}")),
+        }
+    }
+
+    /// Path of the parent of this node (`None` for the root).
+    pub fn parent(&self) -> Option<Self> {
+        let (parent, _) = self.0.rsplit_once('/')?;
+
+        match parent {
+            "" if self.0 != "/" => Some(Self::root()),
+            "" => None,
+            parent => Some(Self(String::from(parent))),
+        }
+    }
+
+    /// Name of this node, relative to its parent.
+    pub fn name(&self) -> &str {
+        self.0.rsplit('/').next().unwrap_or_default()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether this node is `ancestor` itself, or one of its descendants.
+    fn is_within(&self, ancestor: &Self) -> bool {
+        self == ancestor
+            || ancestor.0 == "/"
+            || self
+                .0
+                .strip_prefix(ancestor.0.as_str())
+                .is_some_and(|rest| rest.starts_with('/'))
+    }
+}
+
+impl From<&str> for DevicePath {
+    fn from(value: &str) -> Self {
+        match value.trim_end_matches('/') {
+            "" => Self::root(),
+            path => Self(String::from(path)),
+        }
+    }
+}
+
+impl Display for DevicePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Name of the PCI bus node.
+const PCI_BUS_NAME: &str = "pci0";
+
+/// Name of the node grouping the devices that are not connected to a bus (such as persistent memory).
+const PLATFORM_BUS_NAME: &str = "platform";
+
+/// What a node of the device tree represents.
+#[derive(Clone, Copy, Debug)]
+pub enum DeviceNodeKind {
+    Bus,
+
+    /// PCI function, with its location (`bus`, `device`, `function`).
+    Pci(u8, u8, u8),
+
+    /// Disk device, exposed to the block layer.
+    Disk(AtaDeviceIdentifier),
+
+    /// Partition of a disk device, with its index in the partition table.
+    Partition(AtaDeviceIdentifier, usize),
+}
+
+/// Node of the device tree.
+#[derive(Clone, Debug)]
+pub struct DeviceNode {
+    pub path: DevicePath,
+    pub kind: DeviceNodeKind,
+
+    /// Short human-readable description.
+    pub description: String,
+}
+
+/// Adds the node `name`, child of `parent`, replacing any node previously recorded at that path.
+///
+/// Missing ancestors are added as [`DeviceNodeKind::Bus`] nodes. Returns the path of the node.
+pub fn devtree_insert(
+    parent: &DevicePath,
+    name: &str,
+    kind: DeviceNodeKind,
+    description: String,
+) -> DevicePath {
+    let path = parent.join(name);
+    let mut tree = DEVICE_TREE.write();
+
+    let mut ancestor = path.parent();
+    while let Some(node) = ancestor.filter(|node| node.0 != "/" && !tree.contains_key(node)) {
+        ancestor = node.parent();
+        tree.insert(
+            node.clone(),
+            DeviceNode {
+                path: node,
+                kind: DeviceNodeKind::Bus,
+                description: String::from("bus"),
+            },
+        );
+    }
+
+    tree.insert(
+        path.clone(),
+        DeviceNode {
+            path: path.clone(),
+            kind,
+            description,
+        },
+    );
+
+    path
+}
+
+/// Removes a node, and all of its descendants.
+pub fn devtree_remove(path: &DevicePath) {
+    DEVICE_TREE.write().retain(|node, _| !node.is_within(path));
+}
+
+/// Returns the node at `path`.
+pub fn devtree_node(path: &DevicePath) -> Option<DeviceNode> {
+    DEVICE_TREE.read().get(path).cloned()
+}
+
+/// Returns the direct children of the node at `path`.
+pub fn devtree_children(path: &DevicePath) -> Vec<DeviceNode> {
+    DEVICE_TREE
+        .read()
+        .values()
+        .filter(|node| node.path.parent().as_ref() == Some(path))
+        .cloned()
+        .collect()
+}
+
+/// Adds a PCI function to the tree, under the PCI bus node, and publishes a [`DeviceEvent::PciAdded`] event.
+pub fn devtree_add_pci(location: (u8, u8, u8), description: String) -> DevicePath {
+    let (bus, device, function) = location;
+    let path = DevicePath::pci(bus, device, function);
+
+    let path = devtree_insert(
+        &path.parent().unwrap_or_else(DevicePath::root),
+        path.name(),
+        DeviceNodeKind::Pci(bus, device, function),
+        description,
+    );
+    event::publish(DeviceEvent::PciAdded {
+        location,
+        path: path.clone(),
+    });
+
+    path
+}
+
+/// Removes a PCI function, and the devices connected to it, from the tree, and publishes a
+/// [`DeviceEvent::PciRemoved`] event.
+pub fn devtree_remove_pci(location: (u8, u8, u8)) {
+    let (bus, device, function) = location;
+    let path = DevicePath::pci(bus, device, function);
+
+    if devtree_node(&path).is_none() {
+        return;
+    }
+
+    devtree_remove(&path);
+    event::publish(DeviceEvent::PciRemoved { location, path });
+}
+
+/// Returns the location of every PCI function in the tree.
+pub fn devtree_pci_functions() -> Vec<(u8, u8, u8)> {
+    DEVICE_TREE
+        .read()
+        .values()
+        .filter_map(|node| match node.kind {
+            DeviceNodeKind::Pci(bus, device, function) => Some((bus, device, function)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Adds a disk device to the tree, as the child `name` of its controller, along with its partitions.
+///
+/// Publishes a [`DeviceEvent::DiskAttached`] event, followed by a [`DeviceEvent::PartitionFound`] event for each of
+/// its partitions.
+pub fn devtree_add_disk(
+    controller: &DevicePath,
+    name: &str,
+    drive_id: AtaDeviceIdentifier,
+) -> DevicePath {
+    let path = devtree_insert(
+        controller,
+        name,
+        DeviceNodeKind::Disk(drive_id),
+        format!("{} disk", disk_type_name(drive_id)),
+    );
+    event::publish(DeviceEvent::DiskAttached {
+        drive: drive_id,
+        path: path.clone(),
+    });
+    devtree_update_partitions(drive_id);
+
+    path
+}
+
+/// Adds a disk device that is not connected to a bus (such as persistent memory) to the tree.
+pub fn devtree_add_platform_disk(name: &str, drive_id: AtaDeviceIdentifier) -> DevicePath {
+    devtree_add_disk(&DevicePath::root().join(PLATFORM_BUS_NAME), name, drive_id)
+}
+
+/// Removes a disk device, and its partitions, from the tree, and publishes a [`DeviceEvent::DiskDetached`] event.
+pub fn devtree_remove_disk(drive_id: AtaDeviceIdentifier) {
+    let Some(path) = disk_path(drive_id) else {
+        return;
+    };
+
+    devtree_remove(&path);
+    event::publish(DeviceEvent::DiskDetached {
+        drive: drive_id,
+        path,
+    });
+}
+
+/// Replaces the partition nodes of a disk device with the partitions recorded in the partition registry, and
+/// publishes a [`DeviceEvent::PartitionFound`] event for each of them.
+///
+/// Nothing is done if the disk is not in the tree yet: its partitions are added along with it.
+pub(crate) fn devtree_update_partitions(drive_id: AtaDeviceIdentifier) {
+    let Some(disk) = disk_path(drive_id) else {
+        return;
+    };
+
+    DEVICE_TREE.write().retain(|path, node| {
+        !(path.parent().as_ref() == Some(&disk)
+            && matches!(node.kind, DeviceNodeKind::Partition(..)))
+    });
+
+    for record in partition_records()
+        .into_iter()
+        .filter(|record| same_drive(record.drive_id, drive_id))
+    {
+        let description = record
+            .fs_label
+            .or(record.part_label)
+            .unwrap_or_else(|| String::from("partition"));
+
+        let path = devtree_insert(
+            &disk,
+            &partition_node_name(record.partition_id),
+            DeviceNodeKind::Partition(drive_id, record.partition_id),
+            description,
+        );
+        event::publish(DeviceEvent::PartitionFound {
+            drive: drive_id,
+            partition: record.partition_id,
+            path,
+        });
+    }
+}
+
+/// Returns the path of a disk device.
+pub fn disk_path(drive_id: AtaDeviceIdentifier) -> Option<DevicePath> {
+    DEVICE_TREE
+        .read()
+        .values()
+        .find(|node| matches!(node.kind, DeviceNodeKind::Disk(id) if same_drive(id, drive_id)))
+        .map(|node| node.path.clone())
+}
+
+/// Returns the path of a partition of a disk device.
+pub fn partition_path(drive_id: AtaDeviceIdentifier, partition_id: usize) -> Option<DevicePath> {
+    DEVICE_TREE
+        .read()
+        .values()
+        .find(|node| {
+            matches!(node.kind, DeviceNodeKind::Partition(id, partition)
+                if same_drive(id, drive_id) && partition == partition_id)
+        })
+        .map(|node| node.path.clone())
+}
+
+/// Name of a disk device, to be used in logs: its path if it is in the tree, or its identifier otherwise.
+pub fn disk_name(drive_id: AtaDeviceIdentifier) -> String {
+    disk_path(drive_id).map_or_else(|| drive_id.to_string(), |path| path.0)
+}
+
+/// Name of a partition of a disk device, to be used in logs.
+///
+/// The partition does not need to be in the tree yet (for instance, while its filesystem is being mounted), as long
+/// as its disk is.
+pub fn partition_name(drive_id: AtaDeviceIdentifier, partition_id: usize) -> String {
+    if let Some(disk) = disk_path(drive_id) {
+        return disk.join(&partition_node_name(partition_id)).0;
+    }
+
+    format!("{drive_id}    partition_id = {partition_id}")
+}
+
+/// Writes the device tree, one node per line, indented according to its depth.
+pub fn devtree_report(out: &mut impl Write) -> fmt::Result {
+    let tree = DEVICE_TREE.read();
+
+    if tree.is_empty() {
+        return writeln!(out, "no device discovered");
+    }
+
+    for node in tree.values() {
+        let depth = node.path.as_str().matches('/').count();
+
+        writeln!(
+            out,
+            "{:indent$}{:<16} {}",
+            "",
+            node.path.name(),
+            node.description,
+            indent = 2 * depth.saturating_sub(1)
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Identifiers of devices of different types may compare equal.
+fn same_drive(a: AtaDeviceIdentifier, b: AtaDeviceIdentifier) -> bool {
+    a == b && discriminant(&a.disk_type) == discriminant(&b.disk_type)
+}
+
+/// Partitions are numbered from 1, as usual.
+fn partition_node_name(partition_id: usize) -> String {
+    format!("part{}", partition_id + 1)
+}
+
+fn disk_type_name(drive_id: AtaDeviceIdentifier) -> &'static str {
+    use crate::drivers::generics::dev_disk::SataDeviceType;
+
+    match drive_id.disk_type {
+        SataDeviceType::IDE => "IDE",
+        SataDeviceType::AHCI => "SATA",
+        SataDeviceType::Memory => "memory",
+    }
+}
@@ -0,0 +1,359 @@
+//! Standard API to interact with byte-addressable storage devices.
+//!
+//! Some storage devices are not accessed through sectors, but directly through memory loads and stores: persistent
+//! memory regions (reported by the firmware as E820 type 7), or firmware volumes mapped from a flash chip. They
+//! implement the [`ByteDevice`] trait, which allows reading and writing at any byte offset, so that small blobs (such
+//! as configuration data) can be stored on them without a filesystem.
+//!
+//! A [`ByteBlockDevice`] exposes a byte device to the block layer, as a [`DiskDevice`] with 512-byte sectors.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::drivers::generics::dev_byte::{byte_devices, ByteDevice};
+//!
+//! let pmem = byte_devices().read().values().next().cloned().unwrap();
+//!
+//! pmem.device().write_at(0x100, b"config")?;
+//! pmem.device().flush()?;
+//! ```
+
+use core::arch::asm;
+use core::ptr;
+use core::sync::atomic::AtomicBool;
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use spin::RwLock;
+
+use crate::drivers::devtree::devtree_add_platform_disk;
+use crate::drivers::generics::dev_disk::{DiskDevice, SataDeviceType};
+use crate::drivers::ide::ata_command::AtaCommand;
+use crate::drivers::ide::ata_pio::{AtaError, AtaErrorCode, AtaIoRequest, AtaIoResult, AtaResult};
+use crate::drivers::ide::AtaDeviceIdentifier;
+use crate::errors::{CanFail, IOError};
+use crate::fs::partitions::Partition;
+use crate::info;
+use crate::mem::e820::{memory_regions, MemoryRegionKind};
+use crate::mem::{get_physical_memory, PhyAddr};
+
+/// Size of the sectors of a [`ByteBlockDevice`], in bytes.
+pub const BYTE_DEVICE_SECTOR_SIZE: u64 = 512;
+
+/// Size of a cache line, the granularity of cache write-backs.
+const CACHE_LINE_SIZE: usize = 64;
+
+/// Returns the list of the byte-addressable devices exposed to the block layer, indexed by their identifier.
+pub fn byte_devices() -> &'static RwLock<BTreeMap<AtaDeviceIdentifier, Arc<ByteBlockDevice>>> {
+    static BYTE_DEVICES: OnceCell<RwLock<BTreeMap<AtaDeviceIdentifier, Arc<ByteBlockDevice>>>> =
+        OnceCell::uninit();
+
+    BYTE_DEVICES
+        .try_get_or_init(|| RwLock::new(BTreeMap::new()))
+        .unwrap()
+}
+
+/// Registers a byte-addressable device, and exposes it to the block layer.
+///
+/// Returns the identifier assigned to the device.
+pub fn register_byte_device(device: Arc<dyn ByteDevice>) -> AtaDeviceIdentifier {
+    let mut devices = byte_devices().write();
+
+    let device_id = devices.keys().map(|id| id.device_id + 1).max().unwrap_or(0);
+    let id = AtaDeviceIdentifier::new(SataDeviceType::Memory, 0, device_id);
+
+    devices.insert(id, Arc::new(ByteBlockDevice::new(id, device)));
+    drop(devices);
+
+    devtree_add_platform_disk(&format!("pmem{device_id}"), id);
+
+    id
+}
+
+/// Registers a [`MemoryByteDevice`] for each persistent memory region reported by the firmware memory map.
+///
+/// Regions that cannot be addressed through the current physical memory mapping are skipped.
+pub fn pmem_devices_init() {
+    let regions: Vec<(u64, u64)> = memory_regions()
+        .lock()
+        .iter()
+        .filter(|region| region.kind == MemoryRegionKind::Persistent)
+        .map(|region| (region.base, region.length))
+        .collect();
+
+    for (base, length) in regions {
+        if usize::try_from(base + length).is_err() {
+            continue;
+        }
+
+        let id = register_byte_device(Arc::new(MemoryByteDevice::persistent(
+            PhyAddr::new(base),
+            length,
+        )));
+
+        info!(
+            "pmem",
+            "persistent memory region (base = {:#x}    size = {:#x}) registered as {}",
+            base,
+            length,
+            id
+        );
+    }
+}
+
+/// Storage device that can be read and written at any byte offset.
+pub trait ByteDevice: Send + Sync {
+    /// Reads `buffer.len()` bytes from the device, starting at `offset`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::InvalidCommand`] if the read goes past the end of the device. May return any other variant
+    /// of [`IOError`] in case of a device failure.
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> CanFail<IOError>;
+
+    /// Writes `data` to the device, starting at `offset`.
+    ///
+    /// Data may not be durable until [`ByteDevice::flush`] returns.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::InvalidCommand`] if the device is read-only, or if the write goes past the end of the
+    /// device. May return any other variant of [`IOError`] in case of a device failure.
+    fn write_at(&self, offset: u64, data: &[u8]) -> CanFail<IOError>;
+
+    /// Waits until all the data previously written is durable.
+    ///
+    /// The default implementation does nothing, for devices that do not buffer writes.
+    fn flush(&self) -> CanFail<IOError> {
+        Ok(())
+    }
+
+    /// Returns the size of the device, in bytes.
+    fn size(&self) -> u64;
+
+    /// Returns whether the device can only be read.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+/// Byte-addressable device backed by a region of physical memory.
+///
+/// Used for persistent memory regions, and for memory-mapped flash (which is read-only: programming a flash chip
+/// requires chip-specific commands).
+#[derive(Debug)]
+pub struct MemoryByteDevice {
+    base: PhyAddr,
+    size: u64,
+    read_only: bool,
+
+    /// Whether written data must be written back from the processor caches to be durable.
+    persistent: bool,
+}
+
+impl MemoryByteDevice {
+    /// Persistent memory region, starting at `base`.
+    pub const fn persistent(base: PhyAddr, size: u64) -> Self {
+        Self {
+            base,
+            size,
+            read_only: false,
+            persistent: true,
+        }
+    }
+
+    /// Read-only memory region (such as a flash-mapped firmware volume), starting at `base`.
+    pub const fn read_only(base: PhyAddr, size: u64) -> Self {
+        Self {
+            base,
+            size,
+            read_only: true,
+            persistent: false,
+        }
+    }
+
+    /// Returns a pointer to the byte at `offset`, if `len` bytes starting at `offset` belong to the device.
+    fn range_ptr(&self, offset: u64, len: usize) -> Result<*mut u8, IOError> {
+        let end = offset
+            .checked_add(len as u64)
+            .ok_or(IOError::InvalidCommand)?;
+
+        if end > self.size {
+            return Err(IOError::InvalidCommand);
+        }
+
+        Ok(get_physical_memory(self.base + offset))
+    }
+}
+
+impl ByteDevice for MemoryByteDevice {
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> CanFail<IOError> {
+        let src = self.range_ptr(offset, buffer.len())?;
+
+        unsafe {
+            ptr::copy_nonoverlapping(src, buffer.as_mut_ptr(), buffer.len());
+        }
+
+        Ok(())
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> CanFail<IOError> {
+        if self.read_only {
+            return Err(IOError::InvalidCommand);
+        }
+
+        let dst = self.range_ptr(offset, data.len())?;
+
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), dst, data.len());
+        }
+
+        if self.persistent {
+            write_back_cache_range(dst, data.len());
+        }
+
+        Ok(())
+    }
+
+    fn flush(&self) -> CanFail<IOError> {
+        // Written cache lines are flushed as they are written, wait until the write-backs complete.
+        if self.persistent {
+            unsafe {
+                asm!("sfence", options(nostack, preserves_flags));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+/// Adapter exposing a [`ByteDevice`] to the block layer, with [`BYTE_DEVICE_SECTOR_SIZE`] bytes sectors.
+///
+/// Byte devices hold raw data rather than partitioned disks, their partition table is never loaded.
+pub struct ByteBlockDevice {
+    id: AtaDeviceIdentifier,
+    inner: Arc<dyn ByteDevice>,
+    partitions: Vec<Arc<Partition>>,
+}
+
+unsafe impl Sync for ByteBlockDevice {}
+
+impl ByteBlockDevice {
+    pub fn new(id: AtaDeviceIdentifier, inner: Arc<dyn ByteDevice>) -> Self {
+        Self {
+            id,
+            inner,
+            partitions: Vec::new(),
+        }
+    }
+
+    /// Returns the underlying byte-addressable device.
+    pub fn device(&self) -> &Arc<dyn ByteDevice> {
+        &self.inner
+    }
+
+    fn completed_request(
+        result: CanFail<IOError>,
+        command: AtaCommand,
+        data: Option<Vec<u8>>,
+    ) -> AtaIoRequest {
+        let io_req = AtaIoRequest::new(AtomicBool::new(true));
+
+        let result = match result {
+            Ok(()) => AtaResult::Success,
+            Err(_) => AtaResult::Error(AtaError {
+                code: AtaErrorCode::CommandAbort,
+                lba: 0,
+            }),
+        };
+
+        *io_req.inner.result.lock() = Some(AtaIoResult {
+            result,
+            command,
+            data,
+        });
+
+        io_req
+    }
+}
+
+impl DiskDevice for ByteBlockDevice {
+    fn read(&self, start_lba: u64, sectors_count: u16) -> AtaIoRequest {
+        let mut data =
+            alloc::vec![0; usize::from(sectors_count) * BYTE_DEVICE_SECTOR_SIZE as usize];
+        let result = self.read_into(start_lba, sectors_count, &mut data);
+
+        Self::completed_request(result, AtaCommand::AtaReadSectors, Some(data))
+    }
+
+    fn read_into(&self, start_lba: u64, sectors_count: u16, buffer: &mut [u8]) -> CanFail<IOError> {
+        let size = usize::from(sectors_count) * BYTE_DEVICE_SECTOR_SIZE as usize;
+        let buffer = buffer.get_mut(..size).ok_or(IOError::InvalidCommand)?;
+
+        self.inner
+            .read_at(start_lba * BYTE_DEVICE_SECTOR_SIZE, buffer)
+    }
+
+    fn write(&self, start_lba: u64, sectors_count: u16, data: Vec<u8>) -> AtaIoRequest {
+        let size = usize::from(sectors_count) * BYTE_DEVICE_SECTOR_SIZE as usize;
+        let result = match data.get(..size) {
+            Some(data) => self.write_from(start_lba, data),
+            None => Err(IOError::InvalidCommand),
+        };
+
+        Self::completed_request(result, AtaCommand::AtaWriteSectors, None)
+    }
+
+    fn write_from(&self, start_lba: u64, data: &[u8]) -> CanFail<IOError> {
+        if data.len() as u64 % BYTE_DEVICE_SECTOR_SIZE != 0 {
+            return Err(IOError::InvalidCommand);
+        }
+
+        self.inner
+            .write_at(start_lba * BYTE_DEVICE_SECTOR_SIZE, data)
+    }
+
+    fn flush(&self) -> CanFail<IOError> {
+        self.inner.flush()
+    }
+
+    fn partitions(&self) -> Vec<Arc<Partition>> {
+        self.partitions.clone()
+    }
+
+    fn rescan_partitions(&self) {}
+
+    fn identifier(&self) -> AtaDeviceIdentifier {
+        self.id
+    }
+
+    fn max_sector(&self) -> usize {
+        usize::try_from(self.inner.size() / BYTE_DEVICE_SECTOR_SIZE).unwrap_or(usize::MAX)
+    }
+
+    fn logical_sector_size(&self) -> u64 {
+        BYTE_DEVICE_SECTOR_SIZE
+    }
+}
+
+/// Writes back the cache lines covering `len` bytes at `addr` to memory, without waiting for completion.
+fn write_back_cache_range(addr: *const u8, len: usize) {
+    let start = addr as usize & !(CACHE_LINE_SIZE - 1);
+
+    for line in (start..addr as usize + len).step_by(CACHE_LINE_SIZE) {
+        unsafe {
+            asm!("clflush [{}]", in(reg) line, options(nostack, preserves_flags));
+        }
+    }
+}
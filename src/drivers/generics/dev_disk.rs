@@ -5,11 +5,19 @@
 //!
 //! The `DiskDevice` trait specifies standard methods to interact with disk devices, however the actual
 //! implementation of those method may depend on the physical controller to which the disk is linked.
+//!
+//! Requests issued through a [`SataDevice`] are accounted in the I/O statistics of the device (see [`diskstats`]).
+//!
+//! [`diskstats`]: crate::drivers::generics::diskstats
 
 use crate::drivers::ahci::ahci_devices;
-use crate::drivers::ide::ata_pio::{ata_devices, AtaDevice, AtaIoRequest};
+use crate::drivers::generics::dev_byte::byte_devices;
+use crate::drivers::generics::diskstats::{disk_stats, DiskStats, IoOp};
+use crate::drivers::ide::ata_pio::{ata_devices, AtaDevice, AtaIoRequest, AtaResult};
 use crate::drivers::ide::AtaDeviceIdentifier;
+use crate::errors::{CanFail, IOError};
 use crate::fs::partitions::Partition;
+use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
@@ -23,6 +31,7 @@ use alloc::vec::Vec;
 pub struct SataDevice {
     identifier: AtaDeviceIdentifier,
     inner: Arc<dyn DiskDevice>,
+    stats: &'static DiskStats,
 }
 
 /// Available physical devices types.
@@ -32,21 +41,27 @@ pub struct SataDevice {
 pub enum SataDeviceType {
     IDE,
     AHCI,
+
+    /// Byte-addressable device, exposed to the block layer (see [`ByteBlockDevice`]).
+    ///
+    /// [`ByteBlockDevice`]: crate::drivers::generics::dev_byte::ByteBlockDevice
+    Memory,
 }
 
 /// Returns a [`SataDevice`] structure encapsulating a physical disk device,
 /// from its unique identifier ([`AtaDeviceIdentifier`]).
 pub fn get_sata_drive(id: AtaDeviceIdentifier) -> Option<SataDevice> {
-    match id.disk_type {
-        SataDeviceType::IDE => Some(SataDevice {
-            identifier: id.clone(),
-            inner: ata_devices().read().get(&id)?.clone(),
-        }),
-        SataDeviceType::AHCI => Some(SataDevice {
-            identifier: id.clone(),
-            inner: ahci_devices().read().get(&id)?.clone(),
-        }),
-    }
+    let inner: Arc<dyn DiskDevice> = match id.disk_type {
+        SataDeviceType::IDE => ata_devices().read().get(&id)?.clone(),
+        SataDeviceType::AHCI => ahci_devices().read().get(&id)?.clone(),
+        SataDeviceType::Memory => byte_devices().read().get(&id)?.clone(),
+    };
+
+    Some(SataDevice {
+        identifier: id,
+        inner,
+        stats: disk_stats(id),
+    })
 }
 
 /// Returns an iterator over all availables [`SataDevice`] on the computer.
@@ -68,6 +83,7 @@ impl SataDeviceIterator {
             ahci_devices().read().keys().cloned().collect();
 
         ata_devices_identifiers.append(&mut ahci_device_identifers);
+        ata_devices_identifiers.extend(byte_devices().read().keys().cloned());
 
         Self {
             identifiers: ata_devices_identifiers.into_iter(),
@@ -83,19 +99,89 @@ impl Iterator for SataDeviceIterator {
     }
 }
 
+impl SataDevice {
+    /// Returns the I/O statistics of the device.
+    pub fn stats(&self) -> &'static DiskStats {
+        self.stats
+    }
+
+    /// Size of `sectors_count` sectors, in bytes.
+    fn sectors_size(&self, sectors_count: u16) -> usize {
+        usize::from(sectors_count)
+            * usize::try_from(self.inner.logical_sector_size()).expect("invalid sector size")
+    }
+
+    /// Runs a synchronous request, accounted in the statistics of the device.
+    fn accounted<E>(
+        &self,
+        op: IoOp,
+        bytes: usize,
+        request: impl FnOnce() -> CanFail<E>,
+    ) -> CanFail<E> {
+        let mut accounting = self.stats.start(op, bytes);
+
+        let result = request();
+        accounting.set_failed(result.is_err());
+
+        result
+    }
+}
+
 impl DiskDevice for SataDevice {
     fn read(&self, start_lba: u64, sectors_count: u16) -> AtaIoRequest {
-        self.inner.read(start_lba, sectors_count)
+        let accounting = self
+            .stats
+            .start(IoOp::Read, self.sectors_size(sectors_count));
+
+        let mut request = self.inner.read(start_lba, sectors_count);
+        request.accounting = Some(accounting);
+
+        request
+    }
+
+    fn read_into(&self, start_lba: u64, sectors_count: u16, buffer: &mut [u8]) -> CanFail<IOError> {
+        self.accounted(IoOp::Read, self.sectors_size(sectors_count), || {
+            self.inner.read_into(start_lba, sectors_count, buffer)
+        })
+    }
+
+    fn read_vectored(&self, start_lba: u64, buffers: &mut [&mut [u8]]) -> CanFail<IOError> {
+        let bytes = buffers.iter().map(|buffer| buffer.len()).sum();
+
+        self.accounted(IoOp::Read, bytes, || {
+            self.inner.read_vectored(start_lba, buffers)
+        })
     }
 
     fn write(&self, start_lba: u64, sectors_count: u16, data: Vec<u8>) -> AtaIoRequest {
-        self.inner.write(start_lba, sectors_count, data)
+        let accounting = self
+            .stats
+            .start(IoOp::Write, self.sectors_size(sectors_count));
+
+        let mut request = self.inner.write(start_lba, sectors_count, data);
+        request.accounting = Some(accounting);
+
+        request
+    }
+
+    fn write_from(&self, start_lba: u64, data: &[u8]) -> CanFail<IOError> {
+        self.accounted(IoOp::Write, data.len(), || {
+            self.inner.write_from(start_lba, data)
+        })
+    }
+
+    fn flush(&self) -> CanFail<IOError> {
+        self.accounted(IoOp::Flush, 0, || self.inner.flush())
     }
 
-    fn partitions(&self) -> &Vec<Partition> {
+    fn partitions(&self) -> Vec<Arc<Partition>> {
         self.inner.partitions()
     }
 
+    fn rescan_partitions(&self) {
+        self.inner.rescan_partitions();
+    }
+
     fn identifier(&self) -> AtaDeviceIdentifier {
         self.identifier
     }
@@ -128,6 +214,49 @@ pub trait DiskDevice {
     /// ```
     fn read(&self, start_lba: u64, sectors_count: u16) -> AtaIoRequest;
 
+    /// Reads `sectors_count` sectors from this drive, starting at `start_lba`, directly into `buffer`.
+    ///
+    /// Contrary to [`DiskDevice::read`], this does not allocate an intermediate buffer: devices that support DMA
+    /// transfer the data straight into the caller's buffer. The default implementation falls back to
+    /// [`DiskDevice::read`], and copies the data.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::InvalidCommand`] if `buffer` is smaller than `sectors_count * sector_size`. May return any
+    /// other variant of [`IOError`] in case of a device failure.
+    fn read_into(&self, start_lba: u64, sectors_count: u16, buffer: &mut [u8]) -> CanFail<IOError> {
+        read_into_with_copy(self, start_lba, sectors_count, buffer)
+    }
+
+    /// Reads consecutive sectors from this drive, starting at `start_lba`, into a list of buffers (scatter-gather
+    /// read). Each buffer is filled in turn.
+    ///
+    /// The length of every buffer must be a multiple of the sector size. Devices that support DMA issue a single
+    /// request for the whole list. The default implementation issues a [`DiskDevice::read_into`] request per buffer.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::InvalidCommand`] if the length of a buffer is not a multiple of the sector size. May return
+    /// any other variant of [`IOError`] in case of a device failure.
+    fn read_vectored(&self, start_lba: u64, buffers: &mut [&mut [u8]]) -> CanFail<IOError> {
+        let sector_size = usize::try_from(self.logical_sector_size()).expect("invalid sector size");
+        let mut lba = start_lba;
+
+        for buffer in buffers.iter_mut() {
+            if buffer.len() % sector_size != 0 {
+                return Err(IOError::InvalidCommand);
+            }
+
+            let sectors_count =
+                u16::try_from(buffer.len() / sector_size).map_err(|_| IOError::InvalidCommand)?;
+
+            self.read_into(lba, sectors_count, buffer)?;
+            lba += u64::from(sectors_count);
+        }
+
+        Ok(())
+    }
+
     /// Writes `sectors_count` sectors from the buffer to the drive, starting at `start_lba`.
     ///
     /// - Length of `buffer` must be larger than `sectors_count * sector_size`.
@@ -144,8 +273,51 @@ pub trait DiskDevice {
     /// ```
     fn write(&self, start_lba: u64, sectors_count: u16, data: Vec<u8>) -> AtaIoRequest;
 
+    /// Writes `data` to this drive, starting at `start_lba`, and waits until the operation completes.
+    ///
+    /// The length of `data` must be a multiple of the sector size. Large writes are split into several requests if
+    /// required. Devices that support DMA transfer the data straight from the caller's buffer. The default
+    /// implementation falls back to [`DiskDevice::write`], and copies the data.
+    ///
+    /// Data may still be held in the volatile write cache of the drive when this returns, use [`DiskDevice::flush`]
+    /// to make sure it reached the media.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::InvalidCommand`] if the length of `data` is not a multiple of the sector size, or if the
+    /// write goes past the last sector of the drive. May return any other variant of [`IOError`] in case of a device
+    /// failure.
+    fn write_from(&self, start_lba: u64, data: &[u8]) -> CanFail<IOError> {
+        write_from_with_copy(self, start_lba, data)
+    }
+
+    /// Flushes the volatile write cache of the drive, and waits until all the data previously written reached the
+    /// media.
+    ///
+    /// The default implementation does nothing, for devices without a write cache.
+    ///
+    /// # Errors
+    ///
+    /// May return any variant of [`IOError`] in case of a device failure.
+    fn flush(&self) -> CanFail<IOError> {
+        Ok(())
+    }
+
     /// Returns a list of all partitions defined on the device.
-    fn partitions(&self) -> &Vec<Partition>;
+    ///
+    /// The list is a snapshot: partitions may be added or removed by [`DiskDevice::rescan_partitions`] afterwards.
+    fn partitions(&self) -> Vec<Arc<Partition>>;
+
+    /// Returns the partition at index `partition_id` in the list of partitions of the device.
+    fn partition(&self, partition_id: usize) -> Option<Arc<Partition>> {
+        self.partitions().get(partition_id).cloned()
+    }
+
+    /// Reloads the partition table of the device, and records the partitions found in the partition registry.
+    ///
+    /// Partitions that did not change keep their mounted filesystem. Partitions obtained before the table was
+    /// reloaded remain valid, but may no longer be part of the table.
+    fn rescan_partitions(&self);
 
     /// Returns this device's unique identifier.
     fn identifier(&self) -> AtaDeviceIdentifier;
@@ -156,3 +328,71 @@ pub trait DiskDevice {
     /// Returns the number of bytes per logical sector.
     fn logical_sector_size(&self) -> u64;
 }
+
+/// Default implementation of [`DiskDevice::read_into`], which reads the sectors using [`DiskDevice::read`] and copies
+/// them into `buffer`.
+///
+/// Devices that override [`DiskDevice::read_into`] can use it as a fallback.
+pub(crate) fn read_into_with_copy<D: DiskDevice + ?Sized>(
+    device: &D,
+    start_lba: u64,
+    sectors_count: u16,
+    buffer: &mut [u8],
+) -> CanFail<IOError> {
+    let size = usize::from(sectors_count)
+        * usize::try_from(device.logical_sector_size()).expect("invalid sector size");
+    let buffer = buffer.get_mut(..size).ok_or(IOError::InvalidCommand)?;
+
+    let result = device.read(start_lba, sectors_count).complete();
+
+    if let AtaResult::Error(_) = result.result {
+        return Err(IOError::Unknown);
+    }
+
+    let data = result.data.ok_or(IOError::Unknown)?;
+    buffer.copy_from_slice(data.get(..size).ok_or(IOError::Unknown)?);
+
+    Ok(())
+}
+
+/// Default implementation of [`DiskDevice::write_from`], which copies `data` into intermediate buffers that are
+/// written using [`DiskDevice::write`].
+///
+/// Devices that override [`DiskDevice::write_from`] can use it as a fallback.
+pub(crate) fn write_from_with_copy<D: DiskDevice + ?Sized>(
+    device: &D,
+    start_lba: u64,
+    data: &[u8],
+) -> CanFail<IOError> {
+    let sector_size = usize::try_from(device.logical_sector_size()).expect("invalid sector size");
+
+    if data.len() % sector_size != 0 {
+        return Err(IOError::InvalidCommand);
+    }
+
+    let end_lba = start_lba + u64::try_from(data.len() / sector_size).expect("invalid data size");
+    if end_lba > u64::try_from(device.max_sector()).expect("invalid sector count") {
+        return Err(IOError::InvalidCommand);
+    }
+
+    let mut lba = start_lba;
+
+    for chunk in data.chunks(usize::from(u16::MAX) * sector_size) {
+        let sectors_count =
+            u16::try_from(chunk.len() / sector_size).expect("invalid sectors count");
+        let mut buffer = Vec::new();
+
+        buffer
+            .try_reserve_exact(chunk.len())
+            .map_err(|e| IOError::Exception(Box::new(e)))?;
+        buffer.extend_from_slice(chunk);
+
+        if let AtaResult::Error(_) = device.write(lba, sectors_count, buffer).complete().result {
+            return Err(IOError::Unknown);
+        }
+
+        lba += u64::from(sectors_count);
+    }
+
+    Ok(())
+}
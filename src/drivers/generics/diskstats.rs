@@ -0,0 +1,295 @@
+//! Block I/O statistics.
+//!
+//! Every request going through a [`SataDevice`] is accounted in the [`DiskStats`] of its device: number of requests,
+//! bytes transferred, errors, number of requests in flight, and a latency histogram measured with the `TSC`. Latencies
+//! are only measured once the `TSC` clock is calibrated.
+//!
+//! The histograms use power-of-two buckets: bucket `0` counts requests completed in less than 1 µs, and bucket `i`
+//! requests completed in `[2^(i-1), 2^i)` µs. The last bucket also counts every slower request.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::drivers::generics::diskstats::diskstats_report;
+//!
+//! let mut report = String::new();
+//! diskstats_report(&mut report)?;
+//! ```
+//!
+//! [`SataDevice`]: crate::drivers::generics::dev_disk::SataDevice
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::mem::discriminant;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+
+use crate::drivers::devtree::disk_name;
+use crate::drivers::ide::AtaDeviceIdentifier;
+use crate::x86::tsc::TSC_CLK;
+
+/// Number of buckets of the latency histograms.
+pub const DISKSTATS_LATENCY_BUCKETS: usize = 24;
+
+/// Statistics of every device accessed through the block layer, in the order devices were first accessed.
+static DISK_STATS: Mutex<Vec<(AtaDeviceIdentifier, &'static DiskStats)>> = Mutex::new(Vec::new());
+
+/// Kind of block I/O request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoOp {
+    Read,
+    Write,
+    Flush,
+}
+
+impl IoOp {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Flush => "flush",
+        }
+    }
+}
+
+/// Counters of a kind of request.
+#[derive(Default)]
+struct OpCounters {
+    requests: AtomicU64,
+    bytes: AtomicU64,
+    errors: AtomicU64,
+
+    /// Sum of the measured latencies, in µs.
+    total_latency_us: AtomicU64,
+
+    histogram: [AtomicU64; DISKSTATS_LATENCY_BUCKETS],
+}
+
+impl OpCounters {
+    fn snapshot(&self) -> IoStats {
+        IoStats {
+            requests: self.requests.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            total_latency_us: self.total_latency_us.load(Ordering::Relaxed),
+            histogram: core::array::from_fn(|bucket| {
+                self.histogram[bucket].load(Ordering::Relaxed)
+            }),
+        }
+    }
+}
+
+/// Statistics of a kind of request on a device.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IoStats {
+    /// Number of requests completed.
+    pub requests: u64,
+
+    /// Number of bytes transferred.
+    pub bytes: u64,
+
+    /// Number of requests that failed.
+    pub errors: u64,
+
+    /// Sum of the measured latencies, in µs.
+    pub total_latency_us: u64,
+
+    /// Latency histogram (see the module documentation for the buckets).
+    pub histogram: [u64; DISKSTATS_LATENCY_BUCKETS],
+}
+
+impl IoStats {
+    /// Average latency of the requests, in µs.
+    pub fn mean_latency_us(&self) -> Option<u64> {
+        let measured: u64 = self.histogram.iter().sum();
+
+        self.total_latency_us.checked_div(measured)
+    }
+
+    /// Upper bound of the latency of `percent`% of the requests, in µs (rounded up to the bucket boundary).
+    pub fn percentile_latency_us(&self, percent: u64) -> Option<u64> {
+        let measured: u64 = self.histogram.iter().sum();
+        let target = (measured * percent.min(100)).div_ceil(100).max(1);
+        let mut count = 0;
+
+        for (bucket, requests) in self.histogram.iter().enumerate() {
+            count += requests;
+
+            if count >= target {
+                return Some(1 << bucket);
+            }
+        }
+
+        None
+    }
+}
+
+/// I/O statistics of a disk device.
+#[derive(Default)]
+pub struct DiskStats {
+    read: OpCounters,
+    write: OpCounters,
+    flush: OpCounters,
+
+    in_flight: AtomicU64,
+    max_in_flight: AtomicU64,
+}
+
+impl DiskStats {
+    /// Returns the statistics of the `op` requests.
+    pub fn stats(&self, op: IoOp) -> IoStats {
+        self.counters(op).snapshot()
+    }
+
+    /// Number of requests submitted and not completed yet.
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Maximum number of requests that were in flight at the same time.
+    pub fn max_in_flight(&self) -> u64 {
+        self.max_in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Starts accounting a request transferring `bytes` bytes. The request is completed when the returned
+    /// [`IoAccounting`] is dropped.
+    pub(crate) fn start(&'static self, op: IoOp, bytes: usize) -> IoAccounting {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::Relaxed);
+
+        IoAccounting {
+            stats: self,
+            op,
+            bytes: bytes as u64,
+            start: TSC_CLK.get().map(|tsc| tsc.tsc_read()),
+            failed: false,
+        }
+    }
+
+    fn counters(&self, op: IoOp) -> &OpCounters {
+        match op {
+            IoOp::Read => &self.read,
+            IoOp::Write => &self.write,
+            IoOp::Flush => &self.flush,
+        }
+    }
+}
+
+/// Request being accounted, completed when dropped.
+pub(crate) struct IoAccounting {
+    stats: &'static DiskStats,
+    op: IoOp,
+    bytes: u64,
+
+    /// `TSC` value when the request was submitted.
+    start: Option<u64>,
+
+    failed: bool,
+}
+
+impl IoAccounting {
+    /// Records the outcome of the request.
+    pub(crate) fn set_failed(&mut self, failed: bool) {
+        self.failed = failed;
+    }
+}
+
+impl Drop for IoAccounting {
+    fn drop(&mut self) {
+        let counters = self.stats.counters(self.op);
+
+        counters.requests.fetch_add(1, Ordering::Relaxed);
+        match self.failed {
+            true => counters.errors.fetch_add(1, Ordering::Relaxed),
+            false => counters.bytes.fetch_add(self.bytes, Ordering::Relaxed),
+        };
+
+        if let (Some(start), Some(tsc)) = (self.start, TSC_CLK.get()) {
+            let ticks = tsc.tsc_read().saturating_sub(start);
+            let latency_us = tsc.tsc_ticks_to_micro(ticks as f64) as u64;
+            let bucket = (u64::BITS - latency_us.leading_zeros()) as usize;
+
+            counters
+                .total_latency_us
+                .fetch_add(latency_us, Ordering::Relaxed);
+            counters.histogram[bucket.min(DISKSTATS_LATENCY_BUCKETS - 1)]
+                .fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.stats.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the statistics of a device, registering it on first use.
+pub(crate) fn disk_stats(id: AtaDeviceIdentifier) -> &'static DiskStats {
+    let mut registry = DISK_STATS.lock();
+
+    // Identifiers of devices of different types may compare equal.
+    let existing = registry.iter().find(|(entry, _)| {
+        *entry == id && discriminant(&entry.disk_type) == discriminant(&id.disk_type)
+    });
+
+    match existing {
+        Some((_, stats)) => stats,
+        None => {
+            let stats = Box::leak(Box::<DiskStats>::default());
+            registry.push((id, stats));
+            stats
+        }
+    }
+}
+
+/// Returns the statistics of every device accessed through the block layer.
+pub fn diskstats() -> Vec<(AtaDeviceIdentifier, &'static DiskStats)> {
+    DISK_STATS.lock().clone()
+}
+
+/// Writes a summary of the statistics of every device: one line per device and kind of request.
+pub fn diskstats_report(out: &mut impl Write) -> fmt::Result {
+    let devices = diskstats();
+
+    if devices.is_empty() {
+        return writeln!(out, "no I/O recorded");
+    }
+
+    for (id, stats) in devices {
+        writeln!(
+            out,
+            "{}    in flight = {}    max in flight = {}",
+            disk_name(id),
+            stats.in_flight(),
+            stats.max_in_flight()
+        )?;
+
+        for op in [IoOp::Read, IoOp::Write, IoOp::Flush] {
+            let io = stats.stats(op);
+            if io.requests == 0 {
+                continue;
+            }
+
+            write!(
+                out,
+                "  {:<5} {:>8} reqs {:>12} bytes {:>5} errors",
+                op.name(),
+                io.requests,
+                io.bytes,
+                io.errors
+            )?;
+
+            if let Some(mean) = io.mean_latency_us() {
+                write!(out, "    avg = {mean} µs")?;
+            }
+            if let (Some(p50), Some(p99)) =
+                (io.percentile_latency_us(50), io.percentile_latency_us(99))
+            {
+                write!(out, "    p50 < {p50} µs    p99 < {p99} µs")?;
+            }
+
+            writeln!(out)?;
+        }
+    }
+
+    Ok(())
+}
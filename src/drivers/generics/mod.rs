@@ -1 +1,3 @@
+pub mod dev_byte;
 pub mod dev_disk;
+pub mod diskstats;
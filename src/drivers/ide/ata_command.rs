@@ -39,7 +39,10 @@ pub(crate) enum AtaCommand {
     AtaWriteSectors = 0x30,
     AtaWriteSectorsExt = 0x34,
     AtaWriteMultipleExt = 0x39,
+    AtaWriteDma = 0xCA,
+    AtaWriteDmaExt = 0x35,
     AtaSetMultipleMode = 0xC6,
+    AtaSetFeatures = 0xEF,
 }
 
 impl AtaCommand {
@@ -1,29 +1,48 @@
 use crate::drivers::ahci::device::{ATAMediaRotationRate, SizeFormat};
-use crate::drivers::generics::dev_disk::{DiskDevice, SataDeviceType};
+use crate::drivers::devtree::{devtree_add_disk, DevicePath};
+use crate::drivers::generics::dev_disk::{
+    read_into_with_copy, write_from_with_copy, DiskDevice, SataDeviceType,
+};
+use crate::drivers::generics::diskstats::IoAccounting;
 use crate::drivers::ide::ata_command::AtaCommand;
+use crate::drivers::ide::bus_master::{BusMasterChannel, MAX_TRANSFER_SIZE};
 use crate::drivers::ide::AtaDeviceIdentifier;
-use crate::errors::CanFail;
+use crate::errors::{CanFail, IOError};
 use crate::fs::partitions::gpt::load_drive_gpt;
 use crate::fs::partitions::mbr::{load_drive_mbr, PartitionType};
+use crate::fs::partitions::registry::{reconcile_partitions, register_drive_partitions};
 use crate::fs::partitions::{Partition, PartitionMetadata, PartitionTable};
 use crate::io::{inb, inw, outb, outw, IOPort};
 use crate::mem::utils::Convertible;
-use crate::wait;
+use crate::sync::waitqueue::{poll_until_timeout, WaitQueue};
+use crate::{info, wait};
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
+use alloc::format;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use conquer_once::spin::OnceCell;
 use core::cell::{RefCell, UnsafeCell};
 use core::hint;
+use core::ptr;
 use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use core::time::Duration;
 use modular_bitfield::bitfield;
 use modular_bitfield::specifiers::B4;
 use spin::{Mutex, RwLock};
 
 static LAST_ATA_DEVICE: AtomicU8 = AtomicU8::new(0);
 
+/// `SET FEATURES` subcommand selecting the transfer mode (given in the sector count register).
+const SET_FEATURES_TRANSFER_MODE: u8 = 0x03;
+
+/// Transfer mode value selecting an Ultra DMA mode (the mode number is given in the lower bits).
+const TRANSFER_MODE_UDMA: u8 = 0x40;
+
+/// Maximum time taken by the device to request the first data block of a write command.
+const ATA_DRQ_TIMEOUT: Duration = Duration::from_millis(50);
+
 pub fn ata_devices() -> &'static RwLock<BTreeMap<AtaDeviceIdentifier, Arc<AtaDevice>>> {
     static ATA_DEVICES: OnceCell<RwLock<BTreeMap<AtaDeviceIdentifier, Arc<AtaDevice>>>> =
         OnceCell::uninit();
@@ -43,8 +62,10 @@ pub(crate) struct AtaDevice {
     command_queue: RefCell<Option<AtaCommandRequest>>,
     identify_data: UnsafeCell<AtaIdentify>,
     sectors_per_drq: UnsafeCell<u16>,
+    bus_master: Option<Arc<BusMasterChannel>>,
+    udma_mode: UnsafeCell<Option<u8>>,
     partition_table: UnsafeCell<PartitionTable>,
-    partitions: UnsafeCell<Vec<Partition>>,
+    partitions: RwLock<Vec<Arc<Partition>>>,
 }
 
 #[derive(Debug)]
@@ -56,11 +77,17 @@ pub struct AtaIoResult {
 
 pub struct AtaIoRequest {
     pub(in crate::drivers) inner: Arc<AtaIoRequestInner>,
+
+    /// Statistics updated when the request completes (see [`diskstats`]).
+    ///
+    /// [`diskstats`]: crate::drivers::generics::diskstats
+    pub(in crate::drivers) accounting: Option<IoAccounting>,
 }
 
 pub(in crate::drivers) struct AtaIoRequestInner {
     pub(in crate::drivers) has_completed: AtomicBool,
     pub(in crate::drivers) result: Mutex<Option<AtaIoResult>>,
+    pub(in crate::drivers) completion: WaitQueue,
 }
 
 impl AtaIoRequest {
@@ -73,7 +100,9 @@ impl AtaIoRequest {
             inner: Arc::new(AtaIoRequestInner {
                 has_completed,
                 result: Mutex::new(None),
+                completion: WaitQueue::new(),
             }),
+            accounting: None,
         }
     }
 
@@ -83,14 +112,18 @@ impl AtaIoRequest {
     /// device. That process is asynchronous, and therefore to get the result of the operation you
     /// must make sure it has been fully processed by the device.
     pub fn complete(self) -> AtaIoResult {
-        while !self.inner.has_completed.load(Ordering::Relaxed) {
-            hint::spin_loop();
-        }
+        let Self { inner, accounting } = self;
+
+        inner
+            .completion
+            .wait_until(|| inner.has_completed.load(Ordering::Acquire));
 
-        let request_inner =
-            Arc::into_inner(self.inner).expect("too many references to I/O request");
+        let request_inner = Arc::into_inner(inner).expect("too many references to I/O request");
         let may_result = request_inner.result.into_inner();
         if let Some(result) = may_result {
+            if let Some(mut accounting) = accounting {
+                accounting.set_failed(matches!(result.result, AtaResult::Error(_)));
+            }
             return result;
         }
         core::unreachable!();
@@ -187,55 +220,44 @@ impl DiskDevice for AtaDevice {
     }
 
     fn write(&self, start_lba: u64, sectors_count: u16, mut data: Vec<u8>) -> AtaIoRequest {
-        self.set_lba(start_lba);
-        self.set_sectors_count(sectors_count);
-
-        let initial_write_sz = data.len().min(self.sector_size());
-        let mut initial_sector: Vec<u8> = data.drain(..initial_write_sz).collect();
-        initial_sector.resize(self.sector_size(), 0);
-        let mut initial_sector_iter = initial_sector.iter();
+        let transfer_blk_size = u16::max(
+            0x200,
+            self.sectors_per_drq()
+                * u16::try_from(self.sector_size()).expect("invalid sector size"),
+        );
 
-        let request = match self.identify_data().addressing_mode() {
+        match self.identify_data().addressing_mode() {
             AtaAddressingMode::Lba24 => {
                 let mut remaining_sectors = sectors_count;
-                let buffer = alloc::vec![];
-                let mut read_err = None;
+                let mut write_err = None;
 
                 while remaining_sectors != 0 {
-                    let sectors_to_read = u16::min(0xff, remaining_sectors);
-                    self.set_lba(start_lba + u64::from(sectors_count - remaining_sectors));
-                    self.set_sectors_count(sectors_to_read);
-                    let data_to_write: Vec<u8> = data
-                        .drain(
-                            (usize::from(sectors_count - remaining_sectors) * self.sector_size())
-                                ..(usize::from(sectors_count + 1 - remaining_sectors)
-                                    * self.sector_size()),
-                        )
-                        .collect();
+                    let sectors_to_write = u16::min(0xff, remaining_sectors);
+                    let chunk_size = usize::from(sectors_to_write) * self.sector_size();
+                    let data_to_write: Vec<u8> =
+                        data.drain(..usize::min(chunk_size, data.len())).collect();
+
                     let cmd_result = self
-                        .send_ata_command(
-                            AtaCommandRequest::new(
-                                AtaCommand::AtaWriteSectors,
-                                u64::from(sectors_to_read)
-                                    * u64::try_from(self.sector_size())
-                                        .expect("invalid sector size"),
-                            )
-                            .with_data_buffer(data_to_write)
-                            .with_direction(AtaTransferDirection::Write),
+                        .send_write_command(
+                            AtaCommand::AtaWriteSectors,
+                            start_lba + u64::from(sectors_count - remaining_sectors),
+                            sectors_to_write,
+                            0x200,
+                            data_to_write,
                         )
                         .complete();
 
                     if let AtaResult::Error(err) = cmd_result.result {
-                        read_err = Some(err);
+                        write_err = Some(err);
                         break;
                     }
 
-                    remaining_sectors -= sectors_to_read;
+                    remaining_sectors -= sectors_to_write;
                 }
 
                 let io_req = AtaIoRequest::new(AtomicBool::new(true));
                 let mut io_res = io_req.inner.result.lock();
-                let io_res_code = match read_err {
+                let io_res_code = match write_err {
                     Some(err) => AtaResult::Error(err),
                     None => AtaResult::Success,
                 };
@@ -243,7 +265,7 @@ impl DiskDevice for AtaDevice {
                 *io_res = Some(AtaIoResult {
                     result: io_res_code,
                     command: AtaCommand::AtaWriteSectors,
-                    data: Some(buffer),
+                    data: None,
                 });
                 drop(io_res);
 
@@ -256,37 +278,66 @@ impl DiskDevice for AtaDevice {
                     AtaCommand::AtaWriteMultipleExt
                 };
 
-                let transfer_blk_size = u16::max(
-                    0x200,
-                    self.sectors_per_drq()
-                        * u16::try_from(self.sector_size()).expect("invalid sector size"),
-                );
-                self.send_ata_command(
-                    AtaCommandRequest::new(
-                        ata_cmd,
-                        u64::from(sectors_count)
-                            * u64::try_from(self.sector_size()).expect("invalid sector size"),
-                    )
-                    .with_transfer_blk_size(transfer_blk_size)
-                    .with_data_buffer(data)
-                    .with_direction(AtaTransferDirection::Write),
-                )
+                self.send_write_command(ata_cmd, start_lba, sectors_count, transfer_blk_size, data)
             }
+        }
+    }
+
+    fn read_into(&self, start_lba: u64, sectors_count: u16, buffer: &mut [u8]) -> CanFail<IOError> {
+        if !self.dma_enabled() {
+            return read_into_with_copy(self, start_lba, sectors_count, buffer);
+        }
+
+        let size = usize::from(sectors_count) * self.sector_size();
+        let buffer = buffer.get_mut(..size).ok_or(IOError::InvalidCommand)?;
+
+        self.dma_transfer_chunks(
+            start_lba,
+            buffer.as_mut_ptr(),
+            size,
+            AtaTransferDirection::Read,
+        )
+    }
+
+    fn write_from(&self, start_lba: u64, data: &[u8]) -> CanFail<IOError> {
+        if !self.dma_enabled() {
+            return write_from_with_copy(self, start_lba, data);
+        }
+
+        if data.len() % self.sector_size() != 0 {
+            return Err(IOError::InvalidCommand);
+        }
+
+        self.dma_transfer_chunks(
+            start_lba,
+            data.as_ptr().cast_mut(),
+            data.len(),
+            AtaTransferDirection::Write,
+        )
+    }
+
+    fn flush(&self) -> CanFail<IOError> {
+        let ata_cmd = match self.identify_data().addressing_mode() {
+            AtaAddressingMode::Lba24 => AtaCommand::AtaFlushCache,
+            AtaAddressingMode::Lba48 => AtaCommand::AtaFlushCacheExt,
         };
 
-        for _ in 0..(self.sector_size() >> 1) {
-            let wd_to_wr = u16::from_le_bytes([
-                *initial_sector_iter.next().expect("invalid buffer size"),
-                *initial_sector_iter.next().expect("invalid buffer size"),
-            ]);
-            self.write_data_port(wd_to_wr);
+        match self
+            .send_ata_command(AtaCommandRequest::new(ata_cmd, 0))
+            .complete()
+            .result
+        {
+            AtaResult::Success => Ok(()),
+            AtaResult::Error(_) => Err(IOError::Unknown),
         }
+    }
 
-        request
+    fn partitions(&self) -> Vec<Arc<Partition>> {
+        self.partitions.read().clone()
     }
 
-    fn partitions(&self) -> &Vec<Partition> {
-        unsafe { &(*self.partitions.get()) }
+    fn rescan_partitions(&self) {
+        self.load_partition_table();
     }
 
     fn identifier(&self) -> AtaDeviceIdentifier {
@@ -308,8 +359,9 @@ impl AtaDevice {
         io_base: IOPort,
         ctrl_base: IOPort,
         is_slave: bool,
-        ctrl_id: usize,
         is_prim: bool,
+        controller_path: &DevicePath,
+        bus_master: Option<Arc<BusMasterChannel>>,
     ) -> Result<AtaDeviceIdentifier, AtaErrorCode> {
         if is_slave {
             outb(io_base + 0x6, 1 << 4);
@@ -329,8 +381,10 @@ impl AtaDevice {
             identify_data: UnsafeCell::new(AtaIdentify([0u16; 256])),
             sector_sz: UnsafeCell::new(0),
             sectors_per_drq: UnsafeCell::new(0),
+            bus_master,
+            udma_mode: UnsafeCell::new(None),
             partition_table: UnsafeCell::new(PartitionTable::Unknown),
-            partitions: UnsafeCell::new(alloc::vec![]),
+            partitions: RwLock::new(alloc::vec![]),
         };
         let ctlr_dev_id = match (is_slave, is_prim) {
             (false, true) => 0,
@@ -338,8 +392,10 @@ impl AtaDevice {
             (true, true) => 2,
             (true, false) => 3,
         };
-        let device_id = AtaDeviceIdentifier::new(SataDeviceType::IDE, ctrl_id, ctlr_dev_id);
+        let device_id =
+            AtaDeviceIdentifier::new(SataDeviceType::IDE, id.ide_controller, ctlr_dev_id);
         ata_devices().write().insert(device_id, Arc::new(device));
+        devtree_add_disk(controller_path, &format!("ata{}", id.device_id), device_id);
         let dev_list = ata_devices().read();
 
         let dev = dev_list
@@ -347,12 +403,168 @@ impl AtaDevice {
             .ok_or(AtaErrorCode::DriveNotPresent)?;
         dev.enable_irq();
         dev.identify();
+        dev.negotiate_udma();
 
         dev.load_partition_table();
 
         Ok(device_id)
     }
 
+    /// Selects the fastest Ultra DMA mode supported by both the device and the cable, using `SET FEATURES`.
+    ///
+    /// DMA transfers are only used if the controller supports bus mastering, and if the device accepted the selected
+    /// mode. PIO transfers are used otherwise.
+    pub(super) fn negotiate_udma(&self) {
+        let Some(bus_master) = &self.bus_master else {
+            return;
+        };
+
+        let supported_modes = self.identify_data().udma_modes_supported();
+        if supported_modes == 0 {
+            return;
+        }
+
+        let mut mode =
+            7 - u8::try_from(supported_modes.leading_zeros()).expect("invalid UDMA mode");
+
+        // Modes faster than UDMA 2 require an 80-conductor cable.
+        if mode > 2 && !self.identify_data().has_80_conductor_cable() {
+            mode = 2;
+        }
+
+        outb(self.io_base + 0x1, SET_FEATURES_TRANSFER_MODE);
+        self.set_sectors_count(u16::from(TRANSFER_MODE_UDMA | mode));
+
+        let result = self
+            .send_ata_command(AtaCommandRequest::new(AtaCommand::AtaSetFeatures, 0))
+            .complete();
+
+        if let AtaResult::Error(_) = result.result {
+            info!(
+                "ata",
+                "failed to select UDMA mode {mode}, using PIO transfers"
+            );
+            bus_master.set_drive_dma_capable(self.drive_index(), false);
+            return;
+        }
+
+        unsafe { *self.udma_mode.get() = Some(mode) };
+        bus_master.set_drive_dma_capable(self.drive_index(), true);
+
+        info!("ata", "selected UDMA mode {mode}");
+    }
+
+    /// Indicates whether transfers use the Bus Master DMA engine.
+    pub(super) fn dma_enabled(&self) -> bool {
+        self.bus_master.is_some() && self.udma_mode().is_some()
+    }
+
+    /// Returns the Ultra DMA mode currently selected, if any.
+    pub(super) fn udma_mode(&self) -> Option<u8> {
+        unsafe { *self.udma_mode.get() }
+    }
+
+    /// Index of this device on its channel (0 for the master, 1 for the slave).
+    fn drive_index(&self) -> u8 {
+        u8::from(self.is_slave)
+    }
+
+    /// Transfers `size` bytes from or to the buffer at `buffer`, starting at `start_lba`, using as many DMA commands as
+    /// required.
+    ///
+    /// `buffer` is only written to when reading from the device.
+    fn dma_transfer_chunks(
+        &self,
+        start_lba: u64,
+        buffer: *mut u8,
+        size: usize,
+        direction: AtaTransferDirection,
+    ) -> CanFail<IOError> {
+        let sectors_count = u64::try_from(size / self.sector_size()).expect("invalid data size");
+        if start_lba + sectors_count
+            > u64::try_from(self.max_sector()).expect("invalid sector count")
+        {
+            return Err(IOError::InvalidCommand);
+        }
+
+        let max_chunk_sectors = match self.identify_data().addressing_mode() {
+            AtaAddressingMode::Lba24 => 0xff,
+            AtaAddressingMode::Lba48 => usize::from(u16::MAX),
+        }
+        .min(MAX_TRANSFER_SIZE / self.sector_size());
+        let max_chunk_size = max_chunk_sectors * self.sector_size();
+
+        let mut offset = 0;
+        let mut lba = start_lba;
+
+        while offset < size {
+            let chunk_size = usize::min(max_chunk_size, size - offset);
+            let chunk_sectors =
+                u16::try_from(chunk_size / self.sector_size()).expect("invalid sectors count");
+
+            unsafe {
+                self.dma_transfer(
+                    lba,
+                    chunk_sectors,
+                    buffer.add(offset),
+                    chunk_size,
+                    direction,
+                )?;
+            }
+
+            offset += chunk_size;
+            lba += u64::from(chunk_sectors);
+        }
+
+        Ok(())
+    }
+
+    /// Issues a single `READ DMA` or `WRITE DMA` command (or their 48-bit variant), transferring `size` bytes from or
+    /// to `data`.
+    ///
+    /// The data goes through a buffer allocated for DMA by the controller.
+    ///
+    /// # Safety
+    ///
+    /// `data` must point to a valid memory region of `size` bytes, large enough to hold `sectors_count` sectors. It
+    /// is only written to when reading from the device.
+    unsafe fn dma_transfer(
+        &self,
+        lba: u64,
+        sectors_count: u16,
+        data: *mut u8,
+        size: usize,
+        direction: AtaTransferDirection,
+    ) -> CanFail<IOError> {
+        let bus_master = self.bus_master.as_ref().ok_or(IOError::InvalidDevice)?;
+
+        let ata_cmd = match (self.identify_data().addressing_mode(), direction) {
+            (AtaAddressingMode::Lba24, AtaTransferDirection::Read) => AtaCommand::AtaReadDma,
+            (AtaAddressingMode::Lba48, AtaTransferDirection::Read) => AtaCommand::AtaReadDmaExt,
+            (AtaAddressingMode::Lba24, AtaTransferDirection::Write) => AtaCommand::AtaWriteDma,
+            (AtaAddressingMode::Lba48, AtaTransferDirection::Write) => AtaCommand::AtaWriteDmaExt,
+        };
+
+        let mut buffer = bus_master.dma_alloc(size)?;
+        if matches!(direction, AtaTransferDirection::Write) {
+            ptr::copy_nonoverlapping(data, buffer.as_mut_slice().as_mut_ptr(), size);
+        }
+
+        bus_master.transfer(&[(buffer.dma_addr(), size)], direction, || {
+            self.set_lba(lba);
+            self.set_sectors_count(sectors_count);
+
+            // No data is transferred through the data port, the command completes with a single interrupt.
+            self.send_ata_command(AtaCommandRequest::new(ata_cmd, 0))
+        })?;
+
+        if matches!(direction, AtaTransferDirection::Read) {
+            ptr::copy_nonoverlapping(buffer.as_slice().as_ptr(), data, size);
+        }
+
+        Ok(())
+    }
+
     pub(super) fn enable_irq(&self) {
         ControlRegister::new().write(self.ctrl_base);
     }
@@ -507,14 +719,9 @@ impl AtaDevice {
                 };
                 ata_result.data = queued_cmd.buffer;
                 if let Some(io_req) = &queued_cmd.io_req {
-                    while io_req
-                        .has_completed
-                        .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed)
-                        .is_err()
-                    {
-                        hint::spin_loop();
-                    }
                     *io_req.result.lock() = Some(ata_result);
+                    io_req.has_completed.store(true, Ordering::Release);
+                    io_req.completion.wake_all();
                 }
 
                 while self
@@ -534,63 +741,63 @@ impl AtaDevice {
         let mbr = load_drive_mbr(self, 0);
 
         if mbr.is_pmbr() {
-            let gpt = load_drive_gpt(self);
-
-            if let Some(gpt) = gpt {
+            if let Some(gpt) = load_drive_gpt(self) {
+                let scanned = gpt.get_partitions();
                 unsafe {
-                    *self.partitions.get() = gpt.get_partitions();
                     *self.partition_table.get() = PartitionTable::GPT(gpt);
-
-                    for partition in &mut (*self.partitions.get()) {
-                        partition.load_fs().unwrap();
-                    }
                 }
+                self.update_partitions(scanned, true);
 
                 return;
             }
         }
 
-        unsafe {
-            *self.partitions.get() = mbr.get_partitions();
-        }
+        let mut scanned = mbr.get_partitions();
 
-        unsafe {
-            for partition in (*self.partitions.get()).clone().iter() {
-                if let PartitionMetadata::MBR(mut meta) = partition.metadata() {
-                    // if this device uses _EPBR_, we traverse the linked list to find all partitions.
-                    if matches!(meta.partition_type(), PartitionType::Extended)
-                        || matches!(meta.partition_type(), PartitionType::ExtendedLBA)
-                    {
-                        while load_drive_mbr(self, meta.start_lba() as u64).get_partition_metadata()
-                            [1]
+        for partition in scanned.clone().iter() {
+            if let PartitionMetadata::MBR(mut meta) = partition.metadata() {
+                // if this device uses _EPBR_, we traverse the linked list to find all partitions.
+                if matches!(meta.partition_type(), PartitionType::Extended)
+                    || matches!(meta.partition_type(), PartitionType::ExtendedLBA)
+                {
+                    while load_drive_mbr(self, meta.start_lba() as u64).get_partition_metadata()[1]
                         .is_used()
-                            || load_drive_mbr(self, meta.start_lba() as u64)
-                                .get_partition_metadata()[0]
-                                .is_used()
-                        {
-                            let partitions = load_drive_mbr(self, meta.start_lba() as u64)
-                                .get_partition_metadata();
-
-                            let mut ext_part = partitions[0];
-
-                            ext_part.set_start_lba(ext_part.start_lba() + meta.start_lba());
-
-                            (*self.partitions.get()).push(
-                                Partition::from_metadata(
-                                    0,
-                                    self.id,
-                                    PartitionMetadata::MBR(ext_part),
-                                )
+                        || load_drive_mbr(self, meta.start_lba() as u64).get_partition_metadata()[0]
+                            .is_used()
+                    {
+                        let partitions =
+                            load_drive_mbr(self, meta.start_lba() as u64).get_partition_metadata();
+
+                        let mut ext_part = partitions[0];
+
+                        ext_part.set_start_lba(ext_part.start_lba() + meta.start_lba());
+
+                        scanned.push(
+                            Partition::from_metadata(0, self.id, PartitionMetadata::MBR(ext_part))
                                 .unwrap(),
-                            );
-                            meta = partitions[1];
-                        }
+                        );
+                        meta = partitions[1];
                     }
                 }
             }
+        }
 
+        unsafe {
             *self.partition_table.get() = PartitionTable::MBR(mbr);
         }
+        self.update_partitions(scanned, false);
+    }
+
+    /// Replaces the partitions of this device with the `scanned` ones, keeping the unchanged ones and their mounted
+    /// filesystem (see [`reconcile_partitions`]).
+    ///
+    /// References to the previous partitions remain valid: the list is replaced, not the partitions it points to.
+    fn update_partitions(&self, scanned: Vec<Partition>, mount: bool) {
+        let previous = self.partitions.read().clone();
+        let partitions = reconcile_partitions(self.id, &previous, scanned, mount);
+
+        *self.partitions.write() = partitions.clone();
+        register_drive_partitions(self.id, &partitions);
     }
 
     pub(super) fn may_expect_irq(&self) -> bool {
@@ -651,6 +858,66 @@ impl AtaDevice {
         io_req
     }
 
+    /// Sends a `PIO` write command to the device, and transfers the first data block.
+    ///
+    /// The following blocks are transferred from the interrupt handler, each time the device is ready to receive
+    /// data. `data` is padded with zeros if it is smaller than `sectors_count` sectors.
+    fn send_write_command(
+        &self,
+        command: AtaCommand,
+        lba: u64,
+        sectors_count: u16,
+        transfer_blk_size: u16,
+        mut data: Vec<u8>,
+    ) -> AtaIoRequest {
+        let data_size = usize::from(sectors_count) * self.sector_size();
+        data.resize(data_size, 0);
+        let first_blk: Vec<u8> = data
+            .drain(..usize::min(usize::from(transfer_blk_size), data_size))
+            .collect();
+
+        self.set_lba(lba);
+        self.set_sectors_count(sectors_count);
+
+        let request = self.send_ata_command(
+            AtaCommandRequest::new(
+                command,
+                u64::try_from(data_size).expect("invalid data size"),
+            )
+            .with_transfer_blk_size(transfer_blk_size)
+            .with_data_buffer(data)
+            .with_direction(AtaTransferDirection::Write),
+        );
+
+        // The device does not raise an interrupt before the first data block, we have to wait until it is ready.
+        let ready = poll_until_timeout(
+            || StatusRegister::read_alternate(self.ctrl_base).drq(),
+            ATA_DRQ_TIMEOUT,
+        );
+        if !ready {
+            let queued_cmd = self.command_queue.replace(None);
+            if let Some((command, io_req)) =
+                queued_cmd.and_then(|cmd| Some((cmd.command, cmd.io_req?)))
+            {
+                *io_req.result.lock() = Some(AtaIoResult {
+                    result: AtaResult::Error(AtaError::new(AtaErrorCode::Timeout, lba)),
+                    command,
+                    data: None,
+                });
+                io_req.has_completed.store(true, Ordering::Release);
+                io_req.completion.wake_all();
+            }
+
+            return request;
+        }
+
+        for word in first_blk.chunks_exact(2) {
+            self.write_data_port(u16::from_le_bytes([word[0], word[1]]));
+        }
+
+        request
+    }
+
     fn set_sectors_count(&self, count: u16) {
         match self.identify_data().addressing_mode() {
             AtaAddressingMode::Lba24 => outb(self.io_base + 0x2, count.low_bits()),
@@ -727,6 +994,21 @@ impl AtaIdentify {
         }
     }
 
+    /// Returns the Ultra DMA modes supported by the device (bit `n` is set if `UDMA n` is supported).
+    pub(super) fn udma_modes_supported(&self) -> u8 {
+        // Word 88 is only valid if bit 2 of word 53 is set.
+        if self.0[53] & (1 << 2) == 0 {
+            return 0;
+        }
+
+        (self.0[88] & 0x7f).low_bits()
+    }
+
+    /// Indicates whether the device detected an 80-conductor cable, required for Ultra DMA modes above `UDMA 2`.
+    pub(super) fn has_80_conductor_cable(&self) -> bool {
+        self.0[93] & (1 << 13) != 0
+    }
+
     /// Returns the `maximum queue depth` supported by the device.
     ///
     /// The queue depth includes all command for which acceptance has occurred but not completion.
@@ -1140,4 +1422,5 @@ pub(in crate::drivers) enum AtaErrorCode {
     BadBlock,
     Generic,
     DriveFault,
+    Timeout,
 }
@@ -0,0 +1,245 @@
+//! Bus Master IDE (DMA) engine.
+//!
+//! Each IDE channel has its own set of Bus Master registers (located in the I/O space described by the `BAR` 4 of the
+//! controller), shared between the master and the slave device of the channel. A transfer is described by a
+//! Physical Region Descriptor Table (PRDT), which lists the memory regions the data is transferred from or to.
+//!
+//! The PRDT and the data buffers are allocated for DMA by the controller (see [`dma_alloc`]), below 4 GiB.
+
+use alloc::boxed::Box;
+use bytemuck::{Pod, Zeroable};
+use core::mem;
+use spin::Mutex;
+
+use crate::drivers::ide::ata_pio::{AtaIoRequest, AtaResult, AtaTransferDirection};
+use crate::drivers::ide::{IdeCommandRegister, IdeStatusRegister};
+use crate::errors::{CanFail, IOError};
+use crate::io::{inb, outb, outl, IOPort};
+use crate::mem::dma::{dma_alloc, DmaBuffer, DmaDevice, DMA_MASK_32};
+
+/// Number of entries in the PRDT of a channel.
+const PRDT_ENTRIES: usize = 32;
+
+/// Maximum number of bytes described by a single PRD entry.
+///
+/// A memory region must not cross a 64 KiB boundary.
+const PRD_MAX_SIZE: usize = 0x10000;
+
+/// Maximum number of bytes that can be transferred using a single command, whatever the alignment of the buffer.
+pub(super) const MAX_TRANSFER_SIZE: usize = (PRDT_ENTRIES - 1) * PRD_MAX_SIZE;
+
+/// Marks the last entry of a PRDT.
+const PRD_END_OF_TABLE: u16 = 1 << 15;
+
+/// Physical Region Descriptor, describes a memory region involved in a DMA transfer.
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+#[repr(C)]
+struct PhysicalRegionDescriptor {
+    /// Bus address of the region (must be word-aligned).
+    base: u32,
+
+    /// Size of the region in bytes, 0 meaning 64 KiB.
+    byte_count: u16,
+
+    flags: u16,
+}
+
+/// Bus Master registers of an IDE channel.
+#[derive(Debug)]
+pub(super) struct BusMasterChannel {
+    base: IOPort,
+
+    /// Identifies the controller for DMA.
+    dma_device: DmaDevice,
+
+    /// Physical Region Descriptor Table, page-aligned so that it never crosses a 64 KiB boundary.
+    prdt: Mutex<DmaBuffer>,
+}
+
+impl BusMasterChannel {
+    /// Offset of the `Command` register.
+    const COMMAND: u16 = 0x0;
+
+    /// Offset of the `Status` register.
+    const STATUS: u16 = 0x2;
+
+    /// Offset of the `Descriptor Table Pointer` register.
+    const PRDT_ADDRESS: u16 = 0x4;
+
+    /// Sets up the Bus Master registers located at `base`, for the controller `dma_device`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the PRDT cannot be allocated.
+    pub(super) fn new(base: IOPort, dma_device: DmaDevice) -> Result<Self, IOError> {
+        let prdt = dma_alloc(
+            dma_device,
+            PRDT_ENTRIES * mem::size_of::<PhysicalRegionDescriptor>(),
+            DMA_MASK_32,
+        )
+        .map_err(|e| IOError::Exception(Box::new(e)))?;
+
+        Ok(Self {
+            base,
+            dma_device,
+            prdt: Mutex::new(prdt),
+        })
+    }
+
+    /// Allocates a zeroed buffer of at least `size` bytes, that the controller can access through DMA.
+    pub(super) fn dma_alloc(&self, size: usize) -> Result<DmaBuffer, IOError> {
+        dma_alloc(self.dma_device, size, DMA_MASK_32).map_err(|e| IOError::Exception(Box::new(e)))
+    }
+
+    /// Reads the `Status` register of the channel.
+    pub(super) fn status(&self) -> IdeStatusRegister {
+        IdeStatusRegister::from(inb(self.base + Self::STATUS))
+    }
+
+    /// Indicates to the controller whether the device `drive` (0 for the master, 1 for the slave) was configured for
+    /// DMA transfers.
+    pub(super) fn set_drive_dma_capable(&self, drive: u8, capable: bool) {
+        // Do not clear the interrupt and error bits by accident.
+        let status = self.status().with_int(false).with_error(false);
+        let status = match drive {
+            0 => status.with_drive0_dma(capable),
+            _ => status.with_drive1_dma(capable),
+        };
+
+        outb(self.base + Self::STATUS, status.into());
+    }
+
+    /// Performs a DMA transfer from or to `segments` (bus address and size in bytes).
+    ///
+    /// The PRDT describing the segments is loaded into the controller, then `issue_command` is called to send the
+    /// command to the device, and the engine is started. Waits until the command completes, and stops the engine.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::InvalidCommand`] if a segment is not word-aligned, is not addressable using 32 bits, or if
+    /// the segments cannot be described using [`PRDT_ENTRIES`] entries. May return any other variant of [`IOError`]
+    /// in case of a device failure.
+    ///
+    /// # Safety
+    ///
+    /// Segments must be located in buffers allocated with [`BusMasterChannel::dma_alloc`], that remain allocated
+    /// during the whole transfer.
+    pub(super) unsafe fn transfer(
+        &self,
+        segments: &[(u64, usize)],
+        direction: AtaTransferDirection,
+        issue_command: impl FnOnce() -> AtaIoRequest,
+    ) -> CanFail<IOError> {
+        // The PRDT is shared by both devices of the channel, it remains locked during the whole transfer.
+        let mut prdt = self.prdt.lock();
+        self.load_prdt(&mut prdt, segments, direction)?;
+
+        let request = issue_command();
+        self.start();
+
+        let result = request.complete();
+        let engine_result = self.stop();
+
+        if let AtaResult::Error(_) = result.result {
+            return Err(IOError::Unknown);
+        }
+
+        engine_result
+    }
+
+    /// Builds the PRDT describing `segments`, and loads it into the controller.
+    ///
+    /// The engine is stopped, and the interrupt and error bits are cleared.
+    fn load_prdt(
+        &self,
+        prdt_buffer: &mut DmaBuffer,
+        segments: &[(u64, usize)],
+        direction: AtaTransferDirection,
+    ) -> CanFail<IOError> {
+        let prdt_address =
+            u32::try_from(prdt_buffer.dma_addr()).map_err(|_| IOError::InvalidCommand)?;
+        let prdt: &mut [PhysicalRegionDescriptor] = bytemuck::cast_slice_mut(
+            &mut prdt_buffer.as_mut_slice()
+                [..PRDT_ENTRIES * mem::size_of::<PhysicalRegionDescriptor>()],
+        );
+        let mut count = 0;
+
+        for &(base, size) in segments {
+            let mut address = base;
+            let end = address + size as u64;
+
+            if address % 2 != 0 || size % 2 != 0 || u32::try_from(end).is_err() {
+                return Err(IOError::InvalidCommand);
+            }
+
+            while address < end {
+                let boundary = (address / PRD_MAX_SIZE as u64 + 1) * PRD_MAX_SIZE as u64;
+                let region_size = (u64::min(boundary, end) - address) as usize;
+                let entry = prdt.get_mut(count).ok_or(IOError::InvalidCommand)?;
+
+                *entry = PhysicalRegionDescriptor {
+                    base: u32::try_from(address).expect("invalid PRD address"),
+                    // A byte count of 0 means 64 KiB.
+                    byte_count: (region_size % PRD_MAX_SIZE) as u16,
+                    flags: 0,
+                };
+
+                address += region_size as u64;
+                count += 1;
+            }
+        }
+
+        let last = count.checked_sub(1).ok_or(IOError::InvalidCommand)?;
+        prdt[last].flags = PRD_END_OF_TABLE;
+
+        outb(
+            self.base + Self::COMMAND,
+            IdeCommandRegister::new()
+                .with_write_control(matches!(direction, AtaTransferDirection::Read))
+                .into(),
+        );
+        outb(
+            self.base + Self::STATUS,
+            u8::from(self.status().with_int(true).with_error(true)),
+        );
+        outl(u16::from(self.base + Self::PRDT_ADDRESS), prdt_address);
+
+        Ok(())
+    }
+
+    /// Starts the transfer described by the PRDT.
+    fn start(&self) {
+        let command = IdeCommandRegister::from(inb(self.base + Self::COMMAND));
+
+        outb(
+            self.base + Self::COMMAND,
+            command.with_start_bus_master(true).into(),
+        );
+    }
+
+    /// Stops the engine once the device signaled the end of the command, and acknowledges the interrupt.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::Unknown`] if the controller reported an error during the transfer.
+    fn stop(&self) -> CanFail<IOError> {
+        let command = IdeCommandRegister::from(inb(self.base + Self::COMMAND));
+        outb(
+            self.base + Self::COMMAND,
+            command.with_start_bus_master(false).into(),
+        );
+
+        let status = self.status();
+        let failed = status.error();
+        outb(
+            self.base + Self::STATUS,
+            u8::from(status.with_int(true).with_error(true)),
+        );
+
+        if failed {
+            return Err(IOError::Unknown);
+        }
+
+        Ok(())
+    }
+}
@@ -1,13 +1,20 @@
 pub mod ata_command;
 pub(super) mod ata_pio;
+mod bus_master;
 
+use crate::drivers::devtree::DevicePath;
 use crate::drivers::generics::dev_disk::SataDeviceType;
 use crate::drivers::ide::ata_pio::{ata_devices, AtaDevice};
-use crate::drivers::pci::{pci_devices, DeviceClass};
+use crate::drivers::ide::bus_master::BusMasterChannel;
+use crate::drivers::pci::driver::{PciDeviceId, PciDriver};
+use crate::drivers::pci::DeviceClass;
+use crate::errors::{CanFail, IOError};
 use crate::io::IOPort;
 use crate::irq::manager::get_interrupt_manager;
 use crate::irq::InterruptStackFrame;
+use crate::mem::dma::DmaDevice;
 use crate::x86::apic::InterruptVector;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use conquer_once::spin::OnceCell;
 use core::cmp::Ordering;
@@ -20,6 +27,12 @@ use spin::RwLock;
 use super::pci::device::MappedRegister;
 use super::pci::device::PCIDevice;
 
+/// Interrupt vector of the primary channel, in compatibility mode (legacy `IRQ` 14).
+const ATA_PRIMARY_VECTOR: InterruptVector = InterruptVector::new(0x2E);
+
+/// Interrupt vector of the secondary channel, in compatibility mode (legacy `IRQ` 15).
+const ATA_SECONDARY_VECTOR: InterruptVector = InterruptVector::new(0x2F);
+
 #[interrupt_handler]
 pub fn ata_irq_entry(frame: InterruptStackFrame) {
     for ata_dev in ata_devices().read().values() {
@@ -37,12 +50,20 @@ pub fn ide_controllers() -> &'static RwLock<Vec<IdeController>> {
         .unwrap()
 }
 
-pub fn ide_init() {
-    let ide_controller = pci_devices().get_by_class(DeviceClass::IDEControllerBusMaster);
+/// PCI driver for `IDE` controllers.
+pub static IDE_DRIVER: PciDriver = PciDriver {
+    name: "ide",
+    id_table: &[PciDeviceId::class(DeviceClass::IDEControllerBusMaster)],
+    probe: ide_probe,
+    suspend: None,
+    resume: None,
+    remove: None,
+};
 
-    for controller in ide_controller.iter() {
-        IdeController::init_from_pci(controller);
-    }
+fn ide_probe(pci_dev: &mut PCIDevice<'static>) -> CanFail<IOError> {
+    IdeController::init_from_pci(pci_dev);
+
+    Ok(())
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -92,6 +113,7 @@ impl Display for AtaDeviceIdentifier {
         let disk_type_str = match self.disk_type {
             SataDeviceType::IDE => "IDE",
             SataDeviceType::AHCI => "AHCI",
+            SataDeviceType::Memory => "memory",
         };
         f.write_fmt(format_args!(
             "ATA device   device_type = {}    controller_id = {}    device_id = {}",
@@ -108,14 +130,15 @@ pub struct IdeController {
 }
 
 impl IdeController {
-    pub fn init_from_pci(pci_dev: &PCIDevice) {
+    pub fn init_from_pci(pci_dev: &mut PCIDevice) {
         let prim_chan = &pci_dev.registers[0];
         let prim_chan_ctrl = &pci_dev.registers[1];
         let sec_chan = &pci_dev.registers[2];
         let sec_chan_ctrl = &pci_dev.registers[3];
 
-        get_interrupt_manager().register_static_handler(InterruptVector::from(0x76), ata_irq_entry);
-        get_interrupt_manager().register_static_handler(InterruptVector::from(0x2E), ata_irq_entry);
+        // Legacy IRQ vectors are never handed out by the interrupt manager, no need to allocate them.
+        get_interrupt_manager().register_static_handler(ATA_PRIMARY_VECTOR, ata_irq_entry);
+        get_interrupt_manager().register_static_handler(ATA_SECONDARY_VECTOR, ata_irq_entry);
 
         let ports = match (prim_chan, prim_chan_ctrl, sec_chan, sec_chan_ctrl) {
             (
@@ -155,15 +178,37 @@ impl IdeController {
             ),
         };
 
+        // The Bus Master registers of both channels are located in the I/O space described by the `BAR` 4. Transfers
+        // fall back to PIO if bus mastering cannot be enabled.
+        let bus_master_enabled = pci_dev.set_bus_master(true).is_ok();
+        let (prim_bus_master, sec_bus_master) = match pci_dev.registers[4] {
+            MappedRegister::IO(bus_master_base) if bus_master_base != 0 && bus_master_enabled => {
+                let base = IOPort::from(bus_master_base);
+
+                let dma_device = DmaDevice::from(&*pci_dev);
+
+                (
+                    BusMasterChannel::new(base, dma_device).ok().map(Arc::new),
+                    BusMasterChannel::new(base + 0x8, dma_device)
+                        .ok()
+                        .map(Arc::new),
+                )
+            }
+            _ => (None, None),
+        };
+
         let mut controller_list = ide_controllers().write();
         let controller_id = controller_list.len();
+        let (bus, device, function) = pci_dev.location();
+        let controller_path = DevicePath::pci(bus, device, function);
         let primary_master = AtaDevice::init(
             AtaDeviceIdentifier::new(SataDeviceType::IDE, controller_id, 0),
             ports.0,
             ports.1,
             false,
-            controller_id,
             true,
+            &controller_path,
+            prim_bus_master.clone(),
         )
         .ok();
         let primary_slave = AtaDevice::init(
@@ -171,8 +216,9 @@ impl IdeController {
             ports.0,
             ports.1,
             true,
-            controller_id,
             true,
+            &controller_path,
+            prim_bus_master,
         )
         .ok();
         let secondary_master = AtaDevice::init(
@@ -180,8 +226,9 @@ impl IdeController {
             ports.2,
             ports.3,
             false,
-            controller_id,
             true,
+            &controller_path,
+            sec_bus_master.clone(),
         )
         .ok();
         let secondary_slave = AtaDevice::init(
@@ -189,8 +236,9 @@ impl IdeController {
             ports.2,
             ports.3,
             true,
-            controller_id,
             true,
+            &controller_path,
+            sec_bus_master,
         )
         .ok();
 
@@ -205,24 +253,33 @@ impl IdeController {
 
 struct IdeControllerRegister {}
 
+/// Bus Master IDE Command register.
 #[bitfield]
+#[repr(u8)]
+#[derive(Debug)]
 struct IdeCommandRegister {
     start_bus_master: bool,
     #[skip]
     __: B2,
+    /// Set for a transfer from the device to memory (device read).
     write_control: bool,
     #[skip]
     __: B4,
 }
 
+/// Bus Master IDE Status register.
+///
+/// The `error` and `int` bits are cleared by writing a 1 to them.
 #[bitfield]
+#[repr(u8)]
+#[derive(Debug)]
 struct IdeStatusRegister {
     active: bool,
     error: bool,
     int: bool,
     #[skip]
     __: B2,
-    driveO_dma: bool,
+    drive0_dma: bool,
     drive1_dma: bool,
     simplex_only: bool,
 }
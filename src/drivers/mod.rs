@@ -2,9 +2,13 @@ pub mod acpi;
 #[cfg(feature = "alloc")]
 pub mod ahci;
 #[cfg(feature = "alloc")]
+pub mod devtree;
+#[cfg(feature = "alloc")]
 pub mod ide;
 #[cfg(feature = "alloc")]
 pub mod pci;
+#[cfg(feature = "x86_64")]
+pub mod vtd;
 
 #[cfg(feature = "alloc")]
 pub mod generics;
@@ -255,6 +255,8 @@ pub(super) const SERR_COMMAND_BOFFSET: u8 = 8;
 pub(super) const FAST_B2B_TRANS_COMMAND_BOFFSET: u8 = 9;
 pub(super) const INTERRUPT_DISABLE: u8 = 10;
 
+pub(super) const CAP_PTR_BOFFSET: u8 = 0x34;
+
 pub(super) const CAP_LIST_STATUS_BOFFSET: u8 = 4;
 pub(super) const MHZ66_CAP_STATUS_BOFFSET: u8 = 5;
 pub(super) const FAST_B2B_CAP_STATUS_BOFFSET: u8 = 7;
@@ -274,15 +276,33 @@ pub enum DevselTiming {
 
 impl<'d> PCIDevice<'d> {
     /// Reads a `long` ([`u32`])  from this device PCI Configuration Space.
-    fn read_confl(&self, offset: u8) -> u32 {
+    pub(super) fn read_confl(&self, offset: u8) -> u32 {
         pci_read_long(self.bus, self.device, self.function, offset)
     }
 
     /// Writes a `long` ([`u32`]) to this device PCI Configuration Space
-    unsafe fn write_confl(&mut self, offset: u8, data: u32) {
+    pub(super) unsafe fn write_confl(&mut self, offset: u8, data: u32) {
         pci_write_long(self.bus, self.device, self.function, offset, data);
     }
 
+    /// Reads a `word` ([`u16`]) from this device PCI Configuration Space.
+    ///
+    /// Unlike [`PCIDevice::read_confl`], `offset` is a byte offset (which must be 2-bytes aligned).
+    pub(super) fn read_confw(&self, offset: u8) -> u16 {
+        (self.read_confl(offset / 4) >> ((offset % 4) * 8)) as u16
+    }
+
+    /// Writes a `word` ([`u16`]) to this device PCI Configuration Space.
+    ///
+    /// Unlike [`PCIDevice::write_confl`], `offset` is a byte offset (which must be 2-bytes aligned).
+    pub(super) unsafe fn write_confw(&mut self, offset: u8, data: u16) {
+        let shift = (offset % 4) * 8;
+        let curr_datal = self.read_confl(offset / 4);
+        let new_datal = (curr_datal & !(0xffff << shift)) | (data as u32) << shift;
+
+        self.write_confl(offset / 4, new_datal);
+    }
+
     /// Reads the content of this device's Status register.`
     fn read_status(&self) -> u16 {
         ((self.read_confl(STATUS_WOFFSET) & 0xffff0000) >> 16) as u16
@@ -342,6 +362,21 @@ impl<'d> PCIDevice<'d> {
         self.write_command(0);
     }
 
+    /// Returns the identifier of the manufacturer of this device.
+    pub fn vendor_id(&self) -> u16 {
+        (self.read_confl(0) & 0xffff) as u16
+    }
+
+    /// Returns the identifier of this device, assigned by its vendor.
+    pub fn device_id(&self) -> u16 {
+        (self.read_confl(0) >> 16) as u16
+    }
+
+    /// Returns the location of this device on the PCI bus (bus, device and function numbers).
+    pub fn location(&self) -> (u8, u8, u8) {
+        (self.bus, self.device, self.function)
+    }
+
     pub fn interrupt_line(&self) -> u8 {
         (self.read_confl(INTERRUPT_WOFFSET) & 0xff) as u8
     }
@@ -355,6 +390,34 @@ impl<'d> PCIDevice<'d> {
         self.read_status() & (1 << CAP_LIST_STATUS_BOFFSET) != 0
     }
 
+    /// Looks for the capability `id` in the capabilities linked list of this device.
+    ///
+    /// Returns the offset (in bytes) of the capability in the Configuration Space, if present.
+    pub fn find_capability(&self, id: u8) -> Option<u8> {
+        if !self.capabilities_list_available() {
+            return None;
+        }
+
+        let mut offset = (self.read_confw(CAP_PTR_BOFFSET) & 0xfc) as u8;
+
+        // The list cannot hold more than 48 entries, which protects against malformed (looping) lists.
+        for _ in 0..48 {
+            if offset == 0 {
+                return None;
+            }
+
+            let header = self.read_confw(offset);
+
+            if (header & 0xff) as u8 == id {
+                return Some(offset);
+            }
+
+            offset = ((header >> 8) & 0xfc) as u8;
+        }
+
+        None
+    }
+
     /// Checks if the device is capable of running at 66MHz.
     pub fn device_66mhz_support(&self) -> bool {
         self.read_status() & (1 << MHZ66_CAP_STATUS_BOFFSET) != 0
@@ -0,0 +1,235 @@
+//! PCI driver registration and device binding.
+//!
+//! Drivers declare a table of [`PciDeviceId`] describing the devices they support (by class, or by vendor and
+//! device identifiers), and a probe function called for each matching device. Once the PCI bus was enumerated, each
+//! device is bound to the first registered driver that matches it and whose probe succeeds.
+//!
+//! A driver registered after the enumeration is immediately bound to the devices that are not yet handled by
+//! another driver.
+
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::drivers::pci::device::PCIDevice;
+use crate::drivers::pci::{DeviceClass, PCI_DEVICES};
+use crate::errors::{CanFail, IOError};
+use crate::{error, info};
+
+/// Matches any vendor or device identifier in a [`PciDeviceId`].
+pub const PCI_ANY_ID: u16 = 0xffff;
+
+static PCI_DRIVERS: Mutex<Vec<&'static PciDriver>> = Mutex::new(Vec::new());
+
+static PCI_BINDINGS: Mutex<Vec<PciBinding>> = Mutex::new(Vec::new());
+
+/// Describes a set of PCI devices supported by a driver.
+#[derive(Clone, Copy, Debug)]
+pub struct PciDeviceId {
+    /// Vendor identifier, or [`PCI_ANY_ID`].
+    pub vendor_id: u16,
+
+    /// Device identifier, or [`PCI_ANY_ID`].
+    pub device_id: u16,
+
+    /// Class code (class, subclass and programming interface), compared using `class_mask`.
+    pub class: u32,
+
+    /// Bits of the class code that must match, 0 to match any class.
+    pub class_mask: u32,
+}
+
+impl PciDeviceId {
+    /// Matches every device of the given [`DeviceClass`] (including the programming interface).
+    pub const fn class(class: DeviceClass) -> Self {
+        Self::class_masked(class.code(), 0xff_ffff)
+    }
+
+    /// Matches every device whose class code, masked with `class_mask`, is equal to `class`.
+    ///
+    /// For instance, `class_masked(0x010100, 0xffff00)` matches IDE controllers regardless of their programming
+    /// interface.
+    pub const fn class_masked(class: u32, class_mask: u32) -> Self {
+        Self {
+            vendor_id: PCI_ANY_ID,
+            device_id: PCI_ANY_ID,
+            class,
+            class_mask,
+        }
+    }
+
+    /// Matches a single device model, from its vendor and device identifiers.
+    pub const fn device(vendor_id: u16, device_id: u16) -> Self {
+        Self {
+            vendor_id,
+            device_id,
+            class: 0,
+            class_mask: 0,
+        }
+    }
+
+    /// Checks if `device` is described by this identifier.
+    pub fn matches(&self, device: &PCIDevice) -> bool {
+        (self.vendor_id == PCI_ANY_ID || self.vendor_id == device.vendor_id())
+            && (self.device_id == PCI_ANY_ID || self.device_id == device.device_id())
+            && (u32::from(device.class) & self.class_mask) == (self.class & self.class_mask)
+    }
+}
+
+/// A PCI device driver.
+pub struct PciDriver {
+    /// Name of the driver, used in logs.
+    pub name: &'static str,
+
+    /// Devices supported by the driver.
+    pub id_table: &'static [PciDeviceId],
+
+    /// Initializes a device matching one of the entries of `id_table`.
+    ///
+    /// The device is bound to this driver if the probe succeeds.
+    pub probe: fn(&mut PCIDevice<'static>) -> CanFail<IOError>,
+
+    /// Prepares a device bound to this driver to leave the `D0` power state.
+    ///
+    /// Devices bound to a driver without a suspend callback are never suspended.
+    pub suspend: Option<fn(&mut PCIDevice<'static>) -> CanFail<IOError>>,
+
+    /// Restores a device bound to this driver, once it is back in the `D0` power state.
+    pub resume: Option<fn(&mut PCIDevice<'static>) -> CanFail<IOError>>,
+
+    /// Releases a device bound to this driver, which was removed from the system.
+    ///
+    /// The device may already be physically gone (surprise removal): reads from it may return all ones, and writes
+    /// may be dropped.
+    pub remove: Option<fn(&mut PCIDevice<'static>)>,
+}
+
+impl PciDriver {
+    /// Checks if this driver supports `device`.
+    pub fn matches(&self, device: &PCIDevice) -> bool {
+        self.id_table.iter().any(|id| id.matches(device))
+    }
+}
+
+/// A PCI device bound to a driver.
+#[derive(Clone, Copy)]
+pub struct PciBinding {
+    /// Location of the device (bus, device and function numbers).
+    pub location: (u8, u8, u8),
+
+    /// Driver handling the device.
+    pub driver: &'static PciDriver,
+}
+
+/// Registers a PCI driver.
+///
+/// If the PCI bus was already enumerated, the driver is bound to every matching device that is not already handled
+/// by another driver.
+pub fn register_pci_driver(driver: &'static PciDriver) {
+    PCI_DRIVERS.lock().push(driver);
+
+    if PCI_DEVICES.is_initialized() {
+        bind_driver(driver);
+    }
+}
+
+/// Binds every enumerated PCI device to the first registered driver that supports it.
+///
+/// Devices that are already bound to a driver are skipped.
+pub fn pci_bind_drivers() {
+    let drivers = PCI_DRIVERS.lock().clone();
+
+    for driver in drivers {
+        bind_driver(driver);
+    }
+}
+
+/// Returns the driver bound to the PCI device located at `location` (bus, device and function numbers).
+pub fn pci_device_driver(location: (u8, u8, u8)) -> Option<&'static PciDriver> {
+    PCI_BINDINGS
+        .lock()
+        .iter()
+        .find(|binding| binding.location == location)
+        .map(|binding| binding.driver)
+}
+
+/// Returns every PCI device currently bound to a driver.
+pub fn pci_bindings() -> Vec<PciBinding> {
+    PCI_BINDINGS.lock().clone()
+}
+
+/// Binds a PCI device that was connected after the enumeration to the first registered driver that supports it.
+///
+/// Returns `true` if a driver was bound to the device.
+pub fn pci_probe_device(location: (u8, u8, u8)) -> bool {
+    if pci_device_driver(location).is_some() {
+        return true;
+    }
+
+    let drivers = PCI_DRIVERS.lock().clone();
+    let mut device = PCIDevice::load(location.0, location.1, location.2);
+
+    drivers
+        .into_iter()
+        .any(|driver| driver.matches(&device) && probe(driver, &mut device))
+}
+
+/// Unbinds a PCI device that was removed from the system, after calling the remove callback of its driver.
+pub fn pci_remove_device(location: (u8, u8, u8)) {
+    let Some(driver) = pci_device_driver(location) else {
+        return;
+    };
+
+    let mut device = PCIDevice::load(location.0, location.1, location.2);
+
+    if let Some(remove) = driver.remove {
+        remove(&mut device);
+    }
+
+    PCI_BINDINGS
+        .lock()
+        .retain(|binding| binding.location != location);
+    info!("pci", "unbound {} from driver {}", device, driver.name);
+}
+
+/// Probes `driver` on each unbound PCI device it supports.
+fn bind_driver(driver: &'static PciDriver) {
+    let Some(devices) = PCI_DEVICES.get() else {
+        return;
+    };
+
+    for device in devices.iter() {
+        let location = device.location();
+
+        if !driver.matches(device) || pci_device_driver(location).is_some() {
+            continue;
+        }
+
+        // Probes get their own handle to the device, as the enumerated devices cannot be modified.
+        let mut device = PCIDevice::load(location.0, location.1, location.2);
+
+        probe(driver, &mut device);
+    }
+}
+
+/// Probes `driver` on `device`, and binds them if the probe succeeds.
+fn probe(driver: &'static PciDriver, device: &mut PCIDevice<'static>) -> bool {
+    match (driver.probe)(device) {
+        Ok(()) => {
+            info!("pci", "bound {} to driver {}", device, driver.name);
+            PCI_BINDINGS.lock().push(PciBinding {
+                location: device.location(),
+                driver,
+            });
+
+            true
+        }
+        Err(err) => {
+            error!(
+                "pci",
+                "driver {} failed to probe {}: {:?}", driver.name, device, err
+            );
+
+            false
+        }
+    }
+}
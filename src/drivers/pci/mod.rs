@@ -1,19 +1,32 @@
 use core::mem;
 
+use alloc::string::ToString;
 use alloc::vec::Vec;
 use conquer_once::spin::OnceCell;
 
-use crate::drivers::ide::ide_init;
+use crate::drivers::devtree::devtree_add_pci;
+use crate::drivers::ide::IDE_DRIVER;
 use crate::{
+    config,
     drivers::{
-        ahci::ahci_init,
-        pci::device::{PCIDevice, PCIDevices},
+        ahci::AHCI_DRIVER,
+        pci::{
+            device::{PCIDevice, PCIDevices},
+            driver::{pci_bind_drivers, register_pci_driver},
+            pcie::pcie_hotplug_init,
+        },
     },
     info,
-    io::{inl, outl},
+    io::{
+        port::{Port, PortRange},
+        IOPort,
+    },
 };
 
 pub mod device;
+pub mod driver;
+pub mod pcie;
+pub mod power;
 
 /// List of available PCI devices, after initial enumeration
 pub static PCI_DEVICES: OnceCell<PCIDevices> = OnceCell::uninit();
@@ -24,9 +37,19 @@ pub fn pci_devices() -> &'static PCIDevices {
         .expect("failed to enumerate pci devices")
 }
 
+/// Registers the built-in PCI drivers, which are bound to the enumerated devices they support.
+///
+/// Other drivers may be added using [`register_pci_driver`], before or after the enumeration. The built-in drivers can
+/// be disabled with the `ide.enable` and `ahci.enable` options (see [`config`]).
 pub fn pci_devices_init() {
-    ide_init();
-    ahci_init();
+    if config::get_bool("ide.enable") {
+        register_pci_driver(&IDE_DRIVER);
+    }
+    if config::get_bool("ahci.enable") {
+        register_pci_driver(&AHCI_DRIVER);
+    }
+
+    pcie_hotplug_init();
 }
 
 /// Builds the [`DeviceClass`] enum containing known PCI device classes.
@@ -52,15 +75,22 @@ macro_rules! pci_device_class_def {
             }
         }
 
-        impl From<DeviceClass> for u32 {
-            fn from(value: DeviceClass) -> u32 {
-                match value {
+        impl DeviceClass {
+            /// Returns the class code (class, subclass and programming interface) of this class.
+            pub const fn code(self) -> u32 {
+                match self {
                     $(DeviceClass::$name => $class,)*
                     DeviceClass::Unknown(val) => val,
                 }
             }
         }
 
+        impl From<DeviceClass> for u32 {
+            fn from(value: DeviceClass) -> u32 {
+                value.code()
+            }
+        }
+
         impl From<u32> for DeviceClass {
             fn from(value: u32) -> DeviceClass {
                 match value {
@@ -1179,8 +1209,19 @@ pub fn pci_enumerate() {
     let devices = unsafe { PCI_DEVICES.get_unchecked() };
 
     for device in devices.iter() {
-        info!("pci", "found {:}", device);
+        devtree_add_pci(device.location(), device.to_string());
+
+        match device.link_status() {
+            Some(link) => {
+                info!("pci", "found {:}    link = {}", device, link);
+            }
+            None => {
+                info!("pci", "found {:}", device);
+            }
+        }
     }
+
+    pci_bind_drivers();
 }
 
 /// Performs a recursive PCI devices discovery.
@@ -1280,6 +1321,26 @@ pub(super) fn pci_device_check(bus: u8, device: u8, list: &mut Vec<PCIDevice<'st
     }
 }
 
+/// `CONFIG_ADDRESS` and `CONFIG_DATA` I/O ports, claimed on first use.
+static PCI_CONFIG_PORTS: OnceCell<PciConfigPorts> = OnceCell::uninit();
+
+struct PciConfigPorts {
+    address: Port<u32>,
+    data: Port<u32>,
+}
+
+fn pci_config_ports() -> &'static PciConfigPorts {
+    PCI_CONFIG_PORTS.get_or_init(|| {
+        let ports = PortRange::claim(IOPort::PCI_CONFIG_ADDRESS, 8, "pci")
+            .expect("PCI configuration ports already claimed");
+
+        PciConfigPorts {
+            address: ports.port(0),
+            data: ports.port(4),
+        }
+    })
+}
+
 /// Reads a `long` ([`u32`]) from the PCI Configuration Space.
 pub fn pci_read_long(bus: u8, device: u8, func: u8, offset: u8) -> u32 {
     let mut config_address: u32 = 0;
@@ -1295,11 +1356,12 @@ pub fn pci_read_long(bus: u8, device: u8, func: u8, offset: u8) -> u32 {
 
     // `0xcf8` is the `CONFIG_ADDRESS` I/O port, used to specify the configuration address required
     // to be accessed.
-    outl(0xcf8, config_address);
+    let ports = pci_config_ports();
+    ports.address.write(config_address);
 
     // `0xcfc` is the `CONFIG_DATA` I/O port, it contains the data to transfert to or from the
     // `CONFIG_DATA` register.
-    inl(0xcfc)
+    ports.data.read()
 }
 
 /// Writes a `long` ([`u32`]) to the PCI Configuration Space.
@@ -1317,9 +1379,10 @@ pub fn pci_write_long(bus: u8, device: u8, func: u8, offset: u8, data: u32) {
 
     // `0xcf8` is the `CONFIG_ADDRESS` I/O port, used to specify the configuration address required
     // to be written.
-    outl(0xcf8, config_address);
+    let ports = pci_config_ports();
+    ports.address.write(config_address);
 
     // `0xcfc` is the `CONFIG_DATA` I/O port, it contains the data to transfert to or from the
     // `CONFIG_DATA` register.
-    outl(0xcfc, data);
+    ports.data.write(data);
 }
@@ -0,0 +1,568 @@
+//! PCI Express capability.
+//!
+//! PCI Express devices report the link they are attached through: the negotiated link speed and width can be lower
+//! than the maximum supported by the device (for instance, a x4 NVMe drive that trained at x1 because of a bad slot
+//! or riser), which is logged when the bus is enumerated.
+//!
+//! Root and downstream ports may implement a hot-plug capable slot. These slots raise an interrupt when a device is
+//! connected or removed, and the events are handled by [`pcie_process_hotplug_events`], deferred from the interrupt
+//! handler (see [`defer`]): drivers bound to devices that were removed from a slot are notified through their remove
+//! callback (see [`PciDriver`]), including when the device was removed without notice (_surprise removal_), and
+//! devices connected to a slot are probed.
+//!
+//! [`PciDriver`]: crate::drivers::pci::driver::PciDriver
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt::Display;
+use fzproc_macros::interrupt_handler;
+use spin::Mutex;
+
+use crate::drivers::devtree::{
+    devtree_add_pci, devtree_pci_functions, devtree_remove_pci, DevicePath,
+};
+use crate::drivers::pci::device::PCIDevice;
+use crate::drivers::pci::driver::{pci_bindings, pci_probe_device, pci_remove_device};
+use crate::drivers::pci::{pci_read_long, pci_write_long, PCI_DEVICES};
+use crate::event::{self, DeviceEvent};
+use crate::irq::{deferred::defer, manager::get_interrupt_manager, InterruptStackFrame};
+use crate::x86::apic::{
+    io_apic::get_all_io_apics, local_apic::VectorPriorityClass, mp_table::IOApicIntPin,
+};
+use crate::x86::int::{disable_interrupts, enable_interrupts, interrupts_disabled};
+use crate::{error, info};
+
+/// Identifier of the PCI Express capability.
+pub const PCI_CAP_ID_EXP: u8 = 0x10;
+
+/// Offset of the `PCI Express Capabilities` register, from the start of the capability.
+const PCIE_CAP_OFFSET: u8 = 0x2;
+
+/// Offset of the `Link Capabilities` register, from the start of the capability.
+const LINK_CAP_OFFSET: u8 = 0xC;
+
+/// Offset of the `Link Status` register, from the start of the capability.
+const LINK_STATUS_OFFSET: u8 = 0x12;
+
+/// Offset of the `Slot Capabilities` register, from the start of the capability.
+const SLOT_CAP_OFFSET: u8 = 0x14;
+
+/// Offset of the `Slot Control` register, from the start of the capability.
+const SLOT_CONTROL_OFFSET: u8 = 0x18;
+
+/// Offset of the `Slot Status` register, from the start of the capability.
+const SLOT_STATUS_OFFSET: u8 = 0x1A;
+
+/// Offset of the bus numbers register of a bridge (primary, secondary and subordinate bus numbers).
+const BRIDGE_BUS_NUMBERS_OFFSET: u8 = 0x18;
+
+const PCIE_CAP_SLOT_IMPLEMENTED: u16 = 1 << 8;
+
+const LINK_STATUS_TRAINING: u16 = 1 << 11;
+const LINK_STATUS_DLL_ACTIVE: u16 = 1 << 13;
+
+const SLOT_CAP_SURPRISE_REMOVAL: u32 = 1 << 5;
+const SLOT_CAP_HOTPLUG: u32 = 1 << 6;
+
+const SLOT_CONTROL_PRESENCE_CHANGED_ENABLE: u16 = 1 << 3;
+const SLOT_CONTROL_HOTPLUG_INTERRUPT_ENABLE: u16 = 1 << 5;
+const SLOT_CONTROL_DLL_CHANGED_ENABLE: u16 = 1 << 12;
+
+const SLOT_STATUS_PRESENCE_CHANGED: u16 = 1 << 3;
+const SLOT_STATUS_PRESENCE: u16 = 1 << 6;
+const SLOT_STATUS_DLL_CHANGED: u16 = 1 << 8;
+const SLOT_STATUS_EVENTS: u16 = SLOT_STATUS_PRESENCE_CHANGED | SLOT_STATUS_DLL_CHANGED;
+
+/// Hot-plug capable slots, found by [`pcie_hotplug_init`].
+static HOTPLUG_SLOTS: Mutex<Vec<HotplugSlot>> = Mutex::new(Vec::new());
+
+/// Kind of PCI Express function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PciePortType {
+    Endpoint,
+    LegacyEndpoint,
+    RootPort,
+    UpstreamPort,
+    DownstreamPort,
+    PcieToPciBridge,
+    PciToPcieBridge,
+    RootComplexIntegratedEndpoint,
+    RootComplexEventCollector,
+    Unknown(u8),
+}
+
+impl From<u8> for PciePortType {
+    fn from(value: u8) -> Self {
+        match value {
+            0x0 => Self::Endpoint,
+            0x1 => Self::LegacyEndpoint,
+            0x4 => Self::RootPort,
+            0x5 => Self::UpstreamPort,
+            0x6 => Self::DownstreamPort,
+            0x7 => Self::PcieToPciBridge,
+            0x8 => Self::PciToPcieBridge,
+            0x9 => Self::RootComplexIntegratedEndpoint,
+            0xA => Self::RootComplexEventCollector,
+            _ => Self::Unknown(value),
+        }
+    }
+}
+
+/// Transfer rate of a PCI Express link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LinkSpeed {
+    /// 2.5 GT/s.
+    Gen1,
+
+    /// 5 GT/s.
+    Gen2,
+
+    /// 8 GT/s.
+    Gen3,
+
+    /// 16 GT/s.
+    Gen4,
+
+    /// 32 GT/s.
+    Gen5,
+
+    /// 64 GT/s.
+    Gen6,
+
+    Unknown(u8),
+}
+
+impl From<u8> for LinkSpeed {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Self::Gen1,
+            2 => Self::Gen2,
+            3 => Self::Gen3,
+            4 => Self::Gen4,
+            5 => Self::Gen5,
+            6 => Self::Gen6,
+            _ => Self::Unknown(value),
+        }
+    }
+}
+
+impl Display for LinkSpeed {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Gen1 => write!(f, "2.5 GT/s"),
+            Self::Gen2 => write!(f, "5 GT/s"),
+            Self::Gen3 => write!(f, "8 GT/s"),
+            Self::Gen4 => write!(f, "16 GT/s"),
+            Self::Gen5 => write!(f, "32 GT/s"),
+            Self::Gen6 => write!(f, "64 GT/s"),
+            Self::Unknown(value) => write!(f, "unknown ({value})"),
+        }
+    }
+}
+
+/// PCI Express capability of a PCI device.
+#[derive(Clone, Copy, Debug)]
+pub struct PcieCapability {
+    /// Offset of the capability in the Configuration Space (in bytes).
+    offset: u8,
+
+    /// Content of the `PCI Express Capabilities` register.
+    capabilities: u16,
+
+    /// Content of the `Link Capabilities` register.
+    link_capabilities: u32,
+
+    /// Content of the `Slot Capabilities` register (only valid if a slot is implemented).
+    slot_capabilities: u32,
+}
+
+impl PcieCapability {
+    pub fn port_type(&self) -> PciePortType {
+        PciePortType::from(((self.capabilities >> 4) & 0xf) as u8)
+    }
+
+    /// Maximum link speed supported by the port.
+    pub fn max_link_speed(&self) -> LinkSpeed {
+        LinkSpeed::from((self.link_capabilities & 0xf) as u8)
+    }
+
+    /// Maximum link width (number of lanes) supported by the port.
+    pub fn max_link_width(&self) -> u8 {
+        ((self.link_capabilities >> 4) & 0x3f) as u8
+    }
+
+    /// Checks if the port is connected to a slot (rather than to an integrated component).
+    pub fn slot_implemented(&self) -> bool {
+        self.capabilities & PCIE_CAP_SLOT_IMPLEMENTED != 0
+    }
+
+    /// Checks if the slot of the port supports hot-plug operations.
+    pub fn hotplug_capable(&self) -> bool {
+        self.slot_implemented() && self.slot_capabilities & SLOT_CAP_HOTPLUG != 0
+    }
+
+    /// Checks if a device may be removed from the slot of the port without prior notification.
+    pub fn surprise_removal_capable(&self) -> bool {
+        self.slot_implemented() && self.slot_capabilities & SLOT_CAP_SURPRISE_REMOVAL != 0
+    }
+}
+
+/// Current state of a PCI Express link.
+#[derive(Clone, Copy, Debug)]
+pub struct LinkStatus {
+    /// Negotiated link speed.
+    pub speed: LinkSpeed,
+
+    /// Negotiated link width (number of lanes).
+    pub width: u8,
+
+    /// Maximum link speed supported by the port.
+    pub max_speed: LinkSpeed,
+
+    /// Maximum link width supported by the port.
+    pub max_width: u8,
+
+    /// Whether the link is being trained.
+    pub training: bool,
+
+    /// Whether the data link layer of the link is active (only reported by some downstream ports).
+    pub active: bool,
+}
+
+impl LinkStatus {
+    /// Checks if the link trained below the speed or width supported by the port.
+    pub fn is_degraded(&self) -> bool {
+        self.speed < self.max_speed || self.width < self.max_width
+    }
+}
+
+impl Display for LinkStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "x{} @ {} (max x{} @ {})",
+            self.width, self.speed, self.max_width, self.max_speed
+        )?;
+
+        if self.is_degraded() {
+            write!(f, " degraded")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'d> PCIDevice<'d> {
+    /// Returns the PCI Express capability of this device, if implemented.
+    pub fn pcie_capability(&self) -> Option<PcieCapability> {
+        let offset = self.find_capability(PCI_CAP_ID_EXP)?;
+
+        Some(PcieCapability {
+            offset,
+            capabilities: self.read_confw(offset + PCIE_CAP_OFFSET),
+            link_capabilities: self.read_confdw(offset + LINK_CAP_OFFSET),
+            slot_capabilities: self.read_confdw(offset + SLOT_CAP_OFFSET),
+        })
+    }
+
+    /// Returns the current state of the PCI Express link of this device, if it is a PCI Express device.
+    pub fn link_status(&self) -> Option<LinkStatus> {
+        let pcie = self.pcie_capability()?;
+        let status = self.read_confw(pcie.offset + LINK_STATUS_OFFSET);
+
+        Some(LinkStatus {
+            speed: LinkSpeed::from((status & 0xf) as u8),
+            width: ((status >> 4) & 0x3f) as u8,
+            max_speed: pcie.max_link_speed(),
+            max_width: pcie.max_link_width(),
+            training: status & LINK_STATUS_TRAINING != 0,
+            active: status & LINK_STATUS_DLL_ACTIVE != 0,
+        })
+    }
+
+    /// Reads a `dword` ([`u32`]) from this device PCI Configuration Space, at the byte offset `offset` (which must be
+    /// 4-bytes aligned).
+    fn read_confdw(&self, offset: u8) -> u32 {
+        self.read_confl(offset / 4)
+    }
+}
+
+/// Hot-plug capable slot of a root or downstream port.
+#[derive(Clone, Copy, Debug)]
+struct HotplugSlot {
+    /// Location of the port.
+    location: (u8, u8, u8),
+
+    /// Offset of the PCI Express capability of the port.
+    cap_offset: u8,
+
+    /// Range of buses below the port.
+    secondary_bus: u8,
+    subordinate_bus: u8,
+
+    /// Whether a device was present in the slot when last checked.
+    occupied: bool,
+
+    /// Events of the `Slot Status` register cleared by [`hotplug_irq_entry`], and not processed yet.
+    pending: u16,
+}
+
+impl HotplugSlot {
+    fn read_slot_status(&self) -> u16 {
+        let (bus, device, function) = self.location;
+        let offset = self.cap_offset + SLOT_STATUS_OFFSET;
+
+        (pci_read_long(bus, device, function, offset / 4) >> ((offset % 4) * 8)) as u16
+    }
+
+    /// Enables the hot-plug interrupt of the slot, raised when a device is connected or removed.
+    fn enable_hotplug_interrupt(&self) {
+        let (bus, device, function) = self.location;
+
+        // Status bits are written as 0, so that no pending event is cleared.
+        let index = (self.cap_offset + SLOT_CONTROL_OFFSET) / 4;
+        let control = pci_read_long(bus, device, function, index) & 0xffff;
+        let enable = SLOT_CONTROL_PRESENCE_CHANGED_ENABLE
+            | SLOT_CONTROL_HOTPLUG_INTERRUPT_ENABLE
+            | SLOT_CONTROL_DLL_CHANGED_ENABLE;
+
+        pci_write_long(bus, device, function, index, control | u32::from(enable));
+    }
+
+    /// Clears the write-one-to-clear `bits` of the `Slot Status` register.
+    fn clear_slot_status(&self, bits: u16) {
+        let (bus, device, function) = self.location;
+
+        // The `Slot Control` register shares the `long` of the `Slot Status` register. Only the status bits to clear
+        // are written as 1, so that other pending events are not cleared by accident.
+        let index = (self.cap_offset + SLOT_STATUS_OFFSET) / 4;
+        let control = pci_read_long(bus, device, function, index) & 0xffff;
+
+        pci_write_long(
+            bus,
+            device,
+            function,
+            index,
+            control | (u32::from(bits) << 16),
+        );
+    }
+}
+
+/// Looks for the hot-plug capable slots among the enumerated PCI Express ports, clears their pending events, and
+/// enables their hot-plug interrupt.
+pub fn pcie_hotplug_init() {
+    let Some(devices) = PCI_DEVICES.get() else {
+        return;
+    };
+
+    let mut slots = HOTPLUG_SLOTS.lock();
+    let mut pins = Vec::new();
+
+    for port in devices.iter() {
+        let Some(pcie) = port.pcie_capability() else {
+            continue;
+        };
+
+        if !matches!(
+            pcie.port_type(),
+            PciePortType::RootPort | PciePortType::DownstreamPort
+        ) || !pcie.hotplug_capable()
+        {
+            continue;
+        }
+
+        let bus_numbers = port.read_confdw(BRIDGE_BUS_NUMBERS_OFFSET);
+        let mut slot = HotplugSlot {
+            location: port.location(),
+            cap_offset: pcie.offset,
+            secondary_bus: (bus_numbers >> 8) as u8,
+            subordinate_bus: (bus_numbers >> 16) as u8,
+            occupied: false,
+            pending: 0,
+        };
+
+        slot.occupied = slot.read_slot_status() & SLOT_STATUS_PRESENCE != 0;
+        slot.clear_slot_status(SLOT_STATUS_EVENTS);
+
+        info!(
+            "pcie",
+            "hot-plug slot on {}    occupied = {}    surprise removal = {}",
+            port,
+            slot.occupied,
+            pcie.surprise_removal_capable()
+        );
+
+        slots.push(slot);
+        pins.push(port.interrupt_line());
+    }
+
+    // The interrupt handler locks the slots.
+    let found = slots.clone();
+    drop(slots);
+
+    if found.is_empty() {
+        return;
+    }
+
+    let Some(io_apics) = get_all_io_apics() else {
+        error!(
+            "pcie",
+            "no I/O APIC available, hot-plug events are not handled"
+        );
+        return;
+    };
+
+    let Ok(vector) =
+        get_interrupt_manager().allocate_vector(VectorPriorityClass::DEVICE, "pcie-hotplug")
+    else {
+        error!(
+            "pcie",
+            "no interrupt vector available, hot-plug events are not handled"
+        );
+        return;
+    };
+
+    for io_apic in io_apics {
+        let io_apic = io_apic.1.lock();
+
+        for &pin in &pins {
+            io_apic.map_pin_to_irq(IOApicIntPin::from(pin), vector);
+        }
+    }
+    get_interrupt_manager().register_static_handler(vector, hotplug_irq_entry);
+
+    for slot in &found {
+        let (bus, device, function) = slot.location;
+        if PCIDevice::load(bus, device, function)
+            .set_interrupt_disable(false)
+            .is_err()
+        {
+            error!(
+                "pcie",
+                "failed to enable the interrupts of {:?}", slot.location
+            );
+            continue;
+        }
+
+        slot.enable_hotplug_interrupt();
+    }
+}
+
+/// Hot-plug interrupts entry point.
+///
+/// The events of the slots are cleared (so that the interrupt is no longer asserted), and their processing is
+/// deferred to [`pcie_process_hotplug_events`].
+#[interrupt_handler]
+pub fn hotplug_irq_entry(frame: InterruptStackFrame) {
+    let mut slots = HOTPLUG_SLOTS.lock();
+    let mut pending = false;
+
+    for slot in slots.iter_mut() {
+        let events = slot.read_slot_status() & SLOT_STATUS_EVENTS;
+
+        if events != 0 {
+            slot.clear_slot_status(events);
+            slot.pending |= events;
+            pending = true;
+        }
+    }
+
+    if pending {
+        defer(pcie_process_hotplug_events);
+    }
+}
+
+/// Handles the devices connected to or removed from the hot-plug capable slots since the last call.
+///
+/// Drivers bound to a removed device (or to a device below it) are notified, and the device is unbound. Newly
+/// connected devices are probed.
+///
+/// Drivers may access the devices from their callbacks, this must not be called from an interrupt handler.
+pub fn pcie_process_hotplug_events() {
+    // The slots are shared with the interrupt handler: they are only locked with interrupts disabled, and not while
+    // the drivers are notified.
+    let were_disabled = interrupts_disabled();
+    disable_interrupts();
+
+    let mut changed = Vec::new();
+    for slot in HOTPLUG_SLOTS.lock().iter_mut() {
+        let status = slot.read_slot_status();
+        let events = (status & SLOT_STATUS_EVENTS) | core::mem::take(&mut slot.pending);
+
+        if events == 0 {
+            continue;
+        }
+        slot.clear_slot_status(status & SLOT_STATUS_EVENTS);
+
+        let present = status & SLOT_STATUS_PRESENCE != 0;
+        if present == slot.occupied {
+            continue;
+        }
+        slot.occupied = present;
+        changed.push(*slot);
+    }
+
+    if !were_disabled {
+        enable_interrupts();
+    }
+
+    for slot in &changed {
+        let (bus, device, function) = slot.location;
+        let path = DevicePath::pci(bus, device, function);
+        if slot.occupied {
+            info!("pcie", "device connected below {path}");
+            event::publish(DeviceEvent::LinkUp { path });
+            probe_slot(slot);
+        } else {
+            info!("pcie", "device removed below {path}");
+            remove_slot(slot);
+            event::publish(DeviceEvent::LinkDown { path });
+        }
+    }
+}
+
+/// Probes the functions found on the secondary bus of a slot.
+fn probe_slot(slot: &HotplugSlot) {
+    for device in 0..32 {
+        for function in 0..8 {
+            let vendor_id = pci_read_long(slot.secondary_bus, device, function, 0) & 0xffff;
+
+            if vendor_id == 0xffff {
+                // Function 0 must be implemented by every device.
+                if function == 0 {
+                    break;
+                }
+                continue;
+            }
+
+            let location = (slot.secondary_bus, device, function);
+            devtree_add_pci(
+                location,
+                PCIDevice::load(location.0, location.1, location.2).to_string(),
+            );
+            pci_probe_device(location);
+        }
+    }
+}
+
+/// Unbinds every device located below a slot, from the deepest buses up, and removes them from the device tree.
+fn remove_slot(slot: &HotplugSlot) {
+    let below_slot =
+        |&(bus, _, _): &(u8, u8, u8)| (slot.secondary_bus..=slot.subordinate_bus).contains(&bus);
+
+    let mut removed: Vec<(u8, u8, u8)> = pci_bindings()
+        .iter()
+        .map(|binding| binding.location)
+        .filter(below_slot)
+        .collect();
+
+    removed.sort_unstable_by(|a, b| b.cmp(a));
+
+    for location in removed {
+        pci_remove_device(location);
+    }
+
+    for location in devtree_pci_functions().into_iter().filter(below_slot) {
+        devtree_remove_pci(location);
+    }
+}
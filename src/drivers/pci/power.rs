@@ -0,0 +1,311 @@
+//! PCI Power Management.
+//!
+//! Devices implementing the Power Management capability can be placed into a low-power state (`D1`, `D2` or `D3hot`)
+//! while they are not used, and brought back to the fully operational `D0` state afterwards.
+//!
+//! Drivers may provide suspend and resume callbacks (see [`PciDriver`]), called before a device bound to them leaves
+//! `D0`, and once it is back in `D0`. Devices that are not bound to any driver can be suspended directly.
+//!
+//! [`PciDriver`]: crate::drivers::pci::driver::PciDriver
+
+use alloc::vec::Vec;
+use core::time::Duration;
+use spin::Mutex;
+
+use crate::drivers::pci::device::PCIDevice;
+use crate::drivers::pci::driver::pci_device_driver;
+use crate::drivers::pci::PCI_DEVICES;
+use crate::errors::{CanFail, IOError};
+use crate::info;
+use crate::time::timer;
+
+/// Identifier of the Power Management capability.
+pub const PCI_CAP_ID_PM: u8 = 0x01;
+
+/// Offset of the `Power Management Capabilities` register, from the start of the capability.
+const PMC_OFFSET: u8 = 0x2;
+
+/// Offset of the `Power Management Control/Status` register, from the start of the capability.
+const PMCSR_OFFSET: u8 = 0x4;
+
+const PMCSR_POWER_STATE_MASK: u16 = 0b11;
+
+/// Set if the device keeps its configuration when transitioning from `D3hot` to `D0`.
+const PMCSR_NO_SOFT_RESET: u16 = 1 << 3;
+
+/// Write-one-to-clear bit, which must be written as 0 to be preserved.
+const PMCSR_PME_STATUS: u16 = 1 << 15;
+
+/// Number of `long` in the Configuration Space header, saved before suspending a device.
+const SAVED_HEADER_LEN: usize = 16;
+
+/// Configuration Space header of the suspended devices, restored when they are resumed.
+static SUSPENDED_DEVICES: Mutex<Vec<((u8, u8, u8), [u32; SAVED_HEADER_LEN])>> =
+    Mutex::new(Vec::new());
+
+/// Power states of a PCI function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PowerState {
+    /// Fully operational.
+    D0,
+
+    /// Light sleep state (optional).
+    D1,
+
+    /// Deeper sleep state (optional).
+    D2,
+
+    /// Deepest sleep state, from which the function can still be accessed through the Configuration Space.
+    D3Hot,
+}
+
+impl From<u16> for PowerState {
+    fn from(value: u16) -> Self {
+        match value & PMCSR_POWER_STATE_MASK {
+            0b00 => Self::D0,
+            0b01 => Self::D1,
+            0b10 => Self::D2,
+            _ => Self::D3Hot,
+        }
+    }
+}
+
+impl From<PowerState> for u16 {
+    fn from(value: PowerState) -> Self {
+        match value {
+            PowerState::D0 => 0b00,
+            PowerState::D1 => 0b01,
+            PowerState::D2 => 0b10,
+            PowerState::D3Hot => 0b11,
+        }
+    }
+}
+
+/// Power Management capability of a PCI device.
+#[derive(Clone, Copy, Debug)]
+pub struct PowerManagementCapability {
+    /// Offset of the capability in the Configuration Space (in bytes).
+    offset: u8,
+
+    /// Content of the `Power Management Capabilities` register.
+    capabilities: u16,
+}
+
+impl PowerManagementCapability {
+    /// Version of the Power Management specification implemented by the device.
+    pub fn version(&self) -> u8 {
+        (self.capabilities & 0b111) as u8
+    }
+
+    /// Checks if the device supports the `D1` power state.
+    pub fn d1_support(&self) -> bool {
+        self.capabilities & (1 << 9) != 0
+    }
+
+    /// Checks if the device supports the `D2` power state.
+    pub fn d2_support(&self) -> bool {
+        self.capabilities & (1 << 10) != 0
+    }
+
+    /// Bitmap of the power states from which the device may assert `PME#` (bit 0 for `D0`, up to bit 4 for
+    /// `D3cold`).
+    pub fn pme_support(&self) -> u8 {
+        (self.capabilities >> 11) as u8
+    }
+
+    /// Checks if the device supports the power state `state`.
+    pub fn supports(&self, state: PowerState) -> bool {
+        match state {
+            PowerState::D0 | PowerState::D3Hot => true,
+            PowerState::D1 => self.d1_support(),
+            PowerState::D2 => self.d2_support(),
+        }
+    }
+}
+
+impl<'d> PCIDevice<'d> {
+    /// Returns the Power Management capability of this device, if implemented.
+    pub fn power_management(&self) -> Option<PowerManagementCapability> {
+        let offset = self.find_capability(PCI_CAP_ID_PM)?;
+
+        Some(PowerManagementCapability {
+            offset,
+            capabilities: self.read_confw(offset + PMC_OFFSET),
+        })
+    }
+
+    /// Returns the current power state of this device, if it implements the Power Management capability.
+    pub fn power_state(&self) -> Option<PowerState> {
+        let pm = self.power_management()?;
+
+        Some(PowerState::from(self.read_confw(pm.offset + PMCSR_OFFSET)))
+    }
+
+    /// Places this device into the power state `state`.
+    ///
+    /// The device configuration is not saved: it may be lost when the device goes back to `D0` from `D3hot` (see
+    /// [`pci_suspend_device`] for a higher-level interface).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::InvalidCommand`] if the device does not implement the Power Management capability, if it
+    /// does not support `state`, or if that transition is not allowed. Returns [`IOError::Unknown`] if the device
+    /// did not reach the requested state.
+    pub fn set_power_state(&mut self, state: PowerState) -> CanFail<IOError> {
+        let pm = self.power_management().ok_or(IOError::InvalidCommand)?;
+
+        if !pm.supports(state) {
+            return Err(IOError::InvalidCommand);
+        }
+
+        let control = self.read_confw(pm.offset + PMCSR_OFFSET);
+        let current = PowerState::from(control);
+
+        if current == state {
+            return Ok(());
+        }
+
+        // A device can only go to a deeper sleep state, or back to `D0`.
+        if state != PowerState::D0 && state < current {
+            return Err(IOError::InvalidCommand);
+        }
+
+        let new_control =
+            (control & !(PMCSR_POWER_STATE_MASK | PMCSR_PME_STATUS)) | u16::from(state);
+        unsafe { self.write_confw(pm.offset + PMCSR_OFFSET, new_control) };
+
+        // Recovery times required by the specification (10 ms from or to `D3hot`, 200 us from or to `D2`).
+        if current == PowerState::D3Hot || state == PowerState::D3Hot {
+            timer::sleep(Duration::from_millis(10));
+        } else if current == PowerState::D2 || state == PowerState::D2 {
+            timer::sleep(Duration::from_micros(200));
+        }
+
+        (self.power_state() == Some(state))
+            .then_some(())
+            .ok_or(IOError::Unknown)
+    }
+
+    /// Checks if the device keeps its configuration when going back to `D0` from `D3hot`.
+    fn no_soft_reset(&self) -> bool {
+        self.power_management()
+            .is_some_and(|pm| self.read_confw(pm.offset + PMCSR_OFFSET) & PMCSR_NO_SOFT_RESET != 0)
+    }
+}
+
+/// Suspends the PCI device located at `location` (bus, device and function numbers), and places it into `D3hot`.
+///
+/// If the device is bound to a driver, its suspend callback is called first. The Configuration Space header of the
+/// device is saved, and restored by [`pci_resume_device`].
+///
+/// # Errors
+///
+/// Returns [`IOError::InvalidCommand`] if the device does not implement the Power Management capability, or if it is
+/// bound to a driver that does not support suspending its devices. May return any other variant of [`IOError`] if
+/// the suspend callback fails.
+pub fn pci_suspend_device(location: (u8, u8, u8)) -> CanFail<IOError> {
+    let mut device = PCIDevice::load(location.0, location.1, location.2);
+    let driver = pci_device_driver(location);
+
+    if device.power_management().is_none() {
+        return Err(IOError::InvalidCommand);
+    }
+
+    if let Some(driver) = driver {
+        let suspend = driver.suspend.ok_or(IOError::InvalidCommand)?;
+        suspend(&mut device)?;
+    }
+
+    let mut header = [0u32; SAVED_HEADER_LEN];
+    for (offset, long) in header.iter_mut().enumerate() {
+        *long = device.read_confl(offset as u8);
+    }
+
+    if let Err(err) = device.set_power_state(PowerState::D3Hot) {
+        // Leave the device in a usable state.
+        if let Some(resume) = driver.and_then(|driver| driver.resume) {
+            resume(&mut device)?;
+        }
+
+        return Err(err);
+    }
+
+    let mut suspended = SUSPENDED_DEVICES.lock();
+    suspended.retain(|(loc, _)| *loc != location);
+    suspended.push((location, header));
+
+    Ok(())
+}
+
+/// Brings the PCI device located at `location` (bus, device and function numbers) back to `D0`.
+///
+/// The Configuration Space header saved by [`pci_suspend_device`] is restored if the device lost its configuration,
+/// and the resume callback of its driver is called.
+///
+/// # Errors
+///
+/// Returns [`IOError::InvalidCommand`] if the device does not implement the Power Management capability. May return
+/// any other variant of [`IOError`] if the resume callback fails.
+pub fn pci_resume_device(location: (u8, u8, u8)) -> CanFail<IOError> {
+    let mut device = PCIDevice::load(location.0, location.1, location.2);
+    device.set_power_state(PowerState::D0)?;
+
+    let saved = {
+        let mut suspended = SUSPENDED_DEVICES.lock();
+        let index = suspended.iter().position(|(loc, _)| *loc == location);
+
+        index.map(|index| suspended.remove(index).1)
+    };
+
+    if let Some(header) = saved {
+        if !device.no_soft_reset() {
+            // Identifiers and class code (`long` 0 and 2) are read-only, the command register is restored last.
+            for offset in (3..SAVED_HEADER_LEN).rev() {
+                unsafe { device.write_confl(offset as u8, header[offset]) };
+            }
+            unsafe { device.write_confl(1, header[1]) };
+        }
+    }
+
+    // Mapped registers may have changed while the configuration was lost.
+    let mut device = PCIDevice::load(location.0, location.1, location.2);
+
+    if let Some(resume) = pci_device_driver(location).and_then(|driver| driver.resume) {
+        resume(&mut device)?;
+    }
+
+    Ok(())
+}
+
+/// Places every unused PCI device into `D3hot`.
+///
+/// Devices bound to a driver are left untouched, as well as bridges and display controllers. Returns the number of
+/// devices that were suspended.
+pub fn pci_suspend_idle_devices() -> usize {
+    let Some(devices) = PCI_DEVICES.get() else {
+        return 0;
+    };
+
+    let mut count = 0;
+
+    for device in devices.iter() {
+        let location = device.location();
+        let base_class = u32::from(device.class) >> 16;
+
+        // Bridges (`0x06`) and display controllers (`0x03`) are required even without a driver.
+        if base_class == 0x03 || base_class == 0x06 || pci_device_driver(location).is_some() {
+            continue;
+        }
+
+        if device
+            .power_state()
+            .is_some_and(|state| state == PowerState::D0)
+            && pci_suspend_device(location).is_ok()
+        {
+            info!("pci", "placed {} into D3hot", device);
+            count += 1;
+        }
+    }
+
+    count
+}
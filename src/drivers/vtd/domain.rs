@@ -0,0 +1,243 @@
+//! Translation domains.
+//!
+//! A domain is the address space seen by the devices attached to it: a second-level page table translating bus
+//! addresses (I/O virtual addresses, `IOVAs`) into physical addresses, along with the allocator of the unused ranges
+//! of bus addresses.
+//!
+//! Second-level page tables have the same layout as the 4-level (or 3-level, with 39-bit addresses) x86_64 page
+//! tables, with read and write permission bits.
+
+use core::{arch::asm, ptr, slice};
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+
+use crate::{
+    errors::DmaError,
+    kernel_syms::PAGE_SIZE,
+    mem::{get_physical_memory, PhyAddr},
+    x86::paging::page_alloc::frame_alloc::alloc_page,
+};
+
+const PTE_READ: u64 = 1 << 0;
+const PTE_WRITE: u64 = 1 << 1;
+const PTE_ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+const ENTRIES_PER_TABLE: usize = 512;
+
+/// Size of a cache line, the granularity of cache flushes.
+const CACHE_LINE_SIZE: usize = 64;
+
+/// First bus address handed out: keeps the null bus address unused.
+const IOVA_START: u64 = PAGE_SIZE as u64;
+
+#[derive(Debug)]
+pub(super) struct Domain {
+    /// Domain identifier, tagging the translations cached by the hardware.
+    pub(super) id: u16,
+
+    root: PhyAddr,
+
+    /// Number of levels of the page table (3 or 4).
+    levels: u8,
+
+    /// Whether the hardware snoops the processor caches when walking the page table.
+    coherent: bool,
+
+    /// Unused ranges of bus addresses, as `start -> end` (exclusive).
+    free: BTreeMap<u64, u64>,
+}
+
+impl Domain {
+    /// Creates an empty domain, whose bus addresses are `address_width` bits wide.
+    pub(super) fn new(
+        id: u16,
+        levels: u8,
+        address_width: u8,
+        coherent: bool,
+    ) -> Result<Self, DmaError> {
+        let mut free = BTreeMap::new();
+        free.insert(IOVA_START, 1 << address_width);
+
+        Ok(Self {
+            id,
+            root: alloc_table(coherent)?,
+            levels,
+            coherent,
+            free,
+        })
+    }
+
+    /// Physical address of the root of the page table.
+    pub(super) fn root(&self) -> PhyAddr {
+        self.root
+    }
+
+    /// Allocates `size` bytes of bus addresses, ending at or below `limit`.
+    ///
+    /// Returns the first bus address of the range, or `None` if no large enough range is left.
+    pub(super) fn alloc_iova(&mut self, size: u64, limit: u64) -> Option<u64> {
+        let (start, end) = self
+            .free
+            .iter()
+            .map(|(&start, &end)| (start, end))
+            .find(|&(start, end)| end - start >= size && start + (size - 1) <= limit)?;
+
+        self.free.remove(&start);
+        if start + size < end {
+            self.free.insert(start + size, end);
+        }
+
+        Some(start)
+    }
+
+    /// Releases a range of bus addresses allocated with [`Domain::alloc_iova`].
+    pub(super) fn free_iova(&mut self, mut start: u64, size: u64) {
+        let mut end = start + size;
+
+        if let Some(next_end) = self.free.remove(&end) {
+            end = next_end;
+        }
+
+        if let Some((&prev_start, &prev_end)) = self.free.range(..start).next_back() {
+            if prev_end == start {
+                self.free.remove(&prev_start);
+                start = prev_start;
+            }
+        }
+
+        self.free.insert(start, end);
+    }
+
+    /// Removes the bus addresses in `start..end` from the unused ranges, so that they are never allocated.
+    pub(super) fn reserve_iova(&mut self, start: u64, end: u64) {
+        let overlapping: Vec<(u64, u64)> = self
+            .free
+            .range(..end)
+            .map(|(&s, &e)| (s, e))
+            .filter(|&(_, e)| e > start)
+            .collect();
+
+        for (s, e) in overlapping {
+            self.free.remove(&s);
+
+            if s < start {
+                self.free.insert(s, start);
+            }
+            if e > end {
+                self.free.insert(end, e);
+            }
+        }
+    }
+
+    /// Maps the `size` bytes of physical memory at `phys` at the bus address `iova`.
+    pub(super) fn map(
+        &mut self,
+        iova: u64,
+        phys: PhyAddr,
+        size: usize,
+        write: bool,
+    ) -> Result<(), DmaError> {
+        let flags = if write {
+            PTE_READ | PTE_WRITE
+        } else {
+            PTE_READ
+        };
+
+        for offset in (0..size).step_by(PAGE_SIZE) {
+            let entry = self.leaf_entry(iova + offset as u64, true)?;
+
+            unsafe {
+                ptr::write_volatile(entry, (u64::from(phys) + offset as u64) | flags);
+            }
+            self.flush(entry.cast(), 8);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the translations of the `size` bytes of bus addresses starting at `iova`.
+    ///
+    /// The translations cached by the hardware must be invalidated afterwards.
+    pub(super) fn unmap(&mut self, iova: u64, size: usize) {
+        for offset in (0..size).step_by(PAGE_SIZE) {
+            if let Ok(entry) = self.leaf_entry(iova + offset as u64, false) {
+                unsafe {
+                    ptr::write_volatile(entry, 0);
+                }
+                self.flush(entry.cast(), 8);
+            }
+        }
+    }
+
+    /// Returns a pointer to the last-level entry translating `iova`.
+    ///
+    /// Missing intermediate tables are allocated if `create` is set, otherwise [`DmaError::Unreachable`] is returned.
+    fn leaf_entry(&mut self, iova: u64, create: bool) -> Result<*mut u64, DmaError> {
+        let mut table = self.root;
+
+        for level in (1..self.levels).rev() {
+            let entry = table_entry(table, iova, level);
+            let value = unsafe { ptr::read_volatile(entry) };
+
+            table = if value & (PTE_READ | PTE_WRITE) != 0 {
+                PhyAddr::new(value & PTE_ADDR_MASK)
+            } else if create {
+                let next = alloc_table(self.coherent)?;
+
+                unsafe {
+                    ptr::write_volatile(entry, u64::from(next) | PTE_READ | PTE_WRITE);
+                }
+                self.flush(entry.cast(), 8);
+
+                next
+            } else {
+                return Err(DmaError::Unreachable);
+            };
+        }
+
+        Ok(table_entry(table, iova, 0))
+    }
+
+    fn flush(&self, addr: *const u8, len: usize) {
+        if !self.coherent {
+            flush_cache_range(addr, len);
+        }
+    }
+}
+
+/// Writes back the cache lines covering `len` bytes at `addr` to memory, for hardware that does not snoop the
+/// processor caches.
+pub(super) fn flush_cache_range(addr: *const u8, len: usize) {
+    let start = addr as usize & !(CACHE_LINE_SIZE - 1);
+
+    for line in (start..addr as usize + len).step_by(CACHE_LINE_SIZE) {
+        unsafe {
+            asm!("clflush [{}]", in(reg) line, options(nostack, preserves_flags));
+        }
+    }
+
+    unsafe {
+        asm!("mfence", options(nostack, preserves_flags));
+    }
+}
+
+/// Allocates a zeroed page, for a translation table.
+pub(super) fn alloc_table(coherent: bool) -> Result<PhyAddr, DmaError> {
+    let frame = alloc_page(PAGE_SIZE).map_err(|_| DmaError::OutOfMemory)?;
+    let table = get_physical_memory(frame.start);
+
+    unsafe { slice::from_raw_parts_mut(table, PAGE_SIZE) }.fill(0);
+    if !coherent {
+        flush_cache_range(table, PAGE_SIZE);
+    }
+
+    Ok(frame.start)
+}
+
+/// Returns a pointer to the entry of `table` translating `iova`, for a table of the given level (0 being the last
+/// level).
+fn table_entry(table: PhyAddr, iova: u64, level: u8) -> *mut u64 {
+    let index = (iova >> (12 + 9 * u32::from(level))) as usize % ENTRIES_PER_TABLE;
+
+    unsafe { get_physical_memory(table).cast::<u64>().add(index) }
+}
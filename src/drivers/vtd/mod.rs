@@ -0,0 +1,602 @@
+//! Intel VT-d DMA remapping (`IOMMU`).
+//!
+//! The remapping hardware units described by the ACPI `DMAR` table (see [`DmarTable`]) translate the memory
+//! accesses of the devices under their scope. Each device is given a context entry, that either:
+//!
+//! - points to a translation [`domain`], so that the device can only access the buffers mapped for it, at the bus
+//!   addresses chosen when mapping them. Devices are attached to their own domain the first time a DMA buffer is
+//!   allocated for them (see [`dma_alloc`]).
+//! - lets its requests through untranslated (pass-through), for devices whose driver does not use the DMA API yet.
+//!   On hardware without pass-through support, such devices are blocked.
+//!
+//! Memory regions reported by the firmware as still used by some devices (`RMRR`, such as USB legacy emulation
+//! buffers) are identity-mapped in the domains of these devices.
+//!
+//! Translation faults are recorded by the hardware, and logged every [`FAULT_POLL_INTERVAL`].
+//!
+//! Based on the Intel Virtualization Technology for Directed I/O specification.
+//!
+//! [`DmarTable`]: crate::io::acpi::dmar::DmarTable
+//! [`dma_alloc`]: crate::mem::dma::dma_alloc
+
+use core::{
+    ops::RangeInclusive,
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use spin::Mutex;
+
+use crate::{
+    drivers::pci::{pci_devices, pci_read_long},
+    error,
+    errors::{DmaError, IommuError},
+    info,
+    io::acpi::{
+        dmar::{DeviceScope, DeviceScopeType, DmarEntry, DmarHardwareUnit, DmarTable},
+        RSDP,
+    },
+    kernel_syms::PAGE_SIZE,
+    mem::{dma::DmaDevice, get_physical_memory, PhyAddr},
+    time::timer,
+};
+
+use domain::{alloc_table, flush_cache_range, Domain};
+
+mod domain;
+
+/// Interval between two polls of the fault recording registers.
+pub const FAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+const REG_CAP: usize = 0x08;
+const REG_ECAP: usize = 0x10;
+const REG_GCMD: usize = 0x18;
+const REG_GSTS: usize = 0x1C;
+const REG_RTADDR: usize = 0x20;
+const REG_CCMD: usize = 0x28;
+const REG_FSTS: usize = 0x34;
+
+/// Required write-buffer flushing.
+const CAP_RWBF: u64 = 1 << 4;
+
+/// Caching mode: not-present entries may be cached, and must be invalidated when they are filled.
+const CAP_CM: u64 = 1 << 7;
+
+/// Supported adjusted guest address widths (bit 1: 39-bit, 3 levels. Bit 2: 48-bit, 4 levels).
+const CAP_SAGAW_SHIFT: u64 = 8;
+
+/// Coherent page walks.
+const ECAP_C: u64 = 1 << 0;
+
+/// Pass-through translation type supported.
+const ECAP_PT: u64 = 1 << 6;
+
+const GCMD_TE: u32 = 1 << 31;
+const GCMD_SRTP: u32 = 1 << 30;
+const GCMD_WBF: u32 = 1 << 27;
+
+/// Bits of `GSTS` reflecting persistent states, to be preserved when issuing a command through `GCMD`.
+const GSTS_PERSISTENT_MASK: u32 = 0x96FF_FFFF;
+
+const CCMD_ICC: u64 = 1 << 63;
+const CCMD_GLOBAL: u64 = 1 << 61;
+
+const IOTLB_IVT: u64 = 1 << 63;
+const IOTLB_GLOBAL: u64 = 1 << 60;
+const IOTLB_DOMAIN: u64 = 2 << 60;
+const IOTLB_DRAIN: u64 = (1 << 49) | (1 << 48);
+
+const FSTS_PFO: u32 = 1 << 0;
+
+const ROOT_PRESENT: u64 = 1 << 0;
+const CONTEXT_PRESENT: u64 = 1 << 0;
+const CONTEXT_TT_PASSTHROUGH: u64 = 0b10 << 2;
+
+/// Number of polls of a register before considering that the hardware did not complete a command.
+const REGISTER_TIMEOUT: usize = 1_000_000;
+
+/// Domain identifier used by devices in pass-through mode.
+const PASSTHROUGH_DOMAIN: u16 = 1;
+
+static IOMMU_UNITS: Mutex<Vec<RemappingUnit>> = Mutex::new(Vec::new());
+
+static IOMMU_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Remapping hardware unit.
+#[derive(Debug)]
+struct RemappingUnit {
+    /// Physical address of the registers.
+    register_base: u64,
+
+    segment: u16,
+
+    /// Covers every device of the segment that no other unit covers.
+    include_all: bool,
+
+    /// Devices explicitly under the scope of the unit.
+    endpoints: Vec<DmaDevice>,
+
+    /// Bus numbers under the bridges in the scope of the unit.
+    buses: Vec<RangeInclusive<u8>>,
+
+    cap: u64,
+    ecap: u64,
+
+    /// Number of levels of the page tables (3 or 4).
+    levels: u8,
+
+    root_table: PhyAddr,
+    context_tables: BTreeMap<u8, PhyAddr>,
+    domains: BTreeMap<DmaDevice, Domain>,
+    next_domain: u16,
+}
+
+impl RemappingUnit {
+    fn new(unit: &DmarHardwareUnit) -> Result<Self, IommuError> {
+        let mut remapping_unit = Self {
+            register_base: unit.register_base,
+            segment: unit.segment,
+            include_all: unit.include_pci_all(),
+            endpoints: Vec::new(),
+            buses: Vec::new(),
+            cap: 0,
+            ecap: 0,
+            levels: 0,
+            root_table: PhyAddr::new(0),
+            context_tables: BTreeMap::new(),
+            domains: BTreeMap::new(),
+            next_domain: PASSTHROUGH_DOMAIN + 1,
+        };
+
+        remapping_unit.cap = remapping_unit.read64(REG_CAP);
+        remapping_unit.ecap = remapping_unit.read64(REG_ECAP);
+
+        let sagaw = (remapping_unit.cap >> CAP_SAGAW_SHIFT) & 0x1F;
+        remapping_unit.levels = if sagaw & 0b100 != 0 {
+            4
+        } else if sagaw & 0b10 != 0 {
+            3
+        } else {
+            return Err(IommuError::UnsupportedAddressWidth);
+        };
+
+        for scope in unit.device_scopes() {
+            match scope.kind {
+                DeviceScopeType::Endpoint => {
+                    if let Some(device) = resolve_scope(unit.segment, &scope) {
+                        remapping_unit.endpoints.push(device);
+                    }
+                }
+                DeviceScopeType::Bridge => {
+                    if let Some(device) = resolve_scope(unit.segment, &scope) {
+                        let buses = pci_read_long(device.bus, device.device, device.function, 0x18);
+                        remapping_unit
+                            .buses
+                            .push(((buses >> 8) as u8)..=((buses >> 16) as u8));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        remapping_unit.root_table =
+            alloc_table(remapping_unit.coherent()).map_err(|_| IommuError::OutOfMemory)?;
+
+        Ok(remapping_unit)
+    }
+
+    /// Whether the unit explicitly covers `device`.
+    fn covers(&self, device: DmaDevice) -> bool {
+        device.segment == self.segment
+            && (self.endpoints.contains(&device)
+                || self.buses.iter().any(|buses| buses.contains(&device.bus)))
+    }
+
+    fn coherent(&self) -> bool {
+        self.ecap & ECAP_C != 0
+    }
+
+    /// Width of the bus addresses translated by the domains of this unit.
+    fn address_width(&self) -> u8 {
+        let mgaw = ((self.cap >> 16) & 0x3F) as u8 + 1;
+
+        u8::min(mgaw, 12 + 9 * self.levels)
+    }
+
+    /// Returns a pointer to the context entry of `device`, allocating its context table if needed.
+    fn context_entry(&mut self, device: DmaDevice) -> Result<*mut u64, DmaError> {
+        let coherent = self.coherent();
+
+        let table = match self.context_tables.get(&device.bus) {
+            Some(table) => *table,
+            None => {
+                let table = alloc_table(coherent)?;
+                let root_entry = get_physical_memory(self.root_table)
+                    .cast::<u64>()
+                    .wrapping_add(2 * usize::from(device.bus));
+
+                unsafe {
+                    ptr::write_volatile(root_entry, u64::from(table) | ROOT_PRESENT);
+                }
+                if !coherent {
+                    flush_cache_range(root_entry.cast(), 16);
+                }
+
+                self.context_tables.insert(device.bus, table);
+                table
+            }
+        };
+
+        let devfn = (usize::from(device.device) << 3) | usize::from(device.function);
+
+        Ok(get_physical_memory(table)
+            .cast::<u64>()
+            .wrapping_add(2 * devfn))
+    }
+
+    /// Writes the context entry of `device`.
+    fn set_context(&mut self, device: DmaDevice, lower: u64, domain: u16) -> Result<(), DmaError> {
+        let entry = self.context_entry(device)?;
+        let upper = u64::from(self.levels - 2) | (u64::from(domain) << 8);
+
+        unsafe {
+            ptr::write_volatile(entry, 0);
+            ptr::write_volatile(entry.wrapping_add(1), upper);
+            ptr::write_volatile(entry, lower | CONTEXT_PRESENT);
+        }
+        if !self.coherent() {
+            flush_cache_range(entry.cast(), 16);
+        }
+
+        Ok(())
+    }
+
+    /// Lets the requests of `device` through untranslated, if the hardware supports it.
+    fn set_passthrough(&mut self, device: DmaDevice) -> Result<bool, DmaError> {
+        if self.ecap & ECAP_PT == 0 || self.domains.contains_key(&device) {
+            return Ok(false);
+        }
+
+        self.set_context(device, CONTEXT_TT_PASSTHROUGH, PASSTHROUGH_DOMAIN)?;
+        Ok(true)
+    }
+
+    /// Returns the domain of `device`, attaching the device to a new domain if needed.
+    fn attach(&mut self, device: DmaDevice) -> Result<&mut Domain, DmaError> {
+        if !self.domains.contains_key(&device) {
+            let domain = Domain::new(
+                self.next_domain,
+                self.levels,
+                self.address_width(),
+                self.coherent(),
+            )?;
+            self.next_domain += 1;
+
+            self.set_context(device, u64::from(domain.root()), domain.id)?;
+            self.domains.insert(device, domain);
+
+            if IOMMU_ENABLED.load(Ordering::Acquire) && self.invalidate_all().is_err() {
+                error!("vtd", "context cache invalidation timed out");
+            }
+        }
+
+        Ok(self
+            .domains
+            .get_mut(&device)
+            .expect("device attached to no domain"))
+    }
+
+    /// Maps the `size` bytes of physical memory at `phys` in the domain of `device`, below the bus address `limit`.
+    fn map(
+        &mut self,
+        device: DmaDevice,
+        phys: PhyAddr,
+        size: usize,
+        limit: u64,
+    ) -> Result<u64, DmaError> {
+        let caching_mode = self.cap & CAP_CM != 0;
+        let domain = self.attach(device)?;
+
+        let iova = domain
+            .alloc_iova(size as u64, limit)
+            .ok_or(DmaError::AddressSpaceExhausted)?;
+
+        if let Err(err) = domain.map(iova, phys, size, true) {
+            domain.unmap(iova, size);
+            domain.free_iova(iova, size as u64);
+            return Err(err);
+        }
+
+        // Not-present translations may be cached as well.
+        let domain_id = domain.id;
+        if caching_mode && self.invalidate_domain(domain_id).is_err() {
+            error!("vtd", "IOTLB invalidation timed out");
+        }
+
+        Ok(iova)
+    }
+
+    /// Loads the root table, and enables DMA remapping.
+    fn enable(&mut self) -> Result<(), IommuError> {
+        self.write64(REG_RTADDR, u64::from(self.root_table));
+        self.command(GCMD_SRTP, true)?;
+
+        self.invalidate_all()?;
+        self.command(GCMD_TE, true)
+    }
+
+    /// Issues a command through `GCMD`, and waits for its completion (the status bit being set, or cleared for
+    /// one-shot commands that reset it once done).
+    fn command(&self, command: u32, wait_set: bool) -> Result<(), IommuError> {
+        let status = self.read32(REG_GSTS) & GSTS_PERSISTENT_MASK;
+        self.write32(REG_GCMD, status | command);
+
+        self.wait(|unit| (unit.read32(REG_GSTS) & command != 0) == wait_set)
+    }
+
+    /// Invalidates every translation cached by the hardware (context cache and IOTLB).
+    fn invalidate_all(&self) -> Result<(), IommuError> {
+        if self.cap & CAP_RWBF != 0 {
+            self.command(GCMD_WBF, false)?;
+        }
+
+        self.write64(REG_CCMD, CCMD_ICC | CCMD_GLOBAL);
+        self.wait(|unit| unit.read64(REG_CCMD) & CCMD_ICC == 0)?;
+
+        self.invalidate_iotlb(IOTLB_GLOBAL)
+    }
+
+    /// Invalidates the translations of `domain` cached in the IOTLB.
+    fn invalidate_domain(&self, domain: u16) -> Result<(), IommuError> {
+        self.invalidate_iotlb(IOTLB_DOMAIN | (u64::from(domain) << 32))
+    }
+
+    fn invalidate_iotlb(&self, granularity: u64) -> Result<(), IommuError> {
+        let iotlb = self.iotlb_offset();
+
+        self.write64(iotlb, IOTLB_IVT | IOTLB_DRAIN | granularity);
+        self.wait(|unit| unit.read64(iotlb) & IOTLB_IVT == 0)
+    }
+
+    fn iotlb_offset(&self) -> usize {
+        ((self.ecap >> 8) & 0x3FF) as usize * 16 + 8
+    }
+
+    /// Logs and clears the recorded translation faults.
+    fn report_faults(&self) {
+        let records_offset = ((self.cap >> 24) & 0x3FF) as usize * 16;
+        let records = ((self.cap >> 40) & 0xFF) as usize + 1;
+
+        for record in (0..records).map(|index| records_offset + 16 * index) {
+            let info = self.read64(record + 8);
+
+            // Fault bit.
+            if info & (1 << 63) == 0 {
+                continue;
+            }
+
+            let device = DmaDevice::from_source_id(self.segment, info as u16);
+            error!(
+                "vtd",
+                "DMA {} fault from {:02x}:{:02x}.{} at {:#x} (reason {:#x})",
+                if info & (1 << 62) != 0 {
+                    "read"
+                } else {
+                    "write"
+                },
+                device.bus,
+                device.device,
+                device.function,
+                self.read64(record) & !0xFFF,
+                (info >> 32) as u8
+            );
+
+            self.write32(record + 12, 1 << 31);
+        }
+
+        if self.read32(REG_FSTS) & FSTS_PFO != 0 {
+            error!("vtd", "fault records overflow, some faults were lost");
+            self.write32(REG_FSTS, FSTS_PFO);
+        }
+    }
+
+    fn wait(&self, done: impl Fn(&Self) -> bool) -> Result<(), IommuError> {
+        for _ in 0..REGISTER_TIMEOUT {
+            if done(self) {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+
+        Err(IommuError::Timeout)
+    }
+
+    fn register(&self, offset: usize) -> *mut u8 {
+        get_physical_memory(PhyAddr::new(self.register_base)).wrapping_add(offset)
+    }
+
+    fn read32(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile(self.register(offset).cast()) }
+    }
+
+    fn write32(&self, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile(self.register(offset).cast(), value) }
+    }
+
+    fn read64(&self, offset: usize) -> u64 {
+        unsafe { ptr::read_volatile(self.register(offset).cast()) }
+    }
+
+    fn write64(&self, offset: usize, value: u64) {
+        unsafe { ptr::write_volatile(self.register(offset).cast(), value) }
+    }
+}
+
+/// Sets up the remapping hardware units reported by the `DMAR` table, and enables DMA remapping.
+///
+/// Every enumerated PCI device is given a pass-through context, until a DMA buffer is allocated for it.
+///
+/// # Errors
+///
+/// Returns an error if the platform has no remapping hardware, or if a unit could not be set up. Units set up before
+/// the failure remain enabled.
+pub fn iommu_init() -> Result<(), IommuError> {
+    if RSDP.get().is_none() {
+        return Err(IommuError::NoAcpi);
+    }
+
+    let dmar = DmarTable::load().ok_or(IommuError::NoDmarTable)?;
+    let mut units = IOMMU_UNITS.lock();
+
+    for entry in dmar.entries() {
+        if let DmarEntry::HardwareUnit(unit) = entry {
+            units.push(RemappingUnit::new(&unit)?);
+        }
+    }
+
+    if units.is_empty() {
+        return Err(IommuError::NoRemappingUnit);
+    }
+
+    for pci_device in pci_devices().iter() {
+        let device = DmaDevice::from(pci_device);
+
+        if let Some(unit) = find_unit(&mut units, device) {
+            unit.set_passthrough(device)
+                .map_err(|_| IommuError::OutOfMemory)?;
+        }
+    }
+
+    for entry in dmar.entries() {
+        let DmarEntry::ReservedMemory(region) = entry else {
+            continue;
+        };
+
+        let start = region.base & !(PAGE_SIZE as u64 - 1);
+        let end = (region.limit | (PAGE_SIZE as u64 - 1)) + 1;
+
+        for scope in region.device_scopes() {
+            let Some(device) = resolve_scope(region.segment, &scope) else {
+                continue;
+            };
+            let Some(unit) = find_unit(&mut units, device) else {
+                continue;
+            };
+
+            let domain = unit.attach(device).map_err(|_| IommuError::OutOfMemory)?;
+            domain.reserve_iova(start, end);
+            domain
+                .map(start, PhyAddr::new(start), (end - start) as usize, true)
+                .map_err(|_| IommuError::OutOfMemory)?;
+        }
+    }
+
+    for unit in units.iter_mut() {
+        unit.enable()?;
+
+        info!(
+            "vtd",
+            "DMA remapping enabled (unit = {:#x}, levels = {}, passthrough = {})",
+            unit.register_base,
+            unit.levels,
+            unit.ecap & ECAP_PT != 0
+        );
+    }
+
+    IOMMU_ENABLED.store(true, Ordering::Release);
+    drop(units);
+
+    if timer::periodic(FAULT_POLL_INTERVAL, iommu_poll_faults).is_err() {
+        info!(
+            "vtd",
+            "timer queue unavailable, DMA faults are not reported"
+        );
+    }
+
+    Ok(())
+}
+
+/// Whether DMA remapping is enabled.
+pub fn iommu_enabled() -> bool {
+    IOMMU_ENABLED.load(Ordering::Acquire)
+}
+
+/// Logs the DMA translation faults recorded since the last poll.
+pub fn iommu_poll_faults() {
+    if let Some(units) = IOMMU_UNITS.try_lock() {
+        units.iter().for_each(RemappingUnit::report_faults);
+    }
+}
+
+/// Maps the `size` bytes of physical memory at `phys` in the domain of `device`, below the bus address `limit`.
+///
+/// Returns the bus address of the mapping, or `None` if the requests of `device` are not remapped.
+pub(crate) fn iommu_map(
+    device: DmaDevice,
+    phys: PhyAddr,
+    size: usize,
+    limit: u64,
+) -> Option<Result<u64, DmaError>> {
+    if !iommu_enabled() {
+        return None;
+    }
+
+    let mut units = IOMMU_UNITS.lock();
+
+    find_unit(&mut units, device).map(|unit| unit.map(device, phys, size, limit))
+}
+
+/// Removes a mapping created by [`iommu_map`].
+pub(crate) fn iommu_unmap(device: DmaDevice, iova: u64, size: usize) {
+    let mut units = IOMMU_UNITS.lock();
+    let Some(unit) = find_unit(&mut units, device) else {
+        return;
+    };
+    let Some(domain) = unit.domains.get_mut(&device) else {
+        return;
+    };
+
+    domain.unmap(iova, size);
+    domain.free_iova(iova, size as u64);
+
+    let domain_id = domain.id;
+    if unit.invalidate_domain(domain_id).is_err() {
+        error!("vtd", "IOTLB invalidation timed out");
+    }
+}
+
+/// Returns the unit whose scope includes `device`.
+fn find_unit(units: &mut [RemappingUnit], device: DmaDevice) -> Option<&mut RemappingUnit> {
+    let index = units
+        .iter()
+        .position(|unit| unit.covers(device))
+        .or_else(|| {
+            units
+                .iter()
+                .position(|unit| unit.include_all && unit.segment == device.segment)
+        })?;
+
+    units.get_mut(index)
+}
+
+/// Returns the PCI function designated by a device scope, following its path through the bridges.
+fn resolve_scope(segment: u16, scope: &DeviceScope) -> Option<DmaDevice> {
+    let mut bus = scope.start_bus;
+    let mut target: Option<DmaDevice> = None;
+
+    for (device, function) in scope.path() {
+        // Secondary bus number of the bridge of the previous hop.
+        if let Some(bridge) = target {
+            bus = (pci_read_long(bridge.bus, bridge.device, bridge.function, 0x18) >> 8) as u8;
+        }
+
+        target = Some(DmaDevice {
+            segment,
+            ..DmaDevice::new(bus, device, function)
+        });
+    }
+
+    target
+}
@@ -5,15 +5,15 @@
 
 use crate::errors::IOError;
 use crate::fs::ext4::bitmap::{
-    BlockBitmap, BlockBitmapChksumHi, BlockBitmapChksumLo, InodeBitmap, InodeBitmapChksumHi,
-    InodeBitmapChksumLo,
+    BlockBitmap, BlockBitmapChksum, BlockBitmapChksumHi, BlockBitmapChksumLo, InodeBitmap,
+    InodeBitmapChksum, InodeBitmapChksumHi, InodeBitmapChksumLo,
 };
 use crate::fs::ext4::extent::{Ext4RealBlkId, Ext4RealBlkId32};
 use crate::fs::ext4::inode::{InodeCount, InodeCount16};
 use crate::fs::ext4::sb::{
     Ext4BlkCount, Ext4BlkCount16, Ext4FsUuid, Ext4Superblock, IncompatibleFeatureSet,
 };
-use crate::fs::ext4::{crc32c_calc, LockedExt4Fs, WeakLockedExt4Fs};
+use crate::fs::ext4::{cache_limits, crc32c_calc, LockedExt4Fs, WeakLockedExt4Fs};
 use crate::fs::IOResult;
 use crate::time::{current_timestamp, UnixTimestamp};
 use crate::{error, ext4_flag_field, ext4_uint_field_range};
@@ -415,6 +415,21 @@ impl Ext4GroupDescriptor {
     pub(crate) fn unused_inodes_count(&self) -> InodeCount {
         self.itable_unused_lo.add_high_bits(self.itable_unused_hi)
     }
+
+    /// Returns the flags of this block group.
+    pub(crate) fn flags(&self) -> GroupDescriptorFlags {
+        self.flags
+    }
+
+    /// Returns the on-disk checksum of the [`BlockBitmap`] associated to this block group.
+    pub(super) fn block_bitmap_chksum(&self) -> BlockBitmapChksum {
+        self.block_bitmap_csum_lo + self.block_bitmap_csum_hi
+    }
+
+    /// Returns the on-disk checksum of the [`InodeBitmap`] associated to this block group.
+    pub(super) fn inode_bitmap_chksum(&self) -> InodeBitmapChksum {
+        self.inode_bitmap_csum_lo + self.inode_bitmap_csum_hi
+    }
 }
 
 pub(super) type LockedGroupDescriptor = Arc<RwLock<GroupDescriptor>>;
@@ -429,12 +444,24 @@ pub(super) struct GroupDescriptorCacheEntry {
 
     /// First time this entry was accessed.
     pub(super) first_access: UnixTimestamp,
+
+    /// Value of the cache access clock the last time this entry was accessed, used for LRU eviction.
+    pub(super) last_access: u64,
 }
 
+/// Caches the block group descriptors of an `ext4` filesystem.
+///
+/// The number of entries is bounded by [`Ext4CacheLimits::max_group_descriptors`], least-recently used entries that
+/// are not referenced outside of the cache are evicted first.
+///
+/// [`Ext4CacheLimits::max_group_descriptors`]: crate::fs::ext4::Ext4CacheLimits
 #[derive(Debug)]
 pub(super) struct GroupDescriptorCache {
     pub(super) descriptor_table: HashMap<BlockGroupNumber, GroupDescriptorCacheEntry>,
 
+    /// Monotonic counter, incremented on every cache access.
+    pub(super) access_clock: u64,
+
     pub(super) fs: WeakLockedExt4Fs,
 }
 
@@ -443,18 +470,28 @@ impl GroupDescriptorCache {
         &mut self,
         bg_number: BlockGroupNumber,
     ) -> Option<LockedGroupDescriptor> {
+        self.access_clock += 1;
+
         if let Some(bg_desc_cache_entry) = self.descriptor_table.get_mut(&bg_number) {
             bg_desc_cache_entry
                 .usage_count
                 .fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+            bg_desc_cache_entry.last_access = self.access_clock;
             return Some(bg_desc_cache_entry.group_descriptor.clone());
         }
 
         let bg_desc = self.load_group_descriptor_from_raw(bg_number).ok()?;
+
+        let max_entries = cache_limits().max_group_descriptors;
+        if self.descriptor_table.len() >= max_entries {
+            self.evict_lru(self.descriptor_table.len() + 1 - max_entries);
+        }
+
         let bg_desc_cache_entry = GroupDescriptorCacheEntry {
             group_descriptor: bg_desc.clone(),
             usage_count: AtomicU32::default(),
             first_access: current_timestamp(),
+            last_access: self.access_clock,
         };
 
         self.descriptor_table.insert(bg_number, bg_desc_cache_entry);
@@ -473,6 +510,41 @@ impl GroupDescriptorCache {
         self.descriptor_table.shrink_to(0);
     }
 
+    /// Approximate amount of memory used by a single cache entry, in bytes.
+    ///
+    /// This does not account for the inode and block bitmaps, which may be loaded on demand.
+    pub(super) const ENTRY_SIZE: usize = mem::size_of::<GroupDescriptor>()
+        + mem::size_of::<GroupDescriptorCacheEntry>()
+        + mem::size_of::<BlockGroupNumber>();
+
+    /// Evicts up to `count` entries from the cache, least-recently used first.
+    ///
+    /// Entries that are still referenced outside of the cache are never evicted. Returns the number of entries that
+    /// were evicted.
+    ///
+    /// This does not allocate memory, so that it can be used to release memory when the system is running out of it.
+    pub(super) fn evict_lru(&mut self, count: usize) -> usize {
+        let mut evicted = 0;
+
+        while evicted < count {
+            let lru_entry = self
+                .descriptor_table
+                .iter()
+                .filter(|(_, entry)| Arc::strong_count(&entry.group_descriptor) == 1)
+                .min_by_key(|(_, entry)| entry.last_access)
+                .map(|(&bg_number, _)| bg_number);
+
+            let Some(bg_number) = lru_entry else {
+                break;
+            };
+
+            self.descriptor_table.remove(&bg_number);
+            evicted += 1;
+        }
+
+        evicted
+    }
+
     fn load_group_descriptor_from_raw(
         &self,
         bg_number: BlockGroupNumber,
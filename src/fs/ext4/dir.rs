@@ -3,22 +3,23 @@
 //! Provides methods for loading and parsing directories, as defined by the `ext4` filesystem.
 //! Serves as as interface between the `ext4` definition of a directory and the abstract implementation in `FrozenBoot`
 
-use core::slice;
+use core::cell::RefCell;
 
 use alloc::boxed::Box;
 use alloc::{format, string::String, vec::Vec};
-use bytemuck::{cast, from_bytes, try_cast, Pod, Zeroable};
+use bytemuck::{cast, from_bytes, Pod, Zeroable};
 
-use crate::fs::ext4::file::Ext4File;
+use crate::fs::ext4::file::{Ext4File, ReadaheadBuffer};
+use crate::fs::ext4::htree::{self, DxHashInfo};
 use crate::fs::ext4::inode::{InodeFlags, InodeType, LockedInode, LockedInodeStrongRef};
+use crate::fs::ext4::sb::CompatibleFeatureSet;
 use crate::fs::ext4::LockedExt4Fs;
-use crate::fs::{DirEntry, Directory, FsDirectory};
+use crate::fs::{DirEntry, Directory, FsDirectory, Metadata};
 use crate::{
     errors::{CanFail, IOError},
     ext4_fs_read_bytes,
     fs::{
         ext4::{
-            extent::{Ext4InodeRelBlkId, Ext4InodeRelBlkIdRange},
             inode::{InodeFileMode, InodeNumber, InodeSize},
             ExtentTree,
         },
@@ -153,12 +154,15 @@ pub struct Ext4Filename(pub Vec<u8>);
 
 impl Ext4Filename {
     /// Returns an [`Iterator`] over the characters of the file name.
+    ///
+    /// File names are decoded as UTF-8, invalid sequences being replaced by `U+FFFD`.
     pub fn chars(&self) -> impl Iterator<Item = char> {
-        self.0
-            .clone()
+        let name: Vec<u8> = self.0.iter().copied().filter(|&b| b != 0).collect();
+
+        String::from_utf8_lossy(&name)
+            .chars()
+            .collect::<Vec<char>>()
             .into_iter()
-            .filter(|&b| b != 0)
-            .map(char::from)
     }
 }
 
@@ -170,7 +174,7 @@ impl From<Ext4Filename> for String {
 
 impl From<&str> for Ext4Filename {
     fn from(value: &str) -> Self {
-        Self(value.chars().map(|ch| u8::try_from(ch).unwrap()).collect())
+        Self(value.as_bytes().to_vec())
     }
 }
 
@@ -181,6 +185,7 @@ pub(crate) struct Ext4Directory {
     fs: LockedExt4Fs,
     internal_cursor: usize,
     extent_tree: Option<ExtentTree>,
+    readahead: RefCell<ReadaheadBuffer>,
 }
 
 impl core::fmt::Debug for Ext4Directory {
@@ -206,52 +211,44 @@ impl Iterator for Ext4Directory {
     type Item = Ext4DirectoryEntry;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let inode = self.inode.read();
-        let mut raw_entry = [0u8; Ext4DirectoryEntry::MAX_ENTRY_SIZE];
-        let count_to_read = u64::min(
-            cast(inode.size() - self.internal_cursor.try_into().ok()?),
-            u64::try_from(Ext4DirectoryEntry::MAX_ENTRY_SIZE)
-                .expect("invalid directory entry size"),
-        )
-        .try_into()
-        .ok()?;
-
-        if count_to_read <= 8 {
-            self.internal_cursor = 0;
-            return None;
-        }
-
-        unsafe {
-            self.ext4_read_bytes(self.internal_cursor, count_to_read, &mut raw_entry)
-                .ok()?;
-        }
+        loop {
+            let inode = self.inode.read();
+            let mut raw_entry = [0u8; Ext4DirectoryEntry::MAX_ENTRY_SIZE];
+            let count_to_read = u64::min(
+                cast(inode.size() - self.internal_cursor.try_into().ok()?),
+                u64::try_from(Ext4DirectoryEntry::MAX_ENTRY_SIZE)
+                    .expect("invalid directory entry size"),
+            )
+            .try_into()
+            .ok()?;
+
+            if count_to_read <= 8 {
+                self.internal_cursor = 0;
+                return None;
+            }
 
-        let inode_number: InodeNumber = *from_bytes(&raw_entry[..4]);
-        let rec_len = u16::from_le_bytes(raw_entry[4..6].try_into().ok()?);
-        let name_len = raw_entry[6];
-        let file_type: Option<Ext4DirectoryFileType> = Some(*from_bytes(&[raw_entry[7]]));
-        let raw_name: Vec<u8> = raw_entry[8..8 + usize::from(name_len)].to_vec();
+            unsafe {
+                self.ext4_read_bytes(self.internal_cursor, count_to_read, &mut raw_entry)
+                    .ok()?;
+            }
 
-        if inode_number == InodeNumber::UNUSED_DIR_ENTRY {
-            self.internal_cursor = 0;
-            return None;
-        }
+            let rec_len = u16::from_le_bytes(raw_entry[4..6].try_into().ok()?);
 
-        let name = Ext4Filename(raw_name);
+            if rec_len < 8 {
+                self.internal_cursor = 0;
+                return None;
+            }
 
-        self.internal_cursor = usize::min(
-            self.internal_cursor + usize::from(rec_len),
-            usize::try_from(cast::<InodeSize, u64>(inode.size())).expect("invalid inode size"),
-        );
+            self.internal_cursor = usize::min(
+                self.internal_cursor + usize::from(rec_len),
+                usize::try_from(cast::<InodeSize, u64>(inode.size())).expect("invalid inode size"),
+            );
 
-        Some(Ext4DirectoryEntry {
-            fs: self.fs.clone(),
-            rec_len,
-            name_len,
-            file_type,
-            name,
-            inode_number,
-        })
+            // Unused entries (deleted files, or internal nodes of the hashed index) are skipped.
+            if let Some(entry) = self.parse_entry(&raw_entry[..count_to_read]) {
+                return Some(entry);
+            }
+        }
     }
 }
 
@@ -282,17 +279,113 @@ impl FsDirectory for GenericExt4Directory {
         let inode = self.dir.inode.read();
         Ok(usize::try_from(cast::<InodeSize, u64>(inode.size())).expect("invalid file size"))
     }
+
+    fn metadata(&self) -> IOResult<Metadata> {
+        Ok(self.dir.inode.read().metadata())
+    }
 }
 
 impl Ext4Directory {
     /// Search this directory for a given [`Ext4Filename`].
     ///
     /// Returns the corresponding entry if available.
+    /// Directories with a hashed index (`EXT4_INDEX_FL`) are searched through the index, which only reads the blocks
+    /// that may contain the name. Other directories, or directories whose index is corrupted, are scanned linearly.
     #[allow(clippy::needless_pass_by_value)]
     pub(crate) fn search(&mut self, name: Ext4Filename) -> Option<Ext4DirectoryEntry> {
+        if self.is_indexed() {
+            if let Ok(entry) = self.dx_search(&name) {
+                return entry;
+            }
+        }
+
+        self.internal_cursor = 0;
         self.find(|entry| entry.name == name)
     }
 
+    /// Checks whether this directory has a hashed index, that can be used by the filesystem.
+    fn is_indexed(&self) -> bool {
+        let fs = self.fs.read();
+        let sb = fs.superblock.read();
+
+        sb.feature_compat
+            .includes(CompatibleFeatureSet::EXT4_FEATURE_COMPAT_DIR_INDEX)
+            && self.inode.read().has_flag(InodeFlags::EXT4_INDEX_FL)
+    }
+
+    /// Searches this directory for `name` through its hashed index.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::InvalidCommand`] if the index cannot be used, or any I/O error that happened while reading
+    /// the directory blocks.
+    fn dx_search(&self, name: &Ext4Filename) -> IOResult<Option<Ext4DirectoryEntry>> {
+        let fs = self.fs.read();
+        let sb = fs.superblock.read();
+        let blk_size = usize::try_from(sb.blk_size()).expect("invalid ext4fs block size");
+        let info = DxHashInfo::new(&sb);
+        drop(sb);
+        drop(fs);
+
+        let read_blk = |blk: u64, buf: &mut [u8]| {
+            let offset = usize::try_from(blk)
+                .ok()
+                .and_then(|blk| blk.checked_mul(blk_size))
+                .ok_or(IOError::InvalidCommand)?;
+
+            unsafe { self.ext4_read_bytes(offset, blk_size, buf) }
+        };
+
+        let mut data = alloc::vec![0; blk_size];
+
+        for blk in htree::dx_lookup(&name.0, info, blk_size, read_blk)? {
+            read_blk(blk, &mut data)?;
+
+            let mut offset = 0;
+            while offset + 8 <= blk_size {
+                let rec_len = usize::from(u16::from_le_bytes([data[offset + 4], data[offset + 5]]));
+
+                if rec_len < 8 {
+                    break;
+                }
+
+                if let Some(entry) = self.parse_entry(&data[offset..]) {
+                    if entry.name == *name {
+                        return Ok(Some(entry));
+                    }
+                }
+
+                offset += rec_len;
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Parses the directory entry at the beginning of `raw_entry`.
+    ///
+    /// Returns `None` if the entry is unused, or truncated.
+    fn parse_entry(&self, raw_entry: &[u8]) -> Option<Ext4DirectoryEntry> {
+        let inode_number: InodeNumber = *from_bytes(raw_entry.get(..4)?);
+        let rec_len = u16::from_le_bytes(raw_entry.get(4..6)?.try_into().ok()?);
+        let name_len = *raw_entry.get(6)?;
+        let file_type: Option<Ext4DirectoryFileType> = Some(*from_bytes(&[*raw_entry.get(7)?]));
+        let raw_name: Vec<u8> = raw_entry.get(8..8 + usize::from(name_len))?.to_vec();
+
+        if inode_number == InodeNumber::UNUSED_DIR_ENTRY {
+            return None;
+        }
+
+        Some(Ext4DirectoryEntry {
+            fs: self.fs.clone(),
+            rec_len,
+            name_len,
+            file_type,
+            name: Ext4Filename(raw_name),
+            inode_number,
+        })
+    }
+
     /// Loads a `Ext4Directory` from disk, from its [`InodeNumber`].
     ///
     /// # Errors
@@ -332,6 +425,7 @@ impl Ext4Directory {
             fs: inode_fs_ptr,
             internal_cursor: 0,
             extent_tree,
+            readahead: RefCell::default(),
         })
     }
 
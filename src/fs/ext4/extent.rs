@@ -181,7 +181,7 @@ fn traverse_extent_layer(
 
         let extent_blk = ExtentBlock(data);
         extent_blk.validate_chksum(sb.uuid, inode.number, inode.generation());
-        traverse_extent_layer(fs, &extent_blk, extents, inode);
+        traverse_extent_layer(fs, &extent_blk, extents, inode)?;
     }
 
     Some(())
@@ -207,7 +207,8 @@ impl ExtentTree {
         let extent_blk = inode.i_block.as_extent_block();
         drop(sb);
 
-        traverse_extent_layer(fs.deref(), &extent_blk, &mut extents, inode.deref());
+        // A missing part of the tree would make its blocks read as holes.
+        traverse_extent_layer(fs.deref(), &extent_blk, &mut extents, inode.deref())?;
         extents.sort_unstable();
         drop(inode);
 
@@ -216,26 +217,86 @@ impl ExtentTree {
             locked_inode,
         })
     }
+}
+
+/// Location of the data of logical blocks of an [`Ext4Inode`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BlockMapping {
+    /// The blocks are stored on disk, starting at this physical block.
+    Mapped(Ext4RealBlkId),
+
+    /// The blocks are not backed by initialized data on disk (holes in sparse files, or uninitialized extents), and
+    /// must be read as zeros.
+    Hole,
+}
+
+/// A run of logical blocks of an [`Ext4Inode`] that are physically contiguous on disk, and can therefore be read
+/// using a single disk request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct BlockRun {
+    /// Location of the blocks of the run.
+    pub(crate) mapping: BlockMapping,
 
-    /// Returns the physical block address corresponding to a logical block for this [`Ext4Inode`].
-    pub(crate) fn get_exact_blk_mapping(&self, blk_id: Ext4InodeRelBlkId) -> Option<Ext4RealBlkId> {
-        let ext_id = self
-            .extents
-            .binary_search_by(|ext| {
-                if ext.contains(blk_id) {
-                    return Ordering::Equal;
-                } else if ext.block > blk_id {
-                    return Ordering::Greater;
-                }
+    /// Number of blocks in the run.
+    pub(crate) count: u64,
+}
 
-                Ordering::Less
-            })
-            .ok()?;
+impl ExtentTree {
+    /// Returns the longest run of physically contiguous blocks starting at the logical block `blk_id`, containing at
+    /// most `max_count` blocks.
+    ///
+    /// Adjacent extents are merged into a single run if they are also contiguous on disk.
+    pub(crate) fn blk_run(&self, blk_id: Ext4InodeRelBlkId, max_count: u64) -> BlockRun {
+        let blk = blk_id.0;
+
+        // Extents are sorted and do not overlap: this is the first extent that ends past `blk`.
+        let ext_id = self.extents.partition_point(|ext| ext.logical_end() <= blk);
+
+        let Some(&extent) = self.extents.get(ext_id) else {
+            // Past the last extent of the file.
+            return BlockRun {
+                mapping: BlockMapping::Hole,
+                count: max_count,
+            };
+        };
 
-        let extent = self.extents.get(ext_id)?;
-        let offset_in_extent = blk_id - extent.block;
+        let first = u64::from(extent.block.0);
 
-        Some(extent.start_blk() + offset_in_extent)
+        if first > blk {
+            return BlockRun {
+                mapping: BlockMapping::Hole,
+                count: u64::min(first - blk, max_count),
+            };
+        }
+
+        let mut count = u64::min(extent.logical_end() - blk, max_count);
+
+        if !extent.len.is_initialized() {
+            return BlockRun {
+                mapping: BlockMapping::Hole,
+                count,
+            };
+        }
+
+        let mut prev = extent;
+
+        for next in &self.extents[ext_id + 1..] {
+            if count >= max_count
+                || !next.len.is_initialized()
+                || u64::from(next.block.0) != prev.logical_end()
+                || next.start_blk() != prev.start_blk() + u64::from(prev.len.length())
+            {
+                break;
+            }
+
+            count = u64::min(count + u64::from(next.len.length()), max_count);
+            prev = *next;
+        }
+
+        BlockRun {
+            mapping: BlockMapping::Mapped(extent.start_blk() + (blk - first)),
+            count,
+        }
     }
 }
 
@@ -564,8 +625,9 @@ impl Extent {
         self.start_lo + self.start_hi
     }
 
-    pub(crate) fn contains(&self, blk_id: Ext4InodeRelBlkId) -> bool {
-        self.block <= blk_id && self.block + self.len >= blk_id
+    /// Returns the first logical block past the end of this extent.
+    fn logical_end(&self) -> u64 {
+        u64::from(self.block.0) + u64::from(self.len.length())
     }
 }
 
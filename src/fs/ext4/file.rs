@@ -4,15 +4,21 @@
 //! Serves as as interface between the `ext4` definition of a file and the abstract implementation in `FrozenBoot`
 
 use crate::errors::{CanFail, IOError};
-use crate::fs::ext4::extent::{Ext4InodeRelBlkId, Ext4InodeRelBlkIdRange, ExtentTree};
+use crate::fs::ext4::extent::{BlockMapping, BlockRun, ExtentTree};
 use crate::fs::ext4::inode::{
     InodeFileMode, InodeFlags, InodeNumber, InodeSize, LockedInode, LockedInodeStrongRef,
 };
-use crate::fs::ext4::LockedExt4Fs;
-use crate::fs::{FsFile, IOResult, Seek};
+use crate::fs::ext4::{Ext4Fs, LockedExt4Fs};
+use crate::fs::mount::FileRef;
+use crate::fs::pagecache::{self, PageKey};
+use crate::fs::{FsFile, IOResult, Metadata, Seek};
+use crate::kernel_syms::PAGE_SIZE;
+use crate::mem::PhyAddr;
+use alloc::boxed::Box;
 use alloc::format;
-use bytemuck::{cast, try_cast};
-use core::slice;
+use alloc::vec::Vec;
+use bytemuck::cast;
+use core::cell::RefCell;
 
 /// Representation of a file in the `ext4` filesystem.
 pub(crate) struct Ext4File {
@@ -20,6 +26,13 @@ pub(crate) struct Ext4File {
     inode: LockedInodeStrongRef,
     cursor: usize,
     extent_tree: Option<ExtentTree>,
+    readahead: RefCell<ReadaheadBuffer>,
+
+    /// Content of the file, if it is stored in the inode rather than in data blocks.
+    inline_data: Option<Vec<u8>>,
+
+    /// Keeps the filesystem from being unmounted while the file is open.
+    _open: FileRef,
 }
 
 impl core::fmt::Debug for Ext4File {
@@ -41,9 +54,83 @@ impl core::fmt::Debug for Ext4File {
     }
 }
 
+/// Maximum number of bytes read from the disk at once, when reading a file or a directory.
+pub(crate) const MAX_READ_RUN_SIZE: usize = 0x10_0000;
+
+/// Holds the blocks read during the last disk access of a file or directory, including the blocks read ahead of the
+/// requested range.
+///
+/// Subsequent reads are served from this buffer whenever possible, which avoids issuing a disk request for every small
+/// sequential read (directory entries, for instance).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ReadaheadBuffer {
+    first_blk: u64,
+    blk_count: u64,
+    data: Vec<u8>,
+}
+
+impl ReadaheadBuffer {
+    /// Checks whether the logical block `blk` is currently held in the buffer.
+    pub(crate) fn contains(&self, blk: u64) -> bool {
+        blk >= self.first_blk && blk < self.first_blk + self.blk_count
+    }
+
+    /// Replaces the content of the buffer by the blocks described by `run`, starting at the logical block `first_blk`.
+    ///
+    /// # Errors
+    ///
+    /// May return any variant of [`IOError`] in case of a failure while reading from disk. The buffer is left empty.
+    pub(crate) fn fill(
+        &mut self,
+        fs: &Ext4Fs,
+        first_blk: u64,
+        run: BlockRun,
+        blk_size: usize,
+    ) -> CanFail<IOError> {
+        let size = usize::try_from(run.count).expect("invalid block count") * blk_size;
+
+        self.blk_count = 0;
+        self.data.clear();
+        self.data
+            .try_reserve_exact(size)
+            .map_err(|e| IOError::Exception(Box::new(e)))?;
+        self.data.resize(size, 0);
+
+        if let BlockMapping::Mapped(start) = run.mapping {
+            fs.read_blks_from_device(start, run.count, &mut self.data)?;
+        }
+
+        self.first_blk = first_blk;
+        self.blk_count = run.count;
+
+        Ok(())
+    }
+
+    /// Copies bytes from the buffer into `buf`, starting at the byte `offset` (relative to the beginning of the file).
+    ///
+    /// Returns the number of bytes copied. The block containing `offset` must be held in the buffer.
+    pub(crate) fn copy_to(&self, offset: usize, buf: &mut [u8], blk_size: usize) -> usize {
+        let first_byte = usize::try_from(self.first_blk).expect("invalid block number") * blk_size;
+        let available = &self.data[offset - first_byte..];
+        let count = usize::min(available.len(), buf.len());
+
+        buf[..count].copy_from_slice(&available[..count]);
+
+        count
+    }
+}
+
 /// Implements a method to read bytes associated to a `ext4` structure (such as a file, or a directory).
 ///
-/// File reads and directory enumeration are based on this method.
+/// File reads and directory enumeration are based on this method. The structure must have a `fs`, an `extent_tree`
+/// and a `readahead` (`RefCell<ReadaheadBuffer>`) field.
+///
+/// Physically contiguous blocks are read using a single disk request. Whole blocks are transferred directly into the
+/// caller's buffer, while partial blocks go through a [`ReadaheadBuffer`], which also keeps up to
+/// [`readahead_window`] blocks past the requested range in memory for subsequent reads. Blocks that are not backed
+/// by data on disk (holes of sparse files, uninitialized extents, or blocks past the last extent) are read as zeros.
+///
+/// [`readahead_window`]: crate::fs::ext4::readahead_window
 #[macro_export]
 macro_rules! ext4_fs_read_bytes {
     () => {
@@ -54,83 +141,70 @@ macro_rules! ext4_fs_read_bytes {
             buf: &mut [u8],
         ) -> CanFail<IOError> {
             let fs = self.fs.read();
-            let sb = fs.superblock.read();
-
-            let blk_offset_from_file_start: Ext4InodeRelBlkId =
-                try_cast(u64::try_from(offset).expect("invalid byte offset") / sb.blk_size())
-                    .map_err(|_| IOError::Unknown)?;
-            let blk_offset_from_first_blk =
-                offset % usize::try_from(sb.blk_size()).expect("invalid ext4fs block size");
-            let last_blk: Ext4InodeRelBlkId = try_cast(
-                u64::try_from(offset + count).expect("invalid byte offset") / sb.blk_size(),
-            )
-            .map_err(|_| IOError::Unknown)?;
-            let last_blk_count =
-                count % usize::try_from(sb.blk_size()).expect("invalid ext4fs block size");
-
-            if let Some(ext_tree) = &self.extent_tree {
-                let mut useful_extents = ext_tree.extents.iter().filter(|ext| {
-                    (cast(ext.block)..ext.block + ext.len).contains(&(blk_offset_from_file_start))
-                });
-                let mut curr_extent = useful_extents.next().unwrap();
-
-                for i in Ext4InodeRelBlkIdRange(
-                    blk_offset_from_file_start,
-                    Ext4InodeRelBlkId::min(cast(0_u64), last_blk - 1),
-                ) {
-                    if (curr_extent.block + curr_extent.len) < i {
-                        curr_extent = useful_extents.next().unwrap();
+            let blk_size = usize::try_from(fs.superblock.read().blk_size())
+                .expect("invalid ext4fs block size");
+
+            if count == 0 {
+                return Ok(());
+            }
+
+            // Without an extent tree, the location of the data is unknown: reading it as zeros would silently return
+            // wrong content.
+            let Some(ext_tree) = &self.extent_tree else {
+                return Err(IOError::InvalidCommand);
+            };
+
+            let mut readahead = self.readahead.borrow_mut();
+            let max_run_blks = u64::try_from(usize::max(
+                $crate::fs::ext4::file::MAX_READ_RUN_SIZE / blk_size,
+                1,
+            ))
+            .expect("invalid block count");
+            let last_blk =
+                u64::try_from((offset + count - 1) / blk_size).expect("invalid byte offset");
+
+            let mut pos = offset;
+            let mut buf_pos = 0;
+
+            while buf_pos < count {
+                let blk = u64::try_from(pos / blk_size).expect("invalid byte offset");
+                let full_blks =
+                    u64::try_from((count - buf_pos) / blk_size).expect("invalid block count");
+
+                // Large reads of whole blocks are transferred straight into the caller's buffer, smaller reads go
+                // through the readahead buffer.
+                if !readahead.contains(blk)
+                    && pos % blk_size == 0
+                    && full_blks != 0
+                    && full_blks >= $crate::fs::ext4::readahead_window()
+                {
+                    let run = ext_tree.blk_run(cast(blk), u64::min(full_blks, max_run_blks));
+                    let run_size =
+                        usize::try_from(run.count).expect("invalid block count") * blk_size;
+                    let dest = &mut buf[buf_pos..buf_pos + run_size];
+
+                    match run.mapping {
+                        $crate::fs::ext4::extent::BlockMapping::Mapped(start) => {
+                            fs.read_blks_from_device(start, run.count, dest)?;
+                        }
+                        $crate::fs::ext4::extent::BlockMapping::Hole => dest.fill(0),
                     }
-                    fs.read_blk_from_device(
-                        try_cast(curr_extent.start_blk() + i).map_err(|_| IOError::Unknown)?,
-                        slice::from_raw_parts_mut(
-                            buf.as_mut_ptr().add(
-                                (try_cast::<Ext4InodeRelBlkId, u64>(i)
-                                    .map_err(|_| IOError::Unknown)?
-                                    * sb.blk_size())
-                                .try_into()
-                                .expect("invalid inode number"),
-                            ),
-                            sb.blk_size().try_into().expect("invalid fs block size"),
-                        ),
-                    )?;
-                }
 
-                if (curr_extent.block + curr_extent.len) < last_blk {
-                    curr_extent = useful_extents.next().unwrap();
+                    pos += run_size;
+                    buf_pos += run_size;
+                    continue;
                 }
 
-                let mut temp_buf = fs.allocate_blk();
-                fs.read_blk_from_device(
-                    try_cast(curr_extent.start_blk() + last_blk).map_err(|_| IOError::Unknown)?,
-                    &mut temp_buf,
-                )?;
-
-                if last_blk == cast::<u64, Ext4InodeRelBlkId>(0) {
-                    slice::from_raw_parts_mut(
-                        buf.as_mut_ptr().add(
-                            (try_cast::<Ext4InodeRelBlkId, u64>(last_blk)
-                                .map_err(|_| IOError::Unknown)?
-                                * sb.blk_size())
-                            .try_into()
-                            .expect("invalid inode number"),
-                        ),
-                        last_blk_count - blk_offset_from_first_blk,
-                    )
-                    .copy_from_slice(&temp_buf[blk_offset_from_first_blk..last_blk_count]);
-                } else {
-                    slice::from_raw_parts_mut(
-                        buf.as_mut_ptr().add(
-                            (try_cast::<Ext4InodeRelBlkId, u64>(last_blk)
-                                .map_err(|_| IOError::Unknown)?
-                                * sb.blk_size())
-                            .try_into()
-                            .expect("invalid inode number"),
-                        ),
-                        last_blk_count,
-                    )
-                    .copy_from_slice(&temp_buf[..last_blk_count]);
+                if !readahead.contains(blk) {
+                    let wanted = u64::max(last_blk - blk + 1, $crate::fs::ext4::readahead_window());
+                    let run = ext_tree.blk_run(cast(blk), u64::min(wanted, max_run_blks));
+
+                    readahead.fill(&fs, blk, run, blk_size)?;
                 }
+
+                let copied = readahead.copy_to(pos, &mut buf[buf_pos..count], blk_size);
+                pos += copied;
+                buf_pos += copied;
             }
 
             Ok(())
@@ -171,8 +245,10 @@ impl Ext4File {
             return Err(IOError::Unknown);
         }
 
+        let inline_data = inode.inline_data();
         drop(inode);
 
+        let open = locked_fs.read().open_files().acquire();
         let extent_tree = ExtentTree::load_extent_tree(locked_fs, inode_ptr.clone());
 
         Ok(Self {
@@ -180,17 +256,84 @@ impl Ext4File {
             inode: inode_ptr,
             cursor: 0,
             extent_tree,
+            readahead: RefCell::default(),
+            inline_data,
+            _open: open,
         })
     }
 
+    /// Identifies the page `index` of this file in the page cache.
+    fn page_key(&self, index: u64) -> PageKey {
+        PageKey {
+            fs: self.fs.read().page_cache_id(),
+            inode: u64::from(u32::from(self.inode.read().number)),
+            index,
+        }
+    }
+
+    /// Reads the page `index` of this file from the disk into `page`.
+    ///
+    /// The part of the page past the end of the file is left untouched.
+    fn fill_page(&self, index: u64, page: &mut [u8]) -> CanFail<IOError> {
+        let offset = usize::try_from(index)
+            .ok()
+            .and_then(|index| index.checked_mul(PAGE_SIZE))
+            .ok_or(IOError::InvalidCommand)?;
+        let size = self.size()?;
+
+        if offset >= size {
+            return Ok(());
+        }
+
+        self.read_bytes(offset, usize::min(PAGE_SIZE, size - offset), page)
+    }
+
+    /// Reads `count` bytes of the file starting at byte `offset` into `buf`, from the inode if the file is stored
+    /// inline, or from its data blocks.
+    fn read_bytes(&self, offset: usize, count: usize, buf: &mut [u8]) -> CanFail<IOError> {
+        if let Some(data) = &self.inline_data {
+            let src = data
+                .get(offset..offset + count)
+                .ok_or(IOError::InvalidCommand)?;
+            buf[..count].copy_from_slice(src);
+
+            return Ok(());
+        }
+
+        unsafe { self.ext4_read_bytes(offset, count, buf) }
+    }
+
+    /// Fills `buf` with the content of the file starting at byte `offset`, through the page cache.
+    fn read_cached(&self, offset: usize, buf: &mut [u8]) -> CanFail<IOError> {
+        let mut pos = 0;
+
+        while pos < buf.len() {
+            let index = u64::try_from((offset + pos) / PAGE_SIZE).expect("invalid byte offset");
+
+            pos += pagecache::read(
+                self.page_key(index),
+                (offset + pos) % PAGE_SIZE,
+                &mut buf[pos..],
+                |page| self.fill_page(index, page),
+            )?;
+        }
+
+        Ok(())
+    }
+
     ext4_fs_read_bytes!();
 }
 
 impl FsFile for Ext4File {
     fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
         let bytes_count = usize::min(buf.len(), self.size()? - self.cursor);
+        let buf = &mut buf[..bytes_count];
 
-        unsafe { self.ext4_read_bytes(self.cursor, bytes_count, buf)? };
+        // Without a page cache (when physical memory cannot be allocated, as in the bootloader), the file is read
+        // straight from the disk.
+        if self.read_cached(self.cursor, buf).is_err() {
+            self.read_bytes(self.cursor, bytes_count, buf)?;
+        }
         self.seek(Seek::Forward(bytes_count));
 
         Ok(bytes_count)
@@ -215,6 +358,10 @@ impl FsFile for Ext4File {
         Ok(usize::try_from(cast::<InodeSize, u64>(inode.size())).expect("invalid file size"))
     }
 
+    fn metadata(&self) -> IOResult<Metadata> {
+        Ok(self.inode.read().metadata())
+    }
+
     fn truncate(&mut self, size: usize) -> IOResult<usize> {
         todo!()
     }
@@ -222,4 +369,11 @@ impl FsFile for Ext4File {
     fn extend(&mut self, size: usize) -> IOResult<usize> {
         todo!()
     }
+
+    fn map_page(&mut self, index: u64) -> IOResult<(PageKey, PhyAddr)> {
+        let key = self.page_key(index);
+        let frame = pagecache::get_or_fill(key, true, |page| self.fill_page(index, page))?;
+
+        Ok((key, frame))
+    }
 }
@@ -0,0 +1,617 @@
+//! Read-only consistency check of `ext4` filesystems (`fsck`).
+//!
+//! The check covers the metadata that the driver relies on:
+//!
+//! - checksums of the superblock, of the block group descriptors and of the bitmaps (when the filesystem uses
+//!   `metadata_csum`).
+//! - free block and free inode counts of each block group, compared to their bitmaps.
+//! - directory linkage: starting from the root directory, every entry must point to an inode in use, `.` and `..`
+//!   must point to the directory itself and to its parent, and no directory may be reachable twice. The number of
+//!   directories found in each block group is compared to its descriptor.
+//!
+//! Nothing is repaired, and nothing is written to the disk: problems are only reported. Descriptors and inodes are
+//! loaded through the metadata caches of the filesystem, bitmaps are read straight from the disk.
+
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
+use bytemuck::cast;
+use core::fmt::{self, Display, Write};
+
+use crate::drivers::devtree::partition_name;
+use crate::fs::ext4::block_grp::{BlockGroupNumber, GroupDescriptorFlags};
+use crate::fs::ext4::dir::{Ext4Directory, Ext4DirectoryFileType};
+use crate::fs::ext4::extent::{Ext4RealBlkId, Ext4RealBlkId32};
+use crate::fs::ext4::inode::{InodeCount, InodeFileMode, InodeNumber};
+use crate::fs::ext4::sb::{
+    Ext4BlkCount, Ext4BlkCount32, Ext4ChksumAlgorithm, IncompatibleFeatureSet,
+    ReadOnlyCompatibleFeatureSet,
+};
+use crate::fs::ext4::{crc32c_calc, Ext4Fs, MOUNTED_FILESYSTEMS};
+use crate::x86::tsc::TSC_CLK;
+
+/// File type bits of the inode mode.
+const INODE_MODE_TYPE_MASK: u16 = 0xF000;
+
+/// Inconsistency found by [`Ext4Fs::fsck`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum FsckProblem {
+    SuperblockChecksum,
+
+    /// A block group descriptor could not be read.
+    UnreadableDescriptor {
+        group: u32,
+    },
+
+    DescriptorChecksum {
+        group: u32,
+    },
+
+    /// A bitmap could not be read.
+    UnreadableBitmap {
+        group: u32,
+    },
+
+    BlockBitmapChecksum {
+        group: u32,
+    },
+
+    InodeBitmapChecksum {
+        group: u32,
+    },
+
+    /// The free block count of the descriptor does not match the block bitmap.
+    FreeBlocksCount {
+        group: u32,
+        descriptor: u64,
+        bitmap: u64,
+    },
+
+    /// The free inode count of the descriptor does not match the inode bitmap.
+    FreeInodesCount {
+        group: u32,
+        descriptor: u32,
+        bitmap: u32,
+    },
+
+    /// The directory count of the descriptor does not match the directories reachable from the root.
+    DirectoryCount {
+        group: u32,
+        descriptor: u32,
+        found: u32,
+    },
+
+    /// A directory could not be read.
+    UnreadableDirectory {
+        dir: u32,
+    },
+
+    /// An entry points to an inode that does not exist.
+    InvalidInode {
+        dir: u32,
+        name: String,
+        inode: u32,
+    },
+
+    /// An entry points to an inode marked free in the inode bitmap.
+    FreeInodeReferenced {
+        dir: u32,
+        name: String,
+        inode: u32,
+    },
+
+    InodeChecksum {
+        inode: u32,
+    },
+
+    /// The type of an entry does not match the mode of its inode.
+    FileTypeMismatch {
+        dir: u32,
+        name: String,
+        inode: u32,
+    },
+
+    /// The `.` entry of a directory does not point to the directory itself.
+    BadDotEntry {
+        dir: u32,
+        found: u32,
+    },
+
+    /// The `..` entry of a directory does not point to its parent.
+    BadDotDotEntry {
+        dir: u32,
+        parent: u32,
+        found: u32,
+    },
+
+    /// A directory is reachable from more than one entry.
+    DirectoryHardLink {
+        dir: u32,
+        name: String,
+        inode: u32,
+    },
+}
+
+impl Display for FsckProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::SuperblockChecksum => write!(f, "invalid superblock checksum"),
+            Self::UnreadableDescriptor { group } => {
+                write!(f, "group {group}: descriptor cannot be read")
+            }
+            Self::DescriptorChecksum { group } => {
+                write!(f, "group {group}: invalid descriptor checksum")
+            }
+            Self::UnreadableBitmap { group } => write!(f, "group {group}: bitmap cannot be read"),
+            Self::BlockBitmapChecksum { group } => {
+                write!(f, "group {group}: invalid block bitmap checksum")
+            }
+            Self::InodeBitmapChecksum { group } => {
+                write!(f, "group {group}: invalid inode bitmap checksum")
+            }
+            Self::FreeBlocksCount {
+                group,
+                descriptor,
+                bitmap,
+            } => write!(
+                f,
+                "group {group}: free blocks count is {descriptor}, bitmap has {bitmap}"
+            ),
+            Self::FreeInodesCount {
+                group,
+                descriptor,
+                bitmap,
+            } => write!(
+                f,
+                "group {group}: free inodes count is {descriptor}, bitmap has {bitmap}"
+            ),
+            Self::DirectoryCount {
+                group,
+                descriptor,
+                found,
+            } => write!(
+                f,
+                "group {group}: directory count is {descriptor}, {found} reachable"
+            ),
+            Self::UnreadableDirectory { dir } => write!(f, "directory {dir} cannot be read"),
+            Self::InvalidInode { dir, name, inode } => {
+                write!(
+                    f,
+                    "directory {dir}: entry '{name}' has invalid inode {inode}"
+                )
+            }
+            Self::FreeInodeReferenced { dir, name, inode } => {
+                write!(
+                    f,
+                    "directory {dir}: entry '{name}' points to free inode {inode}"
+                )
+            }
+            Self::InodeChecksum { inode } => write!(f, "inode {inode}: invalid checksum"),
+            Self::FileTypeMismatch { dir, name, inode } => write!(
+                f,
+                "directory {dir}: type of entry '{name}' does not match inode {inode}"
+            ),
+            Self::BadDotEntry { dir, found } => {
+                write!(f, "directory {dir}: '.' points to inode {found}")
+            }
+            Self::BadDotDotEntry { dir, parent, found } => write!(
+                f,
+                "directory {dir}: '..' points to inode {found} instead of {parent}"
+            ),
+            Self::DirectoryHardLink { dir, name, inode } => write!(
+                f,
+                "directory {dir}: entry '{name}' links to directory {inode}, already linked"
+            ),
+        }
+    }
+}
+
+/// Result of [`Ext4Fs::fsck`].
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FsckReport {
+    /// Number of block groups checked.
+    pub(crate) groups: u32,
+
+    /// Number of directories reachable from the root.
+    pub(crate) directories: u32,
+
+    /// Number of directory entries checked.
+    pub(crate) entries: u64,
+
+    pub(crate) problems: Vec<FsckProblem>,
+
+    /// Duration of the check, in µs (if the `TSC` clock is available).
+    pub(crate) elapsed_us: Option<u64>,
+}
+
+impl FsckReport {
+    /// Whether no inconsistency was found.
+    pub(crate) fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Geometry of the filesystem, copied from the superblock.
+struct FsckGeometry {
+    groups: u32,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    inodes_count: u32,
+
+    /// Whether the filesystem uses `metadata_csum`.
+    metadata_csum: bool,
+
+    /// Mask of the bitmap checksums stored in the descriptors (only the low 16 bits with 32-byte descriptors).
+    bitmap_chksum_mask: u32,
+}
+
+impl Ext4Fs {
+    /// Checks the consistency of this filesystem, without modifying it.
+    pub(crate) fn fsck(&self) -> FsckReport {
+        let start = TSC_CLK.get().map(|tsc| tsc.tsc_read());
+        let mut report = FsckReport::default();
+        let geometry = self.fsck_geometry(&mut report);
+
+        // Raw inode bitmap of each group (`None` if it is not initialized, or cannot be read).
+        let mut inode_bitmaps = Vec::new();
+
+        for group in 0..geometry.groups {
+            inode_bitmaps.push(self.fsck_group(&geometry, group, &mut report));
+            report.groups += 1;
+        }
+
+        let dirs_per_group = self.fsck_directories(&geometry, &inode_bitmaps, &mut report);
+
+        for (group, found) in dirs_per_group.into_iter().enumerate() {
+            let group = group as u32;
+            let Some(descriptor) = self.get_group_descriptor(cast(group)) else {
+                continue;
+            };
+
+            let count = descriptor.read().directory_count();
+            if count != found {
+                report.problems.push(FsckProblem::DirectoryCount {
+                    group,
+                    descriptor: count,
+                    found,
+                });
+            }
+        }
+
+        report.elapsed_us = start.zip(TSC_CLK.get()).map(|(start, tsc)| {
+            tsc.tsc_ticks_to_micro(tsc.tsc_read().saturating_sub(start) as f64) as u64
+        });
+
+        report
+    }
+
+    fn fsck_geometry(&self, report: &mut FsckReport) -> FsckGeometry {
+        let sb = self.superblock.read();
+
+        let metadata_csum = sb
+            .feature_ro_compat
+            .includes(ReadOnlyCompatibleFeatureSet::EXT4_FEATURE_R0_COMPAT_METADATA_CSUM);
+
+        if sb.checksum_type == Ext4ChksumAlgorithm::CHKSUM_CRC32_C && !sb.validate_chksum() {
+            report.problems.push(FsckProblem::SuperblockChecksum);
+        }
+
+        let blocks_per_group = cast::<Ext4BlkCount32, u32>(sb.blocks_per_group);
+        let first_data_blk = u64::from(cast::<Ext4RealBlkId32, u32>(sb.first_datablock));
+        let data_blks = cast::<Ext4BlkCount, u64>(sb.blk_count()).saturating_sub(first_data_blk);
+
+        let bitmap_chksum_mask = if sb
+            .feature_incompat
+            .includes(IncompatibleFeatureSet::EXT4_FEATURE_INCOMPAT_64BIT)
+        {
+            u32::MAX
+        } else {
+            0xFFFF
+        };
+
+        FsckGeometry {
+            groups: u32::try_from(data_blks.div_ceil(u64::from(blocks_per_group.max(1))))
+                .unwrap_or(u32::MAX),
+            blocks_per_group,
+            inodes_per_group: cast::<InodeCount, u32>(sb.inodes_per_group),
+            inodes_count: cast::<InodeCount, u32>(sb.inodes_count),
+            metadata_csum,
+            bitmap_chksum_mask,
+        }
+    }
+
+    /// Checks the descriptor and the bitmaps of a block group.
+    ///
+    /// Returns the raw inode bitmap of the group, if it is initialized.
+    fn fsck_group(
+        &self,
+        geometry: &FsckGeometry,
+        group: u32,
+        report: &mut FsckReport,
+    ) -> Option<Vec<u8>> {
+        let Some(locked_descriptor) =
+            self.get_group_descriptor(cast::<u32, BlockGroupNumber>(group))
+        else {
+            report
+                .problems
+                .push(FsckProblem::UnreadableDescriptor { group });
+            return None;
+        };
+        let descriptor = locked_descriptor.read();
+
+        if geometry.metadata_csum && !descriptor.validate_chksum() {
+            report
+                .problems
+                .push(FsckProblem::DescriptorChecksum { group });
+        }
+
+        let uninit = |flag: GroupDescriptorFlags| {
+            descriptor.flags() & flag != GroupDescriptorFlags::default()
+        };
+
+        if !uninit(GroupDescriptorFlags::EXT4_BG_BLOCK_UNINIT) {
+            let bitmap_len = (geometry.blocks_per_group / 8) as usize;
+
+            match self.fsck_read_bitmap(descriptor.block_bitmap_blk_addr(), bitmap_len) {
+                Some(bitmap) => {
+                    let on_disk = cast::<_, u32>(descriptor.block_bitmap_chksum());
+                    if geometry.metadata_csum
+                        && self.fsck_bitmap_chksum(&bitmap) & geometry.bitmap_chksum_mask != on_disk
+                    {
+                        report
+                            .problems
+                            .push(FsckProblem::BlockBitmapChecksum { group });
+                    }
+
+                    let free = u64::from(count_zero_bits(&bitmap));
+                    let count = cast::<Ext4BlkCount, u64>(descriptor.free_blk_count());
+                    if free != count {
+                        report.problems.push(FsckProblem::FreeBlocksCount {
+                            group,
+                            descriptor: count,
+                            bitmap: free,
+                        });
+                    }
+                }
+                None => report
+                    .problems
+                    .push(FsckProblem::UnreadableBitmap { group }),
+            }
+        }
+
+        if uninit(GroupDescriptorFlags::EXT4_BG_INODE_UNINIT) {
+            return None;
+        }
+
+        let bitmap_len = (geometry.inodes_per_group / 8) as usize;
+        let Some(bitmap) = self.fsck_read_bitmap(descriptor.inode_bitmap_blk_addr(), bitmap_len)
+        else {
+            report
+                .problems
+                .push(FsckProblem::UnreadableBitmap { group });
+            return None;
+        };
+
+        let on_disk = cast::<_, u32>(descriptor.inode_bitmap_chksum());
+        if geometry.metadata_csum
+            && self.fsck_bitmap_chksum(&bitmap) & geometry.bitmap_chksum_mask != on_disk
+        {
+            report
+                .problems
+                .push(FsckProblem::InodeBitmapChecksum { group });
+        }
+
+        let free = count_zero_bits(&bitmap);
+        let count = cast::<InodeCount, u32>(descriptor.free_inode_count());
+        if free != count {
+            report.problems.push(FsckProblem::FreeInodesCount {
+                group,
+                descriptor: count,
+                bitmap: free,
+            });
+        }
+
+        Some(bitmap)
+    }
+
+    /// Walks the directory tree from the root, and checks every entry.
+    ///
+    /// Returns the number of directories found in each block group.
+    fn fsck_directories(
+        &self,
+        geometry: &FsckGeometry,
+        inode_bitmaps: &[Option<Vec<u8>>],
+        report: &mut FsckReport,
+    ) -> Vec<u32> {
+        let mut dirs_per_group = alloc::vec![0; geometry.groups as usize];
+        let Some(locked_fs) = self.fs_ptr.upgrade() else {
+            return dirs_per_group;
+        };
+
+        let root = cast::<InodeNumber, u32>(InodeNumber::ROOT_DIR);
+        let mut visited = BTreeSet::from([root]);
+        let mut pending = alloc::vec![(root, root)];
+
+        while let Some((dir, parent)) = pending.pop() {
+            report.directories += 1;
+            if let Some(count) = dirs_per_group.get_mut(self.fsck_inode_group(geometry, dir)) {
+                *count += 1;
+            }
+
+            let Ok(entries) = Ext4Directory::from_inode_id(locked_fs.clone(), cast(dir)) else {
+                report
+                    .problems
+                    .push(FsckProblem::UnreadableDirectory { dir });
+                continue;
+            };
+
+            for entry in entries {
+                report.entries += 1;
+
+                let inode = cast::<InodeNumber, u32>(entry.inode_number);
+                let name = String::from(entry.name);
+
+                match name.as_str() {
+                    "." if inode != dir => {
+                        report
+                            .problems
+                            .push(FsckProblem::BadDotEntry { dir, found: inode });
+                        continue;
+                    }
+                    ".." if inode != parent => {
+                        report.problems.push(FsckProblem::BadDotDotEntry {
+                            dir,
+                            parent,
+                            found: inode,
+                        });
+                        continue;
+                    }
+                    "." | ".." => continue,
+                    _ => (),
+                }
+
+                if inode == 0 || inode > geometry.inodes_count {
+                    report
+                        .problems
+                        .push(FsckProblem::InvalidInode { dir, name, inode });
+                    continue;
+                }
+
+                if !self.fsck_inode_in_use(geometry, inode_bitmaps, inode) {
+                    report
+                        .problems
+                        .push(FsckProblem::FreeInodeReferenced { dir, name, inode });
+                    continue;
+                }
+
+                let Some(locked_inode) = self.get_inode_strong(cast(inode)) else {
+                    report
+                        .problems
+                        .push(FsckProblem::InvalidInode { dir, name, inode });
+                    continue;
+                };
+                let is_dir = {
+                    let inode_data = locked_inode.read();
+
+                    if geometry.metadata_csum && !inode_data.validate_chksum() {
+                        report.problems.push(FsckProblem::InodeChecksum { inode });
+                    }
+
+                    cast::<InodeFileMode, u16>(inode_data.i_mode) & INODE_MODE_TYPE_MASK
+                        == cast::<InodeFileMode, u16>(InodeFileMode::S_IFDIR)
+                };
+
+                let typed_dir = entry.file_type == Some(Ext4DirectoryFileType::DIRECTORY);
+                let untyped = entry.file_type.is_none()
+                    || entry.file_type == Some(Ext4DirectoryFileType::UNKNOWN);
+                if !untyped && typed_dir != is_dir {
+                    report
+                        .problems
+                        .push(FsckProblem::FileTypeMismatch { dir, name, inode });
+                    continue;
+                }
+
+                if !is_dir {
+                    continue;
+                }
+
+                if !visited.insert(inode) {
+                    report
+                        .problems
+                        .push(FsckProblem::DirectoryHardLink { dir, name, inode });
+                    continue;
+                }
+
+                pending.push((inode, dir));
+            }
+        }
+
+        dirs_per_group
+    }
+
+    /// Returns whether `inode` is marked in use in the inode bitmap of its group.
+    fn fsck_inode_in_use(
+        &self,
+        geometry: &FsckGeometry,
+        inode_bitmaps: &[Option<Vec<u8>>],
+        inode: u32,
+    ) -> bool {
+        let index = ((inode - 1) % geometry.inodes_per_group.max(1)) as usize;
+
+        inode_bitmaps
+            .get(self.fsck_inode_group(geometry, inode))
+            .and_then(Option::as_ref)
+            .and_then(|bitmap| bitmap.get(index / 8))
+            .is_some_and(|byte| byte & (1 << (index % 8)) != 0)
+    }
+
+    fn fsck_inode_group(&self, geometry: &FsckGeometry, inode: u32) -> usize {
+        ((inode - 1) / geometry.inodes_per_group.max(1)) as usize
+    }
+
+    /// Reads the first `len` bytes of a bitmap block.
+    fn fsck_read_bitmap(&self, blk_id: Ext4RealBlkId, len: usize) -> Option<Vec<u8>> {
+        let mut blk = self.allocate_blk();
+        self.read_blk_from_device(blk_id, &mut blk).ok()?;
+
+        blk.truncate(len);
+        Some(blk)
+    }
+
+    /// Computes the checksum of a bitmap: `crc32c_calc(fs_uuid + bitmap)`.
+    fn fsck_bitmap_chksum(&self, bitmap: &[u8]) -> u32 {
+        let mut chksum_bytes = Vec::with_capacity(16 + bitmap.len());
+        chksum_bytes.extend_from_slice(bytemuck::bytes_of(&self.superblock.read().uuid));
+        chksum_bytes.extend_from_slice(bitmap);
+
+        crc32c_calc(&chksum_bytes)
+    }
+}
+
+/// Checks every mounted `ext4` filesystem, and writes the problems found.
+pub fn fsck_report(out: &mut impl Write) -> fmt::Result {
+    let filesystems: Vec<_> = MOUNTED_FILESYSTEMS
+        .lock()
+        .iter()
+        .filter_map(|fs| fs.upgrade())
+        .collect();
+
+    if filesystems.is_empty() {
+        return writeln!(out, "no ext4 filesystem mounted");
+    }
+
+    for locked_fs in filesystems {
+        let fs = locked_fs.read();
+        let report = fs.fsck();
+
+        write!(
+            out,
+            "ext4 on {} ({}): {} groups, {} directories, {} entries",
+            partition_name(fs.drive_id, fs.partition_id),
+            fs.volume_label(),
+            report.groups,
+            report.directories,
+            report.entries
+        )?;
+        if let Some(elapsed_us) = report.elapsed_us {
+            write!(out, " in {} ms", elapsed_us / 1000)?;
+        }
+        writeln!(out)?;
+
+        for problem in &report.problems {
+            writeln!(out, "  {problem}")?;
+        }
+
+        match report.is_clean() {
+            true => writeln!(out, "  clean")?,
+            false => writeln!(out, "  {} problems found", report.problems.len())?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Counts the bits cleared in a bitmap.
+fn count_zero_bits(bitmap: &[u8]) -> u32 {
+    bitmap.iter().map(|byte| byte.count_zeros()).sum()
+}
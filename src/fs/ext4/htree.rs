@@ -0,0 +1,344 @@
+//! `ext4` hashed directory index (`htree`).
+//!
+//! Directories with the `EXT4_INDEX_FL` flag keep a hashed B-tree of their entries, in addition to the regular
+//! directory blocks. The first block of the directory holds the root of the tree (`dx_root`), disguised as the `.`
+//! and `..` entries so that the directory can still be read linearly. It maps ranges of hashes of file names to
+//! internal nodes (`dx_node`, disguised as an empty entry covering a whole block), and eventually to the leaf blocks
+//! that contain the directory entries whose names hash into that range.
+//!
+//! Looking up a name therefore reads one block per level of the tree (at most 3), and the leaf block(s) holding its
+//! hash.
+
+use alloc::{vec, vec::Vec};
+use bytemuck::cast;
+
+use crate::errors::{CanFail, IOError};
+use crate::fs::ext4::sb::{Ext4HashAlgorithm, Ext4Superblock, Ext4SuperblockFlags};
+use crate::fs::IOResult;
+
+/// Maximum depth of the tree below its root (3 levels with the `largedir` feature).
+const DX_MAX_INDIRECT_LEVELS: u8 = 3;
+
+/// Offset of the `dx_root_info` structure, following the fake `.` and `..` entries in the root block.
+const DX_ROOT_INFO_OFFSET: usize = 0x18;
+
+/// Offset of the entries of an internal node, following its fake directory entry.
+const DX_NODE_ENTRIES_OFFSET: usize = 0x8;
+
+/// Size of an index entry (`hash`, `block`).
+const DX_ENTRY_SIZE: usize = 8;
+
+/// Set in the hash of an index entry if the hashes of the entries of the preceding leaf block collide with it: the
+/// entries with that hash may be spread over both blocks.
+const DX_HASH_COLLISION: u32 = 1;
+
+/// Seed used by the hash functions when the filesystem does not define one.
+const DX_DEFAULT_SEED: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+
+/// Parameters of the directory hash of a filesystem.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct DxHashInfo {
+    seed: [u32; 4],
+    unsigned: bool,
+}
+
+impl DxHashInfo {
+    /// Reads the directory hash parameters from the superblock.
+    pub(super) fn new(sb: &Ext4Superblock) -> Self {
+        let seed = if sb.hash_seed.iter().all(|&word| word == 0) {
+            DX_DEFAULT_SEED
+        } else {
+            sb.hash_seed
+        };
+
+        Self {
+            seed,
+            unsigned: sb.flags & Ext4SuperblockFlags::UNSIGNED_DIR_HASH
+                != Ext4SuperblockFlags::EMPTY_SET,
+        }
+    }
+}
+
+/// Returns the logical blocks of the directory that may hold the entry named `name`, in the order in which they
+/// should be searched.
+///
+/// `read_blk` reads a logical block of the directory into the provided buffer (of `blk_size` bytes).
+///
+/// # Errors
+///
+/// Returns [`IOError::InvalidCommand`] if the index is corrupted or uses an unsupported hash algorithm (the directory
+/// can still be searched linearly), or any I/O error returned by `read_blk`.
+pub(super) fn dx_lookup(
+    name: &[u8],
+    info: DxHashInfo,
+    blk_size: usize,
+    mut read_blk: impl FnMut(u64, &mut [u8]) -> CanFail<IOError>,
+) -> IOResult<Vec<u64>> {
+    let mut blk = vec![0; blk_size];
+    read_blk(0, &mut blk)?;
+
+    let root_info = blk
+        .get(DX_ROOT_INFO_OFFSET..DX_ROOT_INFO_OFFSET + 8)
+        .ok_or(IOError::InvalidCommand)?;
+    let hash_version = cast::<u8, Ext4HashAlgorithm>(root_info[4]);
+    let info_length = usize::from(root_info[5]);
+    let indirect_levels = root_info[6];
+
+    if indirect_levels >= DX_MAX_INDIRECT_LEVELS {
+        return Err(IOError::InvalidCommand);
+    }
+
+    let hash = dx_hash(name, hash_version, info).ok_or(IOError::InvalidCommand)?;
+    let mut offset = DX_ROOT_INFO_OFFSET + info_length;
+
+    for _ in 0..indirect_levels {
+        let (position, entries) = dx_find_entry(blk.get(offset..).unwrap_or_default(), hash)?;
+        read_blk(entries[position].1, &mut blk)?;
+        offset = DX_NODE_ENTRIES_OFFSET;
+    }
+
+    let (position, entries) = dx_find_entry(blk.get(offset..).unwrap_or_default(), hash)?;
+    let mut leaves = vec![entries[position].1];
+
+    // Entries sharing the hash of the one we are looking for may continue in the following leaf blocks.
+    for &(next_hash, next_blk) in &entries[position + 1..] {
+        if next_hash & DX_HASH_COLLISION == 0 || next_hash & !DX_HASH_COLLISION != hash {
+            break;
+        }
+
+        leaves.push(next_blk);
+    }
+
+    Ok(leaves)
+}
+
+/// Parses the index entries at the beginning of `entries`, and returns them along with the position of the one
+/// covering `hash`.
+///
+/// The first entry holds the `limit` and `count` of the node in place of its hash, and covers every hash lower than
+/// the one of the second entry.
+fn dx_find_entry(entries: &[u8], hash: u32) -> IOResult<(usize, Vec<(u32, u64)>)> {
+    let header = entries
+        .get(..DX_ENTRY_SIZE)
+        .ok_or(IOError::InvalidCommand)?;
+    let limit = usize::from(u16::from_le_bytes([header[0], header[1]]));
+    let count = usize::from(u16::from_le_bytes([header[2], header[3]]));
+
+    if count == 0 || count > limit || count * DX_ENTRY_SIZE > entries.len() {
+        return Err(IOError::InvalidCommand);
+    }
+
+    let entries: Vec<(u32, u64)> = entries[..count * DX_ENTRY_SIZE]
+        .chunks_exact(DX_ENTRY_SIZE)
+        .enumerate()
+        .map(|(i, entry)| {
+            let entry_hash = if i == 0 {
+                0
+            } else {
+                u32::from_le_bytes(entry[..4].try_into().expect("invalid index entry"))
+            };
+
+            (
+                entry_hash,
+                u64::from(u32::from_le_bytes(
+                    entry[4..].try_into().expect("invalid index entry"),
+                )),
+            )
+        })
+        .collect();
+
+    // Entries are sorted by hash: the last one whose hash is lower or equal covers `hash`.
+    let position = entries.partition_point(|&(entry_hash, _)| entry_hash <= hash) - 1;
+
+    Ok((position, entries))
+}
+
+/// Computes the hash of a file name, as stored in the index (its lowest bit is always clear).
+///
+/// `version` is the hash algorithm recorded in the root of the tree. Returns `None` if it is not supported.
+fn dx_hash(name: &[u8], version: Ext4HashAlgorithm, info: DxHashInfo) -> Option<u32> {
+    let mut version = version;
+
+    // The unsigned variants are selected by a superblock flag rather than stored in the tree.
+    if info.unsigned {
+        if version == Ext4HashAlgorithm::LEGACY {
+            version = Ext4HashAlgorithm::LEGACY_UNSIGNED;
+        } else if version == Ext4HashAlgorithm::HALF_MD4 {
+            version = Ext4HashAlgorithm::HALD_MD4_UNSIGNED;
+        } else if version == Ext4HashAlgorithm::TEA {
+            version = Ext4HashAlgorithm::TEA_UNSIGNED;
+        }
+    }
+
+    let mut buf = info.seed;
+    let hash = if version == Ext4HashAlgorithm::LEGACY {
+        dx_hack_hash(name, false)
+    } else if version == Ext4HashAlgorithm::LEGACY_UNSIGNED {
+        dx_hack_hash(name, true)
+    } else if version == Ext4HashAlgorithm::HALF_MD4
+        || version == Ext4HashAlgorithm::HALD_MD4_UNSIGNED
+    {
+        let unsigned = version == Ext4HashAlgorithm::HALD_MD4_UNSIGNED;
+
+        for (i, chunk) in name.chunks(32).enumerate() {
+            let mut input = [0u32; 8];
+            str_to_hash_buf(chunk, name.len() - i * 32, &mut input, unsigned);
+            half_md4_transform(&mut buf, &input);
+        }
+
+        buf[1]
+    } else if version == Ext4HashAlgorithm::TEA || version == Ext4HashAlgorithm::TEA_UNSIGNED {
+        let unsigned = version == Ext4HashAlgorithm::TEA_UNSIGNED;
+
+        for (i, chunk) in name.chunks(16).enumerate() {
+            let mut input = [0u32; 4];
+            str_to_hash_buf(chunk, name.len() - i * 16, &mut input, unsigned);
+            tea_transform(&mut buf, &input);
+        }
+
+        buf[0]
+    } else {
+        return None;
+    };
+
+    let hash = hash & !DX_HASH_COLLISION;
+
+    // The highest hash value is reserved to mark the end of the directory.
+    if hash == 0x7fff_ffff << 1 {
+        return Some(0x7fff_fffe << 1);
+    }
+
+    Some(hash)
+}
+
+/// Converts a byte of a file name to the integer used by the hash functions: depending on the filesystem, names are
+/// hashed as signed or unsigned characters.
+fn hash_char(byte: u8, unsigned: bool) -> u32 {
+    if unsigned {
+        u32::from(byte)
+    } else {
+        i32::from(byte as i8) as u32
+    }
+}
+
+/// Legacy hash function (`dx_hack_hash`).
+fn dx_hack_hash(name: &[u8], unsigned: bool) -> u32 {
+    let (mut hash0, mut hash1): (u32, u32) = (0x12a3_fe2d, 0x37ab_e8f9);
+
+    for &byte in name {
+        let mut hash =
+            hash1.wrapping_add(hash0 ^ hash_char(byte, unsigned).wrapping_mul(7_152_373));
+
+        if hash & 0x8000_0000 != 0 {
+            hash = hash.wrapping_sub(0x7fff_ffff);
+        }
+
+        hash1 = hash0;
+        hash0 = hash;
+    }
+
+    hash0 << 1
+}
+
+/// Fills `buf` with the bytes of `chunk`, the part of a file name being hashed, padded with a value derived from
+/// `remaining`, the length of the name from the beginning of `chunk`.
+fn str_to_hash_buf(chunk: &[u8], remaining: usize, buf: &mut [u32], unsigned: bool) {
+    let len = remaining as u32;
+    let mut pad = len | (len << 8);
+    pad |= pad << 16;
+
+    let mut val = pad;
+    let mut filled = 0;
+
+    for (i, &byte) in chunk.iter().take(buf.len() * 4).enumerate() {
+        val = hash_char(byte, unsigned).wrapping_add(val << 8);
+
+        if i % 4 == 3 {
+            buf[filled] = val;
+            val = pad;
+            filled += 1;
+        }
+    }
+
+    if filled < buf.len() {
+        buf[filled] = val;
+        filled += 1;
+    }
+
+    buf[filled..].fill(pad);
+}
+
+/// `TEA` (Tiny Encryption Algorithm) transform, with 16 rounds.
+fn tea_transform(buf: &mut [u32; 4], input: &[u32; 4]) {
+    const DELTA: u32 = 0x9e37_79b9;
+
+    let (mut b0, mut b1) = (buf[0], buf[1]);
+    let [a, b, c, d] = *input;
+    let mut sum: u32 = 0;
+
+    for _ in 0..16 {
+        sum = sum.wrapping_add(DELTA);
+        b0 = b0.wrapping_add(
+            ((b1 << 4).wrapping_add(a)) ^ b1.wrapping_add(sum) ^ ((b1 >> 5).wrapping_add(b)),
+        );
+        b1 = b1.wrapping_add(
+            ((b0 << 4).wrapping_add(c)) ^ b0.wrapping_add(sum) ^ ((b0 >> 5).wrapping_add(d)),
+        );
+    }
+
+    buf[0] = buf[0].wrapping_add(b0);
+    buf[1] = buf[1].wrapping_add(b1);
+}
+
+/// Cut-down `MD4` transform, with 3 rounds of 8 steps.
+fn half_md4_transform(buf: &mut [u32; 4], input: &[u32; 8]) {
+    const K2: u32 = 0o13_240_474_631;
+    const K3: u32 = 0o15_666_365_641;
+
+    let f = |x: u32, y: u32, z: u32| z ^ (x & (y ^ z));
+    let g = |x: u32, y: u32, z: u32| (x & y).wrapping_add((x ^ y) & z);
+    let h = |x: u32, y: u32, z: u32| x ^ y ^ z;
+
+    let [mut a, mut b, mut c, mut d] = *buf;
+
+    macro_rules! round {
+        ($f: ident, $a: ident, $b: ident, $c: ident, $d: ident, $x: expr, $s: expr) => {
+            $a = $a
+                .wrapping_add($f($b, $c, $d))
+                .wrapping_add($x)
+                .rotate_left($s);
+        };
+    }
+
+    round!(f, a, b, c, d, input[0], 3);
+    round!(f, d, a, b, c, input[1], 7);
+    round!(f, c, d, a, b, input[2], 11);
+    round!(f, b, c, d, a, input[3], 19);
+    round!(f, a, b, c, d, input[4], 3);
+    round!(f, d, a, b, c, input[5], 7);
+    round!(f, c, d, a, b, input[6], 11);
+    round!(f, b, c, d, a, input[7], 19);
+
+    round!(g, a, b, c, d, input[1].wrapping_add(K2), 3);
+    round!(g, d, a, b, c, input[3].wrapping_add(K2), 5);
+    round!(g, c, d, a, b, input[5].wrapping_add(K2), 9);
+    round!(g, b, c, d, a, input[7].wrapping_add(K2), 13);
+    round!(g, a, b, c, d, input[0].wrapping_add(K2), 3);
+    round!(g, d, a, b, c, input[2].wrapping_add(K2), 5);
+    round!(g, c, d, a, b, input[4].wrapping_add(K2), 9);
+    round!(g, b, c, d, a, input[6].wrapping_add(K2), 13);
+
+    round!(h, a, b, c, d, input[3].wrapping_add(K3), 3);
+    round!(h, d, a, b, c, input[7].wrapping_add(K3), 9);
+    round!(h, c, d, a, b, input[2].wrapping_add(K3), 11);
+    round!(h, b, c, d, a, input[6].wrapping_add(K3), 15);
+    round!(h, a, b, c, d, input[1].wrapping_add(K3), 3);
+    round!(h, d, a, b, c, input[5].wrapping_add(K3), 9);
+    round!(h, c, d, a, b, input[0].wrapping_add(K3), 11);
+    round!(h, b, c, d, a, input[4].wrapping_add(K3), 15);
+
+    buf[0] = buf[0].wrapping_add(a);
+    buf[1] = buf[1].wrapping_add(b);
+    buf[2] = buf[2].wrapping_add(c);
+    buf[3] = buf[3].wrapping_add(d);
+}
@@ -16,8 +16,8 @@ use spin::RwLock;
 
 use crate::errors::IOError;
 use crate::fs::ext4::sb::{Ext4FsUuid, LockedSuperblock};
-use crate::fs::ext4::WeakLockedExt4Fs;
-use crate::fs::IOResult;
+use crate::fs::ext4::{cache_limits, WeakLockedExt4Fs};
+use crate::fs::{FileType, IOResult, Metadata};
 use crate::{
     error, ext4_uint_field_derive_display,
     fs::ext4::{crc32c_calc, extent::ExtentBlock},
@@ -143,6 +143,21 @@ impl From<usize> for InodeNumber {
     }
 }
 
+/// Size of the original `ext2` inode structure, preceding the extra fields of the inode.
+const EXT2_INODE_SIZE: usize = 128;
+
+/// Number of bytes of inline data stored in place of the block map of an inode.
+pub(crate) const INLINE_DATA_BLK_SIZE: usize = 60;
+
+/// Magic number at the beginning of the extended attributes stored in an inode.
+const XATTR_MAGIC: u32 = 0xEA02_0000;
+
+/// Size of the fixed part of an extended attribute entry (followed by the attribute name).
+const XATTR_ENTRY_SIZE: usize = 16;
+
+/// Extended attribute namespace of the `system.*` attributes.
+const XATTR_INDEX_SYSTEM: u8 = 7;
+
 /// File mode / type representation.
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash, Pod, Zeroable)]
 #[repr(transparent)]
@@ -881,6 +896,10 @@ pub(crate) struct Inode {
 
     /// `ext4` inode structure
     pub(crate) ext4_struct: Ext4Inode,
+
+    /// Extended attributes stored in the inode itself, past the [`Ext4Inode`] structure (empty if the inode has no
+    /// room for them).
+    pub(crate) xattr_area: Vec<u8>,
 }
 
 impl Inode {
@@ -892,12 +911,101 @@ impl Inode {
         sb: LockedSuperblock,
         ext4_inode: Ext4Inode,
         inode_id: InodeNumber,
+        xattr_area: Vec<u8>,
     ) -> Self {
         Self {
             sb,
             number: inode_id,
             cache: AtomicBool::default(),
             ext4_struct: ext4_inode,
+            xattr_area,
+        }
+    }
+
+    /// Returns the content of this `Inode`, if it is stored inline (`EXT4_INLINE_DATA_FL`).
+    ///
+    /// The first [`INLINE_DATA_BLK_SIZE`] bytes are stored in place of the block map, and the rest of the content in
+    /// the `system.data` extended attribute, within the inode.
+    pub(crate) fn inline_data(&self) -> Option<Vec<u8>> {
+        if !self.has_flag(InodeFlags::EXT4_INLINE_DATA_FL) {
+            return None;
+        }
+
+        let size = usize::try_from(cast::<InodeSize, u64>(self.size())).ok()?;
+        let mut data = self.i_block.0.to_vec();
+
+        if let Some(extra) = self.in_inode_xattr(XATTR_INDEX_SYSTEM, b"data") {
+            data.extend_from_slice(extra);
+        }
+
+        data.resize(size, 0);
+
+        Some(data)
+    }
+
+    /// Returns the target of this `Inode` if it is a fast symbolic link (a symbolic link whose target is stored in
+    /// place of the block map).
+    pub(crate) fn fast_symlink_target(&self) -> Option<Vec<u8>> {
+        let size = usize::try_from(cast::<InodeSize, u64>(self.size())).ok()?;
+
+        if self.file_type() != FileType::SymbolicLink
+            || self.has_flag(InodeFlags::EXT4_INLINE_DATA_FL)
+            || size >= INLINE_DATA_BLK_SIZE
+        {
+            return None;
+        }
+
+        Some(self.i_block.0[..size].to_vec())
+    }
+
+    /// Returns the value of the extended attribute `name` (in the namespace `name_index`) stored in the inode.
+    ///
+    /// Attributes stored in a separate block are not looked up.
+    fn in_inode_xattr(&self, name_index: u8, name: &[u8]) -> Option<&[u8]> {
+        let area = &self.xattr_area;
+
+        if area.get(..4)? != XATTR_MAGIC.to_le_bytes() {
+            return None;
+        }
+
+        // Entries, and the offsets of their values, are relative to the end of the header.
+        let entries = &area[4..];
+        let mut offset = 0;
+
+        while entries.get(offset..offset + 4)? != [0; 4] {
+            let entry = entries.get(offset..offset + XATTR_ENTRY_SIZE)?;
+            let entry_name_len = usize::from(entry[0]);
+            let value_offset = usize::from(u16::from_le_bytes([entry[2], entry[3]]));
+            let value_size =
+                usize::try_from(u32::from_le_bytes(entry[8..12].try_into().ok()?)).ok()?;
+            let entry_name = entries
+                .get(offset + XATTR_ENTRY_SIZE..offset + XATTR_ENTRY_SIZE + entry_name_len)?;
+
+            if entry[1] == name_index && entry_name == name {
+                return entries.get(value_offset..value_offset + value_size);
+            }
+
+            offset += (XATTR_ENTRY_SIZE + entry_name_len).next_multiple_of(4);
+        }
+
+        None
+    }
+
+    /// Returns the file-system independent [`Metadata`] of this `Inode`.
+    pub(crate) fn metadata(&self) -> Metadata {
+        let mode = cast::<InodeFileMode, u16>(self.i_mode);
+
+        Metadata {
+            file_type: self.file_type(),
+            permissions: mode & 0o7777,
+            uid: cast(self.uid()),
+            gid: cast(self.gid()),
+            size: cast(self.size()),
+            links: u32::from(cast::<InodeHardLinkCount, u16>(self.links())),
+            inode: u64::from(cast::<InodeNumber, u32>(self.number)),
+            accessed: self.access_time(),
+            modified: self.modification_time(),
+            changed: self.change_time(),
         }
     }
     /// Compares the checksum of the `Inode` to its on-disk value.
@@ -1067,6 +1175,19 @@ pub(crate) struct Ext4Inode {
 }
 
 impl Ext4Inode {
+    /// Returns the type of this `Inode`, from the file type bits of its mode.
+    pub(crate) fn file_type(&self) -> FileType {
+        match cast::<InodeFileMode, u16>(self.i_mode) & 0xF000 {
+            0x4000 => FileType::Directory,
+            0xA000 => FileType::SymbolicLink,
+            0x2000 => FileType::CharacterDevice,
+            0x6000 => FileType::BlockDevice,
+            0x1000 => FileType::Fifo,
+            0xC000 => FileType::Socket,
+            _ => FileType::Regular,
+        }
+    }
+
     /// Returns the type of this `Inode` (file, directory, ...)
     pub(crate) fn inode_type(&self) -> InodeType {
         InodeType::from(self.i_mode)
@@ -1286,6 +1407,9 @@ pub(super) struct InodeCacheEntry {
     /// Number of times this cache entry was accessed.
     pub(super) usage_count: AtomicU32,
 
+    /// Value of the cache access clock the last time this entry was accessed, used for LRU eviction.
+    pub(super) last_access: u64,
+
     /// Current state of this cache entry.
     pub(super) entry_state: InodeCacheEntryState,
 }
@@ -1310,6 +1434,14 @@ pub(super) struct InodeCacheEntry {
 /// - Batch removal ([`InodeCacheRemovalPolicy::BatchRemoval`]): entries marked for deletion are removed by batch
 ///
 /// Immediate removal is simpler, but batch removal might offer better performances in some cases.
+///
+/// # Eviction
+///
+/// The number of entries is bounded by [`Ext4CacheLimits::max_inodes`]. When the cache is full, the least-recently
+/// used entries are evicted, provided that they are not referenced outside of the cache (the limit is therefore a
+/// soft limit). Entries can also be evicted on demand, using [`InodeCache::evict_lru`].
+///
+/// [`Ext4CacheLimits::max_inodes`]: crate::fs::ext4::Ext4CacheLimits
 #[derive(Debug)]
 pub(super) struct InodeCache {
     /// Backing [`HashMap`] for the inode cache.
@@ -1317,6 +1449,9 @@ pub(super) struct InodeCache {
 
     pub(super) removal_policy: InodeCacheRemovalPolicy,
 
+    /// Monotonic counter, incremented on every cache access.
+    pub(super) access_clock: u64,
+
     pub(super) fs: WeakLockedExt4Fs,
 }
 
@@ -1353,17 +1488,27 @@ impl InodeCache {
         &mut self,
         inode_id: InodeNumber,
     ) -> Option<LockedInodeStrongRef> {
+        self.access_clock += 1;
+
         if let Some(inode_cache_entry) = self.hashtable.get_mut(&inode_id) {
             inode_cache_entry
                 .usage_count
                 .fetch_add(1, Ordering::Relaxed);
+            inode_cache_entry.last_access = self.access_clock;
             return Some(inode_cache_entry.inode.clone());
         }
 
         let inode = self.load_inode_from_raw(inode_id).ok()?;
+
+        let max_entries = cache_limits().max_inodes;
+        if self.hashtable.len() >= max_entries {
+            self.evict_lru(self.hashtable.len() + 1 - max_entries);
+        }
+
         let inode_cache_entry = InodeCacheEntry {
             inode: inode.clone(),
             usage_count: AtomicU32::default(),
+            last_access: self.access_clock,
             entry_state: InodeCacheEntryState::Valid,
         };
         self.hashtable.insert(inode_id, inode_cache_entry);
@@ -1395,10 +1540,20 @@ impl InodeCache {
                 .expect("invalid byte size")];
 
         let mut filled_inode = alloc::vec![0u8; mem::size_of::<Ext4Inode>()];
-        filled_inode[..raw_inode.len()].copy_from_slice(raw_inode);
+        let copied = usize::min(raw_inode.len(), filled_inode.len());
+        filled_inode[..copied].copy_from_slice(&raw_inode[..copied]);
 
         let ext4_inode: Ext4Inode = *from_bytes(&filled_inode);
-        let inode = Inode::from_ext4_inode(fs.superblock.clone(), ext4_inode, inode_id);
+
+        // In-inode extended attributes follow the extra fields of the inode.
+        let xattr_start = EXT2_INODE_SIZE + usize::from(ext4_inode.i_extra_isize.0);
+        let xattr_area = if ext4_inode.i_extra_isize == InodeExtraSize::NO_EXTRA_SIZE {
+            Vec::new()
+        } else {
+            raw_inode.get(xattr_start..).unwrap_or_default().to_vec()
+        };
+
+        let inode = Inode::from_ext4_inode(fs.superblock.clone(), ext4_inode, inode_id, xattr_area);
 
         inode.validate_chksum();
 
@@ -1425,6 +1580,41 @@ impl InodeCache {
         self.hashtable.shrink_to(0);
     }
 
+    /// Approximate amount of memory used by a single cache entry, in bytes.
+    pub(super) const ENTRY_SIZE: usize =
+        mem::size_of::<Inode>() + mem::size_of::<InodeCacheEntry>() + mem::size_of::<InodeNumber>();
+
+    /// Evicts up to `count` entries from the cache, least-recently used first.
+    ///
+    /// Entries marked as `MustClear` are always removed first, and entries that are still referenced outside of the
+    /// cache are never evicted. Returns the number of entries that were evicted.
+    ///
+    /// This does not allocate memory, so that it can be used to release memory when the system is running out of it.
+    pub(super) fn evict_lru(&mut self, count: usize) -> usize {
+        let entries_count = self.hashtable.len();
+        self.flush_invalid_cache_entries();
+
+        let mut evicted = entries_count - self.hashtable.len();
+
+        while evicted < count {
+            let lru_entry = self
+                .hashtable
+                .iter()
+                .filter(|(_, entry)| Arc::strong_count(&entry.inode) == 1)
+                .min_by_key(|(_, entry)| entry.last_access)
+                .map(|(&inode_id, _)| inode_id);
+
+            let Some(inode_id) = lru_entry else {
+                break;
+            };
+
+            self.hashtable.remove(&inode_id);
+            evicted += 1;
+        }
+
+        evicted
+    }
+
     /// Flushes the cache entries marked as `MustClear`, immediately.
     pub(super) fn flush_invalid_cache_entries(&mut self) {
         self.hashtable
@@ -18,24 +18,34 @@ use alloc::sync::{Arc, Weak};
 use alloc::{string::String, vec::Vec};
 use core::cell::RefCell;
 use core::mem::{self, transmute};
+use core::sync::atomic::{AtomicU64, Ordering};
 use dir::GenericExt4Directory;
 
 use hashbrown::HashMap;
 
-use spin::RwLock;
+use spin::{Mutex, RwLock};
 
+use crate::drivers::devtree::partition_name;
 use crate::drivers::generics::dev_disk::{get_sata_drive, DiskDevice};
 use crate::drivers::ide::AtaDeviceIdentifier;
-use crate::errors::MountError;
+use crate::errors::{MountError, UmountError};
 use crate::fs::ext4::block_grp::{BlockGroupNumber, GroupDescriptorCache, LockedGroupDescriptor};
+use crate::fs::ext4::dir::Ext4DirectoryFileType;
 use crate::fs::ext4::extent::Ext4RealBlkId;
+use crate::fs::ext4::file::Ext4File;
 use crate::fs::ext4::inode::{
     InodeCache, InodeCacheRemovalPolicy, InodeNumber, LockedInode, LockedInodeStrongRef,
 };
 use crate::fs::ext4::sb::{Ext4ChksumAlgorithm, Ext4Superblock, LockedSuperblock, Superblock};
-use crate::fs::{Directory, Fs};
+use crate::fs::mount::{register_mount, OpenFiles};
+use crate::fs::pagecache::{self, FsId};
+use crate::fs::{Directory, File, FileType, Fs, FsFile, Metadata, PartFS};
+use crate::mem::shrinker::{self, ShrinkControl, ShrinkReason};
 use crate::{
+    config,
     errors::{CanFail, IOError},
+    event::{self, DeviceEvent, EventKind},
+    fail_point,
     fs::{
         ext4::{dir::Ext4Directory, extent::ExtentTree, inode::Ext4Inode},
         IOResult,
@@ -48,6 +58,8 @@ pub(crate) mod block_grp;
 pub(crate) mod dir;
 pub(crate) mod extent;
 pub(crate) mod file;
+pub(crate) mod fsck;
+pub(super) mod htree;
 pub(crate) mod inode;
 pub(crate) mod sb;
 
@@ -57,7 +69,7 @@ pub(crate) mod sb;
 /// filesystem.
 ///
 /// The [`Ext4Fs`] structure will remain allocated for as long as the filesystem is mounted (as a strong reference is
-/// kept in the global filesystem register).
+/// kept in the global filesystem registry, see [`crate::fs::mount`]).
 pub(super) type LockedExt4Fs = Arc<RwLock<Ext4Fs>>;
 
 /// Weak pointer to a locked [`Ext4Fs`] structure.
@@ -67,6 +79,175 @@ pub(super) type LockedExt4Fs = Arc<RwLock<Ext4Fs>>;
 /// filesystem cannot be unmounted, contrary to a [`LockedExt4Fs`] reference.
 pub(super) type WeakLockedExt4Fs = Weak<RwLock<Ext4Fs>>;
 
+/// Size limits of the metadata caches of `ext4` filesystems.
+///
+/// These are soft limits: entries that are still referenced outside of a cache are never evicted, so a cache may
+/// temporarily grow past its limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Ext4CacheLimits {
+    /// Maximum number of entries in the inode cache of a filesystem.
+    pub(crate) max_inodes: usize,
+
+    /// Maximum number of entries in the block group descriptor cache of a filesystem.
+    pub(crate) max_group_descriptors: usize,
+}
+
+impl Ext4CacheLimits {
+    pub(crate) const DEFAULT: Self = Self {
+        max_inodes: 1024,
+        max_group_descriptors: 256,
+    };
+
+    /// Reads the limits from the `ext4.inode_cache` and `ext4.group_cache` options (see [`config`]).
+    fn from_config() -> Self {
+        let option = |key, default: usize| {
+            config::get_u64(key)
+                .and_then(|value| usize::try_from(value).ok())
+                .unwrap_or(default)
+        };
+
+        Self {
+            max_inodes: option("ext4.inode_cache", Self::DEFAULT.max_inodes),
+            max_group_descriptors: option("ext4.group_cache", Self::DEFAULT.max_group_descriptors),
+        }
+    }
+}
+
+impl Default for Ext4CacheLimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+static CACHE_LIMITS: RwLock<Ext4CacheLimits> = RwLock::new(Ext4CacheLimits::DEFAULT);
+
+/// Returns the current size limits of the `ext4` metadata caches.
+pub(crate) fn cache_limits() -> Ext4CacheLimits {
+    *CACHE_LIMITS.read()
+}
+
+/// Changes the size limits of the `ext4` metadata caches.
+///
+/// Caches that are larger than the new limits are trimmed the next time they are accessed, or when the shrinker
+/// runs.
+pub(crate) fn set_cache_limits(limits: Ext4CacheLimits) {
+    *CACHE_LIMITS.write() = limits;
+}
+
+/// Maximum number of sectors transferred by a single disk request.
+///
+/// Drivers may use 28-bit ATA commands, which can transfer at most 256 sectors at once.
+const MAX_SECTORS_PER_REQUEST: u64 = 0x80;
+
+/// Maximum number of symbolic links followed while resolving a path.
+const MAX_SYMLINK_FOLLOW: usize = 8;
+
+/// Splits `path` into its components, in reverse order (the first component is the last item).
+fn path_components(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|name| !name.is_empty())
+        .rev()
+        .map(String::from)
+        .collect()
+}
+
+/// Default number of blocks read ahead of the current position, when reading a file or a directory.
+pub(crate) const DEFAULT_READAHEAD_WINDOW: u64 = 32;
+
+/// Readahead window set with [`set_readahead_window`], or [`READAHEAD_WINDOW_UNSET`].
+static READAHEAD_WINDOW: AtomicU64 = AtomicU64::new(READAHEAD_WINDOW_UNSET);
+
+/// The readahead window was not changed at runtime: it is read from the `ext4.readahead` option.
+const READAHEAD_WINDOW_UNSET: u64 = u64::MAX;
+
+/// Returns the number of blocks read ahead of the current position, when reading a file or a directory.
+///
+/// Unless changed with [`set_readahead_window`], it is given by the `ext4.readahead` option (see [`config`]).
+pub(crate) fn readahead_window() -> u64 {
+    match READAHEAD_WINDOW.load(Ordering::Relaxed) {
+        READAHEAD_WINDOW_UNSET => {
+            config::get_u64("ext4.readahead").unwrap_or(DEFAULT_READAHEAD_WINDOW)
+        }
+        window => window,
+    }
+}
+
+/// Changes the number of blocks read ahead of the current position, when reading a file or a directory.
+///
+/// Setting the window to 0 disables readahead: only the requested blocks are read from the disk.
+pub(crate) fn set_readahead_window(blocks: u64) {
+    READAHEAD_WINDOW.store(blocks, Ordering::Relaxed);
+}
+
+/// Every `ext4` filesystem mounted so far, used to release cached metadata on demand.
+static MOUNTED_FILESYSTEMS: Mutex<Vec<WeakLockedExt4Fs>> = Mutex::new(Vec::new());
+
+/// Shrinker releasing the inode and block group descriptor cache entries that are not currently in use, on every
+/// mounted `ext4` filesystem.
+///
+/// Filesystems (or caches) that are currently locked are skipped.
+fn shrink_cached_metadata(control: &ShrinkControl) -> usize {
+    let Some(mut filesystems) = MOUNTED_FILESYSTEMS.try_lock() else {
+        return 0;
+    };
+
+    let mut released = 0;
+
+    filesystems.retain(|fs| fs.strong_count() != 0);
+
+    for locked_fs in filesystems.iter().filter_map(Weak::upgrade) {
+        if control.reason == ShrinkReason::LowMemory && released >= control.target {
+            break;
+        }
+
+        let Some(fs) = locked_fs.try_read() else {
+            continue;
+        };
+
+        released += fs.shrink_caches(&ShrinkControl {
+            target: control.target.saturating_sub(released),
+            ..*control
+        });
+    }
+
+    released
+}
+
+/// Device event handler, unmounting the filesystems located on a drive that was disconnected.
+///
+/// Files that are still open on such a filesystem keep a reference to it, but any further disk access fails with
+/// [`IOError::InvalidDevice`].
+fn handle_disk_event(event: &DeviceEvent) {
+    let DeviceEvent::DiskDetached { drive, .. } = *event else {
+        return;
+    };
+
+    let mut detached = Vec::new();
+    MOUNTED_FILESYSTEMS.lock().retain(|fs| {
+        let Some(fs) = fs.upgrade() else {
+            return false;
+        };
+
+        if fs.read().drive_id != drive {
+            return true;
+        }
+        detached.push(fs);
+
+        false
+    });
+
+    for fs in detached {
+        let mut fs = fs.write();
+        fs.force_unmount();
+
+        info!(
+            "ext4-fs",
+            "unmounted ext4 filesystem ({})",
+            partition_name(drive, fs.partition_id)
+        );
+    }
+}
+
 /// Internal representation of a `ext4` filesystem.
 ///
 /// Holds the main data structures required for the operation of the filesystem:
@@ -84,12 +265,21 @@ pub(crate) struct Ext4Fs {
     drive_id: AtaDeviceIdentifier,
     partition_id: usize,
 
+    /// Identifies this filesystem in the page cache.
+    page_cache_id: FsId,
+
     superblock: LockedSuperblock,
 
     descriptors_cache: RefCell<GroupDescriptorCache>,
 
     inode_cache: RefCell<InodeCache>,
 
+    /// Files currently open on this filesystem.
+    open_files: OpenFiles,
+
+    /// Cleared once the filesystem is unmounted: files can no longer be opened.
+    mounted: bool,
+
     fs_ptr: Weak<RwLock<Self>>,
 }
 
@@ -153,6 +343,43 @@ impl Ext4Fs {
         bg_desc_cache.load_cached_group_descriptor_or_insert(bg_number)
     }
 
+    /// Evicts entries from the metadata caches of this filesystem, as requested by a [`ShrinkControl`].
+    ///
+    /// When idle, caches are trimmed down to half of their limits. When running low on memory, least-recently used
+    /// entries are evicted until enough memory was released. Returns an estimation of the number of bytes released.
+    pub(super) fn shrink_caches(&self, control: &ShrinkControl) -> usize {
+        let limits = cache_limits();
+        let mut released = 0;
+
+        if let Ok(mut inode_cache) = self.inode_cache.try_borrow_mut() {
+            let count = match control.reason {
+                ShrinkReason::Idle => inode_cache.len().saturating_sub(limits.max_inodes / 2),
+                ShrinkReason::LowMemory => control.target.div_ceil(InodeCache::ENTRY_SIZE),
+            };
+
+            released += inode_cache.evict_lru(count) * InodeCache::ENTRY_SIZE;
+        }
+
+        if control.reason == ShrinkReason::LowMemory && released >= control.target {
+            return released;
+        }
+
+        if let Ok(mut descriptors_cache) = self.descriptors_cache.try_borrow_mut() {
+            let count = match control.reason {
+                ShrinkReason::Idle => descriptors_cache
+                    .len()
+                    .saturating_sub(limits.max_group_descriptors / 2),
+                ShrinkReason::LowMemory => {
+                    (control.target - released).div_ceil(GroupDescriptorCache::ENTRY_SIZE)
+                }
+            };
+
+            released += descriptors_cache.evict_lru(count) * GroupDescriptorCache::ENTRY_SIZE;
+        }
+
+        released
+    }
+
     /// Returns the root directory of this filesystem.
     ///
     /// # Errors
@@ -160,6 +387,10 @@ impl Ext4Fs {
     /// In case of any I/O error, a generic error will be returned. An error may mean that the filesystem
     /// is corrupted.
     pub(crate) fn root_dir(&self) -> IOResult<Directory> {
+        if !self.mounted {
+            return Err(IOError::InvalidDevice);
+        }
+
         Ok(Box::new(GenericExt4Directory {
             dir: Ext4Directory::from_inode_id(
                 self.fs_ptr.upgrade().ok_or(IOError::Unknown)?,
@@ -168,6 +399,179 @@ impl Ext4Fs {
         }))
     }
 
+    /// Opens the regular file at `path`, an absolute path from the root of this filesystem.
+    ///
+    /// Symbolic links are followed, including the last component of the path.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::NotFound`] if a component of the path does not exist, or is not of the expected type (a
+    /// directory, or a regular file for the last one). Returns [`IOError::InvalidDevice`] if the filesystem was
+    /// unmounted.
+    pub(crate) fn open(&self, path: &str) -> IOResult<File> {
+        let inode_id = self.lookup(path, true)?;
+
+        if !self.inode_metadata(inode_id)?.is_file() {
+            return Err(IOError::NotFound);
+        }
+
+        Ok(Box::new(Ext4File::from_inode_id(
+            self.fs_ptr.upgrade().ok_or(IOError::Unknown)?,
+            inode_id,
+        )?))
+    }
+
+    /// Returns the metadata of the file or directory at `path`, an absolute path from the root of this filesystem.
+    ///
+    /// Symbolic links are followed, including the last component of the path.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::NotFound`] if a component of the path does not exist, or if one of the components but the
+    /// last one is not a directory. Returns [`IOError::InvalidDevice`] if the filesystem was unmounted.
+    pub(crate) fn stat(&self, path: &str) -> IOResult<Metadata> {
+        self.inode_metadata(self.lookup(path, true)?)
+    }
+
+    /// Returns the target of the symbolic link at `path`, an absolute path from the root of this filesystem.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::NotFound`] if there is no file at `path`, and [`IOError::InvalidCommand`] if it is not a
+    /// symbolic link. Returns [`IOError::InvalidDevice`] if the filesystem was unmounted.
+    pub(crate) fn read_link(&self, path: &str) -> IOResult<String> {
+        self.read_link_inode(self.lookup(path, false)?)
+    }
+
+    /// Resolves `path`, an absolute path from the root of this filesystem, to the number of its inode.
+    ///
+    /// Symbolic links met along the path are followed (at most [`MAX_SYMLINK_FOLLOW`] of them), as well as the one at
+    /// the end of the path if `follow_last` is set. Relative targets are resolved from the directory containing the
+    /// link.
+    fn lookup(&self, path: &str, follow_last: bool) -> IOResult<InodeNumber> {
+        if !self.mounted {
+            return Err(IOError::InvalidDevice);
+        }
+
+        let fs_ptr = self.fs_ptr.upgrade().ok_or(IOError::Unknown)?;
+        let mut pending: Vec<String> = path_components(path);
+        let mut dir_id = InodeNumber::ROOT_DIR;
+        let mut inode_id = InodeNumber::ROOT_DIR;
+        let mut links = 0;
+
+        while let Some(name) = pending.pop() {
+            let mut dir = Ext4Directory::from_inode_id(fs_ptr.clone(), dir_id)
+                .map_err(|_| IOError::NotFound)?;
+            let entry = dir.search(name.as_str().into()).ok_or(IOError::NotFound)?;
+            let last = pending.is_empty();
+
+            if entry.file_type == Some(Ext4DirectoryFileType::SYMLINK) && (!last || follow_last) {
+                links += 1;
+                if links > MAX_SYMLINK_FOLLOW {
+                    return Err(IOError::NotFound);
+                }
+
+                let target = self.read_link_inode(entry.inode_number)?;
+                if target.starts_with('/') {
+                    dir_id = InodeNumber::ROOT_DIR;
+                }
+
+                pending.extend(path_components(&target));
+                inode_id = dir_id;
+                continue;
+            }
+
+            inode_id = entry.inode_number;
+            dir_id = inode_id;
+        }
+
+        Ok(inode_id)
+    }
+
+    /// Returns the metadata of the inode `inode_id`.
+    fn inode_metadata(&self, inode_id: InodeNumber) -> IOResult<Metadata> {
+        let inode = self.get_inode_strong(inode_id).ok_or(IOError::NotFound)?;
+        let metadata = inode.read().metadata();
+
+        Ok(metadata)
+    }
+
+    /// Reads the target of the symbolic link `inode_id`.
+    ///
+    /// Short targets are stored in the inode itself (fast symbolic links, or inline data), longer ones in a data
+    /// block.
+    fn read_link_inode(&self, inode_id: InodeNumber) -> IOResult<String> {
+        let inode = self.get_inode_strong(inode_id).ok_or(IOError::NotFound)?;
+        let guard = inode.read();
+
+        if guard.file_type() != FileType::SymbolicLink {
+            return Err(IOError::InvalidCommand);
+        }
+
+        let target = match guard.fast_symlink_target().or_else(|| guard.inline_data()) {
+            Some(target) => target,
+            None => {
+                drop(guard);
+
+                let mut target = Vec::new();
+                Ext4File::from_inode(
+                    self.fs_ptr.upgrade().ok_or(IOError::Unknown)?,
+                    &Arc::downgrade(&inode),
+                )?
+                .read_file(&mut target)?;
+
+                target
+            }
+        };
+
+        String::from_utf8(target).map_err(|e| IOError::Exception(Box::new(e.utf8_error())))
+    }
+
+    /// Unmounts this filesystem: releases its cached metadata and pages, and prevents any further file from being
+    /// opened.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`UmountError::Busy`] if some files are still open on this filesystem.
+    pub(crate) fn unmount(&mut self) -> CanFail<UmountError> {
+        if self.open_files.count() != 0 {
+            return Err(UmountError::Busy);
+        }
+
+        self.force_unmount();
+
+        Ok(())
+    }
+
+    /// Unmounts this filesystem even if some files are still open on it, once the drive it is located on is gone.
+    ///
+    /// The open files keep this structure allocated, but can no longer be read.
+    fn force_unmount(&mut self) {
+        self.mounted = false;
+        self.shrink_caches(&ShrinkControl::low_memory(usize::MAX));
+        pagecache::invalidate_fs(self.page_cache_id);
+    }
+
+    /// Returns the counter of the files open on this filesystem.
+    pub(super) fn open_files(&self) -> &OpenFiles {
+        &self.open_files
+    }
+
+    /// Returns the identifier of this filesystem in the page cache.
+    pub(crate) fn page_cache_id(&self) -> FsId {
+        self.page_cache_id
+    }
+
+    /// Returns the UUID of this filesystem, as stored in the superblock (in the order it is usually displayed).
+    pub(crate) fn uuid(&self) -> [u8; 16] {
+        bytemuck::cast(self.superblock.read().uuid)
+    }
+
+    /// Returns the volume label of this filesystem.
+    pub(crate) fn volume_label(&self) -> String {
+        self.superblock.read().volume_name.into()
+    }
+
     /// Allocates a growable buffer (a [`Vec`]), initialized with a capacity corresponding to the block size
     /// of the filesystem.
     pub(crate) fn allocate_blk(&self) -> Vec<u8> {
@@ -176,32 +580,64 @@ impl Ext4Fs {
     }
 
     fn read_blk_from_device(&self, blk_id: Ext4RealBlkId, buffer: &mut [u8]) -> CanFail<IOError> {
-        // With the new system for disk reads, this adds an unnecessary memcpy since the buffer is specified as an argument
-        // Either change the way block reads are implemented in ext4, or add a way to read from disk and store the retrieved
-        // bytes in a pre-specified buffer, as it was done previously.
+        self.read_blks_from_device(blk_id, 1, buffer)
+    }
+
+    /// Reads `count` physically contiguous blocks, starting at `blk_id`, and stores them in `buffer`.
+    ///
+    /// Issues as few disk requests as possible (a single one, unless the run is larger than what a single request can
+    /// transfer). The data is transferred straight into `buffer`, without any intermediate copy.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::InvalidCommand`] if the blocks lie past the end of the filesystem, or if the buffer is not
+    /// large enough to hold the blocks. May return any other variant of [`IOError`] in case of a disk failure.
+    pub(super) fn read_blks_from_device(
+        &self,
+        blk_id: Ext4RealBlkId,
+        count: u64,
+        buffer: &mut [u8],
+    ) -> CanFail<IOError> {
         let sb = self.superblock.read();
-        if blk_id > sb.blk_count() {
+        if blk_id + count > sb.blk_count() {
             return Err(IOError::InvalidCommand);
         }
 
+        let blk_size = sb.blk_size();
+        let byte_count = usize::try_from(count * blk_size).map_err(|_| IOError::InvalidCommand)?;
+        if buffer.len() < byte_count {
+            return Err(IOError::InvalidCommand);
+        }
+
+        fail_point!("ext4.read", return Err(IOError::Unknown));
+
         let mut drive = get_sata_drive(self.drive_id).ok_or(IOError::InvalidDevice)?;
         let partition_data = drive
-            .partitions()
-            .get(self.partition_id)
+            .partition(self.partition_id)
             .ok_or(IOError::Unknown)?
             .start_lba();
 
-        let sectors_count = sb.blk_size() / drive.logical_sector_size();
-        let start_lba = partition_data + (blk_id * sb.blk_size()) / drive.logical_sector_size();
+        let sector_size = drive.logical_sector_size();
+        let mut lba = partition_data + (blk_id * blk_size) / sector_size;
+        let mut sectors_left = (count * blk_size) / sector_size;
+        let mut buf_offset = 0;
 
-        let read_req = drive
-            .read(
-                start_lba,
+        while sectors_left != 0 {
+            let sectors_count = u64::min(sectors_left, MAX_SECTORS_PER_REQUEST);
+
+            let read_size =
+                usize::try_from(sectors_count * sector_size).expect("invalid read size");
+
+            drive.read_into(
+                lba,
                 u16::try_from(sectors_count).expect("invalid sectors count"),
-            )
-            .complete();
+                &mut buffer[buf_offset..buf_offset + read_size],
+            )?;
 
-        buffer.copy_from_slice(&read_req.data.ok_or(IOError::Unknown)?);
+            lba += sectors_count;
+            sectors_left -= sectors_count;
+            buf_offset += read_size;
+        }
 
         Ok(())
     }
@@ -243,7 +679,8 @@ impl Fs for Ext4Fs {
 
         info!(
             "ext4-fs",
-            "mounted ext4 filesystem on drive {drive_id} partition {partition_id}"
+            "mounted ext4 filesystem ({})",
+            partition_name(drive_id, partition_id)
         );
 
         info!(
@@ -260,20 +697,46 @@ impl Fs for Ext4Fs {
             RwLock::new(Ext4Fs {
                 drive_id,
                 partition_id,
+                page_cache_id: FsId::allocate(),
                 superblock: Arc::new(RwLock::new(sb)),
                 inode_cache: RefCell::new(InodeCache {
                     hashtable: HashMap::default(),
                     removal_policy: InodeCacheRemovalPolicy::default(),
+                    access_clock: 0,
                     fs: ptr.clone(),
                 }),
+                open_files: OpenFiles::default(),
+                mounted: true,
                 fs_ptr: ptr.clone(),
                 descriptors_cache: RefCell::new(GroupDescriptorCache {
                     descriptor_table: HashMap::default(),
+                    access_clock: 0,
                     fs: ptr.clone(),
                 }),
             })
         });
 
+        if shrinker::register_shrinker("ext4-fs", shrink_cached_metadata).is_err() {
+            info!("ext4-fs", "failed to register the metadata cache shrinker");
+        }
+        event::subscribe("ext4-fs", EventKind::DiskDetached.mask(), handle_disk_event);
+
+        let mut filesystems = MOUNTED_FILESYSTEMS.lock();
+        // Limits changed at runtime are kept across later mounts.
+        if filesystems.is_empty() {
+            set_cache_limits(Ext4CacheLimits::from_config());
+        }
+        filesystems.push(Arc::downgrade(&fs));
+        drop(filesystems);
+
+        let open_files = fs.read().open_files.clone();
+        register_mount(
+            drive_id,
+            partition_id,
+            PartFS::Ext4(Box::new(fs.clone())),
+            open_files,
+        );
+
         Ok(fs)
     }
 
@@ -643,11 +643,13 @@ macro_rules! ext4_sb_string_field {
         impl $struct_name {
             /// Returns an [`Iterator`] over the characters of the field.
             pub(crate) fn chars(&self) -> impl Iterator<Item = char> {
-                self.0
-                    .clone()
+                let bytes: alloc::vec::Vec<u8> =
+                    self.0.iter().copied().filter(|&b| b != 0).collect();
+
+                String::from_utf8_lossy(&bytes)
+                    .chars()
+                    .collect::<alloc::vec::Vec<char>>()
                     .into_iter()
-                    .filter(|&b| b != 0)
-                    .map(char::from)
             }
         }
 
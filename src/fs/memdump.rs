@@ -0,0 +1,493 @@
+//! Memory snapshots.
+//!
+//! A partition, named [`MEMDUMP_PARTITION_LABEL`] in the _GPT_ partition table, is reserved to store a snapshot of
+//! the kernel log and of the physical memory. Snapshots are taken on demand (for instance, from a debugging shell),
+//! or on panic if the `memdump.panic` option is set, so that heap corruptions can be analysed offline. Only the last
+//! snapshot is kept.
+//!
+//! The partition layout is the following:
+//!
+//! - first sector: [`MemDumpHeader`], followed by the [`MemDumpRegion`]s that were dumped.
+//! - following sectors: the content of the log, `log_len` bytes long.
+//! - following sectors (starting on a sector boundary): the memory pages, `data_len` bytes long. Each page is stored
+//!   as a [`PageRecord`], followed by its content.
+//!
+//! Pages are compressed: pages filled with zeroes are not stored at all, and the other pages are run-length encoded
+//! (see [`PageEncoding`]) when it makes them smaller. If the partition is too small, the snapshot is truncated, and
+//! [`MEMDUMP_FLAG_TRUNCATED`] is set.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use bytemuck::{bytes_of, from_bytes, Pod, Zeroable};
+use conquer_once::spin::OnceCell;
+
+use crate::drivers::generics::dev_disk::{get_sata_drive, DiskDevice, SataDevice};
+use crate::drivers::ide::AtaDeviceIdentifier;
+use crate::errors::{CanFail, IOError};
+use crate::fs::partitions::gpt::crc32_update;
+use crate::fs::partitions::registry::{find_partition, PartitionSelector};
+use crate::kernel_syms::PAGE_SIZE;
+use crate::klog::{klog_read, KLOG_SIZE};
+use crate::mem::e820::memory_regions;
+use crate::mem::{get_physical_memory_unchecked, PhyAddr};
+use crate::time::now;
+use crate::{error, info};
+
+/// Name of the _GPT_ partition reserved for memory snapshots.
+pub const MEMDUMP_PARTITION_LABEL: &str = "memdump";
+
+/// Maximum number of physical memory regions in a snapshot.
+pub const MEMDUMP_MAX_REGIONS: usize = 16;
+
+/// Set if the partition was too small to hold every page of the snapshot.
+pub const MEMDUMP_FLAG_TRUNCATED: u32 = 1 << 0;
+
+/// Signature found at the start of a valid [`MemDumpHeader`].
+const MEMDUMP_MAGIC: [u8; 8] = *b"FZMEMDMP";
+
+const MEMDUMP_VERSION: u32 = 1;
+
+/// Number of sectors written to the disk at once.
+const MEMDUMP_BATCH_SECTORS: usize = 128;
+
+static MEMDUMP_AREA: OnceCell<MemDumpArea> = OnceCell::uninit();
+
+/// Header of a snapshot, stored in the first sector of the partition.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub struct MemDumpHeader {
+    magic: [u8; 8],
+    version: u32,
+    flags: u32,
+    region_count: u32,
+
+    /// Length of the log stored after the header, in bytes.
+    log_len: u32,
+
+    /// Time elapsed since boot when the snapshot was taken, in microseconds.
+    uptime_us: u64,
+
+    /// Number of pages in the dumped regions.
+    pages: u64,
+
+    /// Number of pages filled with zeroes, which are not stored.
+    zero_pages: u64,
+
+    /// Length of the page records stored after the log, in bytes.
+    data_len: u64,
+
+    /// CRC32 of the page records.
+    data_crc32: u32,
+
+    /// CRC32 of the log.
+    log_crc32: u32,
+}
+
+/// A range of physical memory included in a snapshot.
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable, PartialEq, Eq)]
+#[repr(C)]
+pub struct MemDumpRegion {
+    /// Physical address of the first byte of the region (aligned on a page boundary).
+    pub base: u64,
+
+    /// Length of the region, in bytes (a multiple of the page size).
+    pub length: u64,
+}
+
+/// How the content of a page is stored after its [`PageRecord`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PageEncoding {
+    /// The page is stored as is.
+    Raw = 0,
+
+    /// The page is stored as a sequence of runs, each starting with a control byte `n`:
+    ///
+    /// - `n < 128`: the `n + 1` following bytes are copied as is.
+    /// - `n >= 128`: the following byte is repeated `n - 126` times.
+    RunLength = 1,
+}
+
+/// Header of a page stored in a snapshot.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub struct PageRecord {
+    /// Physical address of the page.
+    pub addr: u64,
+
+    /// [`PageEncoding`] of the content.
+    pub encoding: u32,
+
+    /// Length of the content following the record, in bytes.
+    pub len: u32,
+}
+
+/// Summary of a snapshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemDumpInfo {
+    /// Time elapsed since boot when the snapshot was taken, in microseconds.
+    pub uptime_us: u64,
+    pub pages: u64,
+    pub zero_pages: u64,
+
+    /// Number of bytes used to store the pages.
+    pub stored_bytes: u64,
+    pub truncated: bool,
+}
+
+/// Location of the reserved area on the disk.
+#[derive(Clone, Copy, Debug)]
+struct MemDumpArea {
+    drive_id: AtaDeviceIdentifier,
+    start_lba: u64,
+    sectors: u64,
+    sector_size: usize,
+}
+
+impl MemDumpArea {
+    fn read_header(&self) -> Result<MemDumpHeader, IOError> {
+        let drive = get_sata_drive(self.drive_id).ok_or(IOError::InvalidDevice)?;
+        let mut sector = vec![0u8; self.sector_size];
+        drive.read_into(self.start_lba, 1, &mut sector)?;
+
+        Ok(*from_bytes::<MemDumpHeader>(
+            &sector[..core::mem::size_of::<MemDumpHeader>()],
+        ))
+    }
+
+    fn write_header(&self, header: &MemDumpHeader, regions: &[MemDumpRegion]) -> CanFail<IOError> {
+        let drive = get_sata_drive(self.drive_id).ok_or(IOError::InvalidDevice)?;
+        let mut sector = vec![0u8; self.sector_size];
+        let header_len = core::mem::size_of::<MemDumpHeader>();
+        sector[..header_len].copy_from_slice(bytes_of(header));
+
+        for (i, region) in regions.iter().enumerate() {
+            let offset = header_len + i * core::mem::size_of::<MemDumpRegion>();
+            sector[offset..offset + core::mem::size_of::<MemDumpRegion>()]
+                .copy_from_slice(bytes_of(region));
+        }
+
+        drive.write_from(self.start_lba, &sector)?;
+        drive.flush()
+    }
+}
+
+/// Buffers the content of a snapshot, and writes it to the disk sector by sector.
+struct DumpWriter {
+    drive: SataDevice,
+    sector_size: usize,
+
+    /// Next sector to write.
+    lba: u64,
+    end_lba: u64,
+    buffer: Vec<u8>,
+    crc32: u32,
+    len: u64,
+}
+
+impl DumpWriter {
+    fn new(area: &MemDumpArea, start_lba: u64) -> Result<Self, IOError> {
+        Ok(Self {
+            drive: get_sata_drive(area.drive_id).ok_or(IOError::InvalidDevice)?,
+            sector_size: area.sector_size,
+            lba: start_lba,
+            end_lba: area.start_lba + area.sectors,
+            buffer: Vec::with_capacity(MEMDUMP_BATCH_SECTORS * area.sector_size),
+            crc32: 0,
+            len: 0,
+        })
+    }
+
+    /// Number of bytes that can still be written.
+    fn remaining(&self) -> u64 {
+        let sectors = self.end_lba.saturating_sub(self.lba);
+
+        (sectors * self.sector_size as u64).saturating_sub(self.buffer.len() as u64)
+    }
+
+    fn write(&mut self, data: &[u8]) -> CanFail<IOError> {
+        self.buffer.extend_from_slice(data);
+        self.crc32 = crc32_update(self.crc32, data);
+        self.len += data.len() as u64;
+
+        let batch_len = MEMDUMP_BATCH_SECTORS * self.sector_size;
+        while self.buffer.len() >= batch_len {
+            self.drive.write_from(self.lba, &self.buffer[..batch_len])?;
+            self.buffer.drain(..batch_len);
+            self.lba += MEMDUMP_BATCH_SECTORS as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the buffered content, padded to a sector boundary.
+    ///
+    /// Returns the next sector to write.
+    fn finish(mut self) -> Result<u64, IOError> {
+        if !self.buffer.is_empty() {
+            let sectors = self.buffer.len().div_ceil(self.sector_size);
+            self.buffer.resize(sectors * self.sector_size, 0);
+            self.drive.write_from(self.lba, &self.buffer)?;
+            self.lba += sectors as u64;
+        }
+
+        Ok(self.lba)
+    }
+}
+
+/// Locates the memory snapshot partition, and reports the snapshot it holds, if any.
+///
+/// Must be called once the disk drives were initialized, and their partition tables scanned.
+pub fn memdump_init() {
+    let selector = PartitionSelector::PartLabel(String::from(MEMDUMP_PARTITION_LABEL));
+
+    let Some((drive_id, partition_id)) = find_partition(&selector) else {
+        info!(
+            "memdump",
+            "no '{}' partition, memory snapshots are disabled", MEMDUMP_PARTITION_LABEL
+        );
+        return;
+    };
+
+    let Some(drive) = get_sata_drive(drive_id) else {
+        return;
+    };
+    let Some(partition) = drive.partition(partition_id) else {
+        return;
+    };
+
+    let area = MemDumpArea {
+        drive_id,
+        start_lba: partition.start_lba(),
+        sectors: partition.size_in_sectors(),
+        sector_size: drive.logical_sector_size() as usize,
+    };
+
+    let header_len = core::mem::size_of::<MemDumpHeader>()
+        + MEMDUMP_MAX_REGIONS * core::mem::size_of::<MemDumpRegion>();
+    if area.sectors < 2 || area.sector_size < header_len {
+        error!("memdump", "memory snapshot partition is too small");
+        return;
+    }
+
+    MEMDUMP_AREA.init_once(|| area);
+
+    match memdump_info() {
+        Ok(Some(snapshot)) => {
+            info!(
+                "memdump",
+                "partition holds a snapshot of {} pages, taken {} s after boot",
+                snapshot.pages,
+                snapshot.uptime_us / 1_000_000
+            );
+        }
+        Ok(None) => {
+            info!(
+                "memdump",
+                "memory snapshot partition found (sectors = {})", area.sectors
+            );
+        }
+        Err(_) => {
+            error!("memdump", "failed to read the memory snapshot partition");
+        }
+    }
+}
+
+/// Returns a summary of the snapshot stored on the disk, if any.
+///
+/// # Errors
+///
+/// Returns [`IOError::InvalidDevice`] if no memory snapshot partition was found. May return any other variant of
+/// [`IOError`] in case of a device failure.
+pub fn memdump_info() -> Result<Option<MemDumpInfo>, IOError> {
+    let area = MEMDUMP_AREA.get().ok_or(IOError::InvalidDevice)?;
+    let header = area.read_header()?;
+
+    if header.magic != MEMDUMP_MAGIC || header.version != MEMDUMP_VERSION {
+        return Ok(None);
+    }
+
+    Ok(Some(MemDumpInfo {
+        uptime_us: header.uptime_us,
+        pages: header.pages,
+        zero_pages: header.zero_pages,
+        stored_bytes: header.data_len,
+        truncated: header.flags & MEMDUMP_FLAG_TRUNCATED != 0,
+    }))
+}
+
+/// Writes a snapshot of every usable region of physical memory (see [`memory_regions`]), along with the kernel log.
+///
+/// # Errors
+///
+/// See [`memdump_write`].
+pub fn memdump_write_all() -> Result<MemDumpInfo, IOError> {
+    let regions: Vec<MemDumpRegion> = memory_regions()
+        .lock()
+        .usable()
+        .map(|region| MemDumpRegion {
+            base: region.base,
+            length: region.length,
+        })
+        .collect();
+
+    memdump_write(&regions)
+}
+
+/// Writes a snapshot of the given regions of physical memory, along with the kernel log.
+///
+/// Regions are extended to page boundaries. Only the first [`MEMDUMP_MAX_REGIONS`] regions are dumped.
+///
+/// # Errors
+///
+/// Returns [`IOError::InvalidDevice`] if no memory snapshot partition was found. May return any other variant of
+/// [`IOError`] in case of a device failure.
+pub fn memdump_write(regions: &[MemDumpRegion]) -> Result<MemDumpInfo, IOError> {
+    let area = MEMDUMP_AREA.get().ok_or(IOError::InvalidDevice)?;
+    let page_size = PAGE_SIZE as u64;
+
+    let regions: Vec<MemDumpRegion> = regions
+        .iter()
+        .take(MEMDUMP_MAX_REGIONS)
+        .map(|region| {
+            let base = region.base & !(page_size - 1);
+            let end = (region.base + region.length).next_multiple_of(page_size);
+
+            MemDumpRegion {
+                base,
+                length: end - base,
+            }
+        })
+        .collect();
+
+    // The previous snapshot is invalidated first, so that a header is never valid without its content.
+    area.write_header(&MemDumpHeader::zeroed(), &[])?;
+
+    // At least one sector is left for the pages.
+    let log_capacity = (area.sectors.saturating_sub(2) as usize).saturating_mul(area.sector_size);
+    let mut log = vec![0u8; usize::min(KLOG_SIZE, log_capacity)];
+    let log_len = klog_read(&mut log);
+    log.truncate(log_len);
+
+    let mut writer = DumpWriter::new(area, area.start_lba + 1)?;
+    writer.write(&log)?;
+    let log_crc32 = writer.crc32;
+    let data_lba = writer.finish()?;
+
+    let mut writer = DumpWriter::new(area, data_lba)?;
+    let mut header = MemDumpHeader {
+        magic: MEMDUMP_MAGIC,
+        version: MEMDUMP_VERSION,
+        flags: 0,
+        region_count: regions.len() as u32,
+        log_len: log_len as u32,
+        uptime_us: now() as u64,
+        pages: 0,
+        zero_pages: 0,
+        data_len: 0,
+        data_crc32: 0,
+        log_crc32,
+    };
+
+    let mut encoded = Vec::with_capacity(PAGE_SIZE + PAGE_SIZE / 128 + 1);
+    'regions: for region in &regions {
+        for addr in (region.base..region.base + region.length).step_by(PAGE_SIZE) {
+            header.pages += 1;
+
+            let page = unsafe {
+                core::slice::from_raw_parts(
+                    get_physical_memory_unchecked(PhyAddr::new(addr)),
+                    PAGE_SIZE,
+                )
+            };
+
+            if page.iter().all(|&byte| byte == 0) {
+                header.zero_pages += 1;
+                continue;
+            }
+
+            encoded.clear();
+            rle_encode(page, &mut encoded);
+            let (encoding, content) = if encoded.len() < PAGE_SIZE {
+                (PageEncoding::RunLength, encoded.as_slice())
+            } else {
+                (PageEncoding::Raw, page)
+            };
+
+            let record = PageRecord {
+                addr,
+                encoding: encoding as u32,
+                len: content.len() as u32,
+            };
+
+            let record_len = (core::mem::size_of::<PageRecord>() + content.len()) as u64;
+            if record_len > writer.remaining() {
+                header.flags |= MEMDUMP_FLAG_TRUNCATED;
+                break 'regions;
+            }
+
+            writer.write(bytes_of(&record))?;
+            writer.write(content)?;
+        }
+    }
+
+    header.data_len = writer.len;
+    header.data_crc32 = writer.crc32;
+    writer.finish()?;
+
+    area.write_header(&header, &regions)?;
+
+    let info = MemDumpInfo {
+        uptime_us: header.uptime_us,
+        pages: header.pages,
+        zero_pages: header.zero_pages,
+        stored_bytes: header.data_len,
+        truncated: header.flags & MEMDUMP_FLAG_TRUNCATED != 0,
+    };
+
+    info!(
+        "memdump",
+        "snapshot written ({} pages, {} zero pages, {} KiB stored{})",
+        info.pages,
+        info.zero_pages,
+        info.stored_bytes / 1024,
+        if info.truncated { ", truncated" } else { "" }
+    );
+
+    Ok(info)
+}
+
+/// Run-length encodes `data` (see [`PageEncoding::RunLength`]), appending the result to `out`.
+fn rle_encode(data: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    let mut literal_start = 0;
+
+    let flush_literals = |out: &mut Vec<u8>, literals: &[u8]| {
+        for chunk in literals.chunks(128) {
+            out.push((chunk.len() - 1) as u8);
+            out.extend_from_slice(chunk);
+        }
+    };
+
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take(129)
+            .take_while(|&&byte| byte == data[i])
+            .count();
+
+        if run >= 3 {
+            flush_literals(out, &data[literal_start..i]);
+            out.push((run + 126) as u8);
+            out.push(data[i]);
+
+            i += run;
+            literal_start = i;
+        } else {
+            i += run;
+        }
+    }
+
+    flush_literals(out, &data[literal_start..]);
+}
@@ -19,9 +19,20 @@ use spin::RwLock;
 
 use crate::errors::{IOError, MountError};
 use crate::fs::ext4::LockedExt4Fs;
+use crate::fs::pagecache::PageKey;
+use crate::mem::PhyAddr;
+use crate::time::UnixTimestamp;
 
 pub(crate) mod ext4;
+pub mod memdump;
+pub mod mount;
+pub mod notify;
+pub mod pagecache;
 pub mod partitions;
+pub mod pstore;
+pub mod vfs;
+
+pub use ext4::fsck::fsck_report;
 
 /// Base [`Result`] type for I/O operations, using the corresponding custom error type.
 pub type IOResult<T> = Result<T, IOError>;
@@ -91,6 +102,14 @@ impl FsFile for Box<dyn FsFile> {
     fn extend(&mut self, size: usize) -> IOResult<usize> {
         self.as_mut().extend(size)
     }
+
+    fn map_page(&mut self, index: u64) -> IOResult<(PageKey, PhyAddr)> {
+        self.as_mut().map_page(index)
+    }
+
+    fn metadata(&self) -> IOResult<Metadata> {
+        self.as_ref().metadata()
+    }
 }
 
 /// `Seek` provides a way to move the internal cursor of a file, or to retrieve the current
@@ -108,6 +127,98 @@ pub enum Seek {
     Forward(usize),
 }
 
+/// Type of a file, as stored in its [`Metadata`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    Regular,
+    Directory,
+    SymbolicLink,
+    CharacterDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+}
+
+impl FileType {
+    /// Character used for this file type by `ls -l`.
+    pub fn symbol(self) -> char {
+        match self {
+            Self::Regular => '-',
+            Self::Directory => 'd',
+            Self::SymbolicLink => 'l',
+            Self::CharacterDevice => 'c',
+            Self::BlockDevice => 'b',
+            Self::Fifo => 'p',
+            Self::Socket => 's',
+        }
+    }
+}
+
+/// File-system independent metadata of a file or a directory, as returned by [`FsFile::metadata`] or
+/// [`vfs::stat`].
+#[derive(Clone, Copy, Debug)]
+pub struct Metadata {
+    pub file_type: FileType,
+
+    /// Permission bits (`0o7777` mask of the Unix file mode, including the set-user-ID, set-group-ID and sticky
+    /// bits).
+    pub permissions: u16,
+
+    /// Owner user ID.
+    pub uid: u32,
+
+    /// Owner group ID.
+    pub gid: u32,
+
+    /// Size of the file, in bytes.
+    pub size: u64,
+
+    /// Number of hard links to the file.
+    pub links: u32,
+
+    /// Identifier of the file in its filesystem (its inode number).
+    pub inode: u64,
+
+    /// Time of the last access to the file content.
+    pub accessed: UnixTimestamp,
+
+    /// Time of the last modification of the file content.
+    pub modified: UnixTimestamp,
+
+    /// Time of the last change of the file metadata.
+    pub changed: UnixTimestamp,
+}
+
+impl Metadata {
+    /// Returns `true` if these are the metadata of a directory.
+    pub fn is_dir(&self) -> bool {
+        self.file_type == FileType::Directory
+    }
+
+    /// Returns `true` if these are the metadata of a regular file.
+    pub fn is_file(&self) -> bool {
+        self.file_type == FileType::Regular
+    }
+
+    /// Returns the file mode in its symbolic form, as displayed by `ls -l` (for instance, `drwxr-xr-x`).
+    pub fn mode_string(&self) -> String {
+        const SYMBOLS: [char; 3] = ['r', 'w', 'x'];
+
+        let mut mode = String::with_capacity(10);
+        mode.push(self.file_type.symbol());
+
+        for bit in (0..9).rev() {
+            if self.permissions & (1 << bit) != 0 {
+                mode.push(SYMBOLS[2 - bit % 3]);
+            } else {
+                mode.push('-');
+            }
+        }
+
+        mode
+    }
+}
+
 /// `DirEntry` are returned when iterating over a [`Directory`].
 ///
 /// They can either represent a [`File`], or a [`Directory`].
@@ -146,12 +257,22 @@ pub trait FsDirectory: Iterator + Debug {
     /// In case of any I/O error, a generic error will be returned. An error may mean that the file
     /// is corrupted.
     fn size(&self) -> IOResult<usize>;
+
+    /// Returns the metadata of the directory (owner, permissions, timestamps, ...).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::InvalidCommand`] if the filesystem does not store such metadata.
+    fn metadata(&self) -> IOResult<Metadata> {
+        Err(IOError::InvalidCommand)
+    }
 }
 
 /// A trait to represent a file-system independent file.
 ///
-/// This offers basic functionalities when working with files.
-pub trait FsFile: Debug {
+/// This offers basic functionalities when working with files. Files mapped in an address space are shared by every
+/// task of the process, and must therefore be `Send`.
+pub trait FsFile: Debug + Send {
     /// Read some bytes from the file, and put them inside the specified buffer.
     /// Starts reading from the current position of the internal cursor.
     ///
@@ -206,6 +327,30 @@ pub trait FsFile: Debug {
     /// In case of any I/O error, a generic error will be returned.
     fn extend(&mut self, size: usize) -> IOResult<usize>;
 
+    /// Returns the frame of the page cache holding the page `index` of the file (the page starting at byte
+    /// `index * PAGE_SIZE`), reading it if it is not cached yet.
+    ///
+    /// The page is pinned in the cache, and must be released with [`pagecache::unpin`] once it is no longer mapped.
+    /// The part of the page past the end of the file is zero-filled.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::InvalidCommand`] if the file cannot be accessed through the page cache, or any I/O error
+    /// that happened while reading the page.
+    fn map_page(&mut self, index: u64) -> IOResult<(PageKey, PhyAddr)> {
+        let _ = index;
+        Err(IOError::InvalidCommand)
+    }
+
+    /// Returns the metadata of the file (owner, permissions, timestamps, ...).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::InvalidCommand`] if the filesystem does not store such metadata.
+    fn metadata(&self) -> IOResult<Metadata> {
+        Err(IOError::InvalidCommand)
+    }
+
     /// Reads the whole file, and fill the provided buffer `buf`.
     ///
     /// # Safety
@@ -0,0 +1,236 @@
+//! Registry of the mounted filesystems.
+//!
+//! Every filesystem mounted from a partition is recorded here, and identified by a [`MountId`]. The registry keeps a
+//! strong reference to the filesystem: it remains allocated (and its caches populated) until it is unmounted with
+//! [`umount`].
+//!
+//! Each filesystem counts the files currently open on it ([`OpenFiles`]): a filesystem cannot be unmounted while some
+//! of its files are still in use. Filesystems located on a drive that gets disconnected are removed from the registry
+//! regardless of their open files.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::fs::mount::{mount_of, umount};
+//!
+//! if let Some(mount_id) = mount_of(drive_id, 0) {
+//!     umount(mount_id)?;
+//! }
+//! ```
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::fmt::{self, Display};
+use core::num::ParseIntError;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use spin::RwLock;
+
+use crate::drivers::devtree::partition_name;
+use crate::drivers::ide::AtaDeviceIdentifier;
+use crate::errors::{CanFail, UmountError};
+use crate::event::{self, DeviceEvent, EventKind};
+use crate::fs::notify::{publish_fs_event, FsEvent};
+use crate::fs::PartFS;
+use crate::info;
+
+static MOUNT_TABLE: RwLock<BTreeMap<MountId, MountEntry>> = RwLock::new(BTreeMap::new());
+
+static NEXT_MOUNT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Identifies a mounted filesystem in the registry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MountId(u32);
+
+impl Display for MountId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for MountId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+/// Number of files open on a filesystem.
+///
+/// Cloning an `OpenFiles` shares the same counter.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct OpenFiles(Arc<AtomicUsize>);
+
+impl OpenFiles {
+    /// Records a newly opened file: the returned [`FileRef`] must be kept alive as long as the file is open.
+    pub(crate) fn acquire(&self) -> FileRef {
+        self.0.fetch_add(1, Ordering::AcqRel);
+
+        FileRef(self.0.clone())
+    }
+
+    /// Returns the number of files currently open.
+    pub(crate) fn count(&self) -> usize {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// Reference held by an open file on its filesystem (see [`OpenFiles::acquire`]).
+///
+/// The file is considered closed once every clone of its `FileRef` is dropped.
+#[derive(Debug)]
+pub(crate) struct FileRef(Arc<AtomicUsize>);
+
+impl Clone for FileRef {
+    fn clone(&self) -> Self {
+        self.0.fetch_add(1, Ordering::AcqRel);
+
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for FileRef {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[derive(Clone)]
+struct MountEntry {
+    drive_id: AtaDeviceIdentifier,
+    partition_id: usize,
+    fs: PartFS,
+    open_files: OpenFiles,
+}
+
+/// Description of a mounted filesystem, as returned by [`mounts`].
+#[derive(Clone, Debug)]
+pub struct MountInfo {
+    pub mount_id: MountId,
+
+    /// Drive on which the filesystem is located.
+    pub drive_id: AtaDeviceIdentifier,
+
+    /// Index of the partition in the list of partitions of the drive.
+    pub partition_id: usize,
+
+    /// Type of the filesystem (`ext4`, ...).
+    pub fs_type: &'static str,
+
+    /// Number of files currently open on the filesystem.
+    pub open_files: usize,
+}
+
+/// Records a newly mounted filesystem, and returns its identifier.
+///
+/// Called by the filesystem implementations, once a filesystem is successfully mounted.
+pub(crate) fn register_mount(
+    drive_id: AtaDeviceIdentifier,
+    partition_id: usize,
+    fs: PartFS,
+    open_files: OpenFiles,
+) -> MountId {
+    // Subscribing again is harmless, it simply replaces the previous subscriber.
+    event::subscribe("mount", EventKind::DiskDetached.mask(), handle_disk_event);
+
+    let mount_id = MountId(NEXT_MOUNT_ID.fetch_add(1, Ordering::Relaxed));
+    MOUNT_TABLE.write().insert(
+        mount_id,
+        MountEntry {
+            drive_id,
+            partition_id,
+            fs,
+            open_files,
+        },
+    );
+    publish_fs_event(&FsEvent::Mounted(mount_id));
+
+    mount_id
+}
+
+/// Unmounts the filesystem `mount_id`.
+///
+/// The cached data of the filesystem is released, and the filesystem is removed from the registry. Partitions that
+/// were referencing it can no longer be used to open files.
+///
+/// # Errors
+///
+/// Returns [`UmountError::NotMounted`] if no filesystem is identified by `mount_id`, or [`UmountError::Busy`] if some
+/// of its files are still open.
+pub fn umount(mount_id: MountId) -> CanFail<UmountError> {
+    let mut table = MOUNT_TABLE.write();
+    let entry = table.get(&mount_id).ok_or(UmountError::NotMounted)?;
+
+    match &entry.fs {
+        PartFS::Ext4(fs) => fs.write().unmount()?,
+        PartFS::Unknown => (),
+    }
+
+    info!(
+        "mount",
+        "unmounted filesystem {} ({})",
+        mount_id,
+        partition_name(entry.drive_id, entry.partition_id)
+    );
+    table.remove(&mount_id);
+    drop(table);
+
+    publish_fs_event(&FsEvent::Unmounted(mount_id));
+
+    Ok(())
+}
+
+/// Returns the identifier of the filesystem mounted from the partition `partition_id` of the drive `drive_id`.
+pub fn mount_of(drive_id: AtaDeviceIdentifier, partition_id: usize) -> Option<MountId> {
+    MOUNT_TABLE
+        .read()
+        .iter()
+        .find(|(_, entry)| entry.drive_id == drive_id && entry.partition_id == partition_id)
+        .map(|(mount_id, _)| *mount_id)
+}
+
+/// Returns the filesystem identified by `mount_id`, if it is still mounted.
+pub(crate) fn mounted_fs(mount_id: MountId) -> Option<PartFS> {
+    MOUNT_TABLE
+        .read()
+        .get(&mount_id)
+        .map(|entry| entry.fs.clone())
+}
+
+/// Returns a description of every mounted filesystem.
+pub fn mounts() -> Vec<MountInfo> {
+    MOUNT_TABLE
+        .read()
+        .iter()
+        .map(|(mount_id, entry)| MountInfo {
+            mount_id: *mount_id,
+            drive_id: entry.drive_id,
+            partition_id: entry.partition_id,
+            fs_type: match entry.fs {
+                PartFS::Ext4(_) => "ext4",
+                PartFS::Unknown => "unknown",
+            },
+            open_files: entry.open_files.count(),
+        })
+        .collect()
+}
+
+/// Device event handler, removing the filesystems located on a drive that was disconnected.
+fn handle_disk_event(event: &DeviceEvent) {
+    if let DeviceEvent::DiskDetached { drive, .. } = *event {
+        let mut removed = Vec::new();
+
+        MOUNT_TABLE.write().retain(|mount_id, entry| {
+            if entry.drive_id == drive {
+                removed.push(*mount_id);
+            }
+
+            entry.drive_id != drive
+        });
+
+        for mount_id in removed {
+            publish_fs_event(&FsEvent::Unmounted(mount_id));
+        }
+    }
+}
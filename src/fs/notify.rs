@@ -0,0 +1,132 @@
+//! Filesystem change notifications.
+//!
+//! Kernel components can watch a path (in the form used by [`vfs`](crate::fs::vfs), `/<mount id>/...`) and get
+//! called back when something happens to it: the filesystem containing it gets mounted or unmounted, or the file (or
+//! a file below the watched directory) is modified.
+//!
+//! Handlers are called synchronously by the component publishing the event, without any filesystem lock held: they
+//! may access the filesystems, but should return quickly.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::fs::notify::{watch, FsEvent};
+//!
+//! fn reload_config(event: &FsEvent) {
+//!     info!("config", "configuration changed: {event:?}");
+//! }
+//!
+//! watch("config-reload", "/1/boot/fzboot.cfg", reload_config);
+//! ```
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use spin::Mutex;
+
+use crate::fs::mount::MountId;
+
+/// Event published when a filesystem, or a file, changes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FsEvent {
+    /// A filesystem was mounted.
+    Mounted(MountId),
+
+    /// A filesystem was unmounted, or the drive containing it was disconnected.
+    Unmounted(MountId),
+
+    /// The content or the metadata of the file at this path was modified.
+    Modified(String),
+}
+
+impl FsEvent {
+    /// Returns the path concerned by this event: the root of the filesystem for mount events.
+    pub fn path(&self) -> String {
+        match self {
+            Self::Mounted(mount_id) | Self::Unmounted(mount_id) => format!("/{mount_id}"),
+            Self::Modified(path) => path.clone(),
+        }
+    }
+}
+
+/// Callback called for each [`FsEvent`] concerning a watched path.
+pub type FsEventHandler = fn(&FsEvent);
+
+#[derive(Clone)]
+struct Watch {
+    name: &'static str,
+    path: String,
+    handler: FsEventHandler,
+}
+
+static WATCHES: Mutex<Vec<Watch>> = Mutex::new(Vec::new());
+
+/// Registers `handler`, called for every event concerning `path`.
+///
+/// If `path` is a directory, modifications of the files it contains (at any depth) are reported as well. Mount
+/// events are reported to every watch located in the mounted filesystem, and to the watches of `/`.
+///
+/// Registering a watch under a name that is already in use replaces the previous watch.
+pub fn watch(name: &'static str, path: &str, handler: FsEventHandler) {
+    let path = normalize(path);
+    let mut watches = WATCHES.lock();
+
+    match watches.iter_mut().find(|watch| watch.name == name) {
+        Some(watch) => {
+            watch.path = path;
+            watch.handler = handler;
+        }
+        None => watches.push(Watch {
+            name,
+            path,
+            handler,
+        }),
+    }
+}
+
+/// Unregisters a watch, previously registered using [`watch`].
+pub fn unwatch(name: &'static str) {
+    WATCHES.lock().retain(|watch| watch.name != name);
+}
+
+/// Calls the handler of every watch concerned by `event`.
+pub(crate) fn publish_fs_event(event: &FsEvent) {
+    let event_path = normalize(&event.path());
+    let watches = WATCHES.lock().clone();
+
+    for watch in watches {
+        let concerned = match event {
+            FsEvent::Mounted(_) | FsEvent::Unmounted(_) => {
+                is_below(&watch.path, &event_path) || is_below(&event_path, &watch.path)
+            }
+            FsEvent::Modified(_) => is_below(&event_path, &watch.path),
+        };
+
+        if concerned {
+            (watch.handler)(event);
+        }
+    }
+}
+
+/// Checks whether `path` is `dir`, or a path below `dir`.
+fn is_below(path: &str, dir: &str) -> bool {
+    dir == "/"
+        || path == dir
+        || path
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Returns `path` as an absolute path, without redundant separators.
+fn normalize(path: &str) -> String {
+    let components: Vec<&str> = path.split('/').filter(|name| !name.is_empty()).collect();
+
+    if components.is_empty() {
+        return "/".to_string();
+    }
+
+    format!("/{}", components.join("/"))
+}
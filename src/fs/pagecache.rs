@@ -0,0 +1,325 @@
+//! Page cache.
+//!
+//! Holds the content of files in frames of physical memory, one page at a time. A page is identified by the
+//! filesystem holding the file, the inode of the file, and the index of the page in the file (see [`PageKey`]).
+//!
+//! The cache is shared by file reads ([`FsFile::read`]) and file mappings ([`MappedFile`]): a page of a file is only
+//! read once from the disk, whichever way it is accessed first. Pages are filled by the filesystem on a cache miss.
+//!
+//! Clean pages that are not pinned (pages mapped in an address space are pinned) are released by a shrinker, when
+//! running low on memory or when the cache grew past [`PAGE_CACHE_IDLE_LIMIT`] pages. Modified pages are marked as
+//! dirty ([`mark_dirty`]), and are kept until they are written back by the filesystem (see [`writeback_inode`]).
+//! Filesystems do not have a write path yet, so pages currently never become dirty.
+//!
+//! [`FsFile::read`]: super::FsFile::read
+//! [`MappedFile`]: crate::mem::filemap::MappedFile
+
+use core::{
+    ptr, slice,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use spin::Mutex;
+
+use crate::{
+    errors::{CanFail, IOError},
+    info,
+    kernel_syms::PAGE_SIZE,
+    mem::{
+        get_physical_memory,
+        shrinker::{self, ShrinkControl, ShrinkReason},
+        stats::{self, MemoryConsumer},
+        PhyAddr,
+    },
+    x86::paging::page_alloc::frame_alloc::{alloc_page, free_page, FrameAllocation},
+};
+
+use super::IOResult;
+
+/// Number of pages above which the shrinker trims the cache when the system is idle.
+pub const PAGE_CACHE_IDLE_LIMIT: usize = 1024;
+
+static PAGE_CACHE: Mutex<PageCache> = Mutex::new(PageCache::new());
+
+static NEXT_FS_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies a mounted filesystem in the page cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FsId(u64);
+
+impl FsId {
+    /// Returns a new identifier, to be used by a newly mounted filesystem.
+    pub fn allocate() -> Self {
+        Self(NEXT_FS_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Identifies a page of a file in the page cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PageKey {
+    pub fs: FsId,
+    pub inode: u64,
+
+    /// Index of the page in the file (the page starts at byte `index * PAGE_SIZE` of the file).
+    pub index: u64,
+}
+
+/// Writes a dirty page back to the file it belongs to.
+pub type Writeback = fn(key: PageKey, page: &[u8]) -> CanFail<IOError>;
+
+#[derive(Debug)]
+struct CachedPage {
+    frame: PhyAddr,
+    dirty: bool,
+
+    /// Number of users preventing this page from being released (such as address spaces mapping it).
+    pins: usize,
+    last_access: u64,
+}
+
+struct PageCache {
+    pages: BTreeMap<PageKey, CachedPage>,
+    access_clock: u64,
+}
+
+impl PageCache {
+    const fn new() -> Self {
+        Self {
+            pages: BTreeMap::new(),
+            access_clock: 0,
+        }
+    }
+
+    /// Returns the frame holding the page `key` if it is cached, marking it as accessed (and pinning it if `pin` is
+    /// set).
+    fn lookup(&mut self, key: PageKey, pin: bool) -> Option<PhyAddr> {
+        self.access_clock += 1;
+        let clock = self.access_clock;
+
+        let page = self.pages.get_mut(&key)?;
+        page.last_access = clock;
+        page.pins += usize::from(pin);
+
+        Some(page.frame)
+    }
+
+    /// Inserts `frame`, freshly filled with the content of the page `key`, and returns the frame holding the page.
+    ///
+    /// If the page was inserted while `frame` was being filled, `frame` is released and the cached page is used
+    /// instead.
+    fn insert(&mut self, key: PageKey, frame: PhyAddr, pin: bool) -> PhyAddr {
+        if let Some(cached) = self.lookup(key, pin) {
+            free_page(FrameAllocation {
+                start: frame,
+                length: PAGE_SIZE,
+            });
+            return cached;
+        }
+
+        if self.pages.is_empty()
+            && shrinker::register_shrinker("page-cache", shrink_page_cache).is_err()
+        {
+            info!("page-cache", "failed to register the page cache shrinker");
+        }
+
+        stats::charge(MemoryConsumer::Caches, PAGE_SIZE);
+        self.pages.insert(
+            key,
+            CachedPage {
+                frame,
+                dirty: false,
+                pins: usize::from(pin),
+                last_access: self.access_clock,
+            },
+        );
+
+        frame
+    }
+
+    /// Releases up to `count` clean and unpinned pages, least recently used first, and returns how many were released.
+    fn evict_lru(&mut self, count: usize) -> usize {
+        let mut candidates: Vec<(u64, PageKey)> = self
+            .pages
+            .iter()
+            .filter(|(_, page)| page.pins == 0 && !page.dirty)
+            .map(|(key, page)| (page.last_access, *key))
+            .collect();
+        candidates.sort_unstable();
+
+        let mut evicted = 0;
+        for (_, key) in candidates.into_iter().take(count) {
+            if let Some(page) = self.pages.remove(&key) {
+                release_frame(page.frame);
+                evicted += 1;
+            }
+        }
+
+        evicted
+    }
+}
+
+/// Returns the frame holding the page `key`, calling `fill` to read it on a cache miss.
+///
+/// `fill` receives the (zeroed) content of the new page. If `pin` is set, the page is pinned, and stays in the cache
+/// until it is released with [`unpin`].
+///
+/// # Errors
+///
+/// Returns an error if no frame could be allocated for the page (in particular if the physical memory allocator is not
+/// initialized yet), or if `fill` failed.
+pub fn get_or_fill(
+    key: PageKey,
+    pin: bool,
+    fill: impl FnOnce(&mut [u8]) -> CanFail<IOError>,
+) -> IOResult<PhyAddr> {
+    with_page(key, pin, fill, |frame| frame)
+}
+
+/// Reads bytes from the page `key`, starting at `offset` in the page, calling `fill` to read the page on a cache
+/// miss.
+///
+/// Returns the number of bytes copied into `buf`.
+///
+/// # Errors
+///
+/// Same as [`get_or_fill`].
+pub fn read(
+    key: PageKey,
+    offset: usize,
+    buf: &mut [u8],
+    fill: impl FnOnce(&mut [u8]) -> CanFail<IOError>,
+) -> IOResult<usize> {
+    with_page(key, false, fill, |frame| {
+        let count = usize::min(PAGE_SIZE.saturating_sub(offset), buf.len());
+
+        unsafe {
+            ptr::copy_nonoverlapping(
+                get_physical_memory(frame).add(offset),
+                buf.as_mut_ptr(),
+                count,
+            );
+        }
+
+        count
+    })
+}
+
+/// Calls `f` with the frame holding the page `key`, calling `fill` to read the page on a cache miss.
+///
+/// The cache stays locked while `f` runs, so that the page cannot be released in the meantime. It is not locked while
+/// `fill` runs: reading from the disk may wait for interrupts, and must not hold up every other user of the cache.
+fn with_page<T>(
+    key: PageKey,
+    pin: bool,
+    fill: impl FnOnce(&mut [u8]) -> CanFail<IOError>,
+    f: impl FnOnce(PhyAddr) -> T,
+) -> IOResult<T> {
+    {
+        let mut cache = PAGE_CACHE.lock();
+        if let Some(frame) = cache.lookup(key, pin) {
+            return Ok(f(frame));
+        }
+    }
+
+    let frame = alloc_page(PAGE_SIZE).map_err(|_| IOError::Unknown)?;
+    let content = unsafe { slice::from_raw_parts_mut(get_physical_memory(frame.start), PAGE_SIZE) };
+    content.fill(0);
+
+    if let Err(err) = fill(content) {
+        free_page(frame);
+        return Err(err);
+    }
+
+    let mut cache = PAGE_CACHE.lock();
+    let frame = cache.insert(key, frame.start, pin);
+
+    Ok(f(frame))
+}
+
+/// Releases a pin taken on the page `key` by [`get_or_fill`].
+pub fn unpin(key: PageKey) {
+    if let Some(page) = PAGE_CACHE.lock().pages.get_mut(&key) {
+        page.pins = page.pins.saturating_sub(1);
+    }
+}
+
+/// Marks the page `key` as modified: it is kept in the cache until written back (see [`writeback_inode`]).
+pub fn mark_dirty(key: PageKey) {
+    if let Some(page) = PAGE_CACHE.lock().pages.get_mut(&key) {
+        page.dirty = true;
+    }
+}
+
+/// Writes back the dirty pages of the inode `inode` of the filesystem `fs`, using `writeback`.
+///
+/// Pages are marked as clean once successfully written.
+///
+/// # Errors
+///
+/// Returns the first error returned by `writeback`. The pages that were not written remain dirty.
+pub fn writeback_inode(fs: FsId, inode: u64, writeback: Writeback) -> CanFail<IOError> {
+    let mut cache = PAGE_CACHE.lock();
+    let first = PageKey {
+        fs,
+        inode,
+        index: 0,
+    };
+    let last = PageKey {
+        index: u64::MAX,
+        ..first
+    };
+
+    for (key, page) in cache.pages.range_mut(first..=last) {
+        if !page.dirty {
+            continue;
+        }
+
+        let content = unsafe { slice::from_raw_parts(get_physical_memory(page.frame), PAGE_SIZE) };
+        writeback(*key, content)?;
+        page.dirty = false;
+    }
+
+    Ok(())
+}
+
+/// Drops the clean and unpinned pages of every file of the filesystem `fs` (when it is unmounted, for instance).
+pub fn invalidate_fs(fs: FsId) {
+    let mut cache = PAGE_CACHE.lock();
+
+    cache.pages.retain(|key, page| {
+        if key.fs != fs || page.dirty || page.pins != 0 {
+            return true;
+        }
+
+        release_frame(page.frame);
+        false
+    });
+}
+
+/// Number of pages currently held in the cache.
+pub fn cached_pages() -> usize {
+    PAGE_CACHE.lock().pages.len()
+}
+
+fn release_frame(frame: PhyAddr) {
+    free_page(FrameAllocation {
+        start: frame,
+        length: PAGE_SIZE,
+    });
+    stats::uncharge(MemoryConsumer::Caches, PAGE_SIZE);
+}
+
+/// Shrinker releasing the clean pages of the cache that are not pinned.
+fn shrink_page_cache(control: &ShrinkControl) -> usize {
+    let Some(mut cache) = PAGE_CACHE.try_lock() else {
+        return 0;
+    };
+
+    let count = match control.reason {
+        ShrinkReason::Idle => cache.pages.len().saturating_sub(PAGE_CACHE_IDLE_LIMIT),
+        ShrinkReason::LowMemory => control.target.div_ceil(PAGE_SIZE),
+    };
+
+    cache.evict_lru(count) * PAGE_SIZE
+}
@@ -407,7 +407,15 @@ const CRC_32_ANSI_TAB: [u32; 256] = [
 ];
 
 pub fn crc32_calc(buf: &[u8]) -> u32 {
-    let mut crc_32: u32 = 0xFFFFFFFF;
+    crc32_update(0, buf)
+}
+
+/// Updates the CRC32 `crc` of some data with the following bytes in `buf`.
+///
+/// Used to compute the checksum of data that is not contiguous in memory: the checksum of `a` followed by `b` is
+/// `crc32_update(crc32_calc(a), b)`.
+pub fn crc32_update(crc: u32, buf: &[u8]) -> u32 {
+    let mut crc_32: u32 = !crc;
 
     for &b in buf {
         crc_32 = CRC_32_ANSI_TAB[((crc_32 ^ b as u32) & 0xff) as usize] ^ (crc_32 >> 8);
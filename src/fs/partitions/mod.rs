@@ -2,19 +2,22 @@
 //!
 //! Contains the implementation of the two standards partition scheme, _GPT_ and _MBR_.
 
+use alloc::string::String;
+
 use crate::drivers::ide::AtaDeviceIdentifier;
-use crate::errors::{CanFail, MountError};
+use crate::errors::{CanFail, IOError, MountError};
 use crate::fs::{
     ext4::Ext4Fs,
     partitions::{
         gpt::{GPTPartitionEntry, GUIDPartitionTable},
         mbr::{MBRPartitionEntry, MBRPartitionTable},
     },
-    Fs, PartFS,
+    File, Fs, IOResult, PartFS,
 };
 
 pub mod gpt;
 pub mod mbr;
+pub mod registry;
 
 /// A partition structure, that does not depend on the partition format (_GPT_ or _MBR_).
 ///
@@ -104,6 +107,45 @@ impl Partition {
         }
     }
 
+    /// Returns this partition's size, in sectors.
+    pub fn size_in_sectors(&self) -> u64 {
+        match self.metadata {
+            PartitionMetadata::MBR(meta) => u64::from(meta.sectors_count()),
+            PartitionMetadata::GPT(meta) => meta.size_in_sectors(),
+        }
+    }
+
+    /// Checks whether `other` covers the same sectors of the drive as this partition.
+    pub fn same_extent(&self, other: &Self) -> bool {
+        self.start_lba() == other.start_lba() && self.size_in_sectors() == other.size_in_sectors()
+    }
+
+    /// Opens the regular file at `path` (an absolute path), on the filesystem of this partition.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::NotFound`] if there is no regular file at that path, or [`IOError::InvalidDevice`] if the
+    /// partition does not contain a supported filesystem.
+    pub fn open(&self, path: &str) -> IOResult<File> {
+        match &self.fs {
+            PartFS::Ext4(fs) => fs.read().open(path),
+            PartFS::Unknown => Err(IOError::InvalidDevice),
+        }
+    }
+
+    /// Returns the target of the symbolic link at `path` (an absolute path), on the filesystem of this partition.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IOError::NotFound`] if there is no file at that path, [`IOError::InvalidCommand`] if it is not a
+    /// symbolic link, or [`IOError::InvalidDevice`] if the partition does not contain a supported filesystem.
+    pub fn read_link(&self, path: &str) -> IOResult<String> {
+        match &self.fs {
+            PartFS::Ext4(fs) => fs.read().read_link(path),
+            PartFS::Unknown => Err(IOError::InvalidDevice),
+        }
+    }
+
     /// Returns the partition format dependent metadatas.
     ///
     /// They contain the original table entry for this partition.
@@ -0,0 +1,320 @@
+//! Registry of the partitions found on disk devices.
+//!
+//! Every time the partition table of a drive is scanned, the identifiers of its partitions (_GPT_ partition GUID and
+//! name, filesystem UUID and volume label) are recorded here. A partition can then be addressed using one of those
+//! identifiers (for instance `LABEL=rootfs`), rather than using a drive identifier and a partition index, which
+//! depend on the order in which the devices were detected.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{self, Display};
+use spin::RwLock;
+
+use crate::drivers::devtree::{devtree_update_partitions, partition_name};
+use crate::drivers::generics::dev_disk::{get_sata_drive, DiskDevice};
+use crate::drivers::ide::AtaDeviceIdentifier;
+use crate::errors::{CanFail, IOError};
+use crate::event::{self, DeviceEvent, EventKind};
+use crate::fs::mount::{mount_of, umount};
+use crate::fs::partitions::{Partition, PartitionMetadata};
+use crate::fs::PartFS;
+use crate::warn;
+
+static PARTITION_REGISTRY: RwLock<Vec<PartitionRecord>> = RwLock::new(Vec::new());
+
+/// Identifies a partition, regardless of the drive on which it is located.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PartitionSelector {
+    /// UUID of the filesystem (`UUID=...`).
+    Uuid([u8; 16]),
+
+    /// Volume label of the filesystem (`LABEL=...`).
+    Label(String),
+
+    /// Unique partition GUID, from the _GPT_ partition entry (`PARTUUID=...`).
+    PartUuid([u8; 16]),
+
+    /// Partition name, from the _GPT_ partition entry (`PARTLABEL=...`).
+    PartLabel(String),
+}
+
+impl PartitionSelector {
+    /// Parses a selector such as `LABEL=rootfs` or `PARTUUID=0fc63daf-8483-4772-8e79-3d69d8477de4`.
+    ///
+    /// A leading `root=` is ignored, so that a `root=LABEL=rootfs` option may be used as is. Returns `None` if the
+    /// kind of selector is unknown, or if the UUID is malformed.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let spec = spec.trim();
+        let spec = spec.strip_prefix("root=").unwrap_or(spec);
+        let (kind, value) = spec.split_once('=')?;
+
+        match kind {
+            "UUID" => parse_uuid(value).map(Self::Uuid),
+            "LABEL" => Some(Self::Label(String::from(value))),
+            "PARTUUID" => parse_uuid(value).map(Self::PartUuid),
+            "PARTLABEL" => Some(Self::PartLabel(String::from(value))),
+            _ => None,
+        }
+    }
+}
+
+impl Display for PartitionSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uuid(uuid) => {
+                f.write_str("UUID=")?;
+                fmt_uuid(f, uuid)
+            }
+            Self::Label(label) => write!(f, "LABEL={label}"),
+            Self::PartUuid(uuid) => {
+                f.write_str("PARTUUID=")?;
+                fmt_uuid(f, uuid)
+            }
+            Self::PartLabel(label) => write!(f, "PARTLABEL={label}"),
+        }
+    }
+}
+
+/// Identifiers of a partition, recorded while scanning the partition table of a drive.
+///
+/// UUIDs are stored in the order in which they are usually displayed.
+#[derive(Clone, Debug)]
+pub struct PartitionRecord {
+    /// Drive on which the partition is located.
+    pub drive_id: AtaDeviceIdentifier,
+
+    /// Index of the partition in the list of partitions of the drive.
+    pub partition_id: usize,
+
+    /// Unique partition GUID (_GPT_ only).
+    pub part_uuid: Option<[u8; 16]>,
+
+    /// Partition name (_GPT_ only).
+    pub part_label: Option<String>,
+
+    /// UUID of the filesystem, if it was mounted.
+    pub fs_uuid: Option<[u8; 16]>,
+
+    /// Volume label of the filesystem, if it was mounted and has one.
+    pub fs_label: Option<String>,
+}
+
+impl PartitionRecord {
+    fn from_partition(
+        drive_id: AtaDeviceIdentifier,
+        partition_id: usize,
+        partition: &Partition,
+    ) -> Self {
+        let (part_uuid, part_label) = match partition.metadata() {
+            PartitionMetadata::MBR(_) => (None, None),
+            PartitionMetadata::GPT(meta) => (
+                Some(gpt_guid_bytes(meta.guid())),
+                Some(meta.name()).filter(|name| !name.is_empty()),
+            ),
+        };
+
+        let (fs_uuid, fs_label) = match &partition.fs {
+            PartFS::Ext4(fs) => {
+                let fs = fs.read();
+                (
+                    Some(fs.uuid()),
+                    Some(fs.volume_label()).filter(|label| !label.is_empty()),
+                )
+            }
+            PartFS::Unknown => (None, None),
+        };
+
+        Self {
+            drive_id,
+            partition_id,
+            part_uuid,
+            part_label,
+            fs_uuid,
+            fs_label,
+        }
+    }
+
+    /// Checks whether this partition is the one identified by `selector`.
+    pub fn matches(&self, selector: &PartitionSelector) -> bool {
+        match selector {
+            PartitionSelector::Uuid(uuid) => self.fs_uuid.as_ref() == Some(uuid),
+            PartitionSelector::Label(label) => self.fs_label.as_ref() == Some(label),
+            PartitionSelector::PartUuid(uuid) => self.part_uuid.as_ref() == Some(uuid),
+            PartitionSelector::PartLabel(label) => self.part_label.as_ref() == Some(label),
+        }
+    }
+}
+
+/// Records the partitions of the drive `drive_id`, replacing the ones previously recorded for that drive.
+///
+/// Called by disk drivers each time they (re)load the partition table of a drive.
+pub fn register_drive_partitions(drive_id: AtaDeviceIdentifier, partitions: &[Arc<Partition>]) {
+    // Subscribing again is harmless, it simply replaces the previous subscriber.
+    event::subscribe(
+        "partitions",
+        EventKind::DiskDetached.mask(),
+        handle_disk_event,
+    );
+
+    let mut registry = PARTITION_REGISTRY.write();
+    registry.retain(|record| record.drive_id != drive_id);
+    registry.extend(
+        partitions
+            .iter()
+            .enumerate()
+            .map(|(partition_id, partition)| {
+                PartitionRecord::from_partition(drive_id, partition_id, partition)
+            }),
+    );
+    drop(registry);
+
+    devtree_update_partitions(drive_id);
+}
+
+/// Removes every partition recorded for the drive `drive_id`.
+pub fn unregister_drive_partitions(drive_id: AtaDeviceIdentifier) {
+    PARTITION_REGISTRY
+        .write()
+        .retain(|record| record.drive_id != drive_id);
+
+    devtree_update_partitions(drive_id);
+}
+
+/// Returns the drive and the partition index of the first recorded partition identified by `selector`.
+pub fn find_partition(selector: &PartitionSelector) -> Option<(AtaDeviceIdentifier, usize)> {
+    PARTITION_REGISTRY
+        .read()
+        .iter()
+        .find(|record| record.matches(selector))
+        .map(|record| (record.drive_id, record.partition_id))
+}
+
+/// Returns a copy of every recorded partition.
+pub fn partition_records() -> Vec<PartitionRecord> {
+    PARTITION_REGISTRY.read().clone()
+}
+
+/// Builds the new list of partitions of the drive `drive_id`, after its partition table was (re)loaded.
+///
+/// `previous` is the list of partitions before the table was loaded, and `scanned` the partitions found in the table.
+/// A partition that still covers the same sectors at the same index is kept as is, along with its mounted filesystem.
+/// The filesystems of the partitions that changed or disappeared are unmounted, and the new partitions are mounted if
+/// `mount` is set.
+///
+/// Called by disk drivers each time they (re)load the partition table of a drive.
+pub fn reconcile_partitions(
+    drive_id: AtaDeviceIdentifier,
+    previous: &[Arc<Partition>],
+    scanned: Vec<Partition>,
+    mount: bool,
+) -> Vec<Arc<Partition>> {
+    let mut partitions = Vec::with_capacity(scanned.len());
+
+    for (partition_id, mut partition) in scanned.into_iter().enumerate() {
+        match previous.get(partition_id) {
+            Some(old) if old.same_extent(&partition) => {
+                partitions.push(old.clone());
+                continue;
+            }
+            Some(_) => detach_partition(drive_id, partition_id),
+            None => (),
+        }
+
+        if mount {
+            if let Err(err) = partition.load_fs() {
+                warn!(
+                    "partitions",
+                    "failed to mount {}: {:?}",
+                    partition_name(drive_id, partition_id),
+                    err
+                );
+            }
+        }
+        partitions.push(Arc::new(partition));
+    }
+
+    for partition_id in partitions.len()..previous.len() {
+        detach_partition(drive_id, partition_id);
+    }
+
+    partitions
+}
+
+/// Unmounts the filesystem of a partition that changed or disappeared from the partition table of its drive.
+fn detach_partition(drive_id: AtaDeviceIdentifier, partition_id: usize) {
+    let Some(mount_id) = mount_of(drive_id, partition_id) else {
+        return;
+    };
+
+    if let Err(err) = umount(mount_id) {
+        warn!(
+            "partitions",
+            "{} was removed, but its filesystem could not be unmounted: {:?}",
+            partition_name(drive_id, partition_id),
+            err
+        );
+    }
+}
+
+/// Reloads the partition table of the drive `drive_id`, and updates the registry accordingly.
+///
+/// Partitions that did not change keep their mounted filesystem (see [`reconcile_partitions`]). References to the
+/// previous partitions of the drive (obtained using [`DiskDevice::partitions`]) remain valid, but may describe
+/// partitions that no longer exist.
+///
+/// # Errors
+///
+/// Returns [`IOError::InvalidDevice`] if no drive is identified by `drive_id`.
+pub fn rescan_partitions(drive_id: AtaDeviceIdentifier) -> CanFail<IOError> {
+    let drive = get_sata_drive(drive_id).ok_or(IOError::InvalidDevice)?;
+    drive.rescan_partitions();
+
+    Ok(())
+}
+
+fn handle_disk_event(event: &DeviceEvent) {
+    if let DeviceEvent::DiskDetached { drive, .. } = *event {
+        unregister_drive_partitions(drive);
+    }
+}
+
+/// Converts a _GPT_ GUID, as stored on disk, into the order in which it is usually displayed.
+///
+/// The first three fields of the GUID are stored in little-endian, the last two in big-endian.
+fn gpt_guid_bytes(guid: u128) -> [u8; 16] {
+    let mut bytes = guid.to_le_bytes();
+
+    bytes[0..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+
+    bytes
+}
+
+/// Parses a UUID written as 32 hexadecimal digits, optionally separated by dashes.
+fn parse_uuid(value: &str) -> Option<[u8; 16]> {
+    let mut digits = value.chars().filter(|&c| c != '-');
+    let mut uuid = [0u8; 16];
+
+    for byte in &mut uuid {
+        let high = digits.next()?.to_digit(16)?;
+        let low = digits.next()?.to_digit(16)?;
+
+        *byte = u8::try_from((high << 4) | low).ok()?;
+    }
+
+    digits.next().is_none().then_some(uuid)
+}
+
+fn fmt_uuid(f: &mut fmt::Formatter<'_>, uuid: &[u8; 16]) -> fmt::Result {
+    for (i, byte) in uuid.iter().enumerate() {
+        if matches!(i, 4 | 6 | 8 | 10) {
+            f.write_str("-")?;
+        }
+
+        write!(f, "{byte:02x}")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,370 @@
+//! Persistent crash log.
+//!
+//! A small partition, named [`PSTORE_PARTITION_LABEL`] in the _GPT_ partition table, is reserved to keep a record of
+//! the last crash. When the system panics, the reason of the crash and the tail of the kernel log (see
+//! [`crate::klog`]) are written to that partition, before halting.
+//!
+//! On the next boot, once the partition is found (see [`pstore_init`]), it is checked for a record that was not read
+//! yet. If one is found, it is reported on the console, kept in memory (see [`previous_crash`]), and marked as read.
+//!
+//! The partition layout is the following:
+//!
+//! - first sector: [`PstoreHeader`], including the source location of the panic (since version 2 of the format).
+//! - following sectors: the content of the log, `log_len` bytes long.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use bytemuck::{bytes_of, from_bytes, Pod, Zeroable};
+use conquer_once::spin::OnceCell;
+use core::panic::Location;
+use spin::RwLock;
+
+use crate::drivers::generics::dev_disk::{get_sata_drive, DiskDevice};
+use crate::drivers::ide::AtaDeviceIdentifier;
+use crate::errors::{CanFail, IOError};
+use crate::event::{subscribe, DeviceEvent, EventKind};
+use crate::fs::partitions::gpt::crc32_calc;
+use crate::fs::partitions::registry::{find_partition, PartitionSelector};
+use crate::klog::{klog_read, KLOG_SIZE};
+use crate::{error, info};
+
+/// Name of the _GPT_ partition reserved for the crash log.
+pub const PSTORE_PARTITION_LABEL: &str = "pstore";
+
+/// Signature found at the start of a valid [`PstoreHeader`].
+const PSTORE_MAGIC: [u8; 8] = *b"FZPSTORE";
+
+/// Current version of the format. Records of version 1 have no panic location.
+const PSTORE_VERSION: u32 = 2;
+
+/// Set while the record was not reported yet.
+const PSTORE_FLAG_UNREAD: u32 = 1 << 0;
+
+/// Maximum length of the crash reason, in bytes.
+const PSTORE_REASON_LEN: usize = 128;
+
+/// Maximum length of the source file name of the panic location, in bytes.
+const PSTORE_FILE_LEN: usize = 64;
+
+/// Number of lines of the previous log displayed when a crash record is found.
+const PSTORE_REPORTED_LINES: usize = 10;
+
+static PSTORE_AREA: OnceCell<PstoreArea> = OnceCell::uninit();
+
+static PREVIOUS_CRASH: RwLock<Option<CrashRecord>> = RwLock::new(None);
+
+/// Header of the crash log, stored in the first sector of the partition.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub struct PstoreHeader {
+    magic: [u8; 8],
+    version: u32,
+    flags: u32,
+
+    /// Length of the log stored after the header, in bytes.
+    log_len: u32,
+
+    /// CRC32 of the log.
+    log_crc32: u32,
+
+    /// Reason of the crash (usually the panic message), padded with zeroes.
+    reason: [u8; PSTORE_REASON_LEN],
+
+    /// Line of the panic location, `0` if unknown.
+    line: u32,
+
+    column: u32,
+
+    /// Source file of the panic location, padded with zeroes. Only its end is kept if it is too long.
+    file: [u8; PSTORE_FILE_LEN],
+}
+
+/// A crash recorded during a previous boot.
+#[derive(Clone, Debug)]
+pub struct CrashRecord {
+    /// Reason of the crash (usually the panic message).
+    pub reason: String,
+
+    /// Last lines of the kernel log before the crash.
+    pub log: String,
+
+    /// Source location of the panic, if known.
+    pub location: Option<CrashLocation>,
+}
+
+/// Source location of the panic that caused a crash.
+#[derive(Clone, Debug)]
+pub struct CrashLocation {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Location of the reserved area on the disk.
+#[derive(Clone, Copy, Debug)]
+struct PstoreArea {
+    drive_id: AtaDeviceIdentifier,
+    start_lba: u64,
+    sectors: u64,
+    sector_size: usize,
+}
+
+impl PstoreArea {
+    /// Maximum length of the stored log, in bytes.
+    fn log_capacity(&self) -> usize {
+        let capacity = (self.sectors.saturating_sub(1) as usize).saturating_mul(self.sector_size);
+
+        usize::min(capacity, KLOG_SIZE)
+    }
+
+    fn read_header(&self) -> Result<PstoreHeader, IOError> {
+        let drive = get_sata_drive(self.drive_id).ok_or(IOError::InvalidDevice)?;
+        let mut sector = vec![0u8; self.sector_size];
+        drive.read_into(self.start_lba, 1, &mut sector)?;
+
+        Ok(*from_bytes::<PstoreHeader>(
+            &sector[..core::mem::size_of::<PstoreHeader>()],
+        ))
+    }
+
+    fn write_header(&self, header: &PstoreHeader) -> CanFail<IOError> {
+        let drive = get_sata_drive(self.drive_id).ok_or(IOError::InvalidDevice)?;
+        let mut sector = vec![0u8; self.sector_size];
+        sector[..core::mem::size_of::<PstoreHeader>()].copy_from_slice(bytes_of(header));
+
+        drive.write_from(self.start_lba, &sector)?;
+        drive.flush()
+    }
+
+    /// Reads the record stored in this area, if it is valid and was not reported yet.
+    fn read_record(&self) -> Result<Option<CrashRecord>, IOError> {
+        let header = self.read_header()?;
+
+        if header.magic != PSTORE_MAGIC
+            || !(1..=PSTORE_VERSION).contains(&header.version)
+            || header.flags & PSTORE_FLAG_UNREAD == 0
+        {
+            return Ok(None);
+        }
+
+        let log_len = header.log_len as usize;
+        if log_len > self.log_capacity() {
+            return Ok(None);
+        }
+
+        let drive = get_sata_drive(self.drive_id).ok_or(IOError::InvalidDevice)?;
+        let log_sectors = log_len.div_ceil(self.sector_size);
+        let mut log = vec![0u8; log_sectors * self.sector_size];
+
+        if log_sectors != 0 {
+            drive.read_into(self.start_lba + 1, log_sectors as u16, &mut log)?;
+        }
+        log.truncate(log_len);
+
+        let log = if crc32_calc(&log) == header.log_crc32 {
+            String::from_utf8_lossy(&log).into_owned()
+        } else {
+            String::from("<corrupted log>")
+        };
+
+        // The fields added in version 2 are zeroed in older records.
+        let location = (header.line != 0).then(|| CrashLocation {
+            file: zero_padded_str(&header.file),
+            line: header.line,
+            column: header.column,
+        });
+
+        Ok(Some(CrashRecord {
+            reason: zero_padded_str(&header.reason),
+            log,
+            location,
+        }))
+    }
+}
+
+/// Waits for the crash log partition to be found, and then reports the crash recorded during the previous boot, if
+/// any.
+///
+/// The partition is looked for among the partitions found so far, and among the ones found afterwards (see
+/// [`event`](crate::event)), so this may be called before the disk drives are initialized.
+pub fn pstore_init() {
+    subscribe(
+        "pstore",
+        EventKind::PartitionFound.mask(),
+        pstore_partition_found,
+    );
+
+    if !PSTORE_AREA.is_initialized() {
+        info!(
+            "pstore",
+            "no '{}' partition yet, crash logs are not saved", PSTORE_PARTITION_LABEL
+        );
+    }
+}
+
+fn pstore_partition_found(event: &DeviceEvent) {
+    let DeviceEvent::PartitionFound {
+        drive, partition, ..
+    } = *event
+    else {
+        return;
+    };
+
+    let selector = PartitionSelector::PartLabel(String::from(PSTORE_PARTITION_LABEL));
+    if PSTORE_AREA.is_initialized() || find_partition(&selector) != Some((drive, partition)) {
+        return;
+    }
+
+    pstore_attach(drive, partition);
+}
+
+/// Uses a partition as the crash log partition, and reports the crash it contains, if any.
+fn pstore_attach(drive_id: AtaDeviceIdentifier, partition_id: usize) {
+    let Some(drive) = get_sata_drive(drive_id) else {
+        return;
+    };
+    let Some(partition) = drive.partition(partition_id) else {
+        return;
+    };
+
+    let area = PstoreArea {
+        drive_id,
+        start_lba: partition.start_lba(),
+        sectors: partition.size_in_sectors(),
+        sector_size: drive.logical_sector_size() as usize,
+    };
+
+    if area.sectors < 2 || area.sector_size < core::mem::size_of::<PstoreHeader>() {
+        error!("pstore", "crash log partition is too small");
+        return;
+    }
+
+    let area = *PSTORE_AREA.get_or_init(|| area);
+
+    info!(
+        "pstore",
+        "crash log partition found (start_lba = {}  sectors = {})", area.start_lba, area.sectors
+    );
+
+    match area.read_record() {
+        Ok(Some(record)) => {
+            report_crash(&record);
+            *PREVIOUS_CRASH.write() = Some(record);
+
+            if pstore_clear().is_err() {
+                error!("pstore", "failed to mark the crash record as read");
+            }
+        }
+        Ok(None) => {}
+        Err(_) => {
+            error!("pstore", "failed to read the crash log partition");
+        }
+    }
+}
+
+/// Returns the crash recorded during the previous boot, if one was found by [`pstore_init`].
+pub fn previous_crash() -> Option<CrashRecord> {
+    PREVIOUS_CRASH.read().clone()
+}
+
+/// Writes a crash record, made of `reason`, the source `location` of the panic and the tail of the kernel log.
+///
+/// Meant to be called from a panic handler: the reason is truncated to fit in the header, and the disk cache is
+/// flushed before returning.
+///
+/// # Errors
+///
+/// Returns [`IOError::InvalidDevice`] if no crash log partition was found. May return any other variant of
+/// [`IOError`] in case of a device failure.
+pub fn pstore_write_crash(reason: &str, location: Option<&Location>) -> CanFail<IOError> {
+    let area = PSTORE_AREA.get().ok_or(IOError::InvalidDevice)?;
+    let drive = get_sata_drive(area.drive_id).ok_or(IOError::InvalidDevice)?;
+
+    let mut log = vec![0u8; area.log_capacity()];
+    let log_len = klog_read(&mut log);
+
+    // The log is written first, so that a header is never valid without its log.
+    let log_sectors = log_len.div_ceil(area.sector_size);
+    if log_sectors != 0 {
+        log.resize(log_sectors * area.sector_size, 0);
+        drive.write_from(area.start_lba + 1, &log)?;
+    }
+
+    let mut header = PstoreHeader {
+        magic: PSTORE_MAGIC,
+        version: PSTORE_VERSION,
+        flags: PSTORE_FLAG_UNREAD,
+        log_len: log_len as u32,
+        log_crc32: crc32_calc(&log[..log_len]),
+        reason: [0; PSTORE_REASON_LEN],
+        line: 0,
+        column: 0,
+        file: [0; PSTORE_FILE_LEN],
+    };
+
+    let reason_len = usize::min(reason.len(), PSTORE_REASON_LEN);
+    header.reason[..reason_len].copy_from_slice(&reason.as_bytes()[..reason_len]);
+
+    if let Some(location) = location {
+        // The end of the path is the most useful part.
+        let file = location.file().as_bytes();
+        let file = &file[file.len().saturating_sub(PSTORE_FILE_LEN)..];
+
+        header.file[..file.len()].copy_from_slice(file);
+        header.line = location.line();
+        header.column = location.column();
+    }
+
+    area.write_header(&header)
+}
+
+/// Marks the stored crash record as read, so that it is not reported again.
+///
+/// # Errors
+///
+/// Returns [`IOError::InvalidDevice`] if no crash log partition was found. May return any other variant of
+/// [`IOError`] in case of a device failure.
+pub fn pstore_clear() -> CanFail<IOError> {
+    let area = PSTORE_AREA.get().ok_or(IOError::InvalidDevice)?;
+    let mut header = area.read_header()?;
+
+    if header.flags & PSTORE_FLAG_UNREAD == 0 {
+        return Ok(());
+    }
+
+    header.flags &= !PSTORE_FLAG_UNREAD;
+    area.write_header(&header)
+}
+
+/// Displays the reason of a previous crash, and the last lines of the log.
+fn report_crash(record: &CrashRecord) {
+    error!(
+        "pstore",
+        "the system crashed during the previous boot: {}", record.reason
+    );
+
+    if let Some(location) = &record.location {
+        error!(
+            "pstore",
+            "panicked at {}:{}:{}", location.file, location.line, location.column
+        );
+    }
+
+    let lines: Vec<&str> = record.log.lines().collect();
+    let first_line = lines.len().saturating_sub(PSTORE_REPORTED_LINES);
+
+    for line in &lines[first_line..] {
+        info!("pstore", "| {}", line);
+    }
+}
+
+/// Returns the content of a zero-padded string field.
+fn zero_padded_str(field: &[u8]) -> String {
+    let len = field
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(field.len());
+
+    String::from_utf8_lossy(&field[..len]).into_owned()
+}
@@ -0,0 +1,59 @@
+//! Path-based access to the mounted filesystems.
+//!
+//! There is no single root filesystem yet: every mounted filesystem is reachable under the directory named after its
+//! [`MountId`]. For instance, `/1/boot/fzkernel` designates the file `/boot/fzkernel` of the filesystem mounted with
+//! the identifier `1`, and `/1` its root directory.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::fs::vfs;
+//!
+//! let metadata = vfs::stat("/1/boot/fzkernel")?;
+//! info!("vfs", "{} {} {}", metadata.mode_string(), metadata.uid, metadata.size);
+//! ```
+
+use alloc::string::String;
+
+use crate::errors::IOError;
+use crate::fs::mount::{mounted_fs, MountId};
+use crate::fs::{IOResult, Metadata, PartFS};
+
+/// Returns the metadata of the file or directory at `path`.
+///
+/// # Errors
+///
+/// Returns [`IOError::NotFound`] if `path` does not start with the identifier of a mounted filesystem, or if no file
+/// exists at that path. May return any I/O error that happened while walking the directories.
+pub fn stat(path: &str) -> IOResult<Metadata> {
+    let (fs, path) = resolve(path)?;
+
+    match fs {
+        PartFS::Ext4(fs) => fs.read().stat(path),
+        PartFS::Unknown => Err(IOError::InvalidCommand),
+    }
+}
+
+/// Returns the target of the symbolic link at `path`.
+///
+/// # Errors
+///
+/// Returns [`IOError::NotFound`] if `path` does not start with the identifier of a mounted filesystem, or if no file
+/// exists at that path, and [`IOError::InvalidCommand`] if that file is not a symbolic link.
+pub fn read_link(path: &str) -> IOResult<String> {
+    let (fs, path) = resolve(path)?;
+
+    match fs {
+        PartFS::Ext4(fs) => fs.read().read_link(path),
+        PartFS::Unknown => Err(IOError::InvalidCommand),
+    }
+}
+
+/// Splits `path` into the mounted filesystem it designates, and the path relative to the root of that filesystem.
+fn resolve(path: &str) -> IOResult<(PartFS, &str)> {
+    let path = path.trim_start_matches('/');
+    let (mount_id, fs_path) = path.split_once('/').unwrap_or((path, ""));
+    let mount_id: MountId = mount_id.parse().map_err(|_| IOError::NotFound)?;
+
+    Ok((mounted_fs(mount_id).ok_or(IOError::NotFound)?, fs_path))
+}
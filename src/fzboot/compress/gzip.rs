@@ -0,0 +1,90 @@
+//! gzip (RFC 1952) decoder.
+//!
+//! Only the first member of the file is decompressed. The CRC32 and the size of the decompressed data, stored after
+//! the DEFLATE stream, are checked.
+
+use crate::{
+    compress::inflate::inflate, errors::DecompressionError, fs::partitions::gpt::crc32_calc,
+};
+
+/// Signature of a gzip member.
+pub const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// The only compression method defined by the format (DEFLATE).
+const GZIP_METHOD_DEFLATE: u8 = 8;
+
+const FLG_HCRC: u8 = 1 << 1;
+const FLG_EXTRA: u8 = 1 << 2;
+const FLG_NAME: u8 = 1 << 3;
+const FLG_COMMENT: u8 = 1 << 4;
+
+/// Size of the fixed part of the header.
+const GZIP_HEADER_SIZE: usize = 10;
+
+/// Returns the size of the decompressed data of a gzip file, stored in its last 4 bytes.
+///
+/// The file must only contain a single member, and its decompressed data must be smaller than 4 GiB.
+pub fn original_size(data: &[u8]) -> Option<usize> {
+    let size = data.get(data.len().checked_sub(4)?..)?;
+    usize::try_from(u32::from_le_bytes(size.try_into().ok()?)).ok()
+}
+
+/// Decompresses a gzip member into `output`.
+///
+/// Returns the length of the decompressed data.
+pub fn decompress(data: &[u8], output: &mut [u8]) -> Result<usize, DecompressionError> {
+    let header = data
+        .get(..GZIP_HEADER_SIZE)
+        .ok_or(DecompressionError::UnexpectedEof)?;
+
+    if header[..2] != GZIP_MAGIC {
+        return Err(DecompressionError::UnknownFormat);
+    }
+    if header[2] != GZIP_METHOD_DEFLATE {
+        return Err(DecompressionError::Unsupported);
+    }
+
+    let flags = header[3];
+    let mut pos = GZIP_HEADER_SIZE;
+
+    if flags & FLG_EXTRA != 0 {
+        let len = data
+            .get(pos..pos + 2)
+            .ok_or(DecompressionError::UnexpectedEof)?;
+        pos += 2 + usize::from(u16::from_le_bytes([len[0], len[1]]));
+    }
+
+    // Original file name and comment, both zero-terminated.
+    for flag in [FLG_NAME, FLG_COMMENT] {
+        if flags & flag != 0 {
+            let len = data
+                .get(pos..)
+                .and_then(|field| field.iter().position(|&b| b == 0))
+                .ok_or(DecompressionError::UnexpectedEof)?;
+            pos += len + 1;
+        }
+    }
+
+    if flags & FLG_HCRC != 0 {
+        pos += 2;
+    }
+
+    let stream = data.get(pos..).ok_or(DecompressionError::UnexpectedEof)?;
+    let (out_len, stream_len) = inflate(stream, output)?;
+
+    let trailer = stream
+        .get(stream_len..stream_len + 8)
+        .ok_or(DecompressionError::UnexpectedEof)?;
+    let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+    let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+
+    // The size is stored modulo 2^32.
+    if size != out_len as u32 {
+        return Err(DecompressionError::InvalidData);
+    }
+    if crc != crc32_calc(&output[..out_len]) {
+        return Err(DecompressionError::ChecksumMismatch);
+    }
+
+    Ok(out_len)
+}
@@ -0,0 +1,354 @@
+//! DEFLATE (RFC 1951) decoder.
+//!
+//! The decoder favours a small code size and stack footprint over speed: Huffman codes are decoded bit by bit, using
+//! canonical code tables built from the code lengths of each block.
+
+use crate::errors::DecompressionError;
+
+/// Maximum length of a Huffman code, in bits.
+const MAX_BITS: usize = 15;
+
+/// Maximum number of literal/length codes in a dynamic block.
+const MAX_LCODES: usize = 286;
+
+/// Maximum number of distance codes in a dynamic block.
+const MAX_DCODES: usize = 30;
+
+/// Number of literal/length codes in a fixed block.
+const FIXED_LCODES: usize = 288;
+
+/// Symbol marking the end of a block.
+const END_OF_BLOCK: u16 = 256;
+
+/// Base lengths of the length symbols (257 to 285).
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+
+/// Extra bits of the length symbols (257 to 285).
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distances of the distance symbols.
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+/// Extra bits of the distance symbols.
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order in which the code lengths of the code length alphabet are stored in a dynamic block.
+const CODE_LENGTHS_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Canonical Huffman code.
+struct Huffman {
+    /// Number of codes of each length.
+    count: [u16; MAX_BITS + 1],
+
+    /// Symbols, ordered by code.
+    symbol: [u16; FIXED_LCODES],
+}
+
+impl Huffman {
+    /// Builds the canonical code from the code length of each symbol.
+    ///
+    /// Returns the code and whether it is complete, or an error if it is over-subscribed.
+    fn new(lengths: &[u8]) -> Result<(Self, bool), DecompressionError> {
+        let mut code = Self {
+            count: [0; MAX_BITS + 1],
+            symbol: [0; FIXED_LCODES],
+        };
+
+        for &len in lengths {
+            code.count[usize::from(len)] += 1;
+        }
+
+        // No symbol is used: the code is complete, but decoding with it always fails.
+        if usize::from(code.count[0]) == lengths.len() {
+            return Ok((code, true));
+        }
+
+        // Number of codes still available.
+        let mut left: i32 = 1;
+        for len in 1..=MAX_BITS {
+            left = (left << 1) - i32::from(code.count[len]);
+            if left < 0 {
+                return Err(DecompressionError::InvalidData);
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + code.count[len];
+        }
+
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                code.symbol[usize::from(offsets[usize::from(len)])] = symbol as u16;
+                offsets[usize::from(len)] += 1;
+            }
+        }
+
+        Ok((code, left == 0))
+    }
+
+    /// Whether the code contains a single code of length 1 (the only incomplete codes allowed).
+    fn is_single(&self) -> bool {
+        self.count[1] == 1 && self.count[2..].iter().all(|&count| count == 0)
+    }
+}
+
+/// Bit reader over the compressed data, least significant bit first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buf: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u32) -> Result<u32, DecompressionError> {
+        let mut value = self.bit_buf;
+
+        while self.bit_count < count {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or(DecompressionError::UnexpectedEof)?;
+            value |= u32::from(byte) << self.bit_count;
+            self.pos += 1;
+            self.bit_count += 8;
+        }
+
+        self.bit_buf = value >> count;
+        self.bit_count -= count;
+
+        Ok(value & ((1 << count) - 1))
+    }
+
+    /// Discards the remaining bits of the current byte.
+    fn align(&mut self) {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> Result<u16, DecompressionError> {
+        // First code of the current length, and index of its symbol.
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for len in 1..=MAX_BITS {
+            code |= self.bits(1)? as i32;
+
+            let count = i32::from(huffman.count[len]);
+            if code - first < count {
+                return Ok(huffman.symbol[(index + code - first) as usize]);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(DecompressionError::InvalidData)
+    }
+}
+
+/// Decompresses a raw DEFLATE stream into `output`.
+///
+/// Returns the length of the decompressed data, and the number of bytes of `data` consumed by the stream.
+pub fn inflate(data: &[u8], output: &mut [u8]) -> Result<(usize, usize), DecompressionError> {
+    let mut reader = BitReader {
+        data,
+        pos: 0,
+        bit_buf: 0,
+        bit_count: 0,
+    };
+    let mut out_len = 0;
+
+    loop {
+        let last = reader.bits(1)? == 1;
+
+        out_len = match reader.bits(2)? {
+            0 => inflate_stored(&mut reader, output, out_len)?,
+            1 => {
+                let (lencode, distcode) = fixed_codes();
+                inflate_codes(&mut reader, output, out_len, &lencode, &distcode)?
+            }
+            2 => {
+                let (lencode, distcode) = dynamic_codes(&mut reader)?;
+                inflate_codes(&mut reader, output, out_len, &lencode, &distcode)?
+            }
+            _ => return Err(DecompressionError::InvalidData),
+        };
+
+        if last {
+            return Ok((out_len, reader.pos));
+        }
+    }
+}
+
+fn inflate_stored(
+    reader: &mut BitReader,
+    output: &mut [u8],
+    out_pos: usize,
+) -> Result<usize, DecompressionError> {
+    reader.align();
+
+    let header = reader
+        .data
+        .get(reader.pos..reader.pos + 4)
+        .ok_or(DecompressionError::UnexpectedEof)?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err(DecompressionError::InvalidData);
+    }
+    reader.pos += 4;
+
+    let len = usize::from(len);
+    let stored = reader
+        .data
+        .get(reader.pos..reader.pos + len)
+        .ok_or(DecompressionError::UnexpectedEof)?;
+    output
+        .get_mut(out_pos..out_pos + len)
+        .ok_or(DecompressionError::OutputOverflow)?
+        .copy_from_slice(stored);
+    reader.pos += len;
+
+    Ok(out_pos + len)
+}
+
+fn inflate_codes(
+    reader: &mut BitReader,
+    output: &mut [u8],
+    mut out_pos: usize,
+    lencode: &Huffman,
+    distcode: &Huffman,
+) -> Result<usize, DecompressionError> {
+    loop {
+        let symbol = reader.decode(lencode)?;
+
+        if symbol < END_OF_BLOCK {
+            *output
+                .get_mut(out_pos)
+                .ok_or(DecompressionError::OutputOverflow)? = symbol as u8;
+            out_pos += 1;
+            continue;
+        }
+
+        if symbol == END_OF_BLOCK {
+            return Ok(out_pos);
+        }
+
+        let symbol = usize::from(symbol - END_OF_BLOCK - 1);
+        if symbol >= LENGTH_BASE.len() {
+            return Err(DecompressionError::InvalidData);
+        }
+        let len = usize::from(LENGTH_BASE[symbol])
+            + reader.bits(u32::from(LENGTH_EXTRA[symbol]))? as usize;
+
+        let symbol = usize::from(reader.decode(distcode)?);
+        if symbol >= DIST_BASE.len() {
+            return Err(DecompressionError::InvalidData);
+        }
+        let dist =
+            usize::from(DIST_BASE[symbol]) + reader.bits(u32::from(DIST_EXTRA[symbol]))? as usize;
+
+        if dist > out_pos {
+            return Err(DecompressionError::InvalidData);
+        }
+        if out_pos + len > output.len() {
+            return Err(DecompressionError::OutputOverflow);
+        }
+
+        // The match may overlap the data being written, so it is copied byte by byte.
+        for i in out_pos..out_pos + len {
+            output[i] = output[i - dist];
+        }
+        out_pos += len;
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; FIXED_LCODES];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+
+    // The fixed codes are valid by construction.
+    let (lencode, _) = Huffman::new(&lengths).unwrap();
+    let (distcode, _) = Huffman::new(&[5; MAX_DCODES]).unwrap();
+
+    (lencode, distcode)
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), DecompressionError> {
+    let nlen = reader.bits(5)? as usize + 257;
+    let ndist = reader.bits(5)? as usize + 1;
+    let ncode = reader.bits(4)? as usize + 4;
+
+    if nlen > MAX_LCODES || ndist > MAX_DCODES {
+        return Err(DecompressionError::InvalidData);
+    }
+
+    let mut lengths = [0u8; MAX_LCODES + MAX_DCODES];
+    for &symbol in &CODE_LENGTHS_ORDER[..ncode] {
+        lengths[symbol] = reader.bits(3)? as u8;
+    }
+
+    let (lencode, complete) = Huffman::new(&lengths[..CODE_LENGTHS_ORDER.len()])?;
+    if !complete {
+        return Err(DecompressionError::InvalidData);
+    }
+
+    let mut index = 0;
+    while index < nlen + ndist {
+        let symbol = reader.decode(&lencode)?;
+
+        let (len, repeat) = match symbol {
+            0..=15 => {
+                lengths[index] = symbol as u8;
+                index += 1;
+                continue;
+            }
+            16 if index == 0 => return Err(DecompressionError::InvalidData),
+            16 => (lengths[index - 1], 3 + reader.bits(2)? as usize),
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+
+        if index + repeat > nlen + ndist {
+            return Err(DecompressionError::InvalidData);
+        }
+        lengths[index..index + repeat].fill(len);
+        index += repeat;
+    }
+
+    if lengths[usize::from(END_OF_BLOCK)] == 0 {
+        return Err(DecompressionError::InvalidData);
+    }
+
+    let (lencode, complete) = Huffman::new(&lengths[..nlen])?;
+    if !complete && !lencode.is_single() {
+        return Err(DecompressionError::InvalidData);
+    }
+
+    let (distcode, complete) = Huffman::new(&lengths[nlen..nlen + ndist])?;
+    if !complete && !distcode.is_single() {
+        return Err(DecompressionError::InvalidData);
+    }
+
+    Ok((lencode, distcode))
+}
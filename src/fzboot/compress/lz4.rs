@@ -0,0 +1,270 @@
+//! LZ4 frame format decoder.
+//!
+//! A frame starts with a descriptor (flags, maximum block size, and optionally the size of the content), followed by a
+//! sequence of blocks, each of them either compressed or stored. The frame ends with an empty block, optionally
+//! followed by a checksum of the content. Checksums use the xxHash32 algorithm.
+//!
+//! Blocks may reference the data decompressed from the previous blocks (linked blocks): this is supported as the whole
+//! frame is decompressed into a contiguous buffer. Frames using a dictionary are not supported.
+
+use crate::errors::DecompressionError;
+
+/// Signature of a LZ4 frame.
+pub const LZ4_FRAME_MAGIC: u32 = 0x184D_2204;
+
+const LZ4_VERSION: u8 = 0b01;
+
+const FLG_BLOCK_CHECKSUM: u8 = 1 << 4;
+const FLG_CONTENT_SIZE: u8 = 1 << 3;
+const FLG_CONTENT_CHECKSUM: u8 = 1 << 2;
+const FLG_DICT_ID: u8 = 1 << 0;
+
+/// Set in the size of a block when it is stored without compression.
+const BLOCK_UNCOMPRESSED: u32 = 1 << 31;
+
+/// Every sequence copies at least 4 bytes from the previous data.
+const MIN_MATCH: usize = 4;
+
+const XXH_PRIME32_1: u32 = 0x9E37_79B1;
+const XXH_PRIME32_2: u32 = 0x85EB_CA77;
+const XXH_PRIME32_3: u32 = 0xC2B2_AE3D;
+const XXH_PRIME32_4: u32 = 0x27D4_EB2F;
+const XXH_PRIME32_5: u32 = 0x1656_67B1;
+
+/// Sequential reader over the compressed data.
+struct Input<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Input<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecompressionError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or(DecompressionError::UnexpectedEof)?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecompressionError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, DecompressionError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, DecompressionError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+/// Decompresses a LZ4 frame into `output`.
+///
+/// Returns the length of the decompressed data.
+pub fn decompress_frame(data: &[u8], output: &mut [u8]) -> Result<usize, DecompressionError> {
+    let mut input = Input { data, pos: 0 };
+
+    if input.u32()? != LZ4_FRAME_MAGIC {
+        return Err(DecompressionError::UnknownFormat);
+    }
+
+    let descriptor_start = input.pos;
+    let flags = input.u8()?;
+    let _block_descriptor = input.u8()?;
+
+    if flags >> 6 != LZ4_VERSION {
+        return Err(DecompressionError::Unsupported);
+    }
+    if flags & FLG_DICT_ID != 0 {
+        return Err(DecompressionError::Unsupported);
+    }
+
+    let content_size = match flags & FLG_CONTENT_SIZE {
+        0 => None,
+        _ => Some(input.u64()?),
+    };
+
+    let descriptor = &data[descriptor_start..input.pos];
+    let header_checksum = input.u8()?;
+    if header_checksum != (xxh32(descriptor, 0) >> 8) as u8 {
+        return Err(DecompressionError::ChecksumMismatch);
+    }
+
+    if content_size.is_some_and(|size| size > output.len() as u64) {
+        return Err(DecompressionError::OutputOverflow);
+    }
+
+    let mut out_len = 0;
+
+    loop {
+        let block_size = input.u32()?;
+        if block_size == 0 {
+            break;
+        }
+
+        let len = (block_size & !BLOCK_UNCOMPRESSED) as usize;
+        let block = input.take(len)?;
+
+        if flags & FLG_BLOCK_CHECKSUM != 0 && input.u32()? != xxh32(block, 0) {
+            return Err(DecompressionError::ChecksumMismatch);
+        }
+
+        if block_size & BLOCK_UNCOMPRESSED != 0 {
+            output
+                .get_mut(out_len..out_len + len)
+                .ok_or(DecompressionError::OutputOverflow)?
+                .copy_from_slice(block);
+            out_len += len;
+        } else {
+            out_len = decompress_block(block, output, out_len)?;
+        }
+    }
+
+    if content_size.is_some_and(|size| size != out_len as u64) {
+        return Err(DecompressionError::InvalidData);
+    }
+
+    if flags & FLG_CONTENT_CHECKSUM != 0 && input.u32()? != xxh32(&output[..out_len], 0) {
+        return Err(DecompressionError::ChecksumMismatch);
+    }
+
+    Ok(out_len)
+}
+
+/// Returns the size of the decompressed content of a LZ4 frame, if it is stored in its descriptor.
+pub fn content_size(data: &[u8]) -> Option<usize> {
+    let flags = *data.get(4)?;
+    if flags & FLG_CONTENT_SIZE == 0 {
+        return None;
+    }
+
+    let size = u64::from_le_bytes(data.get(6..14)?.try_into().ok()?);
+    usize::try_from(size).ok()
+}
+
+/// Decompresses a LZ4 block into `output`, starting at offset `out_pos`.
+///
+/// Matches may reference any data preceding `out_pos` in `output`. Returns the offset following the decompressed
+/// data.
+pub fn decompress_block(
+    block: &[u8],
+    output: &mut [u8],
+    mut out_pos: usize,
+) -> Result<usize, DecompressionError> {
+    let mut input = Input {
+        data: block,
+        pos: 0,
+    };
+
+    loop {
+        let token = input.u8()?;
+
+        let literals_len = read_length(&mut input, usize::from(token >> 4))?;
+        let literals = input.take(literals_len)?;
+        output
+            .get_mut(out_pos..out_pos + literals_len)
+            .ok_or(DecompressionError::OutputOverflow)?
+            .copy_from_slice(literals);
+        out_pos += literals_len;
+
+        // The last sequence of a block only contains literals.
+        if input.pos == block.len() {
+            return Ok(out_pos);
+        }
+
+        let offset = usize::from(u16::from_le_bytes(input.take(2)?.try_into().unwrap()));
+        if offset == 0 || offset > out_pos {
+            return Err(DecompressionError::InvalidData);
+        }
+
+        let match_len = read_length(&mut input, usize::from(token & 0xF))? + MIN_MATCH;
+        if out_pos + match_len > output.len() {
+            return Err(DecompressionError::OutputOverflow);
+        }
+
+        // The match may overlap the data being written (when `offset < match_len`), so it is copied byte by byte.
+        for i in out_pos..out_pos + match_len {
+            output[i] = output[i - offset];
+        }
+        out_pos += match_len;
+    }
+}
+
+/// Reads the extension of a length field whose value in the token is `len`.
+fn read_length(input: &mut Input, mut len: usize) -> Result<usize, DecompressionError> {
+    if len != 0xF {
+        return Ok(len);
+    }
+
+    loop {
+        let byte = input.u8()?;
+        len += usize::from(byte);
+
+        if byte != 0xFF {
+            return Ok(len);
+        }
+    }
+}
+
+/// Computes the xxHash32 of `data`.
+pub fn xxh32(data: &[u8], seed: u32) -> u32 {
+    let read_u32 = |bytes: &[u8]| u32::from_le_bytes(bytes[..4].try_into().unwrap());
+    let round = |acc: u32, lane: u32| {
+        acc.wrapping_add(lane.wrapping_mul(XXH_PRIME32_2))
+            .rotate_left(13)
+            .wrapping_mul(XXH_PRIME32_1)
+    };
+
+    let mut stripes = data.chunks_exact(16);
+
+    let mut hash = if data.len() >= 16 {
+        let mut acc = [
+            seed.wrapping_add(XXH_PRIME32_1).wrapping_add(XXH_PRIME32_2),
+            seed.wrapping_add(XXH_PRIME32_2),
+            seed,
+            seed.wrapping_sub(XXH_PRIME32_1),
+        ];
+
+        for stripe in &mut stripes {
+            for (i, lane) in acc.iter_mut().enumerate() {
+                *lane = round(*lane, read_u32(&stripe[i * 4..]));
+            }
+        }
+
+        acc[0]
+            .rotate_left(1)
+            .wrapping_add(acc[1].rotate_left(7))
+            .wrapping_add(acc[2].rotate_left(12))
+            .wrapping_add(acc[3].rotate_left(18))
+    } else {
+        seed.wrapping_add(XXH_PRIME32_5)
+    };
+
+    hash = hash.wrapping_add(data.len() as u32);
+
+    let mut tail = stripes.remainder();
+    while tail.len() >= 4 {
+        hash = hash
+            .wrapping_add(read_u32(tail).wrapping_mul(XXH_PRIME32_3))
+            .rotate_left(17)
+            .wrapping_mul(XXH_PRIME32_4);
+        tail = &tail[4..];
+    }
+    for &byte in tail {
+        hash = hash
+            .wrapping_add(u32::from(byte).wrapping_mul(XXH_PRIME32_5))
+            .rotate_left(11)
+            .wrapping_mul(XXH_PRIME32_1);
+    }
+
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(XXH_PRIME32_2);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(XXH_PRIME32_3);
+    hash ^ (hash >> 16)
+}
@@ -0,0 +1,76 @@
+//! Decompression of compressed payloads (such as the Kernel image).
+//!
+//! The following formats are supported, and detected using their signature:
+//!
+//! - [`Lz4`](CompressionFormat::Lz4): LZ4 frame format (as produced by `lz4`), see [`lz4`].
+//! - [`Gzip`](CompressionFormat::Gzip): DEFLATE stream wrapped in a gzip member (as produced by `gzip`), see
+//! [`gzip`].
+//!
+//! Decompression does not allocate: the data is decompressed into a buffer supplied by the caller, which must be large
+//! enough to hold the whole decompressed payload.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::compress::{decompress, detect_format};
+//!
+//! if detect_format(payload).is_some() {
+//!     let len = decompress(payload, &mut output)?;
+//!     // ...
+//! }
+//! ```
+
+use core::fmt::Display;
+
+use crate::errors::DecompressionError;
+
+pub mod gzip;
+pub mod inflate;
+pub mod lz4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionFormat {
+    Lz4,
+    Gzip,
+}
+
+impl Display for CompressionFormat {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Lz4 => f.write_str("lz4"),
+            Self::Gzip => f.write_str("gzip"),
+        }
+    }
+}
+
+/// Returns the format of `data`, if it starts with the signature of a supported compression format.
+pub fn detect_format(data: &[u8]) -> Option<CompressionFormat> {
+    if data.starts_with(&lz4::LZ4_FRAME_MAGIC.to_le_bytes()) {
+        Some(CompressionFormat::Lz4)
+    } else if data.starts_with(&gzip::GZIP_MAGIC) {
+        Some(CompressionFormat::Gzip)
+    } else {
+        None
+    }
+}
+
+/// Returns the length of the decompressed data, if it is stored in the compressed stream `data`.
+///
+/// The size is always stored by gzip (modulo 2^32, at the end of `data`), but it is optional in LZ4 frames.
+pub fn decompressed_size(data: &[u8]) -> Option<usize> {
+    match detect_format(data)? {
+        CompressionFormat::Lz4 => lz4::content_size(data),
+        CompressionFormat::Gzip => gzip::original_size(data),
+    }
+}
+
+/// Decompresses `data` into `output`, detecting its format.
+///
+/// Returns the length of the decompressed data.
+pub fn decompress(data: &[u8], output: &mut [u8]) -> Result<usize, DecompressionError> {
+    match detect_format(data) {
+        Some(CompressionFormat::Lz4) => lz4::decompress_frame(data, output),
+        Some(CompressionFormat::Gzip) => gzip::decompress(data, output),
+        None => Err(DecompressionError::UnknownFormat),
+    }
+}
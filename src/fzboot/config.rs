@@ -0,0 +1,312 @@
+//! Runtime configuration.
+//!
+//! Options are `key = value` pairs, the key being prefixed with the name of the subsystem it belongs to (for
+//! instance `ahci.enable`). They are resolved at boot from the following sources, each one overriding the previous
+//! ones (see [`ConfigSource`]):
+//!
+//! - the defaults listed in [`CONFIG_DEFAULTS`].
+//! - the configuration file of the boot partition, parsed with [`config_parse_file`].
+//! - the kernel command line, parsed with [`config_parse_cmdline`].
+//!
+//! The configuration file contains one `key = value` entry per line, `#` starting a comment:
+//!
+//! ```text
+//! # Disable the legacy IDE controller.
+//! ide.enable = false
+//! ext4.readahead = 64
+//! ```
+//!
+//! On the command line, any entry whose key contains a `.` is an option (`ide.enable=false`). A key without a value
+//! enables a boolean option (`ahci.enable`).
+//!
+//! Options are queried by the subsystems when they are initialized, so an option only has an effect if its source
+//! was parsed before that. The configuration file is read once the disks are available: earlier options (such as
+//! the disk controllers) can only be set from the command line.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::config;
+//!
+//! config::config_parse_cmdline("ahci.enable=false ext4.readahead=0x40");
+//!
+//! assert!(!config::get_bool("ahci.enable"));
+//! assert_eq!(config::get_u64("ext4.readahead"), Some(64));
+//! ```
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use spin::RwLock;
+
+use crate::failpoint::FAILPOINT_CMDLINE_PREFIX;
+use crate::info;
+
+/// Known options: key, default value and description.
+pub const CONFIG_DEFAULTS: &[(&str, &str, &str)] = &[
+    ("ahci.enable", "true", "Drive AHCI controllers."),
+    ("ide.enable", "true", "Drive IDE controllers."),
+    (
+        "clocksource.watchdog",
+        "true",
+        "Check the TSC against the HPET at regular intervals.",
+    ),
+    (
+        "ext4.readahead",
+        "32",
+        "Number of blocks read ahead when reading an ext4 file.",
+    ),
+    (
+        "ext4.inode_cache",
+        "1024",
+        "Maximum number of inodes cached for each ext4 filesystem.",
+    ),
+    (
+        "ext4.group_cache",
+        "256",
+        "Maximum number of block group descriptors cached for each ext4 filesystem.",
+    ),
+    (
+        "boottime.serial",
+        "false",
+        "Write the boot timeline to the first serial port.",
+    ),
+    (
+        "vesa.mode",
+        "0",
+        "VESA video mode to switch to once in protected mode (0 keeps the mode selected at boot).",
+    ),
+    (
+        "memdump.panic",
+        "false",
+        "Write a memory snapshot to the memdump partition on panic.",
+    ),
+    (
+        "log.level",
+        "info",
+        "Least severe level of the logged messages (error, warn, info or debug).",
+    ),
+    (
+        "log.serial",
+        "false",
+        "Copy the log to the first serial port.",
+    ),
+    (
+        "log.debugcon",
+        "false",
+        "Copy the log to the emulator debug console (port 0xE9).",
+    ),
+];
+
+/// Options set from the configuration file or the command line.
+static CONFIG: RwLock<BTreeMap<String, ConfigValue>> = RwLock::new(BTreeMap::new());
+
+/// Where the value of an option comes from, in increasing order of priority.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigSource {
+    Default,
+    File,
+    CommandLine,
+
+    /// Set at runtime, with [`config_set`].
+    Runtime,
+}
+
+impl ConfigSource {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::File => "file",
+            Self::CommandLine => "cmdline",
+            Self::Runtime => "runtime",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct ConfigValue {
+    value: String,
+    source: ConfigSource,
+}
+
+/// Sets the option `key` to `value`.
+///
+/// The current value is kept if it comes from a source of higher priority. Returns whether the option was set.
+pub fn config_set(key: &str, value: &str, source: ConfigSource) -> bool {
+    let mut config = CONFIG.write();
+
+    if config
+        .get(key)
+        .is_some_and(|current| current.source > source)
+    {
+        return false;
+    }
+
+    config.insert(
+        String::from(key),
+        ConfigValue {
+            value: String::from(value),
+            source,
+        },
+    );
+
+    true
+}
+
+/// Sets the options listed on the kernel command line.
+///
+/// Entries that are not options are ignored. Returns the number of options that were set.
+pub fn config_parse_cmdline(cmdline: &str) -> usize {
+    let mut count = 0;
+
+    for entry in cmdline.split_ascii_whitespace() {
+        if entry.starts_with(FAILPOINT_CMDLINE_PREFIX) {
+            continue;
+        }
+
+        let (key, value) = entry.split_once('=').unwrap_or((entry, "true"));
+        if !key.contains('.') {
+            continue;
+        }
+
+        info!("config", "{key} = {value} (cmdline)");
+        if config_set(key, value, ConfigSource::CommandLine) {
+            count += 1;
+        }
+    }
+
+    count
+}
+
+/// Sets the options listed in a configuration file (see the [module documentation](self) for its syntax).
+///
+/// Invalid lines are ignored. Options already set on the command line are not overridden. Returns the number of
+/// options that were set.
+pub fn config_parse_file(contents: &str) -> usize {
+    let mut count = 0;
+
+    for (line_number, line) in contents.lines().enumerate() {
+        let line = line.split_once('#').map_or(line, |(line, _)| line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let Some((key, value)) = line
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .filter(|(key, _)| !key.is_empty() && !key.contains(char::is_whitespace))
+        else {
+            info!(
+                "config",
+                "ignoring invalid line {}: {line}",
+                line_number + 1
+            );
+            continue;
+        };
+
+        if config_set(key, value, ConfigSource::File) {
+            info!("config", "{key} = {value} (file)");
+            count += 1;
+        }
+    }
+
+    count
+}
+
+/// Returns the value of the option `key`, or its default value.
+pub fn get(key: &str) -> Option<String> {
+    if let Some(entry) = CONFIG.read().get(key) {
+        return Some(entry.value.clone());
+    }
+
+    default_value(key).map(String::from)
+}
+
+/// Returns the value of a boolean option (`true`, `yes`, `on` or `1`, and `false`, `no`, `off` or `0`).
+///
+/// If the value is invalid, the default value is used instead. Unknown options are disabled.
+pub fn get_bool(key: &str) -> bool {
+    if let Some(value) = CONFIG.read().get(key) {
+        match parse_bool(&value.value) {
+            Some(value) => return value,
+            None => {
+                info!("config", "invalid boolean value for {key}: {}", value.value);
+            }
+        }
+    }
+
+    default_value(key).and_then(parse_bool).unwrap_or(false)
+}
+
+/// Returns the value of an integer option (decimal, or hexadecimal with a `0x` prefix).
+///
+/// If the value is invalid, the default value is used instead.
+pub fn get_u64(key: &str) -> Option<u64> {
+    if let Some(value) = CONFIG.read().get(key) {
+        match parse_u64(&value.value) {
+            Some(value) => return Some(value),
+            None => {
+                info!("config", "invalid integer value for {key}: {}", value.value);
+            }
+        }
+    }
+
+    default_value(key).and_then(parse_u64)
+}
+
+/// Returns every option that is either known or set: key, value and source.
+pub fn config_entries() -> Vec<(String, String, ConfigSource)> {
+    let config = CONFIG.read();
+
+    let mut entries: Vec<_> = CONFIG_DEFAULTS
+        .iter()
+        .filter(|(key, _, _)| !config.contains_key(*key))
+        .map(|(key, value, _)| {
+            (
+                String::from(*key),
+                String::from(*value),
+                ConfigSource::Default,
+            )
+        })
+        .chain(
+            config
+                .iter()
+                .map(|(key, entry)| (key.clone(), entry.value.clone(), entry.source)),
+        )
+        .collect();
+
+    entries.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+    entries
+}
+
+/// Writes every option, one per line, with its value and where it comes from.
+pub fn config_report(out: &mut impl Write) -> fmt::Result {
+    for (key, value, source) in config_entries() {
+        writeln!(out, "{key:<24} = {value:<12} ({})", source.name())?;
+    }
+
+    Ok(())
+}
+
+fn default_value(key: &str) -> Option<&'static str> {
+    CONFIG_DEFAULTS
+        .iter()
+        .find(|(default_key, _, _)| *default_key == key)
+        .map(|(_, value, _)| *value)
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_u64(value: &str) -> Option<u64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
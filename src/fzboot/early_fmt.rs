@@ -0,0 +1,194 @@
+//! Formatting without allocation nor `core::fmt`, for the early stages.
+//!
+//! The real-mode stage cannot use `alloc`, and `core::fmt` is too large for its few sectors. [`StackBuffer`] is a
+//! fixed-size buffer on the stack, to which strings and numbers (converted with `numtoa`) are appended: once
+//! filled, the message is printed with [`bios_log`](crate::log::bios_log). Content that does not fit is truncated.
+//!
+//! The [`rerror_fmt!`](crate::rerror_fmt) and [`rinfo_fmt!`](crate::rinfo_fmt) macros build the message from a list of
+//! [`EarlyDisplay`] values, such as the status code of a failed BIOS call.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::bios::services::disk::drive_reset;
+//! use fzboot::early_fmt::Hex;
+//! use fzboot::rerror_fmt;
+//!
+//! if let Err(err) = drive_reset(0x80) {
+//!     rerror_fmt!("failed to reset drive ", Hex(0x80u8), ": ", err);
+//! }
+//! ```
+
+use numtoa::NumToA;
+
+use crate::bios::disk_status_name;
+use crate::errors::BiosError;
+
+/// Default size of the buffer used by the [`rerror_fmt!`](crate::rerror_fmt) and [`rinfo_fmt!`](crate::rinfo_fmt)
+/// macros, in bytes.
+pub const EARLY_FMT_BUFFER_SIZE: usize = 96;
+
+/// Fixed-size text buffer, stored on the stack.
+#[derive(Debug)]
+pub struct StackBuffer<const N: usize> {
+    buffer: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> StackBuffer<N> {
+    /// Creates an empty buffer.
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Appends `s` to the buffer, truncating it if the buffer is full.
+    pub fn push_str(&mut self, s: &str) {
+        let mut len = s.len().min(N - self.len);
+
+        // never split a character
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+
+        self.buffer[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+    }
+
+    /// Appends the decimal representation of `value`.
+    pub fn push_dec<T: NumToA<T> + From<u8>>(&mut self, value: T) {
+        let mut digits = [0u8; 20];
+        self.push_ascii(value.numtoa(T::from(10), &mut digits));
+    }
+
+    /// Appends the hexadecimal representation of `value`, prefixed with `0x`.
+    pub fn push_hex<T: NumToA<T> + From<u8>>(&mut self, value: T) {
+        let mut digits = [0u8; 20];
+        self.push_str("0x");
+        self.push_ascii(value.numtoa(T::from(16), &mut digits));
+    }
+
+    /// Returns the content of the buffer.
+    pub fn as_str(&self) -> &str {
+        // only complete UTF-8 strings are ever appended
+        core::str::from_utf8(&self.buffer[..self.len]).unwrap_or_default()
+    }
+
+    /// Whether the buffer is full: anything appended is dropped.
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Empties the buffer.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn push_ascii(&mut self, digits: &[u8]) {
+        let len = digits.len().min(N - self.len);
+
+        self.buffer[self.len..self.len + len].copy_from_slice(&digits[..len]);
+        self.len += len;
+    }
+}
+
+impl<const N: usize> Default for StackBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Value that can be appended to a [`StackBuffer`].
+pub trait EarlyDisplay {
+    /// Appends the representation of the value to `buffer`.
+    fn fmt_early<const N: usize>(&self, buffer: &mut StackBuffer<N>);
+}
+
+/// Displays a number in hexadecimal, prefixed with `0x`.
+#[derive(Debug, Clone, Copy)]
+pub struct Hex<T>(pub T);
+
+impl EarlyDisplay for &str {
+    fn fmt_early<const N: usize>(&self, buffer: &mut StackBuffer<N>) {
+        buffer.push_str(self);
+    }
+}
+
+macro_rules! early_display_num {
+    ($($num: ty),*) => {
+        $(
+            impl EarlyDisplay for $num {
+                fn fmt_early<const N: usize>(&self, buffer: &mut StackBuffer<N>) {
+                    buffer.push_dec(*self);
+                }
+            }
+
+            impl EarlyDisplay for Hex<$num> {
+                fn fmt_early<const N: usize>(&self, buffer: &mut StackBuffer<N>) {
+                    buffer.push_hex(self.0);
+                }
+            }
+        )*
+    };
+}
+
+early_display_num!(u8, u16, u32, u64, usize);
+
+impl EarlyDisplay for BiosError {
+    fn fmt_early<const N: usize>(&self, buffer: &mut StackBuffer<N>) {
+        match *self {
+            Self::Unsupported => buffer.push_str("unsupported BIOS service"),
+            Self::CallFailed(status) => {
+                buffer.push_str("BIOS call failed (status = ");
+                buffer.push_hex(status);
+                buffer.push_str(")");
+            }
+            Self::DiskFailed(status) => {
+                buffer.push_str("disk call failed: ");
+                buffer.push_str(disk_status_name(status));
+                buffer.push_str(" (status = ");
+                buffer.push_hex(status);
+                buffer.push_str(")");
+            }
+            Self::VbeFailed(status) => {
+                buffer.push_str("VBE call failed (status = ");
+                buffer.push_hex(status);
+                buffer.push_str(")");
+            }
+            Self::InvalidData => buffer.push_str("invalid BIOS data"),
+            Self::A20Unavailable => buffer.push_str("A20 line unavailable"),
+            Self::BridgeUnavailable => buffer.push_str("BIOS bridge unavailable"),
+        }
+    }
+}
+
+/// Real-mode counterpart of [`error!`](crate::error) with formatting: prints the concatenation of a list of
+/// [`EarlyDisplay`](crate::early_fmt::EarlyDisplay) values using the BIOS (see [`bios_log`](crate::log::bios_log)).
+#[macro_export]
+macro_rules! rerror_fmt {
+    ($($part: expr),+ $(,)?) => {
+        $crate::early_log_fmt!($crate::log::LogLevel::Error, $($part),+)
+    };
+}
+
+/// Real-mode counterpart of [`info!`](crate::info) with formatting: prints the concatenation of a list of
+/// [`EarlyDisplay`](crate::early_fmt::EarlyDisplay) values using the BIOS (see [`bios_log`](crate::log::bios_log)).
+#[macro_export]
+macro_rules! rinfo_fmt {
+    ($($part: expr),+ $(,)?) => {
+        $crate::early_log_fmt!($crate::log::LogLevel::Info, $($part),+)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! early_log_fmt {
+    ($level: expr, $($part: expr),+) => {{
+        let mut buffer =
+            $crate::early_fmt::StackBuffer::<{ $crate::early_fmt::EARLY_FMT_BUFFER_SIZE }>::new();
+        $($crate::early_fmt::EarlyDisplay::fmt_early(&$part, &mut buffer);)+
+        $crate::log::bios_log($level, buffer.as_str());
+    }};
+}
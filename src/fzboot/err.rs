@@ -1,10 +1,15 @@
-use core::{fmt::Debug, str::Utf8Error};
+use core::{
+    fmt::{self, Debug, Display},
+    str::Utf8Error,
+};
 
 #[cfg(feature = "alloc")]
 use alloc::boxed::Box;
 #[cfg(feature = "alloc")]
 use alloc::collections::TryReserveError;
 
+use crate::mem::MemoryError;
+
 /// `BaseError` is a common trait implemented by every error type defined in FrozenBoot.
 ///
 /// It is dependent on the [`Debug`] trait, which makes sense as we are dealing with errors.
@@ -71,6 +76,9 @@ pub enum IOError {
     /// Invalid device identifier supplied
     InvalidDevice,
 
+    /// No file or directory exists at the given path.
+    NotFound,
+
     #[cfg(feature = "alloc")]
     /// Generic error.
     Exception(Box<dyn BaseError>),
@@ -104,6 +112,521 @@ pub enum ClockError {
     Exception,
 }
 
+/// `BiosError` defines the errors that can be raised by the real-mode BIOS services wrappers
+/// (see `bios::services`), or when calling them from protected mode (see `bios::bridge`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BiosError {
+    /// The requested BIOS service (or extension) is not supported by the firmware.
+    Unsupported,
+
+    /// The BIOS call failed (carry flag set), with the status code returned in `AH`.
+    CallFailed(u8),
+
+    /// A disk service call (`INT 13h`) failed, with the status code returned in `AH` (see
+    /// `bios::disk_status_name`).
+    DiskFailed(u8),
+
+    /// A VBE function call failed, with the status returned in `AX`.
+    VbeFailed(u16),
+
+    /// The BIOS returned malformed or unexpected data.
+    InvalidData,
+
+    /// None of the available methods managed to enable the A20 line.
+    A20Unavailable,
+
+    /// BIOS services cannot be called from the current execution context (for instance, paging is enabled).
+    BridgeUnavailable,
+}
+
+impl BaseError for BiosError {}
+
+/// `AllocError` is returned by the fallible memory allocation APIs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocError {
+    /// Not enough memory was available to satisfy the request, even after reclaiming memory.
+    OutOfMemory,
+
+    /// The requested layout cannot be satisfied by the allocator (too large, or alignment too strict).
+    InvalidLayout,
+
+    /// The allocator has not been initialized yet.
+    Uninitialized,
+}
+
+impl BaseError for AllocError {}
+
+/// `HeapError` is returned by the consistency checks of the heap, only performed with the `heap_debug` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapError {
+    /// The redzone following the allocation at this address was overwritten (out-of-bounds write).
+    RedzoneOverwritten(usize),
+
+    /// The free block at this address was written to (use after free).
+    PoisonOverwritten(usize),
+
+    /// No allocation was found at this address, or its recorded size does not match (invalid or double free).
+    InvalidBlock(usize),
+
+    /// A free list contains a block at this address, which is outside of the heap or misaligned.
+    InvalidFreeBlock(usize),
+}
+
+impl BaseError for HeapError {}
+
+/// `ModuleError` is returned when a Kernel module cannot be loaded or unloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleError {
+    /// The module is not a valid ELF64 relocatable object for x86_64.
+    InvalidObject,
+
+    /// The module imports a symbol that is neither exported by the Kernel, nor by another module.
+    UnresolvedSymbol,
+
+    /// The module contains a relocation of an unsupported type.
+    UnsupportedRelocation(u32),
+
+    /// A relocated value does not fit in the relocated field.
+    RelocationOverflow,
+
+    /// The module does not define an `init` entry point.
+    MissingEntryPoint,
+
+    /// The `init` entry point of the module returned the given non-zero value.
+    InitFailed(i32),
+
+    /// A module with the same name is already loaded.
+    AlreadyLoaded,
+
+    /// No module with that name is loaded.
+    NotLoaded,
+
+    /// The module exports symbols that are used by another loaded module.
+    InUse,
+
+    /// Not enough memory (or virtual address space) was available to load the module.
+    OutOfMemory,
+
+    /// The module could not be read from the filesystem.
+    IOError,
+}
+
+impl BaseError for ModuleError {}
+
+/// `DecompressionError` is returned when a compressed payload cannot be decompressed (see `compress`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecompressionError {
+    /// The data does not start with the signature of a supported format.
+    UnknownFormat,
+
+    /// The stream uses a feature of the format that is not supported (for instance a preset dictionary).
+    Unsupported,
+
+    /// The stream is malformed.
+    InvalidData,
+
+    /// The input ended before the end of the stream.
+    UnexpectedEof,
+
+    /// The decompressed data does not fit in the output buffer.
+    OutputOverflow,
+
+    /// The checksum of the decompressed data does not match the one stored in the stream.
+    ChecksumMismatch,
+}
+
+impl BaseError for DecompressionError {}
+
+/// `ElfError` is returned when an ELF64 executable image cannot be loaded (see `boot::elf`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// The image is not a valid ELF64 executable for x86_64.
+    InvalidImage,
+
+    /// A segment lies outside of the image, or below the base address it is loaded relative to.
+    InvalidSegment,
+
+    /// The image does not contain any loadable segment.
+    NoLoadableSegment,
+}
+
+impl BaseError for ElfError {}
+
+/// `BootInfoError` is returned when the boot information structure handed over by the bootloader cannot be used (see
+/// `boot::info`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootInfoError {
+    /// The structure does not start with the expected magic number.
+    InvalidMagic,
+
+    /// The structure was produced by an incompatible bootloader.
+    UnsupportedVersion(u16),
+
+    /// The structure is smaller than expected for its version.
+    InvalidSize(u16),
+
+    /// The checksum of the structure does not match its content.
+    InvalidChecksum,
+}
+
+impl BaseError for BootInfoError {}
+
+/// `BootMetadataError` is returned when the boot metadata sector cannot be used, or does not match the kernel image
+/// (see `boot::metadata`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootMetadataError {
+    /// The sector could not be read from the disk.
+    ReadFailed,
+
+    /// The sector could not be written to the disk.
+    WriteFailed,
+
+    /// The sector does not start with the expected magic number (it was not written by the build tool).
+    InvalidMagic,
+
+    /// The sector was written by an incompatible build tool.
+    UnsupportedVersion(u16),
+
+    /// The size of the structure does not match its version.
+    InvalidSize(u16),
+
+    /// The checksum of the structure does not match its content.
+    InvalidChecksum,
+
+    /// The size of the kernel image does not match the one recorded in the sector.
+    KernelSizeMismatch,
+
+    /// The checksum of the kernel image does not match the one recorded in the sector.
+    KernelChecksumMismatch,
+}
+
+impl BaseError for BootMetadataError {}
+
+/// Errors related to the ownership of I/O ports (see `io::port`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortError {
+    /// A port of the range is already owned by another driver, whose name is given.
+    AlreadyClaimed(&'static str),
+
+    /// The range is empty, or extends past the last I/O port.
+    InvalidRange,
+
+    /// The maximum number of claimed ranges was reached.
+    TooManyClaims,
+}
+
+impl BaseError for PortError {}
+
+/// Errors that may happen while setting up the `APIC` of a processor (see `x86::apic`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicError {
+    /// The processor has no local `APIC`.
+    Unsupported,
+
+    /// Neither the ACPI `MADT` nor the _MP Configuration Table_ describe the interrupt controllers.
+    NoInterruptTable,
+
+    /// The interrupt vector of the _LVT_ entry is already in use.
+    VectorUnavailable,
+}
+
+impl BaseError for ApicError {}
+
+/// Errors related to the virtual memory areas of an address space (see [`crate::mem::vma`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaError {
+    /// The region is empty, or its bounds are not aligned on a page boundary.
+    InvalidRange,
+
+    /// The region overlaps an existing area.
+    Overlap,
+
+    /// The address is not covered by any area.
+    NotMapped,
+
+    /// The access is not allowed by the permissions of the area.
+    AccessViolation,
+
+    /// No free range of the address space is large enough.
+    NoSpace,
+
+    /// No physical memory was available to back the page.
+    OutOfMemory,
+
+    /// The content of the page could not be read from the backing file.
+    ReadFailed,
+
+    /// The page could not be inserted in the page table.
+    MappingFailed,
+
+    /// There is no address space to resolve the fault against (no process is running).
+    NoAddressSpace,
+}
+
+impl BaseError for VmaError {}
+
+/// Errors returned by system calls to the calling process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallError {
+    /// No such process (`ESRCH`).
+    NoProcess,
+
+    /// Not enough memory (`ENOMEM`).
+    OutOfMemory,
+
+    /// Bad address (`EFAULT`).
+    BadAddress,
+
+    /// The operation is not supported by the object (`ENODEV`).
+    NoDevice,
+
+    /// Invalid argument (`EINVAL`).
+    InvalidArgument,
+
+    /// Unknown system call (`ENOSYS`).
+    NotImplemented,
+
+    /// No such object (`ENOENT`).
+    NotFound,
+
+    /// Bad handle (`EBADF`).
+    BadHandle,
+
+    /// The operation would block (`EAGAIN`).
+    WouldBlock,
+
+    /// Too many objects are open (`EMFILE`).
+    TooManyHandles,
+
+    /// No reader is left on the pipe (`EPIPE`).
+    BrokenPipe,
+
+    /// Message too long (`EMSGSIZE`).
+    MessageTooLarge,
+
+    /// The operation timed out (`ETIMEDOUT`).
+    TimedOut,
+}
+
+impl SyscallError {
+    /// Returns the error number associated with this error, as defined by Linux.
+    pub fn errno(self) -> i64 {
+        match self {
+            Self::NoProcess => 3,
+            Self::OutOfMemory => 12,
+            Self::BadAddress => 14,
+            Self::NoDevice => 19,
+            Self::InvalidArgument => 22,
+            Self::NotImplemented => 38,
+            Self::NotFound => 2,
+            Self::BadHandle => 9,
+            Self::WouldBlock => 11,
+            Self::TooManyHandles => 24,
+            Self::BrokenPipe => 32,
+            Self::MessageTooLarge => 90,
+            Self::TimedOut => 110,
+        }
+    }
+}
+
+impl From<VmaError> for SyscallError {
+    fn from(value: VmaError) -> Self {
+        match value {
+            VmaError::InvalidRange | VmaError::Overlap => Self::InvalidArgument,
+            VmaError::NotMapped | VmaError::AccessViolation | VmaError::ReadFailed => {
+                Self::BadAddress
+            }
+            VmaError::NoSpace | VmaError::OutOfMemory | VmaError::MappingFailed => {
+                Self::OutOfMemory
+            }
+            VmaError::NoAddressSpace => Self::NoProcess,
+        }
+    }
+}
+
+impl From<IpcError> for SyscallError {
+    fn from(value: IpcError) -> Self {
+        match value {
+            IpcError::WouldBlock => Self::WouldBlock,
+            IpcError::BrokenPipe => Self::BrokenPipe,
+            IpcError::MessageTooLarge => Self::MessageTooLarge,
+            IpcError::TimedOut => Self::TimedOut,
+            IpcError::NotFound => Self::NotFound,
+            IpcError::InvalidArgument => Self::InvalidArgument,
+        }
+    }
+}
+
+impl BaseError for SyscallError {}
+
+/// Errors returned when starting the NMI watchdog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogError {
+    /// The processor does not support architectural performance monitoring.
+    NoPerfMonitoring,
+
+    /// The local APIC of the processor is not available.
+    NoLocalApic,
+}
+
+impl BaseError for WatchdogError {}
+
+/// Errors returned when enabling machine check reporting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MceError {
+    /// The processor does not support the machine check exception and architecture.
+    NotSupported,
+}
+
+impl BaseError for MceError {}
+
+/// Errors returned when setting up the DMA remapping hardware (`IOMMU`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IommuError {
+    /// The ACPI tables were not located.
+    NoAcpi,
+
+    /// The platform does not report any DMA remapping hardware (no `DMAR` table).
+    NoDmarTable,
+
+    /// The `DMAR` table does not describe any remapping hardware unit.
+    NoRemappingUnit,
+
+    /// The remapping hardware supports none of the implemented page table formats.
+    UnsupportedAddressWidth,
+
+    /// The remapping hardware did not complete a command in time.
+    Timeout,
+
+    /// Not enough memory for the translation structures.
+    OutOfMemory,
+}
+
+impl BaseError for IommuError {}
+
+/// Errors returned when allocating DMA buffers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmaError {
+    /// The requested size is zero.
+    InvalidSize,
+
+    /// Not enough physical memory (or memory for the translation structures) left.
+    OutOfMemory,
+
+    /// The buffer is not addressable by the device, and no `IOMMU` can remap it below the device's limit.
+    Unreachable,
+
+    /// No range of bus addresses large enough is left below the device's limit.
+    AddressSpaceExhausted,
+}
+
+impl BaseError for DmaError {}
+
+/// Errors returned by pipes and message queues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcError {
+    /// The operation cannot complete without blocking.
+    WouldBlock,
+
+    /// Every reader of the pipe was dropped.
+    BrokenPipe,
+
+    /// The message does not fit in the queue (or the buffer is smaller than the messages of the queue).
+    MessageTooLarge,
+
+    /// The operation did not complete before the timeout expired.
+    TimedOut,
+
+    /// No message queue is registered under this name.
+    NotFound,
+
+    /// The parameters of the queue are out of bounds.
+    InvalidArgument,
+}
+
+impl BaseError for IpcError {}
+
+/// Errors returned by the ACPI embedded controller driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcError {
+    /// No embedded controller was found on the platform.
+    NotPresent,
+
+    /// The embedded controller did not accept a command, or return data, in time.
+    Timeout,
+}
+
+impl BaseError for EcError {}
+
+/// Errors returned when changing the performance state of the processor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuFreqError {
+    /// The processor does not support (or the firmware disabled) performance state control.
+    NotSupported,
+
+    /// No performance state exists at the requested index.
+    InvalidState,
+}
+
+impl BaseError for CpuFreqError {}
+
+/// Errors returned by the performance monitoring unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PmuError {
+    /// The processor does not support architectural performance monitoring (version 2 or later).
+    NotSupported,
+
+    /// The processor cannot count the requested event.
+    EventUnavailable,
+
+    /// Every counter able to count the requested event is already in use.
+    NoCounterAvailable,
+
+    /// The local APIC of the processor is not available, overflow interrupts cannot be delivered.
+    NoLocalApic,
+}
+
+impl BaseError for PmuError {}
+
+/// Errors returned by the serial port driver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialError {
+    /// No UART responds at the port address.
+    NotPresent,
+}
+
+impl BaseError for SerialError {}
+
+/// Errors returned when ordering the initialization stages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitStageError {
+    /// A stage depends on a stage that was not declared.
+    UnknownDependency,
+
+    /// Stages depend on each other, no order can satisfy their dependencies.
+    DependencyCycle,
+}
+
+impl BaseError for InitStageError {}
+
+/// Errors returned by the cross-processor function calls (see `smp`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmpError {
+    /// The local APIC of the processor is not available, no `IPI` can be sent.
+    NoLocalApic,
+
+    /// The interrupt vector used for the calls is already in use.
+    VectorUnavailable,
+
+    /// The target processor is not online.
+    CpuOffline,
+
+    /// The target processor did not run the function in time.
+    Timeout,
+}
+
+impl BaseError for SmpError {}
+
 #[derive(Debug)]
 pub enum MountError {
     Unknown,
@@ -112,6 +635,18 @@ pub enum MountError {
     IOError,
 }
 
+/// Errors that may happen while unmounting a filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UmountError {
+    /// No filesystem is mounted with that identifier.
+    NotMounted,
+
+    /// Some files are still open on the filesystem.
+    Busy,
+}
+
+impl BaseError for UmountError {}
+
 #[derive(Debug)]
 pub enum InvalidAddress {
     InvalidAlignment,
@@ -138,3 +673,146 @@ impl BaseError for TryReserveError {}
 
 #[cfg(feature = "alloc")]
 impl BaseError for Utf8Error {}
+
+/// Maximum number of contexts attached to a [`KError`].
+pub const KERROR_MAX_CONTEXT: usize = 4;
+
+/// Defines [`KErrorKind`], and the conversion of each error type into a [`KError`].
+macro_rules! kerror_kinds {
+    ($($(#[$attr: meta])* $name: ident($error: ty)),* $(,)?) => {
+        /// Error wrapped in a [`KError`].
+        #[derive(Debug)]
+        pub enum KErrorKind {
+            $($(#[$attr])* $name($error),)*
+        }
+
+        $(
+            $(#[$attr])*
+            impl From<$error> for KError {
+                fn from(error: $error) -> Self {
+                    Self::new(KErrorKind::$name(error))
+                }
+            }
+        )*
+    };
+}
+
+kerror_kinds!(
+    Io(IOError),
+    Video(VideoError),
+    Clock(ClockError),
+    Bios(BiosError),
+    Alloc(AllocError),
+    Heap(HeapError),
+    Memory(MemoryError),
+    Module(ModuleError),
+    Decompression(DecompressionError),
+    Elf(ElfError),
+    BootInfo(BootInfoError),
+    BootMetadata(BootMetadataError),
+    Port(PortError),
+    Apic(ApicError),
+    Vma(VmaError),
+    Syscall(SyscallError),
+    Watchdog(WatchdogError),
+    Mce(MceError),
+    Iommu(IommuError),
+    Dma(DmaError),
+    Ipc(IpcError),
+    Ec(EcError),
+    CpuFreq(CpuFreqError),
+    Pmu(PmuError),
+    Serial(SerialError),
+    InitStage(InitStageError),
+    Smp(SmpError),
+    Mount(MountError),
+    Umount(UmountError),
+    E820(E820Error),
+    Utf8(Utf8Error),
+    #[cfg(feature = "alloc")]
+    TryReserve(TryReserveError),
+);
+
+/// `KError` is the kernel-wide error type: every error type of FrozenBoot converts into it.
+///
+/// Up to [`KERROR_MAX_CONTEXT`] static strings can be attached to the error while it goes up the call stack, describing
+/// what was being done when it happened (see [`Context`]). Its [`Display`] implementation prints the contexts, from the
+/// outermost to the innermost one, followed by the wrapped error. It does not allocate, and can be used before the
+/// heap is available.
+///
+/// # Examples
+///
+/// ```
+/// use fzboot::errors::{Context, KError};
+///
+/// fn mount_root() -> Result<(), KError> {
+///     read_superblock().context("reading superblock")?;
+///     Ok(())
+/// }
+///
+/// if let Err(err) = mount_root().context("mounting rootfs") {
+///     error!("fs", "{err}"); // mounting rootfs: reading superblock: Mount(BadSuperblock)
+/// }
+/// ```
+#[derive(Debug)]
+pub struct KError {
+    kind: KErrorKind,
+
+    /// Contexts, from the innermost to the outermost one.
+    context: [Option<&'static str>; KERROR_MAX_CONTEXT],
+}
+
+impl KError {
+    /// Wraps an error, without any context.
+    pub fn new(kind: KErrorKind) -> Self {
+        Self {
+            kind,
+            context: [None; KERROR_MAX_CONTEXT],
+        }
+    }
+
+    /// Returns the wrapped error.
+    pub fn kind(&self) -> &KErrorKind {
+        &self.kind
+    }
+
+    /// Attaches a context to the error.
+    ///
+    /// Once [`KERROR_MAX_CONTEXT`] contexts are attached, the outermost ones are dropped.
+    pub fn with_context(mut self, context: &'static str) -> Self {
+        if let Some(slot) = self.context.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(context);
+        }
+
+        self
+    }
+
+    /// Returns an iterator over the contexts of the error, from the outermost to the innermost one.
+    pub fn contexts(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.context.iter().rev().flatten().copied()
+    }
+}
+
+impl Display for KError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for context in self.contexts() {
+            write!(f, "{context}: ")?;
+        }
+
+        write!(f, "{:?}", self.kind)
+    }
+}
+
+impl BaseError for KError {}
+
+/// Attaches a context to the error of a [`Result`], converting it into a [`KError`].
+pub trait Context<T> {
+    /// Converts the error into a [`KError`], and attaches `context` to it.
+    fn context(self, context: &'static str) -> Result<T, KError>;
+}
+
+impl<T, E: Into<KError>> Context<T> for Result<T, E> {
+    fn context(self, context: &'static str) -> Result<T, KError> {
+        self.map_err(|err| err.into().with_context(context))
+    }
+}
@@ -0,0 +1,230 @@
+//! Device lifecycle event bus.
+//!
+//! Subsystems publish an event ([`DeviceEvent`]) each time a device appears or disappears, and other subsystems
+//! subscribe to the kinds of events they care about, instead of relying on being initialized after the devices were
+//! enumerated.
+//!
+//! Events are recorded in a history, replayed to every new subscriber: a subscriber registered after the
+//! enumeration is notified of the devices found so far, the same way it would have been if it were registered before.
+//! Events about devices added to the device tree (see [`devtree`]) are published by the device tree itself.
+//!
+//! Handlers are called synchronously by the publisher, and may publish events or subscribe themselves. They must not
+//! block: events may be published from the disk drivers while a device is being initialized.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::event::{subscribe, DeviceEvent, EventKind};
+//!
+//! fn on_partition(event: &DeviceEvent) {
+//!     if let DeviceEvent::PartitionFound { path, .. } = event {
+//!         info!("example", "new partition: {path}");
+//!     }
+//! }
+//!
+//! subscribe("example", EventKind::PartitionFound.mask(), on_partition);
+//! ```
+//!
+//! [`devtree`]: crate::drivers::devtree
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt::{self, Display};
+use spin::Mutex;
+
+use crate::drivers::devtree::DevicePath;
+use crate::drivers::ide::AtaDeviceIdentifier;
+use crate::info;
+
+/// Maximum number of events kept in the history (older events are not replayed).
+pub const EVENT_HISTORY_LEN: usize = 256;
+
+/// Mask matching every kind of event.
+pub const EVENT_MASK_ALL: u32 = u32::MAX;
+
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+
+static HISTORY: Mutex<VecDeque<DeviceEvent>> = Mutex::new(VecDeque::new());
+
+/// Callback called with each event a subscriber is interested in.
+pub type EventHandler = fn(&DeviceEvent);
+
+/// Device lifecycle event.
+#[derive(Clone, Debug)]
+pub enum DeviceEvent {
+    /// A PCI function was found, during the enumeration or in a hot-plug slot.
+    PciAdded {
+        location: (u8, u8, u8),
+        path: DevicePath,
+    },
+
+    /// A PCI function was removed from a hot-plug slot.
+    PciRemoved {
+        location: (u8, u8, u8),
+        path: DevicePath,
+    },
+
+    /// A disk device was identified.
+    DiskAttached {
+        drive: AtaDeviceIdentifier,
+        path: DevicePath,
+    },
+
+    /// A disk device was disconnected.
+    DiskDetached {
+        drive: AtaDeviceIdentifier,
+        path: DevicePath,
+    },
+
+    /// A partition was found on a disk device, with its index in the partition table.
+    PartitionFound {
+        drive: AtaDeviceIdentifier,
+        partition: usize,
+        path: DevicePath,
+    },
+
+    /// The link of a device (network interface, PCI Express port) went up.
+    LinkUp { path: DevicePath },
+
+    /// The link of a device went down.
+    LinkDown { path: DevicePath },
+}
+
+impl DeviceEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::PciAdded { .. } => EventKind::PciAdded,
+            Self::PciRemoved { .. } => EventKind::PciRemoved,
+            Self::DiskAttached { .. } => EventKind::DiskAttached,
+            Self::DiskDetached { .. } => EventKind::DiskDetached,
+            Self::PartitionFound { .. } => EventKind::PartitionFound,
+            Self::LinkUp { .. } => EventKind::LinkUp,
+            Self::LinkDown { .. } => EventKind::LinkDown,
+        }
+    }
+
+    /// Path of the device this event is about.
+    pub fn path(&self) -> &DevicePath {
+        match self {
+            Self::PciAdded { path, .. }
+            | Self::PciRemoved { path, .. }
+            | Self::DiskAttached { path, .. }
+            | Self::DiskDetached { path, .. }
+            | Self::PartitionFound { path, .. }
+            | Self::LinkUp { path }
+            | Self::LinkDown { path } => path,
+        }
+    }
+}
+
+impl Display for DeviceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.kind() {
+            EventKind::PciAdded => "PCI device added",
+            EventKind::PciRemoved => "PCI device removed",
+            EventKind::DiskAttached => "disk attached",
+            EventKind::DiskDetached => "disk detached",
+            EventKind::PartitionFound => "partition found",
+            EventKind::LinkUp => "link up",
+            EventKind::LinkDown => "link down",
+        };
+
+        write!(f, "{action}: {}", self.path())
+    }
+}
+
+/// Kind of [`DeviceEvent`], used to select the events a subscriber is notified of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EventKind {
+    PciAdded,
+    PciRemoved,
+    DiskAttached,
+    DiskDetached,
+    PartitionFound,
+    LinkUp,
+    LinkDown,
+}
+
+impl EventKind {
+    /// Mask matching this kind of event, to be combined with other masks (`|`) when subscribing.
+    pub const fn mask(self) -> u32 {
+        1 << self as u8
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Subscriber {
+    name: &'static str,
+    mask: u32,
+    handler: EventHandler,
+}
+
+/// Registers `handler`, called with each published event of a kind included in `mask`.
+///
+/// The handler is first called with the matching events of the history. Subscribing under a name that is already in
+/// use replaces the previous subscriber (without replaying the history again).
+pub fn subscribe(name: &'static str, mask: u32, handler: EventHandler) {
+    let subscriber = Subscriber {
+        name,
+        mask,
+        handler,
+    };
+
+    {
+        let mut subscribers = SUBSCRIBERS.lock();
+
+        if let Some(entry) = subscribers.iter_mut().find(|entry| entry.name == name) {
+            *entry = subscriber;
+            return;
+        }
+
+        subscribers.push(subscriber);
+    }
+
+    let history: Vec<DeviceEvent> = HISTORY.lock().iter().cloned().collect();
+
+    for event in history
+        .iter()
+        .filter(|event| event.kind().mask() & mask != 0)
+    {
+        handler(event);
+    }
+}
+
+/// Unregisters a subscriber, previously registered using [`subscribe`].
+pub fn unsubscribe(name: &'static str) {
+    SUBSCRIBERS.lock().retain(|entry| entry.name != name);
+}
+
+/// Records an event in the history, and calls every subscriber interested in it.
+pub fn publish(event: DeviceEvent) {
+    {
+        let mut history = HISTORY.lock();
+
+        if history.len() == EVENT_HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(event.clone());
+    }
+
+    let subscribers = SUBSCRIBERS.lock().clone();
+    let mask = event.kind().mask();
+
+    for subscriber in subscribers
+        .iter()
+        .filter(|subscriber| subscriber.mask & mask != 0)
+    {
+        (subscriber.handler)(&event);
+    }
+}
+
+/// Returns the events of the history, from the oldest to the most recent.
+pub fn event_history() -> Vec<DeviceEvent> {
+    HISTORY.lock().iter().cloned().collect()
+}
+
+/// Logs every device event.
+pub fn event_log_init() {
+    subscribe("log", EVENT_MASK_ALL, |event| info!("devices", "{event}"));
+}
@@ -0,0 +1,75 @@
+//! Local `APIC` error reporting.
+//!
+//! The local `APIC` reports the errors it detects (checksum errors on the `APIC` bus, illegal vectors, accesses to
+//! reserved registers, ...) in its _Error Status Register_ (`ESR`), and signals them with the interrupt described by
+//! the _LVT Error_ entry. Without a handler, such errors are silently dropped, along with the interrupt that caused
+//! them: [`apic_error_init`] unmasks the entry, and every error raised is decoded and logged.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use fzproc_macros::interrupt_handler;
+
+use crate::{
+    error,
+    errors::{ApicError, Context, KError},
+    irq::{manager::get_interrupt_manager, InterruptStackFrame},
+    x86::apic::local_apic::{local_apic, try_local_apic, InterruptVector},
+};
+
+/// Number of local `APIC` error interrupts received so far.
+static APIC_ERROR_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Registers the handler of the local `APIC` error interrupt, and unmasks it on the current processor.
+///
+/// # Errors
+///
+/// Returns the error raised while initializing the local `APIC` (see [`try_local_apic`]), or
+/// [`ApicError::VectorUnavailable`] if the vector of the _LVT Error_ entry is already in use.
+pub fn apic_error_init() -> Result<(), KError> {
+    let lapic = try_local_apic().context("enabling APIC error reporting")?;
+    let int_mgr = get_interrupt_manager();
+
+    int_mgr
+        .reserve_vector(InterruptVector::APIC_ERROR_VECTOR, "apic-error")
+        .map_err(|_| ApicError::VectorUnavailable)?;
+
+    if int_mgr
+        .register_static_handler(InterruptVector::APIC_ERROR_VECTOR, apic_error_handler)
+        .is_err()
+    {
+        int_mgr.free_vector(InterruptVector::APIC_ERROR_VECTOR);
+        return Err(ApicError::VectorUnavailable.into());
+    }
+
+    lapic.set_error_interrupt(true);
+
+    Ok(())
+}
+
+/// Number of local `APIC` error interrupts received since boot.
+pub fn apic_error_count() -> u64 {
+    APIC_ERROR_COUNT.load(Ordering::Relaxed)
+}
+
+/// Decodes and logs the content of the _Error Status Register_ of the current processor.
+#[interrupt_handler]
+pub fn apic_error_handler(frame: InterruptStackFrame) {
+    APIC_ERROR_COUNT.fetch_add(1, Ordering::Relaxed);
+
+    let Some(lapic) = local_apic() else {
+        return;
+    };
+
+    let esr = lapic.read_error_register();
+    if esr.is_empty() {
+        error!("apic", "error interrupt raised with an empty ESR");
+        return;
+    }
+
+    error!(
+        "apic",
+        "local APIC error (ESR = {:#04x}): {}",
+        u32::from(esr),
+        esr
+    );
+}
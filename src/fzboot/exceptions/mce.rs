@@ -0,0 +1,526 @@
+//! Machine check architecture (`MCA`) error reporting.
+//!
+//! Hardware errors detected by the processor (cache, TLB, bus or memory controller errors) are logged in its error
+//! reporting banks, each made of 4 MSRs (`MCi_CTL`, `MCi_STATUS`, `MCi_ADDR` and `MCi_MISC`). Errors that could not
+//! be corrected are also signaled with a machine check exception (`#MC`).
+//!
+//! When handling a `#MC`, every bank holding a valid error is decoded into an [`MceRecord`], and the action depends
+//! on the worst [`MceSeverity`] found:
+//!
+//! - if the processor context is corrupt, or if the interrupted program cannot be restarted, the kernel panics after
+//!   logging the decoded errors.
+//! - if an error was not corrected, but the processor state is intact, the kernel panics as well, unless this was
+//!   disabled with [`set_mce_panic_on_uncorrected`].
+//! - otherwise, the banks are cleared and execution resumes.
+//!
+//! Corrected errors do not raise a `#MC`: banks are polled every [`MCE_POLL_INTERVAL`] for such errors. As a `#MC`
+//! may interrupt code holding the lock of the kernel log, errors the kernel recovers from are only logged from the
+//! next poll.
+//!
+//! Errors still present in the banks when the reporting is enabled were logged before the last (warm) reset, and are
+//! reported as such: they usually explain why the machine rebooted.
+
+use core::{
+    fmt::{self, Display},
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+    time::Duration,
+};
+
+use spin::Mutex;
+
+use crate::{
+    error,
+    errors::MceError,
+    info,
+    irq::InterruptStackFrame,
+    klog::klog_force_unlock,
+    time::timer,
+    x86::{
+        cpuid::{cpu_feature_support, CPU_FEAT_MCA, CPU_FEAT_MCE},
+        msr::{msr_read, msr_write},
+        registers::control::{ControlRegister, Cr4},
+    },
+};
+
+use super::panic::panic_entry_exception;
+
+/// Interval between two polls of the error reporting banks, for corrected errors.
+pub const MCE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Maximum number of error reporting banks handled.
+pub const MCE_MAX_BANKS: usize = 32;
+
+/// Maximum number of errors waiting to be logged.
+const MCE_PENDING_RECORDS: usize = 16;
+
+const IA32_MCG_CAP: u32 = 0x179;
+const IA32_MCG_STATUS: u32 = 0x17A;
+const IA32_MCG_CTL: u32 = 0x17B;
+const IA32_MC0_CTL: u32 = 0x400;
+
+const MCG_CAP_COUNT: u64 = 0xFF;
+const MCG_CAP_CTL_P: u64 = 1 << 8;
+
+/// Restart IP valid: the interrupted program can be restarted.
+const MCG_STATUS_RIPV: u64 = 1 << 0;
+
+/// Error IP valid: the interrupted instruction is directly associated with the error.
+const MCG_STATUS_EIPV: u64 = 1 << 1;
+
+/// Machine check in progress: cleared by software once the `#MC` is handled.
+const MCG_STATUS_MCIP: u64 = 1 << 2;
+
+const MCI_STATUS_VAL: u64 = 1 << 63;
+const MCI_STATUS_OVER: u64 = 1 << 62;
+const MCI_STATUS_UC: u64 = 1 << 61;
+const MCI_STATUS_EN: u64 = 1 << 60;
+const MCI_STATUS_MISCV: u64 = 1 << 59;
+const MCI_STATUS_ADDRV: u64 = 1 << 58;
+const MCI_STATUS_PCC: u64 = 1 << 57;
+
+static MCE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+static MCE_PANIC_ON_UNCORRECTED: AtomicBool = AtomicBool::new(true);
+
+/// Number of error reporting banks of the processor.
+static MCE_BANKS: AtomicU8 = AtomicU8::new(0);
+
+/// Number of hardware errors reported since boot.
+static MCE_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Errors recovered from in the `#MC` handler, waiting to be logged by the next poll.
+static MCE_PENDING: Mutex<[Option<MceRecord>; MCE_PENDING_RECORDS]> =
+    Mutex::new([None; MCE_PENDING_RECORDS]);
+
+/// Severity of a hardware error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MceSeverity {
+    /// The error was corrected by the hardware.
+    Corrected,
+
+    /// The error was not corrected, but the state of the processor is intact.
+    Uncorrected,
+
+    /// The state of the processor is corrupt: execution cannot safely continue.
+    Fatal,
+}
+
+/// Class of a hardware error, decoded from its `MCA` error code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MceErrorClass {
+    /// Error of a translation lookaside buffer.
+    Tlb,
+
+    /// Error of the cache hierarchy.
+    Cache,
+
+    /// Error of the memory controller.
+    Memory,
+
+    /// Error on the bus or the interconnect.
+    Bus,
+
+    /// Internal error of the processor (parity, timer, microcode, or model-specific).
+    Internal,
+}
+
+/// Hardware error logged in an error reporting bank.
+#[derive(Clone, Copy, Debug)]
+pub struct MceRecord {
+    /// Index of the bank.
+    pub bank: u8,
+
+    /// Content of `MCi_STATUS`.
+    pub status: u64,
+
+    /// Content of `MCi_ADDR`, if valid.
+    pub addr: Option<u64>,
+
+    /// Content of `MCi_MISC`, if valid.
+    pub misc: Option<u64>,
+}
+
+impl MceRecord {
+    /// Reads the error logged in `bank`, if any.
+    fn read(bank: u8) -> Option<Self> {
+        let status = msr_read(bank_msr(bank, 1))?;
+
+        if status & MCI_STATUS_VAL == 0 {
+            return None;
+        }
+
+        Some(Self {
+            bank,
+            status,
+            addr: (status & MCI_STATUS_ADDRV != 0)
+                .then(|| msr_read(bank_msr(bank, 2)))
+                .flatten(),
+            misc: (status & MCI_STATUS_MISCV != 0)
+                .then(|| msr_read(bank_msr(bank, 3)))
+                .flatten(),
+        })
+    }
+
+    /// Architectural `MCA` error code.
+    pub fn error_code(&self) -> u16 {
+        self.status as u16
+    }
+
+    /// Model-specific error code.
+    pub fn model_code(&self) -> u16 {
+        (self.status >> 16) as u16
+    }
+
+    /// Whether an earlier error was lost, because it was not cleared before this one was logged.
+    pub fn overflow(&self) -> bool {
+        self.status & MCI_STATUS_OVER != 0
+    }
+
+    pub fn severity(&self) -> MceSeverity {
+        if self.status & MCI_STATUS_PCC != 0 {
+            MceSeverity::Fatal
+        } else if self.status & MCI_STATUS_UC != 0 {
+            MceSeverity::Uncorrected
+        } else {
+            MceSeverity::Corrected
+        }
+    }
+
+    /// Class of the error, based on its compound error code.
+    pub fn class(&self) -> MceErrorClass {
+        // Bit 12 (corrected error filtering) is not part of the error code.
+        let code = self.error_code() & !(1 << 12);
+
+        if code & 0xF800 == 0x0800 {
+            MceErrorClass::Bus
+        } else if code & 0xFF00 == 0x0100 {
+            MceErrorClass::Cache
+        } else if code & 0xFF80 == 0x0080 {
+            MceErrorClass::Memory
+        } else if code & 0xFFF0 == 0x0010 {
+            MceErrorClass::Tlb
+        } else {
+            MceErrorClass::Internal
+        }
+    }
+
+    fn fmt_details(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = self.error_code() & !(1 << 12);
+
+        match self.class() {
+            MceErrorClass::Tlb => write!(
+                f,
+                "{} TLB error ({})",
+                transaction_type(code),
+                memory_level(code)
+            ),
+            MceErrorClass::Cache => write!(
+                f,
+                "{} cache error ({}, {})",
+                transaction_type(code),
+                request_type(code),
+                memory_level(code)
+            ),
+            MceErrorClass::Memory => {
+                let transaction = match (code >> 4) & 0x7 {
+                    0 => "generic",
+                    1 => "read",
+                    2 => "write",
+                    3 => "address/command",
+                    4 => "scrubbing",
+                    _ => "reserved",
+                };
+
+                match code & 0xF {
+                    0xF => write!(f, "memory controller {} error", transaction),
+                    channel => write!(
+                        f,
+                        "memory controller {} error (channel {})",
+                        transaction, channel
+                    ),
+                }
+            }
+            MceErrorClass::Bus => {
+                let participation = match (code >> 9) & 0x3 {
+                    0 => "local processor originated",
+                    1 => "local processor responded",
+                    2 => "local processor observed",
+                    _ => "generic",
+                };
+                let timeout = if code & (1 << 8) != 0 {
+                    ", timed out"
+                } else {
+                    ""
+                };
+                let area = match (code >> 2) & 0x3 {
+                    0 => "memory",
+                    2 => "I/O",
+                    3 => "other",
+                    _ => "reserved",
+                };
+
+                write!(
+                    f,
+                    "bus error ({}, {} {} access{}, {})",
+                    participation,
+                    request_type(code),
+                    area,
+                    timeout,
+                    memory_level(code)
+                )
+            }
+            MceErrorClass::Internal => match code {
+                0x0000 => write!(f, "no error"),
+                0x0001 => write!(f, "unclassified error"),
+                0x0002 => write!(f, "microcode ROM parity error"),
+                0x0003 => write!(f, "external error"),
+                0x0004 => write!(f, "FRC error"),
+                0x0005 => write!(f, "internal parity error"),
+                0x0006 => write!(f, "SMM handler code access violation"),
+                0x0400 => write!(f, "internal timer error"),
+                0x0401..=0x07FF => write!(f, "internal unclassified error"),
+                _ => write!(f, "unknown error"),
+            },
+        }
+    }
+}
+
+impl Display for MceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity() {
+            MceSeverity::Corrected => "corrected",
+            MceSeverity::Uncorrected => "uncorrected",
+            MceSeverity::Fatal => "fatal",
+        };
+
+        write!(f, "bank {}: {} ", self.bank, severity)?;
+        self.fmt_details(f)?;
+        write!(
+            f,
+            " [status = {:#018x}, code = {:#06x}, model code = {:#06x}",
+            self.status,
+            self.error_code(),
+            self.model_code()
+        )?;
+
+        if let Some(addr) = self.addr {
+            write!(f, ", addr = {:#x}", addr)?;
+        }
+        if let Some(misc) = self.misc {
+            write!(f, ", misc = {:#x}", misc)?;
+        }
+        if self.overflow() {
+            write!(f, ", overflow")?;
+        }
+        if self.status & MCI_STATUS_EN == 0 {
+            write!(f, ", not signaled")?;
+        }
+
+        write!(f, "]")
+    }
+}
+
+/// Enables the machine check exception and the error reporting banks of the current processor.
+///
+/// Errors left in the banks since the last reset are logged, then cleared.
+///
+/// # Errors
+///
+/// Returns [`MceError::NotSupported`] if the processor does not support the machine check exception and
+/// architecture.
+pub fn mce_init() -> Result<(), MceError> {
+    let supported = cpu_feature_support(CPU_FEAT_MCE).unwrap_or(false)
+        && cpu_feature_support(CPU_FEAT_MCA).unwrap_or(false);
+    if !supported {
+        return Err(MceError::NotSupported);
+    }
+
+    let cap = msr_read(IA32_MCG_CAP).ok_or(MceError::NotSupported)?;
+    let banks = u8::min((cap & MCG_CAP_COUNT) as u8, MCE_MAX_BANKS as u8);
+
+    for bank in 0..banks {
+        if let Some(record) = MceRecord::read(bank) {
+            error!("mce", "error logged before the last reset: {}", record);
+        }
+    }
+
+    unsafe {
+        if cap & MCG_CAP_CTL_P != 0 {
+            msr_write(IA32_MCG_CTL, u64::MAX);
+        }
+
+        for bank in 0..banks {
+            // Bank 0 is configured by the firmware on some processors, and must be left as is.
+            if bank != 0 {
+                msr_write(bank_msr(bank, 0), u64::MAX);
+            }
+            msr_write(bank_msr(bank, 1), 0);
+        }
+    }
+
+    MCE_BANKS.store(banks, Ordering::Relaxed);
+    MCE_ENABLED.store(true, Ordering::Release);
+
+    Cr4::write(Cr4::read().with_machine_check(true));
+
+    if timer::periodic(MCE_POLL_INTERVAL, mce_poll).is_err() {
+        info!(
+            "mce",
+            "timer queue unavailable, corrected errors are not polled"
+        );
+    }
+
+    info!("mce", "machine check reporting enabled ({} banks)", banks);
+
+    Ok(())
+}
+
+/// Sets whether the kernel panics on uncorrected errors that left the processor state intact.
+///
+/// Errors that corrupted the processor state always cause a panic.
+pub fn set_mce_panic_on_uncorrected(enabled: bool) {
+    MCE_PANIC_ON_UNCORRECTED.store(enabled, Ordering::Release);
+}
+
+/// Number of hardware errors reported since boot.
+pub fn mce_count() -> u64 {
+    MCE_COUNT.load(Ordering::Relaxed)
+}
+
+/// Logs the errors recovered from since the last poll, and the corrected errors logged in the banks.
+pub fn mce_poll() {
+    if !MCE_ENABLED.load(Ordering::Acquire) {
+        return;
+    }
+
+    if let Some(mut pending) = MCE_PENDING.try_lock() {
+        for record in pending.iter_mut().filter_map(Option::take) {
+            error!("mce", "{}", record);
+        }
+    }
+
+    for bank in 0..MCE_BANKS.load(Ordering::Relaxed) {
+        // Uncorrected errors are handled by the `#MC` handler.
+        let Some(record) = MceRecord::read(bank).filter(|r| r.status & MCI_STATUS_UC == 0) else {
+            continue;
+        };
+
+        unsafe {
+            msr_write(bank_msr(bank, 1), 0);
+        }
+
+        MCE_COUNT.fetch_add(1, Ordering::Relaxed);
+        error!("mce", "{}", record);
+    }
+}
+
+/// Handles a machine check exception.
+///
+/// Panics if the error cannot be recovered from (see the [module documentation](self)).
+pub(super) fn handle_machine_check(frame: InterruptStackFrame) {
+    let mcg_status = msr_read(IA32_MCG_STATUS).unwrap_or_default();
+
+    let mut records = [None; MCE_MAX_BANKS];
+    let mut worst = MceSeverity::Corrected;
+
+    for bank in 0..MCE_BANKS.load(Ordering::Relaxed) {
+        let record = MceRecord::read(bank);
+
+        if let Some(record) = record {
+            worst = MceSeverity::max(worst, record.severity());
+            MCE_COUNT.fetch_add(1, Ordering::Relaxed);
+        }
+
+        records[usize::from(bank)] = record;
+    }
+
+    // The interrupted program cannot be restarted.
+    if mcg_status & MCG_STATUS_RIPV == 0 {
+        worst = MceSeverity::Fatal;
+    }
+
+    let must_panic = match worst {
+        MceSeverity::Fatal => true,
+        MceSeverity::Uncorrected => MCE_PANIC_ON_UNCORRECTED.load(Ordering::Acquire),
+        MceSeverity::Corrected => false,
+    };
+
+    if must_panic {
+        unsafe {
+            klog_force_unlock();
+        }
+
+        error!(
+            "mce",
+            "machine check (restart ip {}, error ip {})",
+            if mcg_status & MCG_STATUS_RIPV != 0 {
+                "valid"
+            } else {
+                "invalid"
+            },
+            if mcg_status & MCG_STATUS_EIPV != 0 {
+                "valid"
+            } else {
+                "invalid"
+            }
+        );
+        for record in records.iter().flatten() {
+            error!("mce", "{}", record);
+        }
+
+        panic_entry_exception("MACHINE_CHECK", frame.into());
+    }
+
+    unsafe {
+        for record in records.iter().flatten() {
+            msr_write(bank_msr(record.bank, 1), 0);
+        }
+        msr_write(IA32_MCG_STATUS, mcg_status & !MCG_STATUS_MCIP);
+    }
+
+    if let Some(mut pending) = MCE_PENDING.try_lock() {
+        for record in records.into_iter().flatten() {
+            if let Some(slot) = pending.iter_mut().find(|slot| slot.is_none()) {
+                *slot = Some(record);
+            }
+        }
+    }
+}
+
+/// Returns the `register`-th MSR (`CTL`, `STATUS`, `ADDR` then `MISC`) of the error reporting bank `bank`.
+fn bank_msr(bank: u8, register: u32) -> u32 {
+    IA32_MC0_CTL + 4 * u32::from(bank) + register
+}
+
+fn transaction_type(code: u16) -> &'static str {
+    match (code >> 2) & 0x3 {
+        0 => "instruction",
+        1 => "data",
+        2 => "generic",
+        _ => "reserved",
+    }
+}
+
+fn memory_level(code: u16) -> &'static str {
+    match code & 0x3 {
+        0 => "level 0",
+        1 => "level 1",
+        2 => "level 2",
+        _ => "generic level",
+    }
+}
+
+fn request_type(code: u16) -> &'static str {
+    match (code >> 4) & 0xF {
+        0 => "generic",
+        1 => "read",
+        2 => "write",
+        3 => "data read",
+        4 => "data write",
+        5 => "instruction fetch",
+        6 => "prefetch",
+        7 => "eviction",
+        8 => "snoop",
+        _ => "reserved",
+    }
+}
@@ -1,23 +1,99 @@
-use exception_vectors::{DOUBLE_FAULT, GENERAL_PROT_FAULT, PAGE_FAULT};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use exception_vectors::{
+    DOUBLE_FAULT, GENERAL_PROT_FAULT, MACHINE_CHECK, NON_MASKABLE_INTERRUPT, PAGE_FAULT,
+};
 use fzproc_macros::interrupt_handler;
 use panic::panic_entry_exception;
 
-use crate::irq::manager::get_interrupt_manager;
+use crate::{
+    info,
+    irq::manager::get_interrupt_manager,
+    mem::vma::{handle_page_fault, PageFaultCode},
+    smp::smp_stop_nmi,
+    syscall::user_access_fixup,
+    x86::{
+        descriptors::tss::{current_tss, InterruptStackIndex},
+        pmu::handle_pmu_nmi,
+        registers::control::{ControlRegister, Cr2},
+    },
+};
+
+/// Number of non-maskable interrupts received so far.
+static NMI_COUNT: AtomicU64 = AtomicU64::new(0);
 
+pub mod apic_error;
+pub mod mce;
 pub mod panic;
+pub mod watchdog;
 
 pub mod exception_vectors {
     use crate::x86::apic::InterruptVector;
 
+    pub const NON_MASKABLE_INTERRUPT: InterruptVector = InterruptVector::new(0x2);
     pub const DOUBLE_FAULT: InterruptVector = InterruptVector::new(0x8);
     pub const GENERAL_PROT_FAULT: InterruptVector = InterruptVector::new(0xD);
     pub const PAGE_FAULT: InterruptVector = InterruptVector::new(0xE);
+    pub const MACHINE_CHECK: InterruptVector = InterruptVector::new(0x12);
 }
 
+/// Registers the exception handlers.
+///
+/// If the `TSS` of the current processor is set up, double faults, non-maskable interrupts and machine checks are
+/// handled on their dedicated stacks (see [`InterruptStackIndex`]).
 pub fn register_exception_handlers() {
-    get_interrupt_manager().register_static_handler(DOUBLE_FAULT, double_fault_handler);
-    get_interrupt_manager().register_static_handler(GENERAL_PROT_FAULT, unhandled_gpf_handler);
-    get_interrupt_manager().register_static_handler(PAGE_FAULT, unhandled_page_fault_handler);
+    let int_mgr = get_interrupt_manager();
+
+    int_mgr.register_static_handler(NON_MASKABLE_INTERRUPT, nmi_handler);
+    int_mgr.register_static_handler(DOUBLE_FAULT, double_fault_handler);
+    int_mgr.register_static_handler(GENERAL_PROT_FAULT, unhandled_gpf_handler);
+    int_mgr.register_static_handler(PAGE_FAULT, unhandled_page_fault_handler);
+    int_mgr.register_static_handler(MACHINE_CHECK, machine_check_handler);
+
+    if current_tss().is_none() {
+        info!(
+            "exceptions",
+            "no TSS loaded, exceptions are handled on the interrupted stack"
+        );
+        return;
+    }
+
+    for (vector, ist) in [
+        (DOUBLE_FAULT, InterruptStackIndex::DoubleFault),
+        (
+            NON_MASKABLE_INTERRUPT,
+            InterruptStackIndex::NonMaskableInterrupt,
+        ),
+        (MACHINE_CHECK, InterruptStackIndex::MachineCheck),
+    ] {
+        if int_mgr.set_interrupt_stack(vector, ist.into()).is_err() {
+            info!(
+                "exceptions",
+                "failed to set the interrupt stack of vector {:?}", vector
+            );
+        }
+    }
+}
+
+/// Number of non-maskable interrupts received since boot.
+pub fn nmi_count() -> u64 {
+    NMI_COUNT.load(Ordering::Relaxed)
+}
+
+/// Non-maskable interrupts are counted, and passed to the NMI watchdog (see [`watchdog`]).
+///
+/// If another processor panicked, the current one is halted instead (see
+/// [`smp_stop_others`](crate::smp::smp_stop_others)).
+///
+/// This may interrupt code holding any lock (including the one of the kernel log), so nothing is logged from here.
+/// Non-maskable interrupts are not acknowledged: no end of interrupt is signaled to the local APIC, which would
+/// otherwise acknowledge the interrupt being serviced by the interrupted code.
+#[interrupt_handler(eoi = false)]
+pub fn nmi_handler(frame: InterruptStackFrame) {
+    NMI_COUNT.fetch_add(1, Ordering::Relaxed);
+    smp_stop_nmi(&frame);
+    handle_pmu_nmi(&frame);
+    watchdog::handle_watchdog_nmi(frame);
 }
 
 #[interrupt_handler(exception = true)]
@@ -25,12 +101,35 @@ pub fn double_fault_handler(frame: ExceptionStackFrame) {
     panic_entry_exception("DOUBLE_FAULT", frame)
 }
 
+/// Resolves page faults against the virtual memory areas of the current process, and panics if the faulting address
+/// is not covered by any of them (or if the access is not allowed).
+///
+/// Unresolved faults on the memory of a process accessed on behalf of a system call do not panic: the access fails
+/// instead (see [`user_access_fixup`]).
 #[interrupt_handler(exception = true)]
-pub fn unhandled_page_fault_handler(frame: ExceptionStackFrame) {
-    panic_entry_exception("PAGE_FAULT", frame)
+pub fn unhandled_page_fault_handler(frame: ExceptionStackFrame) -> u64 {
+    let fault_addr = Cr2::read().fault_addr();
+
+    if handle_page_fault(fault_addr, PageFaultCode::new(frame.error_code)).is_ok() {
+        return 0;
+    }
+
+    match user_access_fixup(frame.rip) {
+        Some(resume) => u64::from(resume),
+        None => panic_entry_exception("PAGE_FAULT", frame),
+    }
 }
 
 #[interrupt_handler(exception = true)]
 pub fn unhandled_gpf_handler(frame: ExceptionStackFrame) {
     panic_entry_exception("GENERAL_PROTECTION_FAULT", frame)
 }
+
+/// Machine checks do not push an error code, so this is handled as a regular interrupt.
+///
+/// The errors are decoded from the error reporting banks, and execution only resumes if they can be recovered from
+/// (see [`mce`]).
+#[interrupt_handler(eoi = false)]
+pub fn machine_check_handler(frame: InterruptStackFrame) {
+    mce::handle_machine_check(frame);
+}
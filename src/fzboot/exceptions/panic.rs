@@ -1,41 +1,73 @@
 use core::{
     arch::asm,
     fmt::Write,
+    panic::{Location, PanicInfo},
     sync::atomic::{AtomicBool, Ordering},
 };
 
-use alloc::format;
+use alloc::{format, string::String};
 use fzproc_macros::interrupt_handler;
 
 use crate::{
+    fs::pstore::pstore_write_crash,
+    io::qemu_exit::qemu_exit_on_panic,
     irq::{manager::get_interrupt_manager, ExceptionStackFrame},
-    mem::{MemoryAddress, PhyAddr, VirtAddr},
-    video::vesa::{framebuffer::RgbaColor, text_buffer},
+    klog::{klog_force_unlock, klog_write},
+    panicking::{panic_enter, panic_nested},
+    power::reboot::reboot,
+    smp::{smp_stop_others, stopped_cpus},
+    video::{
+        gfx::qr::{QrCode, QrEcc, QR_QUIET_ZONE},
+        vesa::{
+            framebuffer::{RgbaColor, TextFrameBuffer, BORDER},
+            text_buffer,
+        },
+    },
     x86::{
         apic::InterruptVector,
-        descriptors::idt::{GateDescriptor, GateType, InterruptDescriptorTable},
-        int::enable_interrupts,
-        paging::page_table::mapper::{MemoryMapping, PhysicalMemoryMapping},
+        idle::cpu_idle,
+        int::{disable_interrupts, enable_interrupts},
     },
 };
 
 static KEY_PRESSED: AtomicBool = AtomicBool::new(false);
 
+static PANIC_QR_CODE: AtomicBool = AtomicBool::new(true);
+
+/// Largest size of a QR code module on the panic screen, in pixels.
+const PANIC_QR_MAX_SCALE: usize = 4;
+
+/// Vertical position of the QR code on the panic screen (below the panic header), in pixels.
+const PANIC_QR_TOP: usize = 64;
+
 /// Entry point when the kernel explicity panics (usually through the [`core::panic`] macro).
 ///
-/// Only displays the message given at the panic call site, contrary to exceptions handlers that display more
-/// information about the current state of the system.
-pub fn panic_entry_no_exception(error_msg: &str) -> ! {
+/// Only displays the message given at the panic call site and its location, contrary to exceptions handlers that
+/// display more information about the current state of the system.
+///
+/// If a panic is already being handled, only the location of this one is reported (see [`panic_nested`]).
+pub fn panic_entry_no_exception(info: &PanicInfo) -> ! {
+    let location = info.location();
+    if panic_enter() != 0 {
+        panic_nested(location);
+    }
+
+    let unresponsive_cpus = smp_stop_others();
+
     unsafe {
         text_buffer().buffer.force_unlock();
+        klog_force_unlock();
     }
     write_panic_header();
 
     let mut text_buffer: spin::MutexGuard<crate::video::vesa::framebuffer::TextFrameBuffer<'_>> =
         text_buffer().buffer.lock();
 
-    let register_dump = format!("EXPLICIT_PANIC: {}\n", error_msg);
-    text_buffer.write_str_bitmap(&register_dump);
+    let mut report = match location {
+        Some(location) => format!("EXPLICIT_PANIC: {} at {}\n", info.message(), location),
+        None => format!("EXPLICIT_PANIC: {}\n", info.message()),
+    };
+    text_buffer.write_str_bitmap(&report);
 
     let base_ptr: usize;
 
@@ -43,15 +75,24 @@ pub fn panic_entry_no_exception(error_msg: &str) -> ! {
         asm!("mov {}, rbp", out(reg) base_ptr);
     }
 
-    print_stack_trace(base_ptr as *const usize);
-
     drop(text_buffer);
-    any_key_or_reboot()
+    report.push_str(&print_stack_trace(base_ptr as *const usize));
+    report.push_str(&print_other_cpus(unresponsive_cpus));
+
+    any_key_or_reboot(&report, location)
 }
 
 pub fn panic_entry_exception(error_msg: &str, frame: ExceptionStackFrame) -> ! {
+    // An exception raised while handling a panic (for instance, a page fault while drawing the report).
+    if panic_enter() != 0 {
+        panic_nested(None);
+    }
+
+    let unresponsive_cpus = smp_stop_others();
+
     unsafe {
         text_buffer().buffer.force_unlock();
+        klog_force_unlock();
     }
 
     write_panic_header();
@@ -59,14 +100,95 @@ pub fn panic_entry_exception(error_msg: &str, frame: ExceptionStackFrame) -> ! {
     let mut text_buffer: spin::MutexGuard<crate::video::vesa::framebuffer::TextFrameBuffer<'_>> =
         text_buffer().buffer.lock();
 
-    text_buffer.write_str_bitmap(&format!(
+    let mut report = format!(
         "EXCEPTION_{} (#{:x}) STOP at {} \n",
         error_msg, frame.error_code, frame.rip
-    ));
+    );
+    text_buffer.write_str_bitmap(&report);
 
     text_buffer.write_str("\n\n\n");
 
-    text_buffer.write_str_bitmap(&format!(
+    let register_dump = register_dump(&frame);
+    text_buffer.write_str_bitmap(&register_dump);
+    report.push_str(&register_dump);
+
+    drop(text_buffer);
+    report.push_str(&print_stack_trace(frame.registers.rbp as *const usize));
+    report.push_str(&print_other_cpus(unresponsive_cpus));
+
+    any_key_or_reboot(&report, None)
+}
+
+/// Enables or disables the QR code displayed on the panic screen.
+///
+/// The QR code encodes the panic message, the register state and the stack trace, so that crashes can be reported
+/// from a photo of the screen.
+pub fn set_panic_qr_code(enabled: bool) {
+    PANIC_QR_CODE.store(enabled, Ordering::Release);
+}
+
+/// Prints the stack trace, and returns its textual representation.
+fn print_stack_trace(mut frame_base_ptr: *const usize) -> String {
+    unsafe {
+        text_buffer().buffer.force_unlock();
+    }
+    let mut text_buffer: spin::MutexGuard<crate::video::vesa::framebuffer::TextFrameBuffer<'_>> =
+        text_buffer().buffer.lock();
+
+    let mut trace = String::from("Stack trace:\n");
+    text_buffer.write_str_bitmap("\n\nStack trace: \n");
+
+    let mut stack_frame_pos = 0;
+    // as long as the pointer is not null and we have a higher half address (kernel stack is located in the higher half of the virtual
+    // memory address space).
+    while !frame_base_ptr.is_null() && (((frame_base_ptr as u64) & 1 << 63) != 0) {
+        if stack_frame_pos > 6 {
+            break;
+        }
+        let return_addr = unsafe { *(frame_base_ptr.offset(1)) };
+
+        if return_addr != 0 {
+            let line = format!("[{}] {:#018x?} \n", stack_frame_pos, return_addr);
+            text_buffer.write_str_bitmap(&line);
+            trace.push_str(&line);
+        }
+        frame_base_ptr = unsafe { *(frame_base_ptr) as *const usize };
+        stack_frame_pos += 1;
+    }
+
+    trace
+}
+
+/// Prints the register state saved by the processors halted when the panic started (see [`smp_stop_others`]), and
+/// returns its textual representation.
+fn print_other_cpus(unresponsive_cpus: usize) -> String {
+    let mut states = String::new();
+
+    for (cpu, frame) in stopped_cpus() {
+        states.push_str(&format!(
+            "\nCPU {}:\n{}",
+            u8::from(cpu),
+            register_dump(&frame.into())
+        ));
+    }
+
+    if unresponsive_cpus != 0 {
+        states.push_str(&format!(
+            "\n{} processor(s) did not stop\n",
+            unresponsive_cpus
+        ));
+    }
+
+    if !states.is_empty() {
+        text_buffer().buffer.lock().write_str_bitmap(&states);
+    }
+
+    states
+}
+
+/// Formats the register state saved in `frame`.
+fn register_dump(frame: &ExceptionStackFrame) -> String {
+    format!(
         "RSP: {:#018x}        RBP: {:#018x}        RFLAGS: {:#018x}
 RAX: {:#018x}        RBX: {:#018x}        RCX: {:#018x}
 RDX: {:#018x}        RSI: {:#018x}        RDI: {:#018x}
@@ -91,45 +213,42 @@ R14: {:#018x}        R15: {:#018x}        RIP: {:#018x}\n",
         frame.registers.r14,
         frame.registers.r15,
         u64::from(frame.rip)
-    ));
-
-    print_stack_trace(frame.registers.rbp as *const usize);
-
-    drop(text_buffer);
-    any_key_or_reboot()
+    )
 }
 
-fn print_stack_trace(mut frame_base_ptr: *const usize) {
-    unsafe {
-        text_buffer().buffer.force_unlock();
+/// Draws a QR code encoding `report` in the top-right corner of the screen, if enabled.
+///
+/// Nothing is drawn if the report is too large to be encoded, or if the screen is too small to display the QR code.
+fn draw_report_qr(text_buffer: &mut TextFrameBuffer, report: &str) {
+    if !PANIC_QR_CODE.load(Ordering::Acquire) {
+        return;
     }
-    let mut text_buffer: spin::MutexGuard<crate::video::vesa::framebuffer::TextFrameBuffer<'_>> =
-        text_buffer().buffer.lock();
 
-    text_buffer.write_str_bitmap("\n\nStack trace: \n");
+    let Some(qr) = QrCode::encode_bytes(report.as_bytes(), QrEcc::Low) else {
+        return;
+    };
 
-    let mut stack_frame_pos = 0;
-    // as long as the pointer is not null and we have a higher half address (kernel stack is located in the higher half of the virtual
-    // memory address space).
-    while !frame_base_ptr.is_null() && (((frame_base_ptr as u64) & 1 << 63) != 0) {
-        if stack_frame_pos > 6 {
-            break;
-        }
-        let return_addr = unsafe { *(frame_base_ptr.offset(1)) };
+    let modules = qr.size() + 2 * QR_QUIET_ZONE;
+    let available = usize::min(
+        text_buffer.metadata.width / 3,
+        text_buffer.metadata.height / 2,
+    );
+    let scale = usize::min(available / modules, PANIC_QR_MAX_SCALE);
 
-        if return_addr != 0 {
-            text_buffer
-                .write_str_bitmap(&format!("[{}] {:#018x?} \n", stack_frame_pos, return_addr));
-        }
-        frame_base_ptr = unsafe { *(frame_base_ptr) as *const usize };
-        stack_frame_pos += 1;
+    if scale == 0 {
+        return;
     }
+
+    let x = text_buffer.metadata.width - modules * scale - BORDER;
+    qr.draw(text_buffer, x, PANIC_QR_TOP, scale);
 }
 
 fn write_panic_header() {
     let mut text_buffer: spin::MutexGuard<crate::video::vesa::framebuffer::TextFrameBuffer<'_>> =
         text_buffer().buffer.lock();
 
+    // Frames may not be flushed anymore, the panic report is written directly.
+    text_buffer.set_auto_flush(true);
     text_buffer.set_background(Some(RgbaColor(255, 50, 50, 0)));
     text_buffer.clear();
 
@@ -142,12 +261,19 @@ fn write_panic_header() {
     );
 }
 
-fn any_key_or_reboot() -> ! {
+fn any_key_or_reboot(report: &str, location: Option<&Location>) -> ! {
     let mut text_buffer: spin::MutexGuard<crate::video::vesa::framebuffer::TextFrameBuffer<'_>> =
         text_buffer().buffer.lock();
 
     text_buffer.write_str("\n\n\n");
     text_buffer.write_str_bitmap_centered("Press any key to reboot", false);
+    draw_report_qr(&mut text_buffer, report);
+    drop(text_buffer);
+
+    // Best effort, the disk may not be usable anymore.
+    klog_write(report);
+    let _ = pstore_write_crash(report.lines().next().unwrap_or_default(), location);
+    qemu_exit_on_panic();
 
     #[interrupt_handler]
     fn kb_handler(frame: InterruptStackFrame) {
@@ -161,20 +287,13 @@ fn any_key_or_reboot() -> ! {
         get_interrupt_manager().load_idt();
     }
 
-    while !KEY_PRESSED.load(Ordering::Acquire) {}
-
-    unsafe {
-        let mut dummy_idt = InterruptDescriptorTable::<VirtAddr>::new(
-            PhysicalMemoryMapping::KERNEL_DEFAULT_MAPPING.convert(PhyAddr::NULL_PTR + 0x1000_usize),
-        );
-        dummy_idt.set_entry_unchecked(
-            InterruptVector::TIMER_IRQ.into(),
-            GateDescriptor::new(GateType::InterruptGate),
-        );
-
-        dummy_idt.write_table();
-        dummy_idt.enable();
+    loop {
+        disable_interrupts();
+        if KEY_PRESSED.load(Ordering::Acquire) {
+            break;
+        }
+        cpu_idle();
     }
 
-    loop {}
+    reboot();
 }
@@ -0,0 +1,219 @@
+//! NMI watchdog (hard lockup detection).
+//!
+//! The first general-purpose performance counter of each processor counts unhalted core cycles, and raises a
+//! non-maskable interrupt every time it overflows (at most every [`WATCHDOG_PERIOD_SECS`] seconds of busy CPU time).
+//! As an `NMI` cannot be masked, it is delivered even when the processor is stuck with interrupts disabled.
+//!
+//! The scheduler tick signals that the processor is making progress ([`watchdog_touch`]). If the processor does not
+//! report any progress for [`WATCHDOG_THRESHOLD`] consecutive watchdog `NMIs`, it is considered locked up: the
+//! kernel panics from the `NMI` handler, which dumps the state of the processor (registers and stack trace) at the
+//! time of the lockup.
+//!
+//! Halted processors do not count cycles, so an idle processor never triggers the watchdog.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+
+use crate::{
+    errors::WatchdogError,
+    info,
+    irq::InterruptStackFrame,
+    x86::{
+        apic::local_apic::{local_apic, ProcLocalApicID},
+        cpuid::cpu_id,
+        msr::{msr_read, msr_write},
+        tsc::TSC_CLK,
+    },
+};
+
+use super::panic::panic_entry_exception;
+
+/// Approximate busy time between two watchdog `NMIs`, in seconds.
+pub const WATCHDOG_PERIOD_SECS: f64 = 1.0;
+
+/// Number of consecutive watchdog `NMIs` without progress after which a processor is considered locked up.
+pub const WATCHDOG_THRESHOLD: u64 = 10;
+
+const IA32_PMC0: u32 = 0xC1;
+const IA32_PERFEVTSEL0: u32 = 0x186;
+const IA32_PERF_GLOBAL_STATUS: u32 = 0x38E;
+const IA32_PERF_GLOBAL_CTRL: u32 = 0x38F;
+const IA32_PERF_GLOBAL_OVF_CTRL: u32 = 0x390;
+
+/// _UnHalted Core Cycles_ architectural event.
+const EVENT_UNHALTED_CORE_CYCLES: u64 = 0x3C;
+const EVTSEL_USR: u64 = 1 << 16;
+const EVTSEL_OS: u64 = 1 << 17;
+const EVTSEL_INT: u64 = 1 << 20;
+const EVTSEL_EN: u64 = 1 << 22;
+
+static WATCHDOG_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Version of the architectural performance monitoring.
+static PERFMON_VERSION: AtomicU8 = AtomicU8::new(0);
+
+/// Value loaded in the counter after each overflow.
+static COUNTER_RELOAD: AtomicU64 = AtomicU64::new(0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const CPU_WATCHDOG_INIT: CpuWatchdog = CpuWatchdog::new();
+
+/// Watchdog state of each processor, by local APIC identifier.
+static CPU_WATCHDOGS: [CpuWatchdog; 256] = [CPU_WATCHDOG_INIT; 256];
+
+struct CpuWatchdog {
+    /// Incremented every time the processor reports progress.
+    heartbeat: AtomicU64,
+
+    /// Value of `heartbeat` during the last watchdog `NMI`.
+    last_heartbeat: AtomicU64,
+
+    /// Number of consecutive watchdog `NMIs` without progress.
+    stalls: AtomicU64,
+}
+
+impl CpuWatchdog {
+    const fn new() -> Self {
+        Self {
+            heartbeat: AtomicU64::new(0),
+            last_heartbeat: AtomicU64::new(0),
+            stalls: AtomicU64::new(0),
+        }
+    }
+
+    fn current() -> &'static Self {
+        &CPU_WATCHDOGS[usize::from(u8::from(ProcLocalApicID::get()))]
+    }
+}
+
+/// Starts the NMI watchdog on the current processor.
+///
+/// # Errors
+///
+/// Returns [`WatchdogError::NoPerfMonitoring`] if the processor does not support architectural performance
+/// monitoring (or cannot count unhalted core cycles), or [`WatchdogError::NoLocalApic`] if the local APIC is not
+/// available.
+pub fn nmi_watchdog_init() -> Result<(), WatchdogError> {
+    let [perfmon, events, _, _] = cpu_id(0xA).ok_or(WatchdogError::NoPerfMonitoring)?;
+    let version = (perfmon & 0xFF) as u8;
+    let counters = (perfmon >> 8) & 0xFF;
+    let width = (perfmon >> 16) & 0xFF;
+    let events_len = (perfmon >> 24) & 0xFF;
+
+    // Bit 0 of `EBX` is set if the unhalted core cycles event is not available.
+    if version == 0 || counters == 0 || width == 0 || events_len == 0 || events & 1 != 0 {
+        return Err(WatchdogError::NoPerfMonitoring);
+    }
+
+    // Only the low 32 bits of the counters can be written (sign-extended to the counter width). Without a calibrated
+    // TSC, the longest period is used.
+    let period = TSC_CLK.get().map_or(i32::MAX as u64, |tsc| {
+        u64::min(
+            (tsc.frequency() * WATCHDOG_PERIOD_SECS) as u64,
+            i32::MAX as u64,
+        )
+    });
+    let reload = period.wrapping_neg() & ((1 << width) - 1);
+
+    PERFMON_VERSION.store(version, Ordering::Relaxed);
+    COUNTER_RELOAD.store(reload, Ordering::Relaxed);
+
+    let watchdog = CpuWatchdog::current();
+    watchdog.last_heartbeat.store(
+        watchdog.heartbeat.load(Ordering::Relaxed),
+        Ordering::Relaxed,
+    );
+    watchdog.stalls.store(0, Ordering::Relaxed);
+
+    local_apic()
+        .ok_or(WatchdogError::NoLocalApic)?
+        .set_perf_counter_nmi(true);
+
+    unsafe {
+        msr_write(IA32_PERFEVTSEL0, 0);
+        msr_write(IA32_PMC0, reload);
+        msr_write(
+            IA32_PERFEVTSEL0,
+            EVENT_UNHALTED_CORE_CYCLES | EVTSEL_USR | EVTSEL_OS | EVTSEL_INT | EVTSEL_EN,
+        );
+
+        if version >= 2 {
+            let global_ctrl = msr_read(IA32_PERF_GLOBAL_CTRL).unwrap_or_default();
+            msr_write(IA32_PERF_GLOBAL_CTRL, global_ctrl | 1);
+        }
+    }
+
+    WATCHDOG_ENABLED.store(true, Ordering::Release);
+    info!(
+        "watchdog",
+        "NMI watchdog enabled (period = {} cycles, threshold = {})", period, WATCHDOG_THRESHOLD
+    );
+
+    Ok(())
+}
+
+/// Stops the NMI watchdog on the current processor.
+pub fn nmi_watchdog_disable() {
+    if !WATCHDOG_ENABLED.swap(false, Ordering::AcqRel) {
+        return;
+    }
+
+    unsafe {
+        msr_write(IA32_PERFEVTSEL0, 0);
+    }
+
+    if let Some(lapic) = local_apic() {
+        lapic.set_perf_counter_nmi(false);
+    }
+}
+
+/// Signals that the current processor is making progress.
+///
+/// Called on every scheduler tick. Code that legitimately keeps interrupts disabled for a long time (such as
+/// polling a slow device) should call it regularly as well.
+pub fn watchdog_touch() {
+    CpuWatchdog::current()
+        .heartbeat
+        .fetch_add(1, Ordering::Relaxed);
+}
+
+/// Handles an `NMI` raised by the watchdog.
+///
+/// Returns `false` if the `NMI` was not raised by the watchdog. Panics if the current processor did not report any
+/// progress for [`WATCHDOG_THRESHOLD`] watchdog `NMIs`.
+pub(super) fn handle_watchdog_nmi(frame: InterruptStackFrame) -> bool {
+    if !WATCHDOG_ENABLED.load(Ordering::Acquire) {
+        return false;
+    }
+
+    let version = PERFMON_VERSION.load(Ordering::Relaxed);
+    if version >= 2 && msr_read(IA32_PERF_GLOBAL_STATUS).unwrap_or_default() & 1 == 0 {
+        return false;
+    }
+
+    unsafe {
+        msr_write(IA32_PMC0, COUNTER_RELOAD.load(Ordering::Relaxed));
+        if version >= 2 {
+            msr_write(IA32_PERF_GLOBAL_OVF_CTRL, 1);
+        }
+    }
+
+    // The performance counter entry of the LVT is masked when the interrupt is delivered.
+    if let Some(lapic) = local_apic() {
+        lapic.set_perf_counter_nmi(true);
+    }
+
+    let watchdog = CpuWatchdog::current();
+    let heartbeat = watchdog.heartbeat.load(Ordering::Relaxed);
+
+    if watchdog.last_heartbeat.swap(heartbeat, Ordering::Relaxed) != heartbeat {
+        watchdog.stalls.store(0, Ordering::Relaxed);
+        return true;
+    }
+
+    if watchdog.stalls.fetch_add(1, Ordering::Relaxed) + 1 >= WATCHDOG_THRESHOLD {
+        nmi_watchdog_disable();
+        panic_entry_exception("HARD_LOCKUP", frame.into());
+    }
+
+    true
+}
@@ -0,0 +1,239 @@
+//! Fault injection.
+//!
+//! A _failpoint_ is a named site in the code where a failure can be forced, so that the error handling paths that
+//! are hard to reach on real hardware (allocation failures, disk errors, timeouts) can be exercised.
+//!
+//! Failpoints are configured from the kernel command line, using one `failpoint=<name>=<action>` entry per site:
+//!
+//! ```text
+//! failpoint=ahci.read=once failpoint=ext4.read=10% failpoint=alloc.heap=after:5000
+//! ```
+//!
+//! The following actions are supported (see [`FailAction`]):
+//!
+//! - `off`: never fail.
+//! - `always`: always fail.
+//! - `once`: fail the first time the site is reached.
+//! - `times:<n>`: fail the `n` first times the site is reached.
+//! - `after:<n>`: succeed the `n` first times the site is reached, and then always fail.
+//! - `<p>%`: fail with a probability of `p` percent.
+//!
+//! Sites are declared using the [`fail_point!`] macro. Until a failpoint is configured, checking a site only costs
+//! an atomic load.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::fail_point;
+//!
+//! fn read_sector(lba: u64) -> Result<(), IOError> {
+//!     fail_point!("disk.read", return Err(IOError::Unknown));
+//!
+//!     // ...
+//! }
+//! ```
+
+use alloc::{collections::BTreeMap, string::String};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::RwLock;
+
+use crate::info;
+
+/// Prefix of the kernel command line entries configuring a failpoint.
+pub const FAILPOINT_CMDLINE_PREFIX: &str = "failpoint=";
+
+static FAILPOINTS: RwLock<BTreeMap<String, Failpoint>> = RwLock::new(BTreeMap::new());
+
+/// Set once a failpoint was configured, so that sites are cheap to check otherwise.
+static FAILPOINTS_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// State of the pseudo-random generator used by [`FailAction::Probability`].
+static FAILPOINT_RNG: AtomicU64 = AtomicU64::new(0x2545_f491_4f6c_dd1d);
+
+/// What happens when a failpoint site is reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailAction {
+    /// Never fail.
+    Off,
+
+    /// Always fail.
+    Always,
+
+    /// Fail the given number of times, and then succeed.
+    Times(u64),
+
+    /// Succeed the given number of times, and then always fail.
+    After(u64),
+
+    /// Fail with the given probability, in percent.
+    Probability(u8),
+}
+
+impl FailAction {
+    /// Parses an action, using the command line syntax (see the [module documentation](self)).
+    pub fn parse(action: &str) -> Option<Self> {
+        match action {
+            "off" => return Some(Self::Off),
+            "always" => return Some(Self::Always),
+            "once" => return Some(Self::Times(1)),
+            _ => (),
+        }
+
+        if let Some(count) = action.strip_prefix("times:") {
+            return count.parse().ok().map(Self::Times);
+        }
+
+        if let Some(count) = action.strip_prefix("after:") {
+            return count.parse().ok().map(Self::After);
+        }
+
+        action
+            .strip_suffix('%')
+            .and_then(|probability| probability.parse().ok())
+            .filter(|&probability| probability <= 100)
+            .map(Self::Probability)
+    }
+}
+
+/// Statistics of a failpoint, see [`failpoint_stats`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FailpointStats {
+    /// Number of times the site was reached.
+    pub hits: u64,
+
+    /// Number of failures that were injected.
+    pub failures: u64,
+}
+
+#[derive(Debug)]
+struct Failpoint {
+    action: FailAction,
+    hits: AtomicU64,
+    failures: AtomicU64,
+}
+
+impl Failpoint {
+    fn new(action: FailAction) -> Self {
+        Self {
+            action,
+            hits: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+        }
+    }
+
+    fn evaluate(&self) -> bool {
+        let hit = self.hits.fetch_add(1, Ordering::Relaxed);
+
+        let fail = match self.action {
+            FailAction::Off => false,
+            FailAction::Always => true,
+            FailAction::Times(count) => hit < count,
+            FailAction::After(count) => hit >= count,
+            FailAction::Probability(probability) => random() % 100 < u64::from(probability),
+        };
+
+        if fail {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fail
+    }
+}
+
+/// Configures the failpoint `name`, replacing its previous configuration.
+pub fn failpoint_set(name: &str, action: FailAction) {
+    FAILPOINTS
+        .write()
+        .insert(String::from(name), Failpoint::new(action));
+    FAILPOINTS_ACTIVE.store(true, Ordering::Release);
+}
+
+/// Removes the configuration of the failpoint `name`, which never fails afterwards.
+pub fn failpoint_clear(name: &str) {
+    FAILPOINTS.write().remove(name);
+}
+
+/// Configures the failpoints listed on the kernel command line.
+///
+/// Entries that are not failpoints are ignored, as well as invalid entries. Returns the number of failpoints that
+/// were configured.
+pub fn failpoints_parse(cmdline: &str) -> usize {
+    let mut count = 0;
+
+    for entry in cmdline.split_ascii_whitespace() {
+        let Some(entry) = entry.strip_prefix(FAILPOINT_CMDLINE_PREFIX) else {
+            continue;
+        };
+
+        let Some((name, action)) = entry
+            .split_once('=')
+            .and_then(|(name, action)| Some((name, FailAction::parse(action)?)))
+        else {
+            info!("failpoint", "ignoring invalid failpoint: {entry}");
+            continue;
+        };
+
+        info!("failpoint", "{name} = {action:?}");
+        failpoint_set(name, action);
+        count += 1;
+    }
+
+    count
+}
+
+/// Checks if a failure must be injected at the site `name`.
+///
+/// Usually called through the [`fail_point!`] macro. It never allocates, so that it can be used within allocators.
+pub fn failpoint_triggered(name: &str) -> bool {
+    if !FAILPOINTS_ACTIVE.load(Ordering::Acquire) {
+        return false;
+    }
+
+    // The lock is held while configuring a failpoint, which may itself reach a site (when allocating memory).
+    let Some(failpoints) = FAILPOINTS.try_read() else {
+        return false;
+    };
+
+    failpoints.get(name).is_some_and(Failpoint::evaluate)
+}
+
+/// Returns the statistics of the failpoint `name`, if it is configured.
+pub fn failpoint_stats(name: &str) -> Option<FailpointStats> {
+    FAILPOINTS.read().get(name).map(|failpoint| FailpointStats {
+        hits: failpoint.hits.load(Ordering::Relaxed),
+        failures: failpoint.failures.load(Ordering::Relaxed),
+    })
+}
+
+/// Xorshift pseudo-random generator, good enough to spread injected failures.
+fn random() -> u64 {
+    let mut state = FAILPOINT_RNG.load(Ordering::Relaxed);
+
+    state ^= state << 13;
+    state ^= state >> 7;
+    state ^= state << 17;
+
+    FAILPOINT_RNG.store(state, Ordering::Relaxed);
+    state
+}
+
+/// Declares a failpoint site.
+///
+/// If a failure must be injected at the site (see [`failpoint_triggered`]), the expression is evaluated (usually to
+/// return an error).
+///
+/// # Examples
+///
+/// ```
+/// use fzboot::fail_point;
+///
+/// fail_point!("ahci.timeout", return Err(IOError::IOTimeout));
+/// ```
+#[macro_export]
+macro_rules! fail_point {
+    ($name: literal, $fail: expr) => {
+        if $crate::failpoint::failpoint_triggered($name) {
+            $fail;
+        }
+    };
+}
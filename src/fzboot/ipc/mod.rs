@@ -0,0 +1,78 @@
+//! Inter-process communication.
+//!
+//! Kernel tasks and processes communicate through [`pipe`]s (byte streams) and [`mqueue`]s (message queues). Both
+//! can be used directly from the kernel, or through system calls (see `syscall::ipc`).
+//!
+//! Objects opened through system calls are referred to by handles, which are indices in the [`HandleTable`] of the
+//! calling process.
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+
+use mqueue::MessageQueue;
+use pipe::{PipeReader, PipeWriter};
+
+pub mod mqueue;
+pub mod pipe;
+
+/// Maximum number of handles a process can hold.
+pub const MAX_HANDLES: usize = 1024;
+
+/// An object a process holds a handle to.
+#[derive(Debug, Clone)]
+pub enum IpcObject {
+    PipeReader(Arc<PipeReader>),
+    PipeWriter(Arc<PipeWriter>),
+    MessageQueue(Arc<MessageQueue>),
+}
+
+/// Objects opened by a process, by handle.
+#[derive(Debug, Default)]
+pub struct HandleTable {
+    objects: BTreeMap<u32, IpcObject>,
+}
+
+impl HandleTable {
+    pub const fn new() -> Self {
+        Self {
+            objects: BTreeMap::new(),
+        }
+    }
+
+    /// Adds `object` to the table, and returns its handle (the lowest handle not in use).
+    ///
+    /// Returns `None` if the table already holds [`MAX_HANDLES`] handles.
+    pub fn insert(&mut self, object: IpcObject) -> Option<u32> {
+        if self.objects.len() >= MAX_HANDLES {
+            return None;
+        }
+
+        let handle = self
+            .objects
+            .keys()
+            .zip(0..)
+            .find(|(used, free)| **used != *free)
+            .map_or(self.objects.len() as u32, |(_, free)| free);
+
+        self.objects.insert(handle, object);
+        Some(handle)
+    }
+
+    /// Returns the object referred to by `handle`.
+    pub fn get(&self, handle: u32) -> Option<&IpcObject> {
+        self.objects.get(&handle)
+    }
+
+    /// Removes `handle` from the table, and returns the object it referred to.
+    pub fn remove(&mut self, handle: u32) -> Option<IpcObject> {
+        self.objects.remove(&handle)
+    }
+
+    /// Number of handles in use.
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
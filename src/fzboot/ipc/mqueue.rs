@@ -0,0 +1,231 @@
+//! Message queues.
+//!
+//! A [`MessageQueue`] holds up to a fixed number of messages, each of them at most a fixed number of bytes long. The
+//! boundaries of messages are preserved: every receive returns exactly one message, in the order they were sent.
+//! Senders block while the queue is full, and receivers while it is empty.
+//!
+//! Queues are either anonymous ([`MessageQueue::new`]), or registered under a name ([`open`]), so that a process
+//! can reach a kernel service knowing only the name of its queue.
+
+use core::time::Duration;
+
+use alloc::{
+    collections::{btree_map::BTreeMap, vec_deque::VecDeque},
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use spin::Mutex;
+
+use crate::{errors::IpcError, sync::waitqueue::WaitQueue};
+
+/// Maximum size of a message, in bytes.
+pub const MQ_MAX_MESSAGE_SIZE: usize = 8192;
+
+/// Maximum number of messages a queue can hold.
+pub const MQ_MAX_MESSAGES: usize = 256;
+
+static NAMED_QUEUES: Mutex<BTreeMap<String, Arc<MessageQueue>>> = Mutex::new(BTreeMap::new());
+
+/// A queue of fixed-size messages.
+#[derive(Debug)]
+pub struct MessageQueue {
+    message_size: usize,
+    capacity: usize,
+    messages: Mutex<VecDeque<Vec<u8>>>,
+
+    /// Woken up when a message is sent.
+    not_empty: WaitQueue,
+
+    /// Woken up when a message is received.
+    not_full: WaitQueue,
+}
+
+impl MessageQueue {
+    /// Creates a queue holding up to `capacity` messages of at most `message_size` bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IpcError::InvalidArgument`] if either value is zero, or above [`MQ_MAX_MESSAGE_SIZE`] (resp.
+    /// [`MQ_MAX_MESSAGES`]).
+    pub fn new(message_size: usize, capacity: usize) -> Result<Arc<Self>, IpcError> {
+        if !(1..=MQ_MAX_MESSAGE_SIZE).contains(&message_size)
+            || !(1..=MQ_MAX_MESSAGES).contains(&capacity)
+        {
+            return Err(IpcError::InvalidArgument);
+        }
+
+        Ok(Arc::new(Self {
+            message_size,
+            capacity,
+            messages: Mutex::new(VecDeque::with_capacity(capacity)),
+            not_empty: WaitQueue::new(),
+            not_full: WaitQueue::new(),
+        }))
+    }
+
+    /// Maximum size of a message, in bytes.
+    pub fn message_size(&self) -> usize {
+        self.message_size
+    }
+
+    /// Maximum number of messages held by the queue.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of messages currently waiting in the queue.
+    pub fn len(&self) -> usize {
+        self.messages.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Sends `message`, blocking while the queue is full.
+    ///
+    /// Without a `timeout`, blocks until there is room for the message.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IpcError::MessageTooLarge`] if the message is longer than the message size of the queue, or
+    /// [`IpcError::TimedOut`] if the queue remained full until the timeout expired.
+    pub fn send(&self, message: &[u8], timeout: Option<Duration>) -> Result<(), IpcError> {
+        loop {
+            match self.try_send(message) {
+                Err(IpcError::WouldBlock) => {
+                    let cond = || {
+                        self.messages
+                            .try_lock()
+                            .map_or(true, |m| m.len() < self.capacity)
+                    };
+                    self.wait(&self.not_full, cond, timeout)?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Sends `message` without blocking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IpcError::MessageTooLarge`] if the message is longer than the message size of the queue, or
+    /// [`IpcError::WouldBlock`] if the queue is full.
+    pub fn try_send(&self, message: &[u8]) -> Result<(), IpcError> {
+        if message.len() > self.message_size {
+            return Err(IpcError::MessageTooLarge);
+        }
+
+        let mut messages = self.messages.lock();
+        if messages.len() >= self.capacity {
+            return Err(IpcError::WouldBlock);
+        }
+
+        messages.push_back(message.to_vec());
+        drop(messages);
+
+        self.not_empty.wake_one();
+        Ok(())
+    }
+
+    /// Receives the oldest message of the queue into `buf`, blocking while the queue is empty.
+    ///
+    /// Without a `timeout`, blocks until a message is available. Returns the size of the message.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IpcError::MessageTooLarge`] if `buf` is smaller than the message size of the queue, or
+    /// [`IpcError::TimedOut`] if the queue remained empty until the timeout expired.
+    pub fn receive(&self, buf: &mut [u8], timeout: Option<Duration>) -> Result<usize, IpcError> {
+        loop {
+            match self.try_receive(buf) {
+                Err(IpcError::WouldBlock) => {
+                    let cond = || self.messages.try_lock().map_or(true, |m| !m.is_empty());
+                    self.wait(&self.not_empty, cond, timeout)?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Receives the oldest message of the queue into `buf`, without blocking.
+    ///
+    /// Returns the size of the message.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IpcError::MessageTooLarge`] if `buf` is smaller than the message size of the queue, or
+    /// [`IpcError::WouldBlock`] if the queue is empty.
+    pub fn try_receive(&self, buf: &mut [u8]) -> Result<usize, IpcError> {
+        if buf.len() < self.message_size {
+            return Err(IpcError::MessageTooLarge);
+        }
+
+        let message = self
+            .messages
+            .lock()
+            .pop_front()
+            .ok_or(IpcError::WouldBlock)?;
+        buf[..message.len()].copy_from_slice(&message);
+
+        self.not_full.wake_one();
+        Ok(message.len())
+    }
+
+    fn wait(
+        &self,
+        queue: &WaitQueue,
+        cond: impl FnMut() -> bool,
+        timeout: Option<Duration>,
+    ) -> Result<(), IpcError> {
+        if let Some(timeout) = timeout {
+            if !queue.wait_until_timeout(cond, timeout) {
+                return Err(IpcError::TimedOut);
+            }
+        } else {
+            queue.wait_until(cond);
+        }
+
+        Ok(())
+    }
+}
+
+/// Opens the message queue registered under `name`.
+///
+/// If no such queue exists and `create` is set, a new queue is created with the given message size and capacity, and
+/// registered under `name`.
+///
+/// # Errors
+///
+/// Returns [`IpcError::NotFound`] if there is no queue named `name` and `create` is not set, or the errors of
+/// [`MessageQueue::new`].
+pub fn open(name: &str, create: Option<(usize, usize)>) -> Result<Arc<MessageQueue>, IpcError> {
+    let mut queues = NAMED_QUEUES.lock();
+
+    if let Some(queue) = queues.get(name) {
+        return Ok(queue.clone());
+    }
+
+    let (message_size, capacity) = create.ok_or(IpcError::NotFound)?;
+    let queue = MessageQueue::new(message_size, capacity)?;
+    queues.insert(String::from(name), queue.clone());
+
+    Ok(queue)
+}
+
+/// Removes the name `name` of a message queue.
+///
+/// The queue itself remains usable by those who opened it already, and is destroyed once they all released it.
+///
+/// # Errors
+///
+/// Returns [`IpcError::NotFound`] if there is no queue named `name`.
+pub fn unlink(name: &str) -> Result<(), IpcError> {
+    NAMED_QUEUES
+        .lock()
+        .remove(name)
+        .map(|_| ())
+        .ok_or(IpcError::NotFound)
+}
@@ -0,0 +1,211 @@
+//! Pipes.
+//!
+//! A pipe is a one-way byte stream: bytes written to a [`PipeWriter`] are read, in order, from a [`PipeReader`]. The
+//! bytes in transit are held in a ring buffer of [`PIPE_CAPACITY`] bytes. Readers block while the pipe is empty, and
+//! writers while it is full.
+//!
+//! Both ends can be cloned. Once every writer is dropped, readers see the end of the stream (reads return 0 once the
+//! pipe is drained). Once every reader is dropped, writes fail with [`IpcError::BrokenPipe`].
+
+use alloc::{collections::vec_deque::VecDeque, sync::Arc};
+use spin::Mutex;
+
+use crate::{errors::IpcError, sync::waitqueue::WaitQueue};
+
+/// Number of bytes a pipe can hold before writers block.
+pub const PIPE_CAPACITY: usize = 4096;
+
+#[derive(Debug)]
+struct PipeState {
+    buffer: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+#[derive(Debug)]
+struct Pipe {
+    state: Mutex<PipeState>,
+
+    /// Woken up when bytes are written, or when the last writer is dropped.
+    readable: WaitQueue,
+
+    /// Woken up when bytes are read, or when the last reader is dropped.
+    writable: WaitQueue,
+}
+
+impl Pipe {
+    /// Blocks until `cond` returns `true` for the state of the pipe.
+    ///
+    /// The condition is polled with interrupts disabled: if the state is locked by another task, it is considered
+    /// satisfied, and checked again by the caller.
+    fn wait(&self, queue: &WaitQueue, cond: impl Fn(&PipeState) -> bool) {
+        queue.wait_until(|| self.state.try_lock().map_or(true, |state| cond(&state)));
+    }
+}
+
+/// Creates a new pipe, and returns both of its ends.
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe {
+        state: Mutex::new(PipeState {
+            buffer: VecDeque::with_capacity(PIPE_CAPACITY),
+            readers: 1,
+            writers: 1,
+        }),
+        readable: WaitQueue::new(),
+        writable: WaitQueue::new(),
+    });
+
+    (PipeReader(pipe.clone()), PipeWriter(pipe))
+}
+
+/// The reading end of a pipe.
+#[derive(Debug)]
+pub struct PipeReader(Arc<Pipe>);
+
+impl PipeReader {
+    /// Reads bytes from the pipe into `buf`, blocking until at least one byte is available.
+    ///
+    /// Returns the number of bytes read, or 0 if `buf` is empty or every writer was dropped and the pipe is drained.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, IpcError> {
+        loop {
+            match self.try_read(buf) {
+                Err(IpcError::WouldBlock) => self.0.wait(&self.0.readable, |state| {
+                    !state.buffer.is_empty() || state.writers == 0
+                }),
+                result => return result,
+            }
+        }
+    }
+
+    /// Reads bytes from the pipe into `buf`, without blocking.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IpcError::WouldBlock`] if the pipe is empty while writers are still alive.
+    pub fn try_read(&self, buf: &mut [u8]) -> Result<usize, IpcError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut state = self.0.state.lock();
+
+        if state.buffer.is_empty() {
+            return match state.writers {
+                0 => Ok(0),
+                _ => Err(IpcError::WouldBlock),
+            };
+        }
+
+        let count = usize::min(buf.len(), state.buffer.len());
+        for (dest, byte) in buf.iter_mut().zip(state.buffer.drain(..count)) {
+            *dest = byte;
+        }
+        drop(state);
+
+        self.0.writable.wake_all();
+        Ok(count)
+    }
+
+    /// Number of bytes that can be read without blocking.
+    pub fn available(&self) -> usize {
+        self.0.state.lock().buffer.len()
+    }
+}
+
+impl Clone for PipeReader {
+    fn clone(&self) -> Self {
+        self.0.state.lock().readers += 1;
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock();
+        state.readers -= 1;
+
+        if state.readers == 0 {
+            drop(state);
+            self.0.writable.wake_all();
+        }
+    }
+}
+
+/// The writing end of a pipe.
+#[derive(Debug)]
+pub struct PipeWriter(Arc<Pipe>);
+
+impl PipeWriter {
+    /// Writes the whole of `buf` to the pipe, blocking while the pipe is full.
+    ///
+    /// Returns the number of bytes written, which is only lower than the size of `buf` if every reader was dropped
+    /// in the meantime.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IpcError::BrokenPipe`] if no reader is left before anything could be written.
+    pub fn write(&self, buf: &[u8]) -> Result<usize, IpcError> {
+        let mut written = 0;
+
+        while written < buf.len() {
+            match self.try_write(&buf[written..]) {
+                Ok(count) => written += count,
+                Err(IpcError::WouldBlock) => self.0.wait(&self.0.writable, |state| {
+                    state.buffer.len() < PIPE_CAPACITY || state.readers == 0
+                }),
+                Err(IpcError::BrokenPipe) if written != 0 => break,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Writes as much of `buf` as fits in the pipe, without blocking.
+    ///
+    /// Returns the number of bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`IpcError::BrokenPipe`] if every reader was dropped, or [`IpcError::WouldBlock`] if the pipe is full.
+    pub fn try_write(&self, buf: &[u8]) -> Result<usize, IpcError> {
+        let mut state = self.0.state.lock();
+
+        if state.readers == 0 {
+            return Err(IpcError::BrokenPipe);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let count = usize::min(buf.len(), PIPE_CAPACITY - state.buffer.len());
+        if count == 0 {
+            return Err(IpcError::WouldBlock);
+        }
+
+        state.buffer.extend(&buf[..count]);
+        drop(state);
+
+        self.0.readable.wake_all();
+        Ok(count)
+    }
+}
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> Self {
+        self.0.state.lock().writers += 1;
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock();
+        state.writers -= 1;
+
+        if state.writers == 0 {
+            drop(state);
+            self.0.readable.wake_all();
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Work deferred by interrupt handlers.
+//!
+//! Interrupt handlers run with interrupts disabled: they cannot wait for another interrupt (such as the completion of
+//! a command sent to a device). Such work is queued with [`defer`] instead, and is run later on by
+//! [`run_deferred_work`], outside of any interrupt handler.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::irq::deferred::{defer, run_deferred_work};
+//!
+//! fn rescan_ports() {
+//!     // Sends commands to the device, and waits for them to complete.
+//! }
+//!
+//! // From the interrupt handler:
+//! defer(rescan_ports);
+//!
+//! // Later on, outside of the interrupt handler:
+//! run_deferred_work();
+//! ```
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+
+use crate::error;
+use crate::x86::int::{disable_interrupts, enable_interrupts, interrupts_disabled};
+
+/// Maximum number of distinct work functions waiting to be run.
+const MAX_DEFERRED_WORK: usize = 16;
+
+/// Work functions waiting to be run.
+///
+/// A fixed-size array is used, so that no allocation is required from an interrupt handler.
+static DEFERRED_WORK: Mutex<[Option<DeferredWork>; MAX_DEFERRED_WORK]> =
+    Mutex::new([None; MAX_DEFERRED_WORK]);
+
+/// Set while [`run_deferred_work`] is running, as work functions may themselves wait for interrupts.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Function run by [`run_deferred_work`].
+pub type DeferredWork = fn();
+
+/// Queues `work`, to be run by the next call to [`run_deferred_work`].
+///
+/// Queuing a function that is already waiting to be run does nothing: a work function must process every event that
+/// occurred since its last run. May be called from an interrupt handler.
+pub fn defer(work: DeferredWork) {
+    without_interrupts(|| {
+        let mut queue = DEFERRED_WORK.lock();
+
+        if queue
+            .iter()
+            .flatten()
+            .any(|&queued| queued as usize == work as usize)
+        {
+            return;
+        }
+
+        match queue.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => *slot = Some(work),
+            None => error!("irq", "deferred work queue full, work dropped"),
+        }
+    });
+}
+
+/// Runs the work queued by [`defer`], in the order in which it was queued.
+///
+/// Must not be called from an interrupt handler. Does nothing if called while deferred work is already running.
+pub fn run_deferred_work() {
+    if RUNNING.swap(true, Ordering::Acquire) {
+        return;
+    }
+
+    loop {
+        let work = without_interrupts(|| {
+            let mut queue = DEFERRED_WORK.lock();
+            let work = queue[0].take();
+            queue.rotate_left(1);
+
+            work
+        });
+
+        match work {
+            Some(work) => work(),
+            None => break,
+        }
+    }
+
+    RUNNING.store(false, Ordering::Release);
+}
+
+fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    let were_disabled = interrupts_disabled();
+    disable_interrupts();
+
+    let result = f();
+    if !were_disabled {
+        enable_interrupts();
+    }
+
+    result
+}
@@ -32,10 +32,11 @@ use conquer_once::spin::OnceCell;
 use spin::{Mutex, RwLock};
 
 use crate::{
-    errors::CanFail,
+    errors::{BaseError, CanFail},
+    io::pic::PIC_MASKED_VECTORS,
     mem::{MemoryAddress, PhyAddr, PhyAddr32, VirtAddr},
     x86::{
-        apic::local_apic::InterruptVector,
+        apic::local_apic::{InterruptVector, VectorPriorityClass},
         descriptors::{
             gdt::KERNEL_CODE_SELECTOR,
             idt::{GateDescriptor, GateType, InterruptDescriptorTable},
@@ -147,13 +148,42 @@ pub fn get_long_interrupt_manager() -> &'static InterruptManager<VirtAddr> {
 pub struct InterruptManager<A: MemoryAddress> {
     idt: Mutex<InterruptDescriptorTable<A>>,
     pub(super) handler_registry: RwLock<BTreeMap<InterruptVector, InterruptHandler>>,
+    vector_owners: Mutex<[Option<&'static str>; 256]>,
 }
 
+/// Vectors of the processor exceptions, that cannot be allocated.
+const EXCEPTION_VECTORS: core::ops::Range<u8> = 0x00..0x20;
+
+/// Vectors of the legacy `IRQs`, that cannot be allocated: used by the `PIC`, and by the default mapping of the
+/// `I/O APIC` pins (see [`crate::x86::apic::io_apic`]).
+const LEGACY_IRQ_VECTORS: core::ops::Range<u8> = 0x20..0x38;
+
+/// Vector of the system call gate of the kernel, that cannot be allocated.
+pub(crate) const SYSCALL_VECTOR: u8 = 0x80;
+
+/// Maximum number of vectors allocated as a single block (_Multiple Message_ `MSI`): a block cannot span several
+/// priority classes.
+const MAX_VECTOR_BLOCK: usize = 16;
+
 impl<A: MemoryAddress> InterruptManager<A> {
     fn new(base_addr: A) -> Self {
+        let mut vector_owners = [None; 256];
+        for vector in EXCEPTION_VECTORS {
+            vector_owners[usize::from(vector)] = Some("exception");
+        }
+        for vector in LEGACY_IRQ_VECTORS {
+            vector_owners[usize::from(vector)] = Some("legacy-irq");
+        }
+        for vector in PIC_MASKED_VECTORS {
+            vector_owners[usize::from(vector)] = Some("pic-masked");
+        }
+        vector_owners[usize::from(SYSCALL_VECTOR)] = Some("syscall");
+        vector_owners[0xFF] = Some("spurious");
+
         let imgr = Self {
             idt: Mutex::new(InterruptDescriptorTable::new(base_addr)),
             handler_registry: RwLock::new(BTreeMap::new()),
+            vector_owners: Mutex::new(vector_owners),
         };
 
         let default_handler_ptr: fn() = _default_int_handler;
@@ -410,6 +440,158 @@ impl<A: MemoryAddress> InterruptManager<A> {
 
         Ok(())
     }
+
+    /// Handles the given [`InterruptVector`] on the stack `ist` of the _Interrupt Stack Table_ of the current
+    /// processor (see [`crate::x86::descriptors::tss`]), instead of the interrupted stack.
+    ///
+    /// This must be called after registering the handler of the vector, as registering a handler resets the stack
+    /// selection.
+    pub fn set_interrupt_stack(
+        &self,
+        int_vector: InterruptVector,
+        ist: u8,
+    ) -> CanFail<HandlerRegistrationError> {
+        let irq_disabled = interrupts_disabled();
+        disable_interrupts();
+
+        let mut idt = self.idt.lock();
+        let result = idt
+            .set_interrupt_stack(int_vector, ist)
+            .and_then(|()| unsafe { idt.write_table() })
+            .map_err(|_| HandlerRegistrationError::IDTWriteError);
+        drop(idt);
+
+        if !irq_disabled {
+            enable_interrupts();
+        }
+
+        result
+    }
+
+    /// Allows the given [`InterruptVector`] to be raised with the `INT` instruction from the privilege level `dpl`
+    /// (such as the system call gate, raised by user processes).
+    ///
+    /// This must be called after registering the handler of the vector, as registering a handler resets the
+    /// privilege level to ring 0.
+    pub fn set_privilege_level(
+        &self,
+        int_vector: InterruptVector,
+        dpl: PrivilegeLevel,
+    ) -> CanFail<HandlerRegistrationError> {
+        let irq_disabled = interrupts_disabled();
+        disable_interrupts();
+
+        let mut idt = self.idt.lock();
+        let result = idt
+            .set_privilege_level(int_vector, dpl)
+            .and_then(|()| unsafe { idt.write_table() })
+            .map_err(|_| HandlerRegistrationError::IDTWriteError);
+        drop(idt);
+
+        if !irq_disabled {
+            enable_interrupts();
+        }
+
+        result
+    }
+
+    /// Allocates a free interrupt vector in the priority class `priority_class`, on behalf of `owner`.
+    ///
+    /// Drivers should use this instead of a fixed vector: two drivers cannot be handed out the same vector. The
+    /// vector remains allocated until released with [`InterruptManager::free_vector`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`VectorAllocationError::PriorityClassExhausted`] if every vector of the class is already in use.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let int_mgr = get_interrupt_manager();
+    /// let vector = int_mgr.allocate_vector(VectorPriorityClass::DEVICE, "ahci")?;
+    ///
+    /// int_mgr.register_static_handler(vector, irq_entry);
+    /// ```
+    pub(crate) fn allocate_vector(
+        &self,
+        priority_class: VectorPriorityClass,
+        owner: &'static str,
+    ) -> Result<InterruptVector, VectorAllocationError> {
+        self.allocate_vectors(priority_class, 1, owner)
+    }
+
+    /// Allocates `count` contiguous interrupt vectors in the priority class `priority_class`, on behalf of `owner`, and
+    /// returns the first one.
+    ///
+    /// The block is aligned on `count` vectors, as required by _Multiple Message_ `MSI` (the device sets the low bits
+    /// of the vector to the message number).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`VectorAllocationError::InvalidCount`] if `count` is not a power of two, or is larger than a single
+    /// priority class. Returns [`VectorAllocationError::PriorityClassExhausted`] if no free block is available.
+    pub(crate) fn allocate_vectors(
+        &self,
+        priority_class: VectorPriorityClass,
+        count: usize,
+        owner: &'static str,
+    ) -> Result<InterruptVector, VectorAllocationError> {
+        if !count.is_power_of_two() || count > MAX_VECTOR_BLOCK {
+            return Err(VectorAllocationError::InvalidCount);
+        }
+
+        let mut owners = self.vector_owners.lock();
+        let first = priority_class
+            .vectors()
+            .step_by(count)
+            .find(|&first| {
+                let first = usize::from(first);
+                owners[first..first + count].iter().all(Option::is_none)
+            })
+            .ok_or(VectorAllocationError::PriorityClassExhausted)?;
+
+        let first_idx = usize::from(first);
+        for slot in &mut owners[first_idx..first_idx + count] {
+            *slot = Some(owner);
+        }
+
+        Ok(first)
+    }
+
+    /// Reserves the fixed interrupt vector `vector` on behalf of `owner`, so that it cannot be allocated.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`VectorAllocationError::AlreadyReserved`] if the vector is already in use.
+    pub(crate) fn reserve_vector(
+        &self,
+        vector: InterruptVector,
+        owner: &'static str,
+    ) -> CanFail<VectorAllocationError> {
+        let mut owners = self.vector_owners.lock();
+        let slot = &mut owners[usize::from(vector)];
+
+        if slot.is_some() {
+            return Err(VectorAllocationError::AlreadyReserved);
+        }
+
+        *slot = Some(owner);
+
+        Ok(())
+    }
+
+    /// Releases an interrupt vector returned by [`InterruptManager::allocate_vector`] or reserved with
+    /// [`InterruptManager::reserve_vector`].
+    ///
+    /// The handlers registered for this vector are left untouched.
+    pub(crate) fn free_vector(&self, vector: InterruptVector) {
+        self.vector_owners.lock()[usize::from(vector)] = None;
+    }
+
+    /// Returns the owner of the interrupt vector `vector`, if it is in use.
+    pub fn vector_owner(&self, vector: InterruptVector) -> Option<&'static str> {
+        self.vector_owners.lock()[usize::from(vector)]
+    }
 }
 
 /// Errors that may happen while registering a new handler to the `InterruptManager`.
@@ -419,3 +601,18 @@ pub enum HandlerRegistrationError {
 
     NoRuntimeHandlerMapping,
 }
+
+/// Errors that may happen while allocating interrupt vectors from the `InterruptManager`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VectorAllocationError {
+    /// Every vector of the requested priority class is already in use.
+    PriorityClassExhausted,
+
+    /// The vector is already in use.
+    AlreadyReserved,
+
+    /// Invalid number of vectors requested.
+    InvalidCount,
+}
+
+impl BaseError for VectorAllocationError {}
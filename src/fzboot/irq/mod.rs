@@ -1,12 +1,13 @@
 use core::arch::asm;
 
-use crate::io::outb;
-use crate::io::IOPort;
+use crate::io::pic::PIC;
 use crate::mem::VirtAddr;
 use crate::video::vesa::text_buffer;
 use crate::x86::apic::local_apic::local_apic;
 use crate::x86::registers::x86_64::GeneralPurposeRegisters;
 
+pub mod deferred;
+
 #[cfg(feature = "alloc")]
 pub mod manager;
 
@@ -145,6 +146,21 @@ pub(crate) struct ExceptionStackFrame {
     pub(crate) registers: GeneralPurposeRegisters,
 }
 
+impl From<InterruptStackFrame> for ExceptionStackFrame {
+    /// Converts the frame of an exception that does not push an error code (handled as a regular interrupt).
+    fn from(value: InterruptStackFrame) -> Self {
+        Self {
+            error_code: 0,
+            rip: value.rip,
+            cs: value.cs,
+            rflags: value.rflags,
+            stack_ptr: value.stack_ptr,
+            stack_segment: value.stack_segment,
+            registers: value.registers,
+        }
+    }
+}
+
 // todo: restore locks afterwards
 unsafe fn release_locks() {
     text_buffer().buffer.force_unlock();
@@ -157,8 +173,7 @@ pub unsafe fn _int_entry() {
 
 #[no_mangle]
 pub fn _pic_eoi() {
-    outb(IOPort::from(0x20), 0x20);
-    outb(IOPort::from(0xA0), 0x20);
+    PIC::default().end_of_interrupt();
 
     if let Some(lapic) = local_apic() {
         lapic.send_eoi();
@@ -10,24 +10,45 @@ extern crate alloc;
 
 use core::{arch::asm, panic::PanicInfo};
 
-use alloc::format;
 use fzboot::{
-    boot::multiboot::mb_information,
-    exceptions::{panic::panic_entry_no_exception, register_exception_handlers},
+    boot::{
+        boottime::{boottime_export_serial, boottime_mark},
+        info::BootInfo,
+        metadata::mark_boot_successful,
+    },
+    config::{self, config_parse_cmdline},
+    drivers::vtd::iommu_init,
+    exceptions::{
+        apic_error::apic_error_init, mce::mce_init, panic::panic_entry_no_exception,
+        register_exception_handlers, watchdog::nmi_watchdog_init,
+    },
+    failpoint::failpoints_parse,
+    info,
+    io::{acpi::acpi_init_from_rsdp, ps2::keyboard::keyboard_init},
     irq::manager::get_interrupt_manager,
-    kernel_syms::KERNEL_PAGE_TABLE,
+    kernel_syms::{KERNEL_CODE_MAPPING_BASE, KERNEL_PAGE_TABLE},
+    klog::{klog_import, BOOT_LOG_MODULE},
+    log::log_config_apply,
     mem::{
         e820::E820MemoryMap,
         kernel_sec::enable_kernel_mem_sec,
+        ops::mem_ops_enable_sse,
         stack::get_kernel_stack_allocator,
         vmalloc::{init_kernel_heap, SyncKernelHeapAllocator},
         MemoryAddress, PhyAddr, VirtAddr,
     },
     process::init_kernel_process,
-    scheduler::init_global_scheduler,
+    scheduler::{idle_task, init_global_scheduler},
+    smp::smp_init,
+    syscall::syscall_init,
     video::{self},
     x86::{
-        descriptors::gdt::{kernel_init_gdt, LONG_GDT_ADDR},
+        descriptors::{
+            gdt::{kernel_init_gdt, LONG_GDT_ADDR},
+            tss::init_cpu_tss,
+        },
+        errata::apply_errata,
+        idle::idle_init,
         int::enable_interrupts,
         paging::{
             get_memory_mapper, init_global_mapper,
@@ -43,20 +64,41 @@ pub static KERNEL_HEAP_ALLOCATOR: SyncKernelHeapAllocator = SyncKernelHeapAlloca
 #[no_mangle]
 #[link_section = ".start"]
 pub extern "C" fn _start() -> ! {
-    let mut mb_information_ptr: u64 = 0;
+    let mut boot_info_ptr: u64 = 0;
     unsafe {
-        asm!("", out("rcx") mb_information_ptr);
+        asm!("", out("rcx") boot_info_ptr);
     }
+    boottime_mark("kernel-entry");
 
-    let mb_information: mb_information::MultibootInformation = unsafe {
-        core::ptr::read(mb_information_ptr as *const mb_information::MultibootInformation)
-    };
+    let boot_info = unsafe { BootInfo::from_ptr(boot_info_ptr as *const u8) }
+        .expect("invalid boot information");
+
+    // Before `mem_init`: the memory of the bootloader may be reused once the physical memory pool is set up.
+    if let Some(boot_log) = boot_info.module(BOOT_LOG_MODULE) {
+        klog_import(boot_log);
+    }
 
     unsafe {
-        mem_init(&mb_information);
+        mem_init(&boot_info);
+    }
+
+    if let Some(cmdline) = boot_info.cmdline() {
+        failpoints_parse(cmdline);
+        config_parse_cmdline(cmdline);
+    }
+
+    video::vesa::init_text_buffer_from_multiboot(
+        boot_info
+            .framebuffer()
+            .expect("no framebuffer in the boot information"),
+    );
+    log_config_apply();
+
+    match boot_info.rsdp() {
+        Some(rsdp) => acpi_init_from_rsdp(rsdp),
+        None => info!("acpi", "no RSDP handed over by the bootloader"),
     }
 
-    video::vesa::init_text_buffer_from_multiboot(mb_information.framebuffer().unwrap());
     let kernel_stack = get_kernel_stack_allocator().lock().alloc_stack();
 
     unsafe {
@@ -80,36 +122,79 @@ extern "C" fn _kmain() -> ! {
     }
 
     enable_kernel_mem_sec();
+    mem_ops_enable_sse();
 
     unsafe {
         get_interrupt_manager().load_idt();
     }
     register_exception_handlers();
+    apply_errata();
+    if let Err(err) = apic_error_init() {
+        info!("kernel", "local APIC errors are not reported: {}", err);
+    }
+    if let Err(err) = smp_init() {
+        info!("kernel", "cross-processor calls unavailable: {:?}", err);
+    }
+    if let Err(err) = syscall_init() {
+        info!("kernel", "system calls unavailable: {:?}", err);
+    }
+    if let Err(err) = mce_init() {
+        info!("kernel", "machine check reporting unavailable: {:?}", err);
+    }
     init_global_scheduler();
+    if let Err(err) = nmi_watchdog_init() {
+        info!("kernel", "NMI watchdog unavailable: {:?}", err);
+    }
+    if let Err(err) = iommu_init() {
+        info!("kernel", "DMA remapping unavailable: {:?}", err);
+    }
     init_kernel_process();
+    idle_init();
+    keyboard_init();
+
+    boottime_mark("kernel-ready");
+    // the bootloader stops counting the attempts to boot this kernel on the next boot
+    mark_boot_successful();
+    if config::get_bool("boottime.serial") && boottime_export_serial().is_err() {
+        info!("kernel", "no serial port to write the boot timeline to");
+    }
 
     enable_interrupts();
 
-    loop {}
+    idle_task();
 }
 
-unsafe fn mem_init(mb_information: &mb_information::MultibootInformation) {
+unsafe fn mem_init(boot_info: &BootInfo) {
     let memory_map = E820MemoryMap::new(
         PhysicalMemoryMapping::KERNEL_DEFAULT_MAPPING
-            .convert(PhyAddr::from(mb_information.get_mmap_addr()))
+            .convert(boot_info.memory_map_addr())
             .as_mut_ptr(),
     );
 
-    kernel_init_gdt(
+    let (mut gdt, _) = kernel_init_gdt(
         PhysicalMemoryMapping::KERNEL_DEFAULT_MAPPING.convert(PhyAddr::new(LONG_GDT_ADDR)),
     );
 
-    init_phys_memory_pool(memory_map);
+    init_phys_memory_pool(memory_map, kernel_image_size());
     init_global_mapper(KERNEL_PAGE_TABLE);
     init_kernel_heap();
+
+    if init_cpu_tss(&mut gdt).is_err() {
+        info!("kernel", "failed to load the TSS of the boot processor");
+    }
+}
+
+/// Returns the size of the Kernel image in memory, up to the `_kernel_end` symbol of the linker script.
+fn kernel_image_size() -> u64 {
+    extern "C" {
+        static _kernel_end: u8;
+    }
+
+    let kernel_end = unsafe { core::ptr::addr_of!(_kernel_end) } as u64;
+    kernel_end - u64::from(KERNEL_CODE_MAPPING_BASE)
 }
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    panic_entry_no_exception(&format!("{}", info.message()));
+    panic_entry_no_exception(info);
 }
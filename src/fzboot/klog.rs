@@ -0,0 +1,110 @@
+//! Kernel log ring buffer.
+//!
+//! Keeps the last [`KLOG_SIZE`] bytes written to the console output, so that they can be inspected or saved after a
+//! failure (see [`crate::fs::pstore`]), even if they were not displayed.
+//!
+//! The bootloader hands its log over to the kernel as a boot module named [`BOOT_LOG_MODULE`] (see
+//! [`crate::boot::info`]), which the kernel imports at the beginning of its own log (see [`klog_import`]), so that the
+//! messages of the earlier stages are not lost when the kernel sets up its own console.
+
+use core::fmt::{self, Write};
+
+use spin::Mutex;
+
+/// Size of the kernel log ring buffer, in bytes.
+pub const KLOG_SIZE: usize = 0x4000;
+
+/// Name of the boot module containing the log of the bootloader.
+pub const BOOT_LOG_MODULE: &str = "fzboot.log";
+
+/// Separates the imported log of an earlier stage from the messages that follow.
+const IMPORT_SEPARATOR: &str = "--- end of the bootloader log ---\n";
+
+static KLOG: Mutex<LogRing> = Mutex::new(LogRing::new());
+
+struct LogRing {
+    data: [u8; KLOG_SIZE],
+
+    /// Position of the next byte to write.
+    head: usize,
+
+    /// Number of valid bytes in the buffer.
+    len: usize,
+}
+
+impl LogRing {
+    const fn new() -> Self {
+        Self {
+            data: [0; KLOG_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.data[self.head] = byte;
+            self.head = (self.head + 1) % KLOG_SIZE;
+        }
+
+        self.len = usize::min(self.len + bytes.len(), KLOG_SIZE);
+    }
+}
+
+impl Write for LogRing {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Appends `text` to the kernel log.
+pub fn klog_write(text: &str) {
+    KLOG.lock().push(text.as_bytes());
+}
+
+/// Appends a formatted text to the kernel log.
+pub fn klog_write_fmt(args: fmt::Arguments) {
+    let _ = KLOG.lock().write_fmt(args);
+}
+
+/// Appends the log of an earlier boot stage, followed by a separator line.
+///
+/// If `log` does not fit in the ring buffer, only its most recent bytes are kept.
+pub fn klog_import(log: &[u8]) {
+    let mut klog = KLOG.lock();
+    klog.push(log);
+    if !log.ends_with(b"\n") {
+        klog.push(b"\n");
+    }
+    klog.push(IMPORT_SEPARATOR.as_bytes());
+}
+
+/// Copies the most recent bytes of the kernel log into `buffer`, oldest first.
+///
+/// Returns the number of bytes copied. The copy may start in the middle of a UTF-8 character.
+pub fn klog_read(buffer: &mut [u8]) -> usize {
+    let klog = KLOG.lock();
+    let count = usize::min(klog.len, buffer.len());
+    let start = (klog.head + KLOG_SIZE - count) % KLOG_SIZE;
+
+    for (i, byte) in buffer[..count].iter_mut().enumerate() {
+        *byte = klog.data[(start + i) % KLOG_SIZE];
+    }
+
+    count
+}
+
+/// Returns the number of bytes currently held in the kernel log.
+pub fn klog_len() -> usize {
+    KLOG.lock().len
+}
+
+/// Releases the lock of the kernel log.
+///
+/// # Safety
+///
+/// Must only be used when the lock holder can no longer run (for instance, in a panic handler).
+pub unsafe fn klog_force_unlock() {
+    KLOG.force_unlock();
+}
@@ -0,0 +1,291 @@
+//! Log facade.
+//!
+//! Every message printed with the [`error!`](crate::error), [`warn!`](crate::warn), [`info!`](crate::info) and
+//! [`debug!`](crate::debug) macros goes through [`log`], which tags it with its [`LogLevel`] and writes it to:
+//!
+//! - the kernel log ring buffer ([`crate::klog`]), without colors.
+//! - each active [`LogSink`]: the framebuffer console, the first serial port (using ANSI escape sequences for the
+//!   colors) once [`log_serial_init`] was called, and the emulator debug console ([`crate::io::debugcon`]) if enabled.
+//!
+//! Messages less severe than [`max_level`] are dropped.
+//!
+//! In real mode, before the framebuffer is available, [`rinfo!`](crate::rinfo) and [`rerror!`](crate::rerror) print a
+//! string using the BIOS teletype service ([`bios_log`]), and to the debug console if enabled: built with the
+//! `debugcon` feature, every stage logs to the debug console from its first message.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::log::{set_max_level, LogLevel};
+//! use fzboot::{debug, warn};
+//!
+//! set_max_level(LogLevel::Debug);
+//! debug!("ahci", "port {} ready", 2);
+//! warn!("ahci", "port {} did not answer", 3);
+//! ```
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::boot::progress::report_error;
+use crate::errors::{CanFail, SerialError};
+use crate::io::debugcon::{debugcon_write, DebugCon};
+use crate::io::serial::COM1;
+use crate::klog::{klog_write, klog_write_fmt};
+use crate::video::io::__bios_print_str;
+use crate::video::vesa::framebuffer::RgbaColor;
+use crate::video::vesa::macros::{CTX_COLOR, ERR_COLOR};
+use crate::video::vesa::{display_fmt, display_str};
+
+/// Speed of the serial port, when it is initialized to carry the log.
+pub const LOG_SERIAL_BAUD: u32 = 115_200;
+
+/// ANSI sequence resetting the colors.
+const ANSI_RESET: &str = "\x1b[0m";
+
+/// ANSI sequence used for the context of the messages.
+const ANSI_CTX: &str = "\x1b[33m";
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+#[cfg(feature = "debugcon")]
+const DEFAULT_SINKS: u8 = LogSink::Framebuffer as u8 | LogSink::DebugCon as u8;
+
+#[cfg(not(feature = "debugcon"))]
+const DEFAULT_SINKS: u8 = LogSink::Framebuffer as u8;
+
+static ACTIVE_SINKS: AtomicU8 = AtomicU8::new(DEFAULT_SINKS);
+
+/// Severity of a log message, from the most to the least severe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum LogLevel {
+    /// A failure: also switches the boot progress to the verbose log (see [`report_error`]).
+    Error,
+
+    /// An unexpected condition, that does not prevent the boot from going on.
+    Warn,
+
+    /// The regular progress of the boot.
+    Info,
+
+    /// Details only useful when investigating an issue. Dropped by default.
+    Debug,
+}
+
+impl LogLevel {
+    /// Label displayed at the beginning of the messages of this level.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+        }
+    }
+
+    /// Returns the level labelled `name` (see [`LogLevel::label`]).
+    pub fn from_label(name: &str) -> Option<Self> {
+        [Self::Error, Self::Warn, Self::Info, Self::Debug]
+            .into_iter()
+            .find(|level| level.label() == name)
+    }
+
+    /// Color of the label on the framebuffer.
+    pub fn color(self) -> RgbaColor {
+        match self {
+            Self::Error => ERR_COLOR,
+            Self::Warn => RgbaColor(255, 159, 28, 0),
+            Self::Info => RgbaColor(144, 190, 109, 0),
+            Self::Debug => RgbaColor(141, 153, 174, 0),
+        }
+    }
+
+    /// ANSI sequence selecting the color of the label on a terminal.
+    fn ansi_color(self) -> &'static str {
+        match self {
+            Self::Error => "\x1b[31m",
+            Self::Warn => "\x1b[1;33m",
+            Self::Info => "\x1b[32m",
+            Self::Debug => "\x1b[90m",
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Error,
+            1 => Self::Warn,
+            2 => Self::Info,
+            _ => Self::Debug,
+        }
+    }
+}
+
+/// Output to which the log is written, in addition to the kernel log ring buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum LogSink {
+    /// The shared [`TextFrameBuffer`](crate::video::vesa::framebuffer::TextFrameBuffer). Enabled by default.
+    Framebuffer = 1 << 0,
+
+    /// The first serial port (see [`log_serial_init`]).
+    Serial = 1 << 1,
+
+    /// The emulator debug console (see [`crate::io::debugcon`]). Enabled by default with the `debugcon` feature.
+    DebugCon = 1 << 2,
+}
+
+/// Sets the least severe level of the messages that are logged. Defaults to [`LogLevel::Info`].
+pub fn set_max_level(level: LogLevel) {
+    MAX_LEVEL.store(level as u8, Ordering::Release);
+}
+
+/// Returns the least severe level of the messages that are logged.
+pub fn max_level() -> LogLevel {
+    LogLevel::from_u8(MAX_LEVEL.load(Ordering::Acquire))
+}
+
+/// Enables or disables writing the log to `sink`.
+pub fn set_sink_enabled(sink: LogSink, enabled: bool) {
+    if enabled {
+        ACTIVE_SINKS.fetch_or(sink as u8, Ordering::AcqRel);
+    } else {
+        ACTIVE_SINKS.fetch_and(!(sink as u8), Ordering::AcqRel);
+    }
+}
+
+/// Checks whether the log is written to `sink`.
+pub fn sink_enabled(sink: LogSink) -> bool {
+    ACTIVE_SINKS.load(Ordering::Acquire) & sink as u8 != 0
+}
+
+/// Initializes the first serial port if needed, and starts writing the log to it.
+///
+/// # Errors
+///
+/// Returns [`SerialError::NotPresent`] if the machine has no serial port.
+pub fn log_serial_init() -> CanFail<SerialError> {
+    let mut serial = COM1.lock();
+    if !serial.initialized() {
+        serial.init(LOG_SERIAL_BAUD)?;
+    }
+    drop(serial);
+
+    set_sink_enabled(LogSink::Serial, true);
+
+    Ok(())
+}
+
+/// Applies the `log.level`, `log.serial` and `log.debugcon` options of the [configuration](crate::config).
+#[cfg(feature = "alloc")]
+pub fn log_config_apply() {
+    use crate::config;
+
+    if let Some(name) = config::get("log.level") {
+        match LogLevel::from_label(&name) {
+            Some(level) => set_max_level(level),
+            None => crate::warn!("log", "unknown log level: {}", name),
+        }
+    }
+
+    if config::get_bool("log.serial") && log_serial_init().is_err() {
+        crate::warn!("log", "no serial port to write the log to");
+    }
+
+    if config::get_bool("log.debugcon") {
+        set_sink_enabled(LogSink::DebugCon, true);
+    }
+}
+
+/// Logs a message of severity `level`, prefixed by its context `ctx` (usually the name of the subsystem).
+///
+/// A new line is appended to the message. This is called by the logging macros ([`info!`](crate::info), ...).
+pub fn log(level: LogLevel, ctx: Option<&str>, args: fmt::Arguments) {
+    if level > max_level() {
+        return;
+    }
+
+    if level == LogLevel::Error {
+        report_error();
+    }
+
+    klog_write_fmt(format_args!("[{}] ", level.label()));
+    if let Some(ctx) = ctx {
+        klog_write(ctx);
+        klog_write(" : ");
+    }
+    klog_write_fmt(format_args!("{args}\n"));
+
+    if sink_enabled(LogSink::Framebuffer) {
+        display_str("[", None);
+        display_str(level.label(), Some(&level.color()));
+        display_str("] ", None);
+        if let Some(ctx) = ctx {
+            display_str(ctx, Some(&CTX_COLOR));
+            display_str(" : ", None);
+        }
+        display_fmt(format_args!("{args}\n"));
+    }
+
+    if sink_enabled(LogSink::Serial) {
+        // The port may be held by the code that was interrupted, if this is called from a fault handler.
+        if let Some(mut serial) = COM1.try_lock() {
+            let _ = write!(
+                serial,
+                "[{}{}{ANSI_RESET}] ",
+                level.ansi_color(),
+                level.label()
+            );
+            if let Some(ctx) = ctx {
+                let _ = write!(serial, "{ANSI_CTX}{ctx}{ANSI_RESET} : ");
+            }
+            let _ = writeln!(serial, "{args}");
+        }
+    }
+
+    if sink_enabled(LogSink::DebugCon) {
+        let _ = write!(DebugCon, "[{}] ", level.label());
+        if let Some(ctx) = ctx {
+            let _ = write!(DebugCon, "{ctx} : ");
+        }
+        let _ = writeln!(DebugCon, "{args}");
+    }
+}
+
+/// Writes `args` to every active sink, without any level or context. Called by [`println!`](crate::println).
+pub fn write(args: fmt::Arguments) {
+    klog_write_fmt(args);
+
+    if sink_enabled(LogSink::Framebuffer) {
+        display_fmt(args);
+    }
+
+    if sink_enabled(LogSink::Serial) {
+        if let Some(mut serial) = COM1.try_lock() {
+            let _ = serial.write_fmt(args);
+        }
+    }
+
+    if sink_enabled(LogSink::DebugCon) {
+        let _ = DebugCon.write_fmt(args);
+    }
+}
+
+/// Prints `msg` on a new line, prefixed by the label of `level`, using the BIOS teletype service (and to the debug
+/// console, if enabled).
+///
+/// Can only be used in real mode, or through a vm86 monitor: called by [`rinfo!`](crate::rinfo) and
+/// [`rerror!`](crate::rerror).
+pub fn bios_log(level: LogLevel, msg: &str) {
+    __bios_print_str("\r\n[");
+    __bios_print_str(level.label());
+    __bios_print_str("] ");
+    __bios_print_str(msg);
+
+    if sink_enabled(LogSink::DebugCon) {
+        debugcon_write("\n[");
+        debugcon_write(level.label());
+        debugcon_write("] ");
+        debugcon_write(msg);
+    }
+}
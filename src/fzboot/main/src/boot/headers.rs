@@ -1,27 +1,69 @@
-use core::ptr;
-
-use alloc::boxed::Box;
+use alloc::{boxed::Box, vec, vec::Vec};
 use fzboot::{
-    boot::multiboot::mb_information::MultibootInformation,
-    mem::PhyAddr32,
-    video::vesa::video_mode::{ModeInfoBlock, VESA_MODE_BUFFER},
+    boot::{
+        info::{BootInfo, BootModule},
+        multiboot::mb_information::FramebufferMultibootInformation,
+    },
+    io::acpi::rsdp_addr,
+    klog::{klog_len, klog_read, BOOT_LOG_MODULE},
+    mem::{
+        e820::{e820_snapshot, E820_MAP_ADDR},
+        PhyAddr,
+    },
+    video::vesa::video_mode::vesa_mode_info,
+};
+
+/// Command line passed to the kernel, set at build time through the `FZ_KERNEL_CMDLINE` environment variable.
+///
+/// Also used to configure the bootloader itself (for instance, its failpoints).
+pub const KERNEL_CMDLINE: &str = match option_env!("FZ_KERNEL_CMDLINE") {
+    Some(cmdline) => cmdline,
+    None => "",
 };
 
-static BOOTLOADER_NAME: [u8; 11] = [
-    b'F', b'r', b'o', b'z', b'e', b'n', b'B', b'o', b'o', b't', b'\0',
-];
+/// Copies the bootloader log into a [`BootModule`] named [`BOOT_LOG_MODULE`], imported by the kernel.
+///
+/// The messages logged after this is called are not handed over.
+fn boot_log_module() -> BootModule {
+    let mut log = vec![0u8; klog_len()];
+    let length = klog_read(&mut log);
+    let log = &log.leak()[..length];
 
-pub fn dump_multiboot_information_header() -> *mut u8 {
-    let mut header = MultibootInformation::default();
+    BootModule::new(
+        PhyAddr::new(log.as_ptr() as u64),
+        log.len() as u64,
+        BOOT_LOG_MODULE,
+    )
+}
 
-    let vesamode_info_ptr = VESA_MODE_BUFFER as *mut ModeInfoBlock;
-    let vesamode_info = unsafe { ptr::read(vesamode_info_ptr) };
+/// Builds the [`BootInfo`] structure handed over to the kernel, and returns its address.
+pub fn dump_boot_info() -> *mut u8 {
+    let mut boot_info = BootInfo::new();
 
-    header.insert_framebuffer_info(vesamode_info);
-    header.set_bootloader_name(PhyAddr32::new(
-        u32::try_from(ptr::addr_of!(BOOTLOADER_NAME) as *const u8 as usize)
-            .expect("invalid bootloader name string address"),
+    boot_info.set_memory_map(
+        PhyAddr::from(u64::from(E820_MAP_ADDR)),
+        e820_snapshot().len() as u32,
+    );
+    boot_info.set_framebuffer(FramebufferMultibootInformation::from_mode_info(
+        vesa_mode_info(),
     ));
 
-    Box::into_raw(Box::new(header)) as *mut u8
+    if let Some(rsdp) = rsdp_addr() {
+        boot_info.set_rsdp(rsdp);
+    }
+
+    if !KERNEL_CMDLINE.is_empty() {
+        boot_info.set_cmdline(
+            PhyAddr::new(KERNEL_CMDLINE.as_ptr() as u64),
+            KERNEL_CMDLINE.len() as u32,
+        );
+    }
+
+    let modules: Vec<BootModule> = vec![boot_log_module()];
+    let modules = modules.leak();
+    boot_info.set_modules(PhyAddr::new(modules.as_ptr() as u64), modules.len() as u32);
+
+    boot_info.seal();
+
+    Box::into_raw(Box::new(boot_info)) as *mut u8
 }
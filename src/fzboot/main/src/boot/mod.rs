@@ -2,107 +2,302 @@ pub mod headers;
 
 /// Kernel loading related code.
 pub mod fzkernel {
-    use core::cmp::min;
-
-    use alloc::format;
-    use fzboot::kernel_syms::{KERNEL_LOAD_ADDR, KERNEL_SECTOR_SZ};
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use fzboot::boot::elf::ElfExecutable;
+    use fzboot::boot::metadata::{take_boot_success, BootMetadataSector};
+    use fzboot::fs::FsFile;
+    use fzboot::kernel_syms::KERNEL_LOAD_ADDR;
+    use fzboot::mem::e820::{memory_regions, MemoryRegionKind};
     use fzboot::x86::paging::bootinit_paging;
     use fzboot::{
+        compress::{decompress, decompressed_size, detect_format},
+        config::config_parse_file,
         drivers::{
+            devtree::partition_name,
             generics::dev_disk::{get_sata_drive, sata_drives, DiskDevice},
             ide::AtaDeviceIdentifier,
         },
+        fs::partitions::registry::{find_partition, PartitionSelector},
+        fs::partitions::Partition,
         info,
         mem::{MemoryAddress, PhyAddr},
-        println,
+        time, warn,
     };
 
+    /// Selector of the partition containing the kernel code.
+    ///
+    /// Any selector accepted by [`PartitionSelector::parse`] may be used (for instance `LABEL=rootfs`).
+    pub const KERNEL_PARTITION: &str = "PARTLABEL=kernelfs";
+
     /// Attempts to locate the partition containing the kernel code.
     /// Returns the drive and the partition id of the one on which the kernel is stored.
     ///
-    /// Currently, only uses the name of the partition as a reference.
+    /// The partition of the kernel slot to boot is used if a disk holds a boot metadata sector (see
+    /// [`locate_kernel_slot`]). Otherwise, the partition is looked up in the partition registry, using the
+    /// [`KERNEL_PARTITION`] selector.
     pub fn locate_kernel_partition() -> (AtaDeviceIdentifier, usize) {
-        let mut kernel_disk = AtaDeviceIdentifier::new(
-            fzboot::drivers::generics::dev_disk::SataDeviceType::AHCI,
-            0,
-            0,
+        if let Some((kernel_disk, kernel_part_id)) = locate_kernel_slot() {
+            return (kernel_disk, kernel_part_id);
+        }
+
+        let selector =
+            PartitionSelector::parse(KERNEL_PARTITION).expect("invalid kernel partition selector");
+
+        let Some((kernel_disk, kernel_part_id)) = find_partition(&selector) else {
+            panic!("failed to locate kernel ({selector})");
+        };
+
+        info!(
+            "kernel",
+            "located kernel image ({})",
+            partition_name(kernel_disk, kernel_part_id)
         );
 
-        let mut kernel_part_id = 0;
-        let mut found_kernel = false;
-
-        for drive in sata_drives() {
-            for (part_id, partition) in drive.partitions().iter().enumerate() {
-                match partition.metadata() {
-                    fzboot::fs::partitions::PartitionMetadata::MBR(mbr_part) => todo!(),
-                    fzboot::fs::partitions::PartitionMetadata::GPT(gpt_part) => {
-                        if gpt_part.name() == "kernelfs" {
-                            kernel_disk = drive.identifier();
-                            kernel_part_id = part_id;
-                            found_kernel = true;
-
-                            break;
-                        }
-                    }
-                }
-            }
+        (kernel_disk, kernel_part_id)
+    }
 
-            if found_kernel {
-                info!(
-                    "kernel",
-                    "located kernel image ({}    partition_id = {})", kernel_disk, kernel_part_id
-                );
-                break;
-            }
+    /// Selects the kernel slot to boot, using the boot metadata sector of the first disk that holds one.
+    ///
+    /// The boot attempt counter of the metadata is updated, and the bootloader rolls back to the previous slot if
+    /// the active one failed to boot too many times (see [`BootMetadataSector::begin_boot`]).
+    fn locate_kernel_slot() -> Option<(AtaDeviceIdentifier, usize)> {
+        let (device, mut metadata) = sata_drives().find_map(|device| {
+            BootMetadataSector::read_from(&device)
+                .ok()
+                .map(|metadata| (device, metadata))
+        })?;
+
+        let active = metadata.active_slot();
+        let Some(slot_id) = metadata.begin_boot(take_boot_success()) else {
+            warn!("kernel", "boot metadata describes no kernel slot");
+            return None;
+        };
+
+        if slot_id != active {
+            warn!(
+                "kernel",
+                "kernel slot {} failed to boot, rolled back to slot {}", active, slot_id
+            );
         }
 
-        if !found_kernel {
-            panic!("failed to locate kernel");
+        if let Err(err) = metadata.write_to(&device) {
+            warn!("kernel", "failed to update boot metadata: {err:?}");
         }
 
-        (kernel_disk, kernel_part_id)
+        let slot = metadata.slot(slot_id)?;
+        let Some(kernel_part_id) = device
+            .partitions()
+            .iter()
+            .position(|partition| partition.start_lba() == slot.lba())
+        else {
+            warn!(
+                "kernel",
+                "no partition for kernel slot {} (lba = {:#x})",
+                slot_id,
+                slot.lba()
+            );
+            return None;
+        };
+
+        let kernel_disk = device.identifier();
+        info!(
+            "kernel",
+            "located kernel image ({}    slot = {}    healthy = {}    attempts = {})",
+            partition_name(kernel_disk, kernel_part_id),
+            slot_id,
+            slot.healthy(),
+            metadata.boot_attempts()
+        );
+
+        Some((kernel_disk, kernel_part_id))
     }
 
-    /// Loads the kernel in memory from a disk device.
-    pub fn load_kernel(device: AtaDeviceIdentifier, partition: usize) {
+    /// Path of the kernel image, on the filesystem of the kernel partition.
+    pub const KERNEL_PATH: &str = "/boot/kernel.elf";
+
+    /// Path of the configuration file, on the filesystem of the kernel partition.
+    pub const CONFIG_PATH: &str = "/boot/fzboot.cfg";
+
+    /// Loads the options of the configuration file ([`CONFIG_PATH`]), if there is one on the kernel partition.
+    ///
+    /// Options set on the command line take precedence over the ones of the file.
+    pub fn load_config(device: AtaDeviceIdentifier, partition: usize) {
+        let Some(device) = get_sata_drive(device) else {
+            return;
+        };
+        let partitions = device.partitions();
+        let Some(partition) = partitions.get(partition) else {
+            return;
+        };
+
+        let Ok(mut file) = partition.open(CONFIG_PATH) else {
+            info!("config", "no configuration file ({CONFIG_PATH})");
+            return;
+        };
+
+        let mut contents = Vec::new();
+        if let Err(err) = file.read_file(&mut contents) {
+            info!("config", "failed to read {CONFIG_PATH}: {err:?}");
+            return;
+        }
+
+        let Ok(contents) = core::str::from_utf8(&contents) else {
+            info!("config", "{CONFIG_PATH} is not valid UTF-8");
+            return;
+        };
+
+        let count = config_parse_file(contents);
+        info!("config", "loaded {count} options from {CONFIG_PATH}");
+    }
+
+    /// Loads the kernel in memory from a disk device, and returns the physical address of its entry point.
+    ///
+    /// The kernel image is an ELF executable, read from [`KERNEL_PATH`]. It may be compressed (see
+    /// [`compress`](fzboot::compress)), in which case it is decompressed before being loaded.
+    ///
+    /// Before being decompressed, the image is checked against the boot metadata sector (see
+    /// [`verify_kernel_image`]).
+    ///
+    /// The segments of the image are loaded from [`KERNEL_LOAD_ADDR`], which is mapped to
+    /// [`KERNEL_CODE_MAPPING_BASE`](bootinit_paging::KERNEL_CODE_MAPPING_BASE), and the memory they use is reserved.
+    pub fn load_kernel(device: AtaDeviceIdentifier, partition: usize) -> PhyAddr {
         let device = get_sata_drive(device).expect("could not find kernel disk device");
-        let partition = device
-            .partitions()
+        let partitions = device.partitions();
+        let partition = partitions
             .get(partition)
             .expect("could not find kernel partition");
 
-        let mut sectors_read = 0;
+        let mut file = partition
+            .open(KERNEL_PATH)
+            .unwrap_or_else(|err| panic!("failed to open kernel image {KERNEL_PATH}: {err:?}"));
+        let mut image = Vec::new();
+        file.read_file(&mut image)
+            .unwrap_or_else(|err| panic!("failed to read kernel image {KERNEL_PATH}: {err:?}"));
+        verify_kernel_image(&device, partition, &image);
 
-        while sectors_read < KERNEL_SECTOR_SZ {
-            let read = device.read(
-                partition.start_lba() + u64::try_from(sectors_read).expect("invalid sectors count"),
-                0x100,
+        if let Some(format) = detect_format(&image) {
+            image = decompress_kernel(&image);
+            info!(
+                "kernel",
+                "decompressed kernel image ({}    size = {:#x})",
+                format,
+                image.len()
             );
-            let result = read.complete();
-            let read_data = result.data.expect(
-                format!(
-                    "invalid data read when loading kernel (sector {})",
-                    sectors_read
-                )
-                .as_str(),
+        }
+
+        let elf = ElfExecutable::parse(&image).expect("kernel image is not a valid ELF executable");
+        let base = u64::from(bootinit_paging::KERNEL_CODE_MAPPING_BASE);
+        let size = elf
+            .memory_size(base)
+            .unwrap_or_else(|err| panic!("invalid kernel image segments: {err:?}"));
+
+        let mut regions = memory_regions().lock();
+        if !regions.is_usable_range(u64::from(KERNEL_LOAD_ADDR), size) {
+            panic!(
+                "kernel image does not fit in memory (base_addr = {}    size = {:#x})",
+                KERNEL_LOAD_ADDR, size
             );
+        }
+        regions
+            .reserve(u64::from(KERNEL_LOAD_ADDR), size, MemoryRegionKind::Kernel)
+            .expect("failed to reserve kernel image memory");
+        drop(regions);
+
+        unsafe {
+            elf.load(KERNEL_LOAD_ADDR.as_mut_ptr(), base)
+                .unwrap_or_else(|err| panic!("failed to load kernel image: {err:?}"));
+        }
+
+        let entry = elf
+            .entry()
+            .checked_sub(base)
+            .filter(|&offset| offset < size)
+            .expect("kernel entry point lies outside of the image");
 
-            unsafe {
-                let mut mem_slice: &mut [u8] = core::slice::from_raw_parts_mut(
-                    (KERNEL_LOAD_ADDR + sectors_read * 0x200).as_mut_ptr(),
-                    min(0x200 * 0x200, read_data.len()),
+        info!(
+            "kernel",
+            "loaded kernel image to memory (base_addr = {}    size = {:#x}    virtual_base = {})",
+            KERNEL_LOAD_ADDR,
+            size,
+            bootinit_paging::KERNEL_CODE_MAPPING_BASE
+        );
+
+        KERNEL_LOAD_ADDR + entry
+    }
+
+    /// Checks the kernel image read from `partition` against the boot metadata sector written by the build tool.
+    ///
+    /// Images without a valid metadata sector (built by an older build tool, or installed by hand) are loaded without
+    /// being checked. If the metadata describes the image of this partition, the size and checksum of the image must
+    /// match.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the image does not match its metadata, rather than jumping to a corrupted kernel. The bootloader
+    /// first rolls back to the previous kernel slot if it can, so that it is booted after the reset that follows the
+    /// panic.
+    fn verify_kernel_image<D: DiskDevice>(device: &D, partition: &Partition, image: &[u8]) {
+        let mut metadata = match BootMetadataSector::read_from(device) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                warn!(
+                    "kernel",
+                    "no usable boot metadata, kernel image not verified ({err:?})"
                 );
-                mem_slice.copy_from_slice(&read_data);
+                return;
+            }
+        };
+
+        let Some(slot_id) = metadata.slot_by_lba(partition.start_lba()) else {
+            warn!(
+                "kernel",
+                "boot metadata does not describe this partition, kernel image not verified"
+            );
+            return;
+        };
+        let slot = *metadata.slot(slot_id).expect("invalid kernel slot");
+
+        if let Err(err) = slot.verify(image) {
+            if slot_id == metadata.active_slot() && metadata.rollback().is_some() {
+                if let Err(err) = metadata.write_to(device) {
+                    warn!("kernel", "failed to update boot metadata: {err:?}");
+                }
             }
 
-            sectors_read += 0x100;
+            panic!(
+                "kernel image of slot {} does not match boot metadata: {err:?} (expected size = {:#x}    size = {:#x})",
+                slot_id,
+                slot.size(),
+                image.len()
+            );
         }
 
         info!(
             "kernel",
-            "loaded kernel image to memory (base_addr = {}    virtual_base = {})",
-            KERNEL_LOAD_ADDR,
-            bootinit_paging::KERNEL_CODE_MAPPING_BASE
+            "verified kernel image (slot = {}    size = {:#x})",
+            slot_id,
+            image.len()
         );
     }
+
+    /// Decompresses a compressed kernel image.
+    fn decompress_kernel(payload: &[u8]) -> Vec<u8> {
+        let size = decompressed_size(payload).expect("unknown size of the compressed kernel image");
+        let mut image = vec![0; size];
+
+        let start = time::now();
+        let len = decompress(payload, &mut image)
+            .unwrap_or_else(|err| panic!("failed to decompress kernel image: {err:?}"));
+        image.truncate(len);
+
+        info!(
+            "kernel",
+            "decompression took {:.0}us (compressed = {:#x})",
+            time::now() - start,
+            payload.len()
+        );
+
+        image
+    }
 }
@@ -9,56 +9,94 @@ mod boot;
 
 extern crate alloc;
 
+use alloc::format;
 use boot::fzkernel;
 use core::arch::asm;
 use core::{panic::PanicInfo, ptr::NonNull};
+use fzboot::boot::boottime::boottime_export_serial;
 use fzboot::boot::multiboot;
+use fzboot::boot::progress::{progress_init, report_stage, BootStage, ProgressMode};
+use fzboot::boot::stages::{run_stages, InitStage};
+use fzboot::config::{self, config_parse_cmdline};
+use fzboot::drivers::generics::dev_byte::pmem_devices_init;
 use fzboot::drivers::generics::dev_disk::{sata_drives, DiskDevice};
 use fzboot::drivers::ide::AtaDeviceIdentifier;
+use fzboot::event::event_log_init;
+use fzboot::failpoint::failpoints_parse;
+use fzboot::fs::memdump::{memdump_init, memdump_write_all};
 use fzboot::fs::partitions::mbr;
+use fzboot::fs::pstore::{pstore_init, pstore_write_crash};
+use fzboot::io::qemu_exit::qemu_exit_on_panic;
+use fzboot::io::smbios::smbios_init;
+use fzboot::irq::deferred::run_deferred_work;
 use fzboot::irq::manager::{get_interrupt_manager, get_prot_interrupt_manager};
-use fzboot::mem::e820::{e820_entries_bootloader, E820_MAP_ADDR};
+use fzboot::klog::klog_force_unlock;
+use fzboot::log::log_config_apply;
+use fzboot::mem::bump::early_alloc_seal;
+use fzboot::mem::e820::{
+    e820_entries_bootloader, e820_snapshot, init_memory_regions, MemoryRegionKind,
+};
+use fzboot::mem::shrinker::shrinker_init;
 use fzboot::mem::{MemoryAddress, PhyAddr, VirtAddr};
-use fzboot::video::vesa::{init_text_buffer_from_vesa, text_buffer};
+use fzboot::panicking::{panic_enter, panic_nested};
+use fzboot::power::cpufreq::cpufreq_init;
+use fzboot::power::thermal::thermal_init;
+use fzboot::video::vesa::edid::Edid;
+use fzboot::video::vesa::video_mode::{vesa_mode_info, vesa_mode_snapshot};
+use fzboot::video::vesa::{
+    enable_text_back_buffer, init_text_buffer_from_vesa, text_buffer, vesa_switch_mode,
+};
 use fzboot::x86::apic::InterruptVector;
+use fzboot::x86::descriptors::bootinit_idt;
 use fzboot::x86::descriptors::gdt::{long_init_gdt, LONG_GDT_ADDR};
+use fzboot::x86::hypervisor::hypervisor;
+use fzboot::x86::idle::halt_forever;
 use fzboot::x86::int::enable_interrupts;
+use fzboot::x86::kvm::{kvm_handoff, kvm_init};
 use fzboot::x86::paging::bootinit_paging;
 use fzboot::{
     drivers::pci::pci_devices_init,
-    mem::{
-        e820::{AddressRangeDescriptor, E820MemType, E820MemoryMap},
-        MemoryStructure, MEM_STRUCTURE,
-    },
+    mem::{MemoryStructure, MEM_STRUCTURE},
 };
 use fzboot::{drivers::pci::pci_enumerate, io::pic::PIC};
 use fzboot::{error, println};
 use fzboot::{
     info,
-    io::acpi::{acpi_init, hpet::hpet_clk_init},
+    io::acpi::{
+        acpi_init,
+        ec::{ec_events_init, ec_init},
+        hpet::hpet_clk_init,
+    },
     mem::bmalloc::heap::LockedBuddyAllocator,
-    time,
+    time::{
+        self,
+        clocksource::{clocksource_init, clocksource_watchdog_init},
+        timer::timer_init,
+    },
     x86::tsc::TSCClock,
 };
 use fzproc_macros::interrupt_handler;
 
-static mut DEFAULT_HEAP_ADDR: usize = 0x5000000;
-/// Default heap size: 512KiB
-const DEFAULT_HEAP_SIZE: usize = 0x1000000;
-
 /// Minimum heap size: 4KiB
 const MIN_HEAP_SIZE: usize = 0x1000;
 
 const MAX_HEAP_SIZE: usize = 0x1000000;
 
+/// The heap and the stack must be addressable in 32-bit protected mode, including the address following their end.
+const HEAP_ADDR_LIMIT: u64 = 0xFFFF_0000;
+
+/// Size of the low memory area used by real-mode structures and the bootloader code: 1MiB
+const BOOTLOADER_LOW_MEMORY_SIZE: u64 = 0x100000;
+
 /// Default stack size, if enough RAM is available: 32KiB
 const STACK_SIZE: usize = 0x8000;
 
+/// How the boot progress is displayed: the regular log is only shown if an error occurs.
+const BOOT_PROGRESS_MODE: ProgressMode = ProgressMode::Graphical;
+
+/// The heap is placed by [`heap_init`], depending on the memory map: allocations fail until then.
 #[global_allocator]
-pub static BUDDY_ALLOCATOR: LockedBuddyAllocator<14> = LockedBuddyAllocator::new(
-    NonNull::new(unsafe { DEFAULT_HEAP_ADDR as *mut u8 }).unwrap(),
-    DEFAULT_HEAP_SIZE,
-);
+pub static BUDDY_ALLOCATOR: LockedBuddyAllocator<14> = LockedBuddyAllocator::uninit();
 
 #[no_mangle]
 #[link_section = ".start"]
@@ -67,34 +105,130 @@ pub extern "C" fn _start() -> ! {
 }
 
 pub fn boot_main() -> ! {
-    init_text_buffer_from_vesa();
     fzboot::mem::zero_bss();
+    vesa_mode_snapshot();
+    e820_snapshot();
+    init_text_buffer_from_vesa();
     heap_init();
-    acpi_init();
-    clock_init();
-    interrupts_init();
-    pci_enumerate();
-    pci_devices_init();
+    failpoints_parse(boot::headers::KERNEL_CMDLINE);
+    config_parse_cmdline(boot::headers::KERNEL_CMDLINE);
+    log_config_apply();
+    vesa_mode_apply();
+    event_log_init();
+    pstore_init();
+    progress_init(BOOT_PROGRESS_MODE);
+    report_stage(BootStage::Memory);
+    run_stages(BOOT_STAGES).expect("invalid boot stages");
+
+    // A drive may have been connected or disconnected since the disks were set up.
+    run_deferred_work();
 
     let kernel_part = boot::fzkernel::locate_kernel_partition();
-    boot::fzkernel::load_kernel(kernel_part.0, kernel_part.1);
+    boot::fzkernel::load_config(kernel_part.0, kernel_part.1);
+    report_stage(BootStage::Kernel);
+    let kernel_entry = boot::fzkernel::load_kernel(kernel_part.0, kernel_part.1);
 
-    let mb_information_hdr_addr = boot::headers::dump_multiboot_information_header();
+    let boot_info_addr = boot::headers::dump_boot_info();
     bootinit_paging::init_paging();
 
-    info!("kernel", "jumping to kernel main (addr = 0x80000)");
+    report_stage(BootStage::Done);
+    if config::get_bool("boottime.serial") && boottime_export_serial().is_err() {
+        info!("boot", "no serial port to write the boot timeline to");
+    }
+    info!("kernel", "jumping to kernel main (addr = {})", kernel_entry);
+    kvm_handoff();
+
+    let kernel_entry = u32::try_from(u64::from(kernel_entry)).expect("invalid kernel entry point");
 
     unsafe {
-        long_init_gdt(PhyAddr::new(LONG_GDT_ADDR));
-        asm!("mov ecx, {}", in(reg) mb_information_hdr_addr);
-        asm!("mov ebp, 0", "push 0x10", "push 0x800000", "retf");
+        let selectors = long_init_gdt(PhyAddr::new(LONG_GDT_ADDR));
+        bootinit_idt::init_idt();
+        asm!(
+            "mov ebp, 0",
+            "push {:e}",
+            "push {:e}",
+            "retf",
+            in(reg) u32::from(selectors.kernel_code.bytes()),
+            in(reg) kernel_entry,
+            in("ecx") boot_info_addr
+        );
         core::unreachable!();
     }
 }
 
+/// Initialization of the subsystems, once the heap is available.
+const BOOT_STAGES: &[InitStage] = &[
+    InitStage::new("acpi", &[], acpi_init).milestone(BootStage::Acpi),
+    InitStage::new("smbios", &[], smbios_init),
+    InitStage::new("ec", &["acpi"], ec_stage),
+    InitStage::new("display", &[], display_info),
+    InitStage::new("clocks", &["acpi"], clock_init).milestone(BootStage::Clocks),
+    InitStage::new("interrupts", &[], interrupts_init),
+    InitStage::new("timers", &["clocks", "interrupts"], timer_init),
+    InitStage::new("ec-events", &["ec", "timers"], ec_events_stage),
+    InitStage::new("thermal", &["timers"], thermal_init),
+    InitStage::new("shrinker", &["timers"], shrinker_stage),
+    InitStage::new("cpufreq", &["thermal"], cpufreq_stage),
+    InitStage::new(
+        "clocksource-watchdog",
+        &["timers"],
+        clocksource_watchdog_init,
+    ),
+    InitStage::new("text-back-buffer", &["timers"], enable_text_back_buffer),
+    InitStage::new("pci", &["acpi"], pci_enumerate).milestone(BootStage::Pci),
+    InitStage::new("disks", &["pci", "timers"], pci_devices_init).milestone(BootStage::Disks),
+    InitStage::new("pmem", &[], pmem_devices_init),
+    InitStage::new("memdump", &["disks", "pmem"], memdump_init).milestone(BootStage::Filesystems),
+];
+
+/// Switches to the VESA mode set by the `vesa.mode` option, if any.
+fn vesa_mode_apply() {
+    let Some(mode) = config::get_u64("vesa.mode").filter(|&mode| mode != 0) else {
+        return;
+    };
+
+    match u16::try_from(mode).map(vesa_switch_mode) {
+        Ok(Ok(())) => {
+            info!("vesa", "switched to mode {:#x}", mode);
+        }
+        _ => {
+            error!("vesa", "failed to switch to mode {:#x}", mode);
+        }
+    }
+}
+
+fn ec_stage() {
+    if ec_init().is_err() {
+        info!("ec", "no embedded controller found");
+    }
+}
+
+fn ec_events_stage() {
+    if ec_events_init().is_err() {
+        info!("ec", "embedded controller events are not handled");
+    }
+}
+
+fn shrinker_stage() {
+    if shrinker_init().is_err() {
+        info!("shrinker", "caches are only trimmed when running low on memory");
+    }
+}
+
+fn cpufreq_stage() {
+    if cpufreq_init().is_err() {
+        info!("cpufreq", "frequency scaling not supported");
+    }
+}
+
 pub fn clock_init() {
     hpet_clk_init();
     TSCClock::init();
+    if let Some(hypervisor) = hypervisor() {
+        info!("hypervisor", "running under a hypervisor ({})", hypervisor);
+    }
+    kvm_init();
+    clocksource_init();
 
     let curr_time = time::date();
 
@@ -107,6 +241,33 @@ pub fn clock_init() {
     );
 }
 
+/// Logs the monitor identification and the video mode selected in real mode.
+pub fn display_info() {
+    let mode_info = vesa_mode_info();
+    let (width, height, bpp) = (mode_info.width, mode_info.height, mode_info.bits_per_pixel);
+
+    match Edid::load() {
+        Some(edid) => {
+            info!(
+                "vesa",
+                "monitor {} (product {:#06x})    native = {:?}    mode = {}x{}x{}",
+                core::str::from_utf8(&edid.manufacturer_id()).unwrap_or("???"),
+                edid.product_code(),
+                edid.native_resolution(),
+                width,
+                height,
+                bpp
+            );
+        }
+        None => {
+            info!(
+                "vesa",
+                "no EDID available    mode = {}x{}x{}", width, height, bpp
+            );
+        }
+    }
+}
+
 pub fn interrupts_init() {
     let pic = PIC::default();
     pic.remap(0x20, 0x28);
@@ -120,33 +281,45 @@ pub fn interrupts_init() {
 }
 
 pub fn heap_init() {
-    let e820_map = E820MemoryMap::new(E820_MAP_ADDR as *mut u8);
-    let mut best_entry = AddressRangeDescriptor::default();
+    let mut regions = init_memory_regions(e820_snapshot().iter().copied()).lock();
 
-    for entry in e820_map {
-        if matches!(entry.addr_type, E820MemType::RAM) && entry.length() > best_entry.length() {
-            best_entry = entry;
-        }
-    }
+    // Real-mode structures, BIOS data and bootloader code all live in the first megabyte.
+    regions
+        .reserve(0, BOOTLOADER_LOW_MEMORY_SIZE, MemoryRegionKind::Bootloader)
+        .expect("failed to reserve bootloader memory");
+
+    let mut best_entry = regions
+        .largest_usable_below(HEAP_ADDR_LIMIT)
+        .expect("no usable memory region available");
 
-    assert!(best_entry.length() >= MIN_HEAP_SIZE as u64);
+    assert!(best_entry.length >= MIN_HEAP_SIZE as u64);
 
-    if best_entry.length() > MAX_HEAP_SIZE as u64 {
-        // No 64-bit support for now
-        best_entry.length_high = 0;
-        best_entry.length_low = MAX_HEAP_SIZE as u32;
+    // The heap is placed at the end of the region, leaving the memory following the kernel load address (which
+    // usually lies in the same region) free for the kernel image, whose size is only known once it is read.
+    if best_entry.length > MAX_HEAP_SIZE as u64 {
+        best_entry.base = best_entry.end() - MAX_HEAP_SIZE as u64;
+        best_entry.length = MAX_HEAP_SIZE as u64;
     }
 
-    let stack_size_min = (best_entry.length() >> 3) as usize;
+    let stack_size_min = (best_entry.length >> 3) as usize;
     let stack_size = if stack_size_min < STACK_SIZE {
         stack_size_min as usize
     } else {
         STACK_SIZE
     };
     let heap_addr = best_entry.base_addr();
-    let stack_addr = unsafe { heap_addr.add(best_entry.length() as usize) } as usize;
+    let stack_addr = unsafe { heap_addr.add(best_entry.length as usize) } as usize;
 
-    let heap_size = (best_entry.length() as usize) - stack_size;
+    let heap_size = (best_entry.length as usize) - stack_size;
+
+    regions
+        .reserve(
+            best_entry.base,
+            best_entry.length,
+            MemoryRegionKind::Bootloader,
+        )
+        .expect("failed to reserve bootloader heap");
+    drop(regions);
 
     let mem_struct = MemoryStructure {
         heap_addr: heap_addr as usize,
@@ -154,17 +327,13 @@ pub fn heap_init() {
     };
     info!(
         "mem",
-        "relocated heap (addr = {:#x}    size = {:#x})", heap_addr as u64, heap_size
+        "heap placed at {:#x} (size = {:#x})", heap_addr as u64, heap_size
     );
 
     MEM_STRUCTURE.init_once(|| mem_struct);
 
-    unsafe {
-        BUDDY_ALLOCATOR.alloc.lock().resize(
-            NonNull::new(best_entry.base_addr()).unwrap(),
-            heap_size as usize,
-        )
-    };
+    unsafe { BUDDY_ALLOCATOR.init(NonNull::new(heap_addr).unwrap(), heap_size) };
+    early_alloc_seal();
 
     unsafe {
         asm!("mov esp, eax", in("eax") stack_addr);
@@ -173,9 +342,28 @@ pub fn heap_init() {
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    if panic_enter() != 0 {
+        panic_nested(info.location());
+    }
+
     unsafe {
         text_buffer().buffer.force_unlock();
+        klog_force_unlock();
     }
+    text_buffer().buffer.lock().set_auto_flush(true);
     error!("fatal: {info}");
-    loop {}
+    let _ = pstore_write_crash(&format!("{}", info.message()), info.location());
+    if config::get_bool("memdump.panic") {
+        match memdump_write_all() {
+            Ok(dump) => {
+                info!("memdump", "memory snapshot written ({} pages)", dump.pages);
+            }
+            Err(_) => {
+                error!("memdump", "failed to write the memory snapshot");
+            }
+        }
+    }
+    fzboot::mem::stats::print_meminfo();
+    qemu_exit_on_panic();
+    halt_forever();
 }
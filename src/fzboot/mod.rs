@@ -1,12 +1,35 @@
+#[cfg(feature = "alloc")]
+pub mod compress;
+#[cfg(feature = "alloc")]
+pub mod config;
+pub mod early_fmt;
 mod err;
+#[cfg(feature = "alloc")]
+pub mod event;
 #[cfg(feature = "x86_64")]
 pub mod exceptions;
 #[cfg(feature = "alloc")]
+pub mod failpoint;
+#[cfg(feature = "alloc")]
+pub mod ipc;
+#[cfg(feature = "alloc")]
 pub mod irq;
+pub mod klog;
+pub mod log;
+#[cfg(feature = "x86_64")]
+pub mod module;
+pub mod panicking;
+pub mod power;
 #[cfg(feature = "x86_64")]
 pub mod process;
 #[cfg(feature = "x86_64")]
 pub mod scheduler;
+#[cfg(feature = "x86_64")]
+pub mod smp;
+#[cfg(feature = "alloc")]
+pub mod sync;
+#[cfg(feature = "x86_64")]
+pub mod syscall;
 pub mod time;
 
 pub mod errors {
@@ -0,0 +1,216 @@
+//! ELF64 relocatable objects parsing.
+//!
+//! Only the structures required to load a relocatable object (`ET_REL`) are defined: the file header, the section
+//! headers, the symbol table and the relocation entries (with explicit addend).
+
+use bytemuck::{pod_read_unaligned, Pod, Zeroable};
+use core::mem::size_of;
+
+use crate::errors::ModuleError;
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_REL: u16 = 1;
+const EM_X86_64: u16 = 62;
+
+pub(super) const SHT_SYMTAB: u32 = 2;
+pub(super) const SHT_RELA: u32 = 4;
+pub(super) const SHT_NOBITS: u32 = 8;
+
+pub(super) const SHF_WRITE: u64 = 1 << 0;
+pub(super) const SHF_ALLOC: u64 = 1 << 1;
+pub(super) const SHF_EXECINSTR: u64 = 1 << 2;
+
+pub(super) const SHN_UNDEF: u16 = 0;
+pub(super) const SHN_ABS: u16 = 0xFFF1;
+pub(super) const SHN_COMMON: u16 = 0xFFF2;
+
+pub(super) const STB_GLOBAL: u8 = 1;
+pub(super) const STB_WEAK: u8 = 2;
+
+pub(super) const R_X86_64_NONE: u32 = 0;
+pub(super) const R_X86_64_64: u32 = 1;
+pub(super) const R_X86_64_PC32: u32 = 2;
+pub(super) const R_X86_64_PLT32: u32 = 4;
+pub(super) const R_X86_64_32: u32 = 10;
+pub(super) const R_X86_64_32S: u32 = 11;
+pub(super) const R_X86_64_PC64: u32 = 24;
+
+/// ELF64 file header.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub(super) struct ElfHeader {
+    pub ident: [u8; 16],
+    pub elf_type: u16,
+    pub machine: u16,
+    pub version: u32,
+    pub entry: u64,
+    pub phoff: u64,
+    pub shoff: u64,
+    pub flags: u32,
+    pub ehsize: u16,
+    pub phentsize: u16,
+    pub phnum: u16,
+    pub shentsize: u16,
+    pub shnum: u16,
+    pub shstrndx: u16,
+}
+
+/// ELF64 section header.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub(super) struct SectionHeader {
+    pub name: u32,
+    pub sh_type: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub link: u32,
+    pub info: u32,
+    pub addralign: u64,
+    pub entsize: u64,
+}
+
+/// ELF64 symbol table entry.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub(super) struct Symbol {
+    pub name: u32,
+    pub info: u8,
+    pub other: u8,
+    pub shndx: u16,
+    pub value: u64,
+    pub size: u64,
+}
+
+impl Symbol {
+    pub fn binding(&self) -> u8 {
+        self.info >> 4
+    }
+}
+
+/// ELF64 relocation entry, with explicit addend.
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[repr(C)]
+pub(super) struct Rela {
+    pub offset: u64,
+    pub info: u64,
+    pub addend: i64,
+}
+
+impl Rela {
+    pub fn symbol(&self) -> usize {
+        (self.info >> 32) as usize
+    }
+
+    pub fn rel_type(&self) -> u32 {
+        self.info as u32
+    }
+}
+
+/// A relocatable ELF64 object, for x86_64.
+pub(super) struct ElfObject<'o> {
+    image: &'o [u8],
+    sections: &'o [u8],
+    section_count: usize,
+}
+
+impl<'o> ElfObject<'o> {
+    /// Parses the file header of `image`, and checks that it is a relocatable object for x86_64.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ModuleError::InvalidObject`] if `image` is not a valid relocatable object for x86_64.
+    pub fn parse(image: &'o [u8]) -> Result<Self, ModuleError> {
+        let header: ElfHeader = read_struct(image, 0)?;
+
+        if header.ident[..4] != ELF_MAGIC
+            || header.ident[4] != ELFCLASS64
+            || header.ident[5] != ELFDATA2LSB
+            || header.elf_type != ET_REL
+            || header.machine != EM_X86_64
+            || usize::from(header.shentsize) != size_of::<SectionHeader>()
+        {
+            return Err(ModuleError::InvalidObject);
+        }
+
+        let section_count = usize::from(header.shnum);
+        let sections = slice_at(
+            image,
+            header.shoff,
+            (section_count * size_of::<SectionHeader>()) as u64,
+        )?;
+
+        Ok(Self {
+            image,
+            sections,
+            section_count,
+        })
+    }
+
+    /// Number of sections in the object.
+    pub fn section_count(&self) -> usize {
+        self.section_count
+    }
+
+    /// Returns the header of the section at `index`.
+    pub fn section(&self, index: usize) -> Result<SectionHeader, ModuleError> {
+        if index >= self.section_count {
+            return Err(ModuleError::InvalidObject);
+        }
+
+        read_struct(self.sections, index * size_of::<SectionHeader>())
+    }
+
+    /// Returns the content of a section (empty for `SHT_NOBITS` sections).
+    pub fn section_data(&self, section: &SectionHeader) -> Result<&'o [u8], ModuleError> {
+        if section.sh_type == SHT_NOBITS {
+            return Ok(&[]);
+        }
+
+        slice_at(self.image, section.offset, section.size)
+    }
+
+    /// Returns the entry at `index` of a table section (symbol table, or relocations).
+    pub fn entry<T: Pod>(&self, section: &SectionHeader, index: usize) -> Result<T, ModuleError> {
+        read_struct(self.section_data(section)?, index * size_of::<T>())
+    }
+
+    /// Number of entries of a table section (symbol table, or relocations).
+    pub fn entry_count<T: Pod>(&self, section: &SectionHeader) -> usize {
+        section.size as usize / size_of::<T>()
+    }
+
+    /// Reads the null-terminated string at `offset` in the string table `strtab`.
+    pub fn string(&self, strtab: &SectionHeader, offset: u32) -> Result<&'o str, ModuleError> {
+        let table = self.section_data(strtab)?;
+        let start = table
+            .get(offset as usize..)
+            .ok_or(ModuleError::InvalidObject)?;
+        let len = start
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or(ModuleError::InvalidObject)?;
+
+        core::str::from_utf8(&start[..len]).map_err(|_| ModuleError::InvalidObject)
+    }
+}
+
+fn slice_at(image: &[u8], offset: u64, len: u64) -> Result<&[u8], ModuleError> {
+    let start = usize::try_from(offset).map_err(|_| ModuleError::InvalidObject)?;
+    let end = start
+        .checked_add(usize::try_from(len).map_err(|_| ModuleError::InvalidObject)?)
+        .ok_or(ModuleError::InvalidObject)?;
+
+    image.get(start..end).ok_or(ModuleError::InvalidObject)
+}
+
+fn read_struct<T: Pod>(data: &[u8], offset: usize) -> Result<T, ModuleError> {
+    let bytes = data
+        .get(offset..offset + size_of::<T>())
+        .ok_or(ModuleError::InvalidObject)?;
+
+    Ok(pod_read_unaligned(bytes))
+}
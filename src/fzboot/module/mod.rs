@@ -0,0 +1,560 @@
+//! Kernel modules.
+//!
+//! Modules are ELF64 relocatable objects (`.o` files, built with the `large` code model), loaded at runtime into
+//! the segment dedicated to modules ([`KERNEL_MODULES_BASE`]). Loading a module consists in:
+//!
+//! - laying out its allocated sections into three page-aligned segments: code, read-only data, and writable data.
+//! - resolving the symbols it imports, against the symbols exported by the Kernel (see [`symbols`]) and by the
+//!   modules that were previously loaded.
+//! - applying the relocations, and mapping each segment with the appropriate permissions.
+//! - calling its `init` entry point (`extern "C" fn() -> i32`), which must return 0 on success.
+//!
+//! Global symbols defined by a module (other than its `init` and `exit` entry points) are exported to the modules
+//! loaded afterwards. An optional `exit` entry point (`extern "C" fn()`) is called when the module is unloaded.
+//!
+//! The virtual address space used by a module is not reused once it is unloaded.
+
+use alloc::alloc::{alloc, dealloc};
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::slice;
+use spin::{Mutex, RwLock};
+
+use crate::errors::ModuleError;
+use crate::export_symbol;
+use crate::fs::{File, FsFile};
+use crate::kernel_syms::{KERNEL_MODULES_BASE, KERNEL_MODULES_SIZE, PAGE_SIZE};
+use crate::mem::kernel_sec::nx_prot_enabled;
+use crate::mem::stats::{self, MemoryConsumer};
+use crate::mem::{MemoryAddress, VirtAddr};
+use crate::video::vesa::print;
+use crate::x86::paging::page_alloc::frame_alloc::{alloc_page, free_page, FrameAllocation};
+use crate::x86::paging::page_table::mapper::{MemoryMapping, PhysicalMemoryMapping};
+use crate::x86::paging::{get_memory_mapper, PageTableFlags};
+use crate::{error, info};
+
+use self::elf::{
+    ElfObject, Rela, SectionHeader, Symbol, R_X86_64_32, R_X86_64_32S, R_X86_64_64, R_X86_64_NONE,
+    R_X86_64_PC32, R_X86_64_PC64, R_X86_64_PLT32, SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHN_ABS,
+    SHN_COMMON, SHN_UNDEF, SHT_RELA, SHT_SYMTAB, STB_GLOBAL, STB_WEAK,
+};
+use self::symbols::kernel_symbol;
+
+mod elf;
+pub mod symbols;
+
+/// Name of the entry point called when a module is loaded.
+const MODULE_INIT_SYMBOL: &str = "init";
+
+/// Name of the entry point called when a module is unloaded.
+const MODULE_EXIT_SYMBOL: &str = "exit";
+
+static LOADED_MODULES: RwLock<Vec<LoadedModule>> = RwLock::new(Vec::new());
+
+/// Next free address in the segment dedicated to modules.
+static MODULES_MAPPING_PTR: Mutex<u64> = Mutex::new(0);
+
+/// Information about a loaded module.
+#[derive(Clone, Debug)]
+pub struct ModuleInfo {
+    /// Name of the module.
+    pub name: String,
+
+    /// Base virtual address of the module.
+    pub base: VirtAddr,
+
+    /// Size of the module in memory, in bytes.
+    pub size: usize,
+
+    /// Modules whose symbols are used by this module.
+    pub dependencies: Vec<String>,
+}
+
+struct LoadedModule {
+    info: ModuleInfo,
+    frames: FrameAllocation,
+    exports: Vec<(String, u64)>,
+    exit: Option<u64>,
+}
+
+/// Kind of memory segment in which a section is placed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SegmentKind {
+    Code,
+    ReadOnly,
+    Data,
+}
+
+impl SegmentKind {
+    fn of(section: &SectionHeader) -> Self {
+        if section.flags & SHF_EXECINSTR != 0 {
+            Self::Code
+        } else if section.flags & SHF_WRITE != 0 {
+            Self::Data
+        } else {
+            Self::ReadOnly
+        }
+    }
+
+    fn flags(self) -> PageTableFlags {
+        let mut flags = PageTableFlags::new();
+
+        if self == Self::Data {
+            flags.set_write(true);
+        }
+
+        if self != Self::Code && nx_prot_enabled() {
+            flags.set_nxe(true);
+        }
+
+        flags
+    }
+}
+
+/// Placement of the allocated sections of a module, relative to its base address.
+struct ModuleLayout {
+    /// Offset of each section, or `None` if it is not allocated.
+    sections: Vec<Option<usize>>,
+
+    /// Offset, size and kind of each segment.
+    segments: [(usize, usize, SegmentKind); 3],
+
+    /// Total size of the module, in bytes.
+    size: usize,
+}
+
+impl ModuleLayout {
+    fn compute(object: &ElfObject) -> Result<Self, ModuleError> {
+        let kinds = [SegmentKind::Code, SegmentKind::ReadOnly, SegmentKind::Data];
+        let mut sections = vec![None; object.section_count()];
+        let mut segments = [(0, 0, SegmentKind::Code); 3];
+        let mut offset: usize = 0;
+
+        for (segment, kind) in segments.iter_mut().zip(kinds) {
+            let start = offset;
+
+            for (index, placement) in sections.iter_mut().enumerate() {
+                let section = object.section(index)?;
+
+                if section.flags & SHF_ALLOC == 0 || SegmentKind::of(&section) != kind {
+                    continue;
+                }
+
+                let align = usize::max(section.addralign as usize, 1);
+                offset = offset.next_multiple_of(align);
+                *placement = Some(offset);
+                offset += section.size as usize;
+            }
+
+            offset = offset.next_multiple_of(PAGE_SIZE);
+            *segment = (start, offset - start, kind);
+        }
+
+        Ok(Self {
+            sections,
+            segments,
+            size: offset,
+        })
+    }
+}
+
+/// Loads a module from an ELF64 relocatable object, and calls its `init` entry point.
+///
+/// # Errors
+///
+/// Returns [`ModuleError::AlreadyLoaded`] if a module named `name` is already loaded. May return any other variant
+/// of [`ModuleError`] if the object is invalid, if one of its imports cannot be resolved, or if its `init` entry
+/// point fails.
+pub fn module_load(name: &str, image: &[u8]) -> Result<ModuleInfo, ModuleError> {
+    if module_loaded(name) {
+        return Err(ModuleError::AlreadyLoaded);
+    }
+
+    let object = ElfObject::parse(image)?;
+    let layout = ModuleLayout::compute(&object)?;
+
+    if layout.size == 0 {
+        return Err(ModuleError::MissingEntryPoint);
+    }
+
+    let frames = alloc_page(layout.size).map_err(|_| ModuleError::OutOfMemory)?;
+    let base = match reserve_address_space(layout.size) {
+        Some(base) => base,
+        None => {
+            free_page(frames);
+            return Err(ModuleError::OutOfMemory);
+        }
+    };
+
+    // The module is written through the physical memory mapping, before being mapped with its final permissions.
+    let memory = unsafe {
+        slice::from_raw_parts_mut(
+            PhysicalMemoryMapping::KERNEL_DEFAULT_MAPPING
+                .convert(frames.start)
+                .as_mut_ptr::<u8>(),
+            layout.size,
+        )
+    };
+    memory.fill(0);
+
+    let mut module = LoadedModule {
+        info: ModuleInfo {
+            name: name.to_string(),
+            base: VirtAddr::new(base),
+            size: layout.size,
+            dependencies: Vec::new(),
+        },
+        frames,
+        exports: Vec::new(),
+        exit: None,
+    };
+
+    let init = match link_module(&object, &layout, &mut module, memory) {
+        Ok(init) => init,
+        Err(err) => {
+            free_page(module.frames);
+            return Err(err);
+        }
+    };
+
+    for (offset, size, kind) in layout.segments {
+        if size == 0 {
+            continue;
+        }
+
+        unsafe {
+            get_memory_mapper().lock().map_physical_memory(
+                module.frames.start + offset,
+                module.info.base + offset,
+                kind.flags(),
+                PageTableFlags::new().with_write(true),
+                size,
+            );
+        }
+    }
+
+    stats::charge(MemoryConsumer::Drivers, module.frames.length);
+
+    let init: extern "C" fn() -> i32 = unsafe { core::mem::transmute(init as usize) };
+    let status = init();
+
+    if status != 0 {
+        error!("module", "{}: init failed (status = {})", name, status);
+        release_module(module);
+        return Err(ModuleError::InitFailed(status));
+    }
+
+    info!(
+        "module",
+        "loaded {} (base = {}    size = {:#x})", name, module.info.base, module.info.size
+    );
+
+    let info = module.info.clone();
+    LOADED_MODULES.write().push(module);
+
+    Ok(info)
+}
+
+/// Reads a module from `file`, and loads it (see [`module_load`]).
+///
+/// # Errors
+///
+/// Returns [`ModuleError::IOError`] if the file cannot be read. May return any other variant of [`ModuleError`] if
+/// the module cannot be loaded.
+pub fn module_load_file(name: &str, file: &mut File) -> Result<ModuleInfo, ModuleError> {
+    let mut image = Vec::new();
+    file.read_file(&mut image)
+        .map_err(|_| ModuleError::IOError)?;
+
+    module_load(name, &image)
+}
+
+/// Calls the `exit` entry point of the module `name`, and unloads it.
+///
+/// # Errors
+///
+/// Returns [`ModuleError::NotLoaded`] if no module named `name` is loaded, or [`ModuleError::InUse`] if another
+/// loaded module depends on it.
+pub fn module_unload(name: &str) -> Result<(), ModuleError> {
+    let module = {
+        let mut modules = LOADED_MODULES.write();
+
+        let index = modules
+            .iter()
+            .position(|module| module.info.name == name)
+            .ok_or(ModuleError::NotLoaded)?;
+
+        if modules
+            .iter()
+            .any(|module| module.info.dependencies.iter().any(|dep| dep == name))
+        {
+            return Err(ModuleError::InUse);
+        }
+
+        modules.remove(index)
+    };
+
+    if let Some(exit) = module.exit {
+        let exit: extern "C" fn() = unsafe { core::mem::transmute(exit as usize) };
+        exit();
+    }
+
+    info!("module", "unloaded {}", name);
+    release_module(module);
+
+    Ok(())
+}
+
+/// Checks if a module named `name` is loaded.
+pub fn module_loaded(name: &str) -> bool {
+    LOADED_MODULES
+        .read()
+        .iter()
+        .any(|module| module.info.name == name)
+}
+
+/// Returns information about every loaded module.
+pub fn loaded_modules() -> Vec<ModuleInfo> {
+    LOADED_MODULES
+        .read()
+        .iter()
+        .map(|module| module.info.clone())
+        .collect()
+}
+
+/// Resolves the symbols of the module, and applies its relocations.
+///
+/// Returns the address of the `init` entry point of the module.
+fn link_module(
+    object: &ElfObject,
+    layout: &ModuleLayout,
+    module: &mut LoadedModule,
+    memory: &mut [u8],
+) -> Result<u64, ModuleError> {
+    let base = u64::from(module.info.base);
+    let mut symtab = None;
+
+    for index in 0..object.section_count() {
+        let section = object.section(index)?;
+
+        if section.sh_type == SHT_SYMTAB {
+            symtab = Some(section);
+        }
+
+        if let Some(offset) = layout.sections[index] {
+            let data = object.section_data(&section)?;
+            memory[offset..offset + data.len()].copy_from_slice(data);
+        }
+    }
+
+    let symtab = symtab.ok_or(ModuleError::InvalidObject)?;
+    let strtab = object.section(symtab.link as usize)?;
+    let mut symbols = Vec::with_capacity(object.entry_count::<Symbol>(&symtab));
+    let mut init = None;
+
+    for index in 0..object.entry_count::<Symbol>(&symtab) {
+        let symbol: Symbol = object.entry(&symtab, index)?;
+        let name = object.string(&strtab, symbol.name)?;
+
+        let value = match symbol.shndx {
+            SHN_UNDEF if name.is_empty() => 0,
+            SHN_UNDEF => match resolve_import(name, &mut module.info.dependencies) {
+                Some(addr) => addr,
+                None if symbol.binding() == STB_WEAK => 0,
+                None => {
+                    error!("module", "{}: unresolved symbol {}", module.info.name, name);
+                    return Err(ModuleError::UnresolvedSymbol);
+                }
+            },
+            SHN_ABS => symbol.value,
+            SHN_COMMON => return Err(ModuleError::InvalidObject),
+            shndx => match layout.sections.get(usize::from(shndx)) {
+                Some(Some(offset)) => base + *offset as u64 + symbol.value,
+                Some(None) => 0,
+                None => return Err(ModuleError::InvalidObject),
+            },
+        };
+
+        if symbol.shndx != SHN_UNDEF && symbol.binding() == STB_GLOBAL && value != 0 {
+            match name {
+                MODULE_INIT_SYMBOL => init = Some(value),
+                MODULE_EXIT_SYMBOL => module.exit = Some(value),
+                _ => module.exports.push((name.to_string(), value)),
+            }
+        }
+
+        symbols.push(value);
+    }
+
+    for index in 0..object.section_count() {
+        let section = object.section(index)?;
+
+        if section.sh_type != SHT_RELA {
+            continue;
+        }
+
+        let target = object.section(section.info as usize)?;
+        let Some(Some(target_offset)) = layout.sections.get(section.info as usize) else {
+            continue;
+        };
+
+        for rel_index in 0..object.entry_count::<Rela>(&section) {
+            let rela: Rela = object.entry(&section, rel_index)?;
+
+            if rela.offset >= target.size {
+                return Err(ModuleError::InvalidObject);
+            }
+
+            let offset = target_offset + rela.offset as usize;
+            let symbol = *symbols
+                .get(rela.symbol())
+                .ok_or(ModuleError::InvalidObject)?;
+
+            apply_relocation(
+                memory,
+                offset,
+                rela.rel_type(),
+                symbol.wrapping_add_signed(rela.addend),
+                base + offset as u64,
+            )?;
+        }
+    }
+
+    init.ok_or(ModuleError::MissingEntryPoint)
+}
+
+/// Applies a relocation at `offset`, where `value` is the symbol address plus the addend (`S + A`) and `place` is
+/// the address of the relocated field (`P`).
+fn apply_relocation(
+    memory: &mut [u8],
+    offset: usize,
+    rel_type: u32,
+    value: u64,
+    place: u64,
+) -> Result<(), ModuleError> {
+    let relative = value.wrapping_sub(place);
+
+    match rel_type {
+        R_X86_64_NONE => Ok(()),
+        R_X86_64_64 => write_field(memory, offset, &value.to_le_bytes()),
+        R_X86_64_PC64 => write_field(memory, offset, &relative.to_le_bytes()),
+        R_X86_64_PC32 | R_X86_64_PLT32 => {
+            let field =
+                i32::try_from(relative as i64).map_err(|_| ModuleError::RelocationOverflow)?;
+            write_field(memory, offset, &field.to_le_bytes())
+        }
+        R_X86_64_32 => {
+            let field = u32::try_from(value).map_err(|_| ModuleError::RelocationOverflow)?;
+            write_field(memory, offset, &field.to_le_bytes())
+        }
+        R_X86_64_32S => {
+            let field = i32::try_from(value as i64).map_err(|_| ModuleError::RelocationOverflow)?;
+            write_field(memory, offset, &field.to_le_bytes())
+        }
+        _ => Err(ModuleError::UnsupportedRelocation(rel_type)),
+    }
+}
+
+fn write_field(memory: &mut [u8], offset: usize, bytes: &[u8]) -> Result<(), ModuleError> {
+    memory
+        .get_mut(offset..offset + bytes.len())
+        .ok_or(ModuleError::InvalidObject)?
+        .copy_from_slice(bytes);
+
+    Ok(())
+}
+
+/// Resolves a symbol imported by a module, first against the Kernel symbols, then against the symbols exported by
+/// the loaded modules.
+///
+/// The module exporting the symbol is added to `dependencies`.
+fn resolve_import(name: &str, dependencies: &mut Vec<String>) -> Option<u64> {
+    if let Some(addr) = kernel_symbol(name) {
+        return Some(addr);
+    }
+
+    let modules = LOADED_MODULES.read();
+
+    for module in modules.iter() {
+        if let Some((_, addr)) = module.exports.iter().find(|(export, _)| export == name) {
+            if !dependencies.contains(&module.info.name) {
+                dependencies.push(module.info.name.clone());
+            }
+
+            return Some(*addr);
+        }
+    }
+
+    None
+}
+
+/// Reserves `size` bytes of virtual address space in the segment dedicated to modules.
+fn reserve_address_space(size: usize) -> Option<u64> {
+    let mut mapping_ptr = MODULES_MAPPING_PTR.lock();
+    let base = u64::from(KERNEL_MODULES_BASE) + *mapping_ptr;
+
+    if *mapping_ptr + size as u64 > KERNEL_MODULES_SIZE as u64 {
+        return None;
+    }
+
+    *mapping_ptr += size as u64;
+
+    Some(base)
+}
+
+/// Unmaps a module, and releases its physical memory.
+fn release_module(module: LoadedModule) {
+    unsafe {
+        get_memory_mapper()
+            .lock()
+            .unmap_physical_memory(module.info.base, module.info.size);
+    }
+
+    stats::uncharge(MemoryConsumer::Drivers, module.frames.length);
+    free_page(module.frames);
+}
+
+/// Prints a UTF-8 string of `len` bytes, on behalf of a module.
+///
+/// # Safety
+///
+/// `text` must be valid for reads of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn fz_module_print(text: *const u8, len: usize) {
+    if let Ok(text) = core::str::from_utf8(slice::from_raw_parts(text, len)) {
+        print(text);
+    }
+}
+
+/// Allocates `size` bytes from the Kernel heap, aligned on `align` bytes, on behalf of a module.
+///
+/// Returns a null pointer if the allocation failed.
+///
+/// # Safety
+///
+/// `size` must be non-zero.
+#[no_mangle]
+pub unsafe extern "C" fn fz_module_alloc(size: usize, align: usize) -> *mut u8 {
+    match Layout::from_size_align(size, align) {
+        Ok(layout) => alloc(layout),
+        Err(_) => core::ptr::null_mut(),
+    }
+}
+
+/// Releases memory allocated using [`fz_module_alloc`].
+///
+/// # Safety
+///
+/// `ptr` must have been returned by [`fz_module_alloc`], called with the same `size` and `align`.
+#[no_mangle]
+pub unsafe extern "C" fn fz_module_free(ptr: *mut u8, size: usize, align: usize) {
+    if let Ok(layout) = Layout::from_size_align(size, align) {
+        dealloc(ptr, layout);
+    }
+}
+
+export_symbol!(fz_module_print);
+export_symbol!(fz_module_alloc);
+export_symbol!(fz_module_free);
@@ -0,0 +1,108 @@
+//! Kernel symbol table.
+//!
+//! Kernel functions and statics that modules are allowed to use are exported using the [`export_symbol`] macro,
+//! which places an entry into the `.ksymtab` section of the Kernel image. The bounds of that section are provided by
+//! the linker script, using the `_ksymtab_start` and `_ksymtab_end` symbols.
+//!
+//! Exported items should use an unmangled name (`#[no_mangle]`), as modules import them by name.
+//!
+//! [`export_symbol`]: crate::export_symbol
+
+use core::mem::size_of;
+use core::slice;
+
+/// An entry of the Kernel symbol table.
+#[derive(Debug)]
+#[repr(C)]
+pub struct KernelSymbol {
+    name: &'static str,
+    addr: *const (),
+}
+
+// Symbol addresses are never dereferenced through the table.
+unsafe impl Sync for KernelSymbol {}
+
+impl KernelSymbol {
+    pub const fn new(name: &'static str, addr: *const ()) -> Self {
+        Self { name, addr }
+    }
+
+    /// Name under which the symbol is exported.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Address of the exported item.
+    pub fn addr(&self) -> u64 {
+        self.addr as u64
+    }
+}
+
+/// Exports a Kernel function or static, so that it can be imported by Kernel modules.
+///
+/// # Examples
+///
+/// ```
+/// #[no_mangle]
+/// pub extern "C" fn fz_example() {}
+///
+/// export_symbol!(fz_example);
+/// ```
+#[macro_export]
+macro_rules! export_symbol {
+    (static $sym: ident) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".ksymtab"]
+            static KSYM: $crate::module::symbols::KernelSymbol =
+                $crate::module::symbols::KernelSymbol::new(
+                    stringify!($sym),
+                    core::ptr::addr_of!($sym) as *const (),
+                );
+        };
+    };
+    ($sym: ident) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".ksymtab"]
+            static KSYM: $crate::module::symbols::KernelSymbol =
+                $crate::module::symbols::KernelSymbol::new(stringify!($sym), $sym as *const ());
+        };
+    };
+}
+
+/// Returns every symbol exported by the Kernel.
+pub fn kernel_symbols() -> &'static [KernelSymbol] {
+    extern "C" {
+        static _ksymtab_start: KernelSymbol;
+        static _ksymtab_end: KernelSymbol;
+    }
+
+    unsafe {
+        let start = core::ptr::addr_of!(_ksymtab_start);
+        let end = core::ptr::addr_of!(_ksymtab_end);
+        let len = (end as usize - start as usize) / size_of::<KernelSymbol>();
+
+        slice::from_raw_parts(start, len)
+    }
+}
+
+/// Returns the address of the symbol `name`, if it is exported by the Kernel.
+pub fn kernel_symbol(name: &str) -> Option<u64> {
+    kernel_symbols()
+        .iter()
+        .find(|symbol| symbol.name == name)
+        .map(KernelSymbol::addr)
+}
+
+/// Returns the exported symbol containing `addr` (the closest symbol at or below it), and the offset of `addr` from
+/// the start of that symbol.
+///
+/// Only exported symbols are known, so the result is approximate for addresses in non-exported functions.
+pub fn kernel_symbol_at(addr: u64) -> Option<(&'static KernelSymbol, u64)> {
+    kernel_symbols()
+        .iter()
+        .filter(|symbol| symbol.addr() <= addr)
+        .max_by_key(|symbol| symbol.addr())
+        .map(|symbol| (symbol, addr - symbol.addr()))
+}
@@ -0,0 +1,64 @@
+//! Panic reentrancy guard.
+//!
+//! The panic handlers format the panic message, draw on the framebuffer and write the crash log to the disk: any of
+//! these may panic (or fault) again. Panic handlers call [`panic_enter`] first, and divert to [`panic_nested`] if a
+//! panic is already being handled.
+//!
+//! [`panic_nested`] only writes the location of the nested panic to the first serial port, without waiting for any lock
+//! or allocating memory, and then halts (or terminates `QEMU`, see [`qemu_exit_on_panic`]).
+
+use core::fmt::{self, Write};
+use core::panic::Location;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::io::qemu_exit::qemu_exit_on_panic;
+use crate::io::serial::COM1;
+use crate::log::LOG_SERIAL_BAUD;
+use crate::x86::idle::halt_forever;
+
+/// Number of panics currently being handled.
+static PANIC_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Records that a panic is being handled, and returns the number of panics that were already being handled.
+///
+/// The panic handler may only proceed normally if this returns `0`: otherwise, it should call [`panic_nested`].
+pub fn panic_enter() -> usize {
+    PANIC_DEPTH.fetch_add(1, Ordering::AcqRel)
+}
+
+/// Checks whether a panic is being handled.
+pub fn panicking() -> bool {
+    PANIC_DEPTH.load(Ordering::Acquire) != 0
+}
+
+/// Handles a panic raised while handling another one: writes its `location` to the first serial port, and halts.
+///
+/// If this panics as well, the processor is halted without any output.
+pub fn panic_nested(location: Option<&Location>) -> ! {
+    // The first nested panic is counted by `panic_enter`, a third one means that this function panicked.
+    if PANIC_DEPTH.load(Ordering::Acquire) <= 2 {
+        match location {
+            Some(location) => raw_serial_write(format_args!("\nnested panic at {location}\n")),
+            None => raw_serial_write(format_args!("\nnested panic\n")),
+        }
+    }
+
+    qemu_exit_on_panic();
+    halt_forever();
+}
+
+/// Writes directly to the first serial port, initializing it if needed.
+///
+/// The lock of the port is forcibly released: the code holding it will never resume.
+fn raw_serial_write(args: fmt::Arguments) {
+    unsafe {
+        COM1.force_unlock();
+    }
+
+    let mut serial = COM1.lock();
+    if !serial.initialized() && serial.init(LOG_SERIAL_BAUD).is_err() {
+        return;
+    }
+
+    let _ = serial.write_fmt(args);
+}
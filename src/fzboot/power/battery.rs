@@ -0,0 +1,266 @@
+//! Battery and AC adapter status.
+//!
+//! The firmware describes each battery with two ACPI control methods: `_BIF`, which returns static information
+//! (capacities, technology, voltage), and `_BST`, which returns its current state (charging or discharging, rate,
+//! remaining capacity). AC adapters return whether they are online with `_PSR`.
+//!
+//! Evaluating these methods requires an AML interpreter, which is provided by a [`PowerSupply`] registered with
+//! [`register_power_supply`]. This module turns the packages they return into [`BatteryInfo`] and [`BatteryState`],
+//! and computes the values displayed to the user (see [`battery_report`]).
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::power::battery::battery_report;
+//!
+//! let mut report = String::new();
+//! battery_report(&mut report)?;
+//! ```
+
+use core::fmt::{self, Write};
+
+use conquer_once::spin::OnceCell;
+
+/// Value of an integer field of `_BIF` or `_BST` that is unknown.
+const ACPI_BATTERY_UNKNOWN: u64 = 0xFFFF_FFFF;
+
+/// Set in the `_BST` state if the battery is discharging.
+const BST_DISCHARGING: u64 = 1 << 0;
+
+/// Set in the `_BST` state if the battery is charging.
+const BST_CHARGING: u64 = 1 << 1;
+
+/// Set in the `_BST` state if the battery is critically low.
+const BST_CRITICAL: u64 = 1 << 2;
+
+static POWER_SUPPLY: OnceCell<&'static dyn PowerSupply> = OnceCell::uninit();
+
+/// Source of the power supply information of the platform.
+///
+/// Implemented on top of an AML interpreter, which evaluates the `_BIF`, `_BST` and `_PSR` methods of the devices.
+pub trait PowerSupply: Send + Sync {
+    /// Returns the integer fields of the package returned by `_BIF` for the `index`-th battery, or `None` if there
+    /// is no such battery.
+    ///
+    /// String fields are not used, and may be left out.
+    fn battery_info(&self, index: usize) -> Option<[u64; 9]>;
+
+    /// Returns the package returned by `_BST` for the `index`-th battery, or `None` if there is no such battery.
+    fn battery_state(&self, index: usize) -> Option<[u64; 4]>;
+
+    /// Returns the value returned by `_PSR` for the AC adapter, or `None` if there is no AC adapter.
+    fn ac_online(&self) -> Option<u64>;
+}
+
+/// Unit of the capacities and rates of a battery.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerUnit {
+    /// Capacities in mWh, rates in mW.
+    MilliWatt,
+
+    /// Capacities in mAh, rates in mA.
+    MilliAmp,
+}
+
+impl PowerUnit {
+    fn capacity_suffix(self) -> &'static str {
+        match self {
+            Self::MilliWatt => "mWh",
+            Self::MilliAmp => "mAh",
+        }
+    }
+
+    fn rate_suffix(self) -> &'static str {
+        match self {
+            Self::MilliWatt => "mW",
+            Self::MilliAmp => "mA",
+        }
+    }
+}
+
+/// Static information about a battery, returned by `_BIF`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatteryInfo {
+    pub unit: PowerUnit,
+
+    /// Nominal capacity of a new battery.
+    pub design_capacity: Option<u32>,
+
+    /// Capacity of the battery when it was last fully charged.
+    pub last_full_capacity: Option<u32>,
+
+    /// Whether the battery can be recharged.
+    pub rechargeable: bool,
+
+    /// Nominal voltage of a new battery, in mV.
+    pub design_voltage: Option<u32>,
+
+    /// Capacity under which the firmware warns the user.
+    pub warning_capacity: u32,
+
+    /// Capacity under which the firmware considers the battery critically low.
+    pub low_capacity: u32,
+}
+
+impl BatteryInfo {
+    /// Parses the integer fields of the package returned by `_BIF`.
+    pub fn from_bif(package: &[u64; 9]) -> Self {
+        Self {
+            unit: match package[0] {
+                1 => PowerUnit::MilliAmp,
+                _ => PowerUnit::MilliWatt,
+            },
+            design_capacity: known(package[1]),
+            last_full_capacity: known(package[2]),
+            rechargeable: package[3] == 1,
+            design_voltage: known(package[4]),
+            warning_capacity: known(package[5]).unwrap_or(0),
+            low_capacity: known(package[6]).unwrap_or(0),
+        }
+    }
+
+    /// Capacity of a full charge, compared to the nominal capacity of the battery, in percent.
+    pub fn health(&self) -> Option<u32> {
+        percent(self.last_full_capacity?, self.design_capacity?)
+    }
+}
+
+/// Whether a battery is charging.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChargeState {
+    Charging,
+    Discharging,
+
+    /// Neither charging nor discharging (for instance, fully charged while on AC power).
+    Idle,
+}
+
+/// Current state of a battery, returned by `_BST`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatteryState {
+    pub charge: ChargeState,
+
+    /// Whether the battery is critically low.
+    pub critical: bool,
+
+    /// Rate at which the battery is charged or discharged.
+    pub rate: Option<u32>,
+
+    /// Remaining capacity of the battery.
+    pub remaining_capacity: Option<u32>,
+
+    /// Voltage of the battery, in mV.
+    pub voltage: Option<u32>,
+}
+
+impl BatteryState {
+    /// Parses the package returned by `_BST`.
+    pub fn from_bst(package: &[u64; 4]) -> Self {
+        let charge = if package[0] & BST_CHARGING != 0 {
+            ChargeState::Charging
+        } else if package[0] & BST_DISCHARGING != 0 {
+            ChargeState::Discharging
+        } else {
+            ChargeState::Idle
+        };
+
+        Self {
+            charge,
+            critical: package[0] & BST_CRITICAL != 0,
+            rate: known(package[1]),
+            remaining_capacity: known(package[2]),
+            voltage: known(package[3]),
+        }
+    }
+
+    /// Charge level of the battery, in percent.
+    pub fn charge_level(&self, info: &BatteryInfo) -> Option<u32> {
+        let full = info.last_full_capacity.or(info.design_capacity)?;
+
+        percent(self.remaining_capacity?, full).map(|level| level.min(100))
+    }
+}
+
+/// Registers the source of the power supply information of the platform.
+///
+/// Only the first source registered is used.
+pub fn register_power_supply(supply: &'static dyn PowerSupply) {
+    let _ = POWER_SUPPLY.try_init_once(|| supply);
+}
+
+/// Returns the static information and the current state of the `index`-th battery.
+///
+/// Returns `None` if there is no such battery, or if no source of power supply information was registered.
+pub fn battery(index: usize) -> Option<(BatteryInfo, BatteryState)> {
+    let supply = POWER_SUPPLY.get()?;
+
+    let info = BatteryInfo::from_bif(&supply.battery_info(index)?);
+    let state = BatteryState::from_bst(&supply.battery_state(index)?);
+
+    Some((info, state))
+}
+
+/// Returns whether the AC adapter is online.
+///
+/// Returns `None` if there is no AC adapter, or if no source of power supply information was registered.
+pub fn ac_online() -> Option<bool> {
+    POWER_SUPPLY.get()?.ac_online().map(|psr| psr != 0)
+}
+
+/// Writes a human-readable summary of the state of the power supplies, one line per battery.
+pub fn battery_report(out: &mut impl Write) -> fmt::Result {
+    match ac_online() {
+        Some(true) => writeln!(out, "AC adapter: online")?,
+        Some(false) => writeln!(out, "AC adapter: offline")?,
+        None => writeln!(out, "AC adapter: unknown")?,
+    }
+
+    let mut index = 0;
+    while let Some((info, state)) = battery(index) {
+        write!(out, "battery {index}: ")?;
+
+        match state.charge_level(&info) {
+            Some(level) => write!(out, "{level}%")?,
+            None => write!(out, "unknown level")?,
+        }
+
+        write!(out, ", {:?}", state.charge)?;
+        if let Some(rate) = state.rate {
+            write!(out, " at {} {}", rate, info.unit.rate_suffix())?;
+        }
+        if let Some(full) = info.last_full_capacity {
+            write!(out, ", full = {} {}", full, info.unit.capacity_suffix())?;
+        }
+        if let Some(health) = info.health() {
+            write!(out, ", health = {health}%")?;
+        }
+        if state.critical {
+            write!(out, ", CRITICAL")?;
+        }
+        writeln!(out)?;
+
+        index += 1;
+    }
+
+    if index == 0 {
+        writeln!(out, "no battery")?;
+    }
+
+    Ok(())
+}
+
+/// Returns `value`, unless it is the marker of unknown fields.
+fn known(value: u64) -> Option<u32> {
+    match value {
+        ACPI_BATTERY_UNKNOWN => None,
+        value => u32::try_from(value).ok(),
+    }
+}
+
+fn percent(value: u32, total: u32) -> Option<u32> {
+    if total == 0 {
+        return None;
+    }
+
+    u32::try_from(u64::from(value) * 100 / u64::from(total)).ok()
+}
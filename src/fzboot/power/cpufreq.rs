@@ -0,0 +1,225 @@
+//! Processor frequency scaling.
+//!
+//! The processor runs in one of several performance states (_P-states_), each of them being a frequency / voltage
+//! pair. Lower P-states reduce the power consumption and the heat produced by the processor, which matters on real
+//! hardware when waiting for a long time (for instance, for user input in the bootloader).
+//!
+//! The P-states are described by the ACPI `_PSS` method of the processor, which requires an AML interpreter (see
+//! [`cpufreq_register_pss`]). Until then, they are derived from the ratios reported by `MSR_PLATFORM_INFO`, for each
+//! multiple of the 100 MHz bus clock between the maximum efficiency ratio and the maximum non-turbo ratio.
+//!
+//! A P-state is requested by writing its control value to `IA32_PERF_CTL`, as done by [`cpufreq_set_policy`]. Only
+//! the current processor is affected.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::power::cpufreq::{cpufreq_init, cpufreq_set_policy, CpuFreqPolicy};
+//!
+//! cpufreq_init()?;
+//!
+//! cpufreq_set_policy(CpuFreqPolicy::Powersave)?;
+//! wait_for_input();
+//! cpufreq_set_policy(CpuFreqPolicy::Performance)?;
+//! ```
+
+use alloc::vec::Vec;
+
+use spin::Mutex;
+
+use crate::errors::{CanFail, CpuFreqError};
+use crate::info;
+use crate::power::thermal::{register_throttle_callback, thermal_throttled};
+use crate::x86::cpuid::{cpu_feature_support, CPU_FEAT_EIST};
+use crate::x86::errata::{CpuSignature, CpuVendor};
+use crate::x86::msr::{msr_read, msr_write};
+
+const IA32_PERF_STATUS: u32 = 0x198;
+const IA32_PERF_CTL: u32 = 0x199;
+const IA32_MISC_ENABLE: u32 = 0x1A0;
+const MSR_PLATFORM_INFO: u32 = 0xCE;
+
+/// Set in `IA32_MISC_ENABLE` if the firmware enabled Enhanced Intel SpeedStep.
+const MISC_ENABLE_EIST: u64 = 1 << 16;
+
+/// Frequency of the bus clock, multiplied by the ratios of the P-states.
+const BUS_CLOCK_MHZ: u32 = 100;
+
+/// Available P-states, from the fastest to the slowest.
+static PSTATES: Mutex<Vec<PState>> = Mutex::new(Vec::new());
+
+/// Policy selected with [`cpufreq_set_policy`].
+static POLICY: Mutex<CpuFreqPolicy> = Mutex::new(CpuFreqPolicy::Performance);
+
+/// Performance state of the processor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PState {
+    /// Core frequency, in MHz.
+    pub frequency_mhz: u32,
+
+    /// Typical power dissipation, in mW, if known.
+    pub power_mw: Option<u32>,
+
+    /// Value written to `IA32_PERF_CTL` to request this state.
+    pub control: u64,
+}
+
+impl PState {
+    /// Parses an entry of the package returned by `_PSS`.
+    pub fn from_pss(entry: &[u64; 6]) -> Self {
+        Self {
+            frequency_mhz: entry[0] as u32,
+            power_mw: Some(entry[1] as u32),
+            control: entry[4],
+        }
+    }
+
+    /// P-state of the processor at a given bus ratio.
+    fn from_ratio(ratio: u32) -> Self {
+        Self {
+            frequency_mhz: ratio * BUS_CLOCK_MHZ,
+            power_mw: None,
+            control: u64::from(ratio) << 8,
+        }
+    }
+}
+
+/// Performance policy of the processor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuFreqPolicy {
+    /// Runs at the fastest P-state.
+    Performance,
+
+    /// Runs at the slowest P-state.
+    Powersave,
+}
+
+/// Detects the P-states of the processor, and switches to the slowest one when the system is throttled by the
+/// thermal monitoring.
+///
+/// # Errors
+///
+/// Returns [`CpuFreqError::NotSupported`] if the processor is not an Intel processor supporting Enhanced Intel
+/// SpeedStep, or if the firmware disabled it.
+pub fn cpufreq_init() -> CanFail<CpuFreqError> {
+    let intel = CpuSignature::current().is_some_and(|cpu| cpu.vendor == CpuVendor::Intel);
+    if !intel || cpu_feature_support(CPU_FEAT_EIST) != Some(true) {
+        return Err(CpuFreqError::NotSupported);
+    }
+
+    let misc_enable = msr_read(IA32_MISC_ENABLE).ok_or(CpuFreqError::NotSupported)?;
+    if misc_enable & MISC_ENABLE_EIST == 0 {
+        return Err(CpuFreqError::NotSupported);
+    }
+
+    let platform_info = msr_read(MSR_PLATFORM_INFO).ok_or(CpuFreqError::NotSupported)?;
+    let max_ratio = ((platform_info >> 8) & 0xFF) as u32;
+    let min_ratio = ((platform_info >> 40) & 0xFF) as u32;
+
+    if min_ratio == 0 || min_ratio > max_ratio {
+        return Err(CpuFreqError::NotSupported);
+    }
+
+    {
+        let mut pstates = PSTATES.lock();
+        if pstates.is_empty() {
+            pstates.extend((min_ratio..=max_ratio).rev().map(PState::from_ratio));
+        }
+    }
+
+    register_throttle_callback(cpufreq_throttle);
+
+    info!(
+        "cpufreq",
+        "{} P-states ({} - {} MHz), running at {:?} MHz",
+        PSTATES.lock().len(),
+        min_ratio * BUS_CLOCK_MHZ,
+        max_ratio * BUS_CLOCK_MHZ,
+        cpufreq_current_mhz()
+    );
+
+    Ok(())
+}
+
+/// Replaces the P-states derived from the processor ratios with the ones described by the ACPI `_PSS` method.
+///
+/// Entries must be ordered from the fastest to the slowest, as returned by `_PSS`.
+pub fn cpufreq_register_pss(entries: &[[u64; 6]]) {
+    let mut pstates = PSTATES.lock();
+
+    pstates.clear();
+    pstates.extend(entries.iter().map(PState::from_pss));
+}
+
+/// Returns the available P-states, from the fastest to the slowest.
+pub fn cpufreq_pstates() -> Vec<PState> {
+    PSTATES.lock().clone()
+}
+
+/// Returns the current frequency of the processor, in MHz.
+pub fn cpufreq_current_mhz() -> Option<u32> {
+    let status = msr_read(IA32_PERF_STATUS)?;
+
+    Some(((status >> 8) & 0xFF) as u32 * BUS_CLOCK_MHZ)
+}
+
+/// Requests the `index`-th P-state (0 being the fastest).
+///
+/// # Errors
+///
+/// Returns [`CpuFreqError::InvalidState`] if there is no such P-state.
+pub fn cpufreq_set_pstate(index: usize) -> CanFail<CpuFreqError> {
+    let pstate = *PSTATES
+        .lock()
+        .get(index)
+        .ok_or(CpuFreqError::InvalidState)?;
+
+    let perf_ctl = msr_read(IA32_PERF_CTL).ok_or(CpuFreqError::NotSupported)?;
+
+    // Only the target state field is replaced.
+    unsafe {
+        msr_write(
+            IA32_PERF_CTL,
+            (perf_ctl & !0xFFFF) | (pstate.control & 0xFFFF),
+        );
+    }
+
+    Ok(())
+}
+
+/// Selects the performance policy of the processor.
+///
+/// While the system is throttled, the slowest P-state is used regardless of the policy, which is applied again once
+/// the temperature is back to normal.
+///
+/// # Errors
+///
+/// Returns [`CpuFreqError::InvalidState`] if no P-state was detected.
+pub fn cpufreq_set_policy(policy: CpuFreqPolicy) -> CanFail<CpuFreqError> {
+    *POLICY.lock() = policy;
+
+    if thermal_throttled() {
+        return Ok(());
+    }
+
+    apply_policy(policy)
+}
+
+fn apply_policy(policy: CpuFreqPolicy) -> CanFail<CpuFreqError> {
+    let index = match policy {
+        CpuFreqPolicy::Performance => 0,
+        CpuFreqPolicy::Powersave => PSTATES.lock().len().saturating_sub(1),
+    };
+
+    cpufreq_set_pstate(index)
+}
+
+/// Thermal throttling callback.
+fn cpufreq_throttle(throttled: bool) {
+    let policy = match throttled {
+        true => CpuFreqPolicy::Powersave,
+        false => *POLICY.lock(),
+    };
+
+    let _ = apply_policy(policy);
+}
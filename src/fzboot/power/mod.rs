@@ -0,0 +1,11 @@
+//! Platform power management.
+//!
+//! Reports the state of the power supplies of the platform (batteries and AC adapters), monitors its temperature,
+//! controls the frequency of the processor, and resets the system.
+
+pub mod battery;
+#[cfg(feature = "alloc")]
+pub mod cpufreq;
+pub mod reboot;
+#[cfg(feature = "alloc")]
+pub mod thermal;
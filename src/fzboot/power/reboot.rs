@@ -0,0 +1,124 @@
+//! System reset.
+//!
+//! No single reset method works on every system: the keyboard controller is not emulated by some hypervisors and
+//! is missing on recent hardware, while the ACPI reset register is optional. [`reboot`] tries each method of
+//! [`REBOOT_CHAIN`] in turn, waiting for a short while after each attempt before moving to the next one:
+//!
+//! - the ACPI reset register, described by the `FADT` (requires ACPI to be initialized)
+//! - the 8042 keyboard controller, pulsing the CPU reset line
+//! - a triple fault, which always resets the processor
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::power::reboot::reboot;
+//!
+//! reboot();
+//! ```
+
+use core::arch::asm;
+use core::fmt::{self, Display};
+
+use crate::io::acpi::fadt::fadt;
+use crate::io::io_delay;
+use crate::io::ps2::{input_wait, read_ps2_status, send_ps2};
+use crate::warn;
+use crate::x86::int::disable_interrupts;
+
+/// 8042 command pulsing the output line connected to the CPU reset pin.
+const PS2_CMD_PULSE_RESET: u8 = 0xFE;
+
+/// Number of status polls while waiting for the keyboard controller to accept a command.
+const PS2_WAIT_LOOPS: u16 = 0xFFFF;
+
+/// Time given to each method to reset the system, in `io_delay` iterations (roughly 1µs each).
+const REBOOT_ATTEMPT_DELAY: u32 = 500_000;
+
+/// Reset methods tried by [`reboot`], in order.
+pub const REBOOT_CHAIN: [RebootMethod; 3] = [
+    RebootMethod::Acpi,
+    RebootMethod::KeyboardController,
+    RebootMethod::TripleFault,
+];
+
+/// A method used to reset the system.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RebootMethod {
+    /// Write to the reset register described by the ACPI `FADT`.
+    Acpi,
+
+    /// Pulse of the CPU reset line through the 8042 keyboard controller.
+    KeyboardController,
+
+    /// Exception raised without a valid `IDT`, which shuts the processor down.
+    TripleFault,
+}
+
+impl Display for RebootMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Acpi => f.write_str("acpi"),
+            Self::KeyboardController => f.write_str("kbc"),
+            Self::TripleFault => f.write_str("triple fault"),
+        }
+    }
+}
+
+/// Resets the system, trying every method of [`REBOOT_CHAIN`] until one of them works.
+///
+/// Can be called from the panic handlers: nothing is allocated, and no lock is held while waiting.
+pub fn reboot() -> ! {
+    disable_interrupts();
+
+    for method in REBOOT_CHAIN {
+        if try_reboot(method) {
+            reboot_wait();
+            warn!("reboot", "{} reset did not take effect", method);
+        }
+    }
+
+    // A triple fault cannot fail to reset the processor.
+    unreachable!()
+}
+
+/// Attempts to reset the system using `method`.
+///
+/// Returns `false` if the method is not available on this system. If it returns `true`, the system should reset
+/// shortly after.
+pub fn try_reboot(method: RebootMethod) -> bool {
+    match method {
+        RebootMethod::Acpi => fadt().is_some_and(|fadt| fadt.reset()),
+        RebootMethod::KeyboardController => {
+            // the status port floats high when no controller is present
+            if read_ps2_status() == 0xFF || input_wait(PS2_WAIT_LOOPS).is_err() {
+                return false;
+            }
+
+            send_ps2(PS2_CMD_PULSE_RESET);
+            true
+        }
+        RebootMethod::TripleFault => triple_fault(),
+    }
+}
+
+/// Loads an empty `IDT` and raises an exception: the processor cannot deliver it (nor the resulting double fault),
+/// and shuts down.
+fn triple_fault() -> ! {
+    // limit and base of the IDT, for both 32-bit and 64-bit modes
+    let empty_idt = [0u16; 5];
+
+    unsafe {
+        asm!(
+            "lidt [{}]",
+            "int3",
+            in(reg) empty_idt.as_ptr(),
+            options(noreturn)
+        );
+    }
+}
+
+fn reboot_wait() {
+    for _ in 0..REBOOT_ATTEMPT_DELAY {
+        io_delay();
+    }
+}
@@ -0,0 +1,334 @@
+//! Thermal monitoring.
+//!
+//! Each thermal zone reports its temperature, and up to two trip points, as the ACPI thermal zone methods do:
+//!
+//! - `_TMP`: current temperature.
+//! - `_PSV`: passive trip point. Above it, the system must reduce its activity (_passive cooling_): the registered
+//!   throttling callbacks are called (see [`register_throttle_callback`]), and called again once every zone cooled
+//!   down below its passive trip point.
+//! - `_CRT`: critical trip point. Above it, the system is shut down immediately to protect the hardware.
+//!
+//! ACPI thermal zones require an AML interpreter to evaluate these methods, and are registered as [`ThermalSensor`]s
+//! with [`register_thermal_zone`]. The digital thermal sensor of the processor is always registered when available
+//! (see [`CpuThermalSensor`]).
+//!
+//! Zones are polled periodically by the timer queue once [`thermal_init`] was called. The poll runs from the timer
+//! interrupt, which may interrupt code holding the lock of the kernel log: nothing is logged from there. The current
+//! temperatures are available through [`thermal_temperatures`], and the throttling state through
+//! [`thermal_throttled`].
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::power::thermal::{register_throttle_callback, thermal_init};
+//!
+//! fn throttle(enabled: bool) {
+//!     BUSY_POLLING.store(!enabled, Ordering::Relaxed);
+//! }
+//!
+//! thermal_init();
+//! register_throttle_callback(throttle);
+//! ```
+
+use alloc::vec::Vec;
+use core::fmt::{self, Display};
+use core::time::Duration;
+
+use conquer_once::spin::OnceCell;
+use spin::Mutex;
+
+use crate::time::timer;
+use crate::x86::cpuid::cpu_id;
+use crate::x86::errata::{CpuSignature, CpuVendor};
+use crate::x86::msr::msr_read;
+use crate::{error, info};
+
+/// Period at which the thermal zones are polled.
+const THERMAL_POLL_PERIOD: Duration = Duration::from_secs(1);
+
+/// Temperature drop below the passive trip point required to stop throttling, so that the throttling state does not
+/// oscillate around the trip point.
+const THERMAL_PASSIVE_HYSTERESIS: DeciKelvin = DeciKelvin(50);
+
+/// `CPUID` leaf describing the thermal and power management features.
+const CPUID_THERMAL_LEAF: u32 = 0x06;
+
+/// Digital temperature sensor supported (leaf 6, `EAX`).
+const CPUID_THERMAL_DTS: u32 = 1 << 0;
+
+/// Package thermal management supported (leaf 6, `EAX`).
+const CPUID_THERMAL_PTM: u32 = 1 << 6;
+
+const IA32_THERM_STATUS: u32 = 0x19C;
+const IA32_PACKAGE_THERM_STATUS: u32 = 0x1B1;
+const MSR_TEMPERATURE_TARGET: u32 = 0x1A2;
+
+/// Set in the thermal status of the core if the digital readout is valid.
+const THERM_STATUS_READING_VALID: u64 = 1 << 31;
+
+/// Passive trip point of the processor, below its maximum junction temperature.
+const CPU_PASSIVE_MARGIN: DeciKelvin = DeciKelvin(100);
+
+static THERMAL_ZONES: Mutex<Vec<ThermalZone>> = Mutex::new(Vec::new());
+
+static THROTTLE_CALLBACKS: Mutex<Vec<fn(bool)>> = Mutex::new(Vec::new());
+
+/// Whether the system is currently throttled.
+static THROTTLED: Mutex<bool> = Mutex::new(false);
+
+/// Temperature, in tenths of Kelvin (the unit used by the ACPI thermal zone methods).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DeciKelvin(pub u32);
+
+impl DeciKelvin {
+    /// 0°C.
+    const ZERO_CELSIUS: u32 = 2732;
+
+    pub const fn from_celsius(celsius: u32) -> Self {
+        Self(Self::ZERO_CELSIUS + celsius * 10)
+    }
+
+    /// Temperature in tenths of degrees Celsius.
+    pub fn deci_celsius(self) -> i64 {
+        i64::from(self.0) - i64::from(Self::ZERO_CELSIUS)
+    }
+}
+
+impl Display for DeciKelvin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let deci_celsius = self.deci_celsius();
+        let sign = if deci_celsius < 0 { "-" } else { "" };
+
+        write!(
+            f,
+            "{}{}.{}°C",
+            sign,
+            deci_celsius.abs() / 10,
+            deci_celsius.abs() % 10
+        )
+    }
+}
+
+/// Source of the temperature and trip points of a thermal zone.
+pub trait ThermalSensor: Send + Sync {
+    /// Name of the zone, used in logs.
+    fn name(&self) -> &str;
+
+    /// Current temperature of the zone (`_TMP`), or `None` if it cannot be read.
+    fn temperature(&self) -> Option<DeciKelvin>;
+
+    /// Temperature above which the system must be throttled (`_PSV`).
+    fn passive_trip(&self) -> Option<DeciKelvin>;
+
+    /// Temperature above which the system must be shut down (`_CRT`).
+    fn critical_trip(&self) -> Option<DeciKelvin>;
+}
+
+#[derive(Clone, Copy)]
+struct ThermalZone {
+    sensor: &'static dyn ThermalSensor,
+
+    /// Whether the zone is above its passive trip point.
+    throttling: bool,
+}
+
+/// Digital thermal sensor of the processor.
+///
+/// The sensor reports the distance to the maximum junction temperature (`TjMax`), which is the critical trip point.
+/// The package sensor is used when available (`CPUID.06H:EAX[6]`), otherwise the sensor of the current core.
+///
+/// The package status has no validity bit: a null readout would otherwise be taken as `TjMax`. Readings are only used
+/// while the readout of the core is valid, and the sensor is not registered if it is not valid when detected.
+#[derive(Clone, Copy, Debug)]
+pub struct CpuThermalSensor {
+    status_msr: u32,
+    tj_max: DeciKelvin,
+}
+
+impl CpuThermalSensor {
+    /// Returns the digital thermal sensor of the processor, if it has one.
+    ///
+    /// Only Intel processors are supported.
+    pub fn detect() -> Option<Self> {
+        if CpuSignature::current()?.vendor != CpuVendor::Intel {
+            return None;
+        }
+
+        let features = cpu_id(CPUID_THERMAL_LEAF)?[0];
+        if features & CPUID_THERMAL_DTS == 0 {
+            return None;
+        }
+
+        let status_msr = match features & CPUID_THERMAL_PTM {
+            0 => IA32_THERM_STATUS,
+            _ => IA32_PACKAGE_THERM_STATUS,
+        };
+
+        let tj_max = (msr_read(MSR_TEMPERATURE_TARGET)? >> 16) & 0xFF;
+        if tj_max == 0 {
+            return None;
+        }
+
+        if msr_read(IA32_THERM_STATUS)? & THERM_STATUS_READING_VALID == 0 {
+            return None;
+        }
+
+        Some(Self {
+            status_msr,
+            tj_max: DeciKelvin::from_celsius(tj_max as u32),
+        })
+    }
+}
+
+impl ThermalSensor for CpuThermalSensor {
+    fn name(&self) -> &str {
+        "cpu"
+    }
+
+    fn temperature(&self) -> Option<DeciKelvin> {
+        let core_status = msr_read(IA32_THERM_STATUS)?;
+        if core_status & THERM_STATUS_READING_VALID == 0 {
+            return None;
+        }
+
+        let status = match self.status_msr {
+            IA32_THERM_STATUS => core_status,
+            status_msr => msr_read(status_msr)?,
+        };
+
+        // Number of degrees below `TjMax`.
+        let readout = ((status >> 16) & 0x7F) as u32;
+
+        Some(DeciKelvin(self.tj_max.0.saturating_sub(readout * 10)))
+    }
+
+    fn passive_trip(&self) -> Option<DeciKelvin> {
+        Some(DeciKelvin(self.tj_max.0 - CPU_PASSIVE_MARGIN.0))
+    }
+
+    fn critical_trip(&self) -> Option<DeciKelvin> {
+        Some(self.tj_max)
+    }
+}
+
+/// Registers the processor thermal sensor (if available), and starts polling the thermal zones periodically.
+///
+/// Requires the timer queue.
+pub fn thermal_init() {
+    static CPU_SENSOR: OnceCell<CpuThermalSensor> = OnceCell::uninit();
+
+    if let Some(sensor) = CpuThermalSensor::detect() {
+        if CPU_SENSOR.try_init_once(|| sensor).is_ok() {
+            register_thermal_zone(CPU_SENSOR.get().unwrap());
+        }
+    }
+
+    if timer::periodic(THERMAL_POLL_PERIOD, thermal_poll).is_err() {
+        error!(
+            "thermal",
+            "timer queue unavailable, thermal zones are not monitored"
+        );
+        return;
+    }
+
+    info!(
+        "thermal",
+        "monitoring {} thermal zones",
+        THERMAL_ZONES.lock().len()
+    );
+}
+
+/// Registers a thermal zone, polled along with the others.
+pub fn register_thermal_zone(sensor: &'static dyn ThermalSensor) {
+    let passive = sensor.passive_trip();
+    let critical = sensor.critical_trip();
+
+    info!(
+        "thermal",
+        "zone {}: passive = {:?}    critical = {:?}",
+        sensor.name(),
+        passive.map(|trip| trip.deci_celsius() / 10),
+        critical.map(|trip| trip.deci_celsius() / 10)
+    );
+
+    THERMAL_ZONES.lock().push(ThermalZone {
+        sensor,
+        throttling: false,
+    });
+}
+
+/// Registers a callback, called with `true` when a zone goes above its passive trip point, and with `false` once
+/// every zone is back below its passive trip point.
+///
+/// Callbacks are called from the timer interrupt.
+pub fn register_throttle_callback(callback: fn(bool)) {
+    THROTTLE_CALLBACKS.lock().push(callback);
+}
+
+/// Returns whether a zone is above its passive trip point.
+pub fn thermal_throttled() -> bool {
+    *THROTTLED.lock()
+}
+
+/// Returns the current temperature of each thermal zone.
+pub fn thermal_temperatures() -> Vec<(&'static str, Option<DeciKelvin>)> {
+    THERMAL_ZONES
+        .lock()
+        .iter()
+        .map(|zone| (zone.sensor.name(), zone.sensor.temperature()))
+        .collect()
+}
+
+/// Reads the temperature of each thermal zone, and applies the actions required by their trip points.
+///
+/// Called from the timer interrupt: nothing is logged from here.
+fn thermal_poll() {
+    let mut throttling = false;
+
+    for zone in THERMAL_ZONES.lock().iter_mut() {
+        let Some(temperature) = zone.sensor.temperature() else {
+            continue;
+        };
+
+        if let Some(critical) = zone.sensor.critical_trip() {
+            if temperature >= critical {
+                thermal_emergency_shutdown(zone.sensor.name(), temperature);
+            }
+        }
+
+        if let Some(passive) = zone.sensor.passive_trip() {
+            let resume = DeciKelvin(passive.0.saturating_sub(THERMAL_PASSIVE_HYSTERESIS.0));
+
+            zone.throttling = match zone.throttling {
+                false => temperature >= passive,
+                true => temperature > resume,
+            };
+        }
+
+        throttling |= zone.throttling;
+    }
+
+    let mut throttled = THROTTLED.lock();
+    if *throttled == throttling {
+        return;
+    }
+    *throttled = throttling;
+    drop(throttled);
+
+    let callbacks = THROTTLE_CALLBACKS.lock().clone();
+    for callback in callbacks {
+        callback(throttling);
+    }
+}
+
+/// Stops the system, after a zone reached its critical trip point.
+///
+/// Powering off requires the ACPI sleep states, which are not implemented: the processors are halted instead, which
+/// stops most of the heat dissipation. This goes through the panic path, which halts the other processors and
+/// reports the zone without requiring the lock of the kernel log.
+fn thermal_emergency_shutdown(zone: &str, temperature: DeciKelvin) -> ! {
+    panic!(
+        "thermal zone {} reached its critical temperature ({}), emergency shutdown",
+        zone, temperature
+    );
+}
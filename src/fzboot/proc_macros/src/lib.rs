@@ -12,9 +12,17 @@ use syn::{parse_macro_input, Ident, ItemFn};
 struct InterruptHandlerMacroParam {
     int_vector: Option<u16>,
     exception: Option<bool>,
+    eoi: Option<bool>,
 }
 
 /// Generates a wrapper for static interrupt handlers.
+///
+/// On `x86_64`, exception handlers (`exception = true`) may return a `u64`: if it is not 0, execution resumes at this
+/// address rather than at the faulting instruction (used to recover from faults on expected accesses).
+///
+/// The end of interrupt is only signaled for interrupts: exceptions are not delivered by an interrupt controller. The
+/// handlers of exceptions that do not push an error code (such as non-maskable interrupts or machine checks) use
+/// `eoi = false` instead.
 #[proc_macro_attribute]
 pub fn interrupt_handler(
     args: proc_macro::TokenStream,
@@ -30,15 +38,18 @@ pub fn interrupt_handler(
     let InterruptHandlerMacroParam {
         int_vector,
         exception,
+        eoi,
     } = match InterruptHandlerMacroParam::from_list(&attr_args) {
         Ok(p) => p,
         Err(_) => InterruptHandlerMacroParam {
             int_vector: None,
             exception: None,
+            eoi: None,
         },
     };
 
     let is_exception = exception.unwrap_or_default();
+    let sends_eoi = !is_exception && eoi.unwrap_or(true);
 
     let ItemFn {
         attrs: _,
@@ -55,6 +66,9 @@ pub fn interrupt_handler(
     }
 
     let fn_body = &block.stmts;
+    let output = &sig.output;
+    #[cfg(feature = "x86_64")]
+    let resumes = is_exception && !matches!(output, syn::ReturnType::Default);
 
     let wrapped_fn_name = format!("__int_handler_wrapped_{}", sig.ident.to_string());
     let wrapped_fn_ident = Ident::new(wrapped_fn_name.as_str(), Span::mixed_site());
@@ -62,7 +76,7 @@ pub fn interrupt_handler(
         quote! {
             #[no_mangle]
             #[link_section = ".int"]
-            pub extern "C" fn #wrapped_fn_ident (frame: crate::fzboot::irq::ExceptionStackFrame) {
+            pub extern "C" fn #wrapped_fn_ident (frame: crate::fzboot::irq::ExceptionStackFrame) #output {
                 #(#fn_body)*
             }
         }
@@ -76,24 +90,40 @@ pub fn interrupt_handler(
         }
     };
 
+    // Exceptions are not signaled through an interrupt controller: only interrupts are acknowledged.
+    let eoi = if sends_eoi { "call _pic_eoi" } else { "" };
+
     #[cfg(not(feature = "x86_64"))]
     // Define wrapper assembly
     let wrapper = format!(
-        "pushad
+        "cld
+                pushad
                 call _int_entry
                 call {}
-                call _pic_eoi
+                {}
                 popad
                 iretd",
-        wrapped_fn_name
+        wrapped_fn_name, eoi
     );
 
     #[cfg(feature = "x86_64")]
     // Define wrapper assembly
     // TODO: save registers ?
     let wrapper = if is_exception {
+        // the address returned by the handler replaces the saved RIP
+        let resume = if resumes {
+            "
+        test rax, rax
+        jz 2f
+        mov [rbp + 0x80], rax
+        2:"
+        } else {
+            ""
+        };
+
         format!(
             "
+        cld
         push r15
         push r14
         push r13
@@ -123,8 +153,7 @@ pub fn interrupt_handler(
         mov rax, [rbp + 0x78]
         push rax
         mov rdi, rsp
-        call {}
-        call _pic_eoi
+        call {}{}
         add rsp, 0x30
         pop rax
         pop rbx
@@ -141,12 +170,14 @@ pub fn interrupt_handler(
         pop r13
         pop r14
         pop r15
+        add rsp, 0x8
         iretq",
-            wrapped_fn_name
+            wrapped_fn_name, resume
         )
     } else {
         format!(
             "
+        cld
         push r15
         push r14
         push r13
@@ -175,7 +206,7 @@ pub fn interrupt_handler(
         push rax
         mov rdi, rsp
         call {}
-        call _pic_eoi
+        {}
         add rsp, 0x28
         pop rax
         pop rbx
@@ -193,7 +224,7 @@ pub fn interrupt_handler(
         pop r14
         pop r15
         iretq",
-            wrapped_fn_name
+            wrapped_fn_name, eoi
         )
     };
 
@@ -235,7 +266,8 @@ pub fn generate_runtime_handlers_wrapper(_item: TokenStream) -> TokenStream {
 
         #[cfg(not(feature = "x86_64"))]
         let wrapper = format!(
-            "pushad
+            "cld
+            pushad
             call _int_entry
             call {}
             call _pic_eoi
@@ -247,6 +279,7 @@ pub fn generate_runtime_handlers_wrapper(_item: TokenStream) -> TokenStream {
         #[cfg(feature = "x86_64")]
         let wrapper = format!(
             "
+            cld
             push r15
             push r14
             push r13
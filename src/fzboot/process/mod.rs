@@ -10,8 +10,9 @@ use spin::{Mutex, RwLock};
 use thread::{Thread, ThreadFlags, ThreadGroup, ThreadId, THREAD_REGISTRY};
 
 use crate::{
+    ipc::HandleTable,
     kernel_syms::{KERNEL_PAGE_TABLE, PAGE_SIZE},
-    mem::{MemoryAddress, PhyAddr, VirtAddr},
+    mem::{vma::AddressSpace, MemoryAddress, VirtAddr},
     x86::paging::{page_alloc::frame_alloc::alloc_page, PageTable},
 };
 
@@ -31,12 +32,11 @@ pub fn init_kernel_process() {
         name: String::from("system"),
         threads: ThreadGroup::new_empty(),
         parent: None,
-        page_table: PhyAddr::NULL_PTR,
+        address_space: AddressSpace::new(KERNEL_PAGE_TABLE),
+        handles: HandleTable::new(),
         flags: ProcessFlags::default(),
     };
 
-    kernel_process.page_table = KERNEL_PAGE_TABLE;
-
     kernel_process
         .threads
         .insert_thread(ThreadId::KERNEL_INIT_TID);
@@ -71,13 +71,7 @@ pub fn init_kernel_process() {
 }
 
 pub fn get_process(process_id: ProcessId) -> Option<Arc<Mutex<Process>>> {
-    unsafe {
-        PROCESS_REGISTRY
-            .get_unchecked()
-            .read()
-            .get(&process_id)
-            .cloned()
-    }
+    PROCESS_REGISTRY.get()?.read().get(&process_id).cloned()
 }
 
 #[no_mangle]
@@ -114,7 +108,8 @@ pub struct Process {
     pub(crate) name: String,
     threads: ThreadGroup,
     parent: Option<ProcessId>,
-    page_table: PhyAddr,
+    pub(crate) address_space: AddressSpace,
+    pub(crate) handles: HandleTable,
     pub(crate) flags: ProcessFlags,
 }
 
@@ -138,7 +133,8 @@ impl Process {
             name: String::default(),
             threads: ThreadGroup::new_empty(),
             parent: None,
-            page_table: process_page_table_addr,
+            address_space: AddressSpace::new(process_page_table_addr),
+            handles: HandleTable::new(),
             flags: flags,
         }));
 
@@ -100,9 +100,9 @@ impl Thread {
     /// When first created, threads are usually not directly scheduled and therefore won't run until registered in the
     /// global system scheduler.
     pub fn schedule(&self) {
-        get_global_scheduler()
-            .lock()
-            .schedule_sys_task(self.task.lock().id);
+        let task_id = self.task.lock().id;
+
+        get_global_scheduler().lock().schedule_sys_task(task_id);
     }
 
     fn spawn_thread(
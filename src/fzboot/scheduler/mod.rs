@@ -1,22 +1,30 @@
+use core::arch::asm;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 use conquer_once::spin::OnceCell;
 use fzproc_macros::interrupt_handler;
 use queue::TaskQueue;
 use spin::Mutex;
-use strategies::round_robin::{RoundRobinMetadata, RoundRobinScheduling};
-use task::{get_tasks, TaskId, TaskState, CURRENT_TASK_ID};
+use strategies::priority::{
+    PriorityMetadata, PriorityScheduling, SchedulingClass, NICE_MAX, NICE_MIN,
+};
+use task::{
+    current_task_id, get_task, get_tasks, TaskAccounting, TaskId, TaskState, CURRENT_TASK_ID,
+};
 
 use crate::{
     error,
     irq::_pic_eoi,
     x86::{
         apic::InterruptVector,
-        int::{disable_interrupts, enable_interrupts},
+        idle::cpu_idle,
+        int::{disable_interrupts, enable_interrupts, interrupts_disabled},
+        tsc::{TSCClock, TSC_CLK},
     },
 };
 
 use super::{
+    exceptions::watchdog::watchdog_touch,
     irq::{manager::get_interrupt_manager, InterruptStackFrame},
     process::{
         get_process,
@@ -37,8 +45,13 @@ pub static CURRENT_THREAD_ID: AtomicUsize = AtomicUsize::new(0);
 
 static FAILED_SCHEDULING: AtomicUsize = AtomicUsize::new(0);
 
+/// Interrupt vector of the scheduler timer, also raised by software to yield the CPU.
+const SCHEDULER_VECTOR: u8 = 0x20;
+
 #[interrupt_handler]
 pub fn timer_irq_entry(frame: InterruptStackFrame) {
+    watchdog_touch();
+
     if let Some(mut scheduler) = get_global_scheduler().try_lock() {
         let current_process = ProcessId::new(CURRENT_PROCESS_ID.load(Ordering::Relaxed));
         if let Some(process) = get_process(current_process) {
@@ -73,7 +86,8 @@ pub fn timer_irq_entry(frame: InterruptStackFrame) {
 }
 
 pub fn init_global_scheduler() {
-    get_interrupt_manager().register_static_handler(InterruptVector::new(0x20), timer_irq_entry);
+    get_interrupt_manager()
+        .register_static_handler(InterruptVector::new(SCHEDULER_VECTOR), timer_irq_entry);
     get_global_scheduler()
         .lock()
         .schedule_sys_task(TaskId::new(0))
@@ -93,8 +107,176 @@ pub fn current_process_id() -> ProcessId {
     CURRENT_PROCESS_ID.load(Ordering::Relaxed).into()
 }
 
+/// Checks if the global scheduler was initialized (see [`init_global_scheduler`]).
+pub fn scheduler_running() -> bool {
+    GLOBAL_SCHEDULER.is_initialized()
+}
+
+/// Removes the current [`Task`] from the run queue, until it is woken up using [`wake_task`].
+///
+/// The task keeps running until it yields the CPU (see [`yield_now`]), or until it is preempted. Returns `None` if
+/// the scheduler is not running.
+pub fn block_current_task() -> Option<TaskId> {
+    if !scheduler_running() {
+        return None;
+    }
+
+    let task_id = current_task_id();
+    let task = get_task(task_id)?;
+
+    without_interrupts(|| {
+        let mut scheduler = get_global_scheduler().lock();
+        let mut task = task.lock();
+
+        task.state = TaskState::Blocked;
+        task.accounting.block(tsc_read());
+        scheduler.kernel_queue.remove_task(task_id);
+    });
+
+    Some(task_id)
+}
+
+/// Puts a [`Task`] blocked using [`block_current_task`] back into the run queue.
+///
+/// Nothing is done if the task is not blocked. May be called from an interrupt handler.
+pub fn wake_task(task_id: TaskId) {
+    let Some(task) = get_task(task_id) else {
+        return;
+    };
+
+    without_interrupts(|| {
+        let mut scheduler = get_global_scheduler().lock();
+        let mut task = task.lock();
+
+        if matches!(task.state, TaskState::Blocked) {
+            task.state = TaskState::Waiting;
+            task.accounting.wake(tsc_read());
+            scheduler.schedule_task(PriorityMetadata::new(task_id, task.sched_class, task.nice));
+        }
+    });
+}
+
+/// Checks if a [`Task`] is blocked, waiting to be woken up.
+pub fn task_blocked(task_id: TaskId) -> bool {
+    get_task(task_id).is_some_and(|task| matches!(task.lock().state, TaskState::Blocked))
+}
+
+/// Gives up the CPU, so that another [`Task`] can be scheduled.
+///
+/// Returns immediately if no other task is ready to run.
+pub fn yield_now() {
+    if !scheduler_running() {
+        return;
+    }
+
+    // The scheduler is entered as if the timer had fired, so that the state of the task is saved the same way.
+    unsafe {
+        asm!("int {}", const SCHEDULER_VECTOR);
+    }
+}
+
+/// Turns the current [`Task`] into the idle task.
+///
+/// The idle task only runs when no other task is ready, and puts the CPU into a low-power state until the next
+/// interrupt (see [`cpu_idle`]).
+pub fn idle_task() -> ! {
+    set_scheduling_class(current_task_id(), SchedulingClass::Idle);
+
+    loop {
+        disable_interrupts();
+        cpu_idle();
+
+        // The interrupt may have woken a task up.
+        yield_now();
+    }
+}
+
+/// Changes the [`SchedulingClass`] of a [`Task`].
+///
+/// Returns `false` if the task does not exist.
+pub fn set_scheduling_class(task_id: TaskId, class: SchedulingClass) -> bool {
+    update_task_priority(task_id, |task| task.sched_class = class)
+}
+
+/// Changes the nice value of a [`Task`], clamped between [`NICE_MIN`] and [`NICE_MAX`].
+///
+/// Tasks with a lower nice value get a larger share of CPU time than other tasks of the [`SchedulingClass::Normal`]
+/// class. Returns `false` if the task does not exist.
+pub fn set_nice(task_id: TaskId, nice: i8) -> bool {
+    update_task_priority(task_id, |task| task.nice = nice.clamp(NICE_MIN, NICE_MAX))
+}
+
+/// Adds `increment` to the nice value of the current [`Task`], and returns the new nice value.
+///
+/// # Examples
+///
+/// ```
+/// use fzboot::scheduler::nice;
+///
+/// // This driver thread polls a device, and should not slow the console down.
+/// nice(10);
+/// ```
+pub fn nice(increment: i8) -> i8 {
+    let task_id = current_task_id();
+    let mut nice = 0;
+
+    update_task_priority(task_id, |task| {
+        task.nice = task
+            .nice
+            .saturating_add(increment)
+            .clamp(NICE_MIN, NICE_MAX);
+        nice = task.nice;
+    });
+
+    nice
+}
+
+/// Returns the CPU time and sleep/wake statistics of a [`Task`].
+pub fn task_accounting(task_id: TaskId) -> Option<TaskAccounting> {
+    get_task(task_id).map(|task| without_interrupts(|| task.lock().accounting))
+}
+
+/// Applies `update` to a [`Task`], and queues it again (if it is queued) so that the change takes effect.
+fn update_task_priority(task_id: TaskId, update: impl FnOnce(&mut task::Task)) -> bool {
+    let Some(task) = get_task(task_id) else {
+        return false;
+    };
+
+    without_interrupts(|| {
+        let mut scheduler = get_global_scheduler().lock();
+        let mut task = task.lock();
+
+        update(&mut task);
+
+        if scheduler.kernel_queue.contains(task_id) {
+            scheduler.schedule_task(PriorityMetadata::new(task_id, task.sched_class, task.nice));
+        }
+    });
+
+    true
+}
+
+/// Reads the TSC, or returns 0 if it is not available.
+fn tsc_read() -> u64 {
+    TSC_CLK.get().map_or(0, TSCClock::tsc_read)
+}
+
+/// Runs `f` with interrupts disabled, so that locks shared with interrupt handlers can be held.
+fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    let were_disabled = interrupts_disabled();
+    disable_interrupts();
+
+    let result = f();
+
+    if !were_disabled {
+        enable_interrupts();
+    }
+
+    result
+}
+
 pub struct GlobalScheduler {
-    kernel_queue: TaskQueue<RoundRobinMetadata, RoundRobinScheduling>,
+    kernel_queue: TaskQueue<PriorityMetadata, PriorityScheduling>,
     count: usize,
 }
 
@@ -106,14 +288,34 @@ impl GlobalScheduler {
         }
     }
 
+    /// Schedules a [`Task`] for execution, using its current [`SchedulingClass`] and nice value.
     pub fn schedule_sys_task(&mut self, task_id: TaskId) {
-        self.kernel_queue
-            .queue_task(RoundRobinMetadata::new(task_id))
+        let (class, nice) = get_task(task_id).map_or((SchedulingClass::default(), 0), |task| {
+            let task = task.lock();
+            (task.sched_class, task.nice)
+        });
+
+        self.schedule_task(PriorityMetadata::new(task_id, class, nice))
+    }
+
+    pub fn schedule_task(&mut self, metadata: PriorityMetadata) {
+        self.kernel_queue.queue_task(metadata)
     }
 
     pub fn irq_schedule_next_task(&mut self, frame: InterruptStackFrame) {
-        let next_task_id = self.kernel_queue.next_task();
         let current_task_id = TaskId::new(CURRENT_TASK_ID.load(Ordering::Relaxed));
+        let tsc = tsc_read();
+
+        // The interrupted code may hold the lock of the current task, in which case its CPU time is charged later.
+        if let Some(mut current_task) = get_task(current_task_id)
+            .as_ref()
+            .and_then(|task| task.try_lock())
+        {
+            let elapsed = current_task.accounting.charge(tsc);
+            self.kernel_queue.account(current_task_id, elapsed);
+        }
+
+        let next_task_id = self.kernel_queue.next_task();
 
         match next_task_id {
             Some(next_task_id) => {
@@ -129,7 +331,10 @@ impl GlobalScheduler {
                 if let Some(current_task_locked) = current_task_locked {
                     let mut current_task = current_task_locked.lock();
 
-                    current_task.state = TaskState::Waiting;
+                    // Blocked tasks stay blocked until they are woken up.
+                    if !matches!(current_task.state, TaskState::Blocked) {
+                        current_task.state = TaskState::Waiting;
+                    }
                     current_task.gpr = frame.registers;
                     current_task.rip = frame.rip;
                     current_task.stack = frame.stack_ptr;
@@ -145,6 +350,7 @@ impl GlobalScheduler {
                 if !matches!(next_task.state, TaskState::Uninitialized(_)) {
                     next_task.state = TaskState::Running;
                 }
+                next_task.accounting.switch_in(tsc);
 
                 let new_task_frame = InterruptStackFrame {
                     rip: next_task.rip.into(),
@@ -25,4 +25,16 @@ impl<M: TaskSchedulingMetadata, Q: SchedulingStrategy<M>> TaskQueue<M, Q> {
     pub fn queue_task(&mut self, task_metadata: M) {
         self.strategy.insert_task(task_metadata)
     }
+
+    pub fn remove_task(&mut self, task_id: TaskId) {
+        self.strategy.remove_task(task_id)
+    }
+
+    pub fn contains(&self, task_id: TaskId) -> bool {
+        self.strategy.contains(task_id)
+    }
+
+    pub fn account(&mut self, task_id: TaskId, cycles: u64) {
+        self.strategy.account(task_id, cycles)
+    }
 }
@@ -1,5 +1,6 @@
 use super::task::TaskId;
 
+pub mod priority;
 pub mod round_robin;
 
 pub trait SchedulingStrategy<M: TaskSchedulingMetadata> {
@@ -8,6 +9,10 @@ pub trait SchedulingStrategy<M: TaskSchedulingMetadata> {
     fn size(&self) -> usize;
     fn insert_task(&mut self, _: M);
     fn remove_task(&mut self, id: TaskId);
+    fn contains(&self, id: TaskId) -> bool;
+
+    /// Charges `cycles` of CPU time (in TSC cycles) to a task.
+    fn account(&mut self, _id: TaskId, _cycles: u64) {}
 }
 
 pub trait TaskSchedulingMetadata {}
@@ -0,0 +1,164 @@
+//! Priority-based scheduling.
+//!
+//! Tasks belong to one of three [`SchedulingClass`]es:
+//!
+//! - _real-time_ tasks always run before any other task, in a round-robin fashion. They must block (or yield) on
+//!   their own, otherwise lower classes never run.
+//! - _normal_ tasks share the CPU fairly, based on their _nice_ value: each task accumulates a virtual runtime, that
+//!   grows slower for tasks with a lower nice value, and the task with the lowest virtual runtime runs first.
+//! - _idle_ tasks only run when no other task is ready.
+//!
+//! A task that was blocked for a while does not get to run until it catches up with the virtual runtime of the
+//! other tasks: its virtual runtime is raised to the lowest virtual runtime of the queue when it is woken up.
+
+use alloc::collections::vec_deque::VecDeque;
+use alloc::vec::Vec;
+
+use crate::scheduler::task::TaskId;
+
+use super::{SchedulingStrategy, TaskSchedulingMetadata};
+
+/// Highest priority nice value.
+pub const NICE_MIN: i8 = -20;
+
+/// Lowest priority nice value.
+pub const NICE_MAX: i8 = 19;
+
+/// Weight of a task with a nice value of 0.
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// Weight of each nice value, from [`NICE_MIN`] to [`NICE_MAX`].
+///
+/// Each nice level is worth about 10% of CPU time relative to the adjacent level.
+const NICE_WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+/// Scheduling class of a task.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SchedulingClass {
+    /// Runs before any other task.
+    RealTime,
+
+    /// Shares the CPU with other normal tasks, according to their nice value.
+    #[default]
+    Normal,
+
+    /// Only runs when no other task is ready.
+    Idle,
+}
+
+pub struct PriorityScheduling {
+    realtime: VecDeque<PriorityMetadata>,
+    normal: Vec<PriorityMetadata>,
+    idle: VecDeque<PriorityMetadata>,
+
+    /// Lowest virtual runtime of the normal tasks that were scheduled.
+    min_vruntime: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PriorityMetadata {
+    task_id: TaskId,
+    class: SchedulingClass,
+    nice: i8,
+    vruntime: u64,
+}
+
+impl PriorityMetadata {
+    pub fn new(task_id: TaskId, class: SchedulingClass, nice: i8) -> Self {
+        Self {
+            task_id,
+            class,
+            nice: nice.clamp(NICE_MIN, NICE_MAX),
+            vruntime: 0,
+        }
+    }
+
+    fn weight(&self) -> u64 {
+        NICE_WEIGHTS[(self.nice - NICE_MIN) as usize]
+    }
+}
+
+impl TaskSchedulingMetadata for PriorityMetadata {}
+
+impl SchedulingStrategy<PriorityMetadata> for PriorityScheduling {
+    fn next_task(&mut self) -> Option<TaskId> {
+        if let Some(metadata) = self.realtime.pop_front() {
+            let task_id = metadata.task_id;
+            self.realtime.push_back(metadata);
+            return Some(task_id);
+        }
+
+        if let Some(metadata) = self.normal.iter().min_by_key(|metadata| metadata.vruntime) {
+            self.min_vruntime = self.min_vruntime.max(metadata.vruntime);
+            return Some(metadata.task_id);
+        }
+
+        let metadata = self.idle.pop_front()?;
+        let task_id = metadata.task_id;
+        self.idle.push_back(metadata);
+
+        Some(task_id)
+    }
+
+    fn size(&self) -> usize {
+        self.realtime.len() + self.normal.len() + self.idle.len()
+    }
+
+    fn insert_task(&mut self, mut metadata: PriorityMetadata) {
+        // A task queued again (after a change of its nice value) keeps its virtual runtime.
+        let previous_vruntime = self
+            .normal
+            .iter()
+            .find(|queued| queued.task_id == metadata.task_id)
+            .map_or(metadata.vruntime, |queued| queued.vruntime);
+        self.remove_task(metadata.task_id);
+
+        match metadata.class {
+            SchedulingClass::RealTime => self.realtime.push_back(metadata),
+            SchedulingClass::Normal => {
+                metadata.vruntime = previous_vruntime.max(self.min_vruntime);
+                self.normal.push(metadata);
+            }
+            SchedulingClass::Idle => self.idle.push_back(metadata),
+        }
+    }
+
+    fn remove_task(&mut self, id: TaskId) {
+        self.realtime.retain(|metadata| metadata.task_id != id);
+        self.normal.retain(|metadata| metadata.task_id != id);
+        self.idle.retain(|metadata| metadata.task_id != id);
+    }
+
+    fn contains(&self, id: TaskId) -> bool {
+        self.realtime
+            .iter()
+            .chain(self.normal.iter())
+            .chain(self.idle.iter())
+            .any(|metadata| metadata.task_id == id)
+    }
+
+    fn account(&mut self, id: TaskId, cycles: u64) {
+        if let Some(metadata) = self
+            .normal
+            .iter_mut()
+            .find(|metadata| metadata.task_id == id)
+        {
+            metadata.vruntime = metadata
+                .vruntime
+                .saturating_add(cycles.saturating_mul(NICE_0_WEIGHT) / metadata.weight());
+        }
+    }
+
+    fn init() -> Self {
+        Self {
+            realtime: VecDeque::new(),
+            normal: Vec::new(),
+            idle: VecDeque::new(),
+            min_vruntime: 0,
+        }
+    }
+}
@@ -41,15 +41,14 @@ impl SchedulingStrategy<RoundRobinMetadata> for RoundRobinScheduling {
     }
 
     fn remove_task(&mut self, id: TaskId) {
-        match self
-            .task_queue
-            .binary_search(&RoundRobinMetadata { task_id: id })
-        {
-            Ok(idx) => {
-                self.task_queue.remove(idx);
-            }
-            Err(_) => (),
-        }
+        // Tasks are queued in scheduling order, not by identifier.
+        self.task_queue.retain(|metadata| metadata.task_id != id);
+    }
+
+    fn contains(&self, id: TaskId) -> bool {
+        self.task_queue
+            .iter()
+            .any(|metadata| metadata.task_id == id)
     }
 
     fn init() -> Self {
@@ -1,6 +1,7 @@
 use core::{
     arch::{asm, naked_asm},
     sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
 };
 
 use alloc::{collections::btree_map::BTreeMap, sync::Arc};
@@ -10,9 +11,11 @@ use spin::{rwlock::RwLock, Mutex};
 use crate::{
     mem::{stack::get_kernel_stack_allocator, MemoryAddress, VirtAddr},
     process::{get_process, thread::ThreadId, Process, ProcessId},
-    x86::registers::x86_64::GeneralPurposeRegisters,
+    x86::{registers::x86_64::GeneralPurposeRegisters, tsc::TSC_CLK},
 };
 
+use super::strategies::priority::SchedulingClass;
+
 type LockedTaskTree = RwLock<BTreeMap<TaskId, Arc<Mutex<Task>>>>;
 
 static TASKS: OnceCell<LockedTaskTree> = OnceCell::uninit();
@@ -83,6 +86,9 @@ pub struct Task {
     pub(crate) pid: ProcessId,
     pub(crate) tid: ThreadId,
     pub(crate) state: TaskState,
+    pub(crate) sched_class: SchedulingClass,
+    pub(crate) nice: i8,
+    pub(crate) accounting: TaskAccounting,
     pub(super) kernel_stack: VirtAddr,
     pub(super) stack: VirtAddr,
     pub(super) rip: VirtAddr,
@@ -125,6 +131,73 @@ impl Task {
     }
 }
 
+/// CPU time and sleep/wake statistics of a [`Task`], measured in TSC cycles.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TaskAccounting {
+    /// CPU time used by the task.
+    pub cpu_cycles: u64,
+
+    /// Time spent blocked, waiting to be woken up.
+    pub blocked_cycles: u64,
+
+    /// Number of times the task was scheduled for execution.
+    pub switches: u64,
+
+    /// Number of times the task was woken up after blocking.
+    pub wakeups: u64,
+
+    /// TSC value when the task was last scheduled.
+    last_switch_in: u64,
+
+    /// TSC value when the task last blocked.
+    blocked_since: u64,
+}
+
+impl TaskAccounting {
+    /// Returns the CPU time used by the task.
+    ///
+    /// Returns a zero duration if the TSC clock is not initialized.
+    pub fn cpu_time(&self) -> Duration {
+        cycles_to_duration(self.cpu_cycles)
+    }
+
+    /// Returns the time the task spent blocked.
+    pub fn blocked_time(&self) -> Duration {
+        cycles_to_duration(self.blocked_cycles)
+    }
+
+    /// Records that the task was scheduled at `tsc`.
+    pub(super) fn switch_in(&mut self, tsc: u64) {
+        self.last_switch_in = tsc;
+        self.switches += 1;
+    }
+
+    /// Charges the CPU time used since the task was scheduled (or last charged), and returns it.
+    pub(super) fn charge(&mut self, tsc: u64) -> u64 {
+        let elapsed = tsc.saturating_sub(self.last_switch_in);
+
+        self.cpu_cycles += elapsed;
+        self.last_switch_in = tsc;
+
+        elapsed
+    }
+
+    pub(super) fn block(&mut self, tsc: u64) {
+        self.blocked_since = tsc;
+    }
+
+    pub(super) fn wake(&mut self, tsc: u64) {
+        self.blocked_cycles += tsc.saturating_sub(self.blocked_since);
+        self.wakeups += 1;
+    }
+}
+
+fn cycles_to_duration(cycles: u64) -> Duration {
+    TSC_CLK.get().map_or(Duration::ZERO, |tsc| {
+        Duration::from_secs_f64(cycles as f64 / tsc.frequency())
+    })
+}
+
 #[derive(Debug)]
 struct TaskStateSnapshot {
     gpr: GeneralPurposeRegisters,
@@ -142,6 +215,11 @@ pub enum TaskState {
     /// The [`Task`] is waiting to be scheduled for execution.
     Waiting,
 
+    /// The [`Task`] is waiting for an event (see [`WaitQueue`]), and is not scheduled until it is woken up.
+    ///
+    /// [`WaitQueue`]: crate::sync::waitqueue::WaitQueue
+    Blocked,
+
     /// This [`Task`] is new and never got any CPU time allocated.
     Uninitialized(VirtAddr),
 }
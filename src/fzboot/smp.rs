@@ -0,0 +1,391 @@
+//! Cross-processor function calls.
+//!
+//! A function is run on another processor by posting it in the call slot of that processor, and sending it an `IPI`
+//! on [`SMP_CALL_VECTOR`]: the handler runs the function posted in the slot of the current processor, and signals its
+//! completion. [`call_on`] and [`call_all`] wait for the function to complete on every target processor (for at most
+//! [`SMP_CALL_TIMEOUT`]), so that it can safely be used for TLB shootdowns, or to stop the other processors.
+//!
+//! Only processors marked as online receive the calls: the bootstrap processor is marked by [`smp_init`], each
+//! application processor must call [`smp_cpu_online`] once its local APIC and `IDT` are set up.
+//!
+//! While waiting for a call to complete, the calling processor runs the function posted in its own slot: two
+//! processors calling each other with interrupts disabled do not deadlock.
+//!
+//! When the kernel panics, [`smp_stop_others`] halts the other processors with an `NMI` (delivered even if they are
+//! stuck with interrupts disabled), so that they do not keep modifying memory while the panic report is written. Each
+//! processor saves its register state before halting, which is then added to the report (see [`stopped_cpus`]).
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::smp::call_all;
+//!
+//! fn flush_tlb() {
+//!     // ...
+//! }
+//!
+//! call_all(flush_tlb)?;
+//! ```
+
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use fzproc_macros::interrupt_handler;
+use spin::Mutex;
+
+use crate::{
+    errors::SmpError,
+    irq::{manager::get_interrupt_manager, InterruptStackFrame},
+    time::clocksource::current_clocksource,
+    x86::{
+        apic::local_apic::{local_apic, IPIDestinationShorthand, InterruptVector, IPI},
+        idle::halt_forever,
+    },
+};
+
+pub use crate::x86::apic::local_apic::ProcLocalApicID;
+
+/// Interrupt vector of the `IPIs` used to signal a pending call.
+pub const SMP_CALL_VECTOR: InterruptVector = InterruptVector::new(0xF0);
+
+/// Maximum time given to the target processors to run a function.
+pub const SMP_CALL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Number of polls of the call slots after which a call times out, if no clock source is available.
+const SMP_CALL_MAX_POLLS: u64 = 100_000_000;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const CPU_OFFLINE: AtomicBool = AtomicBool::new(false);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const CALL_SLOT_INIT: CallSlot = CallSlot::new();
+
+#[allow(clippy::declare_interior_mutable_const)]
+const CPU_STATE_NONE: Mutex<Option<InterruptStackFrame>> = Mutex::new(None);
+
+/// Value of a call slot while the posted function runs: the slot is neither free nor claimable.
+const CALL_RUNNING: usize = usize::MAX;
+
+/// Value of a call slot while a function is being posted: the slot is neither free nor claimable.
+const CALL_POSTING: usize = usize::MAX - 1;
+
+/// No processor requested the others to stop.
+const NO_STOP_REQUEST: u16 = u16::MAX;
+
+/// Processors receiving the calls, by local APIC identifier.
+static ONLINE_CPUS: [AtomicBool; 256] = [CPU_OFFLINE; 256];
+
+/// Call slot of each processor, by local APIC identifier.
+static CALL_SLOTS: [CallSlot; 256] = [CALL_SLOT_INIT; 256];
+
+/// Local APIC identifier of the processor that stopped the others, or [`NO_STOP_REQUEST`].
+static STOP_REQUESTER: AtomicU16 = AtomicU16::new(NO_STOP_REQUEST);
+
+/// Register state of each stopped processor, by local APIC identifier.
+static STOPPED_CPU_STATES: [Mutex<Option<InterruptStackFrame>>; 256] = [CPU_STATE_NONE; 256];
+
+struct CallSlot {
+    /// Address of the function waiting to be run on the processor, [`CALL_POSTING`] while it is posted,
+    /// [`CALL_RUNNING`] while it runs, or 0 if the slot is free.
+    func: AtomicUsize,
+
+    /// Sequence number of the last function posted in the slot.
+    sequence: AtomicU64,
+
+    /// Sequence number of the last function run by the processor.
+    completed: AtomicU64,
+}
+
+impl CallSlot {
+    const fn new() -> Self {
+        Self {
+            func: AtomicUsize::new(0),
+            sequence: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+        }
+    }
+
+    fn get(cpu: ProcLocalApicID) -> &'static Self {
+        &CALL_SLOTS[usize::from(u8::from(cpu))]
+    }
+
+    /// Posts `func` in this slot, once the previous call completed.
+    ///
+    /// Returns the sequence number of this call, or `None` if the slot stayed busy for [`SMP_CALL_TIMEOUT`].
+    fn post(&self, func: fn()) -> Option<u64> {
+        let reserved = wait_until(|| {
+            self.func
+                .compare_exchange(0, CALL_POSTING, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        });
+
+        if !reserved {
+            return None;
+        }
+
+        // no other processor posts in, nor runs from the slot until `func` is stored
+        let sequence = self.sequence.load(Ordering::Relaxed) + 1;
+        self.sequence.store(sequence, Ordering::Relaxed);
+        self.func.store(func as usize, Ordering::Release);
+
+        Some(sequence)
+    }
+
+    /// Runs the function posted in this slot, if any.
+    ///
+    /// The slot is claimed before the function runs: if the call `IPI` interrupts the processor while it already
+    /// runs the function from [`wait_until`], the handler finds the slot claimed and does not run it a second time.
+    fn run(&self) {
+        let func = self.func.load(Ordering::Acquire);
+        if func == 0 || func == CALL_POSTING || func == CALL_RUNNING {
+            return;
+        }
+
+        if self
+            .func
+            .compare_exchange(func, CALL_RUNNING, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+
+        let sequence = self.sequence.load(Ordering::Relaxed);
+
+        // only function pointers are ever stored in the slot
+        let func: fn() = unsafe { core::mem::transmute::<usize, fn()>(func) };
+        func();
+
+        self.completed.store(sequence, Ordering::Release);
+        self.func.store(0, Ordering::Release);
+    }
+
+    /// Whether the call with the sequence number `sequence` has completed.
+    fn completed(&self, sequence: u64) -> bool {
+        self.completed.load(Ordering::Acquire) >= sequence
+    }
+}
+
+/// Registers the handler of the call `IPIs`, and marks the current (bootstrap) processor as online.
+///
+/// # Errors
+///
+/// Returns [`SmpError::VectorUnavailable`] if [`SMP_CALL_VECTOR`] is already in use.
+pub fn smp_init() -> Result<(), SmpError> {
+    let int_mgr = get_interrupt_manager();
+
+    int_mgr
+        .reserve_vector(SMP_CALL_VECTOR, "smp-call")
+        .map_err(|_| SmpError::VectorUnavailable)?;
+
+    if int_mgr
+        .register_static_handler(SMP_CALL_VECTOR, smp_call_handler)
+        .is_err()
+    {
+        int_mgr.free_vector(SMP_CALL_VECTOR);
+        return Err(SmpError::VectorUnavailable);
+    }
+
+    smp_cpu_online();
+
+    Ok(())
+}
+
+/// Marks the current processor as online: it receives the calls from now on.
+pub fn smp_cpu_online() {
+    ONLINE_CPUS[usize::from(u8::from(ProcLocalApicID::get()))].store(true, Ordering::Release);
+}
+
+/// Whether the processor `cpu` receives the calls.
+pub(crate) fn cpu_online(cpu: ProcLocalApicID) -> bool {
+    ONLINE_CPUS[usize::from(u8::from(cpu))].load(Ordering::Acquire)
+}
+
+/// Returns the local APIC identifier of every online processor.
+pub fn online_cpus() -> impl Iterator<Item = ProcLocalApicID> {
+    (0..=u8::MAX)
+        .map(ProcLocalApicID::from)
+        .filter(|&cpu| cpu_online(cpu))
+}
+
+/// Runs `func` on the processor `cpu`, and waits for it to complete.
+///
+/// If `cpu` is the current processor, `func` is called directly. Otherwise, it runs from an interrupt handler on the
+/// target processor: it must not block, nor take a lock that may be held by the interrupted code.
+///
+/// # Errors
+///
+/// Returns [`SmpError::CpuOffline`] if `cpu` is not online, [`SmpError::NoLocalApic`] if the local APIC of the current
+/// processor is not available, or [`SmpError::Timeout`] if `func` did not complete within [`SMP_CALL_TIMEOUT`].
+pub fn call_on(cpu: ProcLocalApicID, func: fn()) -> Result<(), SmpError> {
+    if cpu == ProcLocalApicID::get() {
+        func();
+        return Ok(());
+    }
+
+    if !cpu_online(cpu) {
+        return Err(SmpError::CpuOffline);
+    }
+
+    let lapic = local_apic().ok_or(SmpError::NoLocalApic)?;
+    let slot = CallSlot::get(cpu);
+    let sequence = slot.post(func).ok_or(SmpError::Timeout)?;
+
+    lapic.dispatch_ipi(IPI::std_int(
+        SMP_CALL_VECTOR,
+        IPIDestinationShorthand::NoShorthand,
+        u8::from(cpu),
+    ));
+
+    if !wait_until(|| slot.completed(sequence)) {
+        return Err(SmpError::Timeout);
+    }
+
+    Ok(())
+}
+
+/// Runs `func` on every online processor (including the current one), and waits for it to complete everywhere.
+///
+/// The same restrictions as [`call_on`] apply to `func`.
+///
+/// # Errors
+///
+/// Returns [`SmpError::NoLocalApic`] if the local APIC of the current processor is not available, or
+/// [`SmpError::Timeout`] if `func` did not complete on every processor within [`SMP_CALL_TIMEOUT`]. `func` still ran
+/// on the current processor.
+pub fn call_all(func: fn()) -> Result<(), SmpError> {
+    let current = ProcLocalApicID::get();
+    let mut sequences: [Option<u64>; 256] = [None; 256];
+    let mut posted_all = true;
+
+    if online_cpus().any(|cpu| cpu != current) {
+        let lapic = local_apic().ok_or(SmpError::NoLocalApic)?;
+
+        for cpu in online_cpus().filter(|&cpu| cpu != current) {
+            let sequence = CallSlot::get(cpu).post(func);
+            posted_all &= sequence.is_some();
+            sequences[usize::from(u8::from(cpu))] = sequence;
+        }
+
+        lapic.dispatch_ipi(IPI::broadcast_others_std_int(SMP_CALL_VECTOR));
+    }
+
+    func();
+
+    let completed = wait_until(|| {
+        (0..=u8::MAX).zip(sequences).all(|(cpu, sequence)| {
+            sequence.map_or(true, |sequence| {
+                CallSlot::get(ProcLocalApicID::from(cpu)).completed(sequence)
+            })
+        })
+    });
+
+    if !completed || !posted_all {
+        return Err(SmpError::Timeout);
+    }
+
+    Ok(())
+}
+
+/// Halts every other online processor, and waits until they saved their register state (for at most
+/// [`SMP_CALL_TIMEOUT`]).
+///
+/// Used by the panic handlers: the other processors never resume. Returns the number of processors that did not stop
+/// in time.
+pub fn smp_stop_others() -> usize {
+    let current = ProcLocalApicID::get();
+
+    if STOP_REQUESTER
+        .compare_exchange(
+            NO_STOP_REQUEST,
+            u16::from(u8::from(current)),
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        )
+        .is_err()
+    {
+        // another processor is stopping everyone, including this one
+        return 0;
+    }
+
+    let others = || online_cpus().filter(move |&cpu| cpu != current);
+    if others().next().is_none() {
+        return 0;
+    }
+
+    let Some(lapic) = local_apic() else {
+        return others().count();
+    };
+    lapic.dispatch_ipi(IPI::broadcast_others_nmi());
+
+    let stopped = |cpu: ProcLocalApicID| {
+        STOPPED_CPU_STATES[usize::from(u8::from(cpu))]
+            .try_lock()
+            .is_some_and(|state| state.is_some())
+    };
+
+    wait_until(|| others().all(stopped));
+
+    others().filter(|&cpu| !stopped(cpu)).count()
+}
+
+/// Returns the register state saved by each processor halted by [`smp_stop_others`].
+pub(crate) fn stopped_cpus() -> impl Iterator<Item = (ProcLocalApicID, InterruptStackFrame)> {
+    online_cpus().filter_map(|cpu| {
+        let state = STOPPED_CPU_STATES[usize::from(u8::from(cpu))].try_lock()?;
+
+        state.map(|frame| (cpu, frame))
+    })
+}
+
+/// Halts the current processor if another one requested it with [`smp_stop_others`], after saving the register
+/// state found in the `frame` of the `NMI`.
+///
+/// Returns without doing anything otherwise.
+pub(crate) fn smp_stop_nmi(frame: &InterruptStackFrame) {
+    let requester = STOP_REQUESTER.load(Ordering::Acquire);
+    let current = ProcLocalApicID::get();
+
+    if requester == NO_STOP_REQUEST || requester == u16::from(u8::from(current)) {
+        return;
+    }
+
+    *STOPPED_CPU_STATES[usize::from(u8::from(current))].lock() = Some(*frame);
+
+    // `NMIs` stay blocked until the handler returns: nothing can wake the processor up
+    halt_forever();
+}
+
+/// Runs the function posted for the current processor.
+#[interrupt_handler]
+pub fn smp_call_handler(frame: InterruptStackFrame) {
+    CallSlot::get(ProcLocalApicID::get()).run();
+}
+
+/// Polls `cond` until it holds, for at most [`SMP_CALL_TIMEOUT`]. Returns `false` on timeout.
+///
+/// Calls posted for the current processor are run while waiting.
+fn wait_until(mut cond: impl FnMut() -> bool) -> bool {
+    let clock = current_clocksource();
+    let start = clock.read();
+    let own_slot = CallSlot::get(ProcLocalApicID::get());
+
+    for poll in 0.. {
+        if cond() {
+            return true;
+        }
+
+        own_slot.run();
+
+        let timed_out = match (start, clock.read()) {
+            (Some(start), Some(now)) => now - start >= SMP_CALL_TIMEOUT.as_micros() as f64,
+            _ => poll >= SMP_CALL_MAX_POLLS,
+        };
+        if timed_out {
+            return false;
+        }
+
+        core::hint::spin_loop();
+    }
+
+    false
+}
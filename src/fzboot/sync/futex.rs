@@ -0,0 +1,71 @@
+//! Futexes.
+//!
+//! A futex lets a task wait until a 32-bit word in memory changes, without spinning. Waiters are kept in a
+//! [`WaitQueue`] associated with the address of the word, created on the first wait and dropped once every waiter
+//! was woken up.
+//!
+//! Contrary to the Linux futexes, a waiter only returns once the word no longer holds the expected value (or on
+//! timeout): a wake-up without a change of the word is treated as spurious.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+
+use super::waitqueue::WaitQueue;
+
+/// Wait queues of the futexes, indexed by the address of the futex word.
+static FUTEX_QUEUES: Mutex<BTreeMap<usize, Arc<WaitQueue>>> = Mutex::new(BTreeMap::new());
+
+/// Blocks the current task while `word` holds `expected`.
+///
+/// Returns `false` if `timeout` elapsed before the value of `word` changed.
+pub fn futex_wait(word: &AtomicU32, expected: u32, timeout: Option<Duration>) -> bool {
+    let queue = futex_queue(word);
+    let cond = || word.load(Ordering::Acquire) != expected;
+
+    let completed = match timeout {
+        Some(timeout) => queue.wait_until_timeout(cond, timeout),
+        None => {
+            queue.wait_until(cond);
+            true
+        }
+    };
+
+    release_queue(word, &queue);
+    completed
+}
+
+/// Wakes up every task waiting on `word`.
+///
+/// Must be called after `word` was changed. May be called from an interrupt handler.
+pub fn futex_wake(word: &AtomicU32) {
+    let queue = FUTEX_QUEUES.lock().get(&address(word)).cloned();
+
+    if let Some(queue) = queue {
+        queue.wake_all();
+    }
+}
+
+fn futex_queue(word: &AtomicU32) -> Arc<WaitQueue> {
+    FUTEX_QUEUES
+        .lock()
+        .entry(address(word))
+        .or_insert_with(|| Arc::new(WaitQueue::new()))
+        .clone()
+}
+
+/// Drops the wait queue of `word` if no other task uses it.
+fn release_queue(word: &AtomicU32, queue: &Arc<WaitQueue>) {
+    let mut queues = FUTEX_QUEUES.lock();
+
+    // One reference held by the map, and one by the caller.
+    if Arc::strong_count(queue) == 2 && !queue.has_waiters() {
+        queues.remove(&address(word));
+    }
+}
+
+fn address(word: &AtomicU32) -> usize {
+    word as *const AtomicU32 as usize
+}
@@ -0,0 +1,4 @@
+//! Synchronization primitives that block the current task, rather than spinning.
+
+pub mod futex;
+pub mod waitqueue;
@@ -0,0 +1,293 @@
+//! Wait queues.
+//!
+//! A [`WaitQueue`] lets a kernel thread block until a condition becomes true, instead of spinning. The thread is
+//! removed from the run queue of the scheduler, and is put back into it when the queue is woken up (usually from an
+//! interrupt handler, once an I/O operation completed).
+//!
+//! When the scheduler is not running (in the bootloader, or early during the kernel initialization), waiting halts
+//! the CPU until the next interrupt instead. If interrupts are disabled, nothing can wake the CPU up, and the
+//! condition is polled.
+//!
+//! Spurious wake-ups are allowed: the condition is always checked again after being woken up.
+//!
+//! Waits with a timeout are woken up by the timer queue (see [`timer`]) once their deadline is reached, if the
+//! condition did not become true before. Conditions that no interrupt signals (such as a status bit of a device that
+//! does not raise an interrupt when it changes) can be waited for with [`poll_until_timeout`], which checks the
+//! condition every [`POLL_INTERVAL`] instead of spinning.
+//!
+//! # Examples
+//!
+//! ```
+//! use core::sync::atomic::{AtomicBool, Ordering};
+//! use fzboot::sync::waitqueue::WaitQueue;
+//!
+//! static DONE: AtomicBool = AtomicBool::new(false);
+//! static DONE_QUEUE: WaitQueue = WaitQueue::new();
+//!
+//! fn irq_handler() {
+//!     DONE.store(true, Ordering::Release);
+//!     DONE_QUEUE.wake_all();
+//! }
+//!
+//! DONE_QUEUE.wait_until(|| DONE.load(Ordering::Acquire));
+//! ```
+
+use alloc::vec::Vec;
+use core::time::Duration;
+use spin::Mutex;
+
+use crate::time::{now, timer};
+use crate::x86::{
+    idle::cpu_idle,
+    int::{disable_interrupts, enable_interrupts, interrupts_disabled},
+};
+
+/// Interval between two checks of the condition of [`poll_until_timeout`].
+pub const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Margin under which a deadline is considered reached, in microseconds.
+///
+/// The timer queue is driven by the HPET, while deadlines are measured with the current clock source: the timer may
+/// fire slightly before the deadline, as measured by the clock source. Without this margin, the task would never be
+/// woken up again.
+const DEADLINE_SLACK: f64 = 50.;
+
+/// Tasks waiting with a timeout, with their deadline (in microseconds).
+static TIMED_WAITERS: Mutex<Vec<(usize, f64)>> = Mutex::new(Vec::new());
+
+/// Queue of the tasks waiting in [`poll_until_timeout`], woken up every [`POLL_INTERVAL`].
+static POLL_QUEUE: WaitQueue = WaitQueue::new();
+
+/// A queue of tasks waiting for a condition to become true.
+#[derive(Debug, Default)]
+pub struct WaitQueue {
+    /// Identifiers of the waiting tasks.
+    waiters: Mutex<Vec<usize>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Blocks the current task until `cond` returns `true`.
+    ///
+    /// `cond` is checked again every time the queue is woken up. It is called with interrupts disabled, and must
+    /// not wait for an interrupt.
+    pub fn wait_until(&self, cond: impl FnMut() -> bool) {
+        self.wait(cond, None);
+    }
+
+    /// Blocks the current task until `cond` returns `true`, or until `timeout` elapsed.
+    ///
+    /// Returns `false` if the timeout was reached before `cond` became true. The timeout is only enforced while the
+    /// timer queue is available (see [`timer`]), and is otherwise checked every time the task is woken up.
+    pub fn wait_until_timeout(&self, cond: impl FnMut() -> bool, timeout: Duration) -> bool {
+        let deadline = now() + timeout.as_secs_f64() * 1_000_000.;
+        let timer = timer::oneshot(timeout, wake_timed_waiters).ok();
+
+        let completed = self.wait(cond, Some(deadline));
+
+        if let Some(timer) = timer {
+            timer::cancel(timer);
+        }
+
+        completed
+    }
+
+    /// Wakes up every task waiting on this queue.
+    ///
+    /// May be called from an interrupt handler.
+    pub fn wake_all(&self) {
+        let waiters = without_interrupts(|| core::mem::take(&mut *self.waiters.lock()));
+
+        for task in waiters {
+            wake_task(task);
+        }
+    }
+
+    /// Wakes up the task that has been waiting on this queue for the longest time.
+    ///
+    /// Returns `false` if no task was waiting. May be called from an interrupt handler.
+    pub fn wake_one(&self) -> bool {
+        let task = without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            (!waiters.is_empty()).then(|| waiters.remove(0))
+        });
+
+        task.map(wake_task).is_some()
+    }
+
+    /// Checks if a task is waiting on this queue.
+    pub fn has_waiters(&self) -> bool {
+        !self.waiters.lock().is_empty()
+    }
+
+    fn wait(&self, mut cond: impl FnMut() -> bool, deadline: Option<f64>) -> bool {
+        let were_disabled = interrupts_disabled();
+
+        loop {
+            disable_interrupts();
+
+            if cond() {
+                break;
+            }
+
+            if deadline.is_some_and(deadline_reached) {
+                restore_interrupts(were_disabled);
+                return false;
+            }
+
+            if were_disabled {
+                core::hint::spin_loop();
+                continue;
+            }
+
+            let Some(task) = block_current_task() else {
+                // Any interrupt (including the one that completes the awaited operation) resumes the execution.
+                cpu_idle();
+                continue;
+            };
+
+            self.waiters.lock().push(task);
+            if let Some(deadline) = deadline {
+                TIMED_WAITERS.lock().push((task, deadline));
+            }
+
+            while task_blocked(task) {
+                enable_interrupts();
+                yield_now();
+
+                disable_interrupts();
+                if task_blocked(task) {
+                    cpu_idle();
+                    disable_interrupts();
+                }
+            }
+
+            self.waiters.lock().retain(|&waiter| waiter != task);
+            TIMED_WAITERS.lock().retain(|&(waiter, _)| waiter != task);
+        }
+
+        restore_interrupts(were_disabled);
+        true
+    }
+}
+
+/// Waits until `cond` returns `true`, checking it every [`POLL_INTERVAL`], for at most `timeout`.
+///
+/// Returns `false` if the timeout was reached before `cond` became true. The current task is blocked between two
+/// checks. If the timer queue is not available, `cond` is polled continuously instead.
+///
+/// As for [`WaitQueue::wait_until`], `cond` is called with interrupts disabled, and must not wait for an interrupt.
+///
+/// # Examples
+///
+/// ```
+/// use core::time::Duration;
+/// use fzboot::sync::waitqueue::poll_until_timeout;
+///
+/// if !poll_until_timeout(|| device_ready(), Duration::from_millis(50)) {
+///     return Err(IOError::Unknown);
+/// }
+/// ```
+pub fn poll_until_timeout(mut cond: impl FnMut() -> bool, timeout: Duration) -> bool {
+    let Ok(poll_timer) = timer::periodic(POLL_INTERVAL, wake_pollers) else {
+        let deadline = now() + timeout.as_secs_f64() * 1_000_000.;
+
+        while !cond() {
+            if now() >= deadline {
+                return false;
+            }
+
+            core::hint::spin_loop();
+        }
+
+        return true;
+    };
+
+    let completed = POLL_QUEUE.wait_until_timeout(cond, timeout);
+    timer::cancel(poll_timer);
+
+    completed
+}
+
+fn wake_pollers() {
+    POLL_QUEUE.wake_all();
+}
+
+fn deadline_reached(deadline: f64) -> bool {
+    now() + DEADLINE_SLACK >= deadline
+}
+
+/// Wakes up the tasks whose timeout expired, so that they can check their deadline.
+fn wake_timed_waiters() {
+    let mut expired = Vec::new();
+
+    TIMED_WAITERS.lock().retain(|&(task, deadline)| {
+        if deadline_reached(deadline) {
+            expired.push(task);
+            return false;
+        }
+
+        true
+    });
+
+    for task in expired {
+        wake_task(task);
+    }
+}
+
+fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    let were_disabled = interrupts_disabled();
+    disable_interrupts();
+
+    let result = f();
+    restore_interrupts(were_disabled);
+
+    result
+}
+
+fn restore_interrupts(were_disabled: bool) {
+    if !were_disabled {
+        enable_interrupts();
+    }
+}
+
+#[cfg(feature = "x86_64")]
+fn block_current_task() -> Option<usize> {
+    crate::scheduler::block_current_task().map(usize::from)
+}
+
+#[cfg(not(feature = "x86_64"))]
+fn block_current_task() -> Option<usize> {
+    None
+}
+
+#[cfg(feature = "x86_64")]
+fn wake_task(task: usize) {
+    crate::scheduler::wake_task(crate::scheduler::task::TaskId::new(task));
+}
+
+#[cfg(not(feature = "x86_64"))]
+fn wake_task(_task: usize) {}
+
+#[cfg(feature = "x86_64")]
+fn task_blocked(task: usize) -> bool {
+    crate::scheduler::task_blocked(crate::scheduler::task::TaskId::new(task))
+}
+
+#[cfg(not(feature = "x86_64"))]
+fn task_blocked(_task: usize) -> bool {
+    false
+}
+
+#[cfg(feature = "x86_64")]
+fn yield_now() {
+    crate::scheduler::yield_now();
+}
+
+#[cfg(not(feature = "x86_64"))]
+fn yield_now() {}
@@ -0,0 +1,288 @@
+//! Inter-process communication system calls.
+//!
+//! Pipes and message queues opened by a process are referred to by handles (see [`HandleTable`]), which play the
+//! role of file descriptors: `read`, `write` and `close` operate on pipe handles, and the `mq_*` calls on message
+//! queue handles.
+//!
+//! Unlike Linux, the timeouts of `mq_timedsend` and `mq_timedreceive` are relative to the time of the call.
+//!
+//! [`HandleTable`]: crate::ipc::HandleTable
+
+use core::{
+    mem::{size_of, MaybeUninit},
+    slice,
+    time::Duration,
+};
+
+use alloc::{sync::Arc, vec};
+
+use crate::{
+    errors::{IpcError, SyscallError},
+    ipc::{
+        mqueue::{self, MessageQueue},
+        pipe::{self, PIPE_CAPACITY},
+        IpcObject,
+    },
+};
+
+use super::{copy_from_user, copy_to_user, current_process, user_str, SyscallResult};
+
+pub const O_CREAT: u64 = 0o100;
+
+/// Maximum length of the name of a message queue.
+pub const MQ_NAME_MAX: usize = 255;
+
+/// Default maximum number of messages of a queue created without attributes.
+pub const MQ_DEFAULT_MAX_MESSAGES: usize = 10;
+
+/// Default message size of a queue created without attributes.
+pub const MQ_DEFAULT_MESSAGE_SIZE: usize = 8192;
+
+/// `read(handle, buf, len)`: reads up to `len` bytes from a pipe, blocking until at least one byte is available.
+///
+/// Returns the number of bytes read, or 0 once every writer closed the pipe. At most [`PIPE_CAPACITY`] bytes are read
+/// at once.
+pub fn sys_read(handle: u64, buf: u64, len: u64) -> SyscallResult {
+    let IpcObject::PipeReader(reader) = get_object(handle)? else {
+        return Err(SyscallError::BadHandle);
+    };
+
+    let len = usize::try_from(len).map_or(PIPE_CAPACITY, |len| len.min(PIPE_CAPACITY));
+    let mut data = vec![0u8; len];
+    let count = reader.read(&mut data)?;
+    copy_to_user(buf, &data[..count])?;
+
+    Ok(count as u64)
+}
+
+/// `write(handle, buf, len)`: writes `len` bytes to a pipe, blocking while the pipe is full.
+///
+/// Returns the number of bytes written. The buffer is copied [`PIPE_CAPACITY`] bytes at a time: if a part of it
+/// cannot be read, the number of bytes written until then is returned.
+pub fn sys_write(handle: u64, buf: u64, len: u64) -> SyscallResult {
+    let IpcObject::PipeWriter(writer) = get_object(handle)? else {
+        return Err(SyscallError::BadHandle);
+    };
+
+    let mut data = vec![0u8; PIPE_CAPACITY];
+    let mut written = 0;
+
+    while written < len {
+        let chunk =
+            usize::try_from(len - written).map_or(PIPE_CAPACITY, |len| len.min(PIPE_CAPACITY));
+
+        let copied = copy_from_user(buf.wrapping_add(written), &mut data[..chunk]);
+        if let Err(err) = copied {
+            return if written == 0 { Err(err) } else { Ok(written) };
+        }
+
+        let count = match writer.write(&data[..chunk]) {
+            Err(_) if written != 0 => break,
+            count => count?,
+        };
+        written += count as u64;
+
+        if count < chunk {
+            break;
+        }
+    }
+
+    Ok(written)
+}
+
+/// `close(handle)`: releases a handle.
+pub fn sys_close(handle: u64) -> SyscallResult {
+    let handle = u32::try_from(handle).map_err(|_| SyscallError::BadHandle)?;
+
+    current_process()?
+        .lock()
+        .handles
+        .remove(handle)
+        .ok_or(SyscallError::BadHandle)?;
+
+    Ok(0)
+}
+
+/// `pipe(handles)`: creates a pipe, and stores the handles of its reading and writing ends (in this order) as two
+/// 32-bit integers at `handles`.
+///
+/// The handles are stored once the process is unlocked: accessing its memory may fault, and the page fault handler
+/// locks the process. If they cannot be stored, both handles are released.
+pub fn sys_pipe(handles: u64) -> SyscallResult {
+    let (reader, writer) = pipe::pipe();
+
+    let process = current_process()?;
+    let mut process = process.lock();
+
+    let reader = process
+        .handles
+        .insert(IpcObject::PipeReader(Arc::new(reader)))
+        .ok_or(SyscallError::TooManyHandles)?;
+    let Some(writer) = process
+        .handles
+        .insert(IpcObject::PipeWriter(Arc::new(writer)))
+    else {
+        process.handles.remove(reader);
+        return Err(SyscallError::TooManyHandles);
+    };
+    drop(process);
+
+    let mut out = [0u8; 2 * size_of::<u32>()];
+    out[..4].copy_from_slice(&reader.to_ne_bytes());
+    out[4..].copy_from_slice(&writer.to_ne_bytes());
+
+    if let Err(err) = copy_to_user(handles, &out) {
+        let process = current_process()?;
+        let mut process = process.lock();
+        process.handles.remove(reader);
+        process.handles.remove(writer);
+
+        return Err(err);
+    }
+
+    Ok(0)
+}
+
+/// `mq_open(name, flags, mode, attr)`: opens the message queue registered under `name`, and returns its handle.
+///
+/// With `O_CREAT`, the queue is created if it does not exist yet, using the maximum number of messages and message
+/// size found in `attr` (or default values if `attr` is null).
+pub fn sys_mq_open(name: u64, flags: u64, _mode: u64, attr: u64) -> SyscallResult {
+    let name = user_str(name, MQ_NAME_MAX)?;
+
+    let create = if flags & O_CREAT == 0 {
+        None
+    } else if attr == 0 {
+        Some((MQ_DEFAULT_MESSAGE_SIZE, MQ_DEFAULT_MAX_MESSAGES))
+    } else {
+        // `struct mq_attr { mq_flags, mq_maxmsg, mq_msgsize, mq_curmsgs }`
+        let [_, capacity, message_size, _] = read_user::<[i64; 4]>(attr)?;
+        let message_size =
+            usize::try_from(message_size).map_err(|_| SyscallError::InvalidArgument)?;
+        let capacity = usize::try_from(capacity).map_err(|_| SyscallError::InvalidArgument)?;
+
+        Some((message_size, capacity))
+    };
+
+    let queue = mqueue::open(&name, create)?;
+    let handle = current_process()?
+        .lock()
+        .handles
+        .insert(IpcObject::MessageQueue(queue))
+        .ok_or(SyscallError::TooManyHandles)?;
+
+    Ok(u64::from(handle))
+}
+
+/// `mq_unlink(name)`: removes the name of a message queue.
+pub fn sys_mq_unlink(name: u64) -> SyscallResult {
+    let name = user_str(name, MQ_NAME_MAX)?;
+    mqueue::unlink(&name)?;
+
+    Ok(0)
+}
+
+/// `mq_timedsend(handle, msg, len, priority, timeout)`: sends the `len` bytes at `msg` to a message queue.
+///
+/// Blocks while the queue is full, until the `timespec` at `timeout` elapsed (or indefinitely if `timeout` is
+/// null). Priorities are not supported: messages are always received in the order they were sent.
+pub fn sys_mq_timedsend(
+    handle: u64,
+    msg: u64,
+    len: u64,
+    _priority: u64,
+    timeout: u64,
+) -> SyscallResult {
+    let queue = get_queue(handle)?;
+    let timeout = read_timeout(timeout)?;
+
+    // longer messages are rejected before anything is allocated
+    let len = usize::try_from(len)
+        .ok()
+        .filter(|len| *len <= queue.message_size())
+        .ok_or(IpcError::MessageTooLarge)?;
+    let mut message = vec![0u8; len];
+    copy_from_user(msg, &mut message)?;
+
+    queue.send(&message, timeout)?;
+
+    Ok(0)
+}
+
+/// `mq_timedreceive(handle, msg, len, priority, timeout)`: receives the oldest message of a message queue into the
+/// buffer of `len` bytes at `msg`, and returns its size.
+///
+/// Blocks while the queue is empty, until the `timespec` at `timeout` elapsed (or indefinitely if `timeout` is
+/// null). If `priority` is not null, the priority of the message (always 0) is stored there.
+pub fn sys_mq_timedreceive(
+    handle: u64,
+    msg: u64,
+    len: u64,
+    priority: u64,
+    timeout: u64,
+) -> SyscallResult {
+    let queue = get_queue(handle)?;
+    let timeout = read_timeout(timeout)?;
+
+    // buffers smaller than the message size are rejected by the queue
+    let len =
+        usize::try_from(len).map_or(queue.message_size(), |len| len.min(queue.message_size()));
+    let mut message = vec![0u8; len];
+    let size = queue.receive(&mut message, timeout)?;
+
+    copy_to_user(msg, &message[..size])?;
+    if priority != 0 {
+        copy_to_user(priority, &0u32.to_ne_bytes())?;
+    }
+
+    Ok(size as u64)
+}
+
+/// Returns the object referred to by `handle` in the table of the current process.
+///
+/// The process is not kept locked: the object can be used while blocking.
+fn get_object(handle: u64) -> Result<IpcObject, SyscallError> {
+    let handle = u32::try_from(handle).map_err(|_| SyscallError::BadHandle)?;
+
+    current_process()?
+        .lock()
+        .handles
+        .get(handle)
+        .cloned()
+        .ok_or(SyscallError::BadHandle)
+}
+
+fn get_queue(handle: u64) -> Result<Arc<MessageQueue>, SyscallError> {
+    match get_object(handle)? {
+        IpcObject::MessageQueue(queue) => Ok(queue),
+        _ => Err(SyscallError::BadHandle),
+    }
+}
+
+/// Reads a `T` from the memory of the current process at `addr`.
+///
+/// `T` must be valid for any bit pattern.
+fn read_user<T: Copy>(addr: u64) -> Result<T, SyscallError> {
+    let mut value = MaybeUninit::<T>::zeroed();
+    let bytes =
+        unsafe { slice::from_raw_parts_mut(value.as_mut_ptr().cast::<u8>(), size_of::<T>()) };
+    copy_from_user(addr, bytes)?;
+
+    Ok(unsafe { value.assume_init() })
+}
+
+/// Reads the `timespec` at `addr`, or returns `None` if `addr` is null.
+fn read_timeout(addr: u64) -> Result<Option<Duration>, SyscallError> {
+    if addr == 0 {
+        return Ok(None);
+    }
+
+    let [secs, nanos] = read_user::<[i64; 2]>(addr)?;
+    let secs = u64::try_from(secs).map_err(|_| SyscallError::InvalidArgument)?;
+    let nanos = u32::try_from(nanos)
+        .ok()
+        .filter(|nanos| *nanos < 1_000_000_000)
+        .ok_or(SyscallError::InvalidArgument)?;
+
+    Ok(Some(Duration::new(secs, nanos)))
+}
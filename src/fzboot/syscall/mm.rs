@@ -0,0 +1,124 @@
+//! Memory management system calls.
+//!
+//! They operate on the address space of the calling process (see [`crate::mem::vma`]). Memory is never allocated
+//! eagerly: these calls only add or remove areas, and pages are populated when first accessed.
+
+use crate::{
+    errors::SyscallError,
+    kernel_syms::PAGE_SIZE,
+    mem::{
+        vma::{VmaBacking, VmaFlags},
+        VirtAddr,
+    },
+    process::{Process, ProcessFlags},
+};
+
+use super::{current_process, SyscallResult};
+
+pub const PROT_READ: u64 = 0x1;
+pub const PROT_WRITE: u64 = 0x2;
+pub const PROT_EXEC: u64 = 0x4;
+
+pub const MAP_SHARED: u64 = 0x01;
+pub const MAP_PRIVATE: u64 = 0x02;
+pub const MAP_FIXED: u64 = 0x10;
+pub const MAP_ANONYMOUS: u64 = 0x20;
+
+/// `mmap(addr, len, prot, flags, fd, offset)`: maps `len` bytes of anonymous memory.
+///
+/// Without `MAP_FIXED`, `addr` is only used as a hint, and the mapping is placed in a free range surrounded by guard
+/// pages. With `MAP_FIXED`, the mapping is placed at `addr`, replacing any previous mapping. Returns the start of the
+/// mapping.
+pub fn sys_mmap(
+    addr: u64,
+    len: u64,
+    prot: u64,
+    flags: u64,
+    _fd: u64,
+    _offset: u64,
+) -> SyscallResult {
+    // Processes do not have file descriptors yet, so files can only be mapped from the kernel (see
+    // `AddressSpace::map` and `MappedFile`).
+    if flags & MAP_ANONYMOUS == 0 {
+        return Err(SyscallError::NoDevice);
+    }
+    if len == 0 || flags & (MAP_SHARED | MAP_PRIVATE) == 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    let len = usize::try_from(len).map_err(|_| SyscallError::InvalidArgument)?;
+
+    let process = current_process()?;
+    let mut process = process.lock();
+    let vma_flags = vma_flags(&process, prot);
+
+    if flags & MAP_FIXED != 0 {
+        process.address_space.map_fixed(
+            VirtAddr::new(addr),
+            len,
+            vma_flags,
+            VmaBacking::Anonymous,
+        )?;
+
+        return Ok(addr);
+    }
+
+    let start = process.address_space.map(
+        Some(VirtAddr::new(addr)),
+        len,
+        vma_flags,
+        VmaBacking::Anonymous,
+    )?;
+
+    Ok(u64::from(start))
+}
+
+/// `munmap(addr, len)`: removes the mappings in `[addr, addr + len)`, and releases their memory.
+pub fn sys_munmap(addr: u64, len: u64) -> SyscallResult {
+    let len = usize::try_from(len)
+        .ok()
+        .and_then(|len| len.checked_next_multiple_of(PAGE_SIZE))
+        .ok_or(SyscallError::InvalidArgument)?;
+
+    current_process()?
+        .lock()
+        .address_space
+        .unmap(VirtAddr::new(addr), len)?;
+
+    Ok(0)
+}
+
+/// `brk(addr)`: moves the program break to `addr`, and returns the new program break.
+///
+/// The current program break is returned if it could not be moved (in particular when `addr` is zero).
+pub fn sys_brk(addr: u64) -> SyscallResult {
+    let process = current_process()?;
+    let mut process = process.lock();
+    let vma_flags = vma_flags(&process, PROT_READ | PROT_WRITE);
+
+    let brk = process
+        .address_space
+        .set_brk(VirtAddr::new(addr), vma_flags);
+
+    Ok(u64::from(brk))
+}
+
+/// Converts the `prot` argument of a system call to the permissions of an area of `process`.
+fn vma_flags(process: &Process, prot: u64) -> VmaFlags {
+    let mut flags = VmaFlags::default();
+
+    if prot & PROT_READ != 0 {
+        flags = flags | VmaFlags::READ;
+    }
+    if prot & PROT_WRITE != 0 {
+        flags = flags | VmaFlags::WRITE;
+    }
+    if prot & PROT_EXEC != 0 {
+        flags = flags | VmaFlags::EXEC;
+    }
+    if process.flags.contains(ProcessFlags::USER_PROCESS) {
+        flags = flags | VmaFlags::USER;
+    }
+
+    flags
+}
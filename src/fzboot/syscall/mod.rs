@@ -0,0 +1,304 @@
+//! System calls.
+//!
+//! System calls are identified by a number (see [`SyscallNumber`]), following the numbering used by Linux on x86_64,
+//! and take up to 6 integer arguments. [`syscall_dispatch`] routes a system call to its handler, and converts the
+//! result of the handler to the value returned to the caller: a non-negative value on success, or the negated error
+//! number on failure (see [`SyscallError::errno`]).
+//!
+//! Processes issue system calls with `int 0x80` (see [`SYSCALL_VECTOR`]), following the Linux `x86_64` register
+//! convention: the number is passed in `rax`, the arguments in `rdi`, `rsi`, `rdx`, `r10`, `r8` and `r9`, and the
+//! result is returned in `rax`.
+
+use core::arch::{global_asm, naked_asm};
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use spin::Mutex;
+
+use crate::{
+    errors::{CanFail, SyscallError},
+    irq::manager::{self, get_interrupt_manager, HandlerRegistrationError},
+    mem::{vma::USER_SPACE_END, VirtAddr},
+    process::{get_process, Process, ProcessFlags},
+    scheduler::current_process_id,
+    x86::{
+        apic::InterruptVector, privilege::PrivilegeLevel,
+        registers::x86_64::GeneralPurposeRegisters,
+    },
+};
+
+pub mod ipc;
+pub mod mm;
+
+/// Vector of the system call gate.
+pub const SYSCALL_VECTOR: InterruptVector = InterruptVector::new(manager::SYSCALL_VECTOR);
+
+/// Result of a system call handler.
+pub type SyscallResult = Result<u64, SyscallError>;
+
+/// Number identifying a system call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u64)]
+pub enum SyscallNumber {
+    Read = 0,
+    Write = 1,
+    Close = 3,
+    Mmap = 9,
+    Munmap = 11,
+    Brk = 12,
+    Pipe = 22,
+    MqOpen = 240,
+    MqUnlink = 241,
+    MqTimedSend = 242,
+    MqTimedReceive = 243,
+}
+
+impl TryFrom<u64> for SyscallNumber {
+    type Error = SyscallError;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Read),
+            1 => Ok(Self::Write),
+            3 => Ok(Self::Close),
+            9 => Ok(Self::Mmap),
+            11 => Ok(Self::Munmap),
+            12 => Ok(Self::Brk),
+            22 => Ok(Self::Pipe),
+            240 => Ok(Self::MqOpen),
+            241 => Ok(Self::MqUnlink),
+            242 => Ok(Self::MqTimedSend),
+            243 => Ok(Self::MqTimedReceive),
+            _ => Err(SyscallError::NotImplemented),
+        }
+    }
+}
+
+/// Executes the system call `number`, on behalf of the current process.
+///
+/// Returns the value to hand back to the caller.
+pub fn syscall_dispatch(number: u64, args: [u64; 6]) -> i64 {
+    let result = SyscallNumber::try_from(number).and_then(|number| match number {
+        SyscallNumber::Read => ipc::sys_read(args[0], args[1], args[2]),
+        SyscallNumber::Write => ipc::sys_write(args[0], args[1], args[2]),
+        SyscallNumber::Close => ipc::sys_close(args[0]),
+        SyscallNumber::Mmap => mm::sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
+        SyscallNumber::Munmap => mm::sys_munmap(args[0], args[1]),
+        SyscallNumber::Brk => mm::sys_brk(args[0]),
+        SyscallNumber::Pipe => ipc::sys_pipe(args[0]),
+        SyscallNumber::MqOpen => ipc::sys_mq_open(args[0], args[1], args[2], args[3]),
+        SyscallNumber::MqUnlink => ipc::sys_mq_unlink(args[0]),
+        SyscallNumber::MqTimedSend => {
+            ipc::sys_mq_timedsend(args[0], args[1], args[2], args[3], args[4])
+        }
+        SyscallNumber::MqTimedReceive => {
+            ipc::sys_mq_timedreceive(args[0], args[1], args[2], args[3], args[4])
+        }
+    });
+
+    match result {
+        Ok(value) => value as i64,
+        Err(err) => -err.errno(),
+    }
+}
+
+/// Registers the system call gate, and allows user processes to raise it.
+///
+/// # Errors
+///
+/// Returns an error if the gate could not be written to the `IDT`.
+pub fn syscall_init() -> CanFail<HandlerRegistrationError> {
+    let int_mgr = get_interrupt_manager();
+
+    int_mgr.register_static_handler(SYSCALL_VECTOR, syscall_entry)?;
+    int_mgr.set_privilege_level(SYSCALL_VECTOR, PrivilegeLevel::Ring3)
+}
+
+/// Entry point of the system call gate.
+///
+/// Saves the registers of the caller, and hands them to [`syscall_handler`]. Every register but `rax` is restored
+/// when returning to the caller.
+#[link_section = ".int"]
+#[naked]
+fn syscall_entry() {
+    unsafe {
+        naked_asm!(
+            "cld",
+            "push r15",
+            "push r14",
+            "push r13",
+            "push r12",
+            "push r11",
+            "push r10",
+            "push r9",
+            "push r8",
+            "push rbp",
+            "push rdi",
+            "push rsi",
+            "push rdx",
+            "push rcx",
+            "push rbx",
+            "push rax",
+            "mov rdi, rsp",
+            "call {handler}",
+            "pop rax",
+            "pop rbx",
+            "pop rcx",
+            "pop rdx",
+            "pop rsi",
+            "pop rdi",
+            "pop rbp",
+            "pop r8",
+            "pop r9",
+            "pop r10",
+            "pop r11",
+            "pop r12",
+            "pop r13",
+            "pop r14",
+            "pop r15",
+            "iretq",
+            handler = sym syscall_handler,
+        )
+    }
+}
+
+/// Executes the system call described by the saved registers of the caller, and stores its result in the saved `rax`.
+extern "C" fn syscall_handler(registers: &mut GeneralPurposeRegisters) {
+    let args = [
+        registers.rdi,
+        registers.rsi,
+        registers.rdx,
+        registers.r10,
+        registers.r8,
+        registers.r9,
+    ];
+
+    registers.rax = syscall_dispatch(registers.rax, args) as u64;
+}
+
+fn current_process() -> Result<Arc<Mutex<Process>>, SyscallError> {
+    get_process(current_process_id()).ok_or(SyscallError::NoProcess)
+}
+
+/// Checks that the buffer of `len` bytes at `addr` can be passed by the calling process.
+///
+/// User processes can only pass buffers of the user part of the address space.
+fn check_user_range(addr: u64, len: usize) -> Result<(), SyscallError> {
+    let end = addr
+        .checked_add(len as u64)
+        .ok_or(SyscallError::BadAddress)?;
+
+    if addr == 0 {
+        return Err(SyscallError::BadAddress);
+    }
+    if current_process()?
+        .lock()
+        .flags
+        .contains(ProcessFlags::USER_PROCESS)
+        && end > u64::from(USER_SPACE_END)
+    {
+        return Err(SyscallError::BadAddress);
+    }
+
+    Ok(())
+}
+
+// Copies `rdx` bytes from `rsi` to `rdi`, and returns the number of bytes left to copy in `rax`.
+//
+// If the copy faults, the page fault handler resumes at `__user_copy_fault` (see `user_access_fixup`).
+global_asm!(
+    ".global __user_copy",
+    "__user_copy:",
+    "mov rcx, rdx",
+    ".global __user_copy_access",
+    "__user_copy_access:",
+    "rep movsb",
+    "xor eax, eax",
+    "ret",
+    ".global __user_copy_fault",
+    "__user_copy_fault:",
+    "mov rax, rcx",
+    "ret",
+);
+
+extern "C" {
+    fn __user_copy(dest: *mut u8, src: *const u8, len: usize) -> usize;
+    static __user_copy_access: u8;
+    static __user_copy_fault: u8;
+}
+
+/// Returns the address at which execution resumes after an unresolved page fault at `rip`, if the fault occurred
+/// while accessing the memory of a process on behalf of a system call.
+///
+/// The copy then fails with [`SyscallError::BadAddress`], instead of the kernel panicking.
+pub(crate) fn user_access_fixup(rip: VirtAddr) -> Option<VirtAddr> {
+    let access = unsafe { core::ptr::addr_of!(__user_copy_access) } as u64;
+
+    (u64::from(rip) == access)
+        .then(|| VirtAddr::new(unsafe { core::ptr::addr_of!(__user_copy_fault) } as u64))
+}
+
+/// Copies `buf.len()` bytes from `addr`, in the memory of the calling process, into `buf`.
+///
+/// Pages of the areas the buffer covers are populated on access by the page fault handler. The caller must not hold
+/// locks taken by the page fault handler (such as the lock of the process).
+///
+/// # Errors
+///
+/// Returns [`SyscallError::BadAddress`] if the buffer cannot be passed by the process, or is not (entirely) mapped.
+fn copy_from_user(addr: u64, buf: &mut [u8]) -> Result<(), SyscallError> {
+    if buf.is_empty() {
+        return Ok(());
+    }
+    check_user_range(addr, buf.len())?;
+
+    match unsafe { __user_copy(buf.as_mut_ptr(), addr as *const u8, buf.len()) } {
+        0 => Ok(()),
+        _ => Err(SyscallError::BadAddress),
+    }
+}
+
+/// Copies `data` to `addr`, in the memory of the calling process.
+///
+/// The same restrictions as [`copy_from_user`] apply.
+///
+/// # Errors
+///
+/// Returns [`SyscallError::BadAddress`] if the buffer cannot be passed by the process, or is not (entirely) mapped.
+fn copy_to_user(addr: u64, data: &[u8]) -> Result<(), SyscallError> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    check_user_range(addr, data.len())?;
+
+    match unsafe { __user_copy(addr as *mut u8, data.as_ptr(), data.len()) } {
+        0 => Ok(()),
+        _ => Err(SyscallError::BadAddress),
+    }
+}
+
+/// Reads the null-terminated string at `addr`, in the memory of the calling process.
+///
+/// The string is read one byte at a time, so that nothing past its terminator is accessed.
+///
+/// # Errors
+///
+/// Returns [`SyscallError::InvalidArgument`] if the string is longer than `max_len` bytes or is not valid UTF-8, or
+/// [`SyscallError::BadAddress`] if it is not (entirely) mapped.
+fn user_str(addr: u64, max_len: usize) -> Result<String, SyscallError> {
+    let mut bytes = Vec::new();
+
+    for offset in 0..=max_len as u64 {
+        let mut byte = [0u8];
+        copy_from_user(
+            addr.checked_add(offset).ok_or(SyscallError::BadAddress)?,
+            &mut byte,
+        )?;
+
+        if byte[0] == 0 {
+            return String::from_utf8(bytes).map_err(|_| SyscallError::InvalidArgument);
+        }
+        bytes.push(byte[0]);
+    }
+
+    Err(SyscallError::InvalidArgument)
+}
@@ -0,0 +1,272 @@
+//! Clock source selection.
+//!
+//! Time measurements (see [`super::now`]) are made using the best available clock source: `kvmclock` when running as
+//! a KVM guest (see [`crate::x86::kvm`]), the TSC when it is invariant, as it is the cheapest one to read, and the HPET
+//! otherwise.
+//!
+//! While the TSC is used, a watchdog regularly compares it against the HPET. If both clocks drift apart, the TSC is
+//! marked as unstable and the HPET becomes the clock source. Switching clock sources preserves the monotonicity of
+//! the time returned by [`clocksource_time`].
+
+use core::fmt::{self, Display};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+
+use crate::errors::{CanFail, ClockError};
+use crate::io::acpi::hpet::HPET_CLK;
+use crate::x86::kvm::{kvmclock_available, kvmclock_time};
+use crate::x86::tsc::TSC_CLK;
+use crate::{error, info};
+
+/// Interval between two checks of the TSC against the HPET, in milliseconds.
+#[cfg(feature = "alloc")]
+const WATCHDOG_INTERVAL_MS: u64 = 500;
+
+/// Maximum relative drift between the TSC and the HPET, over a watchdog interval.
+const WATCHDOG_MAX_DRIFT: f64 = 0.001;
+
+static CURRENT_CLOCKSOURCE: AtomicU8 = AtomicU8::new(ClockSource::Tsc as u8);
+
+/// Offset added to the time read from the current clock source (stored as `f64` bits).
+static CLOCKSOURCE_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+static TSC_UNSTABLE: AtomicBool = AtomicBool::new(false);
+
+/// Last time read by the watchdog, from the TSC and the HPET (stored as `f64` bits).
+static WATCHDOG_LAST_TSC: AtomicU64 = AtomicU64::new(0);
+static WATCHDOG_LAST_HPET: AtomicU64 = AtomicU64::new(0);
+
+/// A clock that can be used for time measurements.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ClockSource {
+    /// Time-Stamp Counter.
+    Tsc,
+
+    /// High Precision Event Timer.
+    Hpet,
+
+    /// KVM paravirtual clock.
+    KvmClock,
+}
+
+impl ClockSource {
+    /// Checks if this clock source can be used on this system.
+    ///
+    /// A 32-bit HPET is not usable, as its counter rolls over after a few minutes.
+    pub fn available(self) -> bool {
+        match self {
+            Self::Tsc => TSC_CLK.is_initialized() && !TSC_UNSTABLE.load(Ordering::Relaxed),
+            Self::Hpet => HPET_CLK.get().is_some_and(|hpet| hpet.clk_width() == 64),
+            Self::KvmClock => kvmclock_available(),
+        }
+    }
+
+    /// Returns the current time of this clock source, in microseconds, or `None` if it is not initialized.
+    pub fn read(self) -> Option<f64> {
+        match self {
+            Self::Tsc => TSC_CLK.get().map(|tsc| tsc.tsc_time()),
+            Self::Hpet => HPET_CLK.get().map(|hpet| hpet.clk_time()),
+            Self::KvmClock => kvmclock_time(),
+        }
+    }
+}
+
+impl From<u8> for ClockSource {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Self::Hpet,
+            2 => Self::KvmClock,
+            _ => Self::Tsc,
+        }
+    }
+}
+
+impl Display for ClockSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tsc => f.write_str("tsc"),
+            Self::Hpet => f.write_str("hpet"),
+            Self::KvmClock => f.write_str("kvm-clock"),
+        }
+    }
+}
+
+/// Selects the best available clock source.
+///
+/// Should be called once the TSC and HPET clocks are initialized, as well as `kvmclock` (see
+/// [`kvm_init`](crate::x86::kvm::kvm_init)). `kvmclock` is preferred if available, then the TSC if it is invariant,
+/// then the HPET.
+pub fn clocksource_init() {
+    let tsc_invariant = TSC_CLK.get().is_some_and(|tsc| tsc.invariant());
+
+    let source = if ClockSource::KvmClock.available() {
+        ClockSource::KvmClock
+    } else {
+        hardware_clocksource()
+    };
+
+    if set_clocksource(source).is_err() {
+        error!("clocksource", "no clock source available");
+        return;
+    }
+
+    info!(
+        "clocksource",
+        "using {} as clock source (invariant_tsc = {})", source, tsc_invariant
+    );
+}
+
+/// Stops using `kvmclock` for time measurements, switching to the TSC if it is invariant, to the HPET otherwise.
+///
+/// Must be called before `kvmclock` is disabled (see [`kvm_handoff`](crate::x86::kvm::kvm_handoff)), as it is still
+/// needed to preserve monotonicity. Nothing is done if `kvmclock` is not the current clock source.
+///
+/// # Errors
+///
+/// Returns [`ClockError::NotPresent`] if neither the TSC nor the HPET is available.
+pub fn clocksource_leave_kvmclock() -> CanFail<ClockError> {
+    if current_clocksource() != ClockSource::KvmClock {
+        return Ok(());
+    }
+
+    let source = hardware_clocksource();
+    set_clocksource(source)?;
+
+    info!("clocksource", "switching from kvm-clock to {}", source);
+
+    Ok(())
+}
+
+/// Returns the best clock source that does not depend on the hypervisor: the TSC if it is invariant (or if there is
+/// no usable HPET), the HPET otherwise.
+fn hardware_clocksource() -> ClockSource {
+    let tsc_invariant = TSC_CLK.get().is_some_and(|tsc| tsc.invariant());
+
+    if tsc_invariant || !ClockSource::Hpet.available() {
+        ClockSource::Tsc
+    } else {
+        ClockSource::Hpet
+    }
+}
+
+/// Returns the clock source currently used for time measurements.
+pub fn current_clocksource() -> ClockSource {
+    ClockSource::from(CURRENT_CLOCKSOURCE.load(Ordering::Acquire))
+}
+
+/// Uses `source` for time measurements.
+///
+/// The time returned by [`clocksource_time`] keeps increasing when switching to another clock source.
+///
+/// # Errors
+///
+/// Returns [`ClockError::NotPresent`] if `source` is not available.
+pub fn set_clocksource(source: ClockSource) -> CanFail<ClockError> {
+    if !source.available() {
+        return Err(ClockError::NotPresent);
+    }
+
+    let new_time = source.read().ok_or(ClockError::NotPresent)?;
+
+    // Compensates the difference between both clocks, so that the time stays monotonic.
+    let offset = match current_clocksource().read() {
+        Some(current_time) => current_time + current_offset() - new_time,
+        None => 0.,
+    };
+
+    CLOCKSOURCE_OFFSET.store(offset.to_bits(), Ordering::Release);
+    CURRENT_CLOCKSOURCE.store(source as u8, Ordering::Release);
+
+    Ok(())
+}
+
+/// Returns the current time in microseconds, using the current clock source.
+///
+/// # Panics
+///
+/// Panics if the current clock source is not initialized.
+pub fn clocksource_time() -> f64 {
+    current_clocksource().read().unwrap() + current_offset()
+}
+
+/// Checks if the TSC was found to drift from the HPET.
+pub fn tsc_unstable() -> bool {
+    TSC_UNSTABLE.load(Ordering::Relaxed)
+}
+
+/// Starts checking the TSC against the HPET at regular intervals.
+///
+/// Requires the timer queue (see [`super::timer`]). Nothing is done if the TSC is not the current clock source, if
+/// there is no HPET to compare it against, or if the `clocksource.watchdog` option is disabled.
+#[cfg(feature = "alloc")]
+pub fn clocksource_watchdog_init() {
+    use core::time::Duration;
+
+    if !crate::config::get_bool("clocksource.watchdog") {
+        info!("clocksource", "TSC watchdog disabled by configuration");
+        return;
+    }
+
+    if current_clocksource() != ClockSource::Tsc || !ClockSource::Hpet.available() {
+        return;
+    }
+
+    watchdog_reset();
+
+    if super::timer::periodic(
+        Duration::from_millis(WATCHDOG_INTERVAL_MS),
+        clocksource_watchdog,
+    )
+    .is_err()
+    {
+        info!(
+            "clocksource",
+            "timer queue unavailable, TSC watchdog disabled"
+        );
+    }
+}
+
+/// Compares the time elapsed on the TSC and on the HPET since the previous check, and switches to the HPET if the
+/// difference is too large.
+fn clocksource_watchdog() {
+    if current_clocksource() != ClockSource::Tsc {
+        return;
+    }
+
+    let (Some(tsc_time), Some(hpet_time)) = (ClockSource::Tsc.read(), ClockSource::Hpet.read())
+    else {
+        return;
+    };
+
+    let tsc_elapsed = tsc_time - f64::from_bits(WATCHDOG_LAST_TSC.load(Ordering::Relaxed));
+    let hpet_elapsed = hpet_time - f64::from_bits(WATCHDOG_LAST_HPET.load(Ordering::Relaxed));
+    watchdog_reset();
+
+    if hpet_elapsed <= 0. || (tsc_elapsed - hpet_elapsed).abs() <= hpet_elapsed * WATCHDOG_MAX_DRIFT
+    {
+        return;
+    }
+
+    error!(
+        "clocksource",
+        "TSC is unstable (tsc_elapsed = {} microsecs  hpet_elapsed = {} microsecs), switching to hpet",
+        tsc_elapsed,
+        hpet_elapsed
+    );
+
+    // Switches before marking the TSC as unstable, as it is still needed to preserve monotonicity.
+    if set_clocksource(ClockSource::Hpet).is_ok() {
+        TSC_UNSTABLE.store(true, Ordering::Relaxed);
+    }
+}
+
+fn watchdog_reset() {
+    if let (Some(tsc_time), Some(hpet_time)) = (ClockSource::Tsc.read(), ClockSource::Hpet.read()) {
+        WATCHDOG_LAST_TSC.store(tsc_time.to_bits(), Ordering::Relaxed);
+        WATCHDOG_LAST_HPET.store(hpet_time.to_bits(), Ordering::Relaxed);
+    }
+}
+
+fn current_offset() -> f64 {
+    f64::from_bits(CLOCKSOURCE_OFFSET.load(Ordering::Acquire))
+}
@@ -6,7 +6,10 @@
 //!
 //! Uses the RTC on the CMOS chip to retrieve the current UTC time.
 
+pub mod clocksource;
 pub mod rtc;
+#[cfg(feature = "alloc")]
+pub mod timer;
 
 use core::fmt::{self, Display};
 
@@ -14,8 +17,6 @@ use core::fmt::{self, Display};
 use alloc::{format, string::String};
 use bytemuck::{Pod, Zeroable};
 
-use crate::x86::tsc::TSC_CLK;
-
 /// Returns the current UTC time as a [`DateTime`], that
 /// can then be further formatted.
 ///
@@ -34,13 +35,13 @@ pub fn date() -> DateTime {
     rtc::rtc_read()
 }
 
-/// Returns the current time in microseconds of the TSC counter.
+/// Returns the current time in microseconds, using the current clock source (see [`clocksource`]).
 ///
 /// Can be used for time measurement.
 ///
 /// # Panics
 ///
-/// Panics if called before initializing the `TSC counter`.
+/// Panics if called before initializing the clock source.
 ///
 /// # Examples
 ///
@@ -55,7 +56,7 @@ pub fn date() -> DateTime {
 /// ```
 #[must_use]
 pub fn now() -> f64 {
-    TSC_CLK.get().unwrap().tsc_time()
+    clocksource::clocksource_time()
 }
 
 #[must_use]
@@ -83,7 +84,13 @@ macro_rules! wait {
     };
 }
 
-/// Waits until a condition is satisfied, or a timeout is reached.
+/// Waits until a condition is satisfied, or a timeout (in milliseconds) is reached.
+///
+/// Evaluates to `Ok(())` as soon as the condition is satisfied.
+///
+/// # Errors
+///
+/// Evaluates to [`IOError::IOTimeout`] if the condition is still not satisfied once the timeout is reached.
 ///
 /// # Examples
 ///
@@ -92,21 +99,37 @@ macro_rules! wait {
 /// ```
 /// use fzboot::time::wait_for;
 ///
-/// wait_for!(statement, 50);
+/// wait_for!(statement, 50)?;
 /// ```
+///
+/// [`IOError::IOTimeout`]: crate::errors::IOError::IOTimeout
 #[macro_export]
 macro_rules! wait_for {
-    ($cond: expr, $timeout: literal) => {
-        let init_time = $crate::time::now();
-        core::hint::spin_loop();
-        while $crate::time::now() < (init_time + 1_000_f64 * $timeout as f64) && !$cond {}
-    };
+    ($cond: expr, $timeout: literal) => {{
+        let deadline = $crate::time::now() + 1_000_f64 * $timeout as f64;
+
+        loop {
+            if $cond {
+                break Ok(());
+            }
+
+            if $crate::time::now() >= deadline {
+                break Err($crate::errors::IOError::IOTimeout);
+            }
+
+            core::hint::spin_loop();
+        }
+    }};
 }
 
 /// Waits until a condition is satisfied, or a timeout is reached.
 ///
 /// If a timeout was reached, an special expression can be executed.
 ///
+/// This busy-waits: once the timer queue is available,
+/// [`poll_until_timeout`](crate::sync::waitqueue::poll_until_timeout) blocks the current task between two checks of
+/// the condition instead.
+///
 /// # Examples
 ///
 /// ```
@@ -13,7 +13,7 @@ use crate::{
 /// Disables interrupts during the operation, and waits a small
 /// delay between write and read.
 #[inline]
-fn __cmos_read(registry: u8) -> u8 {
+pub(crate) fn __cmos_read(registry: u8) -> u8 {
     // Set the registry to which we read from.
     outb(IOPort::from(0x70), registry);
     io_delay();
@@ -22,6 +22,18 @@ fn __cmos_read(registry: u8) -> u8 {
     inb(IOPort::from(0x71))
 }
 
+/// Writes a registry of the CMOS chip.
+///
+/// Waits a small delay between the selection of the registry and the write.
+#[inline]
+pub(crate) fn __cmos_write(registry: u8, value: u8) {
+    // Set the registry to which we write to.
+    outb(IOPort::from(0x70), registry);
+    io_delay();
+
+    outb(IOPort::from(0x71), value);
+}
+
 /// Checks if the RTC value is being updated (rolls over).
 ///
 /// Bit 7 of Status register A indicates if an update is ongoing
@@ -0,0 +1,274 @@
+//! Timer queue, driven by the HPET comparator interrupts.
+//!
+//! Timers are kept in a queue ordered by deadline. The first timer of the HPET is programmed to raise an interrupt
+//! when the earliest deadline is reached: expired timers are then removed from the queue (or re-armed, for periodic
+//! timers), and their callbacks are called.
+//!
+//! Callbacks are called in interrupt context: they must be short, and must not wait for other interrupts.
+//!
+//! # Examples
+//!
+//! ```
+//! use core::time::Duration;
+//! use fzboot::time::timer;
+//!
+//! fn tick() {
+//!     println!("tick");
+//! }
+//!
+//! timer::periodic(Duration::from_millis(500), tick).unwrap();
+//! timer::sleep(Duration::from_secs(2));
+//! ```
+
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+
+use fzproc_macros::interrupt_handler;
+use spin::Mutex;
+
+use crate::errors::ClockError;
+use crate::io::acpi::hpet::{HPETClock, HPET_CLK};
+use crate::irq::{manager::get_interrupt_manager, InterruptStackFrame};
+use crate::x86::apic::{
+    io_apic::get_all_io_apics, local_apic::VectorPriorityClass, mp_table::IOApicIntPin,
+};
+use crate::x86::idle::cpu_idle;
+use crate::x86::int::{disable_interrupts, enable_interrupts, interrupts_disabled};
+use crate::{error, info};
+
+/// HPET timer used to drive the timer queue.
+const HPET_TIMER: u8 = 0;
+
+/// Maximum number of deadlines processed by a single interrupt, so that a periodic timer with a period shorter than
+/// the interrupt latency cannot lock the CPU in the interrupt handler.
+const MAX_EXPIRED_PER_IRQ: usize = 64;
+
+/// Pending timers, indexed by deadline (in HPET clock ticks) and identifier.
+static TIMER_QUEUE: Mutex<BTreeMap<(u64, TimerId), Timer>> = Mutex::new(BTreeMap::new());
+
+static NEXT_TIMER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Set once the HPET timer interrupts were set up.
+static TIMER_QUEUE_READY: AtomicBool = AtomicBool::new(false);
+
+/// Callback called when a timer expires.
+pub type TimerCallback = fn();
+
+/// Identifies a timer in the timer queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TimerId(u64);
+
+#[derive(Clone, Copy, Debug)]
+struct Timer {
+    /// Period of the timer (in HPET clock ticks), for periodic timers.
+    period: Option<u64>,
+    callback: TimerCallback,
+}
+
+/// Sets up the HPET timer used to drive the timer queue.
+///
+/// Requires a 64-bit HPET, whose first timer can be routed to an I/O APIC pin.
+pub fn timer_init() {
+    let Some(hpet) = HPET_CLK.get() else {
+        info!("timer", "no HPET clock available, timer queue disabled");
+        return;
+    };
+
+    if hpet.clk_width() != 64 {
+        info!("timer", "32-bit HPET counter, timer queue disabled");
+        return;
+    }
+
+    // Pins below 16 are usually used by ISA devices.
+    let route_cap = hpet.timer_route_capabilities(HPET_TIMER);
+    let Some(pin) = (16..32)
+        .chain(3..16)
+        .find(|&pin| route_cap & (1 << pin) != 0)
+    else {
+        error!("timer", "cannot route HPET timer interrupts");
+        return;
+    };
+
+    let Some(io_apics) = get_all_io_apics() else {
+        error!("timer", "no I/O APIC available");
+        return;
+    };
+
+    let Ok(vector) =
+        get_interrupt_manager().allocate_vector(VectorPriorityClass::DEVICE_HIGH, "hpet-timer")
+    else {
+        error!("timer", "no interrupt vector available");
+        return;
+    };
+
+    for io_apic in io_apics {
+        io_apic
+            .1
+            .lock()
+            .map_pin_to_irq(IOApicIntPin::from(pin), vector);
+    }
+
+    get_interrupt_manager().register_static_handler(vector, hpet_timer_irq_entry);
+
+    hpet.set_timer_comparator(HPET_TIMER, u64::MAX);
+    hpet.setup_oneshot_timer(HPET_TIMER, pin);
+    TIMER_QUEUE_READY.store(true, Ordering::Release);
+
+    info!("timer", "timer queue initialized (hpet_pin = {pin})");
+}
+
+/// Schedules `callback` to be called once, after `delay`.
+///
+/// # Errors
+///
+/// Returns [`ClockError::NotPresent`] if the timer queue is not available.
+pub fn oneshot(delay: Duration, callback: TimerCallback) -> Result<TimerId, ClockError> {
+    add_timer(delay, None, callback)
+}
+
+/// Schedules `callback` to be called every `period`, starting after one `period`.
+///
+/// # Errors
+///
+/// Returns [`ClockError::NotPresent`] if the timer queue is not available.
+pub fn periodic(period: Duration, callback: TimerCallback) -> Result<TimerId, ClockError> {
+    let hpet = queue_clock()?;
+    let period_ticks = u64::max(hpet.ticks_from_micros(micros(period)), 1);
+
+    add_timer(period, Some(period_ticks), callback)
+}
+
+/// Removes a timer from the queue.
+///
+/// Returns `false` if the timer already expired (for one-shot timers), or was already cancelled.
+pub fn cancel(id: TimerId) -> bool {
+    let mut queue = TIMER_QUEUE.lock();
+    let key = queue.keys().find(|(_, timer_id)| *timer_id == id).copied();
+
+    key.and_then(|key| queue.remove(&key)).is_some()
+}
+
+/// Waits for `duration`.
+///
+/// The CPU is halted until the end of the delay if the timer queue is available, and if interrupts are enabled.
+/// Otherwise, this busy-waits using the TSC.
+pub fn sleep(duration: Duration) {
+    let can_halt = !interrupts_disabled();
+
+    let Ok(hpet) = queue_clock() else {
+        return busy_wait(duration);
+    };
+
+    let deadline = hpet.main_counter() + hpet.ticks_from_micros(micros(duration));
+
+    if !can_halt || oneshot(duration, wake_up).is_err() {
+        return busy_wait(duration);
+    }
+
+    loop {
+        disable_interrupts();
+
+        if hpet.main_counter() >= deadline {
+            break;
+        }
+
+        cpu_idle();
+    }
+
+    enable_interrupts();
+}
+
+/// Returns the HPET clock used by the timer queue.
+fn queue_clock() -> Result<&'static HPETClock<'static>, ClockError> {
+    if !TIMER_QUEUE_READY.load(Ordering::Acquire) {
+        return Err(ClockError::NotPresent);
+    }
+
+    HPET_CLK.get().ok_or(ClockError::NotPresent)
+}
+
+fn add_timer(
+    delay: Duration,
+    period: Option<u64>,
+    callback: TimerCallback,
+) -> Result<TimerId, ClockError> {
+    let hpet = queue_clock()?;
+    let id = TimerId(NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed));
+    let deadline = hpet.main_counter() + hpet.ticks_from_micros(micros(delay));
+
+    let mut queue = TIMER_QUEUE.lock();
+    queue.insert((deadline, id), Timer { period, callback });
+    arm_next(hpet, &queue);
+
+    Ok(id)
+}
+
+/// Programs the HPET comparator for the earliest deadline of the queue.
+///
+/// If that deadline was reached in the meantime, the comparator is programmed for the next clock tick, as the
+/// comparator only fires when the main counter becomes equal to its value.
+fn arm_next(hpet: &HPETClock, queue: &BTreeMap<(u64, TimerId), Timer>) {
+    let Some(&(deadline, _)) = queue.keys().next() else {
+        return;
+    };
+
+    hpet.set_timer_comparator(HPET_TIMER, deadline);
+
+    let now = hpet.main_counter();
+    if now >= deadline {
+        hpet.set_timer_comparator(HPET_TIMER, now + hpet.ticks_from_micros(1.));
+    }
+}
+
+fn busy_wait(duration: Duration) {
+    let end = super::now() + micros(duration);
+
+    while super::now() < end {
+        core::hint::spin_loop();
+    }
+}
+
+fn wake_up() {}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1_000_000.
+}
+
+/// HPET timer interrupt entry point.
+///
+/// Calls the callbacks of every expired timer, and re-arms the comparator for the next deadline.
+#[interrupt_handler]
+pub fn hpet_timer_irq_entry(frame: InterruptStackFrame) {
+    let Ok(hpet) = queue_clock() else {
+        return;
+    };
+
+    for _ in 0..MAX_EXPIRED_PER_IRQ {
+        let mut queue = TIMER_QUEUE.lock();
+        let now = hpet.main_counter();
+
+        let Some(entry) = queue.first_entry() else {
+            return;
+        };
+
+        if entry.key().0 > now {
+            arm_next(hpet, &queue);
+            return;
+        }
+
+        let ((deadline, id), timer) = entry.remove_entry();
+
+        if let Some(period) = timer.period {
+            // Skips the periods that were missed, rather than calling the callback several times in a row.
+            let missed = (now - deadline) / period;
+            queue.insert((deadline + (missed + 1) * period, id), timer);
+        }
+
+        // The queue is not locked while calling the callback, which may add or cancel timers.
+        drop(queue);
+        (timer.callback)();
+    }
+
+    arm_next(hpet, &TIMER_QUEUE.lock());
+}
@@ -0,0 +1,244 @@
+//! DMA Remapping Reporting table (`DMAR`).
+//!
+//! Describes the DMA remapping hardware units (Intel VT-d `IOMMUs`) of the platform, the devices under the scope of
+//! each of them, and the memory regions that some devices keep using for DMA after boot (reserved memory regions,
+//! such as USB legacy emulation buffers), which must remain identity-mapped for these devices.
+//!
+//! Based on the Intel Virtualization Technology for Directed I/O specification, chapter 8.
+
+use core::{mem, slice};
+
+use crate::{io::acpi::sdt::ACPISDTHeader, sdt_getter};
+
+/// `DMAR` table, followed by a list of remapping structures (see [`DmarTable::entries`]).
+#[repr(C, packed)]
+pub struct DmarTable {
+    header: ACPISDTHeader,
+
+    // Maximum DMA physical address width supported by the platform, minus one.
+    host_address_width: u8,
+
+    // Bit 0: interrupt remapping supported. Bit 1: x2APIC opt-out. Bit 2: DMA control opt-in.
+    flags: u8,
+
+    reserved: [u8; 10],
+}
+
+impl DmarTable {
+    sdt_getter!("DMAR");
+
+    /// Maximum DMA physical address width supported by the platform, in bits.
+    pub fn host_address_width(&self) -> u8 {
+        self.host_address_width + 1
+    }
+
+    /// Whether the firmware requests that the remapping hardware stays enabled to protect memory during the boot
+    /// process (_DMA control opt-in_).
+    pub fn dma_control_opt_in(&self) -> bool {
+        self.flags & 0b100 != 0
+    }
+
+    /// Returns an iterator over the remapping structures of the table.
+    pub fn entries(&self) -> DmarEntries<'_> {
+        let table = unsafe {
+            slice::from_raw_parts(
+                (self as *const Self).cast::<u8>(),
+                self.header.length as usize,
+            )
+        };
+
+        DmarEntries {
+            bytes: table.get(mem::size_of::<Self>()..).unwrap_or_default(),
+        }
+    }
+}
+
+/// Remapping structure of the [`DmarTable`].
+#[derive(Clone, Copy, Debug)]
+pub enum DmarEntry<'t> {
+    /// DMA Remapping Hardware Unit Definition (`DRHD`).
+    HardwareUnit(DmarHardwareUnit<'t>),
+
+    /// Reserved Memory Region Reporting (`RMRR`).
+    ReservedMemory(DmarReservedMemory<'t>),
+
+    /// Another kind of remapping structure, identified by its type.
+    Other(u16),
+}
+
+/// Remapping hardware unit, and the devices under its scope.
+#[derive(Clone, Copy, Debug)]
+pub struct DmarHardwareUnit<'t> {
+    flags: u8,
+
+    /// PCI segment of the devices under the scope of the unit.
+    pub segment: u16,
+
+    /// Physical address of the registers of the unit.
+    pub register_base: u64,
+
+    scopes: &'t [u8],
+}
+
+impl<'t> DmarHardwareUnit<'t> {
+    /// Whether the unit covers every device of its segment that is not covered by another unit.
+    ///
+    /// Such a unit has no device scope, except for I/O APICs and HPETs.
+    pub fn include_pci_all(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    pub fn device_scopes(&self) -> DeviceScopes<'t> {
+        DeviceScopes { bytes: self.scopes }
+    }
+}
+
+/// Memory region used for DMA by some devices, which must be identity-mapped for them.
+#[derive(Clone, Copy, Debug)]
+pub struct DmarReservedMemory<'t> {
+    pub segment: u16,
+
+    /// Physical address of the first byte of the region.
+    pub base: u64,
+
+    /// Physical address of the last byte of the region.
+    pub limit: u64,
+
+    scopes: &'t [u8],
+}
+
+impl<'t> DmarReservedMemory<'t> {
+    pub fn device_scopes(&self) -> DeviceScopes<'t> {
+        DeviceScopes { bytes: self.scopes }
+    }
+}
+
+/// Kind of device described by a [`DeviceScope`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceScopeType {
+    /// PCI endpoint device.
+    Endpoint,
+
+    /// PCI-PCI bridge, and every device below it.
+    Bridge,
+
+    IoApic,
+
+    Hpet,
+
+    AcpiNamespaceDevice,
+
+    Unknown(u8),
+}
+
+impl From<u8> for DeviceScopeType {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => Self::Endpoint,
+            2 => Self::Bridge,
+            3 => Self::IoApic,
+            4 => Self::Hpet,
+            5 => Self::AcpiNamespaceDevice,
+            _ => Self::Unknown(value),
+        }
+    }
+}
+
+/// Device (or hierarchy of devices) under the scope of a remapping structure.
+#[derive(Clone, Copy, Debug)]
+pub struct DeviceScope<'t> {
+    pub kind: DeviceScopeType,
+
+    /// I/O APIC or HPET identifier, or ACPI device number, depending on the `kind` of the device.
+    pub enumeration_id: u8,
+
+    /// Bus number of the first hop of the path.
+    pub start_bus: u8,
+
+    path: &'t [u8],
+}
+
+impl<'t> DeviceScope<'t> {
+    /// Path from the `start_bus` to the device, as a list of `(device, function)` hops.
+    ///
+    /// The bus of each hop (but the first one) is the secondary bus of the bridge of the previous hop.
+    pub fn path(&self) -> impl Iterator<Item = (u8, u8)> + 't {
+        self.path.chunks_exact(2).map(|hop| (hop[0], hop[1]))
+    }
+}
+
+/// Iterator over the remapping structures of a [`DmarTable`].
+pub struct DmarEntries<'t> {
+    bytes: &'t [u8],
+}
+
+impl<'t> Iterator for DmarEntries<'t> {
+    type Item = DmarEntry<'t>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let kind = read_u16(self.bytes, 0)?;
+        let length = usize::from(read_u16(self.bytes, 2)?);
+
+        if length < 4 || length > self.bytes.len() {
+            return None;
+        }
+
+        let (entry, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+
+        Some(match kind {
+            0 => DmarEntry::HardwareUnit(DmarHardwareUnit {
+                flags: entry.get(4).copied()?,
+                segment: read_u16(entry, 6)?,
+                register_base: read_u64(entry, 8)?,
+                scopes: entry.get(16..)?,
+            }),
+            1 => DmarEntry::ReservedMemory(DmarReservedMemory {
+                segment: read_u16(entry, 6)?,
+                base: read_u64(entry, 8)?,
+                limit: read_u64(entry, 16)?,
+                scopes: entry.get(24..)?,
+            }),
+            _ => DmarEntry::Other(kind),
+        })
+    }
+}
+
+/// Iterator over the device scopes of a remapping structure.
+pub struct DeviceScopes<'t> {
+    bytes: &'t [u8],
+}
+
+impl<'t> Iterator for DeviceScopes<'t> {
+    type Item = DeviceScope<'t>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let length = usize::from(*self.bytes.get(1)?);
+
+        if length < 6 || length > self.bytes.len() {
+            return None;
+        }
+
+        let (scope, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+
+        Some(DeviceScope {
+            kind: DeviceScopeType::from(scope[0]),
+            enumeration_id: scope[4],
+            start_bus: scope[5],
+            path: &scope[6..],
+        })
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
@@ -0,0 +1,635 @@
+//! ACPI Embedded Controller (`EC`).
+//!
+//! On laptops, the embedded controller handles the battery, the AC adapter, the lid switch and the thermal sensors.
+//! It exposes a 256-byte address space, accessed through a pair of I/O ports: a command / status port and a data port.
+//! These ports are described by the Embedded Controller Boot Resources Table (`ECDT`) when the firmware provides one,
+//! and by the resources (`_CRS`) of the embedded controller device (`PNP0C09`) declared in the `DSDT` otherwise.
+//!
+//! When the controller has an event to report, it sets the `SCI_EVT` status bit (and raises an SCI, through a
+//! general-purpose event). The event is identified by issuing a query command, which returns a query number. The
+//! firmware normally handles a query in a `_Qxx` control method; as AML is not evaluated, drivers register handlers
+//! for the query numbers they care about (see [`register_ec_query_handler`]). Pending events are drained by
+//! [`ec_process_events`], deferred from the SCI handler, or from a periodic timer when SCIs cannot be used (see
+//! [`ec_events_init`]). Unhandled events are discarded, so that the controller does not keep the SCI asserted.
+//!
+//! Based on the ACPI specification, chapters 5.2.15, 6.4 and 12.
+
+use core::{mem, slice, time::Duration};
+
+use conquer_once::spin::OnceCell;
+use fzproc_macros::interrupt_handler;
+use spin::Mutex;
+
+use crate::errors::{CanFail, EcError};
+use crate::io::acpi::fadt::fadt;
+use crate::io::acpi::sdt::{table_checksum, ACPISDTHeader};
+use crate::io::acpi::ACPIAddress;
+use crate::io::{inb, outb, IOPort};
+use crate::irq::{deferred::defer, manager::get_interrupt_manager, InterruptStackFrame};
+use crate::mem::get_physical_memory;
+use crate::time::timer::periodic;
+use crate::x86::apic::{
+    io_apic::get_all_io_apics, local_apic::VectorPriorityClass, mp_table::IOApicIntPin,
+};
+use crate::x86::hypervisor::hypervisor_quirks;
+use crate::{info, sdt_getter};
+
+/// Default command / status port of the embedded controller.
+pub const EC_DEFAULT_COMMAND_PORT: u16 = 0x66;
+
+/// Default data port of the embedded controller.
+pub const EC_DEFAULT_DATA_PORT: u16 = 0x62;
+
+/// Output buffer full: data is available on the data port.
+const EC_STATUS_OBF: u8 = 1 << 0;
+
+/// Input buffer full: the controller has not consumed the last byte written yet.
+const EC_STATUS_IBF: u8 = 1 << 1;
+
+/// An event is pending, and must be identified with a query command.
+const EC_STATUS_SCI_EVT: u8 = 1 << 5;
+
+const EC_CMD_READ: u8 = 0x80;
+const EC_CMD_WRITE: u8 = 0x81;
+const EC_CMD_QUERY: u8 = 0x84;
+
+/// Number of status polls before a transaction is considered timed out.
+const EC_TIMEOUT_LOOPS: u32 = 100_000;
+
+/// Maximum number of events processed by a single call to [`ec_process_events`].
+const EC_MAX_EVENTS: usize = 64;
+
+/// Interval at which pending events are drained, when the controller cannot raise SCIs.
+const EC_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// `ACPIAddress` address space identifier of the system I/O space.
+const ACPI_ADDRESS_SPACE_IO: u8 = 1;
+
+/// `Name (_HID, EisaId ("PNP0C09"))`, as encoded in AML: the hardware identifier of embedded controllers.
+const AML_EC_HID: [u8; 10] = [0x08, b'_', b'H', b'I', b'D', 0x0C, 0x41, 0xD0, 0x0C, 0x09];
+
+/// `Name (_CRS, Buffer (...) {...})`, as encoded in AML.
+const AML_CRS_BUFFER: [u8; 6] = [0x08, b'_', b'C', b'R', b'S', 0x11];
+
+/// `Name (_GPE, ...)`, as encoded in AML.
+const AML_GPE_NAME: [u8; 5] = [0x08, b'_', b'G', b'P', b'E'];
+
+/// `Device` opcode, as encoded in AML.
+const AML_DEVICE_OP: [u8; 2] = [0x5B, 0x82];
+
+/// Maximum distance between a `Device` opcode and the `_HID` of the device (package length and name path).
+const AML_DEVICE_HEADER_MAX: usize = 64;
+
+/// Small resource descriptor of an I/O port range.
+const RESOURCE_IO_PORT: u8 = 0x47;
+
+/// Type of the small resource descriptor ending a resource template.
+const RESOURCE_END_TAG_TYPE: u8 = 0xF;
+
+static EMBEDDED_CONTROLLER: OnceCell<EmbeddedController> = OnceCell::uninit();
+
+/// General-purpose event of the controller, once its SCI is handled (see [`ec_events_init`]).
+static EC_GPE: OnceCell<GpeBit> = OnceCell::uninit();
+
+/// Handler of each query number.
+static EC_QUERY_HANDLERS: Mutex<[Option<fn(u8)>; 256]> = Mutex::new([None; 256]);
+
+/// Embedded Controller Boot Resources Table (`ECDT`), followed by the null-terminated namespace path of the
+/// controller.
+#[repr(C, packed)]
+pub struct EcdtTable {
+    header: ACPISDTHeader,
+
+    // Command / status register.
+    ec_control: ACPIAddress,
+
+    // Data register.
+    ec_data: ACPIAddress,
+
+    uid: u32,
+
+    // General-purpose event bit used by the controller to raise SCIs.
+    gpe_bit: u8,
+}
+
+impl EcdtTable {
+    sdt_getter!("ECDT");
+
+    /// Returns the I/O ports of the controller (command / status, data).
+    ///
+    /// Returns `None` if the registers are not located in the system I/O space.
+    pub fn ports(&self) -> Option<(u16, u16)> {
+        let (control, data) = (&self.ec_control, &self.ec_data);
+
+        if control.address_space_id != ACPI_ADDRESS_SPACE_IO
+            || data.address_space_id != ACPI_ADDRESS_SPACE_IO
+        {
+            return None;
+        }
+
+        Some((
+            u16::try_from(control.address).ok()?,
+            u16::try_from(data.address).ok()?,
+        ))
+    }
+
+    /// General-purpose event bit used by the controller to raise SCIs.
+    pub fn gpe_bit(&self) -> u8 {
+        self.gpe_bit
+    }
+}
+
+/// Resources of the embedded controller device (`PNP0C09`) declared in the `DSDT`.
+///
+/// AML is not evaluated: only a `_CRS` and a `_GPE` declared as constants (rather than computed by control methods)
+/// are found, which is what firmwares do in practice.
+#[derive(Clone, Copy, Debug, Default)]
+struct EcDeviceResources {
+    /// I/O ports of the controller (command / status, data).
+    ports: Option<(u16, u16)>,
+
+    /// General-purpose event bit used by the controller to raise SCIs.
+    gpe: Option<u8>,
+}
+
+impl EcDeviceResources {
+    /// Looks for the embedded controller device in the `DSDT`, and reads its resources.
+    ///
+    /// Returns `None` if no embedded controller device is declared.
+    fn from_dsdt() -> Option<Self> {
+        let dsdt = fadt()?.dsdt()?;
+        let header = unsafe { &*(get_physical_memory(dsdt) as *const ACPISDTHeader) };
+
+        if header.signature != *b"DSDT" || !table_checksum(header) {
+            return None;
+        }
+
+        let table =
+            unsafe { slice::from_raw_parts(get_physical_memory(dsdt), header.length as usize) };
+        let aml = table.get(mem::size_of::<ACPISDTHeader>()..)?;
+
+        let hid = find_bytes(aml, &AML_EC_HID)?;
+        let device_op = aml[hid.saturating_sub(AML_DEVICE_HEADER_MAX)..hid]
+            .windows(AML_DEVICE_OP.len())
+            .rposition(|window| window == AML_DEVICE_OP)?
+            + hid.saturating_sub(AML_DEVICE_HEADER_MAX);
+
+        let body_start = device_op + AML_DEVICE_OP.len();
+        let (device_len, _) = aml_pkg_length(&aml[body_start..])?;
+        let device = aml.get(body_start..body_start + device_len)?;
+
+        Some(Self {
+            ports: find_bytes(device, &AML_CRS_BUFFER)
+                .and_then(|crs| aml_buffer(&device[crs + AML_CRS_BUFFER.len()..]))
+                .and_then(resource_io_ports),
+            gpe: find_bytes(device, &AML_GPE_NAME)
+                .and_then(|gpe| aml_integer(&device[gpe + AML_GPE_NAME.len()..]))
+                .and_then(|(gpe, _)| u8::try_from(gpe).ok()),
+        })
+    }
+}
+
+/// Bit of the general-purpose event block 0 (see [`FadtTable::gpe0_block`]).
+///
+/// [`FadtTable::gpe0_block`]: crate::io::acpi::fadt::FadtTable::gpe0_block
+#[derive(Clone, Copy, Debug)]
+struct GpeBit {
+    status: IOPort,
+    enable: IOPort,
+    mask: u8,
+}
+
+impl GpeBit {
+    /// Returns the bit `gpe` of the general-purpose event block 0 described by the `FADT`.
+    fn new(gpe: u8) -> Option<Self> {
+        let (block, len) = fadt()?.gpe0_block()?;
+
+        // The status registers take the first half of the block, the enable registers the second half.
+        let half = len / 2;
+        if gpe / 8 >= half {
+            return None;
+        }
+
+        let status = block + u16::from(gpe / 8);
+
+        Some(Self {
+            status: IOPort::from(status),
+            enable: IOPort::from(status + u16::from(half)),
+            mask: 1 << (gpe % 8),
+        })
+    }
+
+    fn pending(self) -> bool {
+        inb(self.status) & self.mask != 0
+    }
+
+    /// Clears the status of the event (write-one-to-clear).
+    fn clear(self) {
+        outb(self.status, self.mask);
+    }
+
+    fn enable(self) {
+        outb(self.enable, inb(self.enable) | self.mask);
+    }
+}
+
+/// Embedded controller, accessed through its I/O ports.
+///
+/// Transactions are serialized: a command and its data bytes must not be interleaved with another command.
+#[derive(Debug)]
+pub struct EmbeddedController {
+    command: IOPort,
+    data: IOPort,
+    gpe: Option<u8>,
+    lock: Mutex<()>,
+}
+
+impl EmbeddedController {
+    pub fn new(command: u16, data: u16, gpe: Option<u8>) -> Self {
+        Self {
+            command: IOPort::from(command),
+            data: IOPort::from(data),
+            gpe,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// General-purpose event bit used by the controller to raise SCIs, if known.
+    pub fn gpe(&self) -> Option<u8> {
+        self.gpe
+    }
+
+    /// Reads the byte at `address` in the address space of the controller.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`EcError::Timeout`] if the controller does not respond.
+    pub fn read(&self, address: u8) -> Result<u8, EcError> {
+        let _guard = self.lock.lock();
+
+        self.send_command(EC_CMD_READ)?;
+        self.write_data(address)?;
+        self.read_data()
+    }
+
+    /// Writes `value` at `address` in the address space of the controller.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`EcError::Timeout`] if the controller does not respond.
+    pub fn write(&self, address: u8, value: u8) -> CanFail<EcError> {
+        let _guard = self.lock.lock();
+
+        self.send_command(EC_CMD_WRITE)?;
+        self.write_data(address)?;
+        self.write_data(value)?;
+        self.wait_input_empty()
+    }
+
+    /// Returns whether the controller has an event to report.
+    pub fn event_pending(&self) -> bool {
+        self.status() & EC_STATUS_SCI_EVT != 0
+    }
+
+    /// Identifies the pending event, and acknowledges it.
+    ///
+    /// Returns the query number of the event, or `None` if no event was pending.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`EcError::Timeout`] if the controller does not respond.
+    pub fn query(&self) -> Result<Option<u8>, EcError> {
+        let _guard = self.lock.lock();
+
+        if !self.event_pending() {
+            return Ok(None);
+        }
+
+        self.send_command(EC_CMD_QUERY)?;
+
+        // A query number of 0 means that no event was pending.
+        match self.read_data()? {
+            0 => Ok(None),
+            query => Ok(Some(query)),
+        }
+    }
+
+    fn status(&self) -> u8 {
+        inb(self.command)
+    }
+
+    fn send_command(&self, command: u8) -> CanFail<EcError> {
+        self.wait_input_empty()?;
+        outb(self.command, command);
+
+        Ok(())
+    }
+
+    fn write_data(&self, value: u8) -> CanFail<EcError> {
+        self.wait_input_empty()?;
+        outb(self.data, value);
+
+        Ok(())
+    }
+
+    fn read_data(&self) -> Result<u8, EcError> {
+        self.wait_output_full()?;
+
+        Ok(inb(self.data))
+    }
+
+    fn wait_input_empty(&self) -> CanFail<EcError> {
+        self.wait_status(|status| status & EC_STATUS_IBF == 0)
+    }
+
+    fn wait_output_full(&self) -> CanFail<EcError> {
+        self.wait_status(|status| status & EC_STATUS_OBF != 0)
+    }
+
+    fn wait_status(&self, ready: impl Fn(u8) -> bool) -> CanFail<EcError> {
+        for _ in 0..EC_TIMEOUT_LOOPS {
+            if ready(self.status()) {
+                return Ok(());
+            }
+
+            core::hint::spin_loop();
+        }
+
+        Err(EcError::Timeout)
+    }
+}
+
+/// Returns the embedded controller of the platform, if [`ec_init`] found one.
+pub fn embedded_controller() -> Option<&'static EmbeddedController> {
+    EMBEDDED_CONTROLLER.get()
+}
+
+/// Locates the embedded controller, and discards the events it reported before the kernel was ready to handle them.
+///
+/// The ports are read from the `ECDT` if the firmware provides one, or from the resources of the embedded controller
+/// device declared in the `DSDT` otherwise. The default ports are only used if the device is declared, but its
+/// resources are computed by a control method (unless the hypervisor is known not to emulate an embedded controller,
+/// see [`hypervisor_quirks`]).
+///
+/// # Errors
+///
+/// Returns [`EcError::NotPresent`] if the firmware does not describe an embedded controller, or if no controller
+/// responds on the ports.
+///
+/// # Panics
+///
+/// Panics if called before [`acpi_init`].
+///
+/// [`acpi_init`]: crate::io::acpi::acpi_init
+pub fn ec_init() -> CanFail<EcError> {
+    let (command, data, gpe) = match EcdtTable::load() {
+        Some(ecdt) => {
+            let (command, data) = ecdt.ports().ok_or(EcError::NotPresent)?;
+            (command, data, Some(ecdt.gpe_bit()))
+        }
+        None => {
+            let device = EcDeviceResources::from_dsdt().ok_or(EcError::NotPresent)?;
+
+            match device.ports {
+                Some((command, data)) => (command, data, device.gpe),
+                None if hypervisor_quirks().no_embedded_controller => {
+                    return Err(EcError::NotPresent)
+                }
+                None => (EC_DEFAULT_COMMAND_PORT, EC_DEFAULT_DATA_PORT, device.gpe),
+            }
+        }
+    };
+
+    let ec = EmbeddedController::new(command, data, gpe);
+
+    // Nothing decodes the port: the bus floats high.
+    if ec.status() == 0xff {
+        return Err(EcError::NotPresent);
+    }
+
+    // A legacy port that does not belong to an EC would not complete a transaction.
+    ec.read(0).map_err(|_| EcError::NotPresent)?;
+
+    let ec = EMBEDDED_CONTROLLER.get_or_init(|| ec);
+
+    info!(
+        "ec",
+        "embedded controller at ports {:#x}/{:#x} (gpe = {:?})",
+        command,
+        data,
+        ec.gpe()
+    );
+
+    let discarded = ec_process_events();
+    if discarded != 0 {
+        info!("ec", "{} pending events processed", discarded);
+    }
+
+    Ok(())
+}
+
+/// Arranges for the events reported by the embedded controller to be processed (see [`ec_process_events`]).
+///
+/// If the platform signals ACPI events with SCIs, and the general-purpose event of the controller is known, events are
+/// processed when the controller raises its SCI. They are polled with a periodic timer otherwise. In both cases, the
+/// processing is deferred (see [`defer`]).
+///
+/// # Errors
+///
+/// Returns [`EcError::NotPresent`] if no embedded controller was found, or if neither the SCI nor a timer can be
+/// used.
+pub fn ec_events_init() -> CanFail<EcError> {
+    let ec = embedded_controller().ok_or(EcError::NotPresent)?;
+
+    if let Some(gpe) = ec.gpe() {
+        if ec_sci_init(gpe) {
+            info!("ec", "events signaled by SCI (gpe = {:#x})", gpe);
+            return Ok(());
+        }
+    }
+
+    periodic(EC_POLL_INTERVAL, || defer(ec_drain_events)).map_err(|_| EcError::NotPresent)?;
+    info!("ec", "events polled every {:?}", EC_POLL_INTERVAL);
+
+    Ok(())
+}
+
+/// Routes the SCI to [`sci_irq_entry`], and enables the general-purpose event `gpe` of the controller.
+///
+/// Returns `false` if the SCI cannot be used.
+fn ec_sci_init(gpe: u8) -> bool {
+    let Some(fadt) = fadt() else {
+        return false;
+    };
+    if !fadt.sci_enabled() {
+        return false;
+    }
+
+    let (Some(gpe), Ok(pin)) = (GpeBit::new(gpe), u8::try_from(fadt.sci_interrupt())) else {
+        return false;
+    };
+    let Some(io_apics) = get_all_io_apics() else {
+        return false;
+    };
+    let Ok(vector) =
+        get_interrupt_manager().allocate_vector(VectorPriorityClass::DEVICE, "acpi-sci")
+    else {
+        return false;
+    };
+
+    EC_GPE.init_once(|| gpe);
+    for io_apic in io_apics {
+        io_apic
+            .1
+            .lock()
+            .map_pin_to_irq(IOApicIntPin::from(pin), vector);
+    }
+    get_interrupt_manager().register_static_handler(vector, sci_irq_entry);
+
+    gpe.clear();
+    gpe.enable();
+
+    true
+}
+
+/// System Control Interrupt entry point.
+///
+/// Only the general-purpose event of the embedded controller is handled: its status is cleared, and the events of the
+/// controller are processed later on.
+#[interrupt_handler]
+pub fn sci_irq_entry(frame: InterruptStackFrame) {
+    let Some(gpe) = EC_GPE.get() else {
+        return;
+    };
+
+    if gpe.pending() {
+        gpe.clear();
+        defer(ec_drain_events);
+    }
+}
+
+fn ec_drain_events() {
+    ec_process_events();
+}
+
+/// Registers the handler of the events identified by `query`.
+///
+/// The handler is called with the query number, from [`ec_process_events`]. It replaces any handler previously
+/// registered for this query number.
+pub fn register_ec_query_handler(query: u8, handler: fn(u8)) {
+    EC_QUERY_HANDLERS.lock()[usize::from(query)] = Some(handler);
+}
+
+/// Processes the events pending on the embedded controller, calling the registered handlers.
+///
+/// Called when the controller raises an SCI, or periodically (see [`ec_events_init`]). Returns the number of events
+/// processed.
+pub fn ec_process_events() -> usize {
+    let Some(ec) = embedded_controller() else {
+        return 0;
+    };
+
+    let mut processed = 0;
+
+    while processed < EC_MAX_EVENTS {
+        let Ok(Some(query)) = ec.query() else {
+            break;
+        };
+
+        let handler = EC_QUERY_HANDLERS.lock()[usize::from(query)];
+        if let Some(handler) = handler {
+            handler(query);
+        }
+
+        processed += 1;
+    }
+
+    processed
+}
+
+/// Returns the offset of the first occurrence of `pattern` in `bytes`.
+fn find_bytes(bytes: &[u8], pattern: &[u8]) -> Option<usize> {
+    bytes
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+}
+
+/// Decodes the AML package length at the start of `aml`.
+///
+/// Returns the length (which includes the encoding of the length itself), and the size of its encoding.
+fn aml_pkg_length(aml: &[u8]) -> Option<(usize, usize)> {
+    let lead = *aml.first()?;
+    let follow = usize::from(lead >> 6);
+
+    if follow == 0 {
+        return Some((usize::from(lead & 0x3f), 1));
+    }
+
+    let mut length = usize::from(lead & 0xf);
+    for (i, &byte) in aml.get(1..=follow)?.iter().enumerate() {
+        length |= usize::from(byte) << (4 + 8 * i);
+    }
+
+    Some((length, follow + 1))
+}
+
+/// Decodes the AML integer constant at the start of `aml`.
+///
+/// Returns the value, and the size of its encoding.
+fn aml_integer(aml: &[u8]) -> Option<(u64, usize)> {
+    let size = match *aml.first()? {
+        0x00 => return Some((0, 1)),
+        0x01 => return Some((1, 1)),
+        0xff => return Some((u64::MAX, 1)),
+        0x0a => 1,
+        0x0b => 2,
+        0x0c => 4,
+        0x0e => 8,
+        _ => return None,
+    };
+
+    let mut value = [0u8; 8];
+    value[..size].copy_from_slice(aml.get(1..=size)?);
+
+    Some((u64::from_le_bytes(value), size + 1))
+}
+
+/// Returns the content of the AML buffer whose package length starts `aml` (right after the `Buffer` opcode).
+fn aml_buffer(aml: &[u8]) -> Option<&[u8]> {
+    let (length, length_size) = aml_pkg_length(aml)?;
+    let (_, size_size) = aml_integer(&aml[length_size..])?;
+
+    aml.get(length_size + size_size..length)
+}
+
+/// Returns the I/O ports of an embedded controller (command / status, data), from its resource template.
+///
+/// The template lists the data port first, and the command / status port second.
+fn resource_io_ports(resources: &[u8]) -> Option<(u16, u16)> {
+    let mut ports = [0u16; 2];
+    let mut found = 0;
+    let mut offset = 0;
+
+    while let Some(&tag) = resources.get(offset) {
+        // Large resource descriptors have their 16-bit length after the tag.
+        if tag & 0x80 != 0 {
+            let length = resources.get(offset + 1..offset + 3)?;
+            offset += 3 + usize::from(u16::from_le_bytes([length[0], length[1]]));
+            continue;
+        }
+
+        if (tag >> 3) & 0xf == RESOURCE_END_TAG_TYPE {
+            break;
+        }
+
+        if tag == RESOURCE_IO_PORT && found < ports.len() {
+            let base = resources.get(offset + 2..offset + 4)?;
+            ports[found] = u16::from_le_bytes([base[0], base[1]]);
+            found += 1;
+        }
+
+        offset += 1 + usize::from(tag & 0x7);
+    }
+
+    (found == ports.len()).then_some((ports[1], ports[0]))
+}
@@ -0,0 +1,147 @@
+//! Fixed ACPI Description Table (`FADT`).
+//!
+//! Only the fields required to reset the system through the ACPI reset register, and to handle the System Control
+//! Interrupt (`SCI`), are described.
+//!
+//! Based on the ACPI specification, section 5.2.9.
+
+use crate::io::acpi::sdt::ACPISDTHeader;
+use crate::io::acpi::{ACPIAddress, RSDP};
+use crate::io::{inw, outb, IOPort};
+use crate::mem::{get_physical_memory, PhyAddr};
+use crate::sdt_getter;
+
+/// `PM1` control register bit set when ACPI events are signaled with SCIs (rather than SMIs).
+const PM1_CNT_SCI_EN: u16 = 1 << 0;
+
+/// `FADT` flag set when the reset register is supported.
+const FADT_RESET_REG_SUP: u32 = 1 << 10;
+
+/// Offset of the reset value in the `FADT`: the table must be at least this long for the reset register to be used.
+const FADT_RESET_VALUE_END: u32 = 129;
+
+/// `ACPIAddress` address space identifier of the system memory.
+const ACPI_ADDRESS_SPACE_MEMORY: u8 = 0;
+
+/// `ACPIAddress` address space identifier of the system I/O space.
+const ACPI_ADDRESS_SPACE_IO: u8 = 1;
+
+/// `FADT` table, up to the reset register.
+#[repr(C, packed)]
+pub struct FadtTable {
+    header: ACPISDTHeader,
+
+    firmware_ctrl: u32,
+
+    // Physical address of the `DSDT`.
+    dsdt: u32,
+
+    reserved_0: u8,
+    preferred_pm_profile: u8,
+
+    // Interrupt pin (ISA IRQ) of the System Control Interrupt.
+    sci_int: u16,
+
+    // Fields describing the power management blocks, not used.
+    reserved_1: [u8; 16],
+
+    // I/O port of the PM1a control register block.
+    pm1a_cnt_blk: u32,
+
+    reserved_2: [u8; 12],
+
+    // I/O port of the general-purpose event block 0.
+    gpe0_blk: u32,
+
+    reserved_3: [u8; 8],
+
+    // Length of the general-purpose event block 0, in bytes.
+    gpe0_blk_len: u8,
+
+    reserved_4: [u8; 19],
+
+    // Fixed feature flags.
+    flags: u32,
+
+    // Register written to reset the system.
+    reset_reg: ACPIAddress,
+
+    // Value written to the reset register.
+    reset_value: u8,
+}
+
+impl FadtTable {
+    sdt_getter!("FACP");
+
+    /// Physical address of the `DSDT`, if provided.
+    pub fn dsdt(&self) -> Option<PhyAddr> {
+        (self.dsdt != 0).then(|| PhyAddr::new(u64::from(self.dsdt)))
+    }
+
+    /// Interrupt pin of the System Control Interrupt (`SCI`), raised to signal ACPI events.
+    pub fn sci_interrupt(&self) -> u16 {
+        self.sci_int
+    }
+
+    /// Whether ACPI events are signaled with SCIs: the firmware switches the platform to this mode when asked to by
+    /// the operating system (which is not done yet).
+    pub fn sci_enabled(&self) -> bool {
+        let Ok(port) = u16::try_from(self.pm1a_cnt_blk) else {
+            return false;
+        };
+
+        port != 0 && inw(IOPort::from(port)) & PM1_CNT_SCI_EN != 0
+    }
+
+    /// Returns the I/O port and the length (in bytes) of the general-purpose event block 0, if implemented.
+    ///
+    /// The first half of the block holds the status registers, the second half the enable registers.
+    pub fn gpe0_block(&self) -> Option<(u16, u8)> {
+        let port = u16::try_from(self.gpe0_blk).ok()?;
+
+        (port != 0 && self.gpe0_blk_len != 0).then_some((port, self.gpe0_blk_len))
+    }
+
+    /// Whether the firmware provides a reset register.
+    pub fn reset_supported(&self) -> bool {
+        self.header.length >= FADT_RESET_VALUE_END && self.flags & FADT_RESET_REG_SUP != 0
+    }
+
+    /// Resets the system by writing the reset value to the reset register.
+    ///
+    /// Returns `false` if the reset register is not supported, or is located in an unsupported address space (such
+    /// as the PCI configuration space). Otherwise, the system should reset shortly after the write.
+    pub fn reset(&self) -> bool {
+        if !self.reset_supported() {
+            return false;
+        }
+
+        let (space, address, value) = (
+            self.reset_reg.address_space_id,
+            self.reset_reg.address,
+            self.reset_value,
+        );
+
+        match space {
+            ACPI_ADDRESS_SPACE_IO => {
+                let Ok(port) = u16::try_from(address) else {
+                    return false;
+                };
+                outb(IOPort::from(port), value);
+            }
+            ACPI_ADDRESS_SPACE_MEMORY => unsafe {
+                core::ptr::write_volatile(get_physical_memory(PhyAddr::new(address)), value);
+            },
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+/// Returns the `FADT`, if ACPI was initialized and the firmware provides one.
+pub fn fadt() -> Option<&'static FadtTable> {
+    RSDP.get()?;
+
+    FadtTable::load().map(|table| &*table)
+}
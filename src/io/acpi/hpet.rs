@@ -47,6 +47,15 @@ pub struct HPETClock<'t> {
 }
 
 impl<'t> HPETClock<'t> {
+    /// Offset of the `Main Counter Value` register.
+    const MAIN_COUNTER_OFFSET: usize = 0xf0;
+
+    /// Offset of the registers of the first timer.
+    const TIMER_REGISTERS_OFFSET: usize = 0x100;
+
+    /// Size of the registers of each timer.
+    const TIMER_REGISTERS_SIZE: usize = 0x20;
+
     /// Creates a `HPETClock` from its ACPI description as a [`HPETDescriptionTable`].
     pub fn from_acpi_table(desc: HpetInfo) -> Self {
         let registers: &mut HPETMemRegisters = unsafe { mem::transmute(desc.base_address) };
@@ -87,6 +96,57 @@ impl<'t> HPETClock<'t> {
         self.registers.__num_tim_cap() + 1
     }
 
+    /// Returns the current value of the main counter, in clock ticks.
+    pub fn main_counter(&self) -> u64 {
+        unsafe { core::ptr::read_volatile(self.register(Self::MAIN_COUNTER_OFFSET)) }
+    }
+
+    /// Converts a duration in microseconds into a number of clock ticks.
+    pub fn ticks_from_micros(&self, micros: f64) -> u64 {
+        (micros * self.clk_freq) as u64
+    }
+
+    /// Returns the bitmap of the I/O APIC pins to which the interrupt of timer `timer` can be routed (bit `n` is set
+    /// if the interrupt can be routed to pin `n`).
+    pub fn timer_route_capabilities(&self, timer: u8) -> u32 {
+        let config = unsafe { core::ptr::read_volatile(self.timer_register(timer, 0)) };
+
+        (config >> 32) as u32
+    }
+
+    /// Configures timer `timer` to raise an edge-triggered interrupt on the I/O APIC pin `pin` each time the main
+    /// counter reaches the value of its comparator (see [`HPETClock::set_timer_comparator`]).
+    ///
+    /// The timer operates in non-periodic 64-bit mode.
+    pub fn setup_oneshot_timer(&self, timer: u8, pin: u8) {
+        let config_reg = self.timer_register(timer, 0);
+        let config = unsafe { core::ptr::read_volatile(config_reg) };
+
+        // Clears the interrupt type (edge-triggered), periodic mode, 32-bit mode, interrupt route and FSB bits.
+        let mut new_config = config & !(0x2 | 0x8 | 0x40 | 0x100 | 0x3e00 | 0x4000);
+        new_config |= (u64::from(pin) & 0x1f) << 9;
+        new_config |= 0x4;
+
+        unsafe { core::ptr::write_volatile(config_reg, new_config) };
+    }
+
+    /// Updates the comparator of timer `timer`, which raises an interrupt once the main counter reaches `value`.
+    pub fn set_timer_comparator(&self, timer: u8, value: u64) {
+        unsafe { core::ptr::write_volatile(self.timer_register(timer, 0x8), value) };
+    }
+
+    /// Returns a pointer to the register located at `offset` in the memory-mapped registers.
+    fn register(&self, offset: usize) -> *mut u64 {
+        (self.description_table.base_address + offset) as *mut u64
+    }
+
+    /// Returns a pointer to the register located at `offset` in the registers of timer `timer`.
+    fn timer_register(&self, timer: u8, offset: usize) -> *mut u64 {
+        self.register(
+            Self::TIMER_REGISTERS_OFFSET + usize::from(timer) * Self::TIMER_REGISTERS_SIZE + offset,
+        )
+    }
+
     /// Returns the current time indicated by the `HPETClock`, in microseconds.
     ///
     /// The clock is monotonic, two consecutive reads of the current time may return the same value
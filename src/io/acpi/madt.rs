@@ -0,0 +1,299 @@
+//! Multiple APIC Description Table (`MADT`).
+//!
+//! Describes the interrupt controllers of the platform: the local APIC of each processor, the I/O APICs and the
+//! global system interrupts (`GSIs`) they handle, how the legacy ISA interrupts are routed to them (_interrupt source
+//! overrides_), and which local APIC pins are connected to the `NMI` signal.
+//!
+//! Based on the ACPI specification, section 5.2.12.
+
+use core::{mem, slice};
+
+use crate::io::acpi::RSDP;
+use crate::{io::acpi::sdt::ACPISDTHeader, sdt_getter};
+
+/// `MADT` flag set when the system also has a dual 8259 `PIC` setup, which must be masked before enabling the
+/// `APIC`.
+const MADT_PCAT_COMPAT: u32 = 1 << 0;
+
+/// Local APIC flag set when the processor is ready to use.
+const LOCAL_APIC_ENABLED: u32 = 1 << 0;
+
+/// Local APIC flag set when the processor can be enabled at runtime (when it is not already enabled).
+const LOCAL_APIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// `ACPI Processor UID` matching every processor, in a [`MadtEntry::LocalApicNmi`].
+pub const MADT_ALL_PROCESSORS: u8 = 0xFF;
+
+/// `MADT` table, followed by a list of interrupt controller structures (see [`MadtTable::entries`]).
+#[repr(C, packed)]
+pub struct MadtTable {
+    header: ACPISDTHeader,
+
+    // 32-bit physical address of the local APIC of each processor.
+    local_apic_addr: u32,
+
+    // Bit 0: dual 8259 PIC setup (`PCAT_COMPAT`).
+    flags: u32,
+}
+
+impl MadtTable {
+    sdt_getter!("APIC");
+
+    /// Physical address of the local APIC of each processor.
+    ///
+    /// The 64-bit address provided by a [`MadtEntry::LocalApicAddressOverride`] is preferred over the 32-bit one of
+    /// the table header.
+    pub fn local_apic_addr(&self) -> u64 {
+        self.entries()
+            .find_map(|entry| match entry {
+                MadtEntry::LocalApicAddressOverride(addr) => Some(addr),
+                _ => None,
+            })
+            .unwrap_or(u64::from(self.local_apic_addr))
+    }
+
+    /// Whether the system also has a dual 8259 `PIC` setup.
+    pub fn pcat_compat(&self) -> bool {
+        self.flags & MADT_PCAT_COMPAT != 0
+    }
+
+    /// Returns an iterator over the interrupt controller structures of the table.
+    pub fn entries(&self) -> MadtEntries<'_> {
+        let table = unsafe {
+            slice::from_raw_parts(
+                (self as *const Self).cast::<u8>(),
+                self.header.length as usize,
+            )
+        };
+
+        MadtEntries {
+            bytes: table.get(mem::size_of::<Self>()..).unwrap_or_default(),
+        }
+    }
+
+    /// Returns an iterator over the processors that are usable (enabled, or that can be enabled at runtime).
+    pub fn processors(&self) -> impl Iterator<Item = MadtLocalApic> + '_ {
+        self.entries().filter_map(|entry| match entry {
+            MadtEntry::LocalApic(lapic) if lapic.usable() => Some(lapic),
+            _ => None,
+        })
+    }
+
+    /// Returns an iterator over the I/O APICs of the system.
+    pub fn io_apics(&self) -> impl Iterator<Item = MadtIoApic> + '_ {
+        self.entries().filter_map(|entry| match entry {
+            MadtEntry::IoApic(io_apic) => Some(io_apic),
+            _ => None,
+        })
+    }
+
+    /// Returns an iterator over the interrupt source overrides.
+    ///
+    /// ISA interrupts without an override are identity-mapped to the global system interrupts.
+    pub fn interrupt_overrides(&self) -> impl Iterator<Item = MadtInterruptOverride> + '_ {
+        self.entries().filter_map(|entry| match entry {
+            MadtEntry::InterruptSourceOverride(iso) => Some(iso),
+            _ => None,
+        })
+    }
+
+    /// Returns the local APIC `NMI` structures that apply to the processor whose local APIC id is `apic_id`.
+    pub fn local_apic_nmis(&self, apic_id: u8) -> impl Iterator<Item = MadtLocalApicNmi> + '_ {
+        let processor_id = self
+            .entries()
+            .find_map(|entry| match entry {
+                MadtEntry::LocalApic(lapic) if lapic.apic_id == apic_id => Some(lapic.processor_id),
+                _ => None,
+            })
+            .unwrap_or(MADT_ALL_PROCESSORS);
+
+        self.entries().filter_map(move |entry| match entry {
+            MadtEntry::LocalApicNmi(nmi)
+                if nmi.processor_id == processor_id || nmi.processor_id == MADT_ALL_PROCESSORS =>
+            {
+                Some(nmi)
+            }
+            _ => None,
+        })
+    }
+}
+
+/// Returns the `MADT`, if ACPI was initialized and the firmware provides one.
+pub fn madt() -> Option<&'static MadtTable> {
+    RSDP.get()?;
+
+    MadtTable::load().map(|table| &*table)
+}
+
+/// Interrupt controller structure of the [`MadtTable`].
+#[derive(Clone, Copy, Debug)]
+pub enum MadtEntry {
+    /// Processor Local APIC.
+    LocalApic(MadtLocalApic),
+
+    /// I/O APIC.
+    IoApic(MadtIoApic),
+
+    /// Interrupt Source Override.
+    InterruptSourceOverride(MadtInterruptOverride),
+
+    /// Local APIC `NMI`.
+    LocalApicNmi(MadtLocalApicNmi),
+
+    /// Local APIC Address Override: 64-bit physical address of the local APICs.
+    LocalApicAddressOverride(u64),
+
+    /// Another kind of interrupt controller structure, identified by its type.
+    Other(u8),
+}
+
+/// Local APIC of a processor.
+#[derive(Clone, Copy, Debug)]
+pub struct MadtLocalApic {
+    /// `ACPI Processor UID`, used to match the processor with the other structures.
+    pub processor_id: u8,
+
+    /// Local APIC id of the processor.
+    pub apic_id: u8,
+
+    flags: u32,
+}
+
+impl MadtLocalApic {
+    /// Whether the processor is enabled, or can be enabled at runtime.
+    pub fn usable(&self) -> bool {
+        self.flags & (LOCAL_APIC_ENABLED | LOCAL_APIC_ONLINE_CAPABLE) != 0
+    }
+}
+
+/// I/O APIC, and the range of global system interrupts it handles.
+#[derive(Clone, Copy, Debug)]
+pub struct MadtIoApic {
+    /// I/O APIC id.
+    pub id: u8,
+
+    /// Physical address of the registers of the I/O APIC.
+    pub addr: u32,
+
+    /// Global system interrupt of the first input pin of the I/O APIC.
+    pub gsi_base: u32,
+}
+
+/// Routing of an ISA interrupt to a global system interrupt different from its ISA number, or with non-standard
+/// electrical characteristics.
+#[derive(Clone, Copy, Debug)]
+pub struct MadtInterruptOverride {
+    /// ISA interrupt.
+    pub source: u8,
+
+    /// Global system interrupt signaled by the ISA interrupt.
+    pub gsi: u32,
+
+    /// Polarity and trigger mode of the interrupt.
+    pub flags: MadtIntFlags,
+}
+
+/// Local APIC pin connected to the `NMI` signal.
+#[derive(Clone, Copy, Debug)]
+pub struct MadtLocalApicNmi {
+    /// `ACPI Processor UID` of the processor, or [`MADT_ALL_PROCESSORS`].
+    pub processor_id: u8,
+
+    /// Polarity and trigger mode of the interrupt.
+    pub flags: MadtIntFlags,
+
+    /// Local APIC pin (`LINT0` or `LINT1`).
+    pub lint: u8,
+}
+
+/// `MPS INTI` flags, describing the electrical characteristics of an interrupt.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MadtIntFlags(u16);
+
+impl MadtIntFlags {
+    /// Whether the interrupt is active low.
+    ///
+    /// `None` if the interrupt conforms to the specification of the bus (active high for ISA interrupts).
+    pub fn active_low(self) -> Option<bool> {
+        match self.0 & 0b11 {
+            0b01 => Some(false),
+            0b11 => Some(true),
+            _ => None,
+        }
+    }
+
+    /// Whether the interrupt is level-triggered.
+    ///
+    /// `None` if the interrupt conforms to the specification of the bus (edge-triggered for ISA interrupts).
+    pub fn level_triggered(self) -> Option<bool> {
+        match (self.0 >> 2) & 0b11 {
+            0b01 => Some(false),
+            0b11 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// Iterator over the interrupt controller structures of a [`MadtTable`].
+pub struct MadtEntries<'t> {
+    bytes: &'t [u8],
+}
+
+impl<'t> Iterator for MadtEntries<'t> {
+    type Item = MadtEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let kind = *self.bytes.first()?;
+        let length = usize::from(*self.bytes.get(1)?);
+
+        if length < 2 || length > self.bytes.len() {
+            return None;
+        }
+
+        let (entry, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+
+        Some(match kind {
+            0 => MadtEntry::LocalApic(MadtLocalApic {
+                processor_id: *entry.get(2)?,
+                apic_id: *entry.get(3)?,
+                flags: read_u32(entry, 4)?,
+            }),
+            1 => MadtEntry::IoApic(MadtIoApic {
+                id: *entry.get(2)?,
+                addr: read_u32(entry, 4)?,
+                gsi_base: read_u32(entry, 8)?,
+            }),
+            2 => MadtEntry::InterruptSourceOverride(MadtInterruptOverride {
+                source: *entry.get(3)?,
+                gsi: read_u32(entry, 4)?,
+                flags: MadtIntFlags(read_u16(entry, 8)?),
+            }),
+            4 => MadtEntry::LocalApicNmi(MadtLocalApicNmi {
+                processor_id: *entry.get(2)?,
+                flags: MadtIntFlags(read_u16(entry, 3)?),
+                lint: *entry.get(5)?,
+            }),
+            5 => MadtEntry::LocalApicAddressOverride(read_u64(entry, 4)?),
+            _ => MadtEntry::Other(kind),
+        })
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
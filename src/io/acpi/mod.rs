@@ -9,14 +9,24 @@ use core::{mem, ptr, slice};
 
 use conquer_once::spin::OnceCell;
 
+use crate::mem::{get_physical_memory, PhyAddr};
 use crate::{error, info, println};
 
+pub mod dmar;
+pub mod ec;
+pub mod fadt;
 pub mod hpet;
+pub mod madt;
 pub mod sdt;
 
 /// Shared [`RSDPDescriptor`] initialized during ACPI setup.
 pub static RSDP: OnceCell<RSDPDescriptor> = OnceCell::uninit();
 
+/// Physical address of the [`RSDP`].
+static RSDP_ADDR: OnceCell<PhyAddr> = OnceCell::uninit();
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
 /// ACPI Generic Address Structure.
 ///
 /// Used to express register addresses within ACPI-defined tables.
@@ -131,41 +141,57 @@ pub struct RSDPDescriptorV2 {
     reserved: [u8; 3],
 }
 
+/// Locates the [`RSDPDescriptor`] in the BIOS memory area, and loads it.
+///
+/// # Panics
+///
+/// Panics if no `RSDP` is found.
 pub fn acpi_init() {
-    __load_rsdp();
+    let address = rsdp_scan().expect("failed to locate RSDP descriptor");
+    info!("acpi", "found RSDP descriptor at {}", address);
+
+    __load_rsdp(address);
 }
 
-/// Initialize the `RSDPDescriptor`.
-///
-/// The structure must be located in the physical memory.
-fn __load_rsdp() {
-    // We expect the structure to be in the [0xe0000 - 0xfffff] section of the
-    // physical memory.
-    let mut address = 0xe0000;
-
-    while address < 0xfffff {
-        let sig: &[u8];
-        unsafe {
-            sig = slice::from_raw_parts(address as *mut u8, 8);
-        }
+/// Loads the [`RSDPDescriptor`] located at `address` by an earlier boot stage (see [`crate::boot::info`]), instead
+/// of scanning the memory for it.
+pub fn acpi_init_from_rsdp(address: PhyAddr) {
+    info!("acpi", "using RSDP descriptor at {}", address);
 
-        // We look for the signature of the [`RSDPDescriptor`]
-        if sig == "RSD PTR ".as_bytes() {
-            info!("acpi", "found RSDP descriptor at {:#010x}", address);
-            break;
-        }
+    __load_rsdp(address);
+}
 
-        address += 8;
-    }
+/// Returns the physical address of the loaded [`RSDPDescriptor`], to be handed over to the next boot stage.
+pub fn rsdp_addr() -> Option<PhyAddr> {
+    RSDP_ADDR.get().copied()
+}
 
-    // We went through the whole segment without locating the signature.
-    if address >= 0xfffff {
-        panic!("failed to locate RSDP descriptor");
-    }
+/// Looks for the signature of the [`RSDPDescriptor`] in the `[0xe0000; 0xfffff]` area of the physical memory.
+fn rsdp_scan() -> Option<PhyAddr> {
+    (0xe0000..0xfffff)
+        .step_by(16)
+        .map(PhyAddr::new)
+        .find(|&address| {
+            let sig = unsafe { slice::from_raw_parts(get_physical_memory(address), 8) };
+
+            sig == RSDP_SIGNATURE
+        })
+}
+
+/// Initialize the `RSDPDescriptor`.
+///
+/// The structure must be located in the physical memory.
+fn __load_rsdp(address: PhyAddr) {
+    let ptr = get_physical_memory(address);
 
     // The first fields of the [`RSDPDescriptor`] are identical regardless of the revision.
     // So we cast it early as a V1 descriptor to check the revision first.
-    let rsdp: RSDPDescriptorV1 = unsafe { ptr::read(address as *const RSDPDescriptorV1) };
+    let rsdp: RSDPDescriptorV1 = unsafe { ptr::read(ptr as *const RSDPDescriptorV1) };
+
+    if rsdp.signature != *RSDP_SIGNATURE {
+        error!("acpi", "no RSDP descriptor at {}", address);
+        return;
+    }
 
     info!("acpi", "root system descriptor table at {:#010x}", unsafe {
         ptr::read_unaligned(ptr::addr_of!(rsdp.rsdt_addr))
@@ -185,7 +211,7 @@ fn __load_rsdp() {
         }
         2 => {
             info!("acpi", "ACPI version > 1.0");
-            let rsdp: RSDPDescriptorV2 = unsafe { ptr::read(address as *const RSDPDescriptorV2) };
+            let rsdp: RSDPDescriptorV2 = unsafe { ptr::read(ptr as *const RSDPDescriptorV2) };
 
             let check_checksum = __validate_checksum(&rsdp);
             if !check_checksum {
@@ -196,8 +222,11 @@ fn __load_rsdp() {
         }
         _ => {
             error!("acpi", "Invalid ACPI revision number");
+            return;
         }
     }
+
+    RSDP_ADDR.init_once(|| address);
 }
 
 /// Validate the checksum of a [`RSDPDescriptor`] header, regardless
@@ -1,6 +1,10 @@
 //! ACPI System Description Table.
 
-use core::ptr;
+use core::{mem, ptr};
+
+use crate::io::acpi::{RSDPDescriptor, RSDP};
+use crate::mem::{get_physical_memory, PhyAddr};
+use crate::{error, info};
 
 /// System Description Table header.
 ///
@@ -42,80 +46,139 @@ pub struct ACPISDTHeader {
 
 /// Implement a getter method for a System Description Table.
 ///
-/// Requires only the `signature` of the table as argument.
+/// Requires only the `signature` of the table as argument. The table is looked up using [`find_sdt`].
 ///
 /// # Panics
 ///
 /// Panics if the getter is called before loading the [`RSDPDescriptor`].
+///
+/// [`RSDPDescriptor`]: crate::io::acpi::RSDPDescriptor
 #[macro_export]
 macro_rules! sdt_getter {
     ($sig: literal) => {
         pub fn load() -> Option<&'static mut Self> {
-            let rsdp = $crate::io::acpi::RSDP
-                .get()
-                .expect("ACPI failure: tried to load description table before the main descriptor");
-
-            let (rsdt_addr, entry_count, entry_size) = match rsdp {
-                $crate::io::acpi::RSDPDescriptor::V1(rsdp) => {
-                    let rsdt_header =
-                        unsafe { core::ptr::read(rsdp.rsdt_addr as *const ACPISDTHeader) };
-                    (
-                        rsdp.rsdt_addr,
-                        ((rsdt_header.length - core::mem::size_of::<ACPISDTHeader>() as u32) >> 2),
-                        4,
-                    )
-                }
-                $crate::io::acpi::RSDPDescriptor::V2(rsdp) => {
-                    let rsdt_header =
-                        unsafe { core::ptr::read(rsdp.rsdt_addr as *const ACPISDTHeader) };
-                    (
-                        rsdp.xsdt_addr as u32,
-                        ((rsdt_header.length - core::mem::size_of::<ACPISDTHeader>() as u32) >> 3),
-                        8,
-                    )
-                }
-            };
-            let mut base_address = rsdt_addr + core::mem::size_of::<ACPISDTHeader>() as u32;
-            let mut address =
-                unsafe { core::ptr::read(base_address as *const u32) } as *const ACPISDTHeader;
-            if !(0..entry_count)
-                .map(|_| {
-                    base_address += entry_size;
-                    address = unsafe { core::ptr::read(base_address as *const u32) }
-                        as *const ACPISDTHeader;
-                    let entry = unsafe { core::ptr::read(address) };
-                    entry.signature
-                })
-                .any(|sig| sig == $sig.as_bytes())
-            {
-                return None;
-            }
-
-            if !$crate::io::acpi::sdt::table_checksum(unsafe { &*address }) {
-                return None;
-            }
-
-            $crate::info!("acpi", "{} located at {:#010x}", $sig, address as u32);
-
-            Some(unsafe { &mut *(address as *mut Self) })
+            let table = $crate::io::acpi::sdt::find_sdt($sig)?;
+
+            Some(unsafe { &mut *table.cast::<Self>() })
         }
     };
 }
 
-/// Verifies the checksum of a System Description Table.
+/// Returns the physical addresses of the tables listed by the root table.
 ///
-/// Returns true if the table fields add to 0.
-pub(super) fn table_checksum(header: &ACPISDTHeader) -> bool {
-    let mut checksum: u8 = 0;
-    let header_addr = header as *const ACPISDTHeader as usize;
-    for i in 0..header.checksum {
-        let c_byte = unsafe { ptr::read((header_addr + i as usize) as *mut u8) };
-        checksum = checksum.wrapping_add(c_byte);
+/// The `XSDT` (64-bit entries) is used when the firmware provides one with a valid checksum, the `RSDT` (32-bit
+/// entries) otherwise. Tables that cannot be addressed by the running stage (above 4GiB, in the 32-bit bootloader)
+/// are skipped.
+///
+/// # Panics
+///
+/// Panics if called before loading the [`RSDPDescriptor`].
+pub fn sdt_tables() -> impl Iterator<Item = PhyAddr> {
+    let rsdp = RSDP
+        .get()
+        .expect("ACPI failure: tried to load description table before the main descriptor");
+
+    let xsdt = match rsdp {
+        RSDPDescriptor::V2(rsdp) if rsdp.xsdt_addr != 0 => Some(PhyAddr::new(rsdp.xsdt_addr)),
+        _ => None,
+    }
+    .filter(|&xsdt| addressable(xsdt) && root_table_valid(xsdt, b"XSDT"));
+
+    let rsdt_addr = match rsdp {
+        RSDPDescriptor::V1(rsdp) => rsdp.rsdt_addr,
+        RSDPDescriptor::V2(rsdp) => rsdp.rsdt_addr,
+    };
+
+    let (root, entry_size) = match xsdt {
+        Some(xsdt) => (Some(xsdt), 8),
+        None => {
+            let rsdt = PhyAddr::from(u64::from(rsdt_addr));
+            (root_table_valid(rsdt, b"RSDT").then_some(rsdt), 4)
+        }
+    };
+
+    let entry_count = root.map_or(0, |root| {
+        let header = unsafe { ptr::read(get_physical_memory(root) as *const ACPISDTHeader) };
+        (header.length as usize).saturating_sub(mem::size_of::<ACPISDTHeader>()) / entry_size
+    });
+    let root = root.unwrap_or_default();
+
+    (0..entry_count)
+        .map(move |index| {
+            let entry = unsafe {
+                get_physical_memory(root).add(mem::size_of::<ACPISDTHeader>() + index * entry_size)
+            };
+
+            let addr = match entry_size {
+                8 => unsafe { ptr::read_unaligned(entry as *const u64) },
+                _ => u64::from(unsafe { ptr::read_unaligned(entry as *const u32) }),
+            };
+
+            PhyAddr::new(addr)
+        })
+        .filter(|&addr| addressable(addr))
+}
+
+/// Looks for the table whose signature is `signature` in the root table (see [`sdt_tables`]).
+///
+/// Returns `None` if the table is not found, or if its checksum is invalid.
+///
+/// # Panics
+///
+/// Panics if called before loading the [`RSDPDescriptor`].
+pub fn find_sdt(signature: &str) -> Option<*mut ACPISDTHeader> {
+    let addr = sdt_tables().find(|&addr| {
+        let header = unsafe { ptr::read(get_physical_memory(addr) as *const ACPISDTHeader) };
+        header.signature == signature.as_bytes()
+    })?;
+
+    let table = get_physical_memory(addr).cast::<ACPISDTHeader>();
+    if !table_checksum(unsafe { &*table }) {
+        error!("acpi", "invalid {} checksum", signature);
+        return None;
     }
 
-    if checksum != 0 {
+    info!("acpi", "{} located at {}", signature, addr);
+
+    Some(table)
+}
+
+/// Checks the signature and the checksum of the root table located at `addr`.
+fn root_table_valid(addr: PhyAddr, signature: &[u8; 4]) -> bool {
+    let table = unsafe { &*(get_physical_memory(addr) as *const ACPISDTHeader) };
+
+    if table.signature != *signature || !table_checksum(table) {
+        error!(
+            "acpi",
+            "invalid {} at {}",
+            core::str::from_utf8(signature).unwrap_or_default(),
+            addr
+        );
         return false;
     }
 
     true
 }
+
+/// Checks whether the table at `addr` can be accessed by the running stage.
+fn addressable(addr: PhyAddr) -> bool {
+    #[cfg(feature = "x86_64")]
+    return u64::from(addr) != 0;
+
+    #[cfg(not(feature = "x86_64"))]
+    return u64::from(addr) != 0 && addr <= PhyAddr::MAX_32;
+}
+
+/// Verifies the checksum of a System Description Table.
+///
+/// Returns true if the table fields add to 0.
+pub(super) fn table_checksum(header: &ACPISDTHeader) -> bool {
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            header as *const ACPISDTHeader as *const u8,
+            header.length as usize,
+        )
+    };
+
+    bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) == 0
+}
@@ -0,0 +1,54 @@
+//! Emulator debug console, on port `0xE9`.
+//!
+//! `QEMU` (`-debugcon file:debug.txt`, or `-debugcon stdio`) and Bochs forward every byte written to the port to the
+//! host, without any setup. It only takes a single `out` instruction, does not wait for a lock, and works the same in
+//! real, protected and long mode: it can be used from the very first instructions of each stage, before the
+//! framebuffer or the serial port are configured.
+//!
+//! Writes are silently ignored on real hardware, or when the emulator was not started with a debug console. Enable
+//! the `debugcon` feature to copy the log to the console from the start (see [`LogSink::DebugCon`]).
+//!
+//! # Examples
+//!
+//! ```
+//! use core::fmt::Write;
+//! use fzboot::io::debugcon::{debugcon_write, DebugCon};
+//!
+//! debugcon_write("stage2 entry\n");
+//! let _ = writeln!(DebugCon, "e820 entries: {}", 4);
+//! ```
+//!
+//! [`LogSink::DebugCon`]: crate::log::LogSink::DebugCon
+
+use core::fmt;
+
+use crate::io::{inb, outb, IOPort};
+
+/// Value read from the port when a debug console is attached.
+const DEBUGCON_MAGIC: u8 = 0xE9;
+
+/// Writer to the debug console, to be used with [`write!`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DebugCon;
+
+impl fmt::Write for DebugCon {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        debugcon_write(s);
+
+        Ok(())
+    }
+}
+
+/// Checks whether a debug console is attached.
+///
+/// Reading the port returns `0xE9` under `QEMU` and Bochs. A successful check is not required before writing.
+pub fn debugcon_present() -> bool {
+    inb(IOPort::DEBUGCON) == DEBUGCON_MAGIC
+}
+
+/// Writes `s` to the debug console.
+pub fn debugcon_write(s: &str) {
+    for byte in s.bytes() {
+        outb(IOPort::DEBUGCON, byte);
+    }
+}
@@ -1,2 +1,2 @@
 #[cfg(feature = "real")]
-pub mod bios;
+pub use crate::bios::services::disk as bios;
@@ -3,9 +3,15 @@ use core::arch::asm;
 use core::ops::Add;
 
 pub mod acpi;
+pub mod debugcon;
 pub mod disk;
 pub mod pic;
+pub mod port;
 pub mod ps2;
+pub mod qemu_exit;
+pub mod serial;
+#[cfg(feature = "alloc")]
+pub mod smbios;
 
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash, Pod, Zeroable)]
 #[repr(transparent)]
@@ -23,6 +29,30 @@ impl IOPort {
     pub(crate) const SEC_ATA: Self = Self(0x170);
 
     pub(crate) const SEC_ATA_CTRL: Self = Self(0x376);
+
+    pub(crate) const SYS_CTRL_A: Self = Self(0x92);
+
+    pub(crate) const PIT_CHANNEL_2: Self = Self(0x42);
+
+    pub(crate) const PIT_COMMAND: Self = Self(0x43);
+
+    pub(crate) const PIT_PORT_B: Self = Self(0x61);
+
+    pub(crate) const COM1: Self = Self(0x3F8);
+
+    pub(crate) const PS2_DATA: Self = Self(0x60);
+
+    pub(crate) const PS2_STATUS: Self = Self(0x64);
+
+    pub(crate) const PIC_MASTER: Self = Self(0x20);
+
+    pub(crate) const PIC_SLAVE: Self = Self(0xA0);
+
+    pub(crate) const PCI_CONFIG_ADDRESS: Self = Self(0xCF8);
+
+    pub(crate) const DEBUGCON: Self = Self(0xE9);
+
+    pub(crate) const QEMU_EXIT: Self = Self(0xF4);
 }
 
 impl From<u16> for IOPort {
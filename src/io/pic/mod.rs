@@ -5,7 +5,13 @@
 //! Usually there are 2 PICs configured as master/slave.
 //! Slave interrupts are thus be redirected to the master through one single IRQ.
 
-use crate::io::{io_delay, outb, IOPort};
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+use conquer_once::spin::OnceCell;
+
+use crate::io::port::{Port, PortRange};
+use crate::io::{io_delay, IOPort};
 
 /// Initialization is made by sending ICW (Initialization Command Words)
 /// to both Master and Slave controllers.
@@ -116,71 +122,234 @@ const DEFAULT_ICW4: u8 = 0b00000001;
 
 const SIMPLE_ACKNOWLEDGMENT: u8 = 0b00010000;
 
+/// Non-specific EOI (OCW2).
+const NON_SPECIFIC_EOI: u8 = 0x20;
+
+/// OCW3 command selecting the In-Service Register for the next read of the CMD port.
+const OCW3_READ_ISR: u8 = 0x0B;
+
+/// IRQ of the master PIC to which the slave PIC is connected.
+const CASCADE_IRQ: u8 = 2;
+
+/// Vectors of the master and slave PICs once [disabled](PIC::disable).
+///
+/// A masked PIC may still raise spurious interrupts (IRQ 7 / IRQ 15). They are moved to vectors used by nothing else:
+/// away from the exceptions, from the legacy IRQs delivered by the `I/O APIC`, and from the vectors of the local APIC
+/// (`0xF0` to `0xFF`: cross-processor calls, LVT entries, error and spurious interrupts).
+pub(crate) const PIC_MASKED_VECTORS: Range<u8> = 0xE0..0xF0;
+
+/// Set once the `PIC` was disabled, after the `I/O APIC` took over the delivery of external interrupts.
+static PIC_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Masks of the master (low byte) and slave (high byte) PICs before they were disabled.
+static SAVED_MASKS: AtomicU16 = AtomicU16::new(0);
+
+/// Ports of the master and slave `PIC`, claimed on first use.
+static PIC_PORTS: OnceCell<PicPorts> = OnceCell::uninit();
+
+struct PicPorts {
+    master_cmd: Port<u8>,
+    master_data: Port<u8>,
+    slave_cmd: Port<u8>,
+    slave_data: Port<u8>,
+}
+
+fn pic_ports() -> &'static PicPorts {
+    PIC_PORTS.get_or_init(|| {
+        let master = PortRange::claim(IOPort::PIC_MASTER, 2, "pic")
+            .expect("master PIC ports already claimed");
+        let slave =
+            PortRange::claim(IOPort::PIC_SLAVE, 2, "pic").expect("slave PIC ports already claimed");
+
+        PicPorts {
+            master_cmd: master.port(0),
+            master_data: master.port(1),
+            slave_cmd: slave.port(0),
+            slave_data: slave.port(1),
+        }
+    })
+}
+
 /// A Struct representing a PIC chip
 pub struct PIC {
-    pub master_cmd_port: u16,
-    pub master_data_port: u16,
-    pub slave_cmd_port: u16,
-    pub slave_data_port: u16,
+    ports: &'static PicPorts,
 }
 
 impl PIC {
     /// Creates a `PIC` with default bus ports for master/slave config.
     pub fn default() -> Self {
-        Self {
-            master_cmd_port: 0x20,
-            master_data_port: 0x21,
-            slave_cmd_port: 0xA0,
-            slave_data_port: 0xA1,
-        }
+        Self { ports: pic_ports() }
     }
 
     /// Remap `PIC` IRQs by setting new offset vectors.
     pub fn remap(&self, master_offset: u8, slave_offset: u8) {
         // Start init sequence
-        outb(self.master_cmd_port.into(), DEFAULT_ICW1);
+        self.ports.master_cmd.write(DEFAULT_ICW1);
         io_delay();
-        outb(self.slave_cmd_port.into(), DEFAULT_ICW1);
+        self.ports.slave_cmd.write(DEFAULT_ICW1);
         io_delay();
 
         // Set vector offset
-        outb(self.master_data_port.into(), master_offset);
+        self.ports.master_data.write(master_offset);
         io_delay();
-        outb(self.slave_data_port.into(), slave_offset);
+        self.ports.slave_data.write(slave_offset);
         io_delay();
 
         // Master PIC has slave at IRQ2
-        outb(self.master_data_port.into(), DEFAULT_MASTER_ICW3);
+        self.ports.master_data.write(DEFAULT_MASTER_ICW3);
         io_delay();
-        outb(self.slave_data_port.into(), DEFAULT_SLAVE_ICW3);
+        self.ports.slave_data.write(DEFAULT_SLAVE_ICW3);
         io_delay();
 
         //
-        outb(self.master_data_port.into(), DEFAULT_ICW4);
+        self.ports.master_data.write(DEFAULT_ICW4);
         io_delay();
-        outb(self.slave_data_port.into(), DEFAULT_ICW4);
+        self.ports.slave_data.write(DEFAULT_ICW4);
     }
 
     // Mask utilities are used to enable/disable interrupts on a given controller
 
     /// Masks slave PIC with given bitmask.
     pub fn mask_slave(&self, bitmask: u8) {
-        outb(self.slave_data_port.into(), bitmask);
+        self.ports.slave_data.write(bitmask);
     }
 
     /// Masks master PIC with given bitmask.
     pub fn mask_master(&self, bitmask: u8) {
-        outb(self.master_data_port.into(), bitmask);
+        self.ports.master_data.write(bitmask);
+    }
+
+    /// Returns the current masks of the master and slave PICs.
+    pub fn masks(&self) -> (u8, u8) {
+        (self.ports.master_data.read(), self.ports.slave_data.read())
+    }
+
+    /// Sets the masks of both the master and slave PICs.
+    pub fn set_masks(&self, master_mask: u8, slave_mask: u8) {
+        self.mask_master(master_mask);
+        self.mask_slave(slave_mask);
+    }
+
+    /// Masks a single IRQ (0 to 15), leaving the others untouched.
+    pub fn mask_irq(&self, irq: u8) {
+        let port = self.data_port(irq);
+        port.write(port.read() | (1 << (irq % 8)));
+    }
+
+    /// Unmasks a single IRQ (0 to 15), leaving the others untouched.
+    ///
+    /// Unmasking an IRQ of the slave PIC also unmasks the cascade IRQ of the master.
+    pub fn unmask_irq(&self, irq: u8) {
+        let port = self.data_port(irq);
+        port.write(port.read() & !(1 << (irq % 8)));
+
+        if irq >= 8 {
+            self.unmask_irq(CASCADE_IRQ);
+        }
+    }
+
+    fn data_port(&self, irq: u8) -> &Port<u8> {
+        assert!(irq < 16, "invalid PIC IRQ");
+
+        if irq < 8 {
+            &self.ports.master_data
+        } else {
+            &self.ports.slave_data
+        }
+    }
+
+    /// Returns the In-Service Registers of the master and slave PICs: the IRQs currently being handled.
+    pub fn in_service(&self) -> (u8, u8) {
+        self.ports.master_cmd.write(OCW3_READ_ISR);
+        self.ports.slave_cmd.write(OCW3_READ_ISR);
+
+        (self.ports.master_cmd.read(), self.ports.slave_cmd.read())
+    }
+
+    /// Checks whether `irq` was raised spuriously.
+    ///
+    /// The PICs signal IRQ 7 (master) or IRQ 15 (slave) when an interrupt request disappears before being
+    /// acknowledged by the CPU (electrical noise, or an interrupt masked at the wrong time). Such an interrupt is not
+    /// set in the In-Service Register, and must not be acknowledged. Always false for other IRQs.
+    pub fn is_spurious(&self, irq: u8) -> bool {
+        let (master_isr, slave_isr) = self.in_service();
+
+        match irq {
+            7 => master_isr & 0x80 == 0,
+            15 => slave_isr & 0x80 == 0,
+            _ => false,
+        }
+    }
+
+    /// Acknowledges the interrupt currently handled, if any.
+    ///
+    /// The In-Service Registers are read first, so that spurious interrupts are not acknowledged: a spurious IRQ 7
+    /// is not acknowledged at all, and a spurious IRQ 15 is only acknowledged on the master PIC (which did receive
+    /// it from the slave, on the cascade IRQ). Nothing is written once the `PIC` is [disabled](Self::disable).
+    pub fn end_of_interrupt(&self) {
+        if pic_disabled() {
+            return;
+        }
+
+        let (master_isr, slave_isr) = self.in_service();
+
+        if slave_isr != 0 {
+            self.acknowledge_slave();
+        }
+
+        if master_isr != 0 {
+            self.acknowledge_master();
+        }
+    }
+
+    /// Disables both PICs once the `I/O APIC` took over the delivery of external interrupts.
+    ///
+    /// The PICs are remapped to [`PIC_MASKED_VECTORS`] (spurious interrupts may still be raised while masked), every
+    /// IRQ is masked, and the IRQs still in service are acknowledged. The previous masks are restored by
+    /// [`PIC::enable`].
+    pub fn disable(&self) {
+        if PIC_DISABLED.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let (master_mask, slave_mask) = self.masks();
+        SAVED_MASKS.store(
+            u16::from(master_mask) | (u16::from(slave_mask) << 8),
+            Ordering::Release,
+        );
+
+        self.remap(PIC_MASKED_VECTORS.start, PIC_MASKED_VECTORS.start + 8);
+        self.set_masks(0xFF, 0xFF);
+
+        let (master_isr, slave_isr) = self.in_service();
+        for _ in 0..slave_isr.count_ones() {
+            self.acknowledge_slave();
+        }
+        for _ in 0..master_isr.count_ones() {
+            self.acknowledge_master();
+        }
+    }
+
+    /// Enables the PICs again, with the masks they had before being [disabled](Self::disable).
+    ///
+    /// Used when switching back to `PIC` or `Virtual Wire` mode.
+    pub fn enable(&self) {
+        if !PIC_DISABLED.swap(false, Ordering::AcqRel) {
+            return;
+        }
+
+        let [master_mask, slave_mask] = SAVED_MASKS.load(Ordering::Acquire).to_le_bytes();
+        self.set_masks(master_mask, slave_mask);
     }
 
     /// Acknowledges master
     pub fn acknowledge_master(&self) {
-        outb(self.master_cmd_port.into(), 0x20)
+        self.ports.master_cmd.write(NON_SPECIFIC_EOI)
     }
 
     /// Acknowledges slave
     pub fn acknowledge_slave(&self) {
-        outb(self.slave_cmd_port.into(), 0x20)
+        self.ports.slave_cmd.write(NON_SPECIFIC_EOI)
     }
 
     /// Acknowledges both master and slave
@@ -189,3 +358,8 @@ impl PIC {
         self.acknowledge_slave();
     }
 }
+
+/// Checks whether the `PIC` was disabled, after the `I/O APIC` took over the delivery of external interrupts.
+pub fn pic_disabled() -> bool {
+    PIC_DISABLED.load(Ordering::Acquire)
+}
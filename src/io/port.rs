@@ -0,0 +1,197 @@
+//! Typed I/O ports, with explicit ownership.
+//!
+//! A driver claims the range of I/O ports of its device once with [`PortRange::claim`], and accesses them through
+//! the [`Port`] handles created from the range. Claiming a port that is already owned by another driver fails, which
+//! prevents two drivers from accidentally sharing a device (such as the PS/2 controller, on `0x60` and `0x64`).
+//!
+//! Claims are never released: drivers keep their ports for the lifetime of the system, usually in a `static`.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::io::port::PortRange;
+//! use fzboot::io::IOPort;
+//!
+//! let ports = PortRange::claim(IOPort::from(0x60), 1, "ps2").expect("PS/2 data port already claimed");
+//! let data = ports.port::<u8>(0);
+//!
+//! let scancode = data.read();
+//! ```
+
+use core::marker::PhantomData;
+
+use spin::Mutex;
+
+use crate::errors::PortError;
+use crate::io::{inb, inl, inw, outb, outl, outw, IOPort};
+
+/// Maximum number of port ranges that can be claimed.
+const MAX_PORT_CLAIMS: usize = 64;
+
+static PORT_CLAIMS: Mutex<[Option<PortClaim>; MAX_PORT_CLAIMS]> =
+    Mutex::new([None; MAX_PORT_CLAIMS]);
+
+#[derive(Clone, Copy, Debug)]
+struct PortClaim {
+    base: u16,
+    len: u16,
+    owner: &'static str,
+}
+
+impl PortClaim {
+    fn overlaps(&self, base: u16, len: u16) -> bool {
+        u32::from(base) < u32::from(self.base) + u32::from(self.len)
+            && u32::from(self.base) < u32::from(base) + u32::from(len)
+    }
+}
+
+/// Value that can be transferred through an I/O port: [`u8`], [`u16`] or [`u32`].
+pub trait PortValue: Copy {
+    /// Reads a value from `port`.
+    fn read_from(port: IOPort) -> Self;
+
+    /// Writes `value` to `port`.
+    fn write_to(port: IOPort, value: Self);
+}
+
+impl PortValue for u8 {
+    fn read_from(port: IOPort) -> Self {
+        inb(port)
+    }
+
+    fn write_to(port: IOPort, value: Self) {
+        outb(port, value);
+    }
+}
+
+impl PortValue for u16 {
+    fn read_from(port: IOPort) -> Self {
+        inw(port)
+    }
+
+    fn write_to(port: IOPort, value: Self) {
+        outw(port, value);
+    }
+}
+
+impl PortValue for u32 {
+    fn read_from(port: IOPort) -> Self {
+        inl(u16::from(port))
+    }
+
+    fn write_to(port: IOPort, value: Self) {
+        outl(u16::from(port), value);
+    }
+}
+
+/// Range of I/O ports owned by a driver.
+#[derive(Debug)]
+pub struct PortRange {
+    base: IOPort,
+    len: u16,
+}
+
+impl PortRange {
+    /// Claims the `len` ports starting at `base` on behalf of `owner`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`PortError::AlreadyClaimed`] (with the name of the current owner) if any of the ports is owned by
+    /// another driver, [`PortError::InvalidRange`] if the range is empty or extends past the last port, or
+    /// [`PortError::TooManyClaims`] if no more range can be claimed.
+    pub fn claim(base: IOPort, len: u16, owner: &'static str) -> Result<Self, PortError> {
+        let base_port = u16::from(base);
+        if len == 0 || base_port.checked_add(len - 1).is_none() {
+            return Err(PortError::InvalidRange);
+        }
+
+        let mut claims = PORT_CLAIMS.lock();
+
+        if let Some(claim) = claims
+            .iter()
+            .flatten()
+            .find(|claim| claim.overlaps(base_port, len))
+        {
+            return Err(PortError::AlreadyClaimed(claim.owner));
+        }
+
+        let slot = claims
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(PortError::TooManyClaims)?;
+        *slot = Some(PortClaim {
+            base: base_port,
+            len,
+            owner,
+        });
+
+        Ok(Self { base, len })
+    }
+
+    /// Returns the first port of the range.
+    pub fn base(&self) -> IOPort {
+        self.base
+    }
+
+    /// Returns a handle to the port at `offset` in the range, transferring values of type `T`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `offset` is outside of the range.
+    pub fn port<T: PortValue>(&self, offset: u16) -> Port<T> {
+        assert!(offset < self.len, "I/O port outside of the claimed range");
+
+        Port {
+            port: self.base + offset,
+            value: PhantomData,
+        }
+    }
+}
+
+/// Returns the owner of `port`, if it was claimed.
+pub fn port_owner(port: IOPort) -> Option<&'static str> {
+    PORT_CLAIMS
+        .lock()
+        .iter()
+        .flatten()
+        .find(|claim| claim.overlaps(u16::from(port), 1))
+        .map(|claim| claim.owner)
+}
+
+/// I/O port transferring values of type `T` ([`u8`], [`u16`] or [`u32`]).
+///
+/// Created from a claimed [`PortRange`].
+#[derive(Debug)]
+pub struct Port<T: PortValue> {
+    port: IOPort,
+    value: PhantomData<T>,
+}
+
+impl<T: PortValue> Port<T> {
+    /// Returns a handle to `port`, without claiming it.
+    ///
+    /// # Safety
+    ///
+    /// The port must not be used by another driver.
+    pub const unsafe fn new_unchecked(port: IOPort) -> Self {
+        Self {
+            port,
+            value: PhantomData,
+        }
+    }
+
+    /// Returns the address of the port.
+    pub fn addr(&self) -> IOPort {
+        self.port
+    }
+
+    /// Reads a value from the port.
+    pub fn read(&self) -> T {
+        T::read_from(self.port)
+    }
+
+    /// Writes `value` to the port.
+    pub fn write(&self, value: T) {
+        T::write_to(self.port, value);
+    }
+}
@@ -0,0 +1,336 @@
+//! PS/2 keyboard driver.
+//!
+//! The scancodes received on IRQ 1 (using scancode set 1, as translated by the PS/2 controller) are decoded into
+//! [`KeyEvent`]s, assuming a US QWERTY layout, and pushed into a bounded event queue.
+//!
+//! Consumers read the events using [`read_key`], which blocks until a key is pressed, or [`try_read_key`].
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::io::ps2::keyboard::{keyboard_init, read_key, KeyCode};
+//!
+//! keyboard_init();
+//!
+//! loop {
+//!     if read_key().code == KeyCode::Enter {
+//!         break;
+//!     }
+//! }
+//! ```
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use fzproc_macros::interrupt_handler;
+use spin::Mutex;
+
+use crate::{
+    error, info,
+    io::ps2::{output_wait, read_ps2, read_ps2_status},
+    irq::{manager::get_interrupt_manager, InterruptStackFrame},
+    sync::waitqueue::WaitQueue,
+    x86::{
+        apic::{io_apic::get_all_io_apics, mp_table::IOApicIntPin, InterruptVector},
+        hypervisor::hypervisor_quirks,
+        int::{disable_interrupts, enable_interrupts, interrupts_disabled},
+    },
+};
+
+/// Interrupt vector of the keyboard IRQ.
+pub const KEYBOARD_VECTOR: InterruptVector = InterruptVector::new(0x21);
+
+/// I/O APIC pin to which the keyboard IRQ (ISA IRQ 1) is wired.
+const KEYBOARD_IOAPIC_PIN: u8 = 1;
+
+/// Maximum number of events kept in the queue. Older events are dropped once it is full.
+pub const KEY_EVENTS_CAPACITY: usize = 64;
+
+/// Prefix of the scancodes of extended keys.
+const SCANCODE_EXTENDED: u8 = 0xE0;
+
+/// Bit set in the scancode when a key is released.
+const SCANCODE_RELEASED: u8 = 0x80;
+
+/// Characters of the printable keys of scancode set 1, indexed by scancode.
+const SCANCODE_SET1_LOWER: &[u8; 0x3A] =
+    b"\0\x001234567890-=\0\0qwertyuiop[]\0\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+
+/// Characters of the printable keys of scancode set 1 while `Shift` is held, indexed by scancode.
+const SCANCODE_SET1_UPPER: &[u8; 0x3A] =
+    b"\0\0!@#$%^&*()_+\0\0QWERTYUIOP{}\0\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ";
+
+static KEY_EVENTS: Mutex<KeyEventQueue> = Mutex::new(KeyEventQueue::new());
+
+static KEY_EVENTS_WAIT: WaitQueue = WaitQueue::new();
+
+/// Only accessed from the keyboard IRQ handler.
+static KEYBOARD_DECODER: Mutex<ScancodeDecoder> = Mutex::new(ScancodeDecoder::new());
+
+/// Number of events dropped because the queue was full.
+static KEY_EVENTS_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyCode {
+    /// Printable character (including the space).
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Tab,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Shift,
+    Control,
+    Alt,
+    CapsLock,
+
+    /// Key without any translation, identified by its scancode.
+    Unknown(u8),
+}
+
+/// State of the modifier keys when an event occured.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub caps_lock: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+
+    /// Whether the key was pressed, or released.
+    pub pressed: bool,
+    pub modifiers: Modifiers,
+}
+
+/// Decodes a stream of scancodes (set 1) into [`KeyEvent`]s, tracking the state of the modifier keys.
+#[derive(Debug, Default)]
+pub struct ScancodeDecoder {
+    extended: bool,
+    modifiers: Modifiers,
+}
+
+impl ScancodeDecoder {
+    pub const fn new() -> Self {
+        Self {
+            extended: false,
+            modifiers: Modifiers {
+                shift: false,
+                ctrl: false,
+                alt: false,
+                caps_lock: false,
+            },
+        }
+    }
+
+    /// Decodes the next scancode.
+    ///
+    /// Returns `None` if the scancode is a prefix, and the event is only complete after the next one.
+    pub fn decode(&mut self, scancode: u8) -> Option<KeyEvent> {
+        if scancode == SCANCODE_EXTENDED {
+            self.extended = true;
+            return None;
+        }
+
+        let extended = core::mem::take(&mut self.extended);
+        let pressed = scancode & SCANCODE_RELEASED == 0;
+        let key = scancode & !SCANCODE_RELEASED;
+
+        let code = match (extended, key) {
+            (_, 0x1D) => KeyCode::Control,
+            (_, 0x38) => KeyCode::Alt,
+            (false, 0x2A | 0x36) => KeyCode::Shift,
+            (false, 0x3A) => KeyCode::CapsLock,
+            (false, 0x01) => KeyCode::Escape,
+            (false, 0x0E) => KeyCode::Backspace,
+            (false, 0x0F) => KeyCode::Tab,
+            (_, 0x1C) => KeyCode::Enter,
+            (true, 0x48) => KeyCode::Up,
+            (true, 0x50) => KeyCode::Down,
+            (true, 0x4B) => KeyCode::Left,
+            (true, 0x4D) => KeyCode::Right,
+            (true, 0x47) => KeyCode::Home,
+            (true, 0x4F) => KeyCode::End,
+            (true, 0x53) => KeyCode::Delete,
+            (true, 0x35) => KeyCode::Char('/'),
+            (false, key) => self
+                .translate(key)
+                .map_or(KeyCode::Unknown(key), KeyCode::Char),
+            (true, key) => KeyCode::Unknown(key),
+        };
+
+        match code {
+            KeyCode::Shift => self.modifiers.shift = pressed,
+            KeyCode::Control => self.modifiers.ctrl = pressed,
+            KeyCode::Alt => self.modifiers.alt = pressed,
+            KeyCode::CapsLock if pressed => self.modifiers.caps_lock ^= true,
+            _ => (),
+        }
+
+        Some(KeyEvent {
+            code,
+            pressed,
+            modifiers: self.modifiers,
+        })
+    }
+
+    fn translate(&self, key: u8) -> Option<char> {
+        let lower = *SCANCODE_SET1_LOWER.get(usize::from(key))?;
+        if lower == 0 {
+            return None;
+        }
+
+        let shifted = if lower.is_ascii_alphabetic() {
+            self.modifiers.shift ^ self.modifiers.caps_lock
+        } else {
+            self.modifiers.shift
+        };
+
+        match shifted {
+            true => Some(char::from(SCANCODE_SET1_UPPER[usize::from(key)])),
+            false => Some(char::from(lower)),
+        }
+    }
+}
+
+/// Bounded FIFO of [`KeyEvent`]s.
+struct KeyEventQueue {
+    events: [Option<KeyEvent>; KEY_EVENTS_CAPACITY],
+    head: usize,
+    len: usize,
+}
+
+impl KeyEventQueue {
+    const fn new() -> Self {
+        Self {
+            events: [None; KEY_EVENTS_CAPACITY],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Pushes an event, dropping the oldest one if the queue is full.
+    fn push(&mut self, event: KeyEvent) {
+        if self.len == KEY_EVENTS_CAPACITY {
+            self.pop();
+            KEY_EVENTS_DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.events[(self.head + self.len) % KEY_EVENTS_CAPACITY] = Some(event);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<KeyEvent> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let event = self.events[self.head].take();
+        self.head = (self.head + 1) % KEY_EVENTS_CAPACITY;
+        self.len -= 1;
+
+        event
+    }
+
+    fn clear(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// Sets up the keyboard IRQ, and starts queuing key events.
+///
+/// Nothing is done if the hypervisor may not emulate the PS/2 controller (see [`hypervisor_quirks`]) and it does not
+/// respond.
+pub fn keyboard_init() {
+    // The status port of a missing controller floats high, and would always report a pending byte.
+    if hypervisor_quirks().ps2_may_be_absent && read_ps2_status() == 0xff {
+        info!("ps2", "no PS/2 controller found");
+        return;
+    }
+
+    // Discards the bytes left in the output buffer of the controller, which would prevent further IRQs.
+    while output_wait(1).is_ok() {
+        read_ps2();
+    }
+
+    match get_all_io_apics() {
+        Some(io_apics) if !io_apics.is_empty() => {
+            for io_apic in io_apics {
+                io_apic
+                    .1
+                    .lock()
+                    .map_pin_to_irq(IOApicIntPin::from(KEYBOARD_IOAPIC_PIN), KEYBOARD_VECTOR);
+            }
+        }
+        _ => {
+            error!(
+                "ps2",
+                "no I/O APIC available, relying on the legacy PIC routing"
+            );
+        }
+    }
+
+    get_interrupt_manager().register_static_handler(KEYBOARD_VECTOR, keyboard_irq_entry);
+}
+
+#[interrupt_handler]
+fn keyboard_irq_entry(frame: InterruptStackFrame) {
+    let scancode = read_ps2();
+
+    let Some(event) = KEYBOARD_DECODER.lock().decode(scancode) else {
+        return;
+    };
+
+    KEY_EVENTS.lock().push(event);
+    KEY_EVENTS_WAIT.wake_all();
+}
+
+/// Runs `f` with the event queue locked, without letting the keyboard IRQ handler spin on the lock.
+fn with_key_events<T>(f: impl FnOnce(&mut KeyEventQueue) -> T) -> T {
+    let were_disabled = interrupts_disabled();
+    disable_interrupts();
+
+    let result = f(&mut KEY_EVENTS.lock());
+
+    if !were_disabled {
+        enable_interrupts();
+    }
+
+    result
+}
+
+/// Returns the oldest queued key event, if any.
+pub fn try_read_key() -> Option<KeyEvent> {
+    with_key_events(KeyEventQueue::pop)
+}
+
+/// Returns the next key press, waiting until a key is pressed.
+///
+/// Key releases are skipped.
+pub fn read_key() -> KeyEvent {
+    loop {
+        KEY_EVENTS_WAIT.wait_until(|| with_key_events(|events| events.len != 0));
+
+        if let Some(event) = try_read_key().filter(|event| event.pressed) {
+            return event;
+        }
+    }
+}
+
+/// Discards every queued key event.
+pub fn flush_keys() {
+    with_key_events(KeyEventQueue::clear);
+}
+
+/// Returns the number of key events dropped because they were not read fast enough.
+pub fn dropped_keys() -> u64 {
+    KEY_EVENTS_DROPPED.load(Ordering::Relaxed)
+}
@@ -1,30 +1,55 @@
-use core::arch::asm;
+use conquer_once::spin::OnceCell;
 
 use crate::errors::{CanFail, IOError};
-use crate::io::{inb, outb, IOPort};
+use crate::io::port::{Port, PortRange};
+use crate::io::IOPort;
+
+#[cfg(feature = "alloc")]
+pub mod keyboard;
+
+/// Ports of the PS/2 controller, claimed on first use.
+static PS2_PORTS: OnceCell<Ps2Ports> = OnceCell::uninit();
+
+struct Ps2Ports {
+    data: Port<u8>,
+
+    /// Status register when read, command register when written.
+    command: Port<u8>,
+}
+
+fn ps2_ports() -> &'static Ps2Ports {
+    PS2_PORTS.get_or_init(|| {
+        let data =
+            PortRange::claim(IOPort::PS2_DATA, 1, "ps2").expect("PS/2 data port already claimed");
+        let command = PortRange::claim(IOPort::PS2_STATUS, 1, "ps2")
+            .expect("PS/2 command port already claimed");
+
+        Ps2Ports {
+            data: data.port(0),
+            command: command.port(0),
+        }
+    })
+}
 
 pub fn send_data(data: u8) {
-    outb(IOPort::from(0x60), data);
+    ps2_ports().data.write(data);
 }
 
 pub fn read_ps2() -> u8 {
-    inb(IOPort::from(0x60))
+    ps2_ports().data.read()
+}
+
+pub fn read_ps2_status() -> u8 {
+    ps2_ports().command.read()
 }
 
 pub fn send_ps2(cmd: u8) {
-    outb(IOPort::from(0x64), cmd);
+    ps2_ports().command.write(cmd);
 }
 
 pub fn input_wait(mut loops: u16) -> CanFail<IOError> {
     while loops > 0 {
-        let status_reg: u8;
-
-        unsafe {
-            asm!(
-            "in al, 0x64",
-            out("al") status_reg
-            );
-        }
+        let status_reg = read_ps2_status();
 
         if (status_reg & 2) == 0 {
             return Ok(());
@@ -38,14 +63,7 @@ pub fn input_wait(mut loops: u16) -> CanFail<IOError> {
 
 pub fn output_wait(mut loops: u16) -> CanFail<IOError> {
     while loops > 0 {
-        let status_reg: u8;
-
-        unsafe {
-            asm!(
-            "in al, 0x64",
-            out("al") status_reg
-            );
-        }
+        let status_reg = read_ps2_status();
 
         if (status_reg & 1) == 1 {
             return Ok(());
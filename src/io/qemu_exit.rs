@@ -0,0 +1,58 @@
+//! `QEMU` exit device (`isa-debug-exit`).
+//!
+//! Writing a value `code` to the port of the device terminates `QEMU`, which exits with the status `(code << 1) | 1`.
+//! The status can therefore never be 0: test runners map [`QemuExitCode::Success`] to a successful run instead.
+//!
+//! The device is expected at [`QEMU_EXIT_PORT`]:
+//!
+//! ```text
+//! qemu-system-x86_64 -device isa-debug-exit,iobase=0xf4,iosize=0x04 ...
+//! ```
+//!
+//! With the `qemu_exit` feature, the panic handlers terminate the emulator with [`QemuExitCode::Panic`] once the
+//! panic was reported, instead of waiting for a key press. Without the feature, nothing is written to the port
+//! unless [`qemu_exit`] is explicitly called.
+
+use crate::io::{outl, IOPort};
+use crate::x86::idle::halt_forever;
+
+/// Port of the `isa-debug-exit` device.
+pub const QEMU_EXIT_PORT: IOPort = IOPort::QEMU_EXIT;
+
+/// Status reported to the host, `QEMU` exiting with `(code << 1) | 1`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    /// Every test passed (exit status 33).
+    Success = 0x10,
+
+    /// At least one test failed (exit status 35).
+    Failure = 0x11,
+
+    /// The kernel or the bootloader panicked (exit status 37).
+    Panic = 0x12,
+}
+
+impl QemuExitCode {
+    /// Exit status of the `QEMU` process.
+    pub fn exit_status(self) -> u32 {
+        ((self as u32) << 1) | 1
+    }
+}
+
+/// Terminates `QEMU` with `code`.
+///
+/// Halts forever if the device is not present (on real hardware, or if `QEMU` was started without it).
+pub fn qemu_exit(code: QemuExitCode) -> ! {
+    outl(u16::from(QEMU_EXIT_PORT), code as u32);
+
+    halt_forever();
+}
+
+/// Terminates `QEMU` with [`QemuExitCode::Panic`] if built with the `qemu_exit` feature. Returns otherwise.
+///
+/// Called by the panic handlers, once the panic was reported.
+pub fn qemu_exit_on_panic() {
+    #[cfg(feature = "qemu_exit")]
+    qemu_exit(QemuExitCode::Panic);
+}
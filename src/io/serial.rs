@@ -0,0 +1,135 @@
+//! 16550 UART serial port.
+//!
+//! The serial port is used to move data out of the machine (for instance, profiling samples), to a host capturing the
+//! output of the port (`-serial file:out.txt` with `QEMU`, or a null-modem cable on real hardware).
+//!
+//! Only transmission is supported, by polling: interrupts of the UART are disabled.
+
+use core::fmt::{self, Write};
+
+use spin::Mutex;
+
+use crate::errors::{CanFail, SerialError};
+use crate::io::{inb, outb, IOPort};
+
+/// Frequency of the UART clock divided by 16, in bauds.
+const UART_BASE_BAUD: u32 = 115_200;
+
+// Registers, as offsets from the base port.
+const UART_DATA: u16 = 0;
+const UART_INT_ENABLE: u16 = 1;
+const UART_FIFO_CTRL: u16 = 2;
+const UART_LINE_CTRL: u16 = 3;
+const UART_MODEM_CTRL: u16 = 4;
+const UART_LINE_STATUS: u16 = 5;
+
+/// Divisor latch access bit: the data and interrupt enable registers hold the baud rate divisor.
+const LCR_DLAB: u8 = 1 << 7;
+
+/// 8 data bits, no parity, 1 stop bit.
+const LCR_8N1: u8 = 0b011;
+
+/// Enables and clears the FIFOs, with a 14 bytes threshold.
+const FCR_ENABLE_CLEAR: u8 = 0xC7;
+
+/// Data terminal ready, request to send, `OUT2`.
+const MCR_NORMAL: u8 = 0x0B;
+
+/// Loopback mode, used to check that a UART is present.
+const MCR_LOOPBACK: u8 = 0x1E;
+
+/// Set in the line status when the transmit holding register is empty.
+const LSR_TX_EMPTY: u8 = 1 << 5;
+
+/// Number of line status polls before a byte is dropped.
+const UART_TX_TIMEOUT_LOOPS: u32 = 100_000;
+
+/// First serial port.
+pub static COM1: Mutex<SerialPort> = Mutex::new(SerialPort::new(IOPort::COM1));
+
+/// 16550-compatible UART.
+#[derive(Debug)]
+pub struct SerialPort {
+    base: IOPort,
+    initialized: bool,
+}
+
+impl SerialPort {
+    pub const fn new(base: IOPort) -> Self {
+        Self {
+            base,
+            initialized: false,
+        }
+    }
+
+    /// Whether [`SerialPort::init`] succeeded.
+    pub fn initialized(&self) -> bool {
+        self.initialized
+    }
+
+    /// Configures the UART to transmit at `baud` bauds (8 data bits, no parity, 1 stop bit).
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SerialError::NotPresent`] if no UART answers the loopback test.
+    pub fn init(&mut self, baud: u32) -> CanFail<SerialError> {
+        let divisor = (UART_BASE_BAUD / baud.clamp(1, UART_BASE_BAUD)) as u16;
+
+        outb(self.base + UART_INT_ENABLE, 0);
+        outb(self.base + UART_LINE_CTRL, LCR_DLAB);
+        outb(self.base + UART_DATA, divisor as u8);
+        outb(self.base + UART_INT_ENABLE, (divisor >> 8) as u8);
+        outb(self.base + UART_LINE_CTRL, LCR_8N1);
+        outb(self.base + UART_FIFO_CTRL, FCR_ENABLE_CLEAR);
+
+        // In loopback mode, transmitted bytes are received back.
+        outb(self.base + UART_MODEM_CTRL, MCR_LOOPBACK);
+        outb(self.base + UART_DATA, 0xAE);
+        if inb(self.base + UART_DATA) != 0xAE {
+            return Err(SerialError::NotPresent);
+        }
+
+        outb(self.base + UART_MODEM_CTRL, MCR_NORMAL);
+        self.initialized = true;
+
+        Ok(())
+    }
+
+    /// Transmits a byte, waiting for the transmitter to be ready.
+    ///
+    /// The byte is dropped if the port was not initialized, or if the transmitter does not become ready in time.
+    pub fn write_byte(&mut self, byte: u8) {
+        if !self.initialized {
+            return;
+        }
+
+        for _ in 0..UART_TX_TIMEOUT_LOOPS {
+            if inb(self.base + UART_LINE_STATUS) & LSR_TX_EMPTY != 0 {
+                outb(self.base + UART_DATA, byte);
+                return;
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_byte(byte);
+        }
+    }
+}
+
+impl Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            // Terminals expect CRLF line endings.
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+
+        Ok(())
+    }
+}
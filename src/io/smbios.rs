@@ -0,0 +1,550 @@
+//! SMBIOS (DMI) tables.
+//!
+//! The firmware describes the hardware of the system in a table of SMBIOS structures: system and baseboard
+//! identification, BIOS version, processor sockets, memory devices, and so on. This information is logged at boot,
+//! so that bug reports carry the exact hardware they were observed on.
+//!
+//! The table is located through its entry point, found in the BIOS area of physical memory (`0xF0000` to
+//! `0xFFFFF`): either a 32-bit (`_SM_`) or a 64-bit (`_SM3_`) entry point.
+//!
+//! Based on the DMTF System Management BIOS Reference Specification (DSP0134).
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::io::smbios::{smbios_init, smbios_report};
+//!
+//! smbios_init();
+//!
+//! // Similar to the output of `dmidecode`.
+//! println!("{}", smbios_report().unwrap());
+//! ```
+
+use core::{fmt::Write, slice};
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use conquer_once::spin::OnceCell;
+
+use crate::info;
+
+/// Start of the area of physical memory holding the entry point.
+const ENTRY_POINT_AREA_START: usize = 0xF0000;
+
+/// End of the area of physical memory holding the entry point.
+const ENTRY_POINT_AREA_END: usize = 0x100000;
+
+const STRUCT_BIOS: u8 = 0;
+const STRUCT_SYSTEM: u8 = 1;
+const STRUCT_BASEBOARD: u8 = 2;
+const STRUCT_PROCESSOR: u8 = 4;
+const STRUCT_MEMORY_DEVICE: u8 = 17;
+const STRUCT_END_OF_TABLE: u8 = 127;
+
+/// SMBIOS table of the system, located by [`smbios_init`].
+pub static SMBIOS: OnceCell<SmbiosTable> = OnceCell::uninit();
+
+/// Locates the SMBIOS table, and logs a summary of the hardware it describes.
+pub fn smbios_init() {
+    let Some(table) = SmbiosTable::locate() else {
+        info!("smbios", "no SMBIOS entry point found");
+        return;
+    };
+
+    info!(
+        "smbios",
+        "SMBIOS {}.{} present    table = {:#x}    len = {:#x}",
+        table.version.0,
+        table.version.1,
+        table.address,
+        table.length
+    );
+
+    if let Some(system) = table.system() {
+        info!(
+            "smbios",
+            "system: {} {} (version {})", system.manufacturer, system.product, system.version
+        );
+    }
+    if let Some(bios) = table.bios() {
+        info!(
+            "smbios",
+            "bios: {} {} ({})", bios.vendor, bios.version, bios.release_date
+        );
+    }
+    for cpu in table.processors() {
+        info!(
+            "smbios",
+            "cpu {}: {} {}    {} cores / {} threads    {} MHz",
+            cpu.socket,
+            cpu.manufacturer,
+            cpu.version,
+            cpu.core_count,
+            cpu.thread_count,
+            cpu.current_speed_mhz
+        );
+    }
+
+    let memory: Vec<MemoryDevice> = table.memory_devices();
+    let installed = memory.iter().filter(|mem| mem.size_mib != 0).count();
+    info!(
+        "smbios",
+        "memory: {} MiB in {}/{} slots",
+        memory.iter().map(|mem| mem.size_mib).sum::<u64>(),
+        installed,
+        memory.len()
+    );
+
+    SMBIOS.init_once(|| table);
+}
+
+/// Returns a textual description of the hardware described by the SMBIOS table, similar to the output of
+/// `dmidecode`.
+///
+/// Returns `None` if no SMBIOS table was located.
+pub fn smbios_report() -> Option<String> {
+    let table = SMBIOS.get()?;
+    let mut report = String::new();
+
+    let _ = writeln!(
+        report,
+        "SMBIOS {}.{} present.\n",
+        table.version.0, table.version.1
+    );
+
+    if let Some(bios) = table.bios() {
+        let _ = writeln!(
+            report,
+            "BIOS Information\n\tVendor: {}\n\tVersion: {}\n\tRelease Date: {}\n",
+            bios.vendor, bios.version, bios.release_date
+        );
+    }
+
+    if let Some(system) = table.system() {
+        let _ = writeln!(
+            report,
+            "System Information\n\tManufacturer: {}\n\tProduct Name: {}\n\tVersion: {}\n\tSerial Number: {}\n\tUUID: {}\n",
+            system.manufacturer, system.product, system.version, system.serial, format_uuid(&system.uuid)
+        );
+    }
+
+    if let Some(board) = table.baseboard() {
+        let _ = writeln!(
+            report,
+            "Base Board Information\n\tManufacturer: {}\n\tProduct Name: {}\n\tVersion: {}\n\tSerial Number: {}\n",
+            board.manufacturer, board.product, board.version, board.serial
+        );
+    }
+
+    for cpu in table.processors() {
+        let _ = writeln!(
+            report,
+            "Processor Information\n\tSocket Designation: {}\n\tManufacturer: {}\n\tVersion: {}\n\tID: {:016x}\n\tMax Speed: {} MHz\n\tCurrent Speed: {} MHz\n\tCore Count: {}\n\tThread Count: {}\n",
+            cpu.socket,
+            cpu.manufacturer,
+            cpu.version,
+            cpu.id,
+            cpu.max_speed_mhz,
+            cpu.current_speed_mhz,
+            cpu.core_count,
+            cpu.thread_count
+        );
+    }
+
+    for mem in table.memory_devices() {
+        let _ = write!(
+            report,
+            "Memory Device\n\tLocator: {}\n\tBank Locator: {}\n",
+            mem.locator, mem.bank_locator
+        );
+
+        if mem.size_mib == 0 {
+            let _ = writeln!(report, "\tSize: No Module Installed\n");
+            continue;
+        }
+
+        let _ = writeln!(
+            report,
+            "\tSize: {} MiB\n\tType: {}\n\tSpeed: {} MT/s\n\tManufacturer: {}\n\tSerial Number: {}\n\tPart Number: {}\n",
+            mem.size_mib,
+            memory_type_name(mem.memory_type),
+            mem.speed_mts,
+            mem.manufacturer,
+            mem.serial,
+            mem.part_number
+        );
+    }
+
+    Some(report)
+}
+
+/// Location of the SMBIOS table.
+#[derive(Clone, Copy, Debug)]
+pub struct SmbiosTable {
+    /// SMBIOS version (major, minor).
+    pub version: (u8, u8),
+
+    /// Physical address of the table.
+    pub address: u64,
+
+    /// Length of the table, in bytes (maximum length for 64-bit entry points).
+    pub length: usize,
+}
+
+impl SmbiosTable {
+    /// Locates the SMBIOS table, from the entry point found in the BIOS area.
+    ///
+    /// The 64-bit entry point is preferred if both are present.
+    pub fn locate() -> Option<Self> {
+        let area = unsafe {
+            slice::from_raw_parts(
+                ENTRY_POINT_AREA_START as *const u8,
+                ENTRY_POINT_AREA_END - ENTRY_POINT_AREA_START,
+            )
+        };
+        let entry_points = || (0..area.len()).step_by(16).map(|offset| &area[offset..]);
+
+        entry_points()
+            .find_map(Self::from_entry_point_64)
+            .or_else(|| entry_points().find_map(Self::from_entry_point_32))
+    }
+
+    fn from_entry_point_32(entry: &[u8]) -> Option<Self> {
+        if !entry.starts_with(b"_SM_") {
+            return None;
+        }
+
+        let length = usize::from(*entry.get(5)?);
+        if length < 0x1F || !checksum(entry.get(..length)?) || entry.get(0x10..0x15)? != b"_DMI_" {
+            return None;
+        }
+
+        Some(Self {
+            version: (entry[6], entry[7]),
+            address: u64::from(read_u32(entry, 0x18)?),
+            length: usize::from(read_u16(entry, 0x16)?),
+        })
+    }
+
+    fn from_entry_point_64(entry: &[u8]) -> Option<Self> {
+        if !entry.starts_with(b"_SM3_") {
+            return None;
+        }
+
+        let length = usize::from(*entry.get(6)?);
+        if length < 0x18 || !checksum(entry.get(..length)?) {
+            return None;
+        }
+
+        Some(Self {
+            version: (entry[7], entry[8]),
+            address: read_u64(entry, 0x10)?,
+            length: read_u32(entry, 0x0C)? as usize,
+        })
+    }
+
+    /// Returns an iterator over the structures of the table.
+    pub fn structures(&self) -> SmbiosStructures<'static> {
+        let bytes = usize::try_from(self.address).map_or(&[][..], |address| unsafe {
+            slice::from_raw_parts(address as *const u8, self.length)
+        });
+
+        SmbiosStructures { bytes }
+    }
+
+    /// Returns the first structure of type `kind`.
+    pub fn find(&self, kind: u8) -> Option<SmbiosStructure<'static>> {
+        self.structures().find(|structure| structure.kind == kind)
+    }
+
+    pub fn bios(&self) -> Option<BiosInfo> {
+        let bios = self.find(STRUCT_BIOS)?;
+
+        Some(BiosInfo {
+            vendor: bios.string_at(0x04),
+            version: bios.string_at(0x05),
+            release_date: bios.string_at(0x08),
+        })
+    }
+
+    pub fn system(&self) -> Option<SystemInfo> {
+        let system = self.find(STRUCT_SYSTEM)?;
+
+        let mut uuid = [0; 16];
+        if let Some(bytes) = system.formatted.get(0x08..0x18) {
+            uuid.copy_from_slice(bytes);
+        }
+
+        Some(SystemInfo {
+            manufacturer: system.string_at(0x04),
+            product: system.string_at(0x05),
+            version: system.string_at(0x06),
+            serial: system.string_at(0x07),
+            uuid,
+        })
+    }
+
+    pub fn baseboard(&self) -> Option<BaseboardInfo> {
+        let board = self.find(STRUCT_BASEBOARD)?;
+
+        Some(BaseboardInfo {
+            manufacturer: board.string_at(0x04),
+            product: board.string_at(0x05),
+            version: board.string_at(0x06),
+            serial: board.string_at(0x07),
+        })
+    }
+
+    /// Returns the processor sockets of the system.
+    pub fn processors(&self) -> Vec<ProcessorInfo> {
+        self.structures()
+            .filter(|structure| structure.kind == STRUCT_PROCESSOR)
+            .map(|cpu| ProcessorInfo {
+                socket: cpu.string_at(0x04),
+                manufacturer: cpu.string_at(0x07),
+                version: cpu.string_at(0x10),
+                id: cpu.qword(0x08).unwrap_or_default(),
+                max_speed_mhz: cpu.word(0x14).unwrap_or_default(),
+                current_speed_mhz: cpu.word(0x16).unwrap_or_default(),
+                populated: cpu.byte(0x18).map_or(false, |status| status & 0x40 != 0),
+                core_count: cpu.byte(0x23).unwrap_or_default(),
+                thread_count: cpu.byte(0x25).unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// Returns the memory devices (slots) of the system.
+    pub fn memory_devices(&self) -> Vec<MemoryDevice> {
+        self.structures()
+            .filter(|structure| structure.kind == STRUCT_MEMORY_DEVICE)
+            .map(|mem| MemoryDevice {
+                locator: mem.string_at(0x10),
+                bank_locator: mem.string_at(0x11),
+                size_mib: memory_device_size(&mem),
+                memory_type: mem.byte(0x12).unwrap_or_default(),
+                speed_mts: mem.word(0x15).unwrap_or_default(),
+                manufacturer: mem.string_at(0x17),
+                serial: mem.string_at(0x18),
+                part_number: mem.string_at(0x1A),
+            })
+            .collect()
+    }
+}
+
+/// BIOS information (type 0).
+#[derive(Clone, Debug)]
+pub struct BiosInfo {
+    pub vendor: String,
+    pub version: String,
+    pub release_date: String,
+}
+
+/// System information (type 1).
+#[derive(Clone, Debug)]
+pub struct SystemInfo {
+    pub manufacturer: String,
+    pub product: String,
+    pub version: String,
+    pub serial: String,
+    pub uuid: [u8; 16],
+}
+
+/// Baseboard information (type 2).
+#[derive(Clone, Debug)]
+pub struct BaseboardInfo {
+    pub manufacturer: String,
+    pub product: String,
+    pub version: String,
+    pub serial: String,
+}
+
+/// Processor socket information (type 4).
+#[derive(Clone, Debug)]
+pub struct ProcessorInfo {
+    pub socket: String,
+    pub manufacturer: String,
+    pub version: String,
+
+    /// Content of `EAX` and `EDX` for `CPUID` leaf 1.
+    pub id: u64,
+
+    pub max_speed_mhz: u16,
+    pub current_speed_mhz: u16,
+
+    /// Whether the socket holds a processor.
+    pub populated: bool,
+
+    pub core_count: u8,
+    pub thread_count: u8,
+}
+
+/// Memory device information (type 17).
+#[derive(Clone, Debug)]
+pub struct MemoryDevice {
+    /// Slot of the device.
+    pub locator: String,
+    pub bank_locator: String,
+
+    /// Size of the device in MiB, 0 if the slot is empty.
+    pub size_mib: u64,
+
+    /// Type of memory (`0x1A` for DDR4, see [`memory_type_name`]).
+    pub memory_type: u8,
+
+    /// Maximum speed, in megatransfers per second.
+    pub speed_mts: u16,
+
+    pub manufacturer: String,
+    pub serial: String,
+    pub part_number: String,
+}
+
+/// Structure of the SMBIOS table.
+#[derive(Clone, Copy, Debug)]
+pub struct SmbiosStructure<'t> {
+    pub kind: u8,
+    pub handle: u16,
+
+    /// Formatted area of the structure, including its header.
+    formatted: &'t [u8],
+
+    /// Strings referenced by the formatted area, separated by null bytes.
+    strings: &'t [u8],
+}
+
+impl<'t> SmbiosStructure<'t> {
+    pub fn byte(&self, offset: usize) -> Option<u8> {
+        self.formatted.get(offset).copied()
+    }
+
+    pub fn word(&self, offset: usize) -> Option<u16> {
+        read_u16(self.formatted, offset)
+    }
+
+    pub fn dword(&self, offset: usize) -> Option<u32> {
+        read_u32(self.formatted, offset)
+    }
+
+    pub fn qword(&self, offset: usize) -> Option<u64> {
+        read_u64(self.formatted, offset)
+    }
+
+    /// Returns the string whose index (starting at 1) is stored at `offset` in the formatted area.
+    pub fn string(&self, offset: usize) -> Option<&'t str> {
+        let index = usize::from(self.byte(offset)?).checked_sub(1)?;
+        let string = self.strings.split(|&byte| byte == 0).nth(index)?;
+
+        core::str::from_utf8(string).ok().map(str::trim)
+    }
+
+    /// Returns the string referenced at `offset`, or `"Not Specified"`.
+    fn string_at(&self, offset: usize) -> String {
+        self.string(offset)
+            .filter(|string| !string.is_empty())
+            .unwrap_or("Not Specified")
+            .to_string()
+    }
+}
+
+/// Iterator over the structures of an SMBIOS table.
+pub struct SmbiosStructures<'t> {
+    bytes: &'t [u8],
+}
+
+impl<'t> Iterator for SmbiosStructures<'t> {
+    type Item = SmbiosStructure<'t>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let kind = *self.bytes.first()?;
+        let length = usize::from(*self.bytes.get(1)?);
+
+        if kind == STRUCT_END_OF_TABLE || length < 4 || length > self.bytes.len() {
+            return None;
+        }
+
+        // The string set ends with two null bytes.
+        let strings_len = self.bytes[length..]
+            .windows(2)
+            .position(|window| window == [0, 0])?;
+        let (structure, rest) = self.bytes.split_at(length + strings_len + 2);
+        self.bytes = rest;
+
+        Some(SmbiosStructure {
+            kind,
+            handle: read_u16(structure, 2)?,
+            formatted: &structure[..length],
+            strings: &structure[length..length + strings_len],
+        })
+    }
+}
+
+/// Returns the name of an SMBIOS memory type.
+pub fn memory_type_name(memory_type: u8) -> &'static str {
+    match memory_type {
+        0x12 => "DDR",
+        0x13 => "DDR2",
+        0x18 => "DDR3",
+        0x1A => "DDR4",
+        0x1B => "LPDDR",
+        0x1C => "LPDDR2",
+        0x1D => "LPDDR3",
+        0x1E => "LPDDR4",
+        0x22 => "DDR5",
+        0x23 => "LPDDR5",
+        0x07 => "RAM",
+        0x09 => "FLASH",
+        _ => "Other",
+    }
+}
+
+/// Size of a memory device in MiB.
+fn memory_device_size(mem: &SmbiosStructure) -> u64 {
+    match mem.word(0x0C).unwrap_or_default() {
+        0 | 0xFFFF => 0,
+        0x7FFF => u64::from(mem.dword(0x1C).unwrap_or_default() & 0x7FFF_FFFF),
+        // Size in KiB.
+        size if size & 0x8000 != 0 => u64::from(size & 0x7FFF) / 1024,
+        size => u64::from(size),
+    }
+}
+
+fn format_uuid(uuid: &[u8; 16]) -> String {
+    let mut formatted = String::new();
+
+    // The first three fields are little-endian.
+    for (index, byte) in [3, 2, 1, 0, 5, 4, 7, 6, 8, 9, 10, 11, 12, 13, 14, 15]
+        .into_iter()
+        .enumerate()
+    {
+        if matches!(index, 4 | 6 | 8 | 10) {
+            formatted.push('-');
+        }
+        let _ = write!(formatted, "{:02X}", uuid[byte]);
+    }
+
+    formatted
+}
+
+fn checksum(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
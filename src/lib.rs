@@ -1,6 +1,7 @@
 #![feature(allow_internal_unstable)]
 #![feature(proc_macro_hygiene)]
 #![feature(noop_waker)]
+#![feature(panic_info_message)]
 #![feature(naked_functions)]
 #![feature(type_alias_impl_trait)]
 #![feature(allocator_api)]
@@ -49,8 +50,6 @@ pub use numtoa;
 #[cfg(feature = "alloc")]
 extern crate alloc;
 
-extern crate rlibc;
-
 /// Contains various symbols and constants often reused in the Kernel and bootloader code.
 pub mod kernel_syms {
     use crate::mem::{PhyAddr, VirtAddr};
@@ -58,9 +57,6 @@ pub mod kernel_syms {
     /// Starting physical address to which the Kernel is loaded.
     pub const KERNEL_LOAD_ADDR: PhyAddr = PhyAddr::new(0x800_000);
 
-    /// Size of the Kernel in sectors (512 bytes chunks).
-    pub const KERNEL_SECTOR_SZ: usize = 0x20 * 0x100;
-
     /// Standard size for every Kernel stack.
     pub const KERNEL_STACK_SIZE: usize = 0x800_000;
 
@@ -76,6 +72,15 @@ pub mod kernel_syms {
     /// Base virtual address of the Kernel heap.
     pub const KERNEL_HEAP_BASE: VirtAddr = VirtAddr::new(0xFFFF_B000_0000_0000);
 
+    /// Base virtual address of the segment dedicated to Kernel modules.
+    ///
+    /// Modules must be mapped within 2GB of the Kernel code, so that they can use 32-bit relative addressing to call
+    /// Kernel functions.
+    pub const KERNEL_MODULES_BASE: VirtAddr = VirtAddr::new(0xFFFF_8C00_4000_0000);
+
+    /// Size of the segment dedicated to Kernel modules.
+    pub const KERNEL_MODULES_SIZE: usize = 0x4000_0000;
+
     /// Physical address at which the Kernel [`PageTable`] is located.
     pub const KERNEL_PAGE_TABLE: PhyAddr = PhyAddr::new(0x200_000);
 
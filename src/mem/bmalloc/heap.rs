@@ -7,6 +7,11 @@
 //!
 //! It manages the heap both in real and protected
 //! mode.
+//!
+//! With the `heap_debug` feature, allocations are
+//! followed by a redzone and free blocks are poisoned,
+//! so that out-of-bounds writes and uses after free are
+//! detected (see [`LockedBuddyAllocator::check`]).
 
 use core::{
     alloc::{GlobalAlloc, Layout},
@@ -14,8 +19,24 @@ use core::{
     ptr::{self, NonNull},
 };
 
+use crate::errors::AllocError;
+#[cfg(feature = "heap_debug")]
+use crate::errors::{CanFail, HeapError};
+use crate::fail_point;
+use crate::mem::{oom, stats};
+
+#[cfg(feature = "heap_debug")]
+mod debug;
+
 const MIN_HEAP_ALIGN: usize = 8192;
 
+/// Size added to every allocation for the consistency checks.
+#[cfg(feature = "heap_debug")]
+const HEAP_DEBUG_OVERHEAD: usize = debug::OVERHEAD;
+
+#[cfg(not(feature = "heap_debug"))]
+const HEAP_DEBUG_OVERHEAD: usize = 0;
+
 /// Locked version of the [`BuddyAllocator`].
 ///
 /// It uses a spinlock-based Mutex to ensure interior
@@ -31,18 +52,94 @@ impl<const N: usize> LockedBuddyAllocator<N> {
             alloc: spin::Mutex::new(allocator),
         }
     }
+
+    /// Creates a heap without any memory: every allocation fails until [`LockedBuddyAllocator::init`] is called.
+    ///
+    /// Used when the location of the heap is only known at runtime.
+    pub const fn uninit() -> Self {
+        Self {
+            alloc: spin::Mutex::new(BuddyAllocator::empty()),
+        }
+    }
+
+    /// Hands the memory starting at `base_addr` over to the heap.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be usable, and not be used for anything else from then on. Blocks allocated before the call
+    /// must not be used anymore.
+    pub unsafe fn init(&self, base_addr: NonNull<u8>, max_blk_size: usize) {
+        self.alloc.lock().resize(base_addr, max_blk_size);
+    }
+
+    /// Allocates memory as described by the given [`Layout`].
+    ///
+    /// If the heap is exhausted, the registered OOM handlers are called to release some memory, and the allocation
+    /// is attempted a second time.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AllocError::InvalidLayout`] if the layout can never be satisfied by this heap, or
+    /// [`AllocError::OutOfMemory`] if no block was available even after reclaiming memory.
+    pub fn try_alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        fail_point!("alloc.heap", {
+            stats::record_heap_failure();
+            return Err(AllocError::OutOfMemory);
+        });
+
+        let result = oom::with_reclaim(layout.size(), || unsafe {
+            self.alloc.lock().try_allocate(layout)
+        });
+
+        match result {
+            Ok(_) => stats::record_heap_alloc(layout.size()),
+            Err(_) => stats::record_heap_failure(),
+        }
+
+        #[cfg(feature = "heap_debug")]
+        self.report_corruption();
+
+        result
+    }
+
+    /// Walks the whole heap, checking that no allocation overflowed into its redzone, and that no free block was
+    /// written to.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first [`HeapError`] found.
+    #[cfg(feature = "heap_debug")]
+    pub fn check(&self) -> CanFail<HeapError> {
+        self.alloc.lock().check()
+    }
+
+    /// Panics if a corruption was detected while allocating or freeing a block.
+    ///
+    /// Called once the heap is unlocked, so that the panic handler can still allocate memory.
+    #[cfg(feature = "heap_debug")]
+    fn report_corruption(&self) {
+        let corruption = self.alloc.lock().take_corruption();
+
+        if let Some(err) = corruption {
+            panic!("heap corruption detected: {err:?}");
+        }
+    }
 }
 
 unsafe impl<const N: usize> GlobalAlloc for LockedBuddyAllocator<N> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut allocator = self.alloc.lock();
-
-        allocator.allocate(layout)
+        self.try_alloc(layout)
+            .map_or(ptr::null_mut(), |ptr| ptr.as_ptr())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        stats::record_heap_free(layout.size());
         let mut allocator = self.alloc.lock();
         allocator.deallocate(ptr, layout);
+        drop(allocator);
+
+        #[cfg(feature = "heap_debug")]
+        self.report_corruption();
     }
 }
 
@@ -102,6 +199,12 @@ pub struct BuddyAllocator<const N: usize> {
     /// the top of the linked lists keeping track of
     /// free blocks for each size
     free_lists: [NullLock<*mut FreeBlock>; N],
+
+    /// First corruption detected while allocating or
+    /// freeing a block, reported once the heap is
+    /// unlocked.
+    #[cfg(feature = "heap_debug")]
+    corruption: Option<HeapError>,
 }
 
 impl<const N: usize> BuddyAllocator<N> {
@@ -129,6 +232,20 @@ impl<const N: usize> BuddyAllocator<N> {
         unsafe { Self::from_base_unchecked(base_addr, max_blk_size) }
     }
 
+    /// Creates a `BuddyAllocator` without memory to manage, to be set up later on using
+    /// [`BuddyAllocator::resize`].
+    pub const fn empty() -> Self {
+        Self {
+            base_addr: NullLock::new(ptr::null_mut()),
+            max_blk_size: 0,
+            min_blk_size: 0,
+            log2_min_blk_size: 0,
+            free_lists: [NullLock::new(ptr::null_mut()); N],
+            #[cfg(feature = "heap_debug")]
+            corruption: None,
+        }
+    }
+
     /// Resizes or translates the heap.
     ///
     /// Can be used to dynamically set up the heap depending on available physical memory.
@@ -157,6 +274,8 @@ impl<const N: usize> BuddyAllocator<N> {
 
         let log2_min_blk_size = log2(min_blk_size);
         self.log2_min_blk_size = log2_min_blk_size;
+
+        stats::set_heap_size(max_blk_size);
     }
 
     const unsafe fn from_base_unchecked(base_addr: NonNull<u8>, max_blk_size: usize) -> Self {
@@ -179,11 +298,13 @@ impl<const N: usize> BuddyAllocator<N> {
             min_blk_size,
             free_lists,
             log2_min_blk_size,
+            #[cfg(feature = "heap_debug")]
+            corruption: None,
         }
     }
 
     unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
-        let level_req = self.allocation_level(layout.size(), layout.align());
+        let level_req = self.allocation_level(padded_size(layout.size()), layout.align());
         let mut level = level_req;
 
         // We iterate over the possible block sizes, until
@@ -191,9 +312,16 @@ impl<const N: usize> BuddyAllocator<N> {
         // bigger than the minimal size, we split it.
         while (level as usize) < self.free_lists.len() {
             if let Some(blk) = self.pop_blk_with_level(level) {
+                #[cfg(feature = "heap_debug")]
+                self.check_free_block(blk, level);
+
                 if level > level_req {
                     self.split_blk(blk, level, level_req);
                 }
+
+                #[cfg(feature = "heap_debug")]
+                self.arm_block(blk, level_req, layout.size());
+
                 return blk;
             }
             level += 1;
@@ -203,8 +331,30 @@ impl<const N: usize> BuddyAllocator<N> {
         ptr::null_mut()
     }
 
+    /// Allocates a block as described by the given [`Layout`], without panicking if the layout is invalid.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AllocError::InvalidLayout`] if the layout can never be satisfied by this heap, or
+    /// [`AllocError::OutOfMemory`] if no block is currently available.
+    pub unsafe fn try_allocate(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if self.max_blk_size == 0 {
+            return Err(AllocError::Uninitialized);
+        }
+
+        if padded_size(layout.size()) >= self.max_blk_size || layout.align() >= MIN_HEAP_ALIGN {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        NonNull::new(self.allocate(layout)).ok_or(AllocError::OutOfMemory)
+    }
+
     unsafe fn deallocate(&mut self, block: *mut u8, layout: Layout) {
-        let alloc_level = self.allocation_level(layout.size(), layout.align()) as usize;
+        let alloc_level =
+            self.allocation_level(padded_size(layout.size()), layout.align()) as usize;
+
+        #[cfg(feature = "heap_debug")]
+        self.check_freed_block(block, alloc_level as u8, layout.size());
 
         let mut full_block = block;
         // We merge our newly freed block with its buddy if
@@ -221,6 +371,9 @@ impl<const N: usize> BuddyAllocator<N> {
             // We cannot remove the buddy from the free
             // list, which means it is currently in used,
             // we can stop merging here.
+            #[cfg(feature = "heap_debug")]
+            self.poison_block(full_block, level as u8);
+
             self.free_blk(full_block, level as u8);
 
             return;
@@ -398,6 +551,11 @@ impl<T: Copy> NullLock<T> {
 unsafe impl<T: Copy> Sync for NullLock<T> {}
 unsafe impl<T: Copy> Send for NullLock<T> {}
 
+/// Size of the block needed to allocate `size` bytes, including the redzone with the `heap_debug` feature.
+const fn padded_size(size: usize) -> usize {
+    size + HEAP_DEBUG_OVERHEAD
+}
+
 const fn log2(mut a: usize) -> usize {
     let mut power = 0;
 
@@ -0,0 +1,235 @@
+//! Heap consistency checks, enabled with the `heap_debug` feature.
+//!
+//! - every allocation is followed by a redzone of at least [`REDZONE_SIZE`] bytes, filled with [`REDZONE_BYTE`], and
+//!   by an [`AllocTrailer`] recording the size of the allocation at the end of its block. Both are checked when the
+//!   allocation is freed.
+//! - free blocks are filled with [`POISON_BYTE`] (except for their [`FreeBlock`] header), which is checked when the
+//!   block is allocated again.
+//!
+//! [`BuddyAllocator::check`] walks the whole heap and performs both checks on every block. The entire heap is only
+//! poisoned the first time a block is allocated from it, so the free block spanning the whole heap is never checked.
+
+use core::ptr;
+
+use crate::errors::{CanFail, HeapError};
+
+use super::{BuddyAllocator, FreeBlock};
+
+/// Minimum size of the redzone following an allocation, in bytes.
+pub(super) const REDZONE_SIZE: usize = 16;
+
+/// Byte filling the redzones.
+pub(super) const REDZONE_BYTE: u8 = 0xFD;
+
+/// Byte filling the free blocks.
+pub(super) const POISON_BYTE: u8 = 0x6B;
+
+/// Size added to every allocation, in bytes.
+pub(super) const OVERHEAD: usize = REDZONE_SIZE + core::mem::size_of::<AllocTrailer>();
+
+/// Tag identifying an [`AllocTrailer`], combined with the address and level of the block.
+const TRAILER_TAG: usize = 0x4B43_4548;
+
+/// Stored in the last bytes of an allocated block.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct AllocTrailer {
+    /// Size of the allocation, in bytes.
+    size: usize,
+    tag: usize,
+}
+
+impl<const N: usize> BuddyAllocator<N> {
+    /// Walks the whole heap, checking the redzones of the allocated blocks and the poison of the free blocks.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first [`HeapError`] found.
+    pub fn check(&self) -> CanFail<HeapError> {
+        if self.max_blk_size == 0 {
+            return Ok(());
+        }
+
+        let base = self.base_addr.inner as usize;
+        let end = base + self.max_blk_size;
+
+        for level in 0..N as u8 {
+            for block in self.free_blocks(level) {
+                let addr = block as usize;
+                if !(base..end).contains(&addr) || (addr - base) % self.level_size(level) != 0 {
+                    return Err(HeapError::InvalidFreeBlock(addr));
+                }
+
+                unsafe { self.check_poison(block, level)? };
+            }
+        }
+
+        let mut addr = base;
+        while addr < end {
+            let block = addr as *mut u8;
+            let level = match self.free_level(block) {
+                Some(level) => level,
+                None => {
+                    let (level, trailer) = (0..N as u8)
+                        .find_map(|level| unsafe { self.trailer(block, level) }.map(|t| (level, t)))
+                        .ok_or(HeapError::InvalidBlock(addr))?;
+
+                    unsafe { self.check_allocated(block, level, trailer.size)? };
+
+                    level
+                }
+            };
+
+            addr += self.level_size(level);
+        }
+
+        Ok(())
+    }
+
+    /// Checks a block that was just removed from the free list of `level`, recording a corruption if its poison was
+    /// overwritten.
+    pub(super) unsafe fn check_free_block(&mut self, block: *mut u8, level: u8) {
+        // The whole heap is not poisoned until a block is allocated from it.
+        if level as usize == N - 1 {
+            self.poison_block(block, level);
+            return;
+        }
+
+        if let Err(err) = self.check_poison(block, level) {
+            self.record_corruption(err);
+        }
+    }
+
+    /// Checks an allocated block that is being freed, recording a corruption if its redzone was overwritten.
+    pub(super) unsafe fn check_freed_block(&mut self, block: *mut u8, level: u8, size: usize) {
+        if let Err(err) = self.check_allocated(block, level, size) {
+            self.record_corruption(err);
+        }
+    }
+
+    /// Fills the redzone of a block allocated for `size` bytes, and writes its [`AllocTrailer`].
+    pub(super) unsafe fn arm_block(&self, block: *mut u8, level: u8, size: usize) {
+        let trailer_offset = self.level_size(level) - core::mem::size_of::<AllocTrailer>();
+
+        ptr::write_bytes(block.add(size), REDZONE_BYTE, trailer_offset - size);
+        ptr::write(
+            block.add(trailer_offset).cast::<AllocTrailer>(),
+            AllocTrailer {
+                size,
+                tag: trailer_tag(block, level),
+            },
+        );
+    }
+
+    /// Fills a free block with [`POISON_BYTE`], except for its [`FreeBlock`] header.
+    pub(super) unsafe fn poison_block(&self, block: *mut u8, level: u8) {
+        let header_size = core::mem::size_of::<FreeBlock>();
+
+        ptr::write_bytes(
+            block.add(header_size),
+            POISON_BYTE,
+            self.level_size(level) - header_size,
+        );
+    }
+
+    /// Returns and clears the first corruption detected while allocating or freeing a block.
+    pub(super) fn take_corruption(&mut self) -> Option<HeapError> {
+        self.corruption.take()
+    }
+
+    fn record_corruption(&mut self, err: HeapError) {
+        self.corruption.get_or_insert(err);
+    }
+
+    unsafe fn check_poison(&self, block: *mut u8, level: u8) -> CanFail<HeapError> {
+        if level as usize == N - 1 {
+            return Ok(());
+        }
+
+        let header_size = core::mem::size_of::<FreeBlock>();
+        let poison = core::slice::from_raw_parts(
+            block.add(header_size),
+            self.level_size(level) - header_size,
+        );
+
+        if poison.iter().any(|&byte| byte != POISON_BYTE) {
+            return Err(HeapError::PoisonOverwritten(block as usize));
+        }
+
+        Ok(())
+    }
+
+    unsafe fn check_allocated(&self, block: *mut u8, level: u8, size: usize) -> CanFail<HeapError> {
+        let trailer = self
+            .trailer(block, level)
+            .filter(|trailer| trailer.size == size)
+            .ok_or(HeapError::InvalidBlock(block as usize))?;
+
+        let trailer_offset = self.level_size(level) - core::mem::size_of::<AllocTrailer>();
+        let redzone =
+            core::slice::from_raw_parts(block.add(trailer.size), trailer_offset - trailer.size);
+
+        if redzone.iter().any(|&byte| byte != REDZONE_BYTE) {
+            return Err(HeapError::RedzoneOverwritten(block as usize));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the trailer of `block`, if it is an allocated block of level `level`.
+    unsafe fn trailer(&self, block: *mut u8, level: u8) -> Option<AllocTrailer> {
+        let offset = block as usize - self.base_addr.inner as usize;
+        let block_size = self.level_size(level);
+
+        if offset % block_size != 0 || offset + block_size > self.max_blk_size {
+            return None;
+        }
+
+        let trailer = ptr::read(
+            block
+                .add(block_size - core::mem::size_of::<AllocTrailer>())
+                .cast::<AllocTrailer>(),
+        );
+
+        (trailer.tag == trailer_tag(block, level) && trailer.size + OVERHEAD <= block_size)
+            .then_some(trailer)
+    }
+
+    /// Returns the level of the free list containing `block`, if any.
+    fn free_level(&self, block: *mut u8) -> Option<u8> {
+        (0..N as u8).find(|&level| self.free_blocks(level).any(|free| free == block))
+    }
+
+    /// Iterates over the blocks of the free list of `level`.
+    ///
+    /// The header of a block is only read once the following block is requested, so that a block can be checked
+    /// before being dereferenced.
+    fn free_blocks(&self, level: u8) -> impl Iterator<Item = *mut u8> + '_ {
+        let max_blocks = self.max_blk_size / self.level_size(level);
+        let mut next = self.free_lists[level as usize].inner;
+        let mut prev: Option<*mut FreeBlock> = None;
+
+        core::iter::from_fn(move || {
+            if let Some(prev) = prev {
+                // The `next_blk` field of the block spanning the whole heap is not initialized.
+                next = if level as usize == N - 1 {
+                    ptr::null_mut()
+                } else {
+                    unsafe { (*prev).next_blk.inner }
+                };
+            }
+
+            if next.is_null() {
+                return None;
+            }
+
+            prev = Some(next);
+            Some(next.cast::<u8>())
+        })
+        .take(max_blocks)
+    }
+}
+
+fn trailer_tag(block: *mut u8, level: u8) -> usize {
+    TRAILER_TAG ^ block as usize ^ usize::from(level)
+}
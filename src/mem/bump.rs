@@ -0,0 +1,114 @@
+//! Early-boot bump allocator.
+//!
+//! Before the heap is set up, allocations are served from a small static arena, by moving a cursor forward: memory is
+//! never freed. It is meant for the few structures that have to be copied out of the locations where the real-mode
+//! code left them (the VESA mode information, the E820 memory map), before anything else can overwrite them.
+//!
+//! The arena lives in `.bss`: it must only be used once [`zero_bss`] was called. Once the heap is available,
+//! [`early_alloc_seal`] is called, and any later early allocation fails: the heap must be used instead. Memory
+//! allocated from the arena remains valid for the whole boot.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::mem::bump::early_box;
+//!
+//! let snapshot: &'static mut [u32; 4] = early_box([0; 4]).expect("early arena exhausted");
+//! ```
+//!
+//! [`zero_bss`]: crate::mem::zero_bss
+
+use core::alloc::Layout;
+use core::cell::UnsafeCell;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::info;
+
+/// Size of the early arena, in bytes.
+pub const EARLY_ARENA_SIZE: usize = 0x4000;
+
+static EARLY_ARENA: EarlyArena = EarlyArena(UnsafeCell::new([0; EARLY_ARENA_SIZE]));
+
+/// Offset of the first free byte of the arena.
+static EARLY_CURSOR: AtomicUsize = AtomicUsize::new(0);
+
+static EARLY_SEALED: AtomicBool = AtomicBool::new(false);
+
+#[repr(C, align(4096))]
+struct EarlyArena(UnsafeCell<[u8; EARLY_ARENA_SIZE]>);
+
+// Allocations never overlap: each byte of the arena is handed out at most once.
+unsafe impl Sync for EarlyArena {}
+
+/// Allocates a block of memory matching `layout` from the early arena.
+///
+/// Returns `None` if the arena is exhausted, or if it was sealed with [`early_alloc_seal`].
+pub fn early_alloc(layout: Layout) -> Option<NonNull<u8>> {
+    if EARLY_SEALED.load(Ordering::Acquire) {
+        return None;
+    }
+
+    let base = EARLY_ARENA.0.get() as *mut u8;
+    let mut cursor = EARLY_CURSOR.load(Ordering::Relaxed);
+
+    loop {
+        let start = (base as usize + cursor).next_multiple_of(layout.align()) - base as usize;
+        let end = start.checked_add(layout.size())?;
+        if end > EARLY_ARENA_SIZE {
+            return None;
+        }
+
+        match EARLY_CURSOR.compare_exchange_weak(cursor, end, Ordering::AcqRel, Ordering::Relaxed) {
+            Ok(_) => return NonNull::new(unsafe { base.add(start) }),
+            Err(current) => cursor = current,
+        }
+    }
+}
+
+/// Moves `value` into the early arena.
+///
+/// Returns `None` if there is not enough space left in the arena (`value` is dropped).
+pub fn early_box<T>(value: T) -> Option<&'static mut T> {
+    let ptr = early_alloc(Layout::new::<T>())?.cast::<T>().as_ptr();
+
+    unsafe {
+        ptr::write(ptr, value);
+        Some(&mut *ptr)
+    }
+}
+
+/// Copies `values` into the early arena.
+///
+/// Returns `None` if there is not enough space left in the arena.
+pub fn early_slice<T: Copy>(values: &[T]) -> Option<&'static mut [T]> {
+    let ptr = early_alloc(Layout::array::<T>(values.len()).ok()?)?
+        .cast::<T>()
+        .as_ptr();
+
+    unsafe {
+        ptr::copy_nonoverlapping(values.as_ptr(), ptr, values.len());
+        Some(core::slice::from_raw_parts_mut(ptr, values.len()))
+    }
+}
+
+/// Hands the early allocations over to the heap: the early arena cannot be used anymore.
+///
+/// The memory already allocated from the arena remains valid.
+pub fn early_alloc_seal() {
+    if EARLY_SEALED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    info!(
+        "mem",
+        "early allocator sealed ({:#x} / {:#x} bytes used)",
+        early_alloc_used(),
+        EARLY_ARENA_SIZE
+    );
+}
+
+/// Number of bytes allocated from the early arena (including the alignment padding).
+pub fn early_alloc_used() -> usize {
+    EARLY_CURSOR.load(Ordering::Acquire)
+}
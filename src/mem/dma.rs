@@ -0,0 +1,234 @@
+//! DMA buffers.
+//!
+//! Devices access memory using bus addresses, which differ from physical addresses when an `IOMMU` translates their
+//! requests (see [`vtd`]). A [`DmaBuffer`] is a physically contiguous, zeroed buffer allocated for a single device:
+//! the driver programs the device with [`DmaBuffer::dma_addr`], and accesses the content of the buffer through the
+//! kernel mapping of physical memory.
+//!
+//! With DMA remapping enabled, a device can only reach the buffers allocated for it, and their bus addresses are
+//! chosen below the addressing limit of the device (its DMA mask): a device limited to 32-bit addresses can use
+//! buffers located anywhere in physical memory. Without remapping, the bus address is the physical address, and
+//! allocations beyond the DMA mask fail.
+//!
+//! No `IOMMU` is used by the bootloader: buffers are allocated on the heap, which is identity-mapped, and their bus
+//! address is their physical address.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::mem::dma::{dma_alloc, DmaDevice, DMA_MASK_32};
+//!
+//! let mut buffer = dma_alloc(DmaDevice::from(&pci_device), 4096, DMA_MASK_32).unwrap();
+//! buffer.as_mut_slice()[0] = 0x42;
+//!
+//! program_device(buffer.dma_addr());
+//! ```
+
+#[cfg(not(feature = "x86_64"))]
+use alloc::alloc::{alloc_zeroed, dealloc, Layout};
+use core::slice;
+
+use crate::{drivers::pci::device::PCIDevice, errors::DmaError, kernel_syms::PAGE_SIZE};
+#[cfg(feature = "x86_64")]
+use crate::{
+    drivers::vtd::{iommu_map, iommu_unmap},
+    x86::paging::page_alloc::frame_alloc::{alloc_page, free_page, FrameAllocation},
+};
+
+use super::{get_physical_memory, PhyAddr};
+
+/// DMA mask of devices limited to 32-bit bus addresses.
+pub const DMA_MASK_32: u64 = 0xFFFF_FFFF;
+
+/// DMA mask of devices that can use any 64-bit bus address.
+pub const DMA_MASK_64: u64 = u64::MAX;
+
+/// PCI function performing DMA, identified by its location.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DmaDevice {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl DmaDevice {
+    /// Device on the PCI segment 0.
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            segment: 0,
+            bus,
+            device,
+            function,
+        }
+    }
+
+    /// Returns the PCI requester identifier of the device, which tags its DMA requests.
+    pub const fn source_id(&self) -> u16 {
+        ((self.bus as u16) << 8) | ((self.device as u16) << 3) | (self.function as u16)
+    }
+
+    /// Returns the device of `segment` whose requester identifier is `source_id`.
+    pub const fn from_source_id(segment: u16, source_id: u16) -> Self {
+        Self {
+            segment,
+            bus: (source_id >> 8) as u8,
+            device: ((source_id >> 3) & 0x1f) as u8,
+            function: (source_id & 0x7) as u8,
+        }
+    }
+}
+
+impl From<&PCIDevice<'_>> for DmaDevice {
+    fn from(value: &PCIDevice<'_>) -> Self {
+        let (bus, device, function) = value.location();
+
+        Self::new(bus, device, function)
+    }
+}
+
+/// Physically contiguous buffer, mapped for DMA by a single device.
+///
+/// The buffer is unmapped from the device and released when dropped: the device must not access it anymore.
+#[derive(Debug)]
+pub struct DmaBuffer {
+    device: DmaDevice,
+    start: PhyAddr,
+
+    /// Size of the physical allocation.
+    frames_len: usize,
+
+    /// Size of the buffer, rounded up to a whole number of pages.
+    len: usize,
+
+    dma_addr: u64,
+
+    /// Whether the buffer is mapped in the `IOMMU` domain of the device.
+    remapped: bool,
+}
+
+impl DmaBuffer {
+    /// Device for which the buffer is mapped.
+    pub fn device(&self) -> DmaDevice {
+        self.device
+    }
+
+    /// Bus address of the buffer, to be used by the device.
+    pub fn dma_addr(&self) -> u64 {
+        self.dma_addr
+    }
+
+    /// Physical address of the buffer.
+    pub fn phys_addr(&self) -> PhyAddr {
+        self.start
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(get_physical_memory(self.start), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(get_physical_memory(self.start), self.len) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    #[cfg(feature = "x86_64")]
+    fn drop(&mut self) {
+        if self.remapped {
+            iommu_unmap(self.device, self.dma_addr, self.len);
+        }
+
+        free_page(FrameAllocation {
+            start: self.start,
+            length: self.frames_len,
+        });
+    }
+
+    #[cfg(not(feature = "x86_64"))]
+    fn drop(&mut self) {
+        unsafe {
+            dealloc(
+                get_physical_memory(self.start),
+                heap_layout(self.frames_len),
+            )
+        }
+    }
+}
+
+/// Allocates a zeroed buffer of at least `size` bytes, for DMA by `device`.
+///
+/// `mask` is the highest bus address the device can use (see [`DMA_MASK_32`] and [`DMA_MASK_64`]).
+///
+/// # Errors
+///
+/// Returns [`DmaError::InvalidSize`] if `size` is zero, [`DmaError::OutOfMemory`] if the buffer cannot be
+/// allocated, and [`DmaError::Unreachable`] (without remapping) or [`DmaError::AddressSpaceExhausted`] (with
+/// remapping) if the buffer cannot be placed below `mask`.
+pub fn dma_alloc(device: DmaDevice, size: usize, mask: u64) -> Result<DmaBuffer, DmaError> {
+    if size == 0 {
+        return Err(DmaError::InvalidSize);
+    }
+
+    let len = size.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+    let (start, frames_len) = alloc_frames(len)?;
+
+    let mut buffer = DmaBuffer {
+        device,
+        start,
+        frames_len,
+        len,
+        dma_addr: u64::from(start),
+        remapped: false,
+    };
+    buffer.as_mut_slice().fill(0);
+
+    #[cfg(feature = "x86_64")]
+    if let Some(dma_addr) = iommu_map(device, buffer.start, len, mask) {
+        buffer.dma_addr = dma_addr?;
+        buffer.remapped = true;
+
+        return Ok(buffer);
+    }
+
+    if buffer.dma_addr + (len as u64 - 1) > mask {
+        return Err(DmaError::Unreachable);
+    }
+
+    Ok(buffer)
+}
+
+/// Allocates `len` bytes of physically contiguous memory, and returns their physical address and the size of the
+/// allocation.
+#[cfg(feature = "x86_64")]
+fn alloc_frames(len: usize) -> Result<(PhyAddr, usize), DmaError> {
+    let frames = alloc_page(len).map_err(|_| DmaError::OutOfMemory)?;
+
+    Ok((frames.start, frames.length))
+}
+
+/// Allocates `len` bytes of physically contiguous memory, and returns their physical address and the size of the
+/// allocation.
+#[cfg(not(feature = "x86_64"))]
+fn alloc_frames(len: usize) -> Result<(PhyAddr, usize), DmaError> {
+    let ptr = unsafe { alloc_zeroed(heap_layout(len)) };
+    if ptr.is_null() {
+        return Err(DmaError::OutOfMemory);
+    }
+
+    Ok((PhyAddr::new(ptr as u64), len))
+}
+
+/// Heap allocations are page-aligned, so that they can be used where the device requires an aligned buffer.
+#[cfg(not(feature = "x86_64"))]
+fn heap_layout(len: usize) -> Layout {
+    Layout::from_size_align(len, PAGE_SIZE).expect("invalid DMA buffer size")
+}
@@ -1,17 +1,51 @@
-use core::{arch::asm, ptr};
+use core::alloc::Layout;
+use core::ptr;
 
 use bitfield::bitfield;
+use conquer_once::spin::OnceCell;
+use spin::Mutex;
 
-use crate::{errors::E820Error, hex_print, video::io::cprint_info};
+use crate::{
+    errors::{CanFail, E820Error},
+    hex_print,
+    mem::bump::early_alloc,
+    video::io::cprint_info,
+};
 
 pub const E820_MAP_ADDR: u32 = 0x4804;
 pub static mut E820_MAP_LENGTH: u32 = 0;
 
+/// Copy of the memory map left by the real-mode code at [`E820_MAP_ADDR`] (see [`e820_snapshot`]).
+static E820_SNAPSHOT: OnceCell<&'static [AddressRangeDescriptor]> = OnceCell::uninit();
+
 #[cfg(feature = "alloc")]
 /// Returns the list of memory entries returned by BIOS 0xE820 function.
 pub fn e820_entries_bootloader() -> alloc::vec::Vec<AddressRangeDescriptor> {
-    let map = E820MemoryMap::new(E820_MAP_ADDR as *mut u8);
-    map.into_iter().collect()
+    e820_snapshot().to_vec()
+}
+
+/// Returns the memory map returned by the BIOS 0xE820 function.
+///
+/// The first call copies the map from [`E820_MAP_ADDR`] into the early arena (see [`early_alloc`]): it must happen
+/// once the `.bss` section was cleared, before the low memory is reused.
+///
+/// # Panics
+///
+/// Panics if the early arena is exhausted or sealed when the copy is made.
+pub fn e820_snapshot() -> &'static [AddressRangeDescriptor] {
+    E820_SNAPSHOT.get_or_init(|| {
+        let entry_count = unsafe { ptr::read((E820_MAP_ADDR - 0x4) as *const u32) } as usize;
+        let entries = early_alloc(Layout::array::<AddressRangeDescriptor>(entry_count).unwrap())
+            .expect("no space left for the memory map")
+            .cast::<AddressRangeDescriptor>()
+            .as_ptr();
+
+        for (index, entry) in E820MemoryMap::default().take(entry_count).enumerate() {
+            unsafe { ptr::write(entries.add(index), entry) };
+        }
+
+        unsafe { core::slice::from_raw_parts(entries, entry_count) }
+    })
 }
 
 #[derive(Debug)]
@@ -114,47 +148,8 @@ bitfield! {
     #[repr(packed)]
     pub struct ExtendedAttributesARDS(u8);
     u32;
-    should_ignore, _: 1, 0;
-    non_volatile, _: 1, 1;
-}
-
-#[cfg(not(feature = "x86_64"))]
-fn __mem_entry_e820(mut ebx: u32, buffer: u32) -> Result<u32, E820Error> {
-    let cf: u32;
-
-    unsafe {
-        asm!(
-        "pushf",
-        "push es",
-        "push di",
-        "push ecx",
-        "mov di, ax",
-        "xor ax, ax",
-        "mov es, ax",
-        "mov edx, 0x534D4150",
-        "mov eax, 0xe820",
-        "mov ecx, 24",
-        "int 0x15",
-        "xor edx, edx",
-        "jnc 2f",
-        "mov edx, 1",
-        "2: mov eax, ebx",
-        "pop ecx",
-        "pop di",
-        "pop es",
-        "popf",
-        in("ebx") ebx,
-        in("ax") buffer,
-        lateout("eax") ebx,
-        out("edx") cf,
-        )
-    }
-
-    if cf == 1 || ebx == 0 {
-        return Err(E820Error::new());
-    }
-
-    Ok(ebx)
+    pub should_ignore, _: 1, 0;
+    pub non_volatile, _: 1, 1;
 }
 
 fn e820_type_print(descriptor: &AddressRangeDescriptor) {
@@ -188,16 +183,20 @@ fn e820_type_print(descriptor: &AddressRangeDescriptor) {
 
 #[cfg(feature = "real")]
 pub fn memory_map() {
-    use crate::rinfo;
+    use crate::{
+        bios::services::memory::{e820_entry, E820_ENTRY_SIZE},
+        rinfo,
+    };
 
     let mut entry_count: u32 = 0;
     let mut ebx: u32 = 0;
 
-    while let Ok(result) = __mem_entry_e820(ebx, E820_MAP_ADDR + entry_count * 24) {
+    while let Ok(result) = e820_entry(ebx, (E820_MAP_ADDR + entry_count * E820_ENTRY_SIZE) as u16) {
         ebx = result;
         entry_count += 1;
 
-        let ard = (E820_MAP_ADDR + (entry_count - 1) * 24) as *mut AddressRangeDescriptor;
+        let ard =
+            (E820_MAP_ADDR + (entry_count - 1) * E820_ENTRY_SIZE) as *mut AddressRangeDescriptor;
         let descriptor: &AddressRangeDescriptor = unsafe { &*ard };
 
         let base_addr = (descriptor.base_addr_high << 16) + descriptor.base_addr_low;
@@ -209,7 +208,328 @@ pub fn memory_map() {
         hex_print!((base_addr + length - 1), u32);
 
         e820_type_print(descriptor);
+
+        if ebx == 0 {
+            break;
+        }
     }
 
     unsafe { ptr::write((E820_MAP_ADDR - 0x2) as *mut u32, entry_count) }
 }
+
+/// Maximum number of memory regions that can be tracked after post-processing the E820 memory map.
+pub const MAX_MEMORY_REGIONS: usize = 128;
+
+static MEMORY_REGIONS: OnceCell<Mutex<MemoryRegions>> = OnceCell::uninit();
+
+/// Initializes the global list of [`MemoryRegions`] from the memory map returned by the firmware.
+///
+/// The regions are sorted and normalized (overlapping entries are resolved, adjacent entries of the
+/// same kind are merged). Memory reservations can then be made using [`MemoryRegions::reserve`].
+pub fn init_memory_regions(
+    map: impl IntoIterator<Item = AddressRangeDescriptor>,
+) -> &'static Mutex<MemoryRegions> {
+    MEMORY_REGIONS.init_once(|| Mutex::new(MemoryRegions::from_e820(map)));
+
+    memory_regions()
+}
+
+/// Returns the global list of [`MemoryRegions`], built from the firmware memory map.
+///
+/// # Panics
+///
+/// Panics if the memory regions were not initialized using [`init_memory_regions`].
+pub fn memory_regions() -> &'static Mutex<MemoryRegions> {
+    if let Some(regions) = MEMORY_REGIONS.get() {
+        regions
+    } else {
+        panic!("attempt to access memory regions before initialization")
+    }
+}
+
+/// Kind of a physical [`MemoryRegion`].
+///
+/// The ordering of the variants matters: when two regions overlap, the _greatest_ kind is kept for
+/// the overlapping area.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MemoryRegionKind {
+    /// Free memory, available to the operating system.
+    Usable,
+
+    /// ACPI tables, that can be reclaimed once they have been parsed.
+    AcpiReclaimable,
+
+    /// Persistent (non-volatile) memory.
+    Persistent,
+
+    /// ACPI Non-Volatile Storage, must be preserved across sleep states.
+    AcpiNvs,
+
+    /// Memory reserved by the firmware.
+    Reserved,
+
+    /// Memory that contains errors, or that was disabled by the firmware.
+    Unusable,
+
+    /// Memory used by the bootloader (code, heap, stacks, ...).
+    Bootloader,
+
+    /// Memory holding the kernel image and its early structures.
+    Kernel,
+}
+
+impl From<E820MemType> for MemoryRegionKind {
+    fn from(value: E820MemType) -> Self {
+        match value {
+            E820MemType::RAM => Self::Usable,
+            E820MemType::ACPI => Self::AcpiReclaimable,
+            E820MemType::NVS => Self::AcpiNvs,
+            E820MemType::PERSISTENT => Self::Persistent,
+            E820MemType::UNUSABLE | E820MemType::DISABLED => Self::Unusable,
+            E820MemType::RESERVED | E820MemType::OEM => Self::Reserved,
+        }
+    }
+}
+
+/// A contiguous range of physical memory of a given [`MemoryRegionKind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    /// Physical address of the first byte of the region.
+    pub base: u64,
+
+    /// Length of the region, in bytes.
+    pub length: u64,
+
+    /// Kind of memory in this region.
+    pub kind: MemoryRegionKind,
+}
+
+impl MemoryRegion {
+    const EMPTY: Self = Self {
+        base: 0,
+        length: 0,
+        kind: MemoryRegionKind::Reserved,
+    };
+
+    pub const fn new(base: u64, length: u64, kind: MemoryRegionKind) -> Self {
+        Self { base, length, kind }
+    }
+
+    /// Returns the physical address following the last byte of this region.
+    pub fn end(&self) -> u64 {
+        self.base.saturating_add(self.length)
+    }
+
+    /// Returns a pointer to the base memory address of this `MemoryRegion`.
+    pub fn base_addr(&self) -> *mut u8 {
+        self.base as *mut u8
+    }
+
+    pub fn is_usable(&self) -> bool {
+        matches!(self.kind, MemoryRegionKind::Usable)
+    }
+
+    fn contains(&self, addr: u64) -> bool {
+        addr >= self.base && addr < self.end()
+    }
+}
+
+impl From<AddressRangeDescriptor> for MemoryRegion {
+    fn from(value: AddressRangeDescriptor) -> Self {
+        Self {
+            base: value.base_addr() as u64,
+            length: value.length(),
+            kind: MemoryRegionKind::from(value.addr_type),
+        }
+    }
+}
+
+/// Sorted, non-overlapping list of physical [`MemoryRegion`].
+///
+/// Built from the raw (unsorted, possibly overlapping) memory map returned by the firmware. Does not
+/// require any allocator, so that it can be used before the heap is initialized.
+#[derive(Debug)]
+pub struct MemoryRegions {
+    regions: [MemoryRegion; MAX_MEMORY_REGIONS],
+    len: usize,
+}
+
+impl MemoryRegions {
+    pub const fn new() -> Self {
+        Self {
+            regions: [MemoryRegion::EMPTY; MAX_MEMORY_REGIONS],
+            len: 0,
+        }
+    }
+
+    /// Builds a normalized list of [`MemoryRegion`] from an `E820` memory map.
+    ///
+    /// Entries that do not fit in the list are dropped.
+    pub fn from_e820(map: impl IntoIterator<Item = AddressRangeDescriptor>) -> Self {
+        let mut regions = Self::new();
+
+        for entry in map {
+            if entry.length() == 0 {
+                continue;
+            }
+
+            if regions.push(MemoryRegion::from(entry)).is_err() {
+                break;
+            }
+        }
+
+        regions.normalize();
+        regions
+    }
+
+    /// Marks a range of physical memory as being used, with the given [`MemoryRegionKind`].
+    ///
+    /// The range is carved out of any [`MemoryRegionKind::Usable`] region that overlaps it. Regions of
+    /// a greater kind (for instance, firmware reserved memory) are left untouched.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`E820Error`] if the list is full, and the reservation cannot be recorded.
+    pub fn reserve(&mut self, base: u64, length: u64, kind: MemoryRegionKind) -> CanFail<E820Error> {
+        if length == 0 {
+            return Ok(());
+        }
+
+        // Reserving a range in the middle of a region splits it in three.
+        if self.len + 2 > MAX_MEMORY_REGIONS {
+            return Err(E820Error::new());
+        }
+
+        self.push(MemoryRegion::new(base, length, kind))?;
+        self.normalize();
+
+        Ok(())
+    }
+
+    /// Returns an iterator over every region.
+    pub fn iter(&self) -> impl Iterator<Item = &MemoryRegion> {
+        self.regions[..self.len].iter()
+    }
+
+    /// Returns an iterator over every usable region.
+    pub fn usable(&self) -> impl Iterator<Item = &MemoryRegion> {
+        self.iter().filter(|region| region.is_usable())
+    }
+
+    /// Returns the largest usable region, if any.
+    pub fn largest_usable(&self) -> Option<MemoryRegion> {
+        self.usable().max_by_key(|region| region.length).copied()
+    }
+
+    /// Returns the largest usable region, truncated to the memory located below `limit`, if any.
+    pub fn largest_usable_below(&self, limit: u64) -> Option<MemoryRegion> {
+        self.usable()
+            .filter(|region| region.base < limit)
+            .map(|region| {
+                MemoryRegion::new(
+                    region.base,
+                    region.end().min(limit) - region.base,
+                    region.kind,
+                )
+            })
+            .max_by_key(|region| region.length)
+    }
+
+    /// Returns the total amount of usable memory, in bytes.
+    pub fn total_usable(&self) -> u64 {
+        self.usable().map(|region| region.length).sum()
+    }
+
+    /// Returns whether the whole range of physical memory is usable.
+    pub fn is_usable_range(&self, base: u64, length: u64) -> bool {
+        let end = base.saturating_add(length);
+        let covered: u64 = self
+            .usable()
+            .map(|region| region.end().min(end).saturating_sub(region.base.max(base)))
+            .sum();
+
+        covered == length
+    }
+
+    /// Returns the region containing the physical address `addr`, if any.
+    pub fn region_of(&self, addr: u64) -> Option<&MemoryRegion> {
+        self.iter().find(|region| region.contains(addr))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn push(&mut self, region: MemoryRegion) -> CanFail<E820Error> {
+        if self.len >= MAX_MEMORY_REGIONS {
+            return Err(E820Error::new());
+        }
+
+        self.regions[self.len] = region;
+        self.len += 1;
+
+        Ok(())
+    }
+
+    /// Sorts the regions, resolves overlaps and merges adjacent regions of the same kind.
+    ///
+    /// Every region boundary splits the physical address space in elementary ranges. Each range is
+    /// assigned the greatest kind of the regions covering it, and contiguous ranges of the same kind
+    /// are merged back together.
+    fn normalize(&mut self) {
+        let mut bounds = [0u64; 2 * MAX_MEMORY_REGIONS];
+        let mut bounds_len = 0;
+
+        for region in self.iter() {
+            bounds[bounds_len] = region.base;
+            bounds[bounds_len + 1] = region.end();
+            bounds_len += 2;
+        }
+
+        let bounds = &mut bounds[..bounds_len];
+        bounds.sort_unstable();
+
+        let mut normalized = Self::new();
+
+        for window in bounds.windows(2) {
+            let (start, end) = (window[0], window[1]);
+            if start == end {
+                continue;
+            }
+
+            let Some(kind) = self
+                .iter()
+                .filter(|region| region.contains(start))
+                .map(|region| region.kind)
+                .max()
+            else {
+                continue;
+            };
+
+            if normalized.len > 0 {
+                let last = &mut normalized.regions[normalized.len - 1];
+
+                if last.kind == kind && last.end() == start {
+                    last.length += end - start;
+                    continue;
+                }
+            }
+
+            // Ranges that do not fit anymore are dropped, this only happens with heavily
+            // fragmented firmware memory maps.
+            let _ = normalized.push(MemoryRegion::new(start, end - start, kind));
+        }
+
+        *self = normalized;
+    }
+}
+
+impl Default for MemoryRegions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,118 @@
+//! Files mapped in address spaces.
+//!
+//! A [`MappedFile`] wraps a [`File`] that backs one or more areas (see [`VmaBacking::File`]). Pages are read through
+//! the [`page cache`], the first time an area backed by the file faults on them: a page of a file is only read once no
+//! matter how many times (and in how many address spaces) it is mapped, or whether it was read with
+//! [`FsFile::read`] before. Pages used by a `MappedFile` are pinned in the cache until it is dropped.
+//!
+//! Read-only and shared areas map the cached pages directly, while private writable areas get their own copy of a
+//! page when it is first accessed.
+//!
+//! [`VmaBacking::File`]: super::vma::VmaBacking::File
+//! [`page cache`]: crate::fs::pagecache
+
+use core::ptr;
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+use spin::Mutex;
+
+use crate::{
+    errors::VmaError,
+    fs::{
+        pagecache::{self, PageKey},
+        File, FsFile, IOResult,
+    },
+    kernel_syms::PAGE_SIZE,
+};
+
+use super::{get_physical_memory, PhyAddr};
+
+/// A file that can be mapped in address spaces.
+#[derive(Debug)]
+pub struct MappedFile {
+    file: Mutex<File>,
+    size: usize,
+
+    /// Pages of the file pinned in the page cache, by page index.
+    pages: Mutex<BTreeMap<u64, (PageKey, PhyAddr)>>,
+}
+
+impl MappedFile {
+    /// Prepares `file` to be mapped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the size of the file could not be read.
+    pub fn new(file: File) -> IOResult<Arc<Self>> {
+        let size = file.size()?;
+
+        Ok(Arc::new(Self {
+            file: Mutex::new(file),
+            size,
+            pages: Mutex::new(BTreeMap::new()),
+        }))
+    }
+
+    /// Size of the file, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of pages of the file pinned in the page cache.
+    pub fn pinned_pages(&self) -> usize {
+        self.pages.lock().len()
+    }
+
+    /// Returns the frame holding the page `index` of the file, reading it from the filesystem if it is not cached
+    /// yet.
+    ///
+    /// The part of the page past the end of the file is zero-filled. The frame belongs to the page cache, and remains
+    /// valid as long as this `MappedFile` is alive.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`VmaError::ReadFailed`] if the page could not be read (or if no frame could be allocated for it).
+    pub fn page(&self, index: u64) -> Result<PhyAddr, VmaError> {
+        let mut pages = self.pages.lock();
+
+        if let Some(&(_, frame)) = pages.get(&index) {
+            return Ok(frame);
+        }
+
+        let (key, frame) = self
+            .file
+            .lock()
+            .map_page(index)
+            .map_err(|_| VmaError::ReadFailed)?;
+        pages.insert(index, (key, frame));
+
+        Ok(frame)
+    }
+
+    /// Copies the page `index` of the file to the frame at `dest`.
+    ///
+    /// # Errors
+    ///
+    /// Same as [`page`](Self::page).
+    pub fn copy_page(&self, index: u64, dest: PhyAddr) -> Result<(), VmaError> {
+        let page = self.page(index)?;
+
+        unsafe {
+            ptr::copy_nonoverlapping(
+                get_physical_memory(page),
+                get_physical_memory(dest),
+                PAGE_SIZE,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        for (_, (key, _)) in core::mem::take(self.pages.get_mut()) {
+            pagecache::unpin(key);
+        }
+    }
+}
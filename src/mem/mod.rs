@@ -13,11 +13,23 @@ use conquer_once::spin::OnceCell;
 use crate::x86::paging::page_table::mapper::{MemoryMapping, PhysicalMemoryMapping};
 
 pub mod bmalloc;
+pub mod bump;
+pub mod dma;
 pub mod e820;
+#[cfg(feature = "x86_64")]
+pub mod filemap;
 pub mod kernel_sec;
+pub mod oom;
+pub mod ops;
+#[cfg(all(feature = "x86_64", feature = "mem_shadow"))]
+pub mod shadow;
+pub mod shrinker;
 pub mod stack;
+pub mod stats;
 pub mod utils;
 #[cfg(feature = "x86_64")]
+pub mod vma;
+#[cfg(feature = "x86_64")]
 pub mod vmalloc;
 
 pub static MEM_STRUCTURE: OnceCell<MemoryStructure> = OnceCell::uninit();
@@ -32,8 +44,23 @@ pub static MEM_STRUCTURE: OnceCell<MemoryStructure> = OnceCell::uninit();
 /// ```
 /// let idt_ptr = get_physical_memory(PhyAddr::new(0x0));
 /// ```
+///
+/// With the `mem_shadow` feature, accesses to free frames of the physical memory pool are reported (see
+/// `mem::shadow`).
 #[inline]
 pub fn get_physical_memory(addr: PhyAddr) -> *mut u8 {
+    #[cfg(all(feature = "x86_64", feature = "mem_shadow"))]
+    shadow::shadow_check(addr);
+
+    get_physical_memory_unchecked(addr)
+}
+
+/// Returns a pointer to the physical memory located at address `addr`, like [`get_physical_memory`], but without
+/// checking the access against the shadow map of the physical memory pool.
+///
+/// Used to access free memory on purpose (for instance, to dump the whole memory).
+#[inline]
+pub fn get_physical_memory_unchecked(addr: PhyAddr) -> *mut u8 {
     get_physical_memory_mapping().convert(addr).as_mut_ptr()
 }
 
@@ -0,0 +1,131 @@
+//! Out-of-memory handling.
+//!
+//! Allocators do not panic when running out of memory. Instead, they call the registered [`shrinker`]s, then the OOM
+//! handlers registered with [`register_oom_handler`], which may release memory they do not strictly need, and retry
+//! the allocation once before reporting a failure to the caller.
+//!
+//! [`shrinker`]: crate::mem::shrinker
+//!
+//! Handlers are called with the allocators unlocked, so they are allowed to free memory. They should avoid
+//! allocating memory themselves: nested out-of-memory situations are not handled, and reclaim is simply skipped
+//! while a reclaim pass is already in progress.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+
+use crate::errors::{AllocError, CanFail};
+use crate::info;
+use crate::mem::shrinker::{self, ShrinkControl};
+
+/// Maximum number of OOM handlers that can be registered at once.
+pub const MAX_OOM_HANDLERS: usize = 16;
+
+/// Callback invoked when an allocator runs out of memory.
+///
+/// It is given the size (in bytes) of the allocation that failed, and returns an estimation of the number of bytes
+/// that were released.
+pub type OomHandler = fn(usize) -> usize;
+
+#[derive(Clone, Copy)]
+struct OomHandlerEntry {
+    name: &'static str,
+    handler: OomHandler,
+}
+
+static OOM_HANDLERS: Mutex<[Option<OomHandlerEntry>; MAX_OOM_HANDLERS]> =
+    Mutex::new([None; MAX_OOM_HANDLERS]);
+
+static RECLAIM_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// Registers a new OOM handler.
+///
+/// Handlers are called in registration order, until enough memory was released. Registering a handler under a name
+/// that is already in use replaces the previous handler.
+///
+/// # Errors
+///
+/// Returns [`AllocError::OutOfMemory`] if the handler table is full.
+pub fn register_oom_handler(name: &'static str, handler: OomHandler) -> CanFail<AllocError> {
+    let mut handlers = OOM_HANDLERS.lock();
+
+    let existing = handlers
+        .iter()
+        .position(|entry| entry.is_some_and(|entry| entry.name == name));
+
+    let slot = match existing {
+        Some(index) => &mut handlers[index],
+        None => handlers
+            .iter_mut()
+            .find(|entry| entry.is_none())
+            .ok_or(AllocError::OutOfMemory)?,
+    };
+
+    *slot = Some(OomHandlerEntry { name, handler });
+
+    Ok(())
+}
+
+/// Unregisters an OOM handler, previously registered using [`register_oom_handler`].
+pub fn unregister_oom_handler(name: &'static str) {
+    let mut handlers = OOM_HANDLERS.lock();
+
+    for entry in handlers.iter_mut() {
+        if entry.is_some_and(|handler| handler.name == name) {
+            *entry = None;
+        }
+    }
+}
+
+/// Attempts to release at least `size` bytes of memory, by calling the registered shrinkers and OOM handlers.
+///
+/// Returns the number of bytes that were released. This returns 0 immediately if called while a reclaim pass is
+/// already in progress (from an OOM handler that failed to allocate memory for instance).
+pub fn reclaim(size: usize) -> usize {
+    if RECLAIM_IN_PROGRESS.swap(true, Ordering::Acquire) {
+        return 0;
+    }
+
+    // Copy the table so that handlers can be (un)registered from a handler without deadlocking.
+    let handlers = *OOM_HANDLERS.lock();
+    let mut reclaimed = shrinker::shrink(ShrinkControl::low_memory(size));
+
+    for entry in handlers.iter().flatten() {
+        if reclaimed >= size {
+            break;
+        }
+
+        let released = (entry.handler)(size - reclaimed);
+
+        if released != 0 {
+            info!("oom", "{} released {:#x} bytes", entry.name, released);
+        }
+
+        reclaimed += released;
+    }
+
+    RECLAIM_IN_PROGRESS.store(false, Ordering::Release);
+
+    reclaimed
+}
+
+/// Runs `alloc`, and retries once after reclaiming memory if the first attempt failed.
+///
+/// `alloc` must not hold any allocator lock when returning, otherwise OOM handlers may deadlock when releasing
+/// memory.
+///
+/// # Errors
+///
+/// Returns the error of the second attempt if the allocation still failed after reclaiming memory.
+pub fn with_reclaim<T, E>(size: usize, mut alloc: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+    match alloc() {
+        Ok(allocation) => Ok(allocation),
+        Err(err) => {
+            if reclaim(size) == 0 {
+                return Err(err);
+            }
+
+            alloc()
+        }
+    }
+}
@@ -0,0 +1,439 @@
+//! Memory routines (`memcpy`, `memmove`, `memset`, `memcmp`) used by the compiler.
+//!
+//! Copies and fills use the x86 string instructions. When the processor reports _Enhanced REP MOVSB/STOSB_
+//! (`ERMS`, `CPUID.(EAX=7,ECX=0):EBX[9]`), `rep movsb` / `rep stosb` are used for the whole buffer, as the
+//! microcode then copies whole cache lines. Otherwise, the buffer is processed one word at a time
+//! (`rep movsq` / `rep movsd`), and the remaining bytes one at a time.
+//!
+//! In the kernel, large buffers are processed 64 bytes at a time using the `SSE2` registers on processors without
+//! `ERMS`, once [`mem_ops_enable_sse`] was called. The content of the `XMM` registers is not saved on interrupts nor
+//! on task switches: the registers used are saved on the stack, and restored before returning.
+//!
+//! Backward copies (`memmove` with overlapping buffers) set the direction flag. Interrupts are disabled while it is
+//! set, and the interrupt entry points clear it anyway (see [`interrupt_handler`](fzproc_macros::interrupt_handler)).
+//!
+//! The real-mode stage keeps plain byte loops.
+
+use core::mem::size_of;
+
+#[cfg(not(feature = "real"))]
+use core::arch::asm;
+#[cfg(feature = "x86_64")]
+use core::sync::atomic::AtomicBool;
+#[cfg(not(feature = "real"))]
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[cfg(not(feature = "real"))]
+use crate::x86::cpuid::cpu_id_subleaf;
+#[cfg(feature = "x86_64")]
+use crate::x86::registers::control::{ControlRegister, Cr0, Cr4};
+
+/// `CPUID.(EAX=7,ECX=0):EBX` bit reporting `ERMS`.
+#[cfg(not(feature = "real"))]
+const CPUID_ERMS: u32 = 1 << 9;
+
+#[cfg(not(feature = "real"))]
+const ERMS_SUPPORTED: u8 = 1;
+
+#[cfg(not(feature = "real"))]
+const ERMS_UNSUPPORTED: u8 = 2;
+
+/// Size from which the `SSE2` paths are used, in bytes.
+#[cfg(feature = "x86_64")]
+const SSE_THRESHOLD: usize = 256;
+
+/// Number of bytes processed by each iteration of the `SSE2` paths.
+#[cfg(feature = "x86_64")]
+const SSE_BLOCK: usize = 64;
+
+/// `ERMS` support, or 0 if it was not checked yet.
+#[cfg(not(feature = "real"))]
+static ERMS: AtomicU8 = AtomicU8::new(0);
+
+/// Set once the processor was set up to execute `SSE` instructions (see [`mem_ops_enable_sse`]).
+#[cfg(feature = "x86_64")]
+static SSE_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Checks whether the processor supports _Enhanced REP MOVSB/STOSB_.
+#[cfg(not(feature = "real"))]
+pub fn erms_supported() -> bool {
+    match ERMS.load(Ordering::Relaxed) {
+        ERMS_SUPPORTED => true,
+        ERMS_UNSUPPORTED => false,
+        _ => {
+            let supported = cpu_id_subleaf(7, 0).is_some_and(|leaf| leaf[1] & CPUID_ERMS != 0);
+            ERMS.store(
+                if supported {
+                    ERMS_SUPPORTED
+                } else {
+                    ERMS_UNSUPPORTED
+                },
+                Ordering::Relaxed,
+            );
+
+            supported
+        }
+    }
+}
+
+/// Sets up the current processor to execute `SSE` instructions, and enables the `SSE2` paths of the memory routines.
+///
+/// `SSE2` is part of the `x86_64` baseline: no feature check is required. Must be called on every processor before
+/// it copies memory, once the first one called it.
+#[cfg(feature = "x86_64")]
+pub fn mem_ops_enable_sse() {
+    Cr0::write(
+        Cr0::read()
+            .with_emulation(false)
+            .with_monitor_coprocessor(true),
+    );
+    Cr4::write(Cr4::read().with_osfxsr(true).with_osxmmexcpt(true));
+
+    SSE_ENABLED.store(true, Ordering::Release);
+}
+
+#[cfg(feature = "x86_64")]
+fn sse_enabled() -> bool {
+    SSE_ENABLED.load(Ordering::Acquire)
+}
+
+/// Copies `n` bytes from `src` to `dest`. The buffers must not overlap.
+///
+/// # Safety
+///
+/// `src` must be valid for `n` bytes of reads, and `dest` for `n` bytes of writes.
+#[no_mangle]
+pub unsafe extern "C" fn memcpy(dest: *mut u8, src: *const u8, n: usize) -> *mut u8 {
+    copy_forward(dest, src, n);
+
+    dest
+}
+
+/// Copies `n` bytes from `src` to `dest`. The buffers may overlap.
+///
+/// # Safety
+///
+/// `src` must be valid for `n` bytes of reads, and `dest` for `n` bytes of writes.
+#[no_mangle]
+pub unsafe extern "C" fn memmove(dest: *mut u8, src: *const u8, n: usize) -> *mut u8 {
+    if (dest as usize).wrapping_sub(src as usize) >= n {
+        // `dest` is before `src`, or the buffers do not overlap
+        copy_forward(dest, src, n);
+    } else {
+        copy_backward(dest, src, n);
+    }
+
+    dest
+}
+
+/// Fills `n` bytes of `s` with the byte `c`.
+///
+/// # Safety
+///
+/// `s` must be valid for `n` bytes of writes.
+#[no_mangle]
+pub unsafe extern "C" fn memset(s: *mut u8, c: i32, n: usize) -> *mut u8 {
+    fill(s, c as u8, n);
+
+    s
+}
+
+/// Compares `n` bytes of `s1` and `s2`, returning the difference between the first differing bytes (0 if the
+/// buffers are equal).
+///
+/// # Safety
+///
+/// `s1` and `s2` must be valid for `n` bytes of reads.
+#[no_mangle]
+pub unsafe extern "C" fn memcmp(s1: *const u8, s2: *const u8, n: usize) -> i32 {
+    let mut i = 0;
+
+    // skip the identical words
+    while i + size_of::<usize>() <= n
+        && s1.add(i).cast::<usize>().read_unaligned() == s2.add(i).cast::<usize>().read_unaligned()
+    {
+        i += size_of::<usize>();
+    }
+
+    while i < n {
+        let (a, b) = (*s1.add(i), *s2.add(i));
+        if a != b {
+            return i32::from(a) - i32::from(b);
+        }
+
+        i += 1;
+    }
+
+    0
+}
+
+/// Compares `n` bytes of `s1` and `s2`, returning 0 if they are equal.
+///
+/// # Safety
+///
+/// `s1` and `s2` must be valid for `n` bytes of reads.
+#[no_mangle]
+pub unsafe extern "C" fn bcmp(s1: *const u8, s2: *const u8, n: usize) -> i32 {
+    memcmp(s1, s2, n)
+}
+
+#[cfg(not(feature = "real"))]
+unsafe fn copy_forward(dest: *mut u8, src: *const u8, n: usize) {
+    if erms_supported() {
+        rep_movsb(dest, src, n);
+
+        return;
+    }
+
+    #[cfg(feature = "x86_64")]
+    if n >= SSE_THRESHOLD && sse_enabled() {
+        let blocks = n / SSE_BLOCK;
+        copy_forward_sse(dest, src, blocks);
+
+        let done = blocks * SSE_BLOCK;
+        rep_movsb(dest.add(done), src.add(done), n - done);
+
+        return;
+    }
+
+    let words = n / size_of::<usize>();
+    let bytes = n % size_of::<usize>();
+
+    #[cfg(feature = "x86_64")]
+    asm!(
+        "rep movsq",
+        "mov ecx, {bytes:e}",
+        "rep movsb",
+        bytes = in(reg) bytes,
+        inout("ecx") words => _,
+        inout("esi") src => _,
+        inout("edi") dest => _,
+        options(nostack, preserves_flags)
+    );
+
+    // `esi` cannot be used as an operand on 32-bit targets (LLVM uses it internally): the source pointer is swapped
+    // into it, and `esi` is restored afterwards.
+    #[cfg(not(feature = "x86_64"))]
+    asm!(
+        "xchg {src}, esi",
+        "rep movsd",
+        "mov ecx, {bytes}",
+        "rep movsb",
+        "mov esi, {src}",
+        src = inout(reg) src => _,
+        bytes = in(reg) bytes,
+        inout("ecx") words => _,
+        inout("edi") dest => _,
+        options(nostack, preserves_flags)
+    );
+}
+
+/// Copies `n` bytes from `src` to `dest` using `rep movsb`.
+#[cfg(feature = "x86_64")]
+unsafe fn rep_movsb(dest: *mut u8, src: *const u8, n: usize) {
+    asm!(
+        "rep movsb",
+        inout("ecx") n => _,
+        inout("esi") src => _,
+        inout("edi") dest => _,
+        options(nostack, preserves_flags)
+    );
+}
+
+/// Copies `n` bytes from `src` to `dest` using `rep movsb`.
+///
+/// `esi` cannot be used as an operand on 32-bit targets: the source pointer is swapped into it instead.
+#[cfg(not(any(feature = "x86_64", feature = "real")))]
+unsafe fn rep_movsb(dest: *mut u8, src: *const u8, n: usize) {
+    asm!(
+        "xchg {src}, esi",
+        "rep movsb",
+        "mov esi, {src}",
+        src = inout(reg) src => _,
+        inout("ecx") n => _,
+        inout("edi") dest => _,
+        options(nostack, preserves_flags)
+    );
+}
+
+#[cfg(not(feature = "real"))]
+unsafe fn copy_backward(dest: *mut u8, src: *const u8, n: usize) {
+    if n == 0 {
+        return;
+    }
+
+    // Interrupts are disabled while the direction flag is set, `popf` restores both flags.
+    #[cfg(feature = "x86_64")]
+    asm!(
+        "pushf",
+        "cli",
+        "std",
+        "rep movsb",
+        "cld",
+        "popf",
+        inout("ecx") n => _,
+        inout("esi") src.add(n - 1) => _,
+        inout("edi") dest.add(n - 1) => _,
+    );
+
+    // As in `copy_forward`, `esi` is swapped with the source pointer on 32-bit targets.
+    #[cfg(not(feature = "x86_64"))]
+    asm!(
+        "xchg {src}, esi",
+        "pushf",
+        "cli",
+        "std",
+        "rep movsb",
+        "cld",
+        "popf",
+        "mov esi, {src}",
+        src = inout(reg) src.add(n - 1) => _,
+        inout("ecx") n => _,
+        inout("edi") dest.add(n - 1) => _,
+    );
+}
+
+#[cfg(not(feature = "real"))]
+unsafe fn fill(s: *mut u8, c: u8, n: usize) {
+    if erms_supported() {
+        asm!(
+            "rep stosb",
+            inout("ecx") n => _,
+            inout("edi") s => _,
+            in("al") c,
+            options(nostack, preserves_flags)
+        );
+
+        return;
+    }
+
+    let pattern = usize::from_ne_bytes([c; size_of::<usize>()]);
+
+    #[cfg(feature = "x86_64")]
+    if n >= SSE_THRESHOLD && sse_enabled() {
+        let blocks = n / SSE_BLOCK;
+        fill_sse(s, pattern, blocks);
+
+        let done = blocks * SSE_BLOCK;
+        asm!(
+            "rep stosb",
+            inout("ecx") n - done => _,
+            inout("edi") s.add(done) => _,
+            in("al") c,
+            options(nostack, preserves_flags)
+        );
+
+        return;
+    }
+
+    let words = n / size_of::<usize>();
+    let bytes = n % size_of::<usize>();
+
+    #[cfg(feature = "x86_64")]
+    asm!(
+        "rep stosq",
+        "mov ecx, {bytes:e}",
+        "rep stosb",
+        bytes = in(reg) bytes,
+        inout("ecx") words => _,
+        inout("edi") s => _,
+        in("eax") pattern,
+        options(nostack, preserves_flags)
+    );
+
+    #[cfg(not(feature = "x86_64"))]
+    asm!(
+        "rep stosd",
+        "mov ecx, {bytes:e}",
+        "rep stosb",
+        bytes = in(reg) bytes,
+        inout("ecx") words => _,
+        inout("edi") s => _,
+        in("eax") pattern,
+        options(nostack, preserves_flags)
+    );
+}
+
+/// Copies `blocks` blocks of [`SSE_BLOCK`] bytes from `src` to `dest`, using `XMM0` to `XMM3`.
+///
+/// The registers are saved on the stack beforehand, and restored afterwards.
+#[cfg(feature = "x86_64")]
+unsafe fn copy_forward_sse(dest: *mut u8, src: *const u8, blocks: usize) {
+    asm!(
+        "sub rsp, 64",
+        "movdqu [rsp], xmm0",
+        "movdqu [rsp + 16], xmm1",
+        "movdqu [rsp + 32], xmm2",
+        "movdqu [rsp + 48], xmm3",
+        "2:",
+        "movdqu xmm0, [rsi]",
+        "movdqu xmm1, [rsi + 16]",
+        "movdqu xmm2, [rsi + 32]",
+        "movdqu xmm3, [rsi + 48]",
+        "movdqu [rdi], xmm0",
+        "movdqu [rdi + 16], xmm1",
+        "movdqu [rdi + 32], xmm2",
+        "movdqu [rdi + 48], xmm3",
+        "add rsi, 64",
+        "add rdi, 64",
+        "dec rcx",
+        "jnz 2b",
+        "movdqu xmm0, [rsp]",
+        "movdqu xmm1, [rsp + 16]",
+        "movdqu xmm2, [rsp + 32]",
+        "movdqu xmm3, [rsp + 48]",
+        "add rsp, 64",
+        inout("rcx") blocks => _,
+        inout("rsi") src => _,
+        inout("rdi") dest => _,
+    );
+}
+
+/// Fills `blocks` blocks of [`SSE_BLOCK`] bytes of `s` with `pattern`, using `XMM0`.
+///
+/// The register is saved on the stack beforehand, and restored afterwards.
+#[cfg(feature = "x86_64")]
+unsafe fn fill_sse(s: *mut u8, pattern: usize, blocks: usize) {
+    asm!(
+        "sub rsp, 16",
+        "movdqu [rsp], xmm0",
+        "movq xmm0, {pattern}",
+        "punpcklqdq xmm0, xmm0",
+        "2:",
+        "movdqu [rdi], xmm0",
+        "movdqu [rdi + 16], xmm0",
+        "movdqu [rdi + 32], xmm0",
+        "movdqu [rdi + 48], xmm0",
+        "add rdi, 64",
+        "dec rcx",
+        "jnz 2b",
+        "movdqu xmm0, [rsp]",
+        "add rsp, 16",
+        pattern = in(reg) pattern,
+        inout("rcx") blocks => _,
+        inout("rdi") s => _,
+    );
+}
+
+// Volatile writes prevent the compiler from turning the loops back into calls to these routines.
+
+#[cfg(feature = "real")]
+unsafe fn copy_forward(dest: *mut u8, src: *const u8, n: usize) {
+    for i in 0..n {
+        dest.add(i).write_volatile(*src.add(i));
+    }
+}
+
+#[cfg(feature = "real")]
+unsafe fn copy_backward(dest: *mut u8, src: *const u8, n: usize) {
+    for i in (0..n).rev() {
+        dest.add(i).write_volatile(*src.add(i));
+    }
+}
+
+#[cfg(feature = "real")]
+unsafe fn fill(s: *mut u8, c: u8, n: usize) {
+    for i in 0..n {
+        s.add(i).write_volatile(c);
+    }
+}
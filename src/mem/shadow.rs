@@ -0,0 +1,212 @@
+//! Shadow map of the physical memory pool, enabled with the `mem_shadow` feature.
+//!
+//! One bit is kept for every frame of the physical memory pool (see
+//! [`frame_alloc`](crate::x86::paging::page_alloc::frame_alloc)), set while the frame is free (_poisoned_). Frames are
+//! marked by the frame allocator when they are allocated and freed, and
+//! [`get_physical_memory`](super::get_physical_memory) checks that the frame being accessed is not poisoned.
+//!
+//! Only the frames of the pool located in usable memory are tracked: the pool spans a fixed window of physical memory,
+//! which may cover memory holes, MMIO or firmware reserved ranges.
+//!
+//! The kernel heap keeps the frames backing it once mapped: the pages of a heap block are poisoned when the block is
+//! freed, and unpoisoned when they are allocated again (see [`vmalloc`](crate::mem::vmalloc)).
+//!
+//! Invalid accesses (use after free) and double frees are reported with a backtrace of the caller, instead of
+//! silently corrupting the memory of the allocator or of the next owner of the frame. Accesses outside of the tracked
+//! frames (firmware tables, MMIO, ...) are not checked.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::error;
+use crate::mem::e820::MemoryRegions;
+use crate::mem::{PhyAddr, VirtAddr};
+use crate::x86::paging::page_alloc::frame_alloc::MAX_PHYSICAL_MEM_BLK_SIZE;
+use crate::x86::paging::{get_memory_mapper, Page};
+
+/// Granularity of the shadow map, in bytes.
+const SHADOW_FRAME_SIZE: u64 = 0x1000;
+
+const SHADOW_WORDS: usize = MAX_PHYSICAL_MEM_BLK_SIZE / SHADOW_FRAME_SIZE as usize / 64;
+
+/// Maximum number of reports, so that a single bug does not flood the log.
+const MAX_SHADOW_REPORTS: usize = 16;
+
+/// Maximum number of frames displayed in a backtrace.
+const BACKTRACE_DEPTH: usize = 8;
+
+const SHADOW_WORD_INIT: AtomicU64 = AtomicU64::new(0);
+
+/// Bit set for every free frame of the pool.
+static SHADOW: [AtomicU64; SHADOW_WORDS] = [SHADOW_WORD_INIT; SHADOW_WORDS];
+
+/// Base address of the pool, `u64::MAX` until [`shadow_init`] is called.
+static SHADOW_BASE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Address following the last frame of the pool located in usable memory.
+static SHADOW_END: AtomicU64 = AtomicU64::new(0);
+
+static SHADOW_REPORTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Starts tracking the physical memory pool of `length` bytes starting at `base`, whose frames are initially free.
+///
+/// Only the frames located in the usable `regions` of physical memory are tracked.
+pub(crate) fn shadow_init(base: PhyAddr, length: usize, regions: &MemoryRegions) {
+    let base = u64::from(base);
+    let end = base + length.min(MAX_PHYSICAL_MEM_BLK_SIZE) as u64;
+
+    for word in &SHADOW {
+        word.store(0, Ordering::Relaxed);
+    }
+
+    for region in regions.usable() {
+        let start = region.base.max(base).next_multiple_of(SHADOW_FRAME_SIZE);
+        let stop = region.end().min(end) & !(SHADOW_FRAME_SIZE - 1);
+
+        for frame in (start..stop).step_by(SHADOW_FRAME_SIZE as usize) {
+            let index = ((frame - base) / SHADOW_FRAME_SIZE) as usize;
+            SHADOW[index / 64].fetch_or(1 << (index % 64), Ordering::Relaxed);
+        }
+    }
+
+    SHADOW_END.store(end, Ordering::Relaxed);
+    SHADOW_BASE.store(base, Ordering::Release);
+}
+
+/// Marks the frames containing `[addr; addr + length)` as allocated.
+pub(crate) fn shadow_mark_allocated(addr: PhyAddr, length: usize) {
+    for frame in frames(addr, length) {
+        let (word, bit) = position(frame);
+        SHADOW[word].fetch_and(!bit, Ordering::AcqRel);
+    }
+}
+
+/// Marks the frames containing `[addr; addr + length)` as free, reporting the frames that already were.
+pub(crate) fn shadow_mark_free(addr: PhyAddr, length: usize) {
+    for frame in frames(addr, length) {
+        let (word, bit) = position(frame);
+
+        if SHADOW[word].fetch_or(bit, Ordering::AcqRel) & bit != 0 {
+            report("double free of frame", frame);
+        }
+    }
+}
+
+/// Poisons the pages of the kernel heap entirely covered by the block `[addr; addr + length)`, which is being freed.
+///
+/// Reports the block if its pages already were poisoned (double free).
+pub(crate) fn shadow_poison_heap(addr: VirtAddr, length: usize) {
+    let start = u64::from(addr);
+    let first = start.next_multiple_of(SHADOW_FRAME_SIZE);
+    let last = (start + length as u64) & !(SHADOW_FRAME_SIZE - 1);
+
+    for_each_heap_frame(first, last, |word, bit| {
+        if SHADOW[word].fetch_or(bit, Ordering::AcqRel) & bit != 0 {
+            report("double free of heap block", start);
+            return false;
+        }
+
+        true
+    });
+}
+
+/// Unpoisons the pages of the kernel heap overlapping the block `[addr; addr + length)`, which was just allocated.
+pub(crate) fn shadow_unpoison_heap(addr: VirtAddr, length: usize) {
+    let start = u64::from(addr);
+    let first = start & !(SHADOW_FRAME_SIZE - 1);
+
+    for_each_heap_frame(first, start + length as u64, |word, bit| {
+        SHADOW[word].fetch_and(!bit, Ordering::AcqRel);
+        true
+    });
+}
+
+/// Reports an access to `addr` if it is located in a free frame of the pool.
+#[inline]
+pub(crate) fn shadow_check(addr: PhyAddr) {
+    let frame = u64::from(addr);
+
+    if let Some((word, bit)) = checked_position(frame) {
+        if SHADOW[word].load(Ordering::Acquire) & bit != 0 {
+            report("access to free frame", frame);
+        }
+    }
+}
+
+/// Returns the physical addresses of the frames of the pool overlapping `[addr; addr + length)`.
+fn frames(addr: PhyAddr, length: usize) -> impl Iterator<Item = u64> {
+    let start = u64::from(addr) & !(SHADOW_FRAME_SIZE - 1);
+    let end = u64::from(addr) + length as u64;
+
+    (start..end)
+        .step_by(SHADOW_FRAME_SIZE as usize)
+        .filter(|&frame| checked_position(frame).is_some())
+}
+
+/// Calls `f` with the position in the shadow map of the frame backing every page of the kernel heap in
+/// `[first; last)`, until it returns `false`.
+///
+/// Pages that are not mapped, or not backed by a frame of the pool, are skipped. Nothing is done if the page tables
+/// are being modified (the heap may be called from the memory mapper).
+fn for_each_heap_frame(first: u64, last: u64, mut f: impl FnMut(usize, u64) -> bool) {
+    let Some(mut mapper) = get_memory_mapper().try_lock() else {
+        return;
+    };
+
+    for page in (first..last).step_by(SHADOW_FRAME_SIZE as usize) {
+        let position = mapper
+            .translate_4kb_page(Page::new(VirtAddr::new(page)))
+            .and_then(|frame| checked_position(u64::from(frame.addr())));
+
+        if let Some((word, bit)) = position {
+            if !f(word, bit) {
+                break;
+            }
+        }
+    }
+}
+
+fn position(frame: u64) -> (usize, u64) {
+    checked_position(frame).expect("frame outside of the shadow map")
+}
+
+/// Returns the word and bit of the shadow map tracking the frame containing `addr`, if it belongs to the pool.
+fn checked_position(addr: u64) -> Option<(usize, u64)> {
+    let offset = addr.checked_sub(SHADOW_BASE.load(Ordering::Acquire))?;
+    if addr >= SHADOW_END.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let index = usize::try_from(offset / SHADOW_FRAME_SIZE).ok()?;
+
+    (index < SHADOW_WORDS * 64).then_some((index / 64, 1 << (index % 64)))
+}
+
+#[inline(never)]
+fn report(reason: &str, addr: u64) {
+    if SHADOW_REPORTS.fetch_add(1, Ordering::AcqRel) >= MAX_SHADOW_REPORTS {
+        return;
+    }
+
+    error!("shadow", "{} {:#x}", reason, addr);
+
+    let mut frame_base_ptr: *const usize;
+    unsafe {
+        asm!("mov {}, rbp", out(reg) frame_base_ptr);
+    }
+
+    // Only frames located on the kernel stacks, in the higher half, are followed.
+    for depth in 0..BACKTRACE_DEPTH {
+        if frame_base_ptr.is_null() || (frame_base_ptr as u64) & (1 << 63) == 0 {
+            break;
+        }
+
+        let return_addr = unsafe { *frame_base_ptr.add(1) };
+        if return_addr == 0 {
+            break;
+        }
+
+        error!("shadow", "  [{}] {:#018x}", depth, return_addr);
+        frame_base_ptr = unsafe { *frame_base_ptr as *const usize };
+    }
+}
@@ -0,0 +1,162 @@
+//! Cache shrinkers.
+//!
+//! Subsystems holding memory they can release on demand (mostly caches) register a [`Shrinker`], which is called
+//! either when the system is idle, to trim caches that grew too large, or when running low on memory (see
+//! [`mem::oom`]), to release as much memory as required to satisfy an allocation.
+//!
+//! [`mem::oom`]: crate::mem::oom
+
+use core::time::Duration;
+
+use spin::Mutex;
+
+use crate::errors::{AllocError, CanFail};
+#[cfg(feature = "alloc")]
+use crate::{errors::ClockError, irq::deferred::defer, time::timer};
+
+/// Maximum number of shrinkers that can be registered at once.
+pub const MAX_SHRINKERS: usize = 16;
+
+/// Interval between two calls to [`shrink_idle`], once [`shrinker_init`] was called.
+pub const SHRINK_IDLE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Reason why shrinkers are being called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShrinkReason {
+    /// The system is idle, caches should be trimmed back to their nominal size.
+    Idle,
+
+    /// An allocation failed, as much memory as requested should be released.
+    LowMemory,
+}
+
+/// Describes a shrink request.
+#[derive(Debug, Clone, Copy)]
+pub struct ShrinkControl {
+    /// Why the shrinkers are being called.
+    pub reason: ShrinkReason,
+
+    /// Number of bytes that should be released (only meaningful for [`ShrinkReason::LowMemory`]).
+    pub target: usize,
+}
+
+impl ShrinkControl {
+    /// Request to trim caches while the system is idle.
+    pub const fn idle() -> Self {
+        Self {
+            reason: ShrinkReason::Idle,
+            target: 0,
+        }
+    }
+
+    /// Request to release at least `target` bytes of memory.
+    pub const fn low_memory(target: usize) -> Self {
+        Self {
+            reason: ShrinkReason::LowMemory,
+            target,
+        }
+    }
+}
+
+/// Callback releasing some memory, as described by a [`ShrinkControl`].
+///
+/// Returns an estimation of the number of bytes released. Shrinkers may be called from an allocation failure path,
+/// and therefore must not block on a lock that may be held by the caller (they should skip the work instead).
+pub type Shrinker = fn(&ShrinkControl) -> usize;
+
+#[derive(Clone, Copy)]
+struct ShrinkerEntry {
+    name: &'static str,
+    shrinker: Shrinker,
+}
+
+static SHRINKERS: Mutex<[Option<ShrinkerEntry>; MAX_SHRINKERS]> = Mutex::new([None; MAX_SHRINKERS]);
+
+/// Registers a new shrinker.
+///
+/// Registering a shrinker under a name that is already in use replaces the previous shrinker.
+///
+/// # Errors
+///
+/// Returns [`AllocError::OutOfMemory`] if the shrinker table is full.
+pub fn register_shrinker(name: &'static str, shrinker: Shrinker) -> CanFail<AllocError> {
+    let mut shrinkers = SHRINKERS.lock();
+
+    let existing = shrinkers
+        .iter()
+        .position(|entry| entry.is_some_and(|entry| entry.name == name));
+
+    let slot = match existing {
+        Some(index) => &mut shrinkers[index],
+        None => shrinkers
+            .iter_mut()
+            .find(|entry| entry.is_none())
+            .ok_or(AllocError::OutOfMemory)?,
+    };
+
+    *slot = Some(ShrinkerEntry { name, shrinker });
+
+    Ok(())
+}
+
+/// Unregisters a shrinker, previously registered using [`register_shrinker`].
+pub fn unregister_shrinker(name: &'static str) {
+    let mut shrinkers = SHRINKERS.lock();
+
+    for entry in shrinkers.iter_mut() {
+        if entry.is_some_and(|shrinker| shrinker.name == name) {
+            *entry = None;
+        }
+    }
+}
+
+/// Calls the registered shrinkers.
+///
+/// For [`ShrinkReason::LowMemory`] requests, stops as soon as the target was reached. Returns the number of bytes
+/// released.
+pub fn shrink(control: ShrinkControl) -> usize {
+    let Some(shrinkers) = SHRINKERS.try_lock().map(|shrinkers| *shrinkers) else {
+        return 0;
+    };
+
+    let mut released = 0;
+
+    for entry in shrinkers.iter().flatten() {
+        if control.reason == ShrinkReason::LowMemory && released >= control.target {
+            break;
+        }
+
+        let request = ShrinkControl {
+            target: control.target.saturating_sub(released),
+            ..control
+        };
+
+        released += (entry.shrinker)(&request);
+    }
+
+    released
+}
+
+/// Trims caches back to their nominal size. Meant to be called when the system is idle.
+pub fn shrink_idle() -> usize {
+    shrink(ShrinkControl::idle())
+}
+
+/// Calls [`shrink_idle`] every [`SHRINK_IDLE_INTERVAL`].
+///
+/// Shrinkers are not run from the timer interrupt, but deferred (see [`defer`]).
+///
+/// # Errors
+///
+/// Returns an error if the timer queue is not available.
+#[cfg(feature = "alloc")]
+pub fn shrinker_init() -> CanFail<ClockError> {
+    timer::periodic(SHRINK_IDLE_INTERVAL, || defer(shrink_idle_work))?;
+
+    Ok(())
+}
+
+#[cfg(feature = "alloc")]
+fn shrink_idle_work() {
+    shrink_idle();
+}
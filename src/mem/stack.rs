@@ -11,7 +11,11 @@ use crate::{
     x86::paging::{get_memory_mapper, page_alloc::frame_alloc::alloc_page, PageTableFlags},
 };
 
-use super::{kernel_sec::nx_prot_enabled, Alignment, VirtAddr};
+use super::{
+    kernel_sec::nx_prot_enabled,
+    stats::{self, MemoryConsumer},
+    Alignment, VirtAddr,
+};
 
 #[repr(C)]
 pub struct KernelStack {
@@ -59,6 +63,7 @@ impl VirtualKernelStackAllocator {
             let stack = self.running_ptr;
 
             let stack_page = alloc_page(KERNEL_STACK_SIZE).unwrap();
+            stats::charge(MemoryConsumer::Stacks, stack_page.length);
 
             let mut stack_page_flags = PageTableFlags::new().with_write(true);
 
@@ -0,0 +1,251 @@
+//! Memory usage accounting.
+//!
+//! Keeps track of the physical memory handed out by the frame allocator, of the heap usage, and of the
+//! memory charged to each kernel subsystem. Counters are lock-free, so that they can be updated from the
+//! allocators themselves, and read at any time (even while handling an out-of-memory situation).
+
+use core::fmt::{Display, Formatter};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{info, kernel_syms::PAGE_SIZE};
+
+/// Kernel subsystems to which physical memory can be charged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryConsumer {
+    /// Kernel heap (`vmalloc`) backing pages.
+    KernelHeap,
+
+    /// Kernel stacks.
+    Stacks,
+
+    /// Page table structures.
+    PageTables,
+
+    /// Memory used by device drivers (DMA buffers, command tables, ...).
+    Drivers,
+
+    /// Disk block and inode caches.
+    Caches,
+
+    /// Pages mapped in process address spaces.
+    UserPages,
+
+    /// Any other consumer.
+    Other,
+}
+
+impl MemoryConsumer {
+    const COUNT: usize = 7;
+
+    const ALL: [Self; Self::COUNT] = [
+        Self::KernelHeap,
+        Self::Stacks,
+        Self::PageTables,
+        Self::Drivers,
+        Self::Caches,
+        Self::UserPages,
+        Self::Other,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::KernelHeap => "heap",
+            Self::Stacks => "stacks",
+            Self::PageTables => "page tables",
+            Self::Drivers => "drivers",
+            Self::Caches => "caches",
+            Self::UserPages => "user pages",
+            Self::Other => "other",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+struct MemoryStats {
+    total_frames: AtomicUsize,
+    used_frames: AtomicUsize,
+    heap_size: AtomicUsize,
+    heap_used: AtomicUsize,
+    heap_peak: AtomicUsize,
+    heap_allocs: AtomicUsize,
+    heap_failures: AtomicUsize,
+    charged: [AtomicUsize; MemoryConsumer::COUNT],
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+
+static MEMORY_STATS: MemoryStats = MemoryStats {
+    total_frames: ZERO,
+    used_frames: ZERO,
+    heap_size: ZERO,
+    heap_used: ZERO,
+    heap_peak: ZERO,
+    heap_allocs: ZERO,
+    heap_failures: ZERO,
+    charged: [ZERO; MemoryConsumer::COUNT],
+};
+
+fn frames_count(size: usize) -> usize {
+    size.div_ceil(PAGE_SIZE)
+}
+
+/// Sets the total amount of physical memory managed by the frame allocator, in bytes.
+pub fn set_physical_memory_size(size: usize) {
+    MEMORY_STATS
+        .total_frames
+        .store(frames_count(size), Ordering::Relaxed);
+}
+
+/// Records the allocation of `size` bytes of physical memory.
+pub fn record_frame_alloc(size: usize) {
+    MEMORY_STATS
+        .used_frames
+        .fetch_add(frames_count(size), Ordering::Relaxed);
+}
+
+/// Records the release of `size` bytes of physical memory.
+pub fn record_frame_free(size: usize) {
+    let frames = frames_count(size);
+    let _ = MEMORY_STATS
+        .used_frames
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            Some(used.saturating_sub(frames))
+        });
+}
+
+/// Sets the size of the heap, in bytes.
+pub fn set_heap_size(size: usize) {
+    MEMORY_STATS.heap_size.store(size, Ordering::Relaxed);
+}
+
+/// Records a successful heap allocation of `size` bytes.
+pub fn record_heap_alloc(size: usize) {
+    let used = MEMORY_STATS.heap_used.fetch_add(size, Ordering::Relaxed) + size;
+    MEMORY_STATS.heap_peak.fetch_max(used, Ordering::Relaxed);
+    MEMORY_STATS.heap_allocs.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records a failed heap allocation.
+pub fn record_heap_failure() {
+    MEMORY_STATS.heap_failures.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records the release of a heap allocation of `size` bytes.
+pub fn record_heap_free(size: usize) {
+    let _ = MEMORY_STATS
+        .heap_used
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            Some(used.saturating_sub(size))
+        });
+}
+
+/// Charges `size` bytes of memory to a given subsystem.
+pub fn charge(consumer: MemoryConsumer, size: usize) {
+    MEMORY_STATS.charged[consumer.index()].fetch_add(size, Ordering::Relaxed);
+}
+
+/// Releases `size` bytes of memory previously charged to a given subsystem.
+pub fn uncharge(consumer: MemoryConsumer, size: usize) {
+    let _ = MEMORY_STATS.charged[consumer.index()].fetch_update(
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+        |charged| Some(charged.saturating_sub(size)),
+    );
+}
+
+/// Snapshot of the memory usage of the system.
+#[derive(Debug, Clone, Copy)]
+pub struct MemInfo {
+    /// Number of physical frames managed by the frame allocator.
+    pub total_frames: usize,
+
+    /// Number of physical frames currently allocated.
+    pub used_frames: usize,
+
+    /// Size of the heap, in bytes (0 if unknown).
+    pub heap_size: usize,
+
+    /// Number of bytes currently allocated on the heap.
+    pub heap_used: usize,
+
+    /// Highest number of bytes allocated on the heap at once.
+    pub heap_peak: usize,
+
+    /// Number of successful heap allocations since boot.
+    pub heap_allocs: usize,
+
+    /// Number of failed heap allocations since boot.
+    pub heap_failures: usize,
+
+    charged: [usize; MemoryConsumer::COUNT],
+}
+
+impl MemInfo {
+    /// Number of physical frames still available.
+    pub fn free_frames(&self) -> usize {
+        self.total_frames.saturating_sub(self.used_frames)
+    }
+
+    /// Amount of memory charged to a given subsystem, in bytes.
+    pub fn charged(&self, consumer: MemoryConsumer) -> usize {
+        self.charged[consumer.index()]
+    }
+}
+
+impl Display for MemInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        writeln!(
+            f,
+            "frames: total = {}    used = {}    free = {}",
+            self.total_frames,
+            self.used_frames,
+            self.free_frames()
+        )?;
+        write!(
+            f,
+            "heap: size = {:#x}    used = {:#x}    peak = {:#x}    allocs = {}    failures = {}",
+            self.heap_size, self.heap_used, self.heap_peak, self.heap_allocs, self.heap_failures
+        )?;
+
+        for consumer in MemoryConsumer::ALL {
+            write!(f, "\n{}: {:#x}", consumer.name(), self.charged(consumer))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns a snapshot of the current memory usage.
+pub fn meminfo() -> MemInfo {
+    let mut charged = [0; MemoryConsumer::COUNT];
+
+    for consumer in MemoryConsumer::ALL {
+        charged[consumer.index()] = MEMORY_STATS.charged[consumer.index()].load(Ordering::Relaxed);
+    }
+
+    MemInfo {
+        total_frames: MEMORY_STATS.total_frames.load(Ordering::Relaxed),
+        used_frames: MEMORY_STATS.used_frames.load(Ordering::Relaxed),
+        heap_size: MEMORY_STATS.heap_size.load(Ordering::Relaxed),
+        heap_used: MEMORY_STATS.heap_used.load(Ordering::Relaxed),
+        heap_peak: MEMORY_STATS.heap_peak.load(Ordering::Relaxed),
+        heap_allocs: MEMORY_STATS.heap_allocs.load(Ordering::Relaxed),
+        heap_failures: MEMORY_STATS.heap_failures.load(Ordering::Relaxed),
+        charged,
+    }
+}
+
+/// Prints a memory usage report to the output.
+///
+/// # Panics
+///
+/// Panics if called before initialiazing the shared [`TextFrameBuffer`].
+///
+/// [`TextFrameBuffer`]: crate::video::vesa::framebuffer::TextFrameBuffer
+pub fn print_meminfo() {
+    info!("meminfo", "{}", meminfo());
+}
@@ -0,0 +1,664 @@
+//! Virtual memory areas of process address spaces.
+//!
+//! Every process owns an [`AddressSpace`], that describes which regions of its virtual memory may be accessed, and
+//! how: the _Virtual Memory Areas_ (see [`Vma`]). Areas are populated lazily: the physical memory backing a page is
+//! only allocated and mapped when the page is first accessed, by the page fault handler (see [`handle_page_fault`]).
+//!
+//! Areas are either anonymous (zero-filled), or backed by a file (see [`MappedFile`]).
+//!
+//! Areas are stored in a tree ordered by their start address, and never overlap. Areas placed by the kernel (see
+//! [`AddressSpace::map`], and the heap grown by [`AddressSpace::set_brk`]) are always followed by at least one
+//! unmapped _guard page_, so that running past the end of an area faults instead of silently reaching the next one.
+
+use core::ops::{BitAnd, BitOr};
+use core::ptr;
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+
+use crate::{
+    errors::VmaError,
+    kernel_syms::PAGE_SIZE,
+    process::get_process,
+    scheduler::{current_process_id, scheduler_running},
+    x86::paging::{
+        page_alloc::frame_alloc::{alloc_page, free_page, FrameAllocation},
+        page_table::{
+            mapper::{PageTableMapper, PhysicalMemoryMapping},
+            translate::PageAddressTranslator,
+        },
+        Frame, Page, PageTableFlags,
+    },
+};
+
+use super::{
+    filemap::MappedFile,
+    get_physical_memory,
+    stats::{self, MemoryConsumer},
+    MemoryAddress, PhyAddr, VirtAddr,
+};
+
+/// Lowest address at which areas are placed when no fixed address is requested.
+pub const MMAP_BASE: VirtAddr = VirtAddr::new(0x1000_0000_0000);
+
+/// Default start of the program break (the heap of a process, see [`AddressSpace::set_brk`]).
+pub const BRK_BASE: VirtAddr = VirtAddr::new(0x4000_0000);
+
+/// End of the lower half of the virtual address space, where the areas of processes live.
+pub const USER_SPACE_END: VirtAddr = VirtAddr::new(0x8000_0000_0000);
+
+/// Access permissions of a [`Vma`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VmaFlags(u32);
+
+impl VmaFlags {
+    const NO_FLAGS: Self = Self(0);
+
+    /// Pages of the area can be read.
+    pub const READ: Self = Self(1 << 0);
+
+    /// Pages of the area can be written.
+    pub const WRITE: Self = Self(1 << 1);
+
+    /// Instructions can be fetched from pages of the area.
+    pub const EXEC: Self = Self(1 << 2);
+
+    /// Pages of the area can be accessed from user mode.
+    pub const USER: Self = Self(1 << 3);
+
+    /// Modifications of the pages of a file-backed area are visible to every other mapping of the file.
+    pub const SHARED: Self = Self(1 << 4);
+
+    pub fn contains(self, flags: Self) -> bool {
+        self & flags == flags
+    }
+
+    /// Returns the flags of the [`PageTableEntry`](crate::x86::paging::page_table::PageTableEntry) mapping a page of
+    /// an area with these permissions.
+    fn page_flags(self) -> PageTableFlags {
+        PageTableFlags::new()
+            .with_present(true)
+            .with_write(self.contains(Self::WRITE))
+            .with_user_access(self.contains(Self::USER))
+            .with_nxe(!self.contains(Self::EXEC))
+    }
+}
+
+impl BitOr for VmaFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitAnd for VmaFlags {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl Default for VmaFlags {
+    fn default() -> Self {
+        Self::NO_FLAGS
+    }
+}
+
+/// Source of the content of the pages of a [`Vma`].
+#[derive(Clone, Debug)]
+pub enum VmaBacking {
+    /// Pages are zero-filled when first accessed.
+    Anonymous,
+
+    /// Pages are filled with the content of a file, starting at `offset` (page-aligned) in the file for the first
+    /// page of the area.
+    File { file: Arc<MappedFile>, offset: u64 },
+}
+
+impl VmaBacking {
+    /// Returns the backing of the part of an area starting `delta` bytes after the beginning of the area.
+    fn advance(&self, delta: u64) -> Self {
+        match self {
+            Self::Anonymous => Self::Anonymous,
+            Self::File { file, offset } => Self::File {
+                file: file.clone(),
+                offset: offset + delta,
+            },
+        }
+    }
+}
+
+/// A _Virtual Memory Area_: a contiguous, page-aligned region of an address space that shares the same permissions
+/// and backing.
+#[derive(Clone, Debug)]
+pub struct Vma {
+    start: VirtAddr,
+    end: VirtAddr,
+    flags: VmaFlags,
+    backing: VmaBacking,
+}
+
+impl Vma {
+    /// Creates an area covering `[start, end)`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`VmaError::InvalidRange`] if the area is empty, or if its bounds (or the offset in its backing file)
+    /// are not aligned on a page boundary.
+    pub fn new(
+        start: VirtAddr,
+        end: VirtAddr,
+        flags: VmaFlags,
+        backing: VmaBacking,
+    ) -> Result<Self, VmaError> {
+        if start >= end || !is_page_aligned(start) || !is_page_aligned(end) {
+            return Err(VmaError::InvalidRange);
+        }
+        if let VmaBacking::File { offset, .. } = &backing {
+            if offset % PAGE_SIZE as u64 != 0 {
+                return Err(VmaError::InvalidRange);
+            }
+        }
+
+        Ok(Self {
+            start,
+            end,
+            flags,
+            backing,
+        })
+    }
+
+    pub fn start(&self) -> VirtAddr {
+        self.start
+    }
+
+    pub fn end(&self) -> VirtAddr {
+        self.end
+    }
+
+    pub fn len(&self) -> usize {
+        (u64::from(self.end) - u64::from(self.start)) as usize
+    }
+
+    pub fn flags(&self) -> VmaFlags {
+        self.flags
+    }
+
+    pub fn backing(&self) -> &VmaBacking {
+        &self.backing
+    }
+
+    /// Checks if `addr` lies in this area.
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        addr >= self.start && addr < self.end
+    }
+
+    /// Checks if the pages of this area are mapped directly from the page cache of its backing file, rather than being
+    /// owned by the area.
+    fn maps_page_cache(&self) -> bool {
+        matches!(self.backing, VmaBacking::File { .. })
+            && (self.flags.contains(VmaFlags::SHARED) || !self.flags.contains(VmaFlags::WRITE))
+    }
+
+    /// Splits this area at `addr`, which must lie strictly inside of it.
+    ///
+    /// This area is shrunk to end at `addr`, and the part starting at `addr` is returned.
+    fn split_off(&mut self, addr: VirtAddr) -> Self {
+        let upper = Self {
+            start: addr,
+            end: self.end,
+            flags: self.flags,
+            backing: self
+                .backing
+                .advance(u64::from(addr) - u64::from(self.start)),
+        };
+        self.end = addr;
+
+        upper
+    }
+}
+
+/// Error code pushed by the CPU when a page fault is raised, describing the access that caused it.
+#[derive(Clone, Copy, Debug)]
+pub struct PageFaultCode(u64);
+
+impl PageFaultCode {
+    const PRESENT: u64 = 1 << 0;
+    const WRITE: u64 = 1 << 1;
+    const USER: u64 = 1 << 2;
+    const INSTRUCTION_FETCH: u64 = 1 << 4;
+
+    pub fn new(error_code: u64) -> Self {
+        Self(error_code)
+    }
+
+    /// The fault was caused by a protection violation on a present page (rather than by a non-present page).
+    pub fn protection_violation(self) -> bool {
+        self.0 & Self::PRESENT != 0
+    }
+
+    /// The access causing the fault was a write.
+    pub fn write(self) -> bool {
+        self.0 & Self::WRITE != 0
+    }
+
+    /// The access causing the fault was made from user mode.
+    pub fn user(self) -> bool {
+        self.0 & Self::USER != 0
+    }
+
+    /// The access causing the fault was an instruction fetch.
+    pub fn instruction_fetch(self) -> bool {
+        self.0 & Self::INSTRUCTION_FETCH != 0
+    }
+}
+
+/// Virtual memory layout of a process: its page table, and the areas it may access.
+#[derive(Debug)]
+pub struct AddressSpace {
+    page_table: PhyAddr,
+    areas: BTreeMap<u64, Vma>,
+
+    /// Start of the heap area.
+    brk_start: VirtAddr,
+
+    /// Current program break (end of the heap, not necessarily page-aligned).
+    brk: VirtAddr,
+}
+
+impl AddressSpace {
+    /// Creates an empty address space, using the page table located at `page_table`.
+    pub fn new(page_table: PhyAddr) -> Self {
+        Self {
+            page_table,
+            areas: BTreeMap::new(),
+            brk_start: BRK_BASE,
+            brk: BRK_BASE,
+        }
+    }
+
+    /// Physical address of the top-level page table of this address space.
+    pub fn page_table(&self) -> PhyAddr {
+        self.page_table
+    }
+
+    /// Returns the areas of this address space, ordered by start address.
+    pub fn areas(&self) -> impl Iterator<Item = &Vma> {
+        self.areas.values()
+    }
+
+    /// Returns the area containing `addr`, if any.
+    pub fn find(&self, addr: VirtAddr) -> Option<&Vma> {
+        self.areas
+            .range(..=u64::from(addr))
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| vma.contains(addr))
+    }
+
+    /// Checks if any area overlaps `[start, end)`.
+    pub fn overlaps(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        self.areas
+            .range(..u64::from(end))
+            .next_back()
+            .is_some_and(|(_, vma)| vma.end > start)
+    }
+
+    /// Adds a new area to this address space.
+    ///
+    /// Nothing is mapped until the pages of the area are accessed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`VmaError::Overlap`] if the area overlaps an existing one.
+    pub fn insert(&mut self, vma: Vma) -> Result<(), VmaError> {
+        if self.overlaps(vma.start, vma.end) {
+            return Err(VmaError::Overlap);
+        }
+
+        self.areas.insert(u64::from(vma.start), vma);
+
+        Ok(())
+    }
+
+    /// Removes `[start, start + len)` from this address space.
+    ///
+    /// Areas partially covered by the range are split, and only their part inside of the range is removed. Returns the
+    /// removed parts, whose pages have to be unmapped by the caller.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`VmaError::InvalidRange`] if the range is empty or not page-aligned.
+    pub fn remove(&mut self, start: VirtAddr, len: usize) -> Result<Vec<Vma>, VmaError> {
+        let end = start + len;
+        if len == 0 || !is_page_aligned(start) || !is_page_aligned(end) || end < start {
+            return Err(VmaError::InvalidRange);
+        }
+
+        let covered: Vec<u64> = self
+            .areas
+            .range(..u64::from(end))
+            .rev()
+            .take_while(|(_, vma)| vma.end > start)
+            .map(|(&key, _)| key)
+            .collect();
+
+        let mut removed = Vec::with_capacity(covered.len());
+
+        for key in covered.into_iter().rev() {
+            let mut vma = self.areas.remove(&key).unwrap();
+
+            if vma.start < start {
+                let inner = vma.split_off(start);
+                self.areas.insert(key, vma);
+                vma = inner;
+            }
+
+            if vma.end > end {
+                let upper = vma.split_off(end);
+                self.areas.insert(u64::from(end), upper);
+            }
+
+            removed.push(vma);
+        }
+
+        Ok(removed)
+    }
+
+    /// Adds an area of `len` bytes, at an address chosen by the kernel, and returns its start address.
+    ///
+    /// The area is placed at the lowest free range above `hint` (or [`MMAP_BASE`]) that leaves a guard page on each
+    /// side of it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`VmaError::InvalidRange`] if `len` is zero, or [`VmaError::NoSpace`] if no free range is large enough.
+    pub fn map(
+        &mut self,
+        hint: Option<VirtAddr>,
+        len: usize,
+        flags: VmaFlags,
+        backing: VmaBacking,
+    ) -> Result<VirtAddr, VmaError> {
+        let len = page_align_up(len as u64).ok_or(VmaError::InvalidRange)?;
+        if len == 0 {
+            return Err(VmaError::InvalidRange);
+        }
+
+        let start = self.find_free_range(hint, len).ok_or(VmaError::NoSpace)?;
+        self.insert(Vma::new(start, start + len, flags, backing)?)?;
+
+        Ok(start)
+    }
+
+    /// Adds an area covering `[start, start + len)`, replacing the areas (and unmapping the pages) that were
+    /// previously there.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`VmaError::InvalidRange`] if the range is empty, not page-aligned, or outside of the lower half of
+    /// the address space.
+    pub fn map_fixed(
+        &mut self,
+        start: VirtAddr,
+        len: usize,
+        flags: VmaFlags,
+        backing: VmaBacking,
+    ) -> Result<(), VmaError> {
+        let end = u64::from(start)
+            .checked_add(page_align_up(len as u64).ok_or(VmaError::InvalidRange)?)
+            .ok_or(VmaError::InvalidRange)?;
+        if end > u64::from(USER_SPACE_END) {
+            return Err(VmaError::InvalidRange);
+        }
+
+        let vma = Vma::new(start, VirtAddr::new(end), flags, backing)?;
+        self.unmap(vma.start, vma.len())?;
+
+        self.insert(vma)
+    }
+
+    /// Removes `[start, start + len)` from this address space, and releases the physical memory that was mapped in
+    /// that range.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`VmaError::InvalidRange`] if the range is empty or not page-aligned.
+    pub fn unmap(&mut self, start: VirtAddr, len: usize) -> Result<(), VmaError> {
+        for vma in self.remove(start, len)? {
+            self.release_pages(&vma);
+        }
+
+        Ok(())
+    }
+
+    /// Current program break.
+    pub fn brk(&self) -> VirtAddr {
+        self.brk
+    }
+
+    /// Sets the start of the heap, usually right after the end of the executable loaded in this address space.
+    ///
+    /// Must be called before the heap is first grown.
+    pub fn set_brk_start(&mut self, addr: VirtAddr) {
+        let start = page_align_up(u64::from(addr)).map_or(addr, VirtAddr::new);
+
+        self.brk_start = start;
+        self.brk = start;
+    }
+
+    /// Moves the program break to `addr`, and returns the new program break.
+    ///
+    /// The heap is an anonymous area with permissions `flags`, that is grown or shrunk to end at the page following
+    /// `addr`. As for other areas, its pages are only allocated when accessed. If the program break cannot be moved
+    /// (`addr` is below the start of the heap, or the heap would run into another area or its guard page), the
+    /// current program break is returned unchanged.
+    pub fn set_brk(&mut self, addr: VirtAddr, flags: VmaFlags) -> VirtAddr {
+        let Some(new_end) = page_align_up(u64::from(addr)) else {
+            return self.brk;
+        };
+        let Some(old_end) = page_align_up(u64::from(self.brk)) else {
+            return self.brk;
+        };
+
+        if addr < self.brk_start {
+            return self.brk;
+        }
+
+        if new_end > old_end {
+            let guard_end = new_end + PAGE_SIZE as u64;
+            if guard_end > u64::from(USER_SPACE_END)
+                || self.overlaps(VirtAddr::new(old_end), VirtAddr::new(guard_end))
+            {
+                return self.brk;
+            }
+
+            if old_end == u64::from(self.brk_start) {
+                let heap = Vma::new(
+                    self.brk_start,
+                    VirtAddr::new(new_end),
+                    flags,
+                    VmaBacking::Anonymous,
+                );
+                if heap.and_then(|heap| self.insert(heap)).is_err() {
+                    return self.brk;
+                }
+            } else {
+                match self.areas.get_mut(&u64::from(self.brk_start)) {
+                    Some(heap) if u64::from(heap.end) == old_end => {
+                        heap.end = VirtAddr::new(new_end)
+                    }
+                    _ => return self.brk,
+                }
+            }
+        } else if new_end < old_end
+            && self
+                .unmap(VirtAddr::new(new_end), (old_end - new_end) as usize)
+                .is_err()
+        {
+            return self.brk;
+        }
+
+        self.brk = addr;
+        self.brk
+    }
+
+    /// Returns the lowest free range of `len` bytes above `hint`, surrounded by guard pages.
+    fn find_free_range(&self, hint: Option<VirtAddr>, len: u64) -> Option<VirtAddr> {
+        let guard = PAGE_SIZE as u64;
+        let mut candidate = hint
+            .filter(|hint| !hint.is_null())
+            .and_then(|hint| page_align_up(u64::from(hint)))
+            .unwrap_or(u64::from(MMAP_BASE))
+            .max(guard);
+
+        loop {
+            let end = candidate.checked_add(len)?;
+            if end > u64::from(USER_SPACE_END) {
+                return None;
+            }
+
+            // Areas do not overlap, so if the last area starting before the end of the candidate range (and its guard
+            // page) ends before the candidate, every area does.
+            match self.areas.range(..end + guard).next_back() {
+                Some((_, vma)) if u64::from(vma.end) + guard > candidate => {
+                    candidate = u64::from(vma.end) + guard;
+                }
+                _ => return Some(VirtAddr::new(candidate)),
+            }
+        }
+    }
+
+    /// Unmaps the pages of `vma` that were populated, and frees the physical memory backing them (unless it belongs to
+    /// the page cache of a file).
+    fn release_pages(&self, vma: &Vma) {
+        let mut mapper = self.mapper();
+        let owned = !vma.maps_page_cache();
+
+        for page in (u64::from(vma.start)..u64::from(vma.end)).step_by(PAGE_SIZE) {
+            let frame = mapper.unmap_4kb_page(Page::new(VirtAddr::new(page)));
+
+            if let Some(frame) = frame.filter(|_| owned) {
+                free_page(FrameAllocation {
+                    start: frame.addr(),
+                    length: PAGE_SIZE,
+                });
+                stats::uncharge(MemoryConsumer::UserPages, PAGE_SIZE);
+            }
+        }
+    }
+
+    fn mapper(&self) -> PageTableMapper<PageAddressTranslator, PhysicalMemoryMapping> {
+        unsafe {
+            PageTableMapper::new_from_raw(
+                self.page_table,
+                PhysicalMemoryMapping::KERNEL_DEFAULT_MAPPING,
+            )
+        }
+    }
+
+    /// Resolves a page fault at `addr` against the areas of this address space.
+    ///
+    /// If the access is allowed by the area containing `addr`, a page of physical memory is allocated, populated from
+    /// the area's backing, and mapped at `addr`. Pages of read-only or shared file-backed areas are mapped directly
+    /// from the page cache of the file instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `addr` is not covered by any area, if the access is not allowed, or if the page could not
+    /// be allocated or mapped. The faulting access cannot be resumed in any of these cases.
+    pub fn handle_fault(&mut self, addr: VirtAddr, code: PageFaultCode) -> Result<(), VmaError> {
+        let vma = self.find(addr).ok_or(VmaError::NotMapped)?;
+        let flags = vma.flags;
+
+        if code.protection_violation()
+            || (code.write() && !flags.contains(VmaFlags::WRITE))
+            || (code.instruction_fetch() && !flags.contains(VmaFlags::EXEC))
+            || (code.user() && !flags.contains(VmaFlags::USER))
+        {
+            return Err(VmaError::AccessViolation);
+        }
+
+        let page_addr = VirtAddr::new(u64::from(addr) & !(PAGE_SIZE as u64 - 1));
+        let page = Page::new(page_addr);
+        let delta = u64::from(page_addr) - u64::from(vma.start);
+
+        let parent_flags = PageTableFlags::new()
+            .with_present(true)
+            .with_write(true)
+            .with_user_access(flags.contains(VmaFlags::USER));
+
+        let mut mapper = self.mapper();
+
+        match &vma.backing {
+            VmaBacking::File { file, offset } if vma.maps_page_cache() => {
+                let frame = file.page((offset + delta) / PAGE_SIZE as u64)?;
+
+                return mapper
+                    .map_4kb_page(page, Frame::new(frame), flags.page_flags(), parent_flags)
+                    .map_err(|_| VmaError::MappingFailed);
+            }
+            _ => (),
+        }
+
+        let frame = alloc_page(PAGE_SIZE).map_err(|_| VmaError::OutOfMemory)?;
+
+        let populated = match &vma.backing {
+            VmaBacking::Anonymous => unsafe {
+                ptr::write_bytes(get_physical_memory(frame.start), 0, PAGE_SIZE);
+                Ok(())
+            },
+            VmaBacking::File { file, offset } => {
+                file.copy_page((offset + delta) / PAGE_SIZE as u64, frame.start)
+            }
+        };
+
+        if let Err(err) = populated {
+            free_page(frame);
+            return Err(err);
+        }
+
+        if mapper
+            .map_4kb_page(
+                page,
+                Frame::new(frame.start),
+                flags.page_flags(),
+                parent_flags,
+            )
+            .is_err()
+        {
+            free_page(frame);
+            return Err(VmaError::MappingFailed);
+        }
+
+        stats::charge(MemoryConsumer::UserPages, PAGE_SIZE);
+
+        Ok(())
+    }
+}
+
+/// Resolves a page fault at `addr`, caused by an access described by `code`, against the address space of the
+/// current process.
+///
+/// Returns `Ok` if the faulting page is now mapped, and the faulting instruction can be resumed.
+pub fn handle_page_fault(addr: VirtAddr, code: PageFaultCode) -> Result<(), VmaError> {
+    if !scheduler_running() {
+        return Err(VmaError::NoAddressSpace);
+    }
+
+    let process = get_process(current_process_id()).ok_or(VmaError::NoAddressSpace)?;
+
+    // The fault may have been raised while the process was locked: give up instead of deadlocking.
+    let mut process = process.try_lock().ok_or(VmaError::NoAddressSpace)?;
+
+    process.address_space.handle_fault(addr, code)
+}
+
+fn is_page_aligned(addr: VirtAddr) -> bool {
+    u64::from(addr) % PAGE_SIZE as u64 == 0
+}
+
+fn page_align_up(addr: u64) -> Option<u64> {
+    Some(addr.checked_add(PAGE_SIZE as u64 - 1)? & !(PAGE_SIZE as u64 - 1))
+}
@@ -7,11 +7,17 @@ use core::{
     alloc::Layout,
     mem::size_of,
     ops::{Add, Sub},
+    ptr::NonNull,
 };
 
 use crate::{
+    errors::AllocError,
     kernel_syms::PAGE_SIZE,
-    mem::{vmalloc::rbtree::Node, Alignment, MemoryAddress, VirtAddr},
+    mem::{
+        stats::{self, MemoryConsumer},
+        vmalloc::rbtree::Node,
+        Alignment, MemoryAddress, VirtAddr,
+    },
     x86::paging::{get_memory_mapper, page_alloc::frame_alloc::alloc_page, PageTableFlags},
 };
 
@@ -235,6 +241,23 @@ impl KernelHeapAllocator {
         }
     }
 
+    /// Allocates memory from the Kernel heap as described by the given [`Layout`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`AllocError::InvalidLayout`] for zero-sized allocations, or [`AllocError::OutOfMemory`] if no
+    /// virtual memory block or physical frame was available to satisfy the request.
+    pub(crate) unsafe fn try_kalloc(
+        &mut self,
+        alloc_layout: Layout,
+    ) -> Result<NonNull<u8>, AllocError> {
+        if alloc_layout.size() == 0 {
+            return Err(AllocError::InvalidLayout);
+        }
+
+        NonNull::new(self.kalloc_layout(alloc_layout).as_mut_ptr()).ok_or(AllocError::OutOfMemory)
+    }
+
     /// Frees memory allocated from the Kernel heap.
     ///
     /// Uses the [`AllocHeader`] associated with the allocation to retrieve the allocation size, which does not necessarily need to be
@@ -289,6 +312,7 @@ impl KernelHeapAllocator {
             Ok(ptr) => ptr,
             Err(_) => return VirtAddr::NULL_PTR,
         };
+        stats::charge(MemoryConsumer::KernelHeap, pages.length);
 
         let extra_alloc_size = pages.length - size_req;
 
@@ -2,19 +2,31 @@
 //!
 //! `vmalloc` manages every heap allocations made in kernel-space. It mainly relies on a Red-black tree allocator, along with serveral buddy
 //! allocators. It dynamically allocates and maps physical memory when necessary.
+//!
+//! With the `mem_shadow` feature, the pages of freed blocks are poisoned in the shadow map of the physical memory pool
+//! (see `mem::shadow`), so that accesses to them through the physical memory mapping and double frees are reported.
 
-use core::alloc::GlobalAlloc;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
 
 use conquer_once::spin::OnceCell;
 use kheap::KernelHeapAllocator;
 use spin::Mutex;
 
 use crate::{
+    errors::AllocError,
+    fail_point,
     kernel_syms::{KERNEL_HEAP_BASE, KERNEL_HEAP_SIZE, PAGE_SIZE},
     x86::paging::{get_memory_mapper, page_alloc::frame_alloc::alloc_page, PageTableFlags},
 };
 
-use super::VirtAddr;
+#[cfg(feature = "mem_shadow")]
+use super::shadow::{shadow_poison_heap, shadow_unpoison_heap};
+use super::{
+    oom,
+    stats::{self, MemoryConsumer},
+    VirtAddr,
+};
 
 pub(crate) mod kheap;
 pub(crate) mod rbtree;
@@ -47,6 +59,9 @@ pub unsafe fn init_kernel_heap() {
             last_heap_page.length,
         );
 
+        stats::set_heap_size(KERNEL_HEAP_SIZE);
+        stats::charge(MemoryConsumer::KernelHeap, 2 * PAGE_SIZE);
+
         Mutex::new(KernelHeapAllocator::init(
             KERNEL_HEAP_BASE,
             KERNEL_HEAP_SIZE,
@@ -56,16 +71,49 @@ pub unsafe fn init_kernel_heap() {
 
 pub struct SyncKernelHeapAllocator {}
 
+/// Allocates memory from the Kernel heap as described by the given [`Layout`].
+///
+/// If the heap (or the physical memory backing it) is exhausted, the registered OOM handlers are called to release
+/// some memory, and the allocation is attempted a second time.
+///
+/// # Errors
+///
+/// Returns [`AllocError::Uninitialized`] if called before [`init_kernel_heap`], or [`AllocError::OutOfMemory`] if
+/// the allocation failed even after reclaiming memory.
+pub fn try_kalloc(layout: Layout) -> Result<NonNull<u8>, AllocError> {
+    let heap = KERNEL_HEAP_ALLOCATOR
+        .get()
+        .ok_or(AllocError::Uninitialized)?;
+
+    fail_point!("alloc.heap", {
+        stats::record_heap_failure();
+        return Err(AllocError::OutOfMemory);
+    });
+
+    let result = oom::with_reclaim(layout.size(), || unsafe { heap.lock().try_kalloc(layout) });
+
+    match result {
+        Ok(ptr) => {
+            stats::record_heap_alloc(layout.size());
+            #[cfg(feature = "mem_shadow")]
+            shadow_unpoison_heap(VirtAddr::new(ptr.as_ptr() as u64), layout.size());
+        }
+        Err(_) => stats::record_heap_failure(),
+    }
+
+    result
+}
+
 unsafe impl GlobalAlloc for SyncKernelHeapAllocator {
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        KERNEL_HEAP_ALLOCATOR
-            .get_unchecked()
-            .lock()
-            .kalloc_layout(layout)
-            .as_mut_ptr::<u8>()
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        try_kalloc(layout).map_or(ptr::null_mut(), |ptr| ptr.as_ptr())
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        stats::record_heap_free(layout.size());
+        #[cfg(feature = "mem_shadow")]
+        shadow_poison_heap(VirtAddr::new(ptr as u64), layout.size());
+
         KERNEL_HEAP_ALLOCATOR
             .get_unchecked()
             .lock()
@@ -0,0 +1,9 @@
+//! Graphic utilities, drawn on top of the [`TextFrameBuffer`].
+//!
+//! [`TextFrameBuffer`]: crate::video::vesa::framebuffer::TextFrameBuffer
+
+#[cfg(feature = "alloc")]
+pub mod qr;
+
+#[cfg(feature = "alloc")]
+pub mod screenshot;
@@ -0,0 +1,648 @@
+//! QR Code encoder.
+//!
+//! Encodes arbitrary bytes into a QR Code symbol (model 2, versions 1 to 40), using the byte mode. The smallest
+//! version able to hold the data is selected, and the mask is chosen to minimize the penalty score defined by the
+//! specification (ISO/IEC 18004).
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::video::gfx::qr::{QrCode, QrEcc};
+//!
+//! let qr = QrCode::encode_bytes(b"PAGE_FAULT at 0xffff800000012345", QrEcc::Low).unwrap();
+//! qr.draw(&mut text_buffer().buffer.lock(), 100, 100, 4);
+//! ```
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::video::vesa::framebuffer::{RgbaColor, TextFrameBuffer};
+
+/// Smallest QR Code version.
+pub const QR_MIN_VERSION: u8 = 1;
+
+/// Largest QR Code version.
+pub const QR_MAX_VERSION: u8 = 40;
+
+/// Width of the light border that must surround the symbol, in modules.
+pub const QR_QUIET_ZONE: usize = 4;
+
+/// Error correction codewords per block, indexed by error correction level and version.
+const ECC_CODEWORDS_PER_BLOCK: [[u8; 41]; 4] = [
+    [
+        0, 7, 10, 15, 20, 26, 18, 20, 24, 30, 18, 20, 24, 26, 30, 22, 24, 28, 30, 28, 28, 28, 28,
+        30, 30, 26, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    ],
+    [
+        0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28,
+        28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+    ],
+    [
+        0, 13, 22, 18, 26, 18, 24, 18, 22, 20, 24, 28, 26, 24, 20, 30, 24, 28, 28, 26, 30, 28, 30,
+        30, 30, 30, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    ],
+    [
+        0, 17, 28, 22, 16, 22, 28, 26, 26, 24, 28, 24, 28, 22, 24, 24, 30, 28, 28, 26, 28, 30, 24,
+        30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+    ],
+];
+
+/// Number of error correction blocks, indexed by error correction level and version.
+const ECC_BLOCKS: [[u8; 41]; 4] = [
+    [
+        0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 4, 4, 4, 4, 4, 6, 6, 6, 6, 7, 8, 8, 9, 9, 10, 12, 12, 12, 13,
+        14, 15, 16, 17, 18, 19, 19, 20, 21, 22, 24, 25,
+    ],
+    [
+        0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21,
+        23, 25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49,
+    ],
+    [
+        0, 1, 1, 2, 2, 4, 4, 6, 6, 8, 8, 8, 10, 12, 16, 12, 17, 16, 18, 21, 20, 23, 23, 25, 27, 29,
+        34, 34, 35, 38, 40, 43, 45, 48, 51, 53, 56, 59, 62, 65, 68,
+    ],
+    [
+        0, 1, 1, 2, 4, 4, 4, 5, 6, 8, 8, 11, 11, 16, 16, 18, 16, 19, 21, 25, 25, 25, 34, 30, 32,
+        35, 37, 40, 42, 45, 48, 51, 54, 57, 60, 63, 66, 70, 74, 77, 81,
+    ],
+];
+
+/// Error correction level of a QR Code.
+///
+/// Higher levels can recover from more damage, at the cost of a lower capacity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QrEcc {
+    /// About 7% of the codewords can be restored.
+    Low,
+
+    /// About 15% of the codewords can be restored.
+    Medium,
+
+    /// About 25% of the codewords can be restored.
+    Quartile,
+
+    /// About 30% of the codewords can be restored.
+    High,
+}
+
+impl QrEcc {
+    fn index(self) -> usize {
+        match self {
+            Self::Low => 0,
+            Self::Medium => 1,
+            Self::Quartile => 2,
+            Self::High => 3,
+        }
+    }
+
+    /// Value of the level in the format information.
+    fn format_bits(self) -> u32 {
+        match self {
+            Self::Low => 1,
+            Self::Medium => 0,
+            Self::Quartile => 3,
+            Self::High => 2,
+        }
+    }
+}
+
+/// A QR Code symbol.
+#[derive(Clone, Debug)]
+pub struct QrCode {
+    version: u8,
+    size: usize,
+    ecc: QrEcc,
+
+    /// Color of each module (`true` for dark modules), row by row.
+    modules: Vec<bool>,
+
+    /// Set for modules that belong to function patterns, which are not masked.
+    function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encodes `data` using the smallest version that can hold it with the error correction level `ecc`.
+    ///
+    /// Returns `None` if `data` is too large to fit in a QR Code.
+    pub fn encode_bytes(data: &[u8], ecc: QrEcc) -> Option<Self> {
+        let version = (QR_MIN_VERSION..=QR_MAX_VERSION)
+            .find(|&version| data.len() <= byte_capacity(version, ecc))?;
+
+        let mut qr = Self {
+            version,
+            size: usize::from(version) * 4 + 17,
+            ecc,
+            modules: Vec::new(),
+            function: Vec::new(),
+        };
+        qr.modules = vec![false; qr.size * qr.size];
+        qr.function = vec![false; qr.size * qr.size];
+
+        qr.draw_function_patterns();
+        let codewords = qr.add_ecc_and_interleave(&qr.data_codewords(data));
+        qr.draw_codewords(&codewords);
+
+        // Keeps the mask with the lowest penalty score.
+        let mut best_mask = 0;
+        let mut min_penalty = u32::MAX;
+        for mask in 0..8 {
+            qr.apply_mask(mask);
+            qr.draw_format_bits(mask);
+
+            let penalty = qr.penalty_score();
+            if penalty < min_penalty {
+                best_mask = mask;
+                min_penalty = penalty;
+            }
+
+            // Masking twice restores the original modules.
+            qr.apply_mask(mask);
+        }
+
+        qr.apply_mask(best_mask);
+        qr.draw_format_bits(best_mask);
+
+        Some(qr)
+    }
+
+    /// Returns the version of this QR Code (between 1 and 40).
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Returns the width (and height) of the symbol, in modules, without the quiet zone.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the error correction level of this QR Code.
+    pub fn ecc(&self) -> QrEcc {
+        self.ecc
+    }
+
+    /// Checks if the module at (`x`, `y`) is dark.
+    ///
+    /// Modules outside of the symbol (in the quiet zone) are light.
+    pub fn module(&self, x: usize, y: usize) -> bool {
+        x < self.size && y < self.size && self.modules[y * self.size + x]
+    }
+
+    /// Draws the symbol onto `framebuffer`, including its quiet zone, with its top-left corner at (`x`, `y`).
+    ///
+    /// Each module is drawn as a square of `scale` pixels.
+    pub fn draw(&self, framebuffer: &mut TextFrameBuffer, x: usize, y: usize, scale: usize) {
+        let full_size = (self.size + 2 * QR_QUIET_ZONE) * scale;
+        framebuffer.fill_rect(x, y, full_size, full_size, RgbaColor(255, 255, 255, 0));
+
+        let origin_x = x + QR_QUIET_ZONE * scale;
+        let origin_y = y + QR_QUIET_ZONE * scale;
+
+        for module_y in 0..self.size {
+            for module_x in 0..self.size {
+                if self.module(module_x, module_y) {
+                    framebuffer.fill_rect(
+                        origin_x + module_x * scale,
+                        origin_y + module_y * scale,
+                        scale,
+                        scale,
+                        RgbaColor(0, 0, 0, 0),
+                    );
+                }
+            }
+        }
+    }
+
+    fn set_function_module(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self) {
+        // Timing patterns.
+        for i in 0..self.size {
+            self.set_function_module(6, i, i % 2 == 0);
+            self.set_function_module(i, 6, i % 2 == 0);
+        }
+
+        // Finder patterns (and their separators).
+        self.draw_finder_pattern(3, 3);
+        self.draw_finder_pattern(self.size - 4, 3);
+        self.draw_finder_pattern(3, self.size - 4);
+
+        // Alignment patterns, except the ones overlapping the finder patterns.
+        let positions = alignment_pattern_positions(self.version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &pos_x) in positions.iter().enumerate() {
+            for (j, &pos_y) in positions.iter().enumerate() {
+                if !((i == 0 && j == 0) || (i == 0 && j == last) || (i == last && j == 0)) {
+                    self.draw_alignment_pattern(pos_x, pos_y);
+                }
+            }
+        }
+
+        // Reserves the format information area, which is drawn once the mask is known.
+        self.draw_format_bits(0);
+        self.draw_version();
+    }
+
+    fn draw_finder_pattern(&mut self, x: usize, y: usize) {
+        for dy in -4_isize..=4 {
+            for dx in -4_isize..=4 {
+                let dist = dx.abs().max(dy.abs());
+                let (Some(module_x), Some(module_y)) =
+                    (x.checked_add_signed(dx), y.checked_add_signed(dy))
+                else {
+                    continue;
+                };
+
+                if module_x < self.size && module_y < self.size {
+                    self.set_function_module(module_x, module_y, dist != 2 && dist != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment_pattern(&mut self, x: usize, y: usize) {
+        for dy in -2_isize..=2 {
+            for dx in -2_isize..=2 {
+                self.set_function_module(
+                    x.wrapping_add_signed(dx),
+                    y.wrapping_add_signed(dy),
+                    dx.abs().max(dy.abs()) != 1,
+                );
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u8) {
+        let data = (self.ecc.format_bits() << 3) | u32::from(mask);
+        let mut rem = data;
+        for _ in 0..10 {
+            rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+        }
+        let bits = ((data << 10) | rem) ^ 0x5412;
+        let size = self.size;
+
+        // First copy, around the top-left finder pattern.
+        for i in 0..=5 {
+            self.set_function_module(8, i, bit(bits, i));
+        }
+        self.set_function_module(8, 7, bit(bits, 6));
+        self.set_function_module(8, 8, bit(bits, 7));
+        self.set_function_module(7, 8, bit(bits, 8));
+        for i in 9..15 {
+            self.set_function_module(14 - i, 8, bit(bits, i));
+        }
+
+        // Second copy, split between the two other finder patterns.
+        for i in 0..8 {
+            self.set_function_module(size - 1 - i, 8, bit(bits, i));
+        }
+        for i in 8..15 {
+            self.set_function_module(8, size - 15 + i, bit(bits, i));
+        }
+
+        // Always dark.
+        self.set_function_module(8, size - 8, true);
+    }
+
+    fn draw_version(&mut self) {
+        if self.version < 7 {
+            return;
+        }
+
+        let mut rem = u32::from(self.version);
+        for _ in 0..12 {
+            rem = (rem << 1) ^ ((rem >> 11) * 0x1f25);
+        }
+        let bits = (u32::from(self.version) << 12) | rem;
+
+        for i in 0..18 {
+            let dark = bit(bits, i);
+            let a = self.size - 11 + i % 3;
+            let b = i / 3;
+            self.set_function_module(a, b, dark);
+            self.set_function_module(b, a, dark);
+        }
+    }
+
+    /// Builds the data codewords: byte mode indicator, character count, data, terminator and padding.
+    fn data_codewords(&self, data: &[u8]) -> Vec<u8> {
+        let capacity = data_codewords_count(self.version, self.ecc);
+        let mut bits = BitBuffer::default();
+
+        bits.append(0b0100, 4);
+        bits.append(data.len() as u32, if self.version < 10 { 8 } else { 16 });
+        for &byte in data {
+            bits.append(u32::from(byte), 8);
+        }
+
+        let terminator = usize::min(4, capacity * 8 - bits.len);
+        bits.append(0, terminator);
+        bits.append(0, (8 - bits.len % 8) % 8);
+
+        let mut codewords = bits.bytes;
+        for pad in [0xec, 0x11].into_iter().cycle() {
+            if codewords.len() >= capacity {
+                break;
+            }
+            codewords.push(pad);
+        }
+
+        codewords
+    }
+
+    /// Splits the data codewords into blocks, computes the error correction codewords of each block, and interleaves
+    /// the blocks.
+    fn add_ecc_and_interleave(&self, data: &[u8]) -> Vec<u8> {
+        let blocks_count = usize::from(ECC_BLOCKS[self.ecc.index()][usize::from(self.version)]);
+        let block_ecc_len =
+            usize::from(ECC_CODEWORDS_PER_BLOCK[self.ecc.index()][usize::from(self.version)]);
+        let raw_codewords = raw_data_modules(self.version) / 8;
+        let short_blocks_count = blocks_count - raw_codewords % blocks_count;
+        let short_block_len = raw_codewords / blocks_count;
+
+        let divisor = reed_solomon_divisor(block_ecc_len);
+        let mut blocks = Vec::with_capacity(blocks_count);
+        let mut offset = 0;
+
+        for i in 0..blocks_count {
+            let data_len = short_block_len - block_ecc_len + usize::from(i >= short_blocks_count);
+            let block_data = &data[offset..(offset + data_len)];
+            offset += data_len;
+
+            let mut block = Vec::from(block_data);
+            if i < short_blocks_count {
+                // Placeholder, so that every block has the same length.
+                block.push(0);
+            }
+            block.extend(reed_solomon_remainder(block_data, &divisor));
+            blocks.push(block);
+        }
+
+        let mut result = Vec::with_capacity(raw_codewords);
+        for i in 0..=short_block_len {
+            for (j, block) in blocks.iter().enumerate() {
+                if i != short_block_len - block_ecc_len || j >= short_blocks_count {
+                    result.push(block[i]);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Places the codewords in the data area, in the zigzag order defined by the specification.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+
+            for vert in 0..size {
+                for j in 0..2 {
+                    let x = right - j;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { size - 1 - vert } else { vert };
+
+                    if !self.function[y * size + x] && i < codewords.len() * 8 {
+                        self.modules[y * size + x] = bit(u32::from(codewords[i >> 3]), 7 - (i & 7));
+                        i += 1;
+                    }
+                }
+            }
+
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    /// Inverts the data modules selected by the mask pattern `mask`.
+    fn apply_mask(&mut self, mask: u8) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+
+                let index = y * self.size + x;
+                if invert && !self.function[index] {
+                    self.modules[index] = !self.modules[index];
+                }
+            }
+        }
+    }
+
+    /// Computes the penalty score of the symbol, used to select the mask pattern.
+    fn penalty_score(&self) -> u32 {
+        let size = self.size;
+        let mut penalty = 0;
+
+        // Runs of modules of the same color, and finder-like patterns, in rows and columns.
+        for horizontal in [true, false] {
+            for i in 0..size {
+                let line: Vec<bool> = (0..size)
+                    .map(|j| {
+                        if horizontal {
+                            self.module(j, i)
+                        } else {
+                            self.module(i, j)
+                        }
+                    })
+                    .collect();
+
+                penalty += line_penalty(&line);
+            }
+        }
+
+        // 2x2 blocks of modules of the same color.
+        for y in 0..(size - 1) {
+            for x in 0..(size - 1) {
+                let color = self.module(x, y);
+                if color == self.module(x + 1, y)
+                    && color == self.module(x, y + 1)
+                    && color == self.module(x + 1, y + 1)
+                {
+                    penalty += 3;
+                }
+            }
+        }
+
+        // Balance of dark and light modules.
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let total = size * size;
+        let deviation = (dark * 20).abs_diff(total * 10);
+        penalty += 10 * (deviation / total) as u32;
+
+        penalty
+    }
+}
+
+/// Accumulates bits, most significant bit first.
+#[derive(Default)]
+struct BitBuffer {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitBuffer {
+    /// Appends the `count` lowest bits of `value`.
+    fn append(&mut self, value: u32, count: usize) {
+        for i in (0..count).rev() {
+            if self.len % 8 == 0 {
+                self.bytes.push(0);
+            }
+
+            if bit(value, i) {
+                let last = self.bytes.len() - 1;
+                self.bytes[last] |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+}
+
+/// Penalty of a single row or column: runs of 5 or more modules of the same color, and patterns similar to the
+/// finder patterns.
+fn line_penalty(line: &[bool]) -> u32 {
+    const FINDER_LIKE: [bool; 11] = [
+        true, false, true, true, true, false, true, false, false, false, false,
+    ];
+
+    let mut penalty = 0;
+    let mut run_len = 1;
+
+    for i in 1..=line.len() {
+        if i < line.len() && line[i] == line[i - 1] {
+            run_len += 1;
+            continue;
+        }
+
+        if run_len >= 5 {
+            penalty += 3 + (run_len - 5);
+        }
+        run_len = 1;
+    }
+
+    for window in line.windows(FINDER_LIKE.len()) {
+        if window.iter().eq(FINDER_LIKE.iter()) || window.iter().eq(FINDER_LIKE.iter().rev()) {
+            penalty += 40;
+        }
+    }
+
+    penalty
+}
+
+/// Returns the center coordinates of the alignment patterns (on each axis) for a given version.
+fn alignment_pattern_positions(version: u8) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+
+    let version = usize::from(version);
+    let count = version / 7 + 2;
+    let step = (version * 8 + count * 3 + 5) / (count * 4 - 4) * 2;
+    let size = version * 4 + 17;
+
+    (0..count)
+        .map(|i| match i {
+            0 => 6,
+            i => size - 7 - (count - 1 - i) * step,
+        })
+        .collect()
+}
+
+/// Number of modules available for data and error correction codewords (including remainder bits).
+fn raw_data_modules(version: u8) -> usize {
+    let version = usize::from(version);
+    let mut result = (16 * version + 128) * version + 64;
+
+    if version >= 2 {
+        let alignment_count = version / 7 + 2;
+        result -= (25 * alignment_count - 10) * alignment_count - 55;
+
+        if version >= 7 {
+            result -= 36;
+        }
+    }
+
+    result
+}
+
+/// Number of data codewords for a given version and error correction level.
+fn data_codewords_count(version: u8, ecc: QrEcc) -> usize {
+    raw_data_modules(version) / 8
+        - usize::from(ECC_CODEWORDS_PER_BLOCK[ecc.index()][usize::from(version)])
+            * usize::from(ECC_BLOCKS[ecc.index()][usize::from(version)])
+}
+
+/// Maximum number of bytes that can be encoded using the byte mode.
+fn byte_capacity(version: u8, ecc: QrEcc) -> usize {
+    let header_bits = 4 + if version < 10 { 8 } else { 16 };
+
+    (data_codewords_count(version, ecc) * 8 - header_bits) / 8
+}
+
+/// Computes the generator polynomial of the Reed-Solomon code of degree `degree`, without its leading term.
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0; degree];
+    result[degree - 1] = 1;
+
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+
+    result
+}
+
+/// Computes the Reed-Solomon error correction codewords of `data`.
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0; divisor.len()];
+
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+
+        for (coef, &div) in result.iter_mut().zip(divisor) {
+            *coef ^= gf_multiply(div, factor);
+        }
+    }
+
+    result
+}
+
+/// Multiplies two elements of GF(2^8), modulo `x^8 + x^4 + x^3 + x^2 + 1`.
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11d);
+        z ^= u32::from((y >> i) & 1) * u32::from(x);
+    }
+
+    z as u8
+}
+
+fn bit(value: u32, i: usize) -> bool {
+    (value >> i) & 1 != 0
+}
@@ -0,0 +1,194 @@
+//! Framebuffer screenshots.
+//!
+//! The content of the screen can be captured as a `BMP` or `PPM` image, to make reports about rendering issues
+//! reproducible. Filesystems are read-only, so screenshots are stored on a partition named
+//! [`SCREENSHOT_PARTITION_LABEL`] in the _GPT_ partition table: the image is written at the start of the partition,
+//! and its header holds its size, so it can be extracted with `dd` and opened as is. Only the last screenshot is
+//! kept.
+//!
+//! Screenshots can also be encoded in base64 (see [`screenshot_base64`]), to be sent over a text-only channel.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::video::gfx::screenshot::{screenshot_save, ScreenshotFormat};
+//!
+//! screenshot_save(ScreenshotFormat::Bmp)?;
+//! ```
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::drivers::generics::dev_disk::{get_sata_drive, DiskDevice};
+use crate::errors::IOError;
+use crate::fs::partitions::registry::{find_partition, PartitionSelector};
+use crate::info;
+use crate::video::vesa::framebuffer::{RgbaColor, TextFrameBuffer};
+use crate::video::vesa::text_buffer;
+
+/// Name of the _GPT_ partition reserved for screenshots.
+pub const SCREENSHOT_PARTITION_LABEL: &str = "screenshot";
+
+/// Size of the `BMP` file header and `BITMAPINFOHEADER`, in bytes.
+const BMP_HEADERS_LEN: usize = 14 + 40;
+
+/// Resolution stored in `BMP` files (72 DPI), in pixels per meter.
+const BMP_PIXELS_PER_METER: u32 = 2835;
+
+/// Color of the pixels that cannot be read back from the framebuffer.
+const BLACK: RgbaColor = RgbaColor(0, 0, 0, 0);
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Image format of a screenshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScreenshotFormat {
+    /// Uncompressed 24-bit `BMP`.
+    Bmp,
+
+    /// Binary `PPM` (`P6`).
+    Ppm,
+}
+
+/// Captures the content of the screen, encoded in the given format.
+///
+/// # Panics
+///
+/// Panics if called before the shared text buffer was initialized.
+pub fn screenshot(format: ScreenshotFormat) -> Vec<u8> {
+    let framebuffer = text_buffer().buffer.lock();
+
+    match format {
+        ScreenshotFormat::Bmp => encode_bmp(&framebuffer),
+        ScreenshotFormat::Ppm => encode_ppm(&framebuffer),
+    }
+}
+
+/// Captures the content of the screen, encoded in the given format and then in base64.
+pub fn screenshot_base64(format: ScreenshotFormat) -> String {
+    base64_encode(&screenshot(format))
+}
+
+/// Captures the content of the screen, and writes it to the screenshot partition.
+///
+/// Returns the size of the image, in bytes.
+///
+/// # Errors
+///
+/// Returns [`IOError::NotFound`] if no screenshot partition exists, and [`IOError::InvalidCommand`] if the partition
+/// is too small to hold the image. May return any other variant of [`IOError`] in case of a device failure.
+pub fn screenshot_save(format: ScreenshotFormat) -> Result<usize, IOError> {
+    let selector = PartitionSelector::PartLabel(String::from(SCREENSHOT_PARTITION_LABEL));
+    let (drive_id, partition_id) = find_partition(&selector).ok_or(IOError::NotFound)?;
+
+    let drive = get_sata_drive(drive_id).ok_or(IOError::InvalidDevice)?;
+    let partition = drive.partition(partition_id).ok_or(IOError::NotFound)?;
+    let (start_lba, sectors) = (partition.start_lba(), partition.size_in_sectors());
+    let sector_size = drive.logical_sector_size() as usize;
+
+    let mut image = screenshot(format);
+    let len = image.len();
+
+    image.resize(len.next_multiple_of(sector_size), 0);
+    if image.len() as u64 > sectors * sector_size as u64 {
+        return Err(IOError::InvalidCommand);
+    }
+
+    drive.write_from(start_lba, &image)?;
+    drive.flush()?;
+
+    info!(
+        "screenshot",
+        "saved a {:?} screenshot ({} bytes) to the '{}' partition",
+        format,
+        len,
+        SCREENSHOT_PARTITION_LABEL
+    );
+
+    Ok(len)
+}
+
+/// Encodes the framebuffer as an uncompressed 24-bit `BMP` file, stored top-down.
+fn encode_bmp(framebuffer: &TextFrameBuffer) -> Vec<u8> {
+    let (width, height) = (framebuffer.metadata.width, framebuffer.metadata.height);
+
+    // Rows are padded to a multiple of 4 bytes.
+    let row_len = (width * 3).next_multiple_of(4);
+    let image_len = row_len * height;
+    let file_len = BMP_HEADERS_LEN + image_len;
+
+    let mut bmp = Vec::with_capacity(file_len);
+
+    // File header.
+    bmp.extend_from_slice(b"BM");
+    bmp.extend_from_slice(&(file_len as u32).to_le_bytes());
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+    bmp.extend_from_slice(&(BMP_HEADERS_LEN as u32).to_le_bytes());
+
+    // `BITMAPINFOHEADER`, with a negative height for a top-down image.
+    bmp.extend_from_slice(&40u32.to_le_bytes());
+    bmp.extend_from_slice(&(width as i32).to_le_bytes());
+    bmp.extend_from_slice(&(-(height as i32)).to_le_bytes());
+    bmp.extend_from_slice(&1u16.to_le_bytes());
+    bmp.extend_from_slice(&24u16.to_le_bytes());
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+    bmp.extend_from_slice(&(image_len as u32).to_le_bytes());
+    bmp.extend_from_slice(&BMP_PIXELS_PER_METER.to_le_bytes());
+    bmp.extend_from_slice(&BMP_PIXELS_PER_METER.to_le_bytes());
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+
+    for y in 0..height {
+        for x in 0..width {
+            let px = framebuffer.read_pixel(x, y).unwrap_or(BLACK);
+            bmp.extend_from_slice(&[px.2, px.1, px.0]);
+        }
+
+        bmp.resize(bmp.len() + row_len - width * 3, 0);
+    }
+
+    bmp
+}
+
+/// Encodes the framebuffer as a binary `PPM` (`P6`) file.
+fn encode_ppm(framebuffer: &TextFrameBuffer) -> Vec<u8> {
+    let (width, height) = (framebuffer.metadata.width, framebuffer.metadata.height);
+
+    let mut ppm = Vec::with_capacity(width * height * 3 + 32);
+    ppm.extend_from_slice(alloc::format!("P6\n{width} {height}\n255\n").as_bytes());
+
+    for y in 0..height {
+        for x in 0..width {
+            let px = framebuffer.read_pixel(x, y).unwrap_or(BLACK);
+            ppm.extend_from_slice(&[px.0, px.1, px.2]);
+        }
+    }
+
+    ppm
+}
+
+/// Encodes `data` in base64 (standard alphabet, with padding).
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (group >> (18 - 6 * i)) & 0x3f;
+                encoded.push(char::from(BASE64_ALPHABET[index as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
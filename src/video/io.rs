@@ -9,19 +9,21 @@ macro_rules! print {
     };
 }
 
+/// Real-mode counterpart of [`info!`](crate::info): prints a string using the BIOS (see
+/// [`bios_log`](crate::log::bios_log)).
 #[macro_export]
 macro_rules! rinfo {
     ($msg: tt) => {
-        $crate::video::io::__bios_print_str("\r\n[info] ");
-        $crate::video::io::__bios_print_str($msg);
+        $crate::log::bios_log($crate::log::LogLevel::Info, $msg);
     };
 }
 
+/// Real-mode counterpart of [`error!`](crate::error): prints a string using the BIOS (see
+/// [`bios_log`](crate::log::bios_log)).
 #[macro_export]
 macro_rules! rerror {
     ($msg: tt) => {
-        $crate::video::io::__bios_print_str("\r\n[error] ");
-        $crate::video::io::__bios_print_str($msg);
+        $crate::log::bios_log($crate::log::LogLevel::Error, $msg);
     };
 }
 
@@ -58,13 +60,18 @@ pub fn cprint_info(str: &[u8]) {
     }
 }
 
+/// Prints a character using the BIOS teletype service (see [`teletype_output`]).
+///
+/// [`teletype_output`]: crate::bios::services::video::teletype_output
+#[cfg(feature = "real")]
 pub fn __bios_printc(ch: u8) {
-    let reg: u16 = u16::from(ch) | 0x0e00;
-    unsafe {
-        asm!("push bx", "mov bx, 0", "int 0x10", "pop bx", in("ax") reg);
-    }
+    crate::bios::services::video::teletype_output(ch);
 }
 
+/// The BIOS services are only available to the real-mode stage: the character is discarded.
+#[cfg(not(feature = "real"))]
+pub fn __bios_printc(_ch: u8) {}
+
 pub fn __bios_print(args: fmt::Arguments) {
     let mut writer = Writer {};
 }
@@ -79,18 +86,18 @@ pub fn color() {
     unsafe { asm!("mov ah, 0x0b", "xor bh, bh", "mov bl, 0x01") }
 }
 
+/// Clears the screen, by setting the 80x25 color text mode again (see [`set_vga_mode`]).
+///
+/// [`set_vga_mode`]: crate::bios::services::video::set_vga_mode
+#[cfg(feature = "real")]
 pub fn clear_screen() {
-    unsafe {
-        asm!(
-            "push ax",
-            "mov ah, 0x00",
-            "mov al, 0x03",
-            "int 0x10",
-            "pop ax"
-        )
-    }
+    crate::bios::services::video::set_vga_mode(0x03);
 }
 
+/// The BIOS services are only available to the real-mode stage: does nothing.
+#[cfg(not(feature = "real"))]
+pub fn clear_screen() {}
+
 struct Writer;
 impl Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
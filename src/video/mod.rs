@@ -1,2 +1,5 @@
+pub mod gfx;
 pub mod io;
+#[cfg(feature = "alloc")]
+pub mod readline;
 pub mod vesa;
@@ -0,0 +1,146 @@
+//! Line editor for the text console.
+//!
+//! [`readline`] reads a line from the keyboard (see [`keyboard`](crate::io::ps2::keyboard)), and echoes it to the
+//! shared [`TextFrameBuffer`](crate::video::vesa::framebuffer::TextFrameBuffer). The following keys are supported:
+//!
+//! - `Enter`: validates the line.
+//! - `Backspace`: erases the last character.
+//! - `Up` / `Down`: browses the lines previously entered.
+//! - `Ctrl+U`: erases the whole line.
+//! - `Escape` / `Ctrl+C`: cancels the input.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::video::readline::readline_with;
+//!
+//! if let Some(cmdline) = readline_with("cmdline> ", "root=PARTLABEL=rootfs") {
+//!     // ...
+//! }
+//! ```
+
+use alloc::{collections::VecDeque, string::String};
+use core::fmt::Write;
+use spin::Mutex;
+
+use crate::{
+    io::ps2::keyboard::{read_key, KeyCode},
+    video::vesa::text_buffer,
+};
+
+/// Number of lines kept in the history.
+pub const READLINE_HISTORY_LEN: usize = 32;
+
+/// Maximum length of a line, in bytes.
+pub const READLINE_MAX_LEN: usize = 256;
+
+static READLINE_HISTORY: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Reads a line from the keyboard, after displaying `prompt`.
+///
+/// Returns `None` if the input was cancelled.
+pub fn readline(prompt: &str) -> Option<String> {
+    readline_with(prompt, "")
+}
+
+/// Reads a line from the keyboard, after displaying `prompt`, starting from an `initial` content that can be edited.
+///
+/// Returns `None` if the input was cancelled.
+pub fn readline_with(prompt: &str, initial: &str) -> Option<String> {
+    let mut line = String::from(initial);
+    echo(prompt);
+    echo(&line);
+
+    // The history is not locked while editing, so that another line can be read concurrently.
+    let history = READLINE_HISTORY.lock().clone();
+    let mut history_pos = history.len();
+
+    // Line being edited before browsing the history.
+    let mut draft = String::new();
+
+    loop {
+        let key = read_key();
+        let ctrl = key.modifiers.ctrl;
+
+        match key.code {
+            KeyCode::Enter => break,
+            KeyCode::Escape => {
+                echo("\n");
+                return None;
+            }
+            KeyCode::Char('c') if ctrl => {
+                echo("^C\n");
+                return None;
+            }
+            KeyCode::Char('u') if ctrl => replace_line(&mut line, ""),
+            KeyCode::Backspace => {
+                if line.pop().is_some() {
+                    erase(1);
+                }
+            }
+            KeyCode::Up if history_pos > 0 => {
+                if history_pos == history.len() {
+                    draft = line.clone();
+                }
+
+                history_pos -= 1;
+                replace_line(&mut line, &history[history_pos]);
+            }
+            KeyCode::Down if history_pos < history.len() => {
+                history_pos += 1;
+                replace_line(&mut line, history.get(history_pos).unwrap_or(&draft));
+            }
+            KeyCode::Char(ch) if !ctrl && !key.modifiers.alt => {
+                if line.len() + ch.len_utf8() <= READLINE_MAX_LEN {
+                    line.push(ch);
+                    echo(ch.encode_utf8(&mut [0; 4]));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    echo("\n");
+    history_push(&line);
+
+    Some(line)
+}
+
+/// Returns the lines previously entered, from the oldest to the most recent.
+pub fn readline_history() -> VecDeque<String> {
+    READLINE_HISTORY.lock().clone()
+}
+
+/// Adds a line to the history, unless it is empty or repeats the most recent one.
+fn history_push(line: &str) {
+    let mut history = READLINE_HISTORY.lock();
+
+    if line.is_empty() || history.back().is_some_and(|last| last == line) {
+        return;
+    }
+
+    if history.len() == READLINE_HISTORY_LEN {
+        history.pop_front();
+    }
+    history.push_back(String::from(line));
+}
+
+/// Replaces the displayed line.
+fn replace_line(line: &mut String, content: &str) {
+    erase(line.chars().count());
+    line.clear();
+    line.push_str(content);
+    echo(line);
+}
+
+fn echo(text: &str) {
+    let _ = text_buffer().buffer.lock().write_str(text);
+}
+
+fn erase(count: usize) {
+    let mut text_buffer = text_buffer().buffer.lock();
+
+    for _ in 0..count {
+        text_buffer.erase_char();
+    }
+}
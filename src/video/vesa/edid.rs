@@ -0,0 +1,91 @@
+//! EDID (Extended Display Identification Data) parsing.
+//!
+//! The EDID block of the monitor is read through the VBE/DDC interface while in real mode, where the
+//! native resolution of the monitor is used to select the video mode (`setup_vesa`, in
+//! `x86/real/vesa.S`). It is kept in memory at `VESA_EDID_BUFFER` so that it remains available after
+//! the switch to protected mode.
+//!
+//! Only the 128-byte base block is used: it is enough to identify the monitor and to find its native
+//! resolution (the preferred timing, described by the first detailed timing descriptor).
+
+use core::ptr;
+
+use crate::video::vesa::video_mode::VESA_EDID_BUFFER;
+
+/// Size of an EDID block, in bytes.
+pub const EDID_BLOCK_SIZE: usize = 128;
+
+/// Fixed pattern starting every EDID base block.
+const EDID_HEADER: [u8; 8] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+
+/// Offset of the first detailed timing descriptor, which describes the preferred timing.
+const EDID_PREFERRED_TIMING: usize = 0x36;
+
+/// EDID base block of a monitor.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct Edid {
+    bytes: [u8; EDID_BLOCK_SIZE],
+}
+
+impl Edid {
+    /// Parses an EDID base block, checking its header and checksum.
+    pub fn from_bytes(bytes: [u8; EDID_BLOCK_SIZE]) -> Option<Self> {
+        let checksum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+        if bytes[..8] != EDID_HEADER || checksum != 0 {
+            return None;
+        }
+
+        Some(Self { bytes })
+    }
+
+    /// Returns the EDID block stored at `VESA_EDID_BUFFER` during the real mode stage, if it was
+    /// successfully read.
+    pub fn load() -> Option<Self> {
+        let bytes = unsafe { ptr::read(VESA_EDID_BUFFER as *const [u8; EDID_BLOCK_SIZE]) };
+
+        Self::from_bytes(bytes)
+    }
+
+    /// Three-letter PNP identifier of the manufacturer of the monitor.
+    pub fn manufacturer_id(&self) -> [u8; 3] {
+        let id = u16::from_be_bytes([self.bytes[8], self.bytes[9]]);
+
+        // Three 5-bit letters, 1 being 'A'.
+        [10, 5, 0].map(|shift| b'A' - 1 + ((id >> shift) & 0x1F) as u8)
+    }
+
+    /// Product code assigned by the manufacturer of the monitor.
+    pub fn product_code(&self) -> u16 {
+        u16::from_le_bytes([self.bytes[10], self.bytes[11]])
+    }
+
+    /// EDID version (major, minor).
+    pub fn version(&self) -> (u8, u8) {
+        (self.bytes[18], self.bytes[19])
+    }
+
+    /// Returns the native resolution of the monitor (width, height), from its preferred timing.
+    ///
+    /// Returns `None` if the first descriptor is not a detailed timing descriptor (its pixel clock is
+    /// zero).
+    pub fn native_resolution(&self) -> Option<(u16, u16)> {
+        let timing = &self.bytes[EDID_PREFERRED_TIMING..EDID_PREFERRED_TIMING + 18];
+
+        if timing[0] == 0 && timing[1] == 0 {
+            return None;
+        }
+
+        // The lower 8 bits of the active area are followed by the upper 4 bits, in the high nibble of
+        // a shared byte.
+        let width = u16::from(timing[2]) | (u16::from(timing[4] >> 4) << 8);
+        let height = u16::from(timing[5]) | (u16::from(timing[7] >> 4) << 8);
+
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        Some((width, height))
+    }
+}
@@ -47,10 +47,19 @@ pub const DEFAULT_BG_COLOR: RgbaColor = RgbaColor(26, 28, 34, 0);
 /// A `TextCursor` makes sure that we can track the current
 /// position of the cursor. Line switching , as well as carriage
 /// return are implemented by default.
+///
+/// An optional back buffer can be set (using `set_back_buffer`),
+/// in which case the text is rendered into that back buffer, and
+/// only the regions that changed are copied to the physical
+/// framebuffer when flushing.
 pub struct TextFrameBuffer<'b> {
     pub buffer: &'b mut [u8],
     pub cursor: TextCursor,
     pub metadata: FrameBufferMetadata,
+    back_buffer: Option<&'b mut [u8]>,
+    dirty: Option<DirtyRect>,
+    auto_flush: bool,
+    decoder: Utf8Decoder,
 }
 
 /// Locked version of the [`TextFrameBuffer`].
@@ -91,6 +100,27 @@ pub struct FrameBufferMetadata {
     pub bg_color: Option<RgbaColor>,
 }
 
+/// Region of the back buffer that was modified since the last
+/// flush, in pixels (`x1` and `y1` are excluded).
+#[derive(Clone, Copy)]
+struct DirtyRect {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
+
+impl DirtyRect {
+    fn union(self, other: Self) -> Self {
+        Self {
+            x0: self.x0.min(other.x0),
+            y0: self.y0.min(other.y0),
+            x1: self.x1.max(other.x1),
+            y1: self.y1.max(other.y1),
+        }
+    }
+}
+
 impl Default for TextCursor {
     fn default() -> Self {
         Self {
@@ -118,7 +148,7 @@ impl<'b> TextFrameBuffer<'b> {
         let buffer = unsafe {
             slice::from_raw_parts_mut(
                 info.framebuffer as *mut u8,
-                (info.bits_per_pixel as usize >> 3) * info.height as usize * info.width as usize,
+                info.bytes_per_scanline as usize * info.height as usize,
             )
         };
 
@@ -126,6 +156,10 @@ impl<'b> TextFrameBuffer<'b> {
             buffer,
             cursor: TextCursor::default(),
             metadata,
+            back_buffer: None,
+            dirty: None,
+            auto_flush: true,
+            decoder: Utf8Decoder::default(),
         };
 
         framebuffer.clear();
@@ -154,14 +188,15 @@ impl<'b> TextFrameBuffer<'b> {
             bytes_per_px: info.bpp as usize >> 3,
             width: info.width as usize,
             height: info.height as usize,
-            stride: usize::try_from(info.pitch).expect("invalid framebuffer pitch"),
+            stride: usize::try_from(info.pitch).expect("invalid framebuffer pitch")
+                / (info.bpp as usize >> 3),
             bg_color: Some(DEFAULT_BG_COLOR),
         };
 
         let buffer = unsafe {
             slice::from_raw_parts_mut(
                 mapping_addr.as_mut_ptr::<u8>(),
-                (info.bpp as usize >> 3) * info.height as usize * info.width as usize,
+                info.pitch as usize * info.height as usize,
             )
         };
 
@@ -169,6 +204,10 @@ impl<'b> TextFrameBuffer<'b> {
             buffer,
             cursor: TextCursor::default(),
             metadata,
+            back_buffer: None,
+            dirty: None,
+            auto_flush: true,
+            decoder: Utf8Decoder::default(),
         };
 
         framebuffer.clear();
@@ -176,28 +215,151 @@ impl<'b> TextFrameBuffer<'b> {
         framebuffer
     }
 
+    /// Size of the framebuffer, in bytes.
+    pub fn size(&self) -> usize {
+        self.pitch() * self.metadata.height
+    }
+
+    /// Renders the text into `back_buffer` rather than directly into the
+    /// physical framebuffer.
+    ///
+    /// The back buffer must be at least as large as the framebuffer (see
+    /// `size`). Its content is then copied to the framebuffer when calling
+    /// `flush` (or after each write, if auto-flush is enabled).
+    pub fn set_back_buffer(&mut self, back_buffer: &'b mut [u8]) {
+        let size = self.size();
+        back_buffer[..size].copy_from_slice(&self.buffer[..size]);
+
+        self.back_buffer = Some(back_buffer);
+        self.dirty = None;
+    }
+
+    /// Sets whether the back buffer is flushed after each write.
+    ///
+    /// Auto-flush is enabled by default, and should only be disabled if
+    /// `flush` is regularly called (for instance, once per frame).
+    pub fn set_auto_flush(&mut self, auto_flush: bool) {
+        self.auto_flush = auto_flush;
+        self.flush();
+    }
+
+    /// Copies the regions of the back buffer that changed since the last
+    /// flush to the physical framebuffer.
+    ///
+    /// Does nothing if there is no back buffer.
+    pub fn flush(&mut self) {
+        let (Some(back_buffer), Some(dirty)) = (&self.back_buffer, self.dirty.take()) else {
+            return;
+        };
+
+        let pitch = self.pitch();
+        let bpp = self.metadata.bytes_per_px;
+        let x1 = dirty.x1.min(self.metadata.width);
+        let y1 = dirty.y1.min(self.metadata.height);
+
+        for y in dirty.y0..y1 {
+            let row_range = (y * pitch + dirty.x0 * bpp)..(y * pitch + x1 * bpp);
+            self.buffer[row_range.clone()].copy_from_slice(&back_buffer[row_range]);
+        }
+    }
+
+    /// Fills a rectangle of the [`TextFrameBuffer`] with a given color.
+    ///
+    /// The rectangle is clipped to the dimensions of the framebuffer.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: RgbaColor) {
+        let bpp = self.metadata.bytes_per_px;
+        let stride = self.metadata.stride;
+        let x1 = (x + width).min(self.metadata.width);
+        let y1 = (y + height).min(self.metadata.height);
+
+        if x >= x1 || y >= y1 {
+            return;
+        }
+
+        let px_slice = match self.metadata.layout {
+            PixelLayout::RGB => [color.0, color.1, color.2, color.3],
+            PixelLayout::BGR => [color.2, color.1, color.0, color.3],
+        };
+
+        let target = self.target_mut();
+        for row in y..y1 {
+            let row_bytes = &mut target[((x + row * stride) * bpp)..((x1 + row * stride) * bpp)];
+
+            for chk in row_bytes.chunks_exact_mut(bpp) {
+                chk.copy_from_slice(&px_slice[..bpp]);
+            }
+        }
+
+        self.mark_dirty(x, y, x1 - x, y1 - y);
+        self.flush_if_auto();
+    }
+
+    /// Returns the color of the pixel at (`x`, `y`), as currently rendered.
+    ///
+    /// Reads from the back buffer if there is one, which avoids slow reads
+    /// from video memory. Returns `None` if the pixel is out of bounds, or
+    /// if pixels are less than 3 bytes wide.
+    pub fn read_pixel(&self, x: usize, y: usize) -> Option<RgbaColor> {
+        let bpp = self.metadata.bytes_per_px;
+
+        if x >= self.metadata.width || y >= self.metadata.height || bpp < 3 {
+            return None;
+        }
+
+        let source: &[u8] = match self.back_buffer {
+            Some(ref back_buffer) => back_buffer,
+            None => self.buffer,
+        };
+        let offset = (x + y * self.metadata.stride) * bpp;
+        let px = source.get(offset..offset + bpp)?;
+        let alpha = px.get(3).copied().unwrap_or(0);
+
+        Some(match self.metadata.layout {
+            PixelLayout::RGB => RgbaColor(px[0], px[1], px[2], alpha),
+            PixelLayout::BGR => RgbaColor(px[2], px[1], px[0], alpha),
+        })
+    }
+
     /// Write a string slice into the [`TextFrameBuffer`].
     pub fn write_str_with_color(&mut self, text: &str, color: &RgbaColor) {
         for c in text.chars() {
             self.putchar(c, Some(color));
         }
+        self.flush_if_auto();
+    }
+
+    /// Writes UTF-8 encoded text into the [`TextFrameBuffer`].
+    ///
+    /// The text may be received in several parts: a sequence split across two calls is decoded once complete.
+    /// Invalid sequences are displayed as `U+FFFD`.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        let mut decoder = self.decoder;
+
+        for &byte in bytes {
+            decoder.push(byte, |ch| self.putchar(ch, None));
+        }
+
+        self.decoder = decoder;
+        self.flush_if_auto();
     }
 
     pub fn write_str_bitmap(&mut self, text: &str) {
         for c in text.chars() {
             self.putchar_bitmap(c, false);
         }
+        self.flush_if_auto();
     }
 
     pub fn write_str_bitmap_reversed(&mut self, text: &str) {
         for c in text.chars() {
             self.putchar_bitmap(c, true);
         }
+        self.flush_if_auto();
     }
 
     pub fn write_str_bitmap_centered(&mut self, text: &str, reversed: bool) {
-        let text_width = text.len() * 8;
-        let remaining_width = self.metadata.width - text_width;
+        let text_width: usize = text.chars().map(bitmap_glyph_width).sum();
+        let remaining_width = self.metadata.width.saturating_sub(text_width);
 
         for _ in 0..remaining_width >> 4 {
             self.putchar_bitmap(' ', false);
@@ -210,6 +372,7 @@ impl<'b> TextFrameBuffer<'b> {
         for _ in 0..remaining_width >> 4 {
             self.putchar_bitmap(' ', false);
         }
+        self.flush_if_auto();
     }
 
     /// Prints a character in the `TextFrameBuffer`.
@@ -223,13 +386,20 @@ impl<'b> TextFrameBuffer<'b> {
                 if (self.cursor.x + CHAR_WIDTH) >= self.metadata.width {
                     self.newline();
                 }
-                if (self.cursor.y + CHAR_HEIGHT.val() + BORDER) >= self.metadata.height {
-                    self.clear();
+                while (self.cursor.y + CHAR_HEIGHT.val() + BORDER) >= self.metadata.height {
+                    self.scroll();
                 }
-                let rendered = render_char(ch);
-                match color {
-                    Some(color) => self.write_rasterized_char_with_color(rendered, color),
-                    None => self.write_rasterized_char(rendered),
+                match (render_char(ch), color) {
+                    (Some(rendered), Some(color)) => {
+                        self.write_rasterized_char_with_color(rendered, color)
+                    }
+                    (Some(rendered), None) => self.write_rasterized_char(rendered),
+                    (None, color) => self.write_missing_glyph(
+                        CHAR_WIDTH,
+                        CHAR_HEIGHT.val(),
+                        *color.unwrap_or(&RgbaColor(255, 255, 255, 0)),
+                        RgbaColor(0, 0, 0, 0),
+                    ),
                 }
             }
         }
@@ -240,17 +410,27 @@ impl<'b> TextFrameBuffer<'b> {
             '\n' => self.newline(),
             '\r' => self.carriage_return(),
             ch => {
-                if (self.cursor.x + CHAR_WIDTH) >= self.metadata.width {
+                let width = bitmap_glyph_width(ch);
+
+                if (self.cursor.x + width) >= self.metadata.width {
                     self.newline();
                 }
-                if (self.cursor.y + CHAR_HEIGHT.val() + BORDER) >= self.metadata.height {
-                    self.clear();
+                while (self.cursor.y + CHAR_HEIGHT.val() + BORDER) >= self.metadata.height {
+                    self.scroll();
                 }
-                if let Glyph::Halfwidth(rendered) = get_glyph(ch).unwrap() {
-                    if reversed {
-                        self.write_bitmap_char_reversed(rendered);
-                    } else {
-                        self.write_bitmap_char(rendered);
+                match get_glyph(ch) {
+                    Some(Glyph::Halfwidth(rows)) => {
+                        self.write_bitmap_char(rows.map(u16::from), 8, reversed)
+                    }
+                    Some(Glyph::Fullwidth(rows)) => self.write_bitmap_char(*rows, 16, reversed),
+                    None => {
+                        let (fg, bg) = if reversed { (0, 255) } else { (255, 0) };
+                        self.write_missing_glyph(
+                            width,
+                            16,
+                            RgbaColor(fg, fg, fg, 0),
+                            RgbaColor(bg, bg, bg, 0),
+                        );
                     }
                 }
             }
@@ -271,6 +451,7 @@ impl<'b> TextFrameBuffer<'b> {
                 self.write_px_with_color(self.cursor.x + x, self.cursor.y + y, rendered_color);
             }
         }
+        self.mark_dirty(self.cursor.x, self.cursor.y, char.width(), char.height());
         self.cursor.x += char.width() + CHAR_SPACING;
     }
 
@@ -282,31 +463,44 @@ impl<'b> TextFrameBuffer<'b> {
                 self.write_px_with_intensity(self.cursor.x + x, self.cursor.y + y, *intensity);
             }
         }
+        self.mark_dirty(self.cursor.x, self.cursor.y, char.width(), char.height());
         self.cursor.x += char.width() + CHAR_SPACING;
     }
 
-    fn write_bitmap_char(&mut self, char: &[u8; 16]) {
+    /// Pixel per pixel write of a bitmap char, `width` pixels wide (the most significant bit of each row being the
+    /// leftmost pixel).
+    fn write_bitmap_char(&mut self, char: [u16; 16], width: usize, reversed: bool) {
         for (y, row) in char.iter().enumerate() {
-            for (x, bit) in (0..8).enumerate() {
-                match *row & 1 << (7 - bit) {
-                    0 => self.write_px_with_intensity(self.cursor.x + x, self.cursor.y + y, 0),
-                    _ => self.write_px_with_intensity(self.cursor.x + x, self.cursor.y + y, 255),
-                }
+            for x in 0..width {
+                let set = *row & 1 << (width - 1 - x) != 0;
+                let intensity = if set != reversed { 255 } else { 0 };
+                self.write_px_with_intensity(self.cursor.x + x, self.cursor.y + y, intensity);
             }
         }
-        self.cursor.x += 8 + CHAR_SPACING;
+        self.mark_dirty(self.cursor.x, self.cursor.y, width, char.len());
+        self.cursor.x += width + CHAR_SPACING;
     }
 
-    fn write_bitmap_char_reversed(&mut self, char: &[u8; 16]) {
-        for (y, row) in char.iter().enumerate() {
-            for (x, bit) in (0..8).enumerate() {
-                match *row & 1 << (7 - bit) {
-                    0 => self.write_px_with_intensity(self.cursor.x + x, self.cursor.y + y, 255),
-                    _ => self.write_px_with_intensity(self.cursor.x + x, self.cursor.y + y, 0),
-                }
+    /// Writes a box in place of a character that has no glyph in the font, so that it stays visible (and keeps
+    /// its place) instead of corrupting the display.
+    fn write_missing_glyph(
+        &mut self,
+        width: usize,
+        height: usize,
+        color: RgbaColor,
+        background: RgbaColor,
+    ) {
+        for y in 0..height {
+            for x in 0..width {
+                let border = (x == 1 || x == width - 2) && (1..height - 1).contains(&y)
+                    || (y == 1 || y == height - 2) && (1..width - 1).contains(&x);
+
+                let px_color = if border { color } else { background };
+                self.write_px_with_color(self.cursor.x + x, self.cursor.y + y, px_color);
             }
         }
-        self.cursor.x += 8 + CHAR_SPACING;
+        self.mark_dirty(self.cursor.x, self.cursor.y, width, height);
+        self.cursor.x += width + CHAR_SPACING;
     }
 
     /// Write a pixel to the `TextFrameBuffer` given an intensity.
@@ -346,10 +540,11 @@ impl<'b> TextFrameBuffer<'b> {
                 color.3,
             ],
         };
-        let bytes_offset = (x + y * self.metadata.stride) * self.metadata.bytes_per_px;
+        let bytes_per_px = self.metadata.bytes_per_px;
+        let bytes_offset = (x + y * self.metadata.stride) * bytes_per_px;
 
-        self.buffer[bytes_offset..(bytes_offset + self.metadata.bytes_per_px)]
-            .copy_from_slice(&color_slice[..self.metadata.bytes_per_px]);
+        self.target_mut()[bytes_offset..(bytes_offset + bytes_per_px)]
+            .copy_from_slice(&color_slice[..bytes_per_px]);
     }
 
     /// Moves the cursor to the next line.
@@ -364,6 +559,37 @@ impl<'b> TextFrameBuffer<'b> {
         self.cursor.x = BORDER;
     }
 
+    /// Erases the character before the cursor, and moves the cursor back.
+    ///
+    /// The character must have been written using the default font, in which every character has the same width.
+    /// If the cursor is at the beginning of a line, the last character of the previous line is erased.
+    pub fn erase_char(&mut self) {
+        let char_width = CHAR_WIDTH + CHAR_SPACING;
+        let line_height = CHAR_HEIGHT.val() + LINE_SPACING;
+
+        if self.cursor.x < BORDER + char_width {
+            if self.cursor.y < BORDER + line_height {
+                return;
+            }
+
+            // Number of characters that fit on a line before `putchar` jumps to the next one.
+            let columns = (self.metadata.width - CHAR_WIDTH - BORDER).div_ceil(char_width);
+            self.cursor.y -= line_height;
+            self.cursor.x = BORDER + columns * char_width;
+        }
+
+        self.cursor.x -= char_width;
+
+        let bg_color = self.metadata.bg_color.unwrap_or(RgbaColor(0, 0, 0, 0));
+        self.fill_rect(
+            self.cursor.x,
+            self.cursor.y,
+            char_width,
+            CHAR_HEIGHT.val(),
+            bg_color,
+        );
+    }
+
     /// Clears the `TextFrameBuffer`.
     ///
     /// Resets the background to the background color if defined, or else
@@ -372,28 +598,109 @@ impl<'b> TextFrameBuffer<'b> {
         self.cursor.x = BORDER;
         self.cursor.y = BORDER;
 
-        // A background color was defined
-        if let Some(color) = self.metadata.bg_color {
-            let bpp = self.metadata.bytes_per_px;
-            let px_slice = match self.metadata.layout {
-                PixelLayout::RGB => [color.0, color.1, color.2, color.3],
-                PixelLayout::BGR => [color.2, color.1, color.0, color.3],
-            };
-            match bpp {
-                3 => {
-                    for chk in self.buffer.rchunks_exact_mut(3) {
-                        chk.copy_from_slice(&px_slice[..3]);
-                    }
-                }
-                4 => {
-                    for chk in self.buffer.rchunks_exact_mut(4) {
-                        chk.copy_from_slice(&px_slice);
-                    }
+        self.fill_rows(0, self.metadata.height);
+        self.flush_if_auto();
+    }
+
+    /// Scrolls the text up by one line, and moves the cursor to the
+    /// beginning of the last line.
+    ///
+    /// The content of the buffer is moved using a single copy, rather
+    /// than being drawn again.
+    fn scroll(&mut self) {
+        let line_height = CHAR_HEIGHT.val() + LINE_SPACING;
+        let height = self.metadata.height;
+        let pitch = self.pitch();
+
+        if line_height >= height {
+            return self.clear();
+        }
+
+        self.target_mut()
+            .copy_within((line_height * pitch)..(height * pitch), 0);
+        // Every row of the screen moved.
+        self.mark_dirty(0, 0, self.metadata.width, height);
+        self.fill_rows(height - line_height, height);
+
+        self.cursor.y = self.cursor.y.saturating_sub(line_height);
+        self.carriage_return();
+    }
+
+    /// Fills the rows `y0` to `y1` (excluded) with the background color if
+    /// defined, or else full black.
+    ///
+    /// Only the first row is filled pixel by pixel, and then copied to the
+    /// next ones.
+    fn fill_rows(&mut self, y0: usize, y1: usize) {
+        if y0 >= y1 {
+            return;
+        }
+
+        let bpp = self.metadata.bytes_per_px;
+        let pitch = self.pitch();
+        let row_len = self.metadata.width * bpp;
+        let px_slice = match (self.metadata.bg_color, self.metadata.layout) {
+            (Some(color), PixelLayout::RGB) => [color.0, color.1, color.2, color.3],
+            (Some(color), PixelLayout::BGR) => [color.2, color.1, color.0, color.3],
+            (None, _) => [0; 4],
+        };
+
+        let target = self.target_mut();
+        let first_row = &mut target[(y0 * pitch)..(y0 * pitch + row_len)];
+
+        match bpp {
+            3 | 4 => {
+                for chk in first_row.chunks_exact_mut(bpp) {
+                    chk.copy_from_slice(&px_slice[..bpp]);
                 }
-                _ => self.buffer.fill(0),
             }
-        } else {
-            self.buffer.fill(0);
+            _ => first_row.fill(0),
+        }
+
+        for y in (y0 + 1)..y1 {
+            target.copy_within((y0 * pitch)..(y0 * pitch + row_len), y * pitch);
+        }
+
+        self.mark_dirty(0, y0, self.metadata.width, y1 - y0);
+    }
+
+    /// Number of bytes in between the beginning of two consecutive rows.
+    fn pitch(&self) -> usize {
+        self.metadata.stride * self.metadata.bytes_per_px
+    }
+
+    /// Returns the buffer into which the text is rendered: the back buffer
+    /// if there is one, the physical framebuffer otherwise.
+    fn target_mut(&mut self) -> &mut [u8] {
+        match self.back_buffer {
+            Some(ref mut back_buffer) => back_buffer,
+            None => &mut *self.buffer,
+        }
+    }
+
+    /// Records that a region of the back buffer was modified, and must be
+    /// copied to the physical framebuffer during the next flush.
+    fn mark_dirty(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        if self.back_buffer.is_none() {
+            return;
+        }
+
+        let rect = DirtyRect {
+            x0: x,
+            y0: y,
+            x1: x + width,
+            y1: y + height,
+        };
+
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(rect),
+            None => rect,
+        });
+    }
+
+    fn flush_if_auto(&mut self) {
+        if self.auto_flush {
+            self.flush();
         }
     }
 
@@ -407,13 +714,74 @@ impl<'b> Write for TextFrameBuffer<'b> {
         for ch in s.chars() {
             self.putchar(ch, None);
         }
+        self.flush_if_auto();
         Ok(())
     }
 }
 
 // Get the [`RasterizedChar`] from a raw `char`.
-fn render_char(ch: char) -> RasterizedChar {
-    get_raster(ch, FontWeight::Regular, CHAR_HEIGHT).unwrap_or_else(|| render_char('�'))
+//
+// Returns `None` if the font has no glyph for this character.
+fn render_char(ch: char) -> Option<RasterizedChar> {
+    get_raster(ch, FontWeight::Regular, CHAR_HEIGHT)
+}
+
+/// Width of the bitmap glyph of a raw `char`, in pixels.
+fn bitmap_glyph_width(ch: char) -> usize {
+    match get_glyph(ch) {
+        Some(Glyph::Fullwidth(_)) => 16,
+        _ => 8,
+    }
+}
+
+/// Incremental UTF-8 decoder, for text received in several parts.
+///
+/// Invalid, overlong or truncated sequences are decoded as [`char::REPLACEMENT_CHARACTER`].
+#[derive(Clone, Copy, Default)]
+struct Utf8Decoder {
+    /// Bits of the code point decoded so far.
+    code_point: u32,
+
+    /// Number of continuation bytes still expected.
+    remaining: u8,
+
+    /// Smallest code point that can be encoded with the length of the current sequence.
+    min: u32,
+}
+
+impl Utf8Decoder {
+    /// Feeds a byte to the decoder, calling `emit` for each decoded character.
+    fn push(&mut self, byte: u8, mut emit: impl FnMut(char)) {
+        if self.remaining > 0 {
+            if byte & 0xC0 == 0x80 {
+                self.code_point = self.code_point << 6 | u32::from(byte & 0x3F);
+                self.remaining -= 1;
+
+                if self.remaining == 0 {
+                    let ch =
+                        char::from_u32(self.code_point).filter(|_| self.code_point >= self.min);
+                    emit(ch.unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                return;
+            }
+
+            // Truncated sequence, the byte is decoded on its own.
+            self.remaining = 0;
+            emit(char::REPLACEMENT_CHARACTER);
+        }
+
+        let (code_point, remaining, min) = match byte {
+            0x00..=0x7F => return emit(char::from(byte)),
+            0xC0..=0xDF => (byte & 0x1F, 1, 0x80),
+            0xE0..=0xEF => (byte & 0x0F, 2, 0x800),
+            0xF0..=0xF7 => (byte & 0x07, 3, 0x10000),
+            _ => return emit(char::REPLACEMENT_CHARACTER),
+        };
+
+        self.code_point = u32::from(code_point);
+        self.remaining = remaining;
+        self.min = min;
+    }
 }
 
 /// `RgbaColor` holds the color data for a pixel. Rgba is used as
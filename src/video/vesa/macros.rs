@@ -1,17 +1,19 @@
 //! General purpose macros for text output.
+//!
+//! Every macro writes through the [`log`](crate::log) facade, to all the active sinks (framebuffer, serial port) as
+//! well as to the kernel log ring buffer.
 
 use crate::video::vesa::framebuffer::RgbaColor;
 
 /// Base color when displaying context
 pub const CTX_COLOR: RgbaColor = RgbaColor(234, 190, 124, 0);
 
-/// Base color when displaying errors
+/// Base color when displaying errors (see [`LogLevel::color`](crate::log::LogLevel::color)).
 pub const ERR_COLOR: RgbaColor = RgbaColor(239, 35, 60, 0);
 
 /// Prints to the output, and append a new line.
 ///
-/// Writes into the shared [`TextFrameBuffer`].
-/// It acquires the lock for the duration of the write.
+/// Writes to every active sink of the [`log`](crate::log) facade, without any level.
 ///
 /// # Panics
 ///
@@ -28,41 +30,79 @@ pub const ERR_COLOR: RgbaColor = RgbaColor(239, 35, 60, 0);
 macro_rules! println {
 
     () => {
-        $crate::video::vesa::print("\n");
+        $crate::log::write(format_args!("\n"));
     };
 
     ($($arg: tt)*) => {{
-        $crate::video::vesa::arg_print(format_args_nl!($($arg)*))
+        $crate::log::write(format_args_nl!($($arg)*))
     }};
 }
 
 /// Prints an error message to the output, and append a new line.
 ///
-/// Writes into the shared [`TextFrameBuffer`].
-/// It acquires the lock for the duration of the write operation.
+/// # Examples
+/// ```
+/// use fzboot::eprintln;
+///
+/// eprintln!("failed to initialize paging");
+/// ```
+#[deprecated(note = "use `error!` instead")]
+#[macro_export]
+macro_rules! eprintln {
+    ($($arg: tt)*) => {
+        $crate::log::log($crate::log::LogLevel::Error, None, format_args!($($arg)*))
+    };
+}
+
+/// Logs an error message.
+///
+/// You can specify a 'context' as the first argument when
+/// calling the macro, which will be inserted at the beginning
+/// of the error message.
+///
+/// Switches the boot progress to the verbose log (see
+/// [`report_error`](crate::boot::progress::report_error)).
 ///
 /// # Panics
 ///
-/// Panics if called before initialiazing the shared [`TextFrameBuffer`]
+/// Panics if called before initialiazing the shared [`TextFrameBuffer`].
 ///
 /// # Examples
+///
 /// ```
-/// use fzboot::eprintln;
+/// use fzboot::error;
 ///
-/// eprintln!("failed to initialize paging");
+/// error!("paging", "failed to initialize paging");
 /// ```
-#[allow_internal_unstable(format_args_nl)]
 #[macro_export]
-macro_rules! eprintln {
+macro_rules! error {
+    ($($arg: tt)*) => {
+        $crate::__log!($crate::log::LogLevel::Error, $($arg)*)
+    };
+}
+
+/// Logs a warning message.
+///
+/// You can specify a 'context' as the first argument when
+/// calling the macro, which will be inserted at the beginning
+/// of the message.
+///
+/// # Examples
+///
+/// ```
+/// use fzboot::warn;
+///
+/// warn!("ahci", "port {} did not answer", 2);
+/// ```
+#[macro_export]
+macro_rules! warn {
     ($($arg: tt)*) => {
-        $crate::video::vesa::print("error: ");
-        $crate::video::vesa::arg_print(format_args_nl!($($arg)*))
+        $crate::__log!($crate::log::LogLevel::Warn, $($arg)*)
     };
 }
 
-/// Prints a standard information message to the output.
+/// Logs a standard information message.
 ///
-/// Writes into the shared [`TextFrameBuffer`].
 /// You can specify a 'context' as the first argument when
 /// calling the macro, which will be inserted at the beginning
 /// of the message.
@@ -78,54 +118,43 @@ macro_rules! eprintln {
 ///
 /// info!("paging", "paging enabled");
 /// ```
-#[allow_internal_unstable(format_args_nl)]
 #[macro_export]
 macro_rules! info {
-    // A context was provided, so we insert it at the beginning of
-    // the message.
-    ($ctx: literal, $($arg: tt)*) => {
-        $crate::video::vesa::print("[info] ");
-        $crate::video::vesa::print_colored($ctx, &$crate::video::vesa::macros::CTX_COLOR);
-        $crate::video::vesa::print(" : ");
-        $crate::video::vesa::arg_print(format_args_nl!($($arg)*))
-    };
     ($($arg: tt)*) => {
-        $crate::video::vesa::print("[info] ");
-        $crate::video::vesa::arg_print(format_args_nl!($($arg)*))
+        $crate::__log!($crate::log::LogLevel::Info, $($arg)*)
     };
 }
 
-/// Prints a standard error message to the output.
+/// Logs a debugging message, only displayed if the log level allows it (see
+/// [`set_max_level`](crate::log::set_max_level)).
 ///
-/// Writes into the shared [`TextFrameBuffer`].
 /// You can specify a 'context' as the first argument when
 /// calling the macro, which will be inserted at the beginning
-/// of the error message.
-///
-/// # Panics
-///
-/// Panics if called before initialiazing the shared [`TextFrameBuffer`].
+/// of the message.
 ///
 /// # Examples
 ///
 /// ```
-/// use fzboot::error;
+/// use fzboot::debug;
 ///
-/// error!("paging", "failed to initialize paging");
+/// debug!("ext4", "reading block {}", 42);
 /// ```
-#[allow_internal_unstable(format_args_nl)]
 #[macro_export]
-macro_rules! error {
+macro_rules! debug {
+    ($($arg: tt)*) => {
+        $crate::__log!($crate::log::LogLevel::Debug, $($arg)*)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log {
     // A context was provided, so we insert it at the beginning of
     // the message.
-    ($ctx: literal, $($arg: tt)*) => {
-        $crate::video::vesa::print_colored("[error] ", &$crate::video::vesa::macros::ERR_COLOR);
-        $crate::video::vesa::print_colored($ctx, &$crate::video::vesa::macros::CTX_COLOR);
-        $crate::video::vesa::print(" : ");
-        $crate::video::vesa::arg_print(format_args_nl!($($arg)*))
+    ($level: expr, $ctx: literal, $($arg: tt)*) => {
+        $crate::log::log($level, Some($ctx), format_args!($($arg)*))
     };
-    ($($arg: tt)*) => {
-        $crate::video::vesa::print("[error] ");
-        $crate::video::vesa::arg_print(format_args_nl!($($arg)*))
+    ($level: expr, $($arg: tt)*) => {
+        $crate::log::log($level, None, format_args!($($arg)*))
     };
 }
@@ -11,41 +11,78 @@
 //! when entering protected mode, as well as general
 //! purpose macros to write formatted text to the screen.
 
+#[cfg(feature = "alloc")]
+use alloc::string::String;
 use conquer_once::spin::OnceCell;
 use core::fmt::{self, Write};
-use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "alloc")]
+use spin::Mutex;
 
 use crate::boot::multiboot::mb_information::FramebufferMultibootInformation;
+#[cfg(all(feature = "alloc", not(any(feature = "real", feature = "x86_64"))))]
+use crate::errors::{BiosError, CanFail};
+use crate::klog::{klog_write, klog_write_fmt};
 use crate::mem::VirtAddr;
+#[cfg(feature = "alloc")]
+use crate::time::timer;
 use crate::video::vesa::framebuffer::{LockedTextFrameBuffer, RgbaColor, TextFrameBuffer};
-use crate::video::vesa::video_mode::{ModeInfoBlock, VESA_MODE_BUFFER};
+use crate::video::vesa::video_mode::vesa_mode_info;
 use crate::x86::paging::{get_memory_mapper, PageTableFlags};
 
 #[macro_use]
 pub mod video_mode;
+pub mod edid;
 pub mod framebuffer;
 pub mod macros;
 
 static TEXT_BUFFER: OnceCell<LockedTextFrameBuffer> = OnceCell::uninit();
 
+/// Maximum size of the output kept in memory while it is captured, in bytes.
+pub const CAPTURED_OUTPUT_MAX_LEN: usize = 0x10000;
+
+static OUTPUT_CAPTURED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "alloc")]
+static CAPTURED_OUTPUT: Mutex<String> = Mutex::new(String::new());
+
+/// Interval between two flushes of the back buffer (60 frames per second).
+#[cfg(feature = "alloc")]
+const FRAME_INTERVAL_US: u64 = 16_667;
+
 pub fn text_buffer() -> &'static LockedTextFrameBuffer<'static> {
     TEXT_BUFFER.try_get().unwrap()
 }
 
 pub fn init_text_buffer_from_vesa() {
     TEXT_BUFFER.try_init_once(|| {
-        let vesamode_info_ptr = VESA_MODE_BUFFER as *mut ModeInfoBlock;
-        let vesamode_info = unsafe { ptr::read(vesamode_info_ptr) };
-        let framebuffer = TextFrameBuffer::from_vesamode_info(&vesamode_info);
+        let framebuffer = TextFrameBuffer::from_vesamode_info(vesa_mode_info());
 
         LockedTextFrameBuffer::new(framebuffer)
     });
 }
 
+/// Switches to the VESA video mode `mode` from protected mode (see [`vesa_select_mode`]), and resets the shared
+/// [`TextFrameBuffer`] to the layout of the new mode.
+///
+/// Must be called before the back buffer is enabled (see [`enable_text_back_buffer`]). The screen is cleared.
+///
+/// # Errors
+///
+/// See [`vesa_select_mode`].
+///
+/// [`vesa_select_mode`]: video_mode::vesa_select_mode
+#[cfg(all(feature = "alloc", not(any(feature = "real", feature = "x86_64"))))]
+pub fn vesa_switch_mode(mode: u16) -> CanFail<BiosError> {
+    video_mode::vesa_select_mode(mode)?;
+    *text_buffer().buffer.lock() = TextFrameBuffer::from_vesamode_info(vesa_mode_info());
+
+    Ok(())
+}
+
 pub fn init_text_buffer_from_multiboot(header: FramebufferMultibootInformation) {
     let framebuffer_addr = header.addr;
-    let framebuffer_size =
-        (header.bpp as usize >> 3) * header.height as usize * header.width as usize;
+    let framebuffer_size = header.pitch as usize * header.height as usize;
     let mapping_addr = VirtAddr::new(0xFFFF_D800_0000_000);
 
     let page_flags = PageTableFlags::new()
@@ -68,13 +105,90 @@ pub fn init_text_buffer_from_multiboot(header: FramebufferMultibootInformation)
     });
 }
 
+/// Renders the shared [`TextFrameBuffer`] into a back buffer allocated on the heap.
+///
+/// If the timer queue is available, the back buffer is flushed once per frame
+/// rather than after each write, so that verbose logging does not redraw the
+/// framebuffer more often than it is displayed.
+///
+/// # Panics
+///
+/// Panics if called before the shared buffer was initialized.
+#[cfg(feature = "alloc")]
+pub fn enable_text_back_buffer() {
+    use alloc::vec;
+    use core::time::Duration;
+
+    let mut framebuffer = text_buffer().buffer.lock();
+    let back_buffer = vec![0u8; framebuffer.size()].leak();
+    framebuffer.set_back_buffer(back_buffer);
+    drop(framebuffer);
+
+    if timer::periodic(Duration::from_micros(FRAME_INTERVAL_US), flush_frame).is_ok() {
+        text_buffer().buffer.lock().set_auto_flush(false);
+    }
+}
+
+/// Flushes the back buffer of the shared [`TextFrameBuffer`], unless it is
+/// currently being written to.
+#[cfg(feature = "alloc")]
+fn flush_frame() {
+    if let Some(mut framebuffer) = text_buffer().buffer.try_lock() {
+        framebuffer.flush();
+    }
+}
+
+/// Sets whether the text output is captured rather than displayed.
+///
+/// While captured, everything printed through [`print`], [`arg_print`] and
+/// [`print_colored`] is kept in memory (up to [`CAPTURED_OUTPUT_MAX_LEN`]
+/// bytes), and displayed once the capture ends. The framebuffer can still be
+/// drawn to directly (for instance, to display a splash screen).
+pub fn set_output_captured(captured: bool) {
+    if OUTPUT_CAPTURED.swap(captured, Ordering::AcqRel) == captured || captured {
+        return;
+    }
+
+    #[cfg(feature = "alloc")]
+    {
+        // The captured output was already written to the kernel log.
+        let output = core::mem::take(&mut *CAPTURED_OUTPUT.lock());
+        display_str(&output, None);
+    }
+}
+
+/// Checks if the text output is currently captured (see [`set_output_captured`]).
+pub fn output_captured() -> bool {
+    OUTPUT_CAPTURED.load(Ordering::Acquire)
+}
+
+/// Keeps `args` in memory if the output is captured.
+///
+/// Returns `false` if the output is not captured, and should be displayed.
+fn capture_output(args: fmt::Arguments) -> bool {
+    if !output_captured() {
+        return false;
+    }
+
+    #[cfg(feature = "alloc")]
+    {
+        let mut output = CAPTURED_OUTPUT.lock();
+        if output.len() < CAPTURED_OUTPUT_MAX_LEN {
+            let _ = output.write_fmt(args);
+        }
+    }
+
+    true
+}
+
 /// Prints a formatted text input to the shared [`TextFrameBuffer`].
 ///
 /// # Panics
 ///
 /// Panics if called before the shared buffer was initialized.
 pub fn arg_print(args: fmt::Arguments) {
-    text_buffer().buffer.lock().write_fmt(args).unwrap();
+    klog_write_fmt(args);
+    display_fmt(args);
 }
 
 /// Prints a string slice to the shared [`TextFrameBuffer`]
@@ -83,7 +197,8 @@ pub fn arg_print(args: fmt::Arguments) {
 ///
 /// Panics if called before the shared buffer was initialized
 pub fn print(str: &str) {
-    text_buffer().buffer.lock().write_str(str).unwrap();
+    klog_write(str);
+    display_str(str, None);
 }
 
 /// Prints a string slice to the shared [`TextFrameBuffer`],
@@ -94,13 +209,40 @@ pub fn print(str: &str) {
 ///
 /// Panics if called before the shared buffer was initialized
 pub fn print_colored(str: &str, color: &RgbaColor) {
-    text_buffer().buffer.lock().write_str_with_color(str, color)
+    klog_write(str);
+    display_str(str, Some(color));
+}
+
+/// Displays a formatted text on the shared [`TextFrameBuffer`] (or captures it), without writing it to the kernel
+/// log.
+pub(crate) fn display_fmt(args: fmt::Arguments) {
+    if capture_output(args) {
+        return;
+    }
+
+    text_buffer().buffer.lock().write_fmt(args).unwrap();
+}
+
+/// Displays a string slice on the shared [`TextFrameBuffer`] (or captures it), without writing it to the kernel log.
+pub(crate) fn display_str(str: &str, color: Option<&RgbaColor>) {
+    if capture_output(format_args!("{str}")) {
+        return;
+    }
+
+    let mut buffer = text_buffer().buffer.lock();
+    match color {
+        Some(color) => buffer.write_str_with_color(str, color),
+        None => buffer.write_str(str).unwrap(),
+    }
 }
 
 /// Changes the VESA video mode to the closest one given
 /// conditions (for now, only width and height). Only
 /// keeps video mode that are based on a linear framebuffer.
 ///
+/// Returns the number of the selected mode, which is also kept
+/// in memory at `VESA_MODE_NUMBER`.
+///
 /// This can only run in a real mode execution context, or
 /// using a vm86 monitor.
 ///
@@ -116,63 +258,38 @@ pub fn print_colored(str: &str, color: &RgbaColor) {
 /// at `VESA_VBE_BUFFER` address.
 
 #[cfg(feature = "real")]
-pub fn vesa_mode_setup(x: u16, y: u16) {
+pub fn vesa_mode_setup(x: u16, y: u16) -> u16 {
     use crate::video::vesa::video_mode::*;
-    use core::{cmp::Ordering, mem};
+    use core::{cmp::Ordering, mem, ptr};
 
     let mut best_mode: u16 = 1;
     let mut best_diff: u32 = u32::max_value();
     let mut best_bpp: u8 = 0;
 
-    let vbe_info_blk = video_mode::real_query_vbeinfo().unwrap();
-    let modes = video_mode::VesaVideoModes::new(vbe_info_blk);
-
     // Iterate over all available modes returned
-    for mode in modes {
-        if let Some(mode_info) = video_mode::real_query_modeinfo(mode) {
-            // We need to make sure the mode uses a linear framebuffer.
-            // Bit 7 of the `mode_attributes` equals 1 if a linear
-            // framebuffer is available.
-            // We also make sure that the mode is a graphic mode (bit
-            // 4 of the `mode_attributes`)
-            if mode_info.mode_attributes & (VBE_MODEATTR_LINEAR | VBE_MODEATTR_GRAPHIC)
-                != (VBE_MODEATTR_LINEAR | VBE_MODEATTR_GRAPHIC)
-            {
-                continue;
-            }
+    for (mode, mode_info) in usable_modes() {
+        // We compute the distance between 2 modes by comparing their euclidean
+        // distance to the ideal mode.
+        let px_diff = u32::from(mode_info.width.abs_diff(x)).pow(2)
+            + u32::from(mode_info.height.abs_diff(y)).pow(2);
 
-            // We only support packed pixel memory model or direct color,
-            // so we skip any display mode that does not use one of these.
-            match mode_info.memory_model {
-                MemoryModel::PackedPixel | MemoryModel::DirectColor => {}
-                _ => {
-                    continue;
-                }
+        match px_diff.cmp(&best_diff) {
+            // We found a better fit
+            Ordering::Less => {
+                best_mode = mode;
+                best_diff = px_diff;
+                best_bpp = mode_info.bits_per_pixel;
             }
-
-            // We compute the distance between 2 modes by comparing their euclidean
-            // distance to the ideal mode.
-            let px_diff = (mode_info.width as u32 - x as u32).pow(2)
-                + (mode_info.height as u32 - y as u32).pow(2);
-
-            match px_diff.cmp(&best_diff) {
-                // We found a better fit
-                Ordering::Less => {
+            // In case of equality, we (for now) choose the mode with the
+            // highest bits per pixel count.
+            Ordering::Equal => {
+                if mode_info.bits_per_pixel > best_bpp {
                     best_mode = mode;
                     best_diff = px_diff;
                     best_bpp = mode_info.bits_per_pixel;
                 }
-                // In case of equality, we (for now) choose the mode with the
-                // highest bits per pixel count.
-                Ordering::Equal => {
-                    if mode_info.bits_per_pixel > best_bpp {
-                        best_mode = mode;
-                        best_diff = px_diff;
-                        best_bpp = mode_info.bits_per_pixel;
-                    }
-                }
-                Ordering::Greater => {}
             }
+            Ordering::Greater => {}
         }
     }
 
@@ -189,7 +306,44 @@ pub fn vesa_mode_setup(x: u16, y: u16) {
         (VESA_VBE_BUFFER as usize + mem::size_of::<VbeInfoBlock>()) as *mut ModeInfoBlock;
     unsafe {
         *info_buffer_ptr = best_info;
+        ptr::write(VESA_MODE_NUMBER as *mut u16, best_mode);
     }
+
+    best_mode
+}
+
+/// Returns an iterator over the available VESA video modes that we
+/// can use, along with their `ModeInfoBlock`.
+///
+/// This can only run in a real mode execution context, or
+/// using a vm86 monitor.
+#[cfg(feature = "real")]
+fn usable_modes() -> impl Iterator<Item = (u16, ModeInfoBlock)> {
+    use crate::video::vesa::video_mode::*;
+
+    let vbe_info_blk = video_mode::real_query_vbeinfo().unwrap();
+
+    video_mode::VesaVideoModes::new(vbe_info_blk).filter_map(|mode| {
+        let mode_info = video_mode::real_query_modeinfo(mode)?;
+
+        // We need to make sure the mode uses a linear framebuffer.
+        // Bit 7 of the `mode_attributes` equals 1 if a linear
+        // framebuffer is available.
+        // We also make sure that the mode is a graphic mode (bit
+        // 4 of the `mode_attributes`)
+        if mode_info.mode_attributes & (VBE_MODEATTR_LINEAR | VBE_MODEATTR_GRAPHIC)
+            != (VBE_MODEATTR_LINEAR | VBE_MODEATTR_GRAPHIC)
+        {
+            return None;
+        }
+
+        // We only support packed pixel memory model or direct color,
+        // so we skip any display mode that does not use one of these.
+        match mode_info.memory_model {
+            MemoryModel::PackedPixel | MemoryModel::DirectColor => Some((mode, mode_info)),
+            _ => None,
+        }
+    })
 }
 
 #[macro_export]
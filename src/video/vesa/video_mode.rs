@@ -1,12 +1,22 @@
 //! VBE display mode utilities
 
-use core::{arch::asm, ptr};
+use core::ptr;
+use core::sync::atomic::{AtomicU16, Ordering};
 
 use core::mem;
 
+#[cfg(all(feature = "alloc", not(any(feature = "real", feature = "x86_64"))))]
+use alloc::boxed::Box;
+use spin::RwLock;
+
+#[cfg(all(feature = "alloc", not(any(feature = "real", feature = "x86_64"))))]
+use crate::errors::BiosError;
+
 use crate::{
     errors::{CanFail, VideoError},
+    mem::bump::early_box,
     vbe_const,
+    video::vesa::edid::EDID_BLOCK_SIZE,
 };
 
 /// In-memory location of the [`VbeInfoBlock`] header
@@ -16,6 +26,21 @@ pub const VESA_VBE_BUFFER: u16 = 0x5000;
 // selected display mode.
 pub const VESA_MODE_BUFFER: u16 = 0x5000 + mem::size_of::<VbeInfoBlock>() as u16;
 
+/// In-memory location of the EDID base block of the monitor (see [`Edid`]).
+///
+/// [`Edid`]: crate::video::vesa::edid::Edid
+pub const VESA_EDID_BUFFER: u16 = VESA_MODE_BUFFER + mem::size_of::<ModeInfoBlock>() as u16;
+
+/// In-memory location of the number of the selected display mode.
+pub const VESA_MODE_NUMBER: u16 = VESA_EDID_BUFFER + EDID_BLOCK_SIZE as u16;
+
+/// Copy of the [`ModeInfoBlock`] of the selected display mode (see [`vesa_mode_snapshot`]), replaced when switching
+/// to another mode from protected mode.
+static VESA_MODE_INFO: RwLock<Option<&'static ModeInfoBlock>> = RwLock::new(None);
+
+/// Number of the selected display mode (see [`vesa_mode_snapshot`]).
+static VESA_MODE_SELECTED: AtomicU16 = AtomicU16::new(0);
+
 vbe_const!(VBE_RET_SUPPORTED, 0x4f);
 vbe_const!(VBE_RET_SUCCESS, 0x00);
 vbe_const!(VBE_SUCCESS, (VBE_RET_SUCCESS << 8) | VBE_RET_SUPPORTED);
@@ -69,31 +94,10 @@ pub struct VbeInfoBlock {
 /// monitor.
 #[cfg(feature = "real")]
 pub fn real_set_vesa_mode(mode: u16) -> CanFail<VideoError> {
-    use crate::rerror;
-
-    let result: u16;
-
-    // INT 10H
-    // VBE 02h call: Set VBE Mode
-    //
-    // Input:  AX = 0x4f02
-    //         BX = Desired mode
-    //
-    // Output: AX = VBE return status
-    unsafe {
-        asm!(
-        "push ebx",
-        "mov ax, 0x4f02",
-        "mov bx, cx",
-        "int 0x10",
-        "pop ebx",
-        in("cx") mode,
-        out("ax") result
-        );
-    }
+    use crate::{bios::services::video::vbe_set_mode, early_fmt::Hex, rerror_fmt};
 
-    if result != VBE_SUCCESS {
-        rerror!("Failed to set VESA mode");
+    if let Err(err) = vbe_set_mode(mode) {
+        rerror_fmt!("Failed to set VESA mode ", Hex(mode), ": ", err);
         return Err(VideoError::VesaError);
     }
 
@@ -111,37 +115,12 @@ pub fn real_set_vesa_mode(mode: u16) -> CanFail<VideoError> {
 /// monitor.
 #[cfg(feature = "real")]
 pub fn real_query_modeinfo(mode: u16) -> Option<ModeInfoBlock> {
+    use crate::bios::services::video::vbe_mode_info;
+
     let mut mode_info: ModeInfoBlock = unsafe { mem::zeroed() };
-    let mode_info_ptr: *mut ModeInfoBlock = unsafe { &mut mode_info };
-    let result: u16;
-
-    // INT 10H
-    // VBE 01h call: Return VBE Mode Information
-    //
-    // Input:  AX = 0x4f01
-    //         CX = Mode number
-    //         ES:DI = Pointer to `ModeInfoBlock` structure
-    //
-    // Output: AX = VBE return status
-    unsafe {
-        asm!(
-            "push es",
-            "push di",
-            "mov di, ax",
-            "xor ax, ax",
-            "mov es, ax",
-            "mov ax, 0x4f01",
-            "int 0x10",
-            "pop di",
-            "pop es",
-            in("cx") mode,
-            inout("ax") mode_info_ptr as u16 => result
-        );
-    }
+    let mode_info_ptr: *mut ModeInfoBlock = &mut mode_info;
 
-    if result != VBE_SUCCESS {
-        return None;
-    }
+    vbe_mode_info(mode, mode_info_ptr as usize as u16).ok()?;
 
     Some(mode_info)
 }
@@ -157,6 +136,8 @@ pub fn real_query_modeinfo(mode: u16) -> Option<ModeInfoBlock> {
 /// monitor.
 #[cfg(feature = "real")]
 pub fn real_query_vbeinfo() -> Option<&'static VbeInfoBlock> {
+    use crate::bios::services::video::vbe_controller_info;
+
     // Set the `vbe_signature` field to 'VBE2' in order to
     // query VBE 2.0 informations.
     let pre_sig: *const u8 = "VBE2".as_bytes().as_ptr();
@@ -165,45 +146,111 @@ pub fn real_query_vbeinfo() -> Option<&'static VbeInfoBlock> {
         ptr::write_volatile(VESA_VBE_BUFFER as *mut [u8; 4], *(pre_sig as *mut [u8; 4]));
     }
 
-    let result: u16;
-
-    // INT 10H
-    // VBE 00h call: Return VBE Controller Information
-    //
-    // Input:  AX = 0x4f00
-    //         ES:DI = Pointer to a `VbeInfoBlock`
-    //                 structure
-    //
-    // The `VbeSignature` field should be 'VBE2' when
-    // the block is 512 bytes in size.
-    //
-    // Output: AX = VBE return status
-    unsafe {
-        asm!(
-            "push es",
-            "push di",
-            "mov di, ax",
-            "xor ax, ax",
-            "mov es, ax",
-            "mov ax, 0x4f00",
-            "int 0x10",
-            "pop di",
-            "pop es",
-            inout("ax") VESA_VBE_BUFFER => result
-        );
+    vbe_controller_info(VESA_VBE_BUFFER).ok()?;
+
+    let vbe_info: &VbeInfoBlock = unsafe { mem::transmute(VESA_VBE_BUFFER as *mut VbeInfoBlock) };
+
+    Some(vbe_info)
+}
+
+/// Copies the information about the display mode selected in real mode out of the fixed low-memory buffers
+/// ([`VESA_MODE_BUFFER`] and [`VESA_MODE_NUMBER`]), into the early arena.
+///
+/// Must be called once the `.bss` section was cleared, before anything else is allocated in low memory.
+///
+/// # Panics
+///
+/// Panics if the early arena is exhausted or sealed.
+pub fn vesa_mode_snapshot() {
+    let mode_info = unsafe { ptr::read(VESA_MODE_BUFFER as *const ModeInfoBlock) };
+    *VESA_MODE_INFO.write() =
+        Some(early_box(mode_info).expect("no space left for the VESA mode information"));
+
+    let mode_number = unsafe { ptr::read(VESA_MODE_NUMBER as *const u16) };
+    VESA_MODE_SELECTED.store(mode_number, Ordering::Release);
+}
+
+/// Returns the [`ModeInfoBlock`] of the selected display mode.
+///
+/// # Panics
+///
+/// Panics if called before [`vesa_mode_snapshot`].
+pub fn vesa_mode_info() -> &'static ModeInfoBlock {
+    VESA_MODE_INFO
+        .read()
+        .expect("VESA mode information used before being copied")
+}
+
+/// Returns the number of the selected display mode, `0` if unknown.
+pub fn vesa_mode_number() -> u16 {
+    VESA_MODE_SELECTED.load(Ordering::Acquire)
+}
+
+/// Switches to the VESA video mode `mode` from protected mode, through the real-mode bridge (see [`bios_call`]).
+///
+/// The mode is set up with a linear framebuffer. Returns the [`ModeInfoBlock`] of the new mode: the selected mode
+/// (see [`vesa_mode_info`]) is not updated, use [`vesa_select_mode`] instead.
+///
+/// # Errors
+///
+/// Returns [`BiosError::Unsupported`] if the mode has no linear framebuffer, or any error returned by
+/// [`bios_call`] or by the VBE functions.
+///
+/// [`bios_call`]: crate::bios::bridge::bios_call
+#[cfg(all(feature = "alloc", not(any(feature = "real", feature = "x86_64"))))]
+pub fn vesa_set_mode(mode: u16) -> Result<ModeInfoBlock, BiosError> {
+    use crate::bios::bridge::{bios_call, BiosRegisters, REAL_BRIDGE_BUFFER};
+
+    // VBE 01h call: Return VBE Mode Information, in the bridge buffer.
+    let mut regs = BiosRegisters {
+        eax: 0x4f01,
+        ecx: u32::from(mode),
+        edi: REAL_BRIDGE_BUFFER,
+        ..Default::default()
+    };
+    bios_call(0x10, &mut regs)?;
+    if regs.eax as u16 != VBE_SUCCESS {
+        return Err(BiosError::VbeFailed(regs.eax as u16));
     }
 
-    if result != VBE_SUCCESS {
-        return None;
+    let mode_info = unsafe { ptr::read(REAL_BRIDGE_BUFFER as *const ModeInfoBlock) };
+    if mode_info.mode_attributes & VBE_MODEATTR_LINEAR == 0 {
+        return Err(BiosError::Unsupported);
     }
 
-    let vbe_info: &VbeInfoBlock = unsafe { mem::transmute(VESA_VBE_BUFFER as *mut VbeInfoBlock) };
+    // VBE 02h call: Set VBE Mode, with the linear framebuffer (bit 14 of the mode).
+    let mut regs = BiosRegisters {
+        eax: 0x4f02,
+        ebx: u32::from(mode | 0x4000),
+        ..Default::default()
+    };
+    bios_call(0x10, &mut regs)?;
+    if regs.eax as u16 != VBE_SUCCESS {
+        return Err(BiosError::VbeFailed(regs.eax as u16));
+    }
 
-    Some(vbe_info)
+    Ok(mode_info)
+}
+
+/// Switches to the VESA video mode `mode` from protected mode (see [`vesa_set_mode`]), and makes it the selected
+/// display mode.
+///
+/// # Errors
+///
+/// See [`vesa_set_mode`]. The selected display mode is left unchanged on error.
+#[cfg(all(feature = "alloc", not(any(feature = "real", feature = "x86_64"))))]
+pub fn vesa_select_mode(mode: u16) -> CanFail<BiosError> {
+    let mode_info = vesa_set_mode(mode)?;
+
+    *VESA_MODE_INFO.write() = Some(Box::leak(Box::new(mode_info)));
+    VESA_MODE_SELECTED.store(mode, Ordering::Release);
+
+    Ok(())
 }
 
 /// Mode information block that contains technical details
 /// relative to a specific display mode.
+#[derive(Clone, Copy)]
 #[repr(C, align(256))]
 pub struct ModeInfoBlock {
     // These bits describe the main characteristics
@@ -282,6 +329,7 @@ pub enum PixelLayout {
 }
 
 /// Memory organization used type used for a display mode.
+#[derive(Clone, Copy)]
 #[repr(u8)]
 pub enum MemoryModel {
     TextMode = 0,
@@ -3,12 +3,13 @@
 //! With the [`LocalAPIC`], they are an evolution of the old `PIC` chip. It manages the interrupt issued by I/O devices.
 //! It also provides multiprocessor interrupt management through 24 programmable interrupts (_ISA_, _PCI_, ...)
 
+use crate::io::acpi::madt::{MadtIntFlags, MadtIoApic, MadtTable};
 use crate::mem::{LocklessCell, MemoryAddress, PhyAddr32};
 use crate::x86::apic::local_apic::{
     DeliveryMode, DeliveryStatus, DestinationMode, InterruptVector, PinPolarity, ProcLocalApicID,
     TriggerMode,
 };
-use crate::x86::apic::mp_table::{IOApicIntPin, MPIOApicEntry, MPIOInterruptEntry, MPTable};
+use crate::x86::apic::mp_table::{IOApicIntPin, MPIOApicEntry, MPTable};
 use alloc::vec::Vec;
 use conquer_once::spin::OnceCell;
 use hashbrown::HashMap;
@@ -172,6 +173,35 @@ impl RedTblRegister {
     }
 }
 
+/// Electrical characteristics of the interrupt signal connected to a pin of the `I/O APIC`.
+///
+/// Described by the _MP Table_ or by the ACPI _MADT_. Pins without a route are edge triggered and active high.
+#[derive(Clone, Copy, Debug)]
+struct PinRoute {
+    pin: IOApicIntPin,
+    delivery_mode: DeliveryMode,
+    pin_polarity: PinPolarity,
+    trigger_mode: TriggerMode,
+}
+
+impl From<MadtIntFlags> for PinPolarity {
+    fn from(value: MadtIntFlags) -> Self {
+        match value.active_low() {
+            Some(true) => Self::ActiveLow,
+            _ => Self::ActiveHigh,
+        }
+    }
+}
+
+impl From<MadtIntFlags> for TriggerMode {
+    fn from(value: MadtIntFlags) -> Self {
+        match value.level_triggered() {
+            Some(true) => Self::Level,
+            _ => Self::Edge,
+        }
+    }
+}
+
 /// `I/O APIC` (_I/O Advanced Programmable Interrupt Controller_) implementation main structure.
 ///
 /// It manages the interrupt issued by I/O devices.
@@ -181,23 +211,105 @@ pub(crate) struct IOApic {
     base_addr: PhyAddr32,
     ioregsel: MMIOApicRegister,
     iowin: MMIOApicRegister,
-    mp_int_entries: Vec<MPIOInterruptEntry>,
+    routes: Vec<PinRoute>,
+
+    /// ISA interrupts connected to a pin other than their own number, as `(isa_irq, pin)`.
+    isa_overrides: Vec<(u8, IOApicIntPin)>,
     i8259_pin: Option<IOApicIntPin>,
 }
 
 impl IOApic {
+    /// Initializes an `I/O APIC` described by the _MP Table_.
     pub(crate) fn init(entry: MPIOApicEntry, table: &MPTable) {
+        let routes = table
+            .get_int_to_io_apic(entry.ioapic_id)
+            .iter()
+            .map(|int_entry| PinRoute {
+                pin: int_entry.dest_ioapic_intin,
+                delivery_mode: DeliveryMode::from(int_entry.int_type),
+                pin_polarity: PinPolarity::from(int_entry.int_mode.polarity()),
+                trigger_mode: TriggerMode::from(int_entry.int_mode.trigger_mode()),
+            })
+            .collect();
+
+        let lapic_ids: Vec<ProcLocalApicID> = table
+            .get_processors()
+            .iter()
+            .map(|proc| proc.lapic_id)
+            .collect();
+
+        Self::setup(
+            entry.ioapic_id.into(),
+            entry.addr,
+            routes,
+            // default PIT override
+            alloc::vec![(0, IOApicIntPin::from(2))],
+            &lapic_ids,
+        );
+    }
+
+    /// Initializes an `I/O APIC` described by the ACPI _MADT_.
+    ///
+    /// The ISA interrupts are routed according to the _interrupt source overrides_ of the table, and identity-mapped
+    /// otherwise.
+    pub(crate) fn init_from_madt(entry: MadtIoApic, madt: &MadtTable) {
+        let addr = PhyAddr32::new(entry.addr);
+        let mut routes = Vec::new();
+        let mut isa_overrides = Vec::new();
+
+        for iso in madt.interrupt_overrides() {
+            let Some(pin) = iso.gsi.checked_sub(entry.gsi_base) else {
+                continue;
+            };
+            let Ok(pin) = u8::try_from(pin) else {
+                continue;
+            };
+
+            routes.push(PinRoute {
+                pin: IOApicIntPin::from(pin),
+                delivery_mode: DeliveryMode::Fixed,
+                pin_polarity: PinPolarity::from(iso.flags),
+                trigger_mode: TriggerMode::from(iso.flags),
+            });
+
+            if iso.source != pin {
+                isa_overrides.push((iso.source, IOApicIntPin::from(pin)));
+            }
+        }
+
+        let lapic_ids: Vec<ProcLocalApicID> = madt
+            .processors()
+            .map(|lapic| ProcLocalApicID::from(lapic.apic_id))
+            .collect();
+
+        Self::setup(
+            ProcLocalApicID::from(entry.id),
+            addr,
+            routes,
+            isa_overrides,
+            &lapic_ids,
+        );
+    }
+
+    fn setup(
+        id: ProcLocalApicID,
+        addr: PhyAddr32,
+        routes: Vec<PinRoute>,
+        isa_overrides: Vec<(u8, IOApicIntPin)>,
+        lapic_ids: &[ProcLocalApicID],
+    ) {
         let mut io_apic = Self {
-            id: entry.ioapic_id.into(),
-            base_addr: entry.addr,
-            ioregsel: MMIOApicRegister(entry.addr),
-            iowin: MMIOApicRegister(entry.addr + 0x10_usize),
-            mp_int_entries: table.get_int_to_io_apic(entry.ioapic_id),
+            id,
+            base_addr: addr,
+            ioregsel: MMIOApicRegister(addr),
+            iowin: MMIOApicRegister(addr + 0x10_usize),
+            routes,
+            isa_overrides,
             i8259_pin: None,
         };
 
         io_apic.i8259_pin = io_apic.find_i8259_pin();
-        io_apic.check_ioapic_id(table);
+        io_apic.check_ioapic_id(lapic_ids);
         io_apic.initialize_redtbl();
         io_apic.unmask_all();
 
@@ -315,22 +427,19 @@ impl IOApic {
     /// When an interrupt is issued on the input pin, it will dispatch a interrupt to the `Local APIC` of the _BSP_,
     /// which the vector specified in the redirection entry.
     ///
-    /// Interrupts type ([`PinPolarity`], [`TriggerMode`], ...) match the one contained in the _MP Table_ if
-    /// available, or fallbacks to a default entry (Edge triggered, active high).
+    /// Interrupts type ([`PinPolarity`], [`TriggerMode`], ...) match the one contained in the _MP Table_ or the
+    /// _MADT_ if available, or fallbacks to a default entry (Edge triggered, active high).
     pub(crate) fn map_pin_to_irq(&self, pin: IOApicIntPin, vector: InterruptVector) {
-        let int_entry = self
-            .mp_int_entries
-            .iter()
-            .find(|entry| entry.dest_ioapic_intin == pin);
+        let route = self.routes.iter().find(|route| route.pin == pin);
 
-        if let Some(int_entry) = int_entry {
+        if let Some(route) = route {
             self.write_redirection_entry(&RedTblRegister {
                 id: u8::from(pin),
                 entry: RedTblEntry::new()
                     .with_vector(vector)
-                    .with_delivery_mode(DeliveryMode::from(int_entry.int_type))
-                    .with_pin_polarity(PinPolarity::from(int_entry.int_mode.polarity()))
-                    .with_trigger_mode(TriggerMode::from(int_entry.int_mode.trigger_mode()))
+                    .with_delivery_mode(route.delivery_mode)
+                    .with_pin_polarity(route.pin_polarity)
+                    .with_trigger_mode(route.trigger_mode)
                     .with_destination_mode(DestinationMode::Physical),
             })
         } else {
@@ -404,14 +513,24 @@ impl IOApic {
     ///
     /// Maps all pins to system IRQs, using the pin number + 32 (as the first 32 IRQs are reserved on _Intel_
     /// platforms).
-    /// The default `PIT` overrides is implemented, and pin 2 is redirected to IRQ 32, instead of 34.
+    /// Pins connected to an overridden ISA interrupt are redirected to the IRQ of that interrupt instead (the `PIT`
+    /// is usually connected to pin 2, and redirected to IRQ 32 instead of 34).
     fn initialize_redtbl(&self) {
         for entry in 1..=self
             .read_register::<IOApicVersion>()
             .maximum_redirection_entry()
         {
-            if entry == 2 {
-                self.map_pin_to_irq(IOApicIntPin::from(entry), InterruptVector::from(0x20));
+            let isa_irq = self
+                .isa_overrides
+                .iter()
+                .find(|(_, pin)| u8::from(*pin) == entry)
+                .map(|(isa_irq, _)| *isa_irq);
+
+            if let Some(isa_irq) = isa_irq {
+                self.map_pin_to_irq(
+                    IOApicIntPin::from(entry),
+                    InterruptVector::from(isa_irq + 0x20),
+                );
                 continue;
             }
 
@@ -426,18 +545,9 @@ impl IOApic {
     ///
     /// Each `APIC`-related device communicating on the `APIC Bus` must have a unique identifier ([`ProcLocalApicID`]).
     /// `I/O APIC` id must be determined after having assigned an ID to each `Local APIC` on the system.
-    fn check_ioapic_id(&mut self, table: &MPTable) {
-        let mut valid_id = true;
-        for proc in table.get_processors() {
-            if proc.lapic_id == self.id {
-                self.id += 1;
-                valid_id = false;
-                break;
-            }
-        }
-
-        if !valid_id {
-            self.check_ioapic_id(table);
+    fn check_ioapic_id(&mut self, lapic_ids: &[ProcLocalApicID]) {
+        while lapic_ids.contains(&self.id) {
+            self.id += 1;
         }
 
         self.write_register(IOApicId::new().with_io_apic_id(u8::from(self.id)));
@@ -7,12 +7,16 @@
 
 #![allow(clippy::as_conversions)]
 
+use crate::errors::{ApicError, Context, KError};
+use crate::io::acpi::madt::madt;
+use crate::io::pic::PIC;
 use crate::io::{outb, IOPort};
 use crate::mem::{LocklessCell, MemoryAddress, PhyAddr32};
-use crate::x86::apic::io_apic::IOApic;
+use crate::x86::apic::io_apic::{get_all_io_apics, IOApic};
 use crate::x86::apic::mp_table::{MPInterruptType, MPLocalApicIntPin, MPTable};
 use crate::x86::cpuid::cpu_id;
 use crate::x86::int::{disable_interrupts, enable_interrupts, interrupts_disabled};
+use crate::x86::kvm::pv_eoi_ack;
 use crate::x86::msr::Ia32ApicBase;
 use bytemuck::{Contiguous, Pod, Zeroable};
 use conquer_once::spin::OnceCell;
@@ -32,21 +36,43 @@ static LOCAL_APICS: OnceCell<LocklessCell<HashMap<ProcLocalApicID, LocklessCell<
 /// The underlying structure is lock-free, as it can only be accessed by one processor at a time, as this can
 /// only return the local apic of the current CPU.
 /// Initializes the [`LocalAPIC`] if that was not done already.
-#[allow(clippy::missing_panics_doc)]
+///
+/// Use [`try_local_apic`] to find out why the `LocalAPIC` is not available.
 pub fn local_apic() -> Option<&'static mut LocalAPIC> {
-    let apics = LOCAL_APICS
-        .try_get_or_init(|| LocklessCell::new(HashMap::new()))
-        .ok()?;
+    try_local_apic().ok()
+}
+
+/// Returns the [`LocalAPIC`] associated with the current processor, initializing it if that was not done already.
+///
+/// # Errors
+///
+/// Returns the [`ApicError`] raised while initializing the `LocalAPIC` (see [`LocalAPIC::init`]).
+#[allow(clippy::missing_panics_doc)]
+pub fn try_local_apic() -> Result<&'static mut LocalAPIC, KError> {
+    let apics = LOCAL_APICS.get_or_init(|| LocklessCell::new(HashMap::new()));
 
     if let Some(lapic) = apics.get().get(&ProcLocalApicID::get()) {
-        Some(lapic.get())
-    } else {
-        apics.get().insert(
-            ProcLocalApicID::get(),
-            LocklessCell::new(LocalAPIC::init().ok()?),
-        );
-        Some(apics.get().get(&ProcLocalApicID::get()).unwrap().get())
+        return Ok(lapic.get());
     }
+
+    let lapic = LocalAPIC::init().context("initializing the local APIC")?;
+    apics
+        .get()
+        .insert(ProcLocalApicID::get(), LocklessCell::new(lapic));
+
+    Ok(apics.get().get(&ProcLocalApicID::get()).unwrap().get())
+}
+
+/// Returns the [`LocalAPIC`] associated with the current processor, if it was already initialized.
+///
+/// Contrary to [`local_apic`], this never switches the processor to the `APIC`.
+pub fn local_apic_initialized() -> Option<&'static mut LocalAPIC> {
+    let apics = LOCAL_APICS.get()?;
+
+    apics
+        .get()
+        .get(&ProcLocalApicID::get())
+        .map(|lapic| lapic.get())
 }
 
 /// Local APIC unique identifier.
@@ -56,14 +82,14 @@ pub fn local_apic() -> Option<&'static mut LocalAPIC> {
 /// systems.
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash, Pod, Zeroable)]
 #[repr(transparent)]
-pub(crate) struct ProcLocalApicID(u8);
+pub struct ProcLocalApicID(u8);
 
 impl ProcLocalApicID {
     /// This identifier is reserved, and used to indicate every `LocalAPIC` on the system.
     pub(crate) const ALL_LAPIC: Self = Self(0xFF);
 
     /// Returns the `LocalAPIC` identifier fo the current processor, using the _CPUID_ instruction.
-    pub(crate) fn get() -> Self {
+    pub fn get() -> Self {
         Self(cpu_id(0x1).unwrap()[1].to_le_bytes()[3])
     }
 }
@@ -94,6 +120,8 @@ pub(crate) struct LocalAPICRegisterOffset(u32);
 impl LocalAPICRegisterOffset {
     const VERSION_REGISTER: Self = Self(0x30);
 
+    const TASK_PRIORITY_REGISTER: Self = Self(0x80);
+
     const EOI_REGISTER: Self = Self(0xB0);
 
     const ERROR_REGISTER: Self = Self(0x280);
@@ -241,6 +269,29 @@ pub(crate) enum PinPolarity {
 #[repr(transparent)]
 pub(crate) struct VectorPriorityClass(u8);
 
+impl VectorPriorityClass {
+    /// Priority class for devices that tolerate a high interrupt latency.
+    pub(crate) const DEVICE_LOW: Self = Self(0x4);
+
+    /// Default priority class for devices.
+    pub(crate) const DEVICE: Self = Self(0x7);
+
+    /// Priority class for latency-sensitive devices (timers, ...).
+    pub(crate) const DEVICE_HIGH: Self = Self(0xC);
+
+    /// Returns the priority class `class` (from 2 to 15).
+    pub(crate) const fn new(class: u8) -> Self {
+        assert!(class >= 2 && class < 16, "invalid vector priority class");
+
+        Self(class)
+    }
+
+    /// Returns the interrupt vectors of this priority class.
+    pub(crate) fn vectors(self) -> impl Iterator<Item = InterruptVector> {
+        ((self.0 << 4)..=((self.0 << 4) | 0xf)).map(InterruptVector)
+    }
+}
+
 /// Interrupt vector relative priority.
 ///
 /// Each interrupt-priority class regroups 16 vectors, and the relative priority of each vector in a given class
@@ -260,6 +311,8 @@ pub struct InterruptVector(u8);
 impl InterruptVector {
     /// Spurious vector interrupt vector.
     pub(super) const SPURIOUS_VECTOR: Self = Self(0xFF);
+    /// Vector of the _LVT Error_ entry, raised when the `LocalAPIC` detects an error.
+    pub(crate) const APIC_ERROR_VECTOR: Self = Self(0xFE);
     pub(crate) const TIMER_IRQ: Self = Self(0x20);
 
     pub const fn new(vector: u8) -> Self {
@@ -545,6 +598,7 @@ pub(crate) struct ApicLVT {
 /// It indicates any error detected during interrupt handling. Must be written to to update its content, before
 /// attempting to read its value.
 #[bitfield]
+#[derive(Clone, Copy, Debug)]
 #[repr(u32)]
 pub(crate) struct LocalAPICErrorRegister {
     /// Checksum error for a message sent on the _APIC_ bus.
@@ -575,6 +629,42 @@ pub(crate) struct LocalAPICErrorRegister {
     __: B24,
 }
 
+impl LocalAPICErrorRegister {
+    /// Whether no error is reported.
+    pub(crate) fn is_empty(self) -> bool {
+        u32::from(self) == 0
+    }
+
+    /// Returns a short description of each error reported.
+    pub(crate) fn errors(self) -> impl Iterator<Item = &'static str> {
+        [
+            (self.send_chksum_error(), "send checksum error"),
+            (self.receive_chksum_error(), "receive checksum error"),
+            (self.send_accept_error(), "send accept error"),
+            (self.receive_accept_error(), "receive accept error"),
+            (self.redirectable_ipi(), "redirectable IPI"),
+            (self.send_illegal_vector(), "send illegal vector"),
+            (self.received_illegal_vector(), "received illegal vector"),
+            (self.illegal_register_address(), "illegal register address"),
+        ]
+        .into_iter()
+        .filter_map(|(set, error)| set.then_some(error))
+    }
+}
+
+impl core::fmt::Display for LocalAPICErrorRegister {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (i, error) in self.errors().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            f.write_str(error)?;
+        }
+
+        Ok(())
+    }
+}
+
 /// Describes the various operating mode for the [`LocalApic`].
 ///
 /// The system may run in different interrupt mode, each one using the [`LocalAPIC`] differently.
@@ -608,18 +698,31 @@ pub struct LocalAPIC {
     version_register: LocalAPICVersionRegister,
     lvt: ApicLVT,
     svr: LocalAPICSpuriousVectorRegister,
-    mp_table: MPTable,
+    mp_table: Option<MPTable>,
     operating_mode: APICOperatingMode,
     interrupt_cmd: LocalAPICInterruptCmdRegister,
 }
 
 impl LocalAPIC {
-    pub fn init() -> Result<Self, ()> {
+    /// Switches the current processor to its _Local APIC_, and sets up the I/O APICs if it is the `BSP`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ApicError::NoInterruptTable`] if neither the `MADT` nor the _MP Configuration Table_ is available,
+    /// or [`ApicError::Unsupported`] if the processor has no _Local APIC_.
+    pub fn init() -> Result<Self, ApicError> {
+        let madt = madt();
+        let mp_table = MPTable::load();
+
+        if madt.is_none() && mp_table.is_none() {
+            return Err(ApicError::NoInterruptTable);
+        }
+
+        let msr_register = Ia32ApicBase::read().ok_or(ApicError::Unsupported)?;
         let interrupts_disabled = interrupts_disabled();
         disable_interrupts();
-        let mp_table = MPTable::load().ok_or(())?;
 
-        let operating_mode = if mp_table.imcr_present() {
+        let operating_mode = if mp_table.as_ref().is_some_and(MPTable::imcr_present) {
             APICOperatingMode::PIC
         } else {
             APICOperatingMode::VirtualWire
@@ -627,7 +730,7 @@ impl LocalAPIC {
 
         let mut local_apic = Self {
             apic_id: ProcLocalApicID::get(),
-            msr_register: Ia32ApicBase::read().ok_or(())?,
+            msr_register,
             version_register: LocalAPICVersionRegister::from(0),
             lvt: ApicLVT::default(),
             svr: LocalAPICSpuriousVectorRegister::default(),
@@ -643,8 +746,19 @@ impl LocalAPIC {
 
         // setup I/O APIC if this processor is the BSP
         if local_apic.msr_register.is_bsp() {
-            for io_apic in local_apic.mp_table.get_io_apic() {
-                IOApic::init(io_apic, &local_apic.mp_table);
+            if let Some(madt) = madt {
+                for io_apic in madt.io_apics() {
+                    IOApic::init_from_madt(io_apic, madt);
+                }
+            } else if let Some(mp_table) = &local_apic.mp_table {
+                for io_apic in mp_table.get_io_apic() {
+                    IOApic::init(io_apic, mp_table);
+                }
+            }
+
+            // external interrupts are now delivered by the I/O APIC
+            if get_all_io_apics().is_some_and(|io_apics| !io_apics.is_empty()) {
+                PIC::default().disable();
             }
         }
 
@@ -689,16 +803,72 @@ impl LocalAPIC {
         self.msr_register.global_enable();
     }
 
+    /// Sets the task priority of the processor: interrupts whose priority class (upper 4 bits of the vector) is lower
+    /// or equal to the class of `priority` are held pending.
+    ///
+    /// Returns the previous task priority.
+    pub(crate) fn set_task_priority(&self, priority: u8) -> u8 {
+        let previous = self.read_reg(LocalAPICRegisterOffset::TASK_PRIORITY_REGISTER) as u8;
+        self.write_reg(
+            LocalAPICRegisterOffset::TASK_PRIORITY_REGISTER,
+            u32::from(priority),
+        );
+
+        previous
+    }
+
     /// Updates the `EOI` (_End of Interrupt_) register upon interrupt completion.
+    ///
+    /// Nothing is written if the hypervisor did not require it (see [`pv_eoi_ack`]).
     pub(crate) fn send_eoi(&self) {
+        if pv_eoi_ack() {
+            return;
+        }
+
         self.write_reg(LocalAPICRegisterOffset::EOI_REGISTER, 0);
     }
 
+    /// Delivers performance counter overflows to this processor as `NMIs`, or masks them if `enabled` is false.
+    ///
+    /// The entry is masked by the processor every time the interrupt is delivered, and must be enabled again.
+    pub(crate) fn set_perf_counter_nmi(&mut self, enabled: bool) {
+        self.lvt.perf_count = self
+            .lvt
+            .perf_count
+            .with_delivery_mode(DeliveryMode::NonMaskableInterrupt)
+            .with_vector(InterruptVector(0))
+            .with_masked(!enabled);
+
+        self.write_reg(
+            LocalAPICRegisterOffset::PERF_COUNT_REGISTER,
+            self.lvt.perf_count.into(),
+        );
+    }
+
+    /// Delivers the errors detected by this `LocalAPIC` as an interrupt on [`InterruptVector::APIC_ERROR_VECTOR`], or
+    /// masks them if `enabled` is false.
+    ///
+    /// The errors reported so far are cleared beforehand.
+    pub(crate) fn set_error_interrupt(&mut self, enabled: bool) {
+        self.read_error_register();
+
+        self.lvt.error = self
+            .lvt
+            .error
+            .with_vector(InterruptVector::APIC_ERROR_VECTOR)
+            .with_masked(!enabled);
+
+        self.write_reg(
+            LocalAPICRegisterOffset::LVT_ERR_REGISTER,
+            self.lvt.error.into(),
+        );
+    }
+
     /// Reads the [`LocalAPICErrorRegister`] from the corresponding _APIC_ register.
     ///
     /// It indicates any error detected during interrupt handling. Must be written to to update its content, before
     /// attempting to read its value.
-    pub(super) fn read_error_register(&self) -> LocalAPICErrorRegister {
+    pub(crate) fn read_error_register(&self) -> LocalAPICErrorRegister {
         self.write_reg(LocalAPICRegisterOffset::ERROR_REGISTER, 0);
         self.read_reg(LocalAPICRegisterOffset::ERROR_REGISTER)
             .into()
@@ -718,7 +888,9 @@ impl LocalAPIC {
     /// Disconnects the `LocalApic`, switching back to either `PIC` or `Virtual Wire` mode, depending on what is
     /// available on the system.
     pub(crate) fn disconnect_apic(&mut self) {
-        if self.mp_table.imcr_present() {
+        PIC::default().enable();
+
+        if self.imcr_present() {
             self.switch_to_pic_mode();
             self.operating_mode = APICOperatingMode::PIC;
             return;
@@ -728,6 +900,14 @@ impl LocalAPIC {
         self.operating_mode = APICOperatingMode::VirtualWire;
     }
 
+    /// Whether the system has an `IMCR` (_Interrupt Mode Configuration Register_), used to switch between `PIC` and
+    /// `APIC` mode.
+    ///
+    /// Only reported by the _MP Table_.
+    fn imcr_present(&self) -> bool {
+        self.mp_table.as_ref().is_some_and(MPTable::imcr_present)
+    }
+
     /// Issues an [`IPI`] (_Interprocessor Interrupt_) from this `Local APIC`.
     ///
     /// Writes the requested `IPI` to the `ICR` (_Interrupt Command Register_), using two 32-bits writes.
@@ -775,7 +955,7 @@ impl LocalAPIC {
     /// In `PIC Mode`, the `APIC` components are bypassed, and the interrupt signals that reach the BSP come from
     /// the master `PIC` instead of the `LocalAPIC`.
     fn switch_to_pic_mode(&mut self) {
-        if self.mp_table.imcr_present() {
+        if self.imcr_present() {
             outb(IOPort::IMCR_ADDR, 0x70);
             outb(IOPort::IMCR_DATA, 0x0);
         }
@@ -787,7 +967,7 @@ impl LocalAPIC {
     /// In `PIC Mode`, the `APIC` components are bypassed, and the interrupt signals that reach the BSP come from
     /// the master `PIC` instead of the `LocalAPIC`.
     fn switch_from_pic_mode(&mut self) {
-        if self.mp_table.imcr_present() {
+        if self.imcr_present() {
             outb(IOPort::IMCR_ADDR, 0x70);
             outb(IOPort::IMCR_DATA, 0x1);
         }
@@ -816,34 +996,49 @@ impl LocalAPIC {
             .with_masked(true)
             .with_vector(InterruptVector(0xFD));
 
-        // weird stuff happening here
-        let lapic_lintin1_entry = self
-            .mp_table
-            .get_local_int_connected_to_pin(self.apic_id, MPLocalApicIntPin::LINTIN_1);
-        let lapic_lintin0_entry = self
-            .mp_table
-            .get_local_int_connected_to_pin(self.apic_id, MPLocalApicIntPin::LINTIN_0);
-
-        if let (Some(lintin0_int), Some(lintin1_int)) = (lapic_lintin0_entry, lapic_lintin1_entry) {
-            match (lintin0_int.int_type, lintin1_int.int_type) {
-                (MPInterruptType::External, MPInterruptType::NonMaskable) => {
-                    lint0.set_delivery_mode(DeliveryMode::ExternalInterrupt);
-                    lint1.set_delivery_mode(DeliveryMode::NonMaskableInterrupt);
-                }
-                (MPInterruptType::Vectored, MPInterruptType::NonMaskable) => {
-                    lint1.set_delivery_mode(DeliveryMode::NonMaskableInterrupt);
+        let madt_nmi = madt().and_then(|madt| madt.local_apic_nmis(u8::from(self.apic_id)).next());
+
+        if let Some(nmi) = madt_nmi {
+            let (nmi_lint, ext_lint) = if nmi.lint == 0 {
+                (&mut lint0, &mut lint1)
+            } else {
+                (&mut lint1, &mut lint0)
+            };
+
+            nmi_lint.set_delivery_mode(DeliveryMode::NonMaskableInterrupt);
+            nmi_lint.set_pin_polarity(PinPolarity::from(nmi.flags));
+            nmi_lint.set_trigger_mode(TriggerMode::from(nmi.flags));
+            ext_lint.set_delivery_mode(DeliveryMode::ExternalInterrupt);
+        } else if let Some(mp_table) = &self.mp_table {
+            // weird stuff happening here
+            let lapic_lintin1_entry =
+                mp_table.get_local_int_connected_to_pin(self.apic_id, MPLocalApicIntPin::LINTIN_1);
+            let lapic_lintin0_entry =
+                mp_table.get_local_int_connected_to_pin(self.apic_id, MPLocalApicIntPin::LINTIN_0);
+
+            if let (Some(lintin0_int), Some(lintin1_int)) =
+                (lapic_lintin0_entry, lapic_lintin1_entry)
+            {
+                match (lintin0_int.int_type, lintin1_int.int_type) {
+                    (MPInterruptType::External, MPInterruptType::NonMaskable) => {
+                        lint0.set_delivery_mode(DeliveryMode::ExternalInterrupt);
+                        lint1.set_delivery_mode(DeliveryMode::NonMaskableInterrupt);
+                    }
+                    (MPInterruptType::Vectored, MPInterruptType::NonMaskable) => {
+                        lint1.set_delivery_mode(DeliveryMode::NonMaskableInterrupt);
+                    }
+                    (MPInterruptType::NonMaskable, MPInterruptType::External) => {
+                        lint0.set_delivery_mode(DeliveryMode::NonMaskableInterrupt);
+                        lint1.set_delivery_mode(DeliveryMode::ExternalInterrupt);
+                    }
+                    _ => {}
                 }
-                (MPInterruptType::NonMaskable, MPInterruptType::External) => {
-                    lint0.set_delivery_mode(DeliveryMode::NonMaskableInterrupt);
-                    lint1.set_delivery_mode(DeliveryMode::ExternalInterrupt);
-                }
-                _ => {}
             }
         }
 
         let error = LVTErrorEntry::new()
             .with_masked(true)
-            .with_vector(InterruptVector(0xFE));
+            .with_vector(InterruptVector::APIC_ERROR_VECTOR);
 
         let perf_count = LVTPerformanceCounterEntry::new()
             .with_delivery_mode(DeliveryMode::Fixed)
@@ -1172,6 +1367,21 @@ impl IPI {
         }
     }
 
+    /// Generates a _NMI_ (Non-Maskable Interrupt) broadcast `IPI` message.
+    ///
+    /// Delivers a `NMI` to every processor, except the issuer.
+    pub(crate) fn broadcast_others_nmi() -> Self {
+        Self {
+            vector: InterruptVector(0),
+            delivery_mode: IPIDeliveryMode::NonMaskable,
+            destination_mode: DestinationMode::Physical,
+            level: IPILevel::Assert,
+            trigger_mode: TriggerMode::Edge,
+            destination_shorthand: IPIDestinationShorthand::AllButSelf,
+            destination: 0,
+        }
+    }
+
     /// Generates an _INIT_ request `IPI` message.
     ///
     /// Delivers an `INIT` request to the destination processor, specified in the `destination field`.
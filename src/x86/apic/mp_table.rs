@@ -9,10 +9,9 @@
 
 use crate::mem::{get_physical_memory32, MemoryAddress, PhyAddr32};
 use crate::x86::apic::local_apic::{DeliveryMode, PinPolarity, ProcLocalApicID, TriggerMode};
-use alloc::string::String;
 use alloc::vec::Vec;
 use bytemuck::{bytes_of, from_bytes, try_from_bytes, Pod, Zeroable};
-use core::fmt::{Debug, Formatter};
+use core::fmt::{Debug, Display, Formatter, Write};
 use core::mem::size_of;
 use core::slice;
 use modular_bitfield::prelude::{B22, B24, B4, B6, B7};
@@ -172,15 +171,15 @@ impl MPBusType {
     }
 }
 
-impl From<MPBusType> for String {
-    fn from(value: MPBusType) -> Self {
-        value.chars().collect::<String>()
+impl Display for MPBusType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        self.chars().try_for_each(|c| f.write_char(c))
     }
 }
 
 impl Debug for MPBusType {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
-        f.write_str(&String::from(*self))
+        Display::fmt(self, f)
     }
 }
 
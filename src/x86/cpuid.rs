@@ -201,6 +201,12 @@ cpu_feature_ecx!(
     "CPU supports RDRAND (Read Random Number) instruction"
 );
 
+cpu_feature_ecx!(
+    CPU_FEAT_HYPERVISOR,
+    1 << 31,
+    "Running under a hypervisor (always clear on real hardware)"
+);
+
 cpu_feature_edx!(
     CPU_FEAT_FPU,
     1 << 0,
@@ -473,13 +479,21 @@ pub fn cpu_model_id() -> Option<IntelCpuModel> {
 /// It checks if the requested CPUID leaf is available on this system, and then
 /// returns the content of eax, ebx, ecx and edx in order, after the `CPUID` call.
 pub fn cpu_id(eax: u32) -> Option<[u32; 4]> {
-    let mut result = [0u32; 4];
-
     // Check if the CPUID instruction is supported, and if the requested leaf is available.
     if !(cpu_id_support() & cpu_id_leaf_support(eax)) {
         return None;
     }
 
+    Some(cpu_id_unchecked(eax))
+}
+
+/// Executes a `CPUID` operation, without checking that the leaf `eax` is available.
+///
+/// Used for the leaves that are not reported by the maximum basic or extended leaf, such as the hypervisor leaves
+/// (`0x40000000` and above, see [`crate::x86::hypervisor`]). Requires the `CPUID` instruction to be supported.
+pub fn cpu_id_unchecked(eax: u32) -> [u32; 4] {
+    let mut result = [0u32; 4];
+
     #[cfg(not(feature = "x86_64"))]
     unsafe {
         asm!("cpuid", inout("eax") eax => result[0], out("ebx") result[1], out("ecx") result[2], out("edx") result[3]);
@@ -490,7 +504,7 @@ pub fn cpu_id(eax: u32) -> Option<[u32; 4]> {
         asm!("push rbx", "cpuid", "mov edi, ebx", "pop rbx", inout("eax") eax => result[0], out("edi") result[1], out("ecx") result[2], out("edx") result[3]);
     }
 
-    Some(result)
+    result
 }
 
 pub fn cpu_id_subleaf(eax: u32, ecx: u32) -> Option<[u32; 4]> {
@@ -0,0 +1,196 @@
+//! Long mode _Interrupt Descriptor Table_ (`IDT`) used between the bootloader and the kernel.
+//!
+//! The bootloader runs in protected mode, with a 32-bit `IDT`. Once paging is enabled and before jumping to the
+//! kernel, that table is no longer usable: any exception raised before the kernel loads its own `IDT` would be
+//! interpreted through 8-byte gates, and end in a triple fault.
+//!
+//! [`init_idt`] loads a 64-bit table, which only handles the processor exceptions (vectors `0` to `31`). Each of them
+//! points to a small 64-bit handler, that reports the vector, the error code, the faulting `RIP` and `CR2` on the
+//! `QEMU` debug console (port `0xE9`) and on `COM1`, and then halts the processor. Other vectors are not present, and
+//! therefore raise a _General Protection Fault_ whose error code identifies the vector.
+//!
+//! The handlers run on the current stack: a fault caused by an invalid stack pointer still results in a triple
+//! fault.
+
+use core::{arch::global_asm, ptr};
+
+use crate::{
+    mem::PhyAddr,
+    x86::{
+        apic::InterruptVector,
+        descriptors::{
+            gdt::KERNEL_CODE_SELECTOR,
+            idt::{GateDescriptor, GateType, InterruptDescriptorTable},
+        },
+        privilege::PrivilegeLevel,
+    },
+};
+
+/// Number of vectors reserved for processor exceptions.
+const EXCEPTION_VECTORS: usize = 32;
+
+/// Size of a handler stub (each stub starts on a 16-byte boundary).
+const STUB_SIZE: u64 = 16;
+
+/// Size of the table: header, followed by 256 16-byte gate descriptors.
+const BOOTINIT_IDT_SIZE: usize = 0x10 + 256 * 16;
+
+/// `QEMU` debug console I/O port.
+const DEBUGCON_PORT: u16 = 0xE9;
+
+/// `COM1` base I/O port.
+const COM1_PORT: u16 = 0x3F8;
+
+#[repr(C, align(16))]
+struct BootinitIdt([u8; BOOTINIT_IDT_SIZE]);
+
+/// Storage for the table, in the bootloader image (which remains mapped until the kernel loads its own `IDT`).
+static mut BOOTINIT_IDT: BootinitIdt = BootinitIdt([0; BOOTINIT_IDT_SIZE]);
+
+// 64-bit handlers, only reachable once the processor runs in long mode. Each stub pushes a null error code if the
+// processor does not push one for its vector, followed by the vector, so that the stack layout is identical for every
+// exception.
+global_asm!(
+    r#"
+.pushsection .text.bootinit_idt, "ax"
+.code64
+.balign 16
+.global bootinit_idt_stubs
+bootinit_idt_stubs:
+.irp vector, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31
+    .balign 16
+    .if (\vector != 8) && (\vector != 10) && (\vector != 11) && (\vector != 12) && (\vector != 13) && (\vector != 14) && (\vector != 17) && (\vector != 21) && (\vector != 29) && (\vector != 30)
+    push 0
+    .endif
+    push \vector
+    jmp bootinit_idt_common
+.endr
+
+bootinit_idt_common:
+    lea rsi, [rip + bootinit_idt_msg_vector]
+    call bootinit_idt_puts
+    mov rax, [rsp]
+    call bootinit_idt_puthex
+    lea rsi, [rip + bootinit_idt_msg_error]
+    call bootinit_idt_puts
+    mov rax, [rsp + 8]
+    call bootinit_idt_puthex
+    lea rsi, [rip + bootinit_idt_msg_rip]
+    call bootinit_idt_puts
+    mov rax, [rsp + 16]
+    call bootinit_idt_puthex
+    lea rsi, [rip + bootinit_idt_msg_cr2]
+    call bootinit_idt_puts
+    mov rax, cr2
+    call bootinit_idt_puthex
+    mov al, 10
+    call bootinit_idt_putc
+bootinit_idt_halt:
+    cli
+    hlt
+    jmp bootinit_idt_halt
+
+bootinit_idt_puts:
+    mov al, [rsi]
+    test al, al
+    jz bootinit_idt_puts_end
+    call bootinit_idt_putc
+    inc rsi
+    jmp bootinit_idt_puts
+bootinit_idt_puts_end:
+    ret
+
+bootinit_idt_puthex:
+    mov rbx, rax
+    mov edi, 16
+bootinit_idt_puthex_digit:
+    rol rbx, 4
+    mov al, bl
+    and al, 0x0F
+    add al, 0x30
+    cmp al, 0x39
+    jbe bootinit_idt_puthex_out
+    add al, 0x27
+bootinit_idt_puthex_out:
+    call bootinit_idt_putc
+    dec edi
+    jnz bootinit_idt_puthex_digit
+    ret
+
+bootinit_idt_putc:
+    mov ah, al
+    mov dx, {debugcon}
+    out dx, al
+    mov ecx, 0x10000
+bootinit_idt_putc_wait:
+    mov dx, {com1} + 5
+    in al, dx
+    test al, 0x20
+    jnz bootinit_idt_putc_ready
+    dec ecx
+    jnz bootinit_idt_putc_wait
+bootinit_idt_putc_ready:
+    mov al, ah
+    mov dx, {com1}
+    out dx, al
+    ret
+
+bootinit_idt_msg_vector:
+    .asciz "early fault: vector=0x"
+bootinit_idt_msg_error:
+    .asciz " error=0x"
+bootinit_idt_msg_rip:
+    .asciz " rip=0x"
+bootinit_idt_msg_cr2:
+    .asciz " cr2=0x"
+
+.code32
+.popsection
+"#,
+    debugcon = const DEBUGCON_PORT,
+    com1 = const COM1_PORT,
+);
+
+extern "C" {
+    static bootinit_idt_stubs: u8;
+}
+
+/// Loads the long mode `IDT` used until the kernel loads its own table.
+///
+/// Must be called with interrupts disabled (see [`init_paging`](crate::x86::paging::bootinit_paging::init_paging)),
+/// right before jumping to the kernel: the table is unusable in protected mode.
+#[allow(clippy::missing_panics_doc)]
+pub fn init_idt() {
+    let base = unsafe { ptr::addr_of_mut!(BOOTINIT_IDT) };
+    let mut idt = InterruptDescriptorTable::new(PhyAddr::new(base as u64));
+    let stubs = unsafe { ptr::addr_of!(bootinit_idt_stubs) } as u64;
+
+    for vector in 0..EXCEPTION_VECTORS {
+        let handler = PhyAddr::new(stubs + STUB_SIZE * vector as u64);
+
+        idt.set_entry(
+            InterruptVector::from(vector as u8),
+            GateDescriptor::new(GateType::InterruptGate)
+                .with_dpl(PrivilegeLevel::Ring0)
+                .with_offset(handler)
+                .with_present(true)
+                .with_segment_selector(*KERNEL_CODE_SELECTOR),
+        )
+        .expect("failed to set early exception handler");
+    }
+
+    for vector in EXCEPTION_VECTORS..256 {
+        unsafe {
+            idt.set_entry_unchecked(
+                vector,
+                GateDescriptor::new(GateType::InterruptGate).with_present(false),
+            );
+        }
+    }
+
+    unsafe {
+        idt.write_table()
+            .expect("failed to write the long mode IDT");
+        idt.enable();
+    }
+}
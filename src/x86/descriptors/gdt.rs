@@ -8,7 +8,7 @@
 #![allow(clippy::as_conversions)]
 
 use crate::errors::CanFail;
-use crate::mem::{MemoryAddress, MemoryError, PhyAddr};
+use crate::mem::{MemoryAddress, MemoryError, PhyAddr, VirtAddr};
 use crate::x86::msr::{Ia32ExtendedFeature, ModelSpecificRegister};
 use crate::x86::privilege::PrivilegeLevel;
 use crate::{BitIndex, Convertible};
@@ -26,35 +26,29 @@ use modular_bitfield::{bitfield, BitfieldSpecifier};
 
 pub const LONG_GDT_ADDR: u64 = 0x4000;
 
+/// Offset of the kernel data segment in the `GDT`s built by [`GdtBuilder`].
+const KERNEL_DATA_OFFSET: u16 = 0x08;
+
+/// Offset of the kernel code segment in the `GDT`s built by [`GdtBuilder`].
+const KERNEL_CODE_OFFSET: u16 = 0x10;
+
+/// Offset of the usermode data segment in the `GDT`s built by [`GdtBuilder`].
+const USERMODE_DATA_OFFSET: u16 = 0x18;
+
+/// Offset of the usermode code segment in the `GDT`s built by [`GdtBuilder`].
+const USERMODE_CODE_OFFSET: u16 = 0x20;
+
 /// Initializes the long mode [`GlobalDescriptorTable`], with long code segment.
 ///
+/// Returns the selectors of the table, used to jump to the kernel.
+///
 /// # Safety
 ///
 /// Overwrites anything in memory at [`base_address`].
-#[allow(clippy::missing_panics_doc)]
-pub unsafe fn long_init_gdt<A: MemoryAddress>(base_address: A) {
-    let mut gdt = GlobalDescriptorTable::new(base_address);
-    gdt.add_entry::<DataSegmentType>(
-        SegmentDescriptor::new_segment::<DataSegmentType>(DataSegmentType::ReadWrite)
-            .with_present(true)
-            .with_base(PhyAddr::new(0))
-            .unwrap()
-            .with_limit(0xFF_FFF)
-            .unwrap(),
-    )
-    .unwrap();
-    gdt.add_entry::<CodeSegmentType>(
-        SegmentDescriptor::new_segment::<CodeSegmentType>(CodeSegmentType::ExecuteRead)
-            .with_present(true)
-            .with_base(PhyAddr::new(0))
-            .unwrap()
-            .with_limit(0xFF_FFF)
-            .unwrap()
-            .enable_long()
-            .unwrap(),
-    )
-    .unwrap();
-    gdt.update();
+pub unsafe fn long_init_gdt<A: MemoryAddress>(base_address: A) -> GdtSelectors {
+    let (_, selectors) = GdtBuilder::new(base_address).build();
+
+    selectors
 }
 
 /// Initializes the Kernel mode [`GlobalDescriptorTable`], with long code segment along with Usermode (`CPL` = 3) segments.
@@ -62,55 +56,143 @@ pub unsafe fn long_init_gdt<A: MemoryAddress>(base_address: A) {
 /// # Safety
 ///
 /// Overwrites anything in memory at [`base_address`].
-pub unsafe fn kernel_init_gdt<A: MemoryAddress>(base_address: A) {
-    let mut gdt = GlobalDescriptorTable::new(base_address);
-    gdt.add_entry::<DataSegmentType>(
-        SegmentDescriptor::new_segment::<DataSegmentType>(DataSegmentType::ReadWrite)
-            .with_present(true)
-            .with_base(PhyAddr::new(0))
-            .unwrap()
-            .with_limit(0xFF_FFF)
-            .unwrap(),
-    )
-    .unwrap();
-
-    gdt.add_entry::<CodeSegmentType>(
-        SegmentDescriptor::new_segment::<CodeSegmentType>(CodeSegmentType::ExecuteRead)
-            .with_present(true)
-            .with_base(PhyAddr::new(0))
-            .unwrap()
-            .with_limit(0xFF_FFF)
-            .unwrap()
-            .enable_long()
-            .unwrap(),
-    )
-    .unwrap();
-
-    gdt.add_entry::<DataSegmentType>(
-        SegmentDescriptor::new_segment::<DataSegmentType>(DataSegmentType::ReadWrite)
-            .with_present(true)
-            .with_privilege_level(PrivilegeLevel::Ring3)
-            .with_base(PhyAddr::new(0))
-            .unwrap()
-            .with_limit(0xFF_FFF)
-            .unwrap(),
-    )
-    .unwrap();
-
-    gdt.add_entry::<CodeSegmentType>(
-        SegmentDescriptor::new_segment::<CodeSegmentType>(CodeSegmentType::ExecuteRead)
-            .with_present(true)
-            .with_privilege_level(PrivilegeLevel::Ring3)
-            .with_base(PhyAddr::new(0))
-            .unwrap()
-            .with_limit(0xFF_FFF)
-            .unwrap()
-            .enable_long()
-            .unwrap(),
-    )
-    .unwrap();
-
-    gdt.update();
+pub unsafe fn kernel_init_gdt<A: MemoryAddress>(
+    base_address: A,
+) -> (GlobalDescriptorTable<A>, GdtSelectors) {
+    GdtBuilder::new(base_address)
+        .with_usermode_segments()
+        .build()
+}
+
+/// Selectors of the segments of a [`GlobalDescriptorTable`] built with a [`GdtBuilder`].
+///
+/// The `TSS` selector of each processor is returned by [`init_cpu_tss`](super::tss::init_cpu_tss), which appends its
+/// descriptor to the table.
+#[derive(Clone, Copy, Debug)]
+pub struct GdtSelectors {
+    /// Flat kernel (`CPL` = 0) data segment.
+    pub kernel_data: SegmentSelector,
+
+    /// Flat kernel (`CPL` = 0) long mode code segment.
+    pub kernel_code: SegmentSelector,
+
+    /// Flat usermode (`CPL` = 3) data segment, if present.
+    pub usermode_data: Option<SegmentSelector>,
+
+    /// Flat usermode (`CPL` = 3) long mode code segment, if present.
+    pub usermode_code: Option<SegmentSelector>,
+}
+
+/// Builder for a [`GlobalDescriptorTable`] with flat segments.
+///
+/// Segments are always laid out in the same order (kernel data, kernel code, then usermode data and code), so that
+/// the selectors of every table built that way match [`KERNEL_DATA_SELECTOR`], [`KERNEL_CODE_SELECTOR`],
+/// [`USERMODE_DATA_SELECTOR`] and [`USERMODE_CODE_SELECTOR`]. These remain valid across the switch from the
+/// bootloader table to the kernel one.
+///
+/// # Examples
+///
+/// ```
+/// let (gdt, selectors) = unsafe {
+///     GdtBuilder::new(PhyAddr::new(LONG_GDT_ADDR))
+///         .with_usermode_segments()
+///         .build()
+/// };
+/// ```
+pub struct GdtBuilder<A: MemoryAddress> {
+    gdt: GlobalDescriptorTable<A>,
+    selectors: GdtSelectors,
+}
+
+impl<A: MemoryAddress> GdtBuilder<A> {
+    /// Starts building a table located at `base_address`, with the kernel segments.
+    ///
+    /// # Safety
+    ///
+    /// Overwrites anything in memory at [`base_address`]. The memory used by the table must not be overwritten later
+    /// on.
+    #[allow(clippy::missing_panics_doc)]
+    pub unsafe fn new(base_address: A) -> Self {
+        let mut gdt = GlobalDescriptorTable::new(base_address);
+
+        let kernel_data = gdt
+            .add_segment::<DataSegmentType>(
+                flat_segment(DataSegmentType::ReadWrite, PrivilegeLevel::Ring0),
+                *KERNEL_DATA_SELECTOR,
+            )
+            .expect("failed to add the kernel data segment");
+        let kernel_code = gdt
+            .add_segment::<CodeSegmentType>(
+                flat_segment(CodeSegmentType::ExecuteRead, PrivilegeLevel::Ring0)
+                    .enable_long()
+                    .unwrap(),
+                *KERNEL_CODE_SELECTOR,
+            )
+            .expect("failed to add the kernel code segment");
+
+        Self {
+            gdt,
+            selectors: GdtSelectors {
+                kernel_data,
+                kernel_code,
+                usermode_data: None,
+                usermode_code: None,
+            },
+        }
+    }
+
+    /// Adds the usermode (`CPL` = 3) data and code segments.
+    #[allow(clippy::missing_panics_doc)]
+    #[must_use]
+    pub fn with_usermode_segments(mut self) -> Self {
+        // The memory following the table is free for use, as required by `GdtBuilder::new`.
+        let (usermode_data, usermode_code) = unsafe {
+            let usermode_data = self
+                .gdt
+                .add_segment::<DataSegmentType>(
+                    flat_segment(DataSegmentType::ReadWrite, PrivilegeLevel::Ring3),
+                    *USERMODE_DATA_SELECTOR,
+                )
+                .expect("failed to add the usermode data segment");
+            let usermode_code = self
+                .gdt
+                .add_segment::<CodeSegmentType>(
+                    flat_segment(CodeSegmentType::ExecuteRead, PrivilegeLevel::Ring3)
+                        .enable_long()
+                        .unwrap(),
+                    *USERMODE_CODE_SELECTOR,
+                )
+                .expect("failed to add the usermode code segment");
+
+            (usermode_data, usermode_code)
+        };
+
+        self.selectors.usermode_data = Some(usermode_data);
+        self.selectors.usermode_code = Some(usermode_code);
+        self
+    }
+
+    /// Loads the table in the _GDTR_ register, and returns it along with its selectors.
+    ///
+    /// # Safety
+    ///
+    /// Updates the CPU internals: the segment registers must remain valid with the new table.
+    pub unsafe fn build(self) -> (GlobalDescriptorTable<A>, GdtSelectors) {
+        self.gdt.update();
+
+        (self.gdt, self.selectors)
+    }
+}
+
+/// Returns a present segment descriptor covering the whole address space.
+fn flat_segment<T: SegmentType>(seg_type: T, priv_lvl: PrivilegeLevel) -> SegmentDescriptor {
+    SegmentDescriptor::new_segment::<T>(seg_type)
+        .with_present(true)
+        .with_privilege_level(priv_lvl)
+        .with_base(PhyAddr::new(0))
+        .unwrap()
+        .with_limit(0xFF_FFF)
+        .unwrap()
 }
 
 /// _Global Descriptor Table_ (`GDT`) structure.
@@ -239,6 +321,14 @@ impl<A: MemoryAddress> GlobalDescriptorTable<A> {
         self.update();
     }
 
+    /// Returns the selector of the next entry added to this _Global Descriptor Table_.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn next_selector(&self) -> SegmentSelector {
+        SegmentSelector::gdt_selector()
+            .with_index(self.length)
+            .expect("misaligned GDT entry")
+    }
+
     /// Adds a new `segment descriptor` (as a [`SegmentDescriptor`]) to this _Global Descriptor Table_.
     ///
     /// Updates the content of the _GDTR_ register as well.
@@ -255,7 +345,7 @@ impl<A: MemoryAddress> GlobalDescriptorTable<A> {
         &mut self,
         descriptor: SegmentDescriptor,
     ) -> CanFail<MemoryError> {
-        if A::WIDTH == 64 && descriptor.is_system_segment() {
+        if A::WIDTH == 8 && descriptor.is_system_segment() {
             let descriptor_bytes = descriptor.as_long_mode_system_desc();
             self.tail_address.as_nonnull_ptr()?.write(descriptor_bytes);
             self.tail_address = self.tail_address + 0x10;
@@ -271,6 +361,28 @@ impl<A: MemoryAddress> GlobalDescriptorTable<A> {
 
         Ok(())
     }
+
+    /// Adds a segment descriptor that must be referenced by `expected`, and returns its selector.
+    ///
+    /// # Safety
+    ///
+    /// Same requirements as [`GlobalDescriptorTable::add_entry`].
+    unsafe fn add_segment<T: SegmentType>(
+        &mut self,
+        descriptor: SegmentDescriptor,
+        expected: SegmentSelector,
+    ) -> Result<SegmentSelector, MemoryError> {
+        let selector = self.next_selector();
+        assert_eq!(
+            selector.bytes() & !0b11,
+            expected.bytes() & !0b11,
+            "unexpected GDT layout"
+        );
+
+        self.add_entry::<T>(descriptor)?;
+
+        Ok(expected)
+    }
 }
 
 impl<A: MemoryAddress> Debug for GlobalDescriptorTable<A> {
@@ -551,6 +663,24 @@ impl SegmentDescriptor {
         ))
     }
 
+    /// Returns a copy of this `system segment` descriptor while updating the base address field, with a full 64-bit
+    /// address (for `TSS` or `LDT` descriptors in long mode).
+    ///
+    /// # Errors
+    ///
+    /// May return [`InvalidSegmentDescriptor::InvalidBaseAddress`] if this is not a system segment descriptor.
+    pub fn with_system_base(self, address: VirtAddr) -> Result<Self, InvalidSegmentDescriptor> {
+        if !self.is_system_segment() {
+            return Err(InvalidSegmentDescriptor::InvalidBaseAddress);
+        }
+
+        Ok(Self::with_inner(
+            self.inner
+                .with_base_lo(u64::from(address).convert_with_mask(0x00FF_FFFF))
+                .with_base_hi(u64::from(address) >> 24),
+        ))
+    }
+
     /// Returns a copy of this `segment descriptor` while updating the segment type.
     ///
     /// It indicates the segment type and the types of accesses it supports (read, read/write, execute only, ...).
@@ -971,7 +1101,7 @@ impl SegmentType for SystemSegmentType {
 pub static KERNEL_DATA_SELECTOR: Lazy<SegmentSelector> = Lazy::new(|| {
     match SegmentSelector::gdt_selector()
         .with_rpl(PrivilegeLevel::Ring0)
-        .with_index(0x08)
+        .with_index(KERNEL_DATA_OFFSET)
     {
         Ok(ds) => ds,
         Err(_) => panic!("invalid kernel data selector"),
@@ -981,7 +1111,7 @@ pub static KERNEL_DATA_SELECTOR: Lazy<SegmentSelector> = Lazy::new(|| {
 pub static KERNEL_CODE_SELECTOR: Lazy<SegmentSelector> = Lazy::new(|| {
     match SegmentSelector::gdt_selector()
         .with_rpl(PrivilegeLevel::Ring0)
-        .with_index(0x10)
+        .with_index(KERNEL_CODE_OFFSET)
     {
         Ok(ds) => ds,
         Err(_) => panic!("invalid kernel code selector"),
@@ -991,7 +1121,7 @@ pub static KERNEL_CODE_SELECTOR: Lazy<SegmentSelector> = Lazy::new(|| {
 pub static USERMODE_DATA_SELECTOR: Lazy<SegmentSelector> = Lazy::new(|| {
     match SegmentSelector::gdt_selector()
         .with_rpl(PrivilegeLevel::Ring3)
-        .with_index(0x18)
+        .with_index(USERMODE_DATA_OFFSET)
     {
         Ok(ds) => ds,
         Err(_) => panic!("invalid usermode data selector"),
@@ -1001,7 +1131,7 @@ pub static USERMODE_DATA_SELECTOR: Lazy<SegmentSelector> = Lazy::new(|| {
 pub static USERMODE_CODE_SELECTOR: Lazy<SegmentSelector> = Lazy::new(|| {
     match SegmentSelector::gdt_selector()
         .with_rpl(PrivilegeLevel::Ring3)
-        .with_index(0x20)
+        .with_index(USERMODE_CODE_OFFSET)
     {
         Ok(ds) => ds,
         Err(_) => panic!("invalid usermode code selector"),
@@ -140,6 +140,38 @@ impl<A: MemoryAddress> InterruptDescriptorTable<A> {
             .unwrap() = descriptor;
     }
 
+    /// Selects the entry `ist` of the _Interrupt Stack Table_ as the stack used to handle the vector `ivt`.
+    ///
+    /// An `ist` of `0` disables the stack switch.
+    pub fn set_interrupt_stack(&mut self, ivt: InterruptVector, ist: u8) -> CanFail<IDTError> {
+        let entry = self
+            .entries
+            .get_mut(usize::from(ivt))
+            .ok_or(IDTError::OutOfBoundsVector)?;
+
+        *entry = entry.with_ist(ist);
+
+        Ok(())
+    }
+
+    /// Sets the privilege level required to raise the vector `ivt` with the `INT` instruction.
+    ///
+    /// Hardware interrupts and exceptions ignore this level.
+    pub fn set_privilege_level(
+        &mut self,
+        ivt: InterruptVector,
+        dpl: PrivilegeLevel,
+    ) -> CanFail<IDTError> {
+        let entry = self
+            .entries
+            .get_mut(usize::from(ivt))
+            .ok_or(IDTError::OutOfBoundsVector)?;
+
+        *entry = entry.with_dpl(dpl);
+
+        Ok(())
+    }
+
     pub fn set_entry(
         &mut self,
         ivt: InterruptVector,
@@ -270,6 +302,19 @@ impl GateDescriptor {
         Self { inner: new_inner }
     }
 
+    /// Returns a copy of this descriptor, using the entry `ist` (`1` to `7`) of the _Interrupt Stack Table_ as the
+    /// handler stack (or the current stack if `ist` is `0`).
+    pub(crate) fn with_ist(self, ist: u8) -> Self {
+        if ist > 7 {
+            error!("idt", "invalid interrupt stack table index: {}", ist);
+            return self;
+        }
+
+        Self {
+            inner: self.inner.with_ist(ist),
+        }
+    }
+
     pub(crate) fn with_segment_selector(self, selector: SegmentSelector) -> Self {
         Self {
             inner: self.inner.with_segment_selector(selector.inner),
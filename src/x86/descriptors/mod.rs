@@ -1,2 +1,6 @@
+#[cfg(all(feature = "alloc", not(any(feature = "real", feature = "x86_64"))))]
+pub mod bootinit_idt;
 pub mod gdt;
 pub mod idt;
+#[cfg(feature = "x86_64")]
+pub mod tss;
@@ -0,0 +1,180 @@
+//! x86_64 _Task State Segment_ (`TSS`) related structures and methods.
+//!
+//! In long mode, the `TSS` no longer holds the state of a task, but the stack pointers used by the processor when
+//! handling interrupts: the privilege level stacks (`RSP0`-`RSP2`), loaded when an interrupt changes the privilege
+//! level, and the _Interrupt Stack Table_ (`IST`), seven stacks that interrupt gates can select regardless of the
+//! privilege level (see [`GateDescriptor::with_ist`]).
+//!
+//! Each processor has its own `TSS`, with dedicated `IST` stacks for the double fault (`#DF`), non-maskable
+//! interrupt (`NMI`) and machine check (`#MC`) handlers (see [`InterruptStackIndex`]). These handlers therefore
+//! always run on a known-good stack, even when the interrupted code overflowed its own stack: a kernel stack overflow
+//! ends in a double fault that can still report the issue, instead of a triple fault.
+//!
+//! [`GateDescriptor::with_ist`]: super::idt::GateDescriptor::with_ist
+
+use core::{arch::asm, mem::size_of};
+
+use alloc::{boxed::Box, collections::btree_map::BTreeMap};
+use spin::Mutex;
+
+use crate::{
+    info,
+    mem::{stack::get_kernel_stack_allocator, MemoryError, VirtAddr},
+    x86::apic::local_apic::ProcLocalApicID,
+};
+
+use super::gdt::{GlobalDescriptorTable, SegmentDescriptor, SegmentSelector, SystemSegmentType};
+
+/// `TSS` of each processor and its selector, by local APIC identifier.
+static CPU_TSS: Mutex<BTreeMap<ProcLocalApicID, (&'static TaskStateSegment, SegmentSelector)>> =
+    Mutex::new(BTreeMap::new());
+
+/// Entries of the _Interrupt Stack Table_ reserved for specific handlers.
+///
+/// Index `0` means that the interrupt does not switch stacks, so the first entry of the table is index `1`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum InterruptStackIndex {
+    /// Stack of the double fault (`#DF`) handler.
+    DoubleFault = 1,
+
+    /// Stack of the non-maskable interrupt (`NMI`) handler.
+    NonMaskableInterrupt = 2,
+
+    /// Stack of the machine check (`#MC`) handler.
+    MachineCheck = 3,
+}
+
+impl InterruptStackIndex {
+    const ALL: [Self; 3] = [
+        Self::DoubleFault,
+        Self::NonMaskableInterrupt,
+        Self::MachineCheck,
+    ];
+}
+
+impl From<InterruptStackIndex> for u8 {
+    fn from(value: InterruptStackIndex) -> Self {
+        value as u8
+    }
+}
+
+/// 64-bit _Task State Segment_.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C, packed(4))]
+pub struct TaskStateSegment {
+    _reserved_0: u32,
+
+    /// Stack pointers loaded when an interrupt raises the privilege level to ring 0, 1 or 2.
+    privilege_stacks: [u64; 3],
+    _reserved_1: u64,
+
+    /// Stack pointers of the _Interrupt Stack Table_ (`IST1` to `IST7`).
+    interrupt_stacks: [u64; 7],
+    _reserved_2: u64,
+    _reserved_3: u16,
+
+    /// Offset of the I/O permission bitmap from the base of the `TSS`.
+    io_map_base: u16,
+}
+
+impl TaskStateSegment {
+    /// Returns an empty `TSS`, without any I/O permission bitmap.
+    pub const fn new() -> Self {
+        Self {
+            _reserved_0: 0,
+            privilege_stacks: [0; 3],
+            _reserved_1: 0,
+            interrupt_stacks: [0; 7],
+            _reserved_2: 0,
+            _reserved_3: 0,
+            io_map_base: size_of::<Self>() as u16,
+        }
+    }
+
+    /// Sets the top of the stack loaded when an interrupt is raised from user mode (`RSP0`).
+    pub fn set_kernel_stack(&mut self, stack_top: VirtAddr) {
+        let mut stacks = self.privilege_stacks;
+        stacks[0] = u64::from(stack_top);
+        self.privilege_stacks = stacks;
+    }
+
+    /// Returns the top of the stack used for the `IST` entry `index`.
+    pub fn interrupt_stack(&self, index: InterruptStackIndex) -> VirtAddr {
+        let stacks = self.interrupt_stacks;
+        VirtAddr::new(stacks[usize::from(u8::from(index)) - 1])
+    }
+
+    /// Sets the top of the stack used for the `IST` entry `index`.
+    pub fn set_interrupt_stack(&mut self, index: InterruptStackIndex, stack_top: VirtAddr) {
+        let mut stacks = self.interrupt_stacks;
+        stacks[usize::from(u8::from(index)) - 1] = u64::from(stack_top);
+        self.interrupt_stacks = stacks;
+    }
+}
+
+/// Sets up the `TSS` of the current processor, with its _Interrupt Stack Table_ and the stack used to handle
+/// interrupts raised from user mode (such as system calls), and loads it in the task register.
+///
+/// Each of these stacks is a kernel stack (see [`get_kernel_stack_allocator`]).
+///
+/// The `TSS` descriptor is appended to `gdt`, and its selector is returned. Does nothing if the current processor
+/// already has a `TSS`.
+///
+/// # Errors
+///
+/// Returns an error if the descriptor could not be written to the `GDT`.
+///
+/// # Safety
+///
+/// `gdt` must be the `GDT` currently loaded, and have enough free memory after its last entry for a system segment
+/// descriptor.
+pub unsafe fn init_cpu_tss(
+    gdt: &mut GlobalDescriptorTable<VirtAddr>,
+) -> Result<SegmentSelector, MemoryError> {
+    let cpu = ProcLocalApicID::get();
+    let mut cpu_tss = CPU_TSS.lock();
+
+    if let Some((_, selector)) = cpu_tss.get(&cpu) {
+        return Ok(*selector);
+    }
+
+    let mut tss = TaskStateSegment::new();
+    tss.set_kernel_stack(get_kernel_stack_allocator().lock().alloc_stack());
+    for index in InterruptStackIndex::ALL {
+        tss.set_interrupt_stack(index, get_kernel_stack_allocator().lock().alloc_stack());
+    }
+
+    let tss: &'static TaskStateSegment = Box::leak(Box::new(tss));
+    let tss_addr = tss as *const TaskStateSegment as u64;
+
+    let selector = gdt.next_selector();
+    gdt.add_entry::<SystemSegmentType>(
+        SegmentDescriptor::new_segment::<SystemSegmentType>(SystemSegmentType::AvailableTSS)
+            .with_present(true)
+            .with_system_base(VirtAddr::new(tss_addr))
+            .expect("invalid TSS base address")
+            .with_limit(size_of::<TaskStateSegment>() as u32 - 1)
+            .expect("invalid TSS limit"),
+    )?;
+
+    asm!("ltr {:x}", in(reg) selector.bytes(), options(nostack, preserves_flags));
+
+    cpu_tss.insert(cpu, (tss, selector));
+    info!(
+        "tss",
+        "loaded TSS for cpu {} (selector {:#x})",
+        u8::from(cpu),
+        selector.bytes()
+    );
+
+    Ok(selector)
+}
+
+/// Returns the `TSS` of the current processor, if it was set up (see [`init_cpu_tss`]).
+pub fn current_tss() -> Option<&'static TaskStateSegment> {
+    CPU_TSS
+        .lock()
+        .get(&ProcLocalApicID::get())
+        .map(|(tss, _)| *tss)
+}
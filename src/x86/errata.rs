@@ -0,0 +1,327 @@
+//! Processor errata workarounds.
+//!
+//! Some processors have known defects (errata), documented by their vendor along with a workaround: disabling a
+//! feature, or setting an undocumented "chicken bit" in an MSR. Some of them are fixed by later microcode updates.
+//!
+//! [`apply_errata`] identifies the current processor ([`CpuSignature`]), and applies the workaround of every erratum
+//! of the [`ERRATA`] table that affects it. It must be called early during boot, before the affected features are
+//! used.
+//!
+//! # Adding an erratum
+//!
+//! Add an [`Erratum`] entry to the [`ERRATA`] table, with the vendor's identifier of the erratum, the processors
+//! it affects ([`CpuMatch`]), and a workaround function. If a microcode update fixes the erratum, set
+//! `fixed_in_microcode` so that the workaround is skipped on up-to-date processors. Errata that affect several
+//! model ranges with different microcode fixes can use one entry per range.
+
+use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{
+    info,
+    x86::{
+        cpuid::{cpu_feature_support, cpu_id, CPU_FEAT_HYPERVISOR},
+        idle::disable_mwait,
+        msr::{msr_read, msr_write},
+    },
+};
+
+/// Intel: microcode update signature (`IA32_BIOS_SIGN_ID`). AMD: current patch level.
+const IA32_BIOS_SIGN_ID: u32 = 0x8B;
+
+/// AMD decode configuration register.
+const AMD_MSR_DE_CFG: u32 = 0xC001_1029;
+
+/// AMD Zen 2 chicken bits register.
+const AMD_MSR_ZEN2_CHICKEN: u32 = 0xC001_10E3;
+
+/// Bitmap of the entries of [`ERRATA`] whose workaround was applied (the table holds at most 64 entries).
+static APPLIED_ERRATA: AtomicU64 = AtomicU64::new(0);
+
+/// Known errata, and their workarounds.
+pub static ERRATA: &[Erratum] = &[
+    Erratum {
+        id: "APL30",
+        description: "MONITOR may not wake MWAIT up on a write to the monitored address",
+        cpus: CpuMatch::new(CpuVendor::Intel, 0x06, 0x5C..=0x5C),
+        fixed_in_microcode: None,
+        bare_metal_only: false,
+        workaround: disable_mwait_workaround,
+    },
+    Erratum {
+        id: "AMD-SB-7008",
+        description: "Zenbleed: vector registers may leak data across contexts",
+        cpus: CpuMatch::new(CpuVendor::Amd, 0x17, 0x30..=0x3F),
+        fixed_in_microcode: Some(0x0830_107A),
+        bare_metal_only: true,
+        workaround: zenbleed_workaround,
+    },
+    Erratum {
+        id: "AMD-SB-7008",
+        description: "Zenbleed: vector registers may leak data across contexts",
+        cpus: CpuMatch::new(CpuVendor::Amd, 0x17, 0x40..=0x4F),
+        fixed_in_microcode: None,
+        bare_metal_only: true,
+        workaround: zenbleed_workaround,
+    },
+    Erratum {
+        id: "AMD-SB-7008",
+        description: "Zenbleed: vector registers may leak data across contexts",
+        cpus: CpuMatch::new(CpuVendor::Amd, 0x17, 0x60..=0x67),
+        fixed_in_microcode: Some(0x0860_010B),
+        bare_metal_only: true,
+        workaround: zenbleed_workaround,
+    },
+    Erratum {
+        id: "AMD-SB-7008",
+        description: "Zenbleed: vector registers may leak data across contexts",
+        cpus: CpuMatch::new(CpuVendor::Amd, 0x17, 0x68..=0x6F),
+        fixed_in_microcode: Some(0x0860_8105),
+        bare_metal_only: true,
+        workaround: zenbleed_workaround,
+    },
+    Erratum {
+        id: "AMD-SB-7008",
+        description: "Zenbleed: vector registers may leak data across contexts",
+        cpus: CpuMatch::new(CpuVendor::Amd, 0x17, 0x70..=0x7F),
+        fixed_in_microcode: Some(0x0870_1032),
+        bare_metal_only: true,
+        workaround: zenbleed_workaround,
+    },
+    Erratum {
+        id: "AMD-SB-7008",
+        description: "Zenbleed: vector registers may leak data across contexts",
+        cpus: CpuMatch::new(CpuVendor::Amd, 0x17, 0xA0..=0xAF),
+        fixed_in_microcode: Some(0x08A0_0008),
+        bare_metal_only: true,
+        workaround: zenbleed_workaround,
+    },
+    Erratum {
+        id: "ZEN2-SPECTRAL-CHICKEN",
+        description: "return address predictions may be used across privilege levels",
+        cpus: CpuMatch::new(CpuVendor::Amd, 0x17, 0x30..=0x7F),
+        fixed_in_microcode: None,
+        bare_metal_only: true,
+        workaround: zen2_spectral_chicken_workaround,
+    },
+];
+
+/// Vendor of a processor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CpuVendor {
+    Intel,
+    Amd,
+    Other,
+}
+
+/// Identification of a processor, as used to match errata.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuSignature {
+    pub vendor: CpuVendor,
+
+    /// Family, including the extended family.
+    pub family: u8,
+
+    /// Model, including the extended model.
+    pub model: u8,
+
+    pub stepping: u8,
+
+    /// Revision of the loaded microcode, if available.
+    pub microcode: Option<u32>,
+}
+
+impl CpuSignature {
+    /// Identifies the current processor.
+    ///
+    /// Returns `None` if the `CPUID` instruction is not supported.
+    pub fn current() -> Option<Self> {
+        let [_, ebx, ecx, edx] = cpu_id(0)?;
+        let vendor = match (ebx, edx, ecx) {
+            // "GenuineIntel"
+            (0x756E_6547, 0x4965_6E69, 0x6C65_746E) => CpuVendor::Intel,
+            // "AuthenticAMD"
+            (0x6874_7541, 0x6974_6E65, 0x444D_4163) => CpuVendor::Amd,
+            _ => CpuVendor::Other,
+        };
+
+        let eax = cpu_id(1)?[0];
+        let base_family = ((eax >> 8) & 0xF) as u8;
+        let base_model = ((eax >> 4) & 0xF) as u8;
+        let ext_model = ((eax >> 16) & 0xF) as u8;
+
+        let family = match base_family {
+            0xF => base_family + ((eax >> 20) & 0xFF) as u8,
+            _ => base_family,
+        };
+        let model = match base_family {
+            0x6 | 0xF => (ext_model << 4) | base_model,
+            _ => base_model,
+        };
+
+        Some(Self {
+            vendor,
+            family,
+            model,
+            stepping: (eax & 0xF) as u8,
+            microcode: microcode_revision(vendor),
+        })
+    }
+}
+
+/// Set of processors affected by an erratum.
+#[derive(Clone, Debug)]
+pub struct CpuMatch {
+    pub vendor: CpuVendor,
+    pub family: u8,
+    pub models: RangeInclusive<u8>,
+    pub steppings: RangeInclusive<u8>,
+}
+
+impl CpuMatch {
+    /// Matches every stepping of the given models.
+    pub const fn new(vendor: CpuVendor, family: u8, models: RangeInclusive<u8>) -> Self {
+        Self {
+            vendor,
+            family,
+            models,
+            steppings: 0..=0xF,
+        }
+    }
+
+    /// Restricts the match to the given steppings.
+    pub const fn with_steppings(mut self, steppings: RangeInclusive<u8>) -> Self {
+        self.steppings = steppings;
+        self
+    }
+
+    pub fn matches(&self, cpu: &CpuSignature) -> bool {
+        self.vendor == cpu.vendor
+            && self.family == cpu.family
+            && self.models.contains(&cpu.model)
+            && self.steppings.contains(&cpu.stepping)
+    }
+}
+
+/// A known processor erratum, and its workaround.
+#[derive(Clone, Debug)]
+pub struct Erratum {
+    /// Identifier of the erratum, as documented by the vendor.
+    pub id: &'static str,
+
+    pub description: &'static str,
+
+    /// Affected processors.
+    pub cpus: CpuMatch,
+
+    /// First microcode revision fixing the erratum, if any.
+    pub fixed_in_microcode: Option<u32>,
+
+    /// Skips the workaround when running under a hypervisor (which is responsible for it, and usually does not
+    /// allow writing to the MSRs involved).
+    pub bare_metal_only: bool,
+
+    /// Applies the workaround on the current processor.
+    pub workaround: fn(&CpuSignature),
+}
+
+impl Erratum {
+    /// Checks whether the erratum affects `cpu`.
+    pub fn affects(&self, cpu: &CpuSignature) -> bool {
+        if !self.cpus.matches(cpu) {
+            return false;
+        }
+
+        match (self.fixed_in_microcode, cpu.microcode) {
+            (Some(fixed), Some(revision)) => revision < fixed,
+            _ => true,
+        }
+    }
+}
+
+/// Applies the workarounds of every erratum affecting the current processor.
+///
+/// Returns the number of workarounds applied.
+pub fn apply_errata() -> usize {
+    let Some(cpu) = CpuSignature::current() else {
+        return 0;
+    };
+    let hypervisor = cpu_feature_support(CPU_FEAT_HYPERVISOR).unwrap_or(false);
+
+    info!(
+        "errata",
+        "cpu {:?} family {:#x} model {:#x} stepping {:#x} microcode {:#x}",
+        cpu.vendor,
+        cpu.family,
+        cpu.model,
+        cpu.stepping,
+        cpu.microcode.unwrap_or_default()
+    );
+
+    let mut applied = 0;
+
+    for (index, erratum) in ERRATA.iter().enumerate() {
+        if !erratum.affects(&cpu) || (erratum.bare_metal_only && hypervisor) {
+            continue;
+        }
+
+        (erratum.workaround)(&cpu);
+        APPLIED_ERRATA.fetch_or(1 << index, Ordering::Relaxed);
+        applied += 1;
+
+        info!(
+            "errata",
+            "applied workaround for {} ({})", erratum.id, erratum.description
+        );
+    }
+
+    applied
+}
+
+/// Checks whether the workaround of the erratum `id` was applied.
+pub fn erratum_applied(id: &str) -> bool {
+    let applied = APPLIED_ERRATA.load(Ordering::Relaxed);
+
+    ERRATA
+        .iter()
+        .enumerate()
+        .any(|(index, erratum)| erratum.id == id && applied & (1 << index) != 0)
+}
+
+/// Returns the revision of the loaded microcode.
+fn microcode_revision(vendor: CpuVendor) -> Option<u32> {
+    match vendor {
+        CpuVendor::Intel => {
+            // The signature is only updated by `CPUID`, after clearing the register.
+            unsafe {
+                msr_write(IA32_BIOS_SIGN_ID, 0);
+            }
+            cpu_id(1)?;
+
+            Some((msr_read(IA32_BIOS_SIGN_ID)? >> 32) as u32)
+        }
+        CpuVendor::Amd => Some(msr_read(IA32_BIOS_SIGN_ID)? as u32),
+        CpuVendor::Other => None,
+    }
+}
+
+fn disable_mwait_workaround(_cpu: &CpuSignature) {
+    disable_mwait();
+}
+
+/// Sets the `FP_BACKUP_FIX` bit of `DE_CFG`.
+fn zenbleed_workaround(_cpu: &CpuSignature) {
+    if let Some(de_cfg) = msr_read(AMD_MSR_DE_CFG) {
+        unsafe {
+            msr_write(AMD_MSR_DE_CFG, de_cfg | (1 << 9));
+        }
+    }
+}
+
+fn zen2_spectral_chicken_workaround(_cpu: &CpuSignature) {
+    if let Some(chicken) = msr_read(AMD_MSR_ZEN2_CHICKEN) {
+        unsafe {
+            msr_write(AMD_MSR_ZEN2_CHICKEN, chicken | (1 << 1));
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Hypervisor detection.
+//!
+//! When running as a virtual machine guest, the `CPUID` leaf `0x40000000` identifies the hypervisor, and reports the
+//! maximum hypervisor leaf. The following leaves describe the paravirtual features offered by the hypervisor (see
+//! [`crate::x86::kvm`] for KVM).
+//!
+//! Some hypervisors do not emulate all the legacy devices found on real hardware, and probing their ports may hang or
+//! return garbage. The drivers consult [`hypervisor_quirks`] before probing such devices.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::x86::hypervisor::{hypervisor, Hypervisor};
+//!
+//! if hypervisor() == Some(Hypervisor::Kvm) {
+//!     info!("kvm", "running as a KVM guest");
+//! }
+//! ```
+
+use core::fmt::{self, Display};
+
+use crate::x86::cpuid::{
+    cpu_feature_support, cpu_id_support, cpu_id_unchecked, CPU_FEAT_HYPERVISOR,
+};
+
+/// First hypervisor `CPUID` leaf, returning the signature of the hypervisor.
+pub const HYPERVISOR_CPUID_BASE: u32 = 0x4000_0000;
+
+/// A hypervisor, identified by its `CPUID` signature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hypervisor {
+    /// Linux KVM (used by QEMU with hardware acceleration).
+    Kvm,
+
+    /// Microsoft Hyper-V.
+    HyperV,
+
+    /// VMware Workstation / ESXi.
+    VMware,
+
+    /// A hypervisor without specific support.
+    Unknown,
+}
+
+impl Hypervisor {
+    fn from_signature(signature: &[u8; 12]) -> Self {
+        match signature {
+            b"KVMKVMKVM\0\0\0" => Self::Kvm,
+            b"Microsoft Hv" => Self::HyperV,
+            b"VMwareVMware" => Self::VMware,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl Hypervisor {
+    /// Returns the adjustments to the probing of devices required by this hypervisor.
+    pub fn quirks(self) -> HypervisorQuirks {
+        match self {
+            // Generation 2 virtual machines have no PS/2 controller, and no embedded controller is ever emulated.
+            Self::HyperV => HypervisorQuirks {
+                ps2_may_be_absent: true,
+                no_embedded_controller: true,
+            },
+            Self::VMware => HypervisorQuirks {
+                no_embedded_controller: true,
+                ..HypervisorQuirks::default()
+            },
+            Self::Kvm | Self::Unknown => HypervisorQuirks::default(),
+        }
+    }
+}
+
+impl Display for Hypervisor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Kvm => f.write_str("kvm"),
+            Self::HyperV => f.write_str("hyperv"),
+            Self::VMware => f.write_str("vmware"),
+            Self::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
+/// Adjustments to the probing of legacy devices, depending on the hypervisor (see [`hypervisor_quirks`]).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HypervisorQuirks {
+    /// The PS/2 controller may not be emulated, in which case its status port floats high.
+    pub ps2_may_be_absent: bool,
+
+    /// No embedded controller is emulated: the default EC ports must not be probed.
+    pub no_embedded_controller: bool,
+}
+
+/// Returns the hypervisor running this system, or `None` when running on real hardware.
+pub fn hypervisor() -> Option<Hypervisor> {
+    if !cpu_feature_support(CPU_FEAT_HYPERVISOR)? {
+        return None;
+    }
+
+    let leaf = cpu_id_unchecked(HYPERVISOR_CPUID_BASE);
+    let mut signature = [0u8; 12];
+    signature[..4].copy_from_slice(&leaf[1].to_ne_bytes());
+    signature[4..8].copy_from_slice(&leaf[2].to_ne_bytes());
+    signature[8..].copy_from_slice(&leaf[3].to_ne_bytes());
+
+    Some(Hypervisor::from_signature(&signature))
+}
+
+/// Returns the adjustments to the probing of devices required by the hypervisor running this system.
+///
+/// No adjustment is required when running on real hardware.
+pub fn hypervisor_quirks() -> HypervisorQuirks {
+    hypervisor().map_or_else(HypervisorQuirks::default, Hypervisor::quirks)
+}
+
+/// Executes a hypervisor `CPUID` leaf (`0x40000000` and above).
+///
+/// Returns `None` when not running under a hypervisor, or if the leaf is not reported by the hypervisor.
+pub fn hypervisor_cpuid(leaf: u32) -> Option<[u32; 4]> {
+    if !cpu_id_support() || !cpu_feature_support(CPU_FEAT_HYPERVISOR)? {
+        return None;
+    }
+
+    // Some hypervisors report 0 as their maximum leaf, meaning that the leaves up to 0x40000001 are available.
+    let max_leaf = u32::max(
+        cpu_id_unchecked(HYPERVISOR_CPUID_BASE)[0],
+        HYPERVISOR_CPUID_BASE + 1,
+    );
+
+    (HYPERVISOR_CPUID_BASE..=max_leaf)
+        .contains(&leaf)
+        .then(|| cpu_id_unchecked(leaf))
+}
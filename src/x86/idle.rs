@@ -0,0 +1,239 @@
+//! CPU idle management.
+//!
+//! When there is nothing to do, the CPU should be put into a low-power state until the next interrupt, rather than
+//! spinning. [`cpu_idle`] uses `MWAIT` when the CPU supports it (which allows deeper C-states), and falls back to
+//! `HLT` otherwise.
+//!
+//! [`idle_init`] must be called once to detect `MWAIT` support. Until then, `HLT` is used.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::x86::idle::{cpu_idle, idle_init};
+//! use fzboot::x86::int::disable_interrupts;
+//!
+//! idle_init();
+//!
+//! loop {
+//!     disable_interrupts();
+//!     if work_pending() {
+//!         do_work();
+//!         continue;
+//!     }
+//!
+//!     // Interrupts are enabled again, and the CPU sleeps until the next one.
+//!     cpu_idle();
+//! }
+//! ```
+
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering},
+};
+
+use crate::{
+    info,
+    x86::{
+        cpuid::{cpu_feature_support, cpu_id, CPU_FEAT_MONITOR},
+        int::disable_interrupts,
+        tsc::TSC_CLK,
+    },
+};
+
+/// `CPUID` leaf describing the `MONITOR`/`MWAIT` features.
+const CPUID_MWAIT_LEAF: u32 = 0x05;
+
+/// The supported C-states are enumerated (leaf 5, `ECX`).
+const MWAIT_ECX_EXTENSIONS: u32 = 1 << 0;
+
+/// Deepest C-state requested with `MWAIT`.
+///
+/// Deeper states save more power, but take longer to exit, and may stop the local APIC timer.
+const MWAIT_MAX_CSTATE: u32 = 2;
+
+/// Instruction used to idle the CPU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IdleMethod {
+    /// `HLT`, enters the C1 state.
+    Halt = 0,
+
+    /// `MONITOR`/`MWAIT`, targeting the deepest supported C-state.
+    Mwait = 1,
+}
+
+static IDLE_METHOD: AtomicU8 = AtomicU8::new(IdleMethod::Halt as u8);
+
+/// Set when `MWAIT` must not be used, even if it is supported (see [`disable_mwait`]).
+static MWAIT_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// `EAX` hint passed to `MWAIT` (target C-state and sub-state).
+static MWAIT_HINT: AtomicU32 = AtomicU32::new(0);
+
+/// Cache line monitored while idling with `MWAIT`. Writing to it wakes the CPU up.
+#[repr(align(64))]
+struct MonitorLine(AtomicU64);
+
+static IDLE_MONITOR: MonitorLine = MonitorLine(AtomicU64::new(0));
+
+/// Time spent idling, in TSC cycles.
+static IDLE_CYCLES: AtomicU64 = AtomicU64::new(0);
+
+/// Number of times the CPU was put into an idle state.
+static IDLE_ENTRIES: AtomicU64 = AtomicU64::new(0);
+
+/// Idle statistics, see [`idle_stats`].
+#[derive(Clone, Copy, Debug)]
+pub struct IdleStats {
+    /// Time spent idling, in TSC cycles (0 if the TSC clock was not initialized).
+    pub cycles: u64,
+
+    /// Number of times the CPU was put into an idle state.
+    pub entries: u64,
+}
+
+/// Selects the instruction used to idle the CPU.
+///
+/// `MWAIT` is used if it is supported, and if the C-states it can enter are enumerated by `CPUID`, unless it was
+/// disabled (see [`disable_mwait`]).
+pub fn idle_init() {
+    let Some(hint) = mwait_hint() else {
+        info!("idle", "using HLT to idle the CPU");
+        return;
+    };
+
+    MWAIT_HINT.store(hint, Ordering::Relaxed);
+    IDLE_METHOD.store(IdleMethod::Mwait as u8, Ordering::Relaxed);
+
+    info!("idle", "using MWAIT to idle the CPU    hint = {:#x}", hint);
+}
+
+/// Returns the instruction used to idle the CPU.
+pub fn idle_method() -> IdleMethod {
+    match IDLE_METHOD.load(Ordering::Relaxed) {
+        1 => IdleMethod::Mwait,
+        _ => IdleMethod::Halt,
+    }
+}
+
+/// Forces the instruction used to idle the CPU.
+///
+/// Returns `false` if `MWAIT` was requested but is not supported.
+pub fn set_idle_method(method: IdleMethod) -> bool {
+    if method == IdleMethod::Mwait {
+        let Some(hint) = mwait_hint() else {
+            return false;
+        };
+
+        MWAIT_HINT.store(hint, Ordering::Relaxed);
+    }
+
+    IDLE_METHOD.store(method as u8, Ordering::Relaxed);
+    true
+}
+
+/// Prevents `MWAIT` from being used to idle the CPU, for processors on which it is not reliable.
+///
+/// Switches back to `HLT` if `MWAIT` was already in use.
+pub fn disable_mwait() {
+    MWAIT_DISABLED.store(true, Ordering::Relaxed);
+    IDLE_METHOD.store(IdleMethod::Halt as u8, Ordering::Relaxed);
+}
+
+/// Enables interrupts, and puts the CPU into a low-power state until the next interrupt.
+///
+/// Must be called with interrupts disabled, after checking that there is nothing to do: interrupts are enabled
+/// atomically with entering the idle state, so that an interrupt raised after that check wakes the CPU up instead of
+/// being missed. Interrupts are enabled when this returns.
+pub fn cpu_idle() {
+    let start = tsc_read();
+
+    match idle_method() {
+        IdleMethod::Halt => unsafe {
+            asm!("sti", "hlt", options(nostack));
+        },
+        IdleMethod::Mwait => unsafe {
+            monitor(IDLE_MONITOR.0.as_ptr() as usize);
+            // `sti` delays interrupts until the end of the next instruction, so none can fire before `mwait`.
+            asm!(
+                "sti",
+                "mwait",
+                in("eax") MWAIT_HINT.load(Ordering::Relaxed),
+                in("ecx") 0u32,
+                options(nostack)
+            );
+        },
+    }
+
+    IDLE_CYCLES.fetch_add(tsc_read().saturating_sub(start), Ordering::Relaxed);
+    IDLE_ENTRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Wakes up the CPU idling with `MWAIT`, without raising an interrupt.
+///
+/// Does nothing when `HLT` is used.
+pub fn idle_wake() {
+    IDLE_MONITOR.0.fetch_add(1, Ordering::Release);
+}
+
+/// Idles the CPU forever.
+pub fn idle_loop() -> ! {
+    loop {
+        disable_interrupts();
+        cpu_idle();
+    }
+}
+
+/// Halts the CPU forever, with interrupts disabled.
+///
+/// Unlike an empty `loop {}`, the host CPU is not kept busy when running in a virtual machine.
+pub fn halt_forever() -> ! {
+    loop {
+        unsafe {
+            asm!("cli", "hlt", options(nostack));
+        }
+    }
+}
+
+/// Returns the idle statistics since boot.
+pub fn idle_stats() -> IdleStats {
+    IdleStats {
+        cycles: IDLE_CYCLES.load(Ordering::Relaxed),
+        entries: IDLE_ENTRIES.load(Ordering::Relaxed),
+    }
+}
+
+/// Computes the `MWAIT` hint of the deepest supported C-state (up to [`MWAIT_MAX_CSTATE`]).
+///
+/// Returns `None` if `MWAIT` is not supported (or disabled), or if no C-state is enumerated.
+fn mwait_hint() -> Option<u32> {
+    if MWAIT_DISABLED.load(Ordering::Relaxed) || !cpu_feature_support(CPU_FEAT_MONITOR)? {
+        return None;
+    }
+
+    let leaf = cpu_id(CPUID_MWAIT_LEAF)?;
+
+    if leaf[2] & MWAIT_ECX_EXTENSIONS == 0 {
+        return None;
+    }
+
+    // EDX holds the number of sub-states of each C-state, 4 bits each, starting from C0.
+    let (cstate, substates) = (1..=MWAIT_MAX_CSTATE)
+        .map(|cstate| (cstate, (leaf[3] >> (4 * cstate)) & 0xf))
+        .rev()
+        .find(|&(_, substates)| substates != 0)?;
+
+    Some(((cstate - 1) << 4) | (substates - 1))
+}
+
+unsafe fn monitor(addr: usize) {
+    #[cfg(target_arch = "x86_64")]
+    asm!("monitor", in("rax") addr, in("ecx") 0u32, in("edx") 0u32, options(nostack));
+
+    #[cfg(not(target_arch = "x86_64"))]
+    asm!("monitor", in("eax") addr, in("ecx") 0u32, in("edx") 0u32, options(nostack));
+}
+
+fn tsc_read() -> u64 {
+    TSC_CLK.get().map_or(0, |tsc| tsc.tsc_read())
+}
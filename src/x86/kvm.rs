@@ -0,0 +1,239 @@
+//! KVM paravirtual features.
+//!
+//! When running as a KVM guest (see [`crate::x86::hypervisor`]), the following features are used if the hypervisor
+//! offers them (`CPUID` leaf `0x40000001`):
+//!
+//! - `kvmclock`: the hypervisor keeps a [`PvclockTimeInfo`] structure up to date in guest memory, from which the
+//!   current time is computed using the TSC. It is not affected by the TSC calibration errors, nor by the TSC drifting
+//!   when the virtual CPU is migrated, and is used as a clock source (see [`crate::time::clocksource`]).
+//! - `PV EOI`: the hypervisor flags in guest memory the interrupts whose end of interrupt does not need to be
+//!   signaled to the (emulated) local APIC, which avoids a VM exit for most interrupts (see [`pv_eoi_ack`]).
+//!
+//! The hypervisor keeps writing to the registered memory until the features are disabled: [`kvm_handoff`] must be
+//! called before handing the memory over to another program.
+
+use core::cell::UnsafeCell;
+use core::ptr::{addr_of, read_volatile};
+use core::sync::atomic::{fence, AtomicBool, AtomicU32, Ordering};
+
+use crate::time::clocksource::clocksource_leave_kvmclock;
+use crate::x86::hypervisor::{hypervisor, hypervisor_cpuid, Hypervisor};
+use crate::x86::msr::msr_write;
+use crate::x86::tsc::rdtsc;
+use crate::{error, info};
+
+/// `CPUID` leaf reporting the paravirtual features offered by KVM.
+const KVM_CPUID_FEATURES: u32 = 0x4000_0001;
+
+/// `kvmclock` is available, using [`MSR_KVM_SYSTEM_TIME`].
+const KVM_FEATURE_CLOCKSOURCE: u32 = 1 << 0;
+
+/// `kvmclock` is available, using [`MSR_KVM_SYSTEM_TIME_NEW`].
+const KVM_FEATURE_CLOCKSOURCE2: u32 = 1 << 3;
+
+/// `PV EOI` is available.
+const KVM_FEATURE_PV_EOI: u32 = 1 << 6;
+
+const MSR_KVM_SYSTEM_TIME: u32 = 0x12;
+const MSR_KVM_SYSTEM_TIME_NEW: u32 = 0x4B56_4D01;
+const MSR_KVM_PV_EOI_EN: u32 = 0x4B56_4D04;
+
+/// Enables the feature whose memory area is written to a KVM MSR.
+const KVM_MSR_ENABLED: u64 = 1 << 0;
+
+/// Set by the hypervisor in [`PV_EOI`] when the end of the current interrupt does not need to be signaled.
+const KVM_PV_EOI_ENABLED: u32 = 1 << 0;
+
+static KVMCLOCK: PvclockArea = PvclockArea(UnsafeCell::new(PvclockTimeInfo::zeroed()));
+
+/// MSR used to register [`KVMCLOCK`], or 0 if `kvmclock` is not enabled.
+static KVMCLOCK_MSR: AtomicU32 = AtomicU32::new(0);
+
+static PV_EOI: AtomicU32 = AtomicU32::new(0);
+
+static PV_EOI_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Time information shared with the hypervisor (`pvclock_vcpu_time_info`).
+///
+/// The hypervisor makes `version` odd while updating the structure.
+#[derive(Clone, Copy, Debug)]
+#[repr(C, align(32))]
+pub struct PvclockTimeInfo {
+    version: u32,
+    pad0: u32,
+
+    /// Value of the TSC when `system_time` was updated.
+    tsc_timestamp: u64,
+
+    /// Time elapsed since the start of the virtual machine, in nanoseconds.
+    system_time: u64,
+
+    /// Multiplier converting TSC ticks (shifted by `tsc_shift`) to nanoseconds, as a 32.32 fixed-point number.
+    tsc_to_system_mul: u32,
+    tsc_shift: i8,
+    flags: u8,
+    pad: [u8; 2],
+}
+
+impl PvclockTimeInfo {
+    const fn zeroed() -> Self {
+        Self {
+            version: 0,
+            pad0: 0,
+            tsc_timestamp: 0,
+            system_time: 0,
+            tsc_to_system_mul: 0,
+            tsc_shift: 0,
+            flags: 0,
+            pad: [0; 2],
+        }
+    }
+
+    /// Converts a TSC value to the time elapsed since the start of the virtual machine, in nanoseconds.
+    fn tsc_to_ns(&self, tsc: u64) -> u64 {
+        let mut delta = tsc.wrapping_sub(self.tsc_timestamp);
+
+        if self.tsc_shift < 0 {
+            delta >>= self.tsc_shift.unsigned_abs();
+        } else {
+            delta <<= self.tsc_shift;
+        }
+
+        let scaled = (u128::from(delta) * u128::from(self.tsc_to_system_mul)) >> 32;
+
+        self.system_time.wrapping_add(scaled as u64)
+    }
+}
+
+/// Memory area updated by the hypervisor.
+struct PvclockArea(UnsafeCell<PvclockTimeInfo>);
+
+unsafe impl Sync for PvclockArea {}
+
+impl PvclockArea {
+    /// Reads a consistent copy of the structure, retrying while the hypervisor updates it.
+    fn read(&self) -> (PvclockTimeInfo, u64) {
+        let info = self.0.get();
+
+        loop {
+            let version = unsafe { read_volatile(addr_of!((*info).version)) };
+            if version & 1 != 0 {
+                core::hint::spin_loop();
+                continue;
+            }
+
+            fence(Ordering::Acquire);
+            let copy = unsafe { read_volatile(info) };
+            let tsc = rdtsc();
+            fence(Ordering::Acquire);
+
+            if unsafe { read_volatile(addr_of!((*info).version)) } == version {
+                return (copy, tsc);
+            }
+        }
+    }
+}
+
+/// Enables the KVM paravirtual features offered by the hypervisor, if running as a KVM guest.
+pub fn kvm_init() {
+    if hypervisor() != Some(Hypervisor::Kvm) {
+        return;
+    }
+
+    let features = hypervisor_cpuid(KVM_CPUID_FEATURES).map_or(0, |leaf| leaf[0]);
+
+    let clock_msr = if features & KVM_FEATURE_CLOCKSOURCE2 != 0 {
+        Some(MSR_KVM_SYSTEM_TIME_NEW)
+    } else if features & KVM_FEATURE_CLOCKSOURCE != 0 {
+        Some(MSR_KVM_SYSTEM_TIME)
+    } else {
+        None
+    };
+
+    if let Some(msr) = clock_msr {
+        unsafe { msr_write(msr, image_phys_addr(KVMCLOCK.0.get()) | KVM_MSR_ENABLED) };
+        KVMCLOCK_MSR.store(msr, Ordering::Release);
+    }
+
+    if features & KVM_FEATURE_PV_EOI != 0 {
+        unsafe {
+            msr_write(
+                MSR_KVM_PV_EOI_EN,
+                image_phys_addr(PV_EOI.as_ptr()) | KVM_MSR_ENABLED,
+            )
+        };
+        PV_EOI_ENABLED.store(true, Ordering::Release);
+    }
+
+    info!(
+        "kvm",
+        "running as a KVM guest (features = {:#x})    kvmclock = {}    pv_eoi = {}",
+        features,
+        clock_msr.is_some(),
+        pv_eoi_enabled()
+    );
+}
+
+/// Disables the KVM paravirtual features, so that the hypervisor stops writing to the memory registered by
+/// [`kvm_init`].
+///
+/// If `kvmclock` is the current clock source, the TSC or the HPET is used from then on.
+pub fn kvm_handoff() {
+    if clocksource_leave_kvmclock().is_err() {
+        error!("kvm", "no clock source left once kvmclock is disabled");
+    }
+
+    let clock_msr = KVMCLOCK_MSR.swap(0, Ordering::AcqRel);
+    if clock_msr != 0 {
+        unsafe { msr_write(clock_msr, 0) };
+    }
+
+    if PV_EOI_ENABLED.swap(false, Ordering::AcqRel) {
+        unsafe { msr_write(MSR_KVM_PV_EOI_EN, 0) };
+    }
+}
+
+/// Checks whether `kvmclock` is enabled.
+pub fn kvmclock_available() -> bool {
+    KVMCLOCK_MSR.load(Ordering::Acquire) != 0
+}
+
+/// Returns the time elapsed since the start of the virtual machine, in microseconds, or `None` if `kvmclock` is not
+/// enabled.
+pub fn kvmclock_time() -> Option<f64> {
+    if !kvmclock_available() {
+        return None;
+    }
+
+    let (info, tsc) = KVMCLOCK.read();
+
+    Some(info.tsc_to_ns(tsc) as f64 / 1000.)
+}
+
+/// Checks whether `PV EOI` is enabled.
+pub fn pv_eoi_enabled() -> bool {
+    PV_EOI_ENABLED.load(Ordering::Acquire)
+}
+
+/// Acknowledges the current interrupt using `PV EOI`.
+///
+/// Returns `true` if the hypervisor did not require the end of interrupt to be signaled to the local APIC, in which
+/// case the `EOI` register must not be written.
+pub fn pv_eoi_ack() -> bool {
+    pv_eoi_enabled()
+        && PV_EOI.fetch_and(!KVM_PV_EOI_ENABLED, Ordering::AcqRel) & KVM_PV_EOI_ENABLED != 0
+}
+
+/// Returns the physical address of a static variable of the running image.
+///
+/// The bootloader is identity mapped, while the kernel image is mapped at [`KERNEL_CODE_MAPPING_BASE`].
+///
+/// [`KERNEL_CODE_MAPPING_BASE`]: crate::kernel_syms::KERNEL_CODE_MAPPING_BASE
+fn image_phys_addr<T>(ptr: *const T) -> u64 {
+    #[cfg(feature = "x86_64")]
+    return u64::from(crate::kernel_syms::KERNEL_LOAD_ADDR) + ptr as u64
+        - u64::from(crate::kernel_syms::KERNEL_CODE_MAPPING_BASE);
+
+    #[cfg(not(feature = "x86_64"))]
+    return ptr as u64;
+}
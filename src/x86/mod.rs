@@ -6,7 +6,11 @@
 #![allow(clippy::semicolon_if_nothing_returned)]
 
 pub mod cpuid;
+pub mod errata;
 pub mod flags;
+pub mod hypervisor;
+pub mod idle;
+pub mod kvm;
 pub mod msr;
 pub mod tsc;
 
@@ -19,6 +23,8 @@ pub mod apic;
 pub mod descriptors;
 #[cfg(feature = "alloc")]
 pub mod paging;
+#[cfg(all(feature = "alloc", feature = "x86_64"))]
+pub mod pmu;
 pub mod privilege;
 pub mod registers;
 
@@ -42,4 +48,15 @@ pub mod int {
             asm!("sti");
         }
     }
+
+    /// Enables interrupts and halts the CPU until the next interrupt.
+    ///
+    /// No interrupt can be delivered between both instructions, so that an interrupt raised after interrupts were
+    /// disabled to check a wake-up condition cannot be missed.
+    #[inline]
+    pub fn enable_interrupts_and_halt() {
+        unsafe {
+            asm!("sti", "hlt");
+        }
+    }
 }
@@ -113,6 +113,10 @@ impl Frame {
     pub(crate) fn new(start: PhyAddr) -> Self {
         Self { addr: start }
     }
+
+    pub(crate) fn addr(self) -> PhyAddr {
+        self.addr
+    }
 }
 
 impl From<FrameAllocation> for Frame {
@@ -145,7 +149,8 @@ pub mod bootinit_paging {
     ///
     /// Enables 64-bit level 4 paging if supported.
     /// Identity maps the physical memory, and also maps it to the virtual segment starting at [`KERNEL_PHYS_MAPPING_BASE`].
-    /// Disables interrupts (the `IDT` has to be updated to support 64-bit).
+    /// Disables interrupts: the protected mode `IDT` is unusable from now on, until the long mode table is loaded (see
+    /// [`crate::x86::descriptors::bootinit_idt`]).
     #[allow(clippy::missing_panics_doc)]
     pub fn init_paging() {
         identity_map_phys_level4(0, PhyAddr::new(0));
@@ -12,8 +12,10 @@ use conquer_once::spin::OnceCell;
 use spin::Mutex;
 
 use crate::kernel_syms;
-use crate::mem::e820::{AddressRangeDescriptor, E820MemType, E820MemoryMap};
-use crate::mem::{MemoryAddress, PhyAddr};
+use crate::mem::e820::{init_memory_regions, E820MemoryMap, MemoryRegionKind};
+#[cfg(feature = "mem_shadow")]
+use crate::mem::shadow::{shadow_init, shadow_mark_allocated, shadow_mark_free};
+use crate::mem::{oom, stats, MemoryAddress, PhyAddr};
 use crate::x86::paging::page_table::mapper::{MemoryMapping, PhysicalMemoryMapping};
 use core::cmp::{max, min};
 use core::mem::MaybeUninit;
@@ -22,6 +24,9 @@ use core::sync::atomic::{AtomicPtr, Ordering};
 
 pub const MAX_PHYSICAL_MEM_BLK_SIZE: usize = 0x20000000;
 
+/// Size of the low memory area (real-mode structures, BIOS data), never handed out by the allocator.
+const LOW_MEMORY_SIZE: u64 = 0x100000;
+
 /// Defines the basic set of operations that should be offered by a physical memory allocator (_Frame_ allocator)
 pub trait FrameAllocator {
     /// Allocates a `Frame` (contiguous area of physical memory) from the physical memory pool associated with this
@@ -72,6 +77,10 @@ pub unsafe extern "C" fn pm_alloc(alloc_size: usize) -> *mut u8 {
         let allocation_attempt = mem_pool.lock().allocate(alloc_size);
 
         if let Ok(alloc) = allocation_attempt {
+            stats::record_frame_alloc(alloc.length);
+            #[cfg(feature = "mem_shadow")]
+            shadow_mark_allocated(alloc.start, alloc.length);
+
             alloc.start.as_mut_ptr()
         } else {
             null_mut()
@@ -84,6 +93,10 @@ pub unsafe extern "C" fn pm_alloc(alloc_size: usize) -> *mut u8 {
 #[no_mangle]
 pub unsafe extern "C" fn pm_free(alloc_base: *mut u8, alloc_size: usize) {
     if let Some(mem_pool) = PHYSICAL_MEMORY_POOL.get() {
+        stats::record_frame_free(alloc_size);
+        #[cfg(feature = "mem_shadow")]
+        shadow_mark_free(PhyAddr::from(alloc_base), alloc_size);
+
         mem_pool.lock().deallocate(FrameAllocation {
             start: PhyAddr::from(alloc_base),
             length: alloc_size,
@@ -91,26 +104,35 @@ pub unsafe extern "C" fn pm_free(alloc_base: *mut u8, alloc_size: usize) {
     }
 }
 
+/// Initializes the physical memory pool, from the largest usable memory region.
+///
+/// The memory used by the Kernel image (`kernel_size` bytes, from [`kernel_syms::KERNEL_LOAD_ADDR`]) is excluded
+/// from the pool.
 #[no_mangle]
-pub unsafe extern "C" fn init_phys_memory_pool(memory_map: E820MemoryMap) {
-    let mut largest_ram_segment = AddressRangeDescriptor::default();
-
-    for entry in memory_map {
-        if matches!(entry.addr_type, E820MemType::RAM)
-            && entry.length() > largest_ram_segment.length()
-        {
-            largest_ram_segment = entry;
-        }
-    }
-
-    let mut segment_base = PhyAddr::from(largest_ram_segment.base_addr());
-
-    // check if the kernel mapping is located inside the largest ram segment
-    if kernel_syms::KERNEL_LOAD_ADDR > segment_base
-        && kernel_syms::KERNEL_LOAD_ADDR < segment_base + largest_ram_segment.length()
-    {
-        segment_base = kernel_syms::KERNEL_LOAD_ADDR + kernel_syms::KERNEL_SECTOR_SZ * 0x200;
-    }
+pub unsafe extern "C" fn init_phys_memory_pool(memory_map: E820MemoryMap, kernel_size: u64) {
+    let mut regions = init_memory_regions(memory_map).lock();
+
+    regions
+        .reserve(0, LOW_MEMORY_SIZE, MemoryRegionKind::Reserved)
+        .expect("failed to reserve low memory");
+    regions
+        .reserve(
+            u64::from(kernel_syms::KERNEL_LOAD_ADDR),
+            kernel_size,
+            MemoryRegionKind::Kernel,
+        )
+        .expect("failed to reserve kernel image memory");
+
+    let largest_ram_segment = regions
+        .largest_usable()
+        .expect("no usable physical memory available");
+
+    let segment_base = PhyAddr::new(largest_ram_segment.base);
+
+    stats::set_physical_memory_size(min(
+        MAX_PHYSICAL_MEM_BLK_SIZE,
+        usize::try_from(largest_ram_segment.length).unwrap_or(usize::MAX),
+    ));
 
     assert!(
         !PHYSICAL_MEMORY_POOL.is_initialized(),
@@ -124,19 +146,47 @@ pub unsafe extern "C" fn init_phys_memory_pool(memory_map: E820MemoryMap) {
             MAX_PHYSICAL_MEM_BLK_SIZE,
         ))
     });
+
+    #[cfg(feature = "mem_shadow")]
+    shadow_init(
+        segment_base,
+        usize::try_from(largest_ram_segment.length).unwrap_or(usize::MAX),
+        &regions,
+    );
 }
 
 // TODO: add allocation flags (urgent allocation that panic if lock is held, ...)
 pub fn alloc_page(alloc_size: usize) -> Result<FrameAllocation, FrameAllocationError> {
     if let Some(mem_pool) = PHYSICAL_MEMORY_POOL.get() {
-        mem_pool.lock().allocate(alloc_size)
+        let alloc = mem_pool.lock().allocate(alloc_size)?;
+        stats::record_frame_alloc(alloc.length);
+        #[cfg(feature = "mem_shadow")]
+        shadow_mark_allocated(alloc.start, alloc.length);
+
+        Ok(alloc)
     } else {
         Err(FrameAllocationError::NoAvailableFrame)
     }
 }
 
+/// Allocates physical memory, calling the registered OOM handlers if no frame of appropriate size is available.
+///
+/// Contrary to [`alloc_page`], this may end up releasing heap memory, and therefore must not be called with the
+/// kernel heap lock held.
+///
+/// # Errors
+///
+/// Returns [`FrameAllocationError::NoAvailableFrame`] if the allocation still failed after reclaiming memory.
+pub fn try_alloc_page(alloc_size: usize) -> Result<FrameAllocation, FrameAllocationError> {
+    oom::with_reclaim(alloc_size, || alloc_page(alloc_size))
+}
+
 pub fn free_page(alloc: FrameAllocation) {
     if let Some(mem_pool) = PHYSICAL_MEMORY_POOL.get() {
+        stats::record_frame_free(alloc.length);
+        #[cfg(feature = "mem_shadow")]
+        shadow_mark_free(alloc.start, alloc.length);
+
         mem_pool.lock().deallocate(alloc)
     }
 }
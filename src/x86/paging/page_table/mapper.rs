@@ -1,4 +1,5 @@
 use crate::kernel_syms::PAGE_SIZE;
+use crate::mem::stats::{self, MemoryConsumer};
 use crate::mem::{MemoryAddress, PhyAddr, VirtAddr};
 use crate::x86::paging::page_alloc::frame_alloc::alloc_page;
 use crate::x86::paging::page_table::translate::Translator;
@@ -151,6 +152,49 @@ impl<T: Translator, M: MemoryMapping> PageTableMapper<T, M> {
         Ok(())
     }
 
+    /// Removes the mapping of a 4 KB [`Page`], and returns the [`Frame`] it was mapped to.
+    ///
+    /// Returns `None` if the page is not mapped, or if it is part of a larger page.
+    pub(crate) fn unmap_4kb_page(&mut self, page: Page) -> Option<Frame> {
+        let entry = self.get_4kb_page_entry(page)?;
+
+        let frame = entry.frame();
+        *entry = PageTableEntry::EMPTY_ENTRY;
+        invalidate_tlb_entry(page.start);
+
+        Some(frame)
+    }
+
+    /// Returns the [`Frame`] a 4 KB [`Page`] is mapped to.
+    ///
+    /// Returns `None` if the page is not mapped, or if it is part of a larger page.
+    pub(crate) fn translate_4kb_page(&mut self, page: Page) -> Option<Frame> {
+        self.get_4kb_page_entry(page).map(|entry| entry.frame())
+    }
+
+    /// Returns the used page table entry mapping a 4 KB [`Page`], if any.
+    fn get_4kb_page_entry(&mut self, page: Page) -> Option<&mut PageTableEntry> {
+        let translated_addr = T::translate_address(page.start);
+        let mut table = self.pml4.as_mut();
+
+        for offset in [
+            translated_addr.pml4_offset(),
+            translated_addr.pdpte_offset(),
+            translated_addr.pde_offset(),
+        ] {
+            let entry = table.get_mut(offset);
+            if !entry.used() || entry.flags().huge_page() {
+                return None;
+            }
+
+            table = PageTableMapper::<T, M>::get_next_table(self.phys_mapping, entry).ok()?;
+        }
+
+        let entry = table.get_mut(translated_addr.pte_offset());
+
+        entry.used().then_some(entry)
+    }
+
     fn get_or_create_entry(
         mapping: M,
         entry: &mut PageTableEntry,
@@ -159,6 +203,7 @@ impl<T: Translator, M: MemoryMapping> PageTableMapper<T, M> {
         if !entry.used() {
             let table_addr =
                 alloc_page(PAGE_SIZE).map_err(|_| PageTableCreationError::AllocationError)?;
+            stats::charge(MemoryConsumer::PageTables, PAGE_SIZE);
             unsafe {
                 *mapping.convert(table_addr.start).as_mut_ptr::<PageTable>() = PageTable::default()
             }
@@ -225,6 +270,7 @@ impl<T: Translator, M: MemoryMapping> PageTableMapper<T, M> {
                 .unwrap()
             } else {
                 let page_table_dir_addr = alloc_page(PAGE_SIZE).unwrap();
+                stats::charge(MemoryConsumer::PageTables, PAGE_SIZE);
                 *self
                     .phys_mapping
                     .convert(page_table_dir_addr.start)
@@ -256,6 +302,7 @@ impl<T: Translator, M: MemoryMapping> PageTableMapper<T, M> {
                         .unwrap()
                     } else {
                         let directory_ptr_table_entry_addr = alloc_page(PAGE_SIZE).unwrap();
+                        stats::charge(MemoryConsumer::PageTables, PAGE_SIZE);
                         page_table_dir
                             .get_mut(directory_ptr_table_entry_id)
                             .map_to_addr(
@@ -334,6 +381,7 @@ impl<T: Translator, M: MemoryMapping> PageTableMapper<T, M> {
                                 .unwrap()
                             } else {
                                 let directory_entry_addr = alloc_page(PAGE_SIZE).unwrap();
+                                stats::charge(MemoryConsumer::PageTables, PAGE_SIZE);
                                 directory_ptr_table_entry
                                     .get_mut(directory_entry_id)
                                     .map_to_addr(
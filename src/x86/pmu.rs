@@ -0,0 +1,649 @@
+//! Performance monitoring unit (`PMU`).
+//!
+//! Intel processors implementing architectural performance monitoring provide general-purpose counters, which can
+//! count any of the architectural events (see [`PmuEvent`]), and fixed-function counters, which each count a single
+//! event (instructions retired, core cycles, reference cycles).
+//!
+//! Counters are used in two ways:
+//!
+//! - counting: [`perf_counter_start`] programs a counter on the current processor, which is read back with
+//!   [`PerfCounter::read`]. Fixed-function counters are used when they can count the requested event.
+//! - sampling: [`profiler_start`] programs a general-purpose counter to overflow every `period` events. Each overflow
+//!   raises a performance monitoring interrupt (delivered as an `NMI`, like the NMI watchdog), which records the
+//!   call stack of the interrupted code in a per-CPU buffer. [`perf_report`] aggregates the samples by kernel symbol,
+//!   to locate hotspots, and [`perf_folded`] exports the call stacks for flamegraph tools.
+//!
+//! The first general-purpose counter is reserved for the NMI watchdog (see [`watchdog`]), and the second one for
+//! sampling.
+//!
+//! # Examples
+//!
+//! ```
+//! use fzboot::x86::pmu::{perf_report, profiler_start, profiler_stop, PmuEvent};
+//!
+//! profiler_start(PmuEvent::CoreCycles, 100_000)?;
+//! render_console();
+//! profiler_stop();
+//!
+//! let mut report = String::new();
+//! perf_report(&mut report, 20)?;
+//! ```
+//!
+//! To draw a flamegraph of the samples, the folded stacks are streamed over the serial port with
+//! [`perf_export_serial`], captured on the host (`-serial file:perf.folded` with `QEMU`), and rendered with
+//! `inferno-flamegraph perf.folded > perf.svg`.
+//!
+//! [`watchdog`]: crate::exceptions::watchdog
+
+use alloc::alloc::{alloc_zeroed, handle_alloc_error};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::fmt::{self, Write};
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+use crate::errors::{CanFail, PmuError, SerialError};
+use crate::io::serial::COM1;
+use crate::irq::InterruptStackFrame;
+use crate::module::symbols::kernel_symbol_at;
+use crate::x86::apic::local_apic::{local_apic, ProcLocalApicID};
+use crate::x86::cpuid::cpu_id;
+use crate::x86::msr::{msr_read, msr_write};
+
+/// Number of samples kept for each processor. Samples recorded once the buffer is full are lost.
+pub const PMU_SAMPLE_BUFFER_LEN: usize = 2048;
+
+/// Maximum number of frames recorded in the call stack of a sample.
+pub const PMU_STACK_DEPTH: usize = 16;
+
+/// Baud rate of the serial port used by [`perf_export_serial`].
+const PERF_EXPORT_BAUD: u32 = 115_200;
+
+const IA32_PMC0: u32 = 0xC1;
+const IA32_PERFEVTSEL0: u32 = 0x186;
+const IA32_FIXED_CTR0: u32 = 0x309;
+const IA32_FIXED_CTR_CTRL: u32 = 0x38D;
+const IA32_PERF_GLOBAL_STATUS: u32 = 0x38E;
+const IA32_PERF_GLOBAL_CTRL: u32 = 0x38F;
+const IA32_PERF_GLOBAL_OVF_CTRL: u32 = 0x390;
+
+const EVTSEL_USR: u64 = 1 << 16;
+const EVTSEL_OS: u64 = 1 << 17;
+const EVTSEL_INT: u64 = 1 << 20;
+const EVTSEL_EN: u64 = 1 << 22;
+
+/// Counts in ring 0 and ring 3 (4-bit field of `IA32_FIXED_CTR_CTRL`, for each fixed-function counter).
+const FIXED_CTRL_OS_USR: u64 = 0b0011;
+
+/// Bit of the fixed-function counters in the global control and status registers.
+const GLOBAL_FIXED_SHIFT: u32 = 32;
+
+/// General-purpose counter used by the NMI watchdog.
+const WATCHDOG_COUNTER: u32 = 0;
+
+/// General-purpose counter used for sampling.
+const SAMPLING_COUNTER: u32 = 1;
+
+/// Counters in use, as in the global control register (general-purpose counters in the low bits, fixed-function
+/// counters starting at bit 32).
+static COUNTERS_IN_USE: AtomicU64 = AtomicU64::new(1 << WATCHDOG_COUNTER | 1 << SAMPLING_COUNTER);
+
+static SAMPLING: AtomicBool = AtomicBool::new(false);
+
+/// Value loaded in the sampling counter after each overflow.
+static SAMPLE_RELOAD: AtomicU64 = AtomicU64::new(0);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_SAMPLE_BUFFER: AtomicPtr<SampleBuffer> = AtomicPtr::new(ptr::null_mut());
+
+/// Sample buffer of each processor, by local APIC identifier.
+static SAMPLE_BUFFERS: [AtomicPtr<SampleBuffer>; 256] = [NO_SAMPLE_BUFFER; 256];
+
+/// Architectural performance monitoring event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PmuEvent {
+    /// Unhalted core cycles.
+    CoreCycles,
+
+    InstructionsRetired,
+
+    /// Unhalted reference cycles (at the TSC frequency).
+    ReferenceCycles,
+
+    /// Requests to the last level cache.
+    LlcReferences,
+
+    /// Requests missing the last level cache.
+    LlcMisses,
+
+    BranchesRetired,
+
+    /// Mispredicted branches retired.
+    BranchMisses,
+}
+
+impl PmuEvent {
+    /// Returns the event number and unit mask of the event.
+    fn encoding(self) -> (u64, u64) {
+        match self {
+            Self::CoreCycles => (0x3C, 0x00),
+            Self::InstructionsRetired => (0xC0, 0x00),
+            Self::ReferenceCycles => (0x3C, 0x01),
+            Self::LlcReferences => (0x2E, 0x4F),
+            Self::LlcMisses => (0x2E, 0x41),
+            Self::BranchesRetired => (0xC4, 0x00),
+            Self::BranchMisses => (0xC5, 0x00),
+        }
+    }
+
+    /// Bit of the event in the availability vector (`CPUID` leaf `0xA`, `EBX`).
+    fn cpuid_bit(self) -> u32 {
+        match self {
+            Self::CoreCycles => 0,
+            Self::InstructionsRetired => 1,
+            Self::ReferenceCycles => 2,
+            Self::LlcReferences => 3,
+            Self::LlcMisses => 4,
+            Self::BranchesRetired => 5,
+            Self::BranchMisses => 6,
+        }
+    }
+
+    /// Fixed-function counter counting this event, if any.
+    fn fixed_counter(self) -> Option<u32> {
+        match self {
+            Self::InstructionsRetired => Some(0),
+            Self::CoreCycles => Some(1),
+            Self::ReferenceCycles => Some(2),
+            _ => None,
+        }
+    }
+}
+
+/// Performance monitoring capabilities of the processor.
+#[derive(Clone, Copy, Debug)]
+pub struct PmuInfo {
+    /// Version of the architectural performance monitoring.
+    pub version: u8,
+
+    /// Number of general-purpose counters.
+    pub counters: u32,
+
+    /// Width of the general-purpose counters, in bits.
+    pub width: u32,
+
+    /// Number of fixed-function counters.
+    pub fixed_counters: u32,
+
+    /// Bit vector of the unavailable architectural events.
+    unavailable_events: u32,
+
+    /// Length of the bit vector of architectural events.
+    events_len: u32,
+}
+
+impl PmuInfo {
+    /// Reads the performance monitoring capabilities of the processor.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`PmuError::NotSupported`] if the processor does not support architectural performance monitoring
+    /// version 2 or later (which introduced the global control registers).
+    pub fn current() -> Result<Self, PmuError> {
+        let [eax, ebx, _, edx] = cpu_id(0xA).ok_or(PmuError::NotSupported)?;
+
+        let info = Self {
+            version: (eax & 0xFF) as u8,
+            counters: (eax >> 8) & 0xFF,
+            width: (eax >> 16) & 0xFF,
+            fixed_counters: edx & 0x1F,
+            unavailable_events: ebx,
+            events_len: (eax >> 24) & 0xFF,
+        };
+
+        if info.version < 2 || info.width == 0 {
+            return Err(PmuError::NotSupported);
+        }
+
+        Ok(info)
+    }
+
+    /// Whether the processor can count `event`.
+    pub fn supports(&self, event: PmuEvent) -> bool {
+        let bit = event.cpuid_bit();
+
+        bit < self.events_len && self.unavailable_events & (1 << bit) == 0
+    }
+}
+
+/// Counter programmed on the current processor.
+///
+/// The counter keeps counting until it is stopped with [`PerfCounter::stop`].
+#[derive(Debug)]
+pub struct PerfCounter {
+    /// Bit of the counter in the global control register.
+    global_bit: u32,
+    event: PmuEvent,
+}
+
+impl PerfCounter {
+    /// Event counted by this counter.
+    pub fn event(&self) -> PmuEvent {
+        self.event
+    }
+
+    /// Returns the number of events counted since the counter was started.
+    pub fn read(&self) -> u64 {
+        msr_read(self.counter_msr()).unwrap_or_default()
+    }
+
+    /// Stops the counter, and returns the number of events counted.
+    pub fn stop(self) -> u64 {
+        let count = self.read();
+        let global_ctrl = msr_read(IA32_PERF_GLOBAL_CTRL).unwrap_or_default();
+
+        unsafe {
+            msr_write(IA32_PERF_GLOBAL_CTRL, global_ctrl & !(1 << self.global_bit));
+
+            if let Some(fixed) = self.global_bit.checked_sub(GLOBAL_FIXED_SHIFT) {
+                let fixed_ctrl = msr_read(IA32_FIXED_CTR_CTRL).unwrap_or_default();
+                msr_write(IA32_FIXED_CTR_CTRL, fixed_ctrl & !(0xF << (4 * fixed)));
+            } else {
+                msr_write(IA32_PERFEVTSEL0 + self.global_bit, 0);
+            }
+        }
+
+        COUNTERS_IN_USE.fetch_and(!(1 << self.global_bit), Ordering::AcqRel);
+
+        count
+    }
+
+    fn counter_msr(&self) -> u32 {
+        match self.global_bit.checked_sub(GLOBAL_FIXED_SHIFT) {
+            Some(fixed) => IA32_FIXED_CTR0 + fixed,
+            None => IA32_PMC0 + self.global_bit,
+        }
+    }
+}
+
+/// Starts counting `event` on the current processor.
+///
+/// # Errors
+///
+/// Returns [`PmuError::NotSupported`] if performance monitoring is not supported, [`PmuError::EventUnavailable`] if
+/// the processor cannot count `event`, or [`PmuError::NoCounterAvailable`] if every counter able to count `event` is
+/// in use.
+pub fn perf_counter_start(event: PmuEvent) -> Result<PerfCounter, PmuError> {
+    let info = PmuInfo::current()?;
+
+    if !info.supports(event) {
+        return Err(PmuError::EventUnavailable);
+    }
+
+    let fixed = event
+        .fixed_counter()
+        .filter(|&fixed| fixed < info.fixed_counters)
+        .map(|fixed| fixed + GLOBAL_FIXED_SHIFT);
+    let general = (0..info.counters.min(GLOBAL_FIXED_SHIFT))
+        .filter(|&counter| counter != WATCHDOG_COUNTER && counter != SAMPLING_COUNTER);
+
+    let global_bit = fixed
+        .into_iter()
+        .chain(general)
+        .find(|&bit| COUNTERS_IN_USE.fetch_or(1 << bit, Ordering::AcqRel) & (1 << bit) == 0)
+        .ok_or(PmuError::NoCounterAvailable)?;
+
+    let counter = PerfCounter { global_bit, event };
+
+    unsafe {
+        msr_write(counter.counter_msr(), 0);
+
+        if let Some(fixed) = global_bit.checked_sub(GLOBAL_FIXED_SHIFT) {
+            let fixed_ctrl =
+                msr_read(IA32_FIXED_CTR_CTRL).unwrap_or_default() & !(0xF << (4 * fixed));
+            msr_write(
+                IA32_FIXED_CTR_CTRL,
+                fixed_ctrl | FIXED_CTRL_OS_USR << (4 * fixed),
+            );
+        } else {
+            let (event, umask) = event.encoding();
+            msr_write(
+                IA32_PERFEVTSEL0 + global_bit,
+                event | umask << 8 | EVTSEL_USR | EVTSEL_OS | EVTSEL_EN,
+            );
+        }
+
+        let global_ctrl = msr_read(IA32_PERF_GLOBAL_CTRL).unwrap_or_default();
+        msr_write(IA32_PERF_GLOBAL_CTRL, global_ctrl | 1 << global_bit);
+    }
+
+    Ok(counter)
+}
+
+/// Samples recorded on a processor.
+///
+/// Each sample is the call stack of the interrupted code, from the innermost frame (the interrupted instruction), and
+/// padded with zeros.
+struct SampleBuffer {
+    stacks: [[AtomicU64; PMU_STACK_DEPTH]; PMU_SAMPLE_BUFFER_LEN],
+
+    /// Number of samples recorded (may exceed the length of the buffer).
+    len: AtomicUsize,
+}
+
+impl SampleBuffer {
+    /// Allocates an empty buffer.
+    ///
+    /// The buffer is too large to be built on the stack: it is allocated zeroed instead, which is a valid empty buffer.
+    fn alloc() -> *mut Self {
+        let layout = Layout::new::<Self>();
+
+        let buffer = unsafe { alloc_zeroed(layout) }.cast::<Self>();
+        if buffer.is_null() {
+            handle_alloc_error(layout);
+        }
+
+        buffer
+    }
+
+    /// Records the call stack of the code interrupted by `frame`.
+    ///
+    /// The stack is walked through the saved frame pointers, for kernel code only.
+    fn record(&self, frame: &InterruptStackFrame) {
+        let index = self.len.fetch_add(1, Ordering::AcqRel);
+        let Some(stack) = self.stacks.get(index) else {
+            return;
+        };
+
+        let mut frame_ptr = frame.registers.rbp;
+        let user = frame.cs & 0b11 != 0;
+
+        for (depth, slot) in stack.iter().enumerate() {
+            let addr = match depth {
+                0 => u64::from(frame.rip),
+
+                // Kernel stacks are located in the higher half of the address space.
+                _ if user || frame_ptr & 1 << 63 == 0 || frame_ptr % 8 != 0 => 0,
+
+                _ => {
+                    let (next, return_addr) = unsafe {
+                        let base = frame_ptr as *const u64;
+                        (*base, *base.add(1))
+                    };
+
+                    // The stack grows downwards: the frame of the caller must be above the current one.
+                    frame_ptr = if next > frame_ptr { next } else { 0 };
+                    return_addr
+                }
+            };
+
+            slot.store(addr, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the recorded call stacks, from the innermost frame.
+    fn stacks(&self) -> impl Iterator<Item = impl Iterator<Item = u64> + '_> + '_ {
+        let len = self.len.load(Ordering::Acquire).min(PMU_SAMPLE_BUFFER_LEN);
+
+        self.stacks[..len].iter().map(|stack| {
+            stack
+                .iter()
+                .map(|addr| addr.load(Ordering::Relaxed))
+                .take_while(|&addr| addr != 0)
+        })
+    }
+
+    fn lost(&self) -> usize {
+        self.len
+            .load(Ordering::Acquire)
+            .saturating_sub(PMU_SAMPLE_BUFFER_LEN)
+    }
+}
+
+/// Returns the sample buffer of each processor that was sampled.
+fn sample_buffers() -> impl Iterator<Item = &'static SampleBuffer> {
+    SAMPLE_BUFFERS
+        .iter()
+        .filter_map(|slot| unsafe { slot.load(Ordering::Acquire).as_ref() })
+}
+
+/// Starts sampling the current processor, every `period` occurrences of `event`.
+///
+/// The samples previously recorded on this processor are discarded.
+///
+/// # Errors
+///
+/// Returns [`PmuError::NotSupported`] if performance monitoring is not supported (or if the processor has a single
+/// general-purpose counter), [`PmuError::EventUnavailable`] if the processor cannot count `event`, or
+/// [`PmuError::NoLocalApic`] if overflow interrupts cannot be delivered.
+pub fn profiler_start(event: PmuEvent, period: u64) -> Result<(), PmuError> {
+    let info = PmuInfo::current()?;
+
+    if info.counters <= SAMPLING_COUNTER {
+        return Err(PmuError::NotSupported);
+    }
+    if !info.supports(event) {
+        return Err(PmuError::EventUnavailable);
+    }
+
+    let lapic = local_apic().ok_or(PmuError::NoLocalApic)?;
+
+    let slot = &SAMPLE_BUFFERS[usize::from(u8::from(ProcLocalApicID::get()))];
+    if slot.load(Ordering::Acquire).is_null() {
+        slot.store(SampleBuffer::alloc(), Ordering::Release);
+    }
+    unsafe { &*slot.load(Ordering::Acquire) }
+        .len
+        .store(0, Ordering::Release);
+
+    // Only the low 32 bits of the counters can be written (sign-extended to the counter width).
+    let period = period.clamp(1, i32::MAX as u64);
+    let reload = period.wrapping_neg() & ((1 << info.width) - 1);
+    SAMPLE_RELOAD.store(reload, Ordering::Relaxed);
+
+    lapic.set_perf_counter_nmi(true);
+
+    let (event, umask) = event.encoding();
+    unsafe {
+        msr_write(IA32_PERFEVTSEL0 + SAMPLING_COUNTER, 0);
+        msr_write(IA32_PMC0 + SAMPLING_COUNTER, reload);
+        msr_write(
+            IA32_PERFEVTSEL0 + SAMPLING_COUNTER,
+            event | umask << 8 | EVTSEL_USR | EVTSEL_OS | EVTSEL_INT | EVTSEL_EN,
+        );
+
+        let global_ctrl = msr_read(IA32_PERF_GLOBAL_CTRL).unwrap_or_default();
+        msr_write(IA32_PERF_GLOBAL_CTRL, global_ctrl | 1 << SAMPLING_COUNTER);
+    }
+
+    SAMPLING.store(true, Ordering::Release);
+
+    Ok(())
+}
+
+/// Stops sampling the current processor. The recorded samples are kept until the next [`profiler_start`].
+pub fn profiler_stop() {
+    if !SAMPLING.swap(false, Ordering::AcqRel) {
+        return;
+    }
+
+    unsafe {
+        msr_write(IA32_PERFEVTSEL0 + SAMPLING_COUNTER, 0);
+
+        let global_ctrl = msr_read(IA32_PERF_GLOBAL_CTRL).unwrap_or_default();
+        msr_write(
+            IA32_PERF_GLOBAL_CTRL,
+            global_ctrl & !(1 << SAMPLING_COUNTER),
+        );
+    }
+}
+
+/// Records a sample if the `NMI` was raised by an overflow of the sampling counter.
+///
+/// Returns `false` if the `NMI` was not raised by the sampling counter. Nothing is logged, as the `NMI` may have
+/// interrupted code holding the lock of the kernel log.
+pub(crate) fn handle_pmu_nmi(frame: &InterruptStackFrame) -> bool {
+    if !SAMPLING.load(Ordering::Acquire) {
+        return false;
+    }
+
+    let status = msr_read(IA32_PERF_GLOBAL_STATUS).unwrap_or_default();
+    if status & (1 << SAMPLING_COUNTER) == 0 {
+        return false;
+    }
+
+    unsafe {
+        msr_write(
+            IA32_PMC0 + SAMPLING_COUNTER,
+            SAMPLE_RELOAD.load(Ordering::Relaxed),
+        );
+        msr_write(IA32_PERF_GLOBAL_OVF_CTRL, 1 << SAMPLING_COUNTER);
+    }
+
+    // The performance counter entry of the LVT is masked when the interrupt is delivered.
+    if let Some(lapic) = local_apic() {
+        lapic.set_perf_counter_nmi(true);
+    }
+
+    let buffer =
+        SAMPLE_BUFFERS[usize::from(u8::from(ProcLocalApicID::get()))].load(Ordering::Acquire);
+    if let Some(buffer) = unsafe { buffer.as_ref() } {
+        buffer.record(frame);
+    }
+
+    true
+}
+
+/// Samples attributed to a kernel symbol.
+#[derive(Clone, Copy, Debug)]
+pub struct PerfEntry {
+    /// Exported kernel symbol containing the sampled addresses, or `None` if they are not covered by any symbol.
+    pub symbol: Option<&'static str>,
+
+    /// Address of the symbol, or sampled address if no symbol covers it.
+    pub addr: u64,
+
+    pub samples: usize,
+}
+
+/// Aggregates the samples recorded on every processor by kernel symbol, from the most to the least sampled.
+///
+/// Returns the entries, and the number of samples lost because a buffer was full.
+pub fn perf_top() -> (Vec<PerfEntry>, usize) {
+    let mut entries: BTreeMap<u64, PerfEntry> = BTreeMap::new();
+    let mut lost = 0;
+
+    for buffer in sample_buffers() {
+        lost += buffer.lost();
+
+        for ip in buffer.stacks().filter_map(|mut stack| stack.next()) {
+            let (symbol, addr) = match kernel_symbol_at(ip) {
+                Some((symbol, _)) => (Some(symbol.name()), symbol.addr()),
+                None => (None, ip),
+            };
+
+            entries
+                .entry(addr)
+                .or_insert(PerfEntry {
+                    symbol,
+                    addr,
+                    samples: 0,
+                })
+                .samples += 1;
+        }
+    }
+
+    let mut entries: Vec<PerfEntry> = entries.into_values().collect();
+    entries.sort_unstable_by(|a, b| b.samples.cmp(&a.samples));
+
+    (entries, lost)
+}
+
+/// Writes the `limit` most sampled kernel symbols, with their share of the samples.
+pub fn perf_report(out: &mut impl Write, limit: usize) -> fmt::Result {
+    let (entries, lost) = perf_top();
+    let total: usize = entries.iter().map(|entry| entry.samples).sum();
+
+    if total == 0 {
+        return writeln!(out, "no samples");
+    }
+
+    writeln!(out, "{total} samples ({lost} lost)")?;
+
+    for entry in entries.iter().take(limit) {
+        let share = entry.samples * 1000 / total;
+        write!(
+            out,
+            "{:>3}.{}%  {:>6}  ",
+            share / 10,
+            share % 10,
+            entry.samples
+        )?;
+
+        match entry.symbol {
+            Some(name) => writeln!(out, "{name}")?,
+            None => writeln!(out, "{:#018x}", entry.addr)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the recorded call stacks in the folded format: one `outermost;...;innermost count` line for each distinct
+/// call stack, as read by flamegraph tools (`inferno-flamegraph`, `flamegraph.pl`).
+///
+/// Frames are named after the exported kernel symbol containing them, or after their address if there is none.
+pub fn perf_folded(out: &mut impl Write) -> fmt::Result {
+    let mut stacks: BTreeMap<String, usize> = BTreeMap::new();
+
+    for buffer in sample_buffers() {
+        for stack in buffer.stacks() {
+            let frames: Vec<u64> = stack.collect();
+            let mut folded = String::new();
+
+            for (depth, &addr) in frames.iter().enumerate().rev() {
+                // Return addresses point after the call instruction, which may be the first byte of the next symbol.
+                let lookup = if depth == 0 { addr } else { addr - 1 };
+
+                if !folded.is_empty() {
+                    folded.push(';');
+                }
+
+                match kernel_symbol_at(lookup) {
+                    Some((symbol, _)) => folded.push_str(symbol.name()),
+                    None => write!(folded, "{addr:#x}")?,
+                }
+            }
+
+            *stacks.entry(folded).or_default() += 1;
+        }
+    }
+
+    for (stack, count) in stacks {
+        writeln!(out, "{stack} {count}")?;
+    }
+
+    Ok(())
+}
+
+/// Streams the recorded call stacks to the first serial port, in the folded format (see [`perf_folded`]).
+///
+/// # Errors
+///
+/// Returns [`SerialError::NotPresent`] if the machine has no serial port.
+pub fn perf_export_serial() -> CanFail<SerialError> {
+    let mut folded = String::new();
+    let _ = perf_folded(&mut folded);
+
+    let mut serial = COM1.lock();
+    if !serial.initialized() {
+        serial.init(PERF_EXPORT_BAUD)?;
+    }
+
+    // Written as is: flamegraph tools expect LF line endings.
+    serial.write_bytes(folded.as_bytes());
+
+    Ok(())
+}
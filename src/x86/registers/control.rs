@@ -9,6 +9,8 @@
 #![allow(clippy::missing_errors_doc)]
 
 use crate::errors::InvalidAddress;
+#[cfg(feature = "x86_64")]
+use crate::mem::VirtAddr;
 use crate::mem::{Alignment, MemoryAddress, PhyAddr, PhyAddr32};
 use core::arch::asm;
 use modular_bitfield::bitfield;
@@ -24,10 +26,10 @@ pub struct Cr0 {
     protection_enable: bool,
 
     /// Controls the interaction of the _WAIT_ (or _FWAIT_) instruction with the _TS_ flag.
-    monitor_coprocessor: bool,
+    pub monitor_coprocessor: bool,
 
     /// Indicates that the processor does not have an external _x87 FPU_.
-    emulation: bool,
+    pub emulation: bool,
 
     /// Processor sets this flag on every task switch, and allows the saving of the _x87_ _FPU_/_MMX_/_SSE_ ... context
     /// on a task switch to be delayed until an instruction is actually executed by the new task.
@@ -531,6 +533,51 @@ impl ControlRegister for Cr4 {
     }
 }
 
+/// _Control Register 2_ structure.
+///
+/// Contains the linear address that caused the last page fault.
+#[cfg(feature = "x86_64")]
+#[derive(Clone, Copy, Debug)]
+pub struct Cr2 {
+    fault_addr: VirtAddr,
+}
+
+#[cfg(feature = "x86_64")]
+impl Cr2 {
+    /// Linear address that caused the last page fault.
+    pub fn fault_addr(self) -> VirtAddr {
+        self.fault_addr
+    }
+}
+
+#[cfg(feature = "x86_64")]
+impl ControlRegister for Cr2 {
+    fn read() -> Self {
+        let cr_bits: u64;
+        unsafe {
+            asm!(
+                "mov {}, cr2",
+                out(reg) cr_bits,
+                options(nomem, nostack)
+            )
+        }
+
+        Self {
+            fault_addr: VirtAddr::new(cr_bits),
+        }
+    }
+
+    fn write(self) {
+        unsafe {
+            asm!(
+                "mov cr2, {}",
+                in(reg) u64::from(self.fault_addr),
+                options(nomem, nostack)
+            )
+        }
+    }
+}
+
 pub trait ControlRegister {
     /// Reads the current content of the _Control Register_.
     ///
@@ -34,7 +34,7 @@ use conquer_once::spin::OnceCell;
 use crate::{
     errors::{CanFail, ClockError},
     info,
-    io::acpi::hpet::HPET_CLK,
+    io::{acpi::hpet::HPET_CLK, inb, outb, IOPort},
     x86::{
         cpuid::{
             cpu_family_id, cpu_feature_support, cpu_id, cpu_model_id, IntelCpuModel, CPU_FEAT_TSC,
@@ -49,6 +49,18 @@ pub static TSC_CLK: OnceCell<TSCClock> = OnceCell::uninit();
 
 const TSC_EXT_CALIBRATION_DELAY: u32 = 1000;
 
+/// Maximum relative difference between the frequency reported by `CPUID` and the measured one.
+const TSC_CALIBRATION_TOLERANCE: f64 = 0.01;
+
+/// Frequency of the `PIT` input clock, in Hz.
+const PIT_FREQUENCY: f64 = 1_193_182_f64;
+
+/// Initial count of the `PIT` channel 2 during calibration (about 10ms).
+const PIT_CALIBRATION_COUNT: u16 = 11_932;
+
+/// Maximum number of polls of the `PIT` channel 2 output, before giving up the calibration.
+const PIT_CALIBRATION_MAX_POLLS: u32 = 1_000_000;
+
 /// [`TSCClock`] stores the required information to work with a calibrated TSC clock.
 pub struct TSCClock {
     tsc_freq: f64,
@@ -59,8 +71,10 @@ pub struct TSCClock {
 impl TSCClock {
     /// Initializes the shared TSC clock.
     ///
-    /// It should be only called once. It automatically calibrates the clock using `CPUID` or the
-    /// shared [`HPET_CLK`] if available.
+    /// It should be only called once. It automatically calibrates the clock using `CPUID`, and
+    /// cross-checks that frequency against the shared [`HPET_CLK`] (or the `PIT` if there is no
+    /// HPET). The measured frequency is used when `CPUID` is not conclusive, or when both values
+    /// disagree.
     ///
     /// Returns an error if there are no available calibration methods, or if `TSC` is not
     /// supported on the device.
@@ -80,12 +94,7 @@ impl TSCClock {
             hpet_calibration: false,
         };
         info!("tsc", "beginning TSC clock calibration");
-        clk.__calibrate_tsc_cpuid()
-            .or_else(|_| clk.__calibrate_tsc_with_hpet())
-            .map(|x| {
-                info!("tsc", "failed CPUID calibration, using HPET clock instead");
-                x
-            })?;
+        clk.tsc_freq = clk.__calibrate_tsc()?;
 
         info!(
             "tsc",
@@ -100,6 +109,17 @@ impl TSCClock {
         Ok(())
     }
 
+    /// Checks if the TSC runs at a constant rate, regardless of the CPU frequency and power
+    /// states (as reported by `CPUID`).
+    pub fn invariant(&self) -> bool {
+        self.invariant
+    }
+
+    /// Returns the frequency of the TSC, in Hz.
+    pub fn frequency(&self) -> f64 {
+        self.tsc_freq
+    }
+
     /// Reads the current value of the TSC counter, using a serialized read.
     ///
     /// As it is serialized, it waits untill all previous instructions have been executed before
@@ -126,8 +146,8 @@ impl TSCClock {
         if self.invariant {
             return Ok(self.tsc_freq);
         }
-        self.__calibrate_tsc_cpuid()
-            .or_else(|_| self.__calibrate_tsc_with_hpet())
+        self.tsc_freq = self.__calibrate_tsc()?;
+        Ok(self.tsc_freq)
     }
 
     /// Reads the current value of the TSC counter.
@@ -137,14 +157,7 @@ impl TSCClock {
     ///
     /// `tsc_serialized_read` provides a way to make a serialized read of the counter.
     pub fn tsc_read(&self) -> u64 {
-        let low_msr: u32;
-        let high_msr: u32;
-
-        unsafe {
-            asm!("rdtsc", out("eax") low_msr, out("edx") high_msr, options(nostack, nomem));
-        }
-
-        ((high_msr as u64) << 32) + low_msr as u64
+        rdtsc()
     }
 
     /// Returns the current time in microseconds of the TSC counter.
@@ -160,6 +173,79 @@ impl TSCClock {
         (1_000_000_f64 * ticks) / self.tsc_freq
     }
 
+    /// Determines the frequency of the TSC, in Hz.
+    ///
+    /// The frequency reported by `CPUID` is only trusted if it is within
+    /// [`TSC_CALIBRATION_TOLERANCE`] of the frequency measured against another clock source,
+    /// when such a measure is possible.
+    fn __calibrate_tsc(&mut self) -> Result<f64, ClockError> {
+        self.hpet_calibration = false;
+
+        let measured = self
+            .__calibrate_tsc_with_hpet()
+            .or_else(|_| self.__calibrate_tsc_with_pit());
+
+        match (self.__calibrate_tsc_cpuid(), measured) {
+            (Ok(cpuid_freq), Ok(measured_freq)) => {
+                if (cpuid_freq - measured_freq).abs() > measured_freq * TSC_CALIBRATION_TOLERANCE {
+                    info!(
+                        "tsc",
+                        "CPUID frequency ({} hz) differs from the measured frequency ({} hz), using the measured one",
+                        cpuid_freq,
+                        measured_freq
+                    );
+                    return Ok(measured_freq);
+                }
+
+                self.hpet_calibration = false;
+                Ok(cpuid_freq)
+            }
+            (Ok(cpuid_freq), Err(_)) => Ok(cpuid_freq),
+            (Err(_), Ok(measured_freq)) => {
+                info!(
+                    "tsc",
+                    "failed CPUID calibration, using measured frequency instead"
+                );
+                Ok(measured_freq)
+            }
+            (Err(_), Err(_)) => Err(ClockError::CalibrationError),
+        }
+    }
+
+    /// Calibrates the TSC using the channel 2 of the `PIT`.
+    ///
+    /// The channel is programmed to count down from [`PIT_CALIBRATION_COUNT`], and the TSC is
+    /// read before and after the countdown. Fails if the countdown never ends, which happens on
+    /// systems without a `PIT`.
+    fn __calibrate_tsc_with_pit(&self) -> Result<f64, ClockError> {
+        let port_b = inb(IOPort::PIT_PORT_B);
+
+        // Enables the gate of channel 2, and disables the speaker output.
+        outb(IOPort::PIT_PORT_B, (port_b & !0x2) | 0x1);
+
+        // Channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count), binary.
+        outb(IOPort::PIT_COMMAND, 0b1011_0000);
+        outb(IOPort::PIT_CHANNEL_2, (PIT_CALIBRATION_COUNT & 0xff) as u8);
+        outb(IOPort::PIT_CHANNEL_2, (PIT_CALIBRATION_COUNT >> 8) as u8);
+
+        let entry_tsc = self.tsc_read();
+
+        // The output of channel 2 (bit 5) goes high once the countdown reaches 0.
+        let mut polls = 0;
+        while inb(IOPort::PIT_PORT_B) & 0x20 == 0 {
+            polls += 1;
+            if polls > PIT_CALIBRATION_MAX_POLLS {
+                outb(IOPort::PIT_PORT_B, port_b);
+                return Err(ClockError::CalibrationError);
+            }
+        }
+
+        let exit_tsc = self.tsc_read();
+        outb(IOPort::PIT_PORT_B, port_b);
+
+        Ok((exit_tsc - entry_tsc) as f64 * PIT_FREQUENCY / f64::from(PIT_CALIBRATION_COUNT))
+    }
+
     /// Calibrates the TSC using the [`HPETClock`].
     ///
     /// Returns the frequency of the TSC, in Hz, or fails if there is no available [`HPETClock'].
@@ -181,7 +267,6 @@ impl TSCClock {
 
         let freq = ((exit_tsc - entry_tsc) as f64) * 1_000_000_f64 / (exit_us - entry_us);
 
-        self.tsc_freq = freq;
         self.hpet_calibration = true;
         Ok(freq)
     }
@@ -193,7 +278,7 @@ impl TSCClock {
     ///
     /// If none of this worked, it uses the information contained in `MSR_PLATFORM_INFO` as a
     /// final attemps at retrieving the TSC frequency.
-    fn __calibrate_tsc_cpuid(&mut self) -> Result<f64, ClockError> {
+    fn __calibrate_tsc_cpuid(&self) -> Result<f64, ClockError> {
         // TSC and Crystal clock Information Leaf -> CPUID.15H
         // EAX : Denominator of TSC/"core crystal clock" ratio
         // EBX : Numerator of TSC/"core crystal clock" ratio
@@ -202,9 +287,7 @@ impl TSCClock {
 
         if res[0] != 0 && res[1] != 0 {
             if res[2] != 0 {
-                let tsc_freq = (res[1] as f64 / res[0] as f64) * res[2] as f64;
-                self.tsc_freq = tsc_freq;
-                return Ok(tsc_freq);
+                return Ok((res[1] as f64 / res[0] as f64) * res[2] as f64);
             }
 
             if let Some(cpu_family) = cpu_family_id() {
@@ -262,12 +345,29 @@ impl TSCClock {
 }
 
 /// Checks if the TSC is invariant (fixed frequency).
+///
+/// The invariant TSC support is reported by bit 8 of `EDX`, for the `CPUID` leaf 80000007H.
 fn __tsc_invariant_support() -> bool {
-    if let Some(id) = cpu_id(0x80000008) {
-        if id[3] != 0 {
+    if let Some(id) = cpu_id(0x80000007) {
+        if id[3] & (1 << 8) != 0 {
             return true;
         }
     }
 
     false
 }
+
+/// Reads the current value of the TSC counter, without going through [`TSC_CLK`].
+///
+/// The counter can be read before the clock is initialized, but its value can only be converted to a duration once the
+/// frequency is known.
+pub fn rdtsc() -> u64 {
+    let low_msr: u32;
+    let high_msr: u32;
+
+    unsafe {
+        asm!("rdtsc", out("eax") low_msr, out("edx") high_msr, options(nostack, nomem));
+    }
+
+    ((high_msr as u64) << 32) + low_msr as u64
+}